@@ -0,0 +1,8 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // protoc comes with the build, nothing needs to be installed
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/wtf.proto"], &["proto"])?;
+    Ok(())
+}
@@ -0,0 +1,196 @@
+use anyhow::{bail, Context, Result};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use bitcoin::hashes::{sha256, Hash};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tracing::debug;
+
+/// Probes of orchestrators and the API's documentation get through without a key and aren't
+/// counted
+const OPEN_PATHS: [&str; 4] = ["/healthz", "/readyz", "/openapi.json", "/docs"];
+/// Clients tracked before those with a full allowance are forgotten
+const MAX_CLIENTS: usize = 10_000;
+
+/// Requests a client has left, refilled continuously
+#[derive(Debug)]
+struct Bucket {
+    requests: f64,
+    at: Instant,
+}
+
+/// Why [`Access::check`] turned a request away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Denied {
+    NoKey,
+    /// Seconds until the client may ask again
+    RateLimited(u64),
+}
+
+/// Who may use `serve` and how often. With API keys every request needs one, as
+/// `Authorization: Bearer KEY`, `X-API-Key: KEY` or, for WebSocket clients that can't set
+/// headers, `?api_key=KEY`. With a rate limit each key, or each IP address without keys, gets
+/// that many requests a minute and may spend up to a minute's worth at once. Behind a reverse
+/// proxy all clients share its address.
+#[derive(Debug, Default)]
+pub struct Access {
+    /// Only digests are kept, a lookup doesn't compare the keys byte by byte
+    keys: HashSet<sha256::Hash>,
+    /// Requests per minute
+    rate_limit: Option<u32>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl Access {
+    pub fn new(keys: &[String], rate_limit: Option<u32>) -> Result<Self> {
+        if rate_limit == Some(0) {
+            bail!("the rate limit must allow at least one request a minute");
+        }
+        Ok(Access {
+            keys: keys
+                .iter()
+                .map(|key| sha256::Hash::hash(key.as_bytes()))
+                .collect(),
+            rate_limit,
+            buckets: Mutex::default(),
+        })
+    }
+
+    /// API keys in `path`, one per line, skipping blank lines and `#` comments
+    pub fn read_keys(path: &Path) -> Result<Vec<String>> {
+        let keys = std::fs::read_to_string(path)
+            .with_context(|| format!("reading API keys from {}", path.display()))?;
+        let keys: Vec<String> = keys
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect();
+        if keys.is_empty() {
+            bail!("{} has no API keys", path.display());
+        }
+        Ok(keys)
+    }
+
+    pub fn requires_key(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// Anyone may ask as often as they like
+    pub fn is_open(&self) -> bool {
+        self.keys.is_empty() && self.rate_limit.is_none()
+    }
+
+    /// Let a request with `key` from `address` in, naming the client for logs
+    pub fn check(&self, key: Option<&str>, address: SocketAddr) -> Result<String, Denied> {
+        let client = self.client(key, address).ok_or(Denied::NoKey)?;
+        self.admit(&client).map_err(Denied::RateLimited)?;
+        Ok(client)
+    }
+
+    /// The client a request comes from, by its key if keys are required, `None` if it has no
+    /// valid one
+    fn client(&self, key: Option<&str>, address: SocketAddr) -> Option<String> {
+        if self.keys.is_empty() {
+            return Some(address.ip().to_string());
+        }
+        let digest = sha256::Hash::hash(key?.trim().as_bytes());
+        // the digest names the client in logs and buckets instead of the key itself
+        self.keys
+            .contains(&digest)
+            .then(|| format!("key {}", &digest.to_string()[..8]))
+    }
+
+    /// The key of an HTTP request, from its headers or `query`
+    fn key<'a>(headers: &'a HeaderMap, query: Option<&'a str>) -> Option<&'a str> {
+        let bearer = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let header_key = headers
+            .get("x-api-key")
+            .and_then(|value| value.to_str().ok());
+        let query_key = query.and_then(|query| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("api_key="))
+        });
+        bearer.or(header_key).or(query_key)
+    }
+
+    /// Take a request from the client's allowance, or the seconds until there is one
+    fn admit(&self, client: &str) -> Result<(), u64> {
+        let Some(per_minute) = self.rate_limit.map(f64::from) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let refill = |bucket: &Bucket| {
+            (bucket.requests + now.duration_since(bucket.at).as_secs_f64() * per_minute / 60.)
+                .min(per_minute)
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_CLIENTS {
+            buckets.retain(|_, bucket| refill(bucket) < per_minute);
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            requests: per_minute,
+            at: now,
+        });
+        bucket.requests = refill(bucket);
+        bucket.at = now;
+        if bucket.requests < 1. {
+            return Err(((1. - bucket.requests) * 60. / per_minute).ceil() as u64);
+        }
+        bucket.requests -= 1.;
+        Ok(())
+    }
+
+    /// Turn away requests without a valid key, or over the rate limit
+    pub async fn middleware(
+        State(access): State<Arc<Access>>,
+        ConnectInfo(address): ConnectInfo<SocketAddr>,
+        request: Request<Body>,
+        next: Next<Body>,
+    ) -> Response {
+        if OPEN_PATHS.contains(&request.uri().path()) {
+            return next.run(request).await;
+        }
+        let key = Self::key(request.headers(), request.uri().query());
+        match access.check(key, address) {
+            Ok(_) => {}
+            Err(Denied::NoKey) => {
+                debug!("{address}: no valid API key");
+                let error = Json(json!({ "error": "a valid API key is required" }));
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    [(header::WWW_AUTHENTICATE, "Bearer")],
+                    error,
+                )
+                    .into_response();
+            }
+            Err(Denied::RateLimited(retry_after)) => {
+                debug!("{address}: rate limited for {retry_after} s");
+                let error = Json(json!({ "error": "too many requests" }));
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    error,
+                )
+                    .into_response();
+            }
+        }
+        next.run(request).await
+    }
+}
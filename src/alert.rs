@@ -0,0 +1,333 @@
+use crate::{calc::Calc, replay::Replay, storage::Storage};
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// A significant move between two consecutive fee estimates
+#[derive(Debug, Clone, Serialize)]
+pub struct EstimateChange {
+    pub old_sat_vb: f64,
+    pub new_sat_vb: f64,
+    /// (new - old) / old
+    pub relative_change: f64,
+    /// Weight added minus weight removed above the old estimate since it was computed
+    pub net_weight_above_old: f64,
+}
+
+/// Compares each fresh estimate with the previous one and reports moves beyond a relative
+/// threshold, at most once per cooldown period.
+pub struct EstimateWatch {
+    threshold: f64,
+    cooldown: Duration,
+    webhook: Option<String>,
+    client: reqwest::Client,
+    previous: Option<f64>,
+    last_alert: Option<Instant>,
+}
+
+impl EstimateWatch {
+    pub fn new(threshold: f64, cooldown: Duration) -> Self {
+        EstimateWatch {
+            threshold,
+            cooldown,
+            webhook: None,
+            client: reqwest::Client::new(),
+            previous: None,
+            last_alert: None,
+        }
+    }
+
+    /// POST every reported change as JSON to `url`
+    pub fn with_webhook(mut self, url: impl Into<String>) -> Self {
+        self.webhook = Some(url.into());
+        self
+    }
+
+    /// Feed the next estimate (sat/vB) together with the mempool changes since the previous one,
+    /// given as `(fee_rate_sat_vb, weight)` pairs with removals carrying negative weight.
+    pub fn observe(
+        &mut self,
+        estimate: f64,
+        delta: impl IntoIterator<Item = (f64, f64)>,
+    ) -> Option<EstimateChange> {
+        let old = self.previous.replace(estimate)?;
+        if old <= 0. {
+            return None;
+        }
+
+        let relative_change = (estimate - old) / old;
+        if relative_change.abs() < self.threshold {
+            return None;
+        }
+
+        let now = Instant::now();
+        if let Some(last_alert) = self.last_alert {
+            if now.duration_since(last_alert) < self.cooldown {
+                return None;
+            }
+        }
+        self.last_alert = Some(now);
+
+        let net_weight_above_old = delta
+            .into_iter()
+            .filter(|(fee_rate, _)| *fee_rate >= old)
+            .map(|(_, weight)| weight)
+            .sum();
+
+        Some(EstimateChange {
+            old_sat_vb: old,
+            new_sat_vb: estimate,
+            relative_change,
+            net_weight_above_old,
+        })
+    }
+
+    /// Emit the change as a structured log event and to the webhook, if configured
+    pub async fn notify(&self, change: &EstimateChange) {
+        info!(
+            old_sat_vb = change.old_sat_vb,
+            new_sat_vb = change.new_sat_vb,
+            relative_change = change.relative_change,
+            net_weight_above_old = change.net_weight_above_old,
+            "estimate_changed"
+        );
+
+        if let Some(url) = &self.webhook {
+            let result = self
+                .client
+                .post(url)
+                .json(change)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                warn!("estimate webhook failed: {e}");
+            }
+        }
+    }
+}
+
+/// What set a fee alert off
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "trigger", rename_all = "snake_case")]
+pub enum Trigger {
+    /// The estimate fell to `threshold_sat_vb` or below
+    Below { threshold_sat_vb: f64 },
+    /// The estimate rose to `threshold_sat_vb` or above
+    Above { threshold_sat_vb: f64 },
+    /// The estimate moved by `change_pct` from `from_sat_vb` within `within_secs`
+    Change {
+        from_sat_vb: f64,
+        change_pct: f64,
+        within_secs: i64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeAlert {
+    /// Time of the snapshot the estimate is from
+    pub timestamp: i64,
+    pub target: u32,
+    pub confidence: f64,
+    pub fee_rate_sat_vb: f64,
+    #[serde(flatten)]
+    pub trigger: Trigger,
+}
+
+/// Watches the estimate for one target and fires when it crosses a threshold or moves by more
+/// than a percentage within a time span, say to batch withdrawals once fees drop.
+pub struct FeeAlerts {
+    below: Vec<f64>,
+    above: Vec<f64>,
+    /// Percentage and seconds
+    change: Option<(f64, i64)>,
+    webhook: Option<String>,
+    command: Option<String>,
+    client: reqwest::Client,
+    previous: Option<f64>,
+    /// Estimates within the change span, oldest first
+    history: VecDeque<(i64, f64)>,
+}
+
+impl FeeAlerts {
+    /// Fire when the estimate crosses one of `below` downwards or one of `above` upwards, or
+    /// when it moves by `change` percent within `change` seconds
+    pub fn new(below: Vec<f64>, above: Vec<f64>, change: Option<(f64, i64)>) -> Result<Self> {
+        if below.is_empty() && above.is_empty() && change.is_none() {
+            bail!("no alerts configured, set a threshold or a change percentage");
+        }
+        if let Some((pct, secs)) = change {
+            if pct <= 0. || secs <= 0 {
+                bail!("changes must be over 0% within more than 0 seconds");
+            }
+        }
+        Ok(FeeAlerts {
+            below,
+            above,
+            change,
+            webhook: None,
+            command: None,
+            client: reqwest::Client::new(),
+            previous: None,
+            history: VecDeque::new(),
+        })
+    }
+
+    /// POST every alert as JSON to `url`
+    pub fn with_webhook(mut self, url: impl Into<String>) -> Self {
+        self.webhook = Some(url.into());
+        self
+    }
+
+    /// Run `command` with `sh -c` on every alert, with the alert as JSON in `WTF_ALERT`
+    pub fn with_command(mut self, command: impl Into<String>) -> Self {
+        self.command = Some(command.into());
+        self
+    }
+
+    /// Feed the estimate of the snapshot taken at `timestamp`. Thresholds only fire when
+    /// crossed, not on the first estimate.
+    pub fn observe(
+        &mut self,
+        timestamp: i64,
+        target: u32,
+        confidence: f64,
+        fee_rate: f64,
+    ) -> Vec<FeeAlert> {
+        let mut triggers = Vec::new();
+        if let Some(previous) = self.previous.replace(fee_rate) {
+            for threshold in &self.below {
+                if previous > *threshold && fee_rate <= *threshold {
+                    triggers.push(Trigger::Below {
+                        threshold_sat_vb: *threshold,
+                    });
+                }
+            }
+            for threshold in &self.above {
+                if previous < *threshold && fee_rate >= *threshold {
+                    triggers.push(Trigger::Above {
+                        threshold_sat_vb: *threshold,
+                    });
+                }
+            }
+        }
+
+        if let Some((pct, within_secs)) = self.change {
+            while self
+                .history
+                .front()
+                .is_some_and(|(time, _)| *time < timestamp - within_secs)
+            {
+                self.history.pop_front();
+            }
+            let largest = self
+                .history
+                .iter()
+                .filter(|(_, old)| *old > 0.)
+                .map(|(_, old)| (*old, (fee_rate - old) / old * 100.))
+                .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()));
+            match largest {
+                Some((from_sat_vb, change_pct)) if change_pct.abs() >= pct => {
+                    triggers.push(Trigger::Change {
+                        from_sat_vb,
+                        change_pct,
+                        within_secs,
+                    });
+                    // start over, so one move fires once
+                    self.history.clear();
+                }
+                _ => {}
+            }
+            self.history.push_back((timestamp, fee_rate));
+        }
+
+        triggers
+            .into_iter()
+            .map(|trigger| FeeAlert {
+                timestamp,
+                target,
+                confidence,
+                fee_rate_sat_vb: fee_rate,
+                trigger,
+            })
+            .collect()
+    }
+
+    /// Emit the alert as a structured log event, to the webhook and to the command, if
+    /// configured
+    pub async fn notify(&self, alert: &FeeAlert) {
+        info!(
+            target = alert.target,
+            fee_rate_sat_vb = alert.fee_rate_sat_vb,
+            trigger = ?alert.trigger,
+            "fee_alert"
+        );
+
+        if let Some(url) = &self.webhook {
+            let result = self
+                .client
+                .post(url)
+                .json(alert)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                warn!("alert webhook failed: {e}");
+            }
+        }
+
+        if let Some(command) = &self.command {
+            let json = serde_json::to_string(alert).unwrap_or_default();
+            let status = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .env("WTF_ALERT", json)
+                .status()
+                .await;
+            match status {
+                Ok(status) if !status.success() => warn!("alert command exited with {status}"),
+                Ok(_) => {}
+                Err(e) => warn!("alert command failed: {e}"),
+            }
+        }
+    }
+
+    /// Estimate `target` at `confidence` like `calc` on each new snapshot, looking for one
+    /// every `poll`, and notify of the alerts. Runs until interrupted.
+    pub async fn watch(
+        mut self,
+        storage: &dyn Storage,
+        target: u32,
+        confidence: f64,
+        poll: Duration,
+    ) -> Result<()> {
+        let mut seen: Option<i64> = None;
+        loop {
+            let latest = Replay::new(storage)?.latest();
+            if latest.is_some() && latest != seen {
+                seen = latest;
+                match Calc::targets(storage, &[target], &[confidence]) {
+                    Ok(estimates) => {
+                        for estimate in estimates {
+                            let alerts = self.observe(
+                                latest.unwrap_or_default(),
+                                target,
+                                confidence,
+                                estimate.fee_rate_sat_vb,
+                            );
+                            for alert in &alerts {
+                                self.notify(alert).await;
+                            }
+                        }
+                    }
+                    Err(e) => warn!("estimating for alerts: {e:#}"),
+                }
+            }
+            tokio::time::sleep(poll).await;
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use crate::{
+    calc::{Calc, WINDOW_SECS},
+    dataset::FileKind,
+    model::Trained,
+    replay::Replay,
+    storage::Storage,
+};
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use tracing::info;
+
+/// Share of a block's recorded confirmations below its floor, so CPFP parents and prioritised
+/// transactions don't make it look cheaper than it was
+const FLOOR_QUANTILE: f64 = 0.05;
+
+/// How the estimates for one target fared
+#[derive(Debug, Clone, Serialize)]
+pub struct TargetReport {
+    pub target: u32,
+    /// Estimates whose blocks were all recorded
+    pub scored: usize,
+    /// Estimates too low for any of the `target` blocks that followed
+    pub misses: usize,
+    pub miss_rate: f64,
+    /// How much more than needed the estimates that made it paid, in percent
+    pub mean_overpay_pct: f64,
+    pub median_overpay_pct: f64,
+    /// Estimates whose blocks weren't recorded (yet)
+    pub unscored: usize,
+}
+
+/// Our estimates against Bitcoin Core's recorded at the same time, for one target
+#[derive(Debug, Clone, Serialize)]
+pub struct Comparison {
+    pub target: u32,
+    /// Estimates both made and that could be scored
+    pub compared: usize,
+    /// Got in where Core missed, or got in paying less
+    pub better: usize,
+    /// Missed where Core got in, or paid more
+    pub worse: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// Times of the first and the last estimate
+    pub from: i64,
+    pub to: i64,
+    pub confidence: f64,
+    pub targets: Vec<TargetReport>,
+    /// Bitcoin Core's `estimatesmartfee` scored the same way, for the targets it was recorded for
+    pub core: Vec<TargetReport>,
+    pub comparisons: Vec<Comparison>,
+}
+
+/// An estimate as it would have been made at the time
+#[derive(Debug, Clone, Copy)]
+struct Issued {
+    timestamp: i64,
+    height: u64,
+    fee_rate: f64,
+}
+
+/// Fee rate an estimate paid and the least it needed to pay
+#[derive(Debug, Clone, Copy)]
+struct Outcome {
+    fee_rate: f64,
+    needed: f64,
+}
+
+impl Outcome {
+    fn hit(&self) -> bool {
+        self.fee_rate >= self.needed
+    }
+
+    fn overpay_pct(&self) -> f64 {
+        (self.fee_rate - self.needed) / self.needed.max(f64::EPSILON) * 100.
+    }
+
+    /// Getting in beats missing, then the cheaper hit or the closer miss wins
+    fn better_than(&self, other: &Outcome) -> Option<bool> {
+        match (self.hit(), other.hit()) {
+            (true, false) => Some(true),
+            (false, true) => Some(false),
+            (true, true) if self.fee_rate != other.fee_rate => Some(self.fee_rate < other.fee_rate),
+            (false, false) if self.fee_rate != other.fee_rate => {
+                Some(self.fee_rate > other.fee_rate)
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct Backtest;
+
+impl Backtest {
+    /// Replay `from..=to`, estimate like [`Calc::calc`] every `every_secs` from the snapshots
+    /// recorded up to then, and score the estimates against the blocks that followed. An
+    /// estimate made at height `h` hits when it reaches the floor of one of the blocks
+    /// `h + 1..=h + target`, the lowest fee rate the block still took. Bitcoin Core's
+    /// `estimatesmartfee` answers recorded with `record --core-estimates` are scored next to
+    /// them.
+    #[tracing::instrument(skip(storage))]
+    pub fn run(
+        storage: &dyn Storage,
+        from: i64,
+        to: i64,
+        every_secs: i64,
+        targets: &[u32],
+        confidence: f64,
+    ) -> Result<Report> {
+        if every_secs <= 0 {
+            bail!("estimates must be at least a second apart");
+        }
+        if targets.is_empty() || targets.contains(&0) {
+            bail!("targets must be at least one block");
+        }
+        if !(confidence > 0. && confidence <= 1.) {
+            bail!("confidence must be in (0, 1], got {confidence}");
+        }
+
+        let floors = Self::floors(storage)?;
+        let (times, issued) = Self::issue(storage, from, to, every_secs, targets, confidence)?;
+        let Some((from, to)) = times else {
+            bail!("no snapshots to estimate from between {from} and {to}");
+        };
+
+        let core_estimates = Self::core_estimates(storage)?;
+        let mut reports = Vec::new();
+        let mut core = Vec::new();
+        let mut comparisons = Vec::new();
+        for (target, issued) in targets.iter().zip(issued) {
+            let outcomes: Vec<Option<Outcome>> = issued
+                .iter()
+                .map(|estimate| Self::outcome(&floors, *target, estimate))
+                .collect();
+            reports.push(Self::report(*target, &outcomes));
+
+            // Core's latest answer at the time of each of our estimates
+            let Some(recorded) = core_estimates.get(target) else {
+                continue;
+            };
+            let core_outcomes: Vec<Option<Outcome>> = issued
+                .iter()
+                .map(|estimate| {
+                    let (_, fee_rate) = recorded
+                        .range(estimate.timestamp - WINDOW_SECS..=estimate.timestamp)
+                        .next_back()?;
+                    let core_estimate = Issued {
+                        fee_rate: *fee_rate,
+                        ..*estimate
+                    };
+                    Self::outcome(&floors, *target, &core_estimate)
+                })
+                .collect();
+            core.push(Self::report(*target, &core_outcomes));
+
+            let mut comparison = Comparison {
+                target: *target,
+                compared: 0,
+                better: 0,
+                worse: 0,
+            };
+            for (ours, theirs) in outcomes.iter().zip(&core_outcomes) {
+                let (Some(ours), Some(theirs)) = (ours, theirs) else {
+                    continue;
+                };
+                comparison.compared += 1;
+                match ours.better_than(theirs) {
+                    Some(true) => comparison.better += 1,
+                    Some(false) => comparison.worse += 1,
+                    None => {}
+                }
+            }
+            comparisons.push(comparison);
+        }
+        Ok(Report {
+            from,
+            to,
+            confidence,
+            targets: reports,
+            core,
+            comparisons,
+        })
+    }
+
+    /// Estimates as [`Backtest::run`] makes them for `target`, as the time, the estimate and the
+    /// least the blocks that followed needed, if they were all recorded
+    pub fn series(
+        storage: &dyn Storage,
+        from: i64,
+        to: i64,
+        every_secs: i64,
+        target: u32,
+        confidence: f64,
+    ) -> Result<Vec<(i64, f64, Option<f64>)>> {
+        if every_secs <= 0 {
+            bail!("estimates must be at least a second apart");
+        }
+        if target == 0 {
+            bail!("targets must be at least one block");
+        }
+        if !(confidence > 0. && confidence <= 1.) {
+            bail!("confidence must be in (0, 1], got {confidence}");
+        }
+        let floors = Self::floors(storage)?;
+        let (_, issued) = Self::issue(storage, from, to, every_secs, &[target], confidence)?;
+        Ok(issued[0]
+            .iter()
+            .map(|estimate| {
+                let needed = Self::needed(&floors, estimate.height, target);
+                (estimate.timestamp, estimate.fee_rate, needed)
+            })
+            .collect())
+    }
+
+    /// Score the estimates `model` makes every `every_secs` within `from..=to` against the
+    /// blocks that followed, like [`Backtest::run`] does those of [`Calc::calc`]. Snapshots
+    /// the model has no estimate for count as unscored.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn score(
+        storage: &dyn Storage,
+        floors: &BTreeMap<u64, f64>,
+        model: &Trained,
+        from: i64,
+        to: i64,
+        every_secs: i64,
+        targets: &[u32],
+        confidence: f64,
+    ) -> Result<Vec<TargetReport>> {
+        if every_secs <= 0 {
+            bail!("estimates must be at least a second apart");
+        }
+        let mut outcomes: Vec<Vec<Option<Outcome>>> = vec![Vec::new(); targets.len()];
+        let mut next_tick = from;
+        Replay::new(storage)?.walk(from, to, |snapshot| {
+            if snapshot.timestamp < next_tick {
+                return Ok(());
+            }
+            next_tick = snapshot.timestamp + every_secs;
+            for (target, outcomes) in targets.iter().zip(outcomes.iter_mut()) {
+                let outcome =
+                    model
+                        .estimate(snapshot, *target, confidence)
+                        .ok()
+                        .and_then(|fee_rate| {
+                            let estimate = Issued {
+                                timestamp: snapshot.timestamp,
+                                height: snapshot.height,
+                                fee_rate,
+                            };
+                            Self::outcome(floors, *target, &estimate)
+                        });
+                outcomes.push(outcome);
+            }
+            Ok(())
+        })?;
+        Ok(targets
+            .iter()
+            .zip(outcomes)
+            .map(|(target, outcomes)| Self::report(*target, &outcomes))
+            .collect())
+    }
+
+    /// Estimates every `every_secs` from `from` to `to` per target, with the times of the first
+    /// and the last
+    #[allow(clippy::type_complexity)]
+    fn issue(
+        storage: &dyn Storage,
+        from: i64,
+        to: i64,
+        every_secs: i64,
+        targets: &[u32],
+        confidence: f64,
+    ) -> Result<(Option<(i64, i64)>, Vec<Vec<Issued>>)> {
+        let replay = Replay::new(storage)?;
+
+        // cutoffs of the snapshots within the estimation window, per target
+        let mut windows: Vec<VecDeque<(i64, f64)>> = vec![VecDeque::new(); targets.len()];
+        let mut issued: Vec<Vec<Issued>> = (0..targets.len()).map(|_| Vec::new()).collect();
+        let mut next_tick = from;
+        let mut times: Option<(i64, i64)> = None;
+        replay.walk(from.saturating_sub(WINDOW_SECS), to, |snapshot| {
+            for (window, target) in windows.iter_mut().zip(targets) {
+                window.push_back((snapshot.timestamp, Calc::block_cutoff(snapshot, *target)));
+                while window
+                    .front()
+                    .is_some_and(|(timestamp, _)| *timestamp < snapshot.timestamp - WINDOW_SECS)
+                {
+                    window.pop_front();
+                }
+            }
+            if snapshot.timestamp < next_tick {
+                return Ok(());
+            }
+            next_tick = snapshot.timestamp + every_secs;
+            let first = times.map_or(snapshot.timestamp, |(first, _)| first);
+            times = Some((first, snapshot.timestamp));
+            for (window, issued) in windows.iter().zip(issued.iter_mut()) {
+                let mut cutoffs: Vec<f64> = window.iter().map(|(_, cutoff)| *cutoff).collect();
+                cutoffs.sort_by(f64::total_cmp);
+                issued.push(Issued {
+                    timestamp: snapshot.timestamp,
+                    height: snapshot.height,
+                    fee_rate: Calc::quantile(&cutoffs, confidence),
+                });
+            }
+            Ok(())
+        })?;
+        Ok((times, issued))
+    }
+
+    /// Recorded `estimatesmartfee` fee rates by target and time
+    fn core_estimates(storage: &dyn Storage) -> Result<HashMap<u32, BTreeMap<i64, f64>>> {
+        let mut estimates: HashMap<u32, BTreeMap<i64, f64>> = HashMap::new();
+        for file in storage.list()? {
+            if file.kind != FileKind::CoreEstimates {
+                continue;
+            }
+            let frame = storage.read(&file)?;
+            let targets = frame.column("target")?.u32()?;
+            let fee_rates = frame.column("fee_rate_sat_vb")?.f64()?;
+            for (target, fee_rate) in targets.into_iter().zip(fee_rates) {
+                if let (Some(target), Some(fee_rate)) = (target, fee_rate) {
+                    estimates
+                        .entry(target)
+                        .or_default()
+                        .insert(file.timestamp, fee_rate);
+                }
+            }
+        }
+        Ok(estimates)
+    }
+
+    /// Lowest fee rate each recorded block took, by height. Blocks without recorded
+    /// confirmations fall back to their imported block fees.
+    pub(crate) fn floors(storage: &dyn Storage) -> Result<BTreeMap<u64, f64>> {
+        let mut rates: BTreeMap<u64, Vec<f64>> = BTreeMap::new();
+        for confirmation in Calc::confirmations(storage, i64::MIN)?
+            .into_iter()
+            .filter(|c| !c.out_of_band)
+        {
+            rates
+                .entry(confirmation.height)
+                .or_default()
+                .push(confirmation.fee_rate_sat_vb);
+        }
+        let mut floors: BTreeMap<u64, f64> = rates
+            .into_iter()
+            .map(|(height, mut rates)| {
+                rates.sort_by(f64::total_cmp);
+                (height, Calc::quantile(&rates, FLOOR_QUANTILE))
+            })
+            .collect();
+        for file in storage.list()? {
+            if file.kind != FileKind::BlockFees || floors.contains_key(&file.height) {
+                continue;
+            }
+            // the lowest rate is often a parent its child paid for, the 10th percentile is
+            // closer to what the block took
+            let frame = storage.read(&file)?;
+            let fee_rate = frame.column("fee_rate_p10")?.f64()?.into_iter().next();
+            if let Some(fee_rate) = fee_rate.flatten() {
+                floors.insert(file.height, fee_rate);
+            }
+        }
+        Ok(floors)
+    }
+
+    /// How `estimate` fared, `None` if not all of the `target` blocks after it were recorded
+    fn outcome(floors: &BTreeMap<u64, f64>, target: u32, estimate: &Issued) -> Option<Outcome> {
+        Some(Outcome {
+            fee_rate: estimate.fee_rate,
+            needed: Self::needed(floors, estimate.height, target)?,
+        })
+    }
+
+    /// Lowest of the floors of the blocks `height + 1..=height + target`, `None` if not all of
+    /// them were recorded
+    pub(crate) fn needed(floors: &BTreeMap<u64, f64>, height: u64, target: u32) -> Option<f64> {
+        (height + 1..=height + target as u64)
+            .map(|height| floors.get(&height).copied())
+            .collect::<Option<Vec<f64>>>()?
+            .into_iter()
+            .reduce(f64::min)
+    }
+
+    fn report(target: u32, outcomes: &[Option<Outcome>]) -> TargetReport {
+        let scored: Vec<&Outcome> = outcomes.iter().flatten().collect();
+        let misses = scored.iter().filter(|o| !o.hit()).count();
+        let mut overpay: Vec<f64> = scored
+            .iter()
+            .filter(|o| o.hit())
+            .map(|o| o.overpay_pct())
+            .collect();
+        overpay.sort_by(f64::total_cmp);
+        let report = TargetReport {
+            target,
+            scored: scored.len(),
+            misses,
+            miss_rate: if scored.is_empty() {
+                0.
+            } else {
+                misses as f64 / scored.len() as f64
+            },
+            mean_overpay_pct: if overpay.is_empty() {
+                0.
+            } else {
+                overpay.iter().sum::<f64>() / overpay.len() as f64
+            },
+            median_overpay_pct: if overpay.is_empty() {
+                0.
+            } else {
+                Calc::quantile(&overpay, 0.5)
+            },
+            unscored: outcomes.len() - scored.len(),
+        };
+        info!(
+            "target: {target}, scored: {}, miss_rate: {:.3}",
+            report.scored, report.miss_rate
+        );
+        report
+    }
+}
@@ -0,0 +1,136 @@
+use crate::node::Node;
+use anyhow::{bail, Result};
+use bitcoin::{Amount, Txid};
+use serde::Serialize;
+
+/// Transactions a replacement may evict at most, BIP125 rule 5
+const MAX_REPLACEMENT_CANDIDATES: u64 = 100;
+
+/// An unconfirmed transaction to be replaced, and the node's policy it is replaced under
+#[derive(Debug, Clone, Copy)]
+pub struct Stuck {
+    pub txid: Txid,
+    pub vsize: u64,
+    /// sat, with `prioritisetransaction` applied
+    pub fee: u64,
+    /// sat of the transaction and its descendants, all evicted by the replacement
+    pub descendant_fee: u64,
+    /// Including the transaction itself
+    pub descendant_count: u64,
+    /// It or an unconfirmed ancestor signals BIP125, or the node replaces anything
+    pub replaceable: bool,
+    /// sat/vB
+    pub mempool_min_fee_rate: f64,
+    pub incremental_relay_fee_rate: f64,
+}
+
+impl Stuck {
+    /// `txid` as in the mempool of `node`, `None` when it isn't there
+    pub async fn from_node(node: &dyn Node, txid: Txid) -> Result<Option<Self>> {
+        let entries = node.get_mempool_entries(&[txid]).await?;
+        let Some(entry) = entries.get(&txid) else {
+            return Ok(None);
+        };
+        let info = node.get_mempool_info().await?;
+        // BTC/kvB to sat/vB
+        let sat_vb = |fee_rate: Amount| fee_rate.to_sat() as f64 / 1000.;
+        Ok(Some(Stuck {
+            txid,
+            vsize: entry.vsize,
+            fee: entry.fees.modified.to_sat(),
+            descendant_fee: entry.fees.descendant.to_sat(),
+            descendant_count: entry.descendant_count,
+            replaceable: entry.bip125_replaceable || info.full_rbf,
+            mempool_min_fee_rate: sat_vb(info.mempool_min_fee),
+            incremental_relay_fee_rate: sat_vb(info.incremental_relay_fee),
+        }))
+    }
+}
+
+/// The least a replacement pays to be relayed and to confirm within a target
+#[derive(Debug, Clone, Serialize)]
+pub struct Bump {
+    pub txid: Txid,
+    pub vsize: u64,
+    /// sat
+    pub fee: u64,
+    /// sat/vB
+    pub fee_rate: f64,
+    /// Evicted with it, itself included
+    pub descendant_count: u64,
+    /// sat they pay together
+    pub descendant_fee: u64,
+    /// Nodes running the same policy accept a replacement at all
+    pub replaceable: bool,
+    /// Virtual size the replacement is assumed to have
+    pub replacement_vsize: u64,
+    /// sat the replacement pays at least to be relayed: the evicted fees plus the incremental
+    /// relay fee on its own vsize, a higher fee rate than the original and the minimum
+    /// mempool fee
+    pub relay_fee: u64,
+    pub target: u32,
+    pub confidence: f64,
+    /// sat/vB confirming within `target` at `confidence`
+    pub target_fee_rate: f64,
+    /// sat the replacement pays, the higher of the relay fee and the target fee rate on its
+    /// vsize
+    pub replacement_fee: u64,
+    /// sat/vB
+    pub replacement_fee_rate: f64,
+    /// sat more than the original
+    pub fee_delta: u64,
+    /// `relay` when relaying asks for more than the target, `target` otherwise
+    pub bound_by: &'static str,
+}
+
+impl Bump {
+    /// Replace `stuck` with a transaction of `replacement_vsize`, the original's by default,
+    /// paying at least `target_fee_rate` and whatever BIP125 asks for more
+    pub fn new(
+        stuck: &Stuck,
+        replacement_vsize: Option<u64>,
+        target: u32,
+        confidence: f64,
+        target_fee_rate: f64,
+    ) -> Result<Self> {
+        let vsize = replacement_vsize.unwrap_or(stuck.vsize);
+        if vsize == 0 {
+            bail!("the replacement has to be at least one vbyte");
+        }
+        if !target_fee_rate.is_finite() || target_fee_rate < 0. {
+            bail!("fee rate must be at least 0, got {target_fee_rate}");
+        }
+        let fee_rate = stuck.fee as f64 / stuck.vsize.max(1) as f64;
+        // rule 3 and 4: pay for what is evicted, and for relaying itself on top
+        let evicted =
+            stuck.descendant_fee + (stuck.incremental_relay_fee_rate * vsize as f64).ceil() as u64;
+        // rule 6: a higher fee rate than the original
+        let higher_rate = (fee_rate * vsize as f64).floor() as u64 + 1;
+        let mempool_min = (stuck.mempool_min_fee_rate * vsize as f64).ceil() as u64;
+        let relay_fee = evicted.max(higher_rate).max(mempool_min);
+        let target_fee = (target_fee_rate * vsize as f64).ceil() as u64;
+        let replacement_fee = relay_fee.max(target_fee);
+        Ok(Bump {
+            txid: stuck.txid,
+            vsize: stuck.vsize,
+            fee: stuck.fee,
+            fee_rate,
+            descendant_count: stuck.descendant_count,
+            descendant_fee: stuck.descendant_fee,
+            replaceable: stuck.replaceable && stuck.descendant_count <= MAX_REPLACEMENT_CANDIDATES,
+            replacement_vsize: vsize,
+            relay_fee,
+            target,
+            confidence,
+            target_fee_rate,
+            replacement_fee,
+            replacement_fee_rate: replacement_fee as f64 / vsize as f64,
+            fee_delta: replacement_fee.saturating_sub(stuck.fee),
+            bound_by: if relay_fee > target_fee {
+                "relay"
+            } else {
+                "target"
+            },
+        })
+    }
+}
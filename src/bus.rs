@@ -0,0 +1,289 @@
+use crate::{
+    dataset::{FileKind, SnapshotFile},
+    sink::Sink,
+};
+use anyhow::{bail, Context, Result};
+use bitcoin::Network;
+use polars::prelude::*;
+use reqwest::Url;
+use serde_json::{json, Map, Value};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        Mutex,
+    },
+};
+use tracing::{debug, info, warn};
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_SUBJECT: &str = "wtf.mempool";
+
+/// Where messages go
+#[derive(Debug, Clone)]
+enum Transport {
+    /// NATS core publish on `subject`
+    Nats { address: String, subject: String },
+    /// Kafka through a Confluent-compatible REST proxy
+    KafkaRest { url: Url },
+}
+
+/// Publishes full and delta rows as JSON to NATS (`nats://host:4222/subject`) or to Kafka
+/// through a REST proxy (`kafka+http://proxy:8082/topic`). Every row is a message of its own
+/// unless `?batch=true` is given, then every file is one message holding an array of rows.
+pub struct BusSink {
+    sender: UnboundedSender<Vec<Value>>,
+    network: String,
+    batch: bool,
+}
+
+impl BusSink {
+    pub fn start(url: &str, network: Network) -> Result<Self> {
+        let parsed = Url::parse(url).with_context(|| format!("parsing sink {url}"))?;
+        let batch = parsed
+            .query_pairs()
+            .any(|(key, value)| key == "batch" && value == "true");
+        let name = parsed.path().trim_start_matches('/');
+        let transport = match parsed.scheme() {
+            "nats" => Transport::Nats {
+                address: format!(
+                    "{}:{}",
+                    parsed.host_str().unwrap_or("localhost"),
+                    parsed.port().unwrap_or(4222)
+                ),
+                subject: if name.is_empty() {
+                    DEFAULT_SUBJECT.to_string()
+                } else {
+                    name.to_string()
+                },
+            },
+            scheme @ ("kafka+http" | "kafka+https") => {
+                if name.is_empty() {
+                    bail!("kafka sink {url} is missing a topic");
+                }
+                let port = parsed.port().map(|p| format!(":{p}")).unwrap_or_default();
+                let proxy = Url::parse(&format!(
+                    "{}://{}{port}/topics/{name}",
+                    scheme.trim_start_matches("kafka+"),
+                    parsed.host_str().unwrap_or("localhost"),
+                ))?;
+                Transport::KafkaRest { url: proxy }
+            }
+            _ => bail!("unsupported sink {url}"),
+        };
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(Self::run(transport, receiver));
+        Ok(BusSink {
+            sender,
+            network: network.to_string(),
+            batch,
+        })
+    }
+
+    async fn run(transport: Transport, mut receiver: UnboundedReceiver<Vec<Value>>) {
+        let client = reqwest::Client::new();
+        let mut nats: Option<Nats> = None;
+        while let Some(messages) = receiver.recv().await {
+            let mut delay = Duration::from_secs(1);
+            loop {
+                let result = match &transport {
+                    Transport::Nats { address, subject } => {
+                        Nats::publish_all(&mut nats, address, subject, &messages).await
+                    }
+                    Transport::KafkaRest { url } => Self::produce(&client, url, &messages).await,
+                };
+                match result {
+                    Ok(()) => break,
+                    Err(e) => {
+                        warn!("bus sink failed, retrying in {}s: {e:#}", delay.as_secs());
+                        tokio::time::sleep(delay).await;
+                        delay = (delay * 2).min(MAX_RETRY_DELAY);
+                    }
+                }
+            }
+            debug!("published {} messages", messages.len());
+        }
+    }
+
+    async fn produce(client: &reqwest::Client, url: &Url, messages: &[Value]) -> Result<()> {
+        let records: Vec<Value> = messages.iter().map(|m| json!({ "value": m })).collect();
+        let response = client
+            .post(url.clone())
+            .header("content-type", "application/vnd.kafka.json.v2+json")
+            .body(serde_json::to_vec(&json!({ "records": records }))?)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            bail!("kafka proxy returned {status}: {}", response.text().await?);
+        }
+        Ok(())
+    }
+
+    /// Rows of `frame` as JSON objects, tagged with where they were recorded
+    fn rows(&self, file: &SnapshotFile, frame: &DataFrame) -> Vec<Value> {
+        let mut rows: Vec<Map<String, Value>> = (0..frame.height())
+            .map(|_| {
+                let mut row = Map::new();
+                row.insert("network".into(), self.network.clone().into());
+                row.insert("height".into(), file.height.into());
+                row.insert("snapshot_timestamp".into(), file.timestamp.into());
+                row.insert("kind".into(), file.kind.as_str().into());
+                row
+            })
+            .collect();
+        for column in frame.get_columns() {
+            if column.name() == "network" {
+                continue;
+            }
+            for (row, value) in rows.iter_mut().zip(column.iter()) {
+                row.insert(column.name().to_string(), to_json(value));
+            }
+        }
+        rows.into_iter().map(Value::Object).collect()
+    }
+}
+
+impl Sink for BusSink {
+    fn name(&self) -> &str {
+        "bus"
+    }
+
+    fn accept(&self, file: &SnapshotFile, frame: &DataFrame) -> Result<()> {
+        if !matches!(file.kind, FileKind::Full | FileKind::Delta) || frame.height() == 0 {
+            return Ok(());
+        }
+        let rows = self.rows(file, frame);
+        let messages = if self.batch {
+            vec![Value::Array(rows)]
+        } else {
+            rows
+        };
+        self.sender.send(messages)?;
+        Ok(())
+    }
+}
+
+fn to_json(value: AnyValue) -> Value {
+    match value {
+        AnyValue::Null => Value::Null,
+        AnyValue::Boolean(v) => v.into(),
+        AnyValue::Utf8(v) => v.into(),
+        AnyValue::UInt32(v) => v.into(),
+        AnyValue::UInt64(v) => v.into(),
+        AnyValue::Int32(v) => v.into(),
+        AnyValue::Int64(v) => v.into(),
+        AnyValue::Float32(v) => v.into(),
+        AnyValue::Float64(v) => v.into(),
+        other => other.to_string().into(),
+    }
+}
+
+/// Just enough of the NATS client protocol to publish
+struct Nats {
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl Nats {
+    async fn connect(address: &str) -> Result<Self> {
+        let (read, mut writer) = TcpStream::connect(address).await?.into_split();
+        let mut reader = BufReader::new(read);
+        let mut info = String::new();
+        reader.read_line(&mut info).await?;
+        if !info.starts_with("INFO") {
+            bail!("{address} is not a NATS server");
+        }
+        writer
+            .write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"wtf\"}\r\n")
+            .await?;
+        info!("connected to nats at {address}");
+
+        let nats = Nats {
+            writer: Arc::new(Mutex::new(writer)),
+            closed: Arc::new(AtomicBool::new(false)),
+        };
+        tokio::spawn(Self::answer_pings(
+            reader,
+            nats.writer.clone(),
+            nats.closed.clone(),
+        ));
+        Ok(nats)
+    }
+
+    /// The server drops clients that don't answer its keep-alive pings
+    async fn answer_pings(
+        mut reader: BufReader<OwnedReadHalf>,
+        writer: Arc<Mutex<OwnedWriteHalf>>,
+        closed: Arc<AtomicBool>,
+    ) {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match reader.read_line(&mut line).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) if line.starts_with("PING") => {
+                    if writer.lock().await.write_all(b"PONG\r\n").await.is_err() {
+                        break;
+                    }
+                }
+                Ok(_) if line.starts_with("-ERR") => warn!("nats error: {}", line.trim()),
+                Ok(_) => {}
+            }
+        }
+        closed.store(true, Ordering::Relaxed);
+    }
+
+    /// Publish on the current connection, reconnecting first if it was lost
+    async fn publish_all(
+        connection: &mut Option<Nats>,
+        address: &str,
+        subject: &str,
+        messages: &[Value],
+    ) -> Result<()> {
+        if connection
+            .as_ref()
+            .is_some_and(|c| c.closed.load(Ordering::Relaxed))
+        {
+            *connection = None;
+        }
+        if connection.is_none() {
+            *connection = Some(Nats::connect(address).await?);
+        }
+        let result = connection
+            .as_ref()
+            .unwrap()
+            .publish(subject, messages)
+            .await;
+        if result.is_err() {
+            *connection = None;
+        }
+        result
+    }
+
+    async fn publish(&self, subject: &str, messages: &[Value]) -> Result<()> {
+        let mut buffer = Vec::new();
+        for message in messages {
+            let payload = serde_json::to_vec(message)?;
+            buffer.extend(format!("PUB {subject} {}\r\n", payload.len()).as_bytes());
+            buffer.extend(payload);
+            buffer.extend(b"\r\n");
+        }
+        let mut writer = self.writer.lock().await;
+        writer.write_all(&buffer).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+}
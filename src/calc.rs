@@ -0,0 +1,648 @@
+use crate::{
+    dataset::{Dataset, FileKind},
+    model::Trained,
+    replay::{Replay, Snapshot},
+    storage::Storage,
+    template::Template,
+    unit::FeeUnit,
+};
+use anyhow::{bail, Result};
+use bitcoin::Txid;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use tracing::info;
+
+/// Block weight available to transactions (4M WU minus the reserve Core keeps for the coinbase)
+pub(crate) const BLOCK_TX_WEIGHT: f64 = 3_996_000.;
+/// Fee rate reported when the whole mempool fits into the next block
+pub const MIN_RELAY_FEE_RATE: f64 = 1.;
+/// Only snapshots this recent take part in the estimate
+pub const WINDOW_SECS: i64 = 60 * 60;
+/// Confirmations this recent tell how long fee rates waited
+pub(crate) const HISTORY_SECS: i64 = 7 * 24 * 60 * 60;
+/// Recorded confirmations judged together when estimating from history
+const MIN_SAMPLES: usize = 50;
+/// Confirmation targets estimated when none is asked for, in blocks
+pub const TARGETS: [u32; 5] = [1, 3, 6, 12, 144];
+/// Time horizons of the matrix in minutes, a block taking ten
+pub const HORIZONS_MINUTES: [u32; 6] = [30, 60, 180, 360, 720, 1440];
+/// Confidence levels of the matrix when none are asked for
+pub const MATRIX_CONFIDENCES: [f64; 4] = [0.5, 0.8, 0.9, 0.95];
+
+/// Preset confidence levels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Band {
+    /// 0.5, 0.8 and 0.95, the low, medium and high options of a wallet
+    Wallet,
+    /// 0.25, 0.5 and 0.75
+    Quartiles,
+    /// 0.1, 0.25, 0.5, 0.75, 0.9, 0.95 and 0.99
+    Fine,
+}
+
+impl Band {
+    pub fn levels(self) -> &'static [f64] {
+        match self {
+            Band::Wallet => &[0.5, 0.8, 0.95],
+            Band::Quartiles => &[0.25, 0.5, 0.75],
+            Band::Fine => &[0.1, 0.25, 0.5, 0.75, 0.9, 0.95, 0.99],
+        }
+    }
+}
+
+/// Presets by name
+pub type Presets = BTreeMap<String, Preset>;
+
+/// A confidence and target under a name, for those who'd rather not pick them
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    pub confidence: f64,
+    pub target: u32,
+}
+
+impl Preset {
+    /// Known without a config file, `[preset.NAME]` in it changes them or adds others
+    pub const DEFAULTS: [(&'static str, Preset); 4] = [
+        (
+            "economy",
+            Preset {
+                confidence: 0.8,
+                target: 144,
+            },
+        ),
+        (
+            "standard",
+            Preset {
+                confidence: 0.8,
+                target: 6,
+            },
+        ),
+        (
+            "priority",
+            Preset {
+                confidence: 0.9,
+                target: 3,
+            },
+        ),
+        (
+            "urgent",
+            Preset {
+                confidence: 0.95,
+                target: 1,
+            },
+        ),
+    ];
+
+    /// [`Preset::DEFAULTS`] with `configured` over them
+    pub fn all(configured: &Presets) -> Presets {
+        let mut presets: Presets = Self::DEFAULTS
+            .iter()
+            .map(|(name, preset)| (name.to_string(), *preset))
+            .collect();
+        presets.extend(
+            configured
+                .iter()
+                .map(|(name, preset)| (name.clone(), *preset)),
+        );
+        presets
+    }
+
+    pub fn find(presets: &Presets, name: &str) -> Result<Preset> {
+        match presets.get(name) {
+            Some(preset) => Ok(*preset),
+            None => bail!(
+                "unknown preset {name}, expected one of {}",
+                presets.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+}
+
+/// A recorded transaction confirmed by a block
+#[derive(Debug, Clone, Copy)]
+pub struct Confirmation {
+    pub txid: Txid,
+    pub height: u64,
+    /// Unix timestamp the block was recorded at
+    pub confirmed_at: i64,
+    pub fee_rate_sat_vb: f64,
+    pub wait_blocks: u64,
+    /// Paid well below what its block was projected to take, likely prioritised out of band
+    pub out_of_band: bool,
+}
+
+/// Fee rate expected to confirm within a number of blocks
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct TargetEstimate {
+    pub confidence: f64,
+    pub target: u32,
+    /// The higher of the two below, sat/vB
+    pub fee_rate_sat_vb: f64,
+    /// Lowest fee rate in the last of the projected `target` blocks of the latest snapshot
+    pub projected_sat_vb: f64,
+    /// Lowest fee rate at which `confidence` of the recorded confirmations waited at most
+    /// `target` blocks, if enough were recorded
+    pub historical_sat_vb: Option<f64>,
+    /// Recorded confirmations the historical fee rate is based on
+    pub confirmations: usize,
+}
+
+/// Fee rates by confidence and time horizon, like whatthefee.io shows them
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Matrix {
+    pub confidences: Vec<f64>,
+    pub horizons_minutes: Vec<u32>,
+    /// A row per confidence and a column per horizon
+    pub fee_rates: Vec<Vec<f64>>,
+    /// Of the fee rates if converted, sat/vB otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<FeeUnit>)]
+    pub unit: Option<&'static str>,
+}
+
+impl Matrix {
+    /// The fee rates converted to `unit`
+    pub fn convert(mut self, unit: FeeUnit) -> Self {
+        if unit != FeeUnit::SatVb {
+            for rate in self.fee_rates.iter_mut().flatten() {
+                *rate = unit.convert(*rate);
+            }
+            self.unit = Some(unit.as_str());
+        }
+        self
+    }
+}
+
+/// mempool.space's `/api/v1/fees/recommended`, whole sat/vB
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RecommendedFees {
+    /// Next block
+    pub fastest_fee: u64,
+    /// Within 3 blocks
+    pub half_hour_fee: u64,
+    /// Within 6 blocks
+    pub hour_fee: u64,
+    pub economy_fee: u64,
+    pub minimum_fee: u64,
+}
+
+pub struct Calc;
+
+impl Calc {
+    /// Estimate the fee rate in sat/vB that would have made it into the next `target` blocks in
+    /// `confidence` of the snapshots recorded during the last hour.
+    pub fn calc(storage: &dyn Storage, confidence: f64, target: u32) -> Result<f64> {
+        Ok(Self::quantiles(storage, &[confidence], target)?[0])
+    }
+
+    /// [`Calc::calc`] for each of `confidences`, walking the snapshots once
+    #[tracing::instrument(skip(storage))]
+    pub fn quantiles(storage: &dyn Storage, confidences: &[f64], target: u32) -> Result<Vec<f64>> {
+        Self::check_confidences(confidences)?;
+        if target == 0 {
+            bail!("target must be at least one block");
+        }
+
+        let replay = Replay::new(storage)?;
+        let Some(latest) = replay.latest() else {
+            bail!("no recorded snapshots found");
+        };
+        let mut cutoffs = Vec::new();
+        replay.walk(latest - WINDOW_SECS, latest, |snapshot| {
+            cutoffs.push(Self::block_cutoff(snapshot, target));
+            Ok(())
+        })?;
+
+        if cutoffs.is_empty() {
+            bail!("no complete mempool state could be reconstructed");
+        }
+        cutoffs.sort_by(f64::total_cmp);
+        let estimates: Vec<f64> = confidences
+            .iter()
+            .map(|confidence| Self::quantile(&cutoffs, *confidence))
+            .collect();
+
+        info!(
+            "snapshots: {}, estimates_sat_vb: {:.2?}",
+            cutoffs.len(),
+            estimates
+        );
+        Ok(estimates)
+    }
+
+    /// The value `confidence` of the sorted, non-empty `values` are at or below
+    pub fn quantile(values: &[f64], confidence: f64) -> f64 {
+        let rank = ((confidence * values.len() as f64).ceil() as usize).max(1) - 1;
+        values[rank.min(values.len() - 1)]
+    }
+
+    fn check_confidences(confidences: &[f64]) -> Result<()> {
+        if confidences.is_empty() {
+            bail!("no confidence given");
+        }
+        for confidence in confidences {
+            if !(*confidence > 0. && *confidence <= 1.) {
+                bail!("confidence must be in (0, 1], got {confidence}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Estimate the fee rate in sat/vB that confirms within each of `targets` blocks at each of
+    /// `confidences`, from the projected blocks of the latest snapshot and the wait of the
+    /// transactions confirmed during the last week
+    pub fn targets(
+        storage: &dyn Storage,
+        targets: &[u32],
+        confidences: &[f64],
+    ) -> Result<Vec<TargetEstimate>> {
+        Self::targets_excluding(storage, targets, confidences, &HashSet::new())
+    }
+
+    /// [`Calc::targets`] projecting the blocks without the transactions in `exclude`, like a
+    /// service's own transactions it is about to replace. Only the projection changes, the
+    /// recorded waits stay what they were.
+    #[tracing::instrument(skip(storage, exclude), fields(excluded = exclude.len()))]
+    pub fn targets_excluding(
+        storage: &dyn Storage,
+        targets: &[u32],
+        confidences: &[f64],
+        exclude: &HashSet<Txid>,
+    ) -> Result<Vec<TargetEstimate>> {
+        Self::check_confidences(confidences)?;
+        if targets.contains(&0) {
+            bail!("target must be at least one block");
+        }
+
+        let replay = Replay::new(storage)?;
+        let Some(latest) = replay.latest() else {
+            bail!("no recorded snapshots found");
+        };
+        let mut snapshot = replay.at(latest)?;
+        if !exclude.is_empty() {
+            let exclude: HashSet<String> = exclude.iter().map(Txid::to_string).collect();
+            let before = snapshot.transactions.len();
+            snapshot
+                .transactions
+                .retain(|txid, _| !exclude.contains(txid));
+            info!("excluded: {}", before - snapshot.transactions.len());
+        }
+        let max_target = targets.iter().copied().max().unwrap_or(1);
+        let template = Template::from_snapshot(&snapshot, max_target as usize);
+        // what a miner was paid on the side says nothing about what a fee rate buys
+        let confirmations: Vec<Confirmation> = Self::confirmations(storage, latest - HISTORY_SECS)?
+            .into_iter()
+            .filter(|c| !c.out_of_band)
+            .collect();
+
+        let mut estimates = Vec::with_capacity(targets.len() * confidences.len());
+        for (&confidence, &target) in confidences
+            .iter()
+            .flat_map(|c| targets.iter().map(move |t| (c, t)))
+        {
+            let projected_sat_vb = Self::projected_cutoff(&template, target);
+            let historical_sat_vb = Self::historical(&confirmations, target, confidence);
+            let fee_rate_sat_vb = historical_sat_vb.map_or(projected_sat_vb, |historical| {
+                historical.max(projected_sat_vb)
+            });
+            info!(
+                "target: {target}, confirmations: {}, estimate_sat_vb: {fee_rate_sat_vb:.2}",
+                confirmations.len()
+            );
+            estimates.push(TargetEstimate {
+                confidence,
+                target,
+                fee_rate_sat_vb,
+                projected_sat_vb,
+                historical_sat_vb,
+                confirmations: confirmations.len(),
+            });
+        }
+        Ok(estimates)
+    }
+
+    /// [`Calc::targets`] for each of [`HORIZONS_MINUTES`] at each of `confidences`
+    pub fn matrix(storage: &dyn Storage, confidences: &[f64]) -> Result<Matrix> {
+        let targets: Vec<u32> = HORIZONS_MINUTES.iter().map(|m| m / 10).collect();
+        let estimates = Self::targets(storage, &targets, confidences)?;
+        Ok(Matrix {
+            confidences: confidences.to_vec(),
+            horizons_minutes: HORIZONS_MINUTES.to_vec(),
+            fee_rates: estimates
+                .chunks(targets.len())
+                .map(|row| row.iter().map(|e| e.fee_rate_sat_vb).collect())
+                .collect(),
+            unit: None,
+        })
+    }
+
+    /// Fee rates for 1, 3 and 6 blocks in the shape of mempool.space. Like mempool.space, the
+    /// economy fee is at most twice the minimum and no fee is below the next lower one.
+    pub fn recommended(storage: &dyn Storage, confidence: f64) -> Result<RecommendedFees> {
+        let estimates = Self::targets(storage, &[1, 3, 6], &[confidence])?;
+        let whole = |fee_rate: f64| fee_rate.ceil() as u64;
+        let minimum_fee = whole(Self::minimum_fee(storage)?);
+        let hour_fee = whole(estimates[2].fee_rate_sat_vb).max(minimum_fee);
+        let half_hour_fee = whole(estimates[1].fee_rate_sat_vb).max(hour_fee);
+        let fastest_fee = whole(estimates[0].fee_rate_sat_vb).max(half_hour_fee);
+        Ok(RecommendedFees {
+            fastest_fee,
+            half_hour_fee,
+            hour_fee,
+            economy_fee: (2 * minimum_fee).min(hour_fee),
+            minimum_fee,
+        })
+    }
+
+    /// Lowest fee rate the node took into its mempool at the latest snapshot
+    pub(crate) fn minimum_fee(storage: &dyn Storage) -> Result<f64> {
+        let latest = storage
+            .list()?
+            .into_iter()
+            .filter(|file| file.kind == FileKind::Meta)
+            .max_by_key(|file| file.timestamp);
+        let Some(file) = latest else {
+            return Ok(MIN_RELAY_FEE_RATE);
+        };
+        let frame = storage.read(&file)?;
+        let mut minimum = MIN_RELAY_FEE_RATE;
+        for column in ["mempool_min_fee_sat_vb", "min_relay_tx_fee_sat_vb"] {
+            if let Some(fee_rate) = frame.column(column)?.f64()?.get(0) {
+                minimum = minimum.max(fee_rate);
+            }
+        }
+        Ok(minimum)
+    }
+
+    /// Every confirmation recorded since `since`, leaving out blocks a reorg disconnected
+    pub fn confirmations(storage: &dyn Storage, since: i64) -> Result<Vec<Confirmation>> {
+        let files: Vec<_> = storage
+            .list()?
+            .into_iter()
+            .filter(|f| f.timestamp >= since)
+            .collect();
+        let mut disconnected: HashSet<String> = HashSet::new();
+        for file in files.iter().filter(|f| f.kind == FileKind::Reorg) {
+            let frame = storage.read(file)?;
+            let hashes = frame.column("disconnected_hash")?.utf8()?;
+            disconnected.extend(hashes.into_iter().flatten().map(String::from));
+        }
+
+        let mut confirmations = Vec::new();
+        for file in files.iter().filter(|f| f.kind == FileKind::Block) {
+            let frame = storage.read(file)?;
+            // labels written before reorgs were tracked don't name their block
+            if let Ok(hashes) = frame.column("block_hash") {
+                let hash = hashes.utf8()?.get(0);
+                if hash.is_some_and(|hash| disconnected.contains(hash)) {
+                    continue;
+                }
+            }
+            let txids = frame.column("txid")?.utf8()?;
+            let weights = frame.column("weight")?.f64()?;
+            let fees = frame.column("fee_sat")?.f64()?;
+            let wait_blocks = frame.column("wait_blocks")?.u64()?;
+            // labels written before out-of-band confirmations were flagged have none
+            let out_of_band: Vec<bool> = match frame.column("out_of_band") {
+                Ok(column) => column
+                    .bool()?
+                    .into_iter()
+                    .map(|v| v == Some(true))
+                    .collect(),
+                Err(_) => vec![false; frame.height()],
+            };
+            for ((((txid, weight), fee), wait), out_of_band) in txids
+                .into_iter()
+                .zip(weights)
+                .zip(fees)
+                .zip(wait_blocks)
+                .zip(out_of_band)
+            {
+                let (Some(txid), Some(weight), Some(fee), Some(wait)) = (txid, weight, fee, wait)
+                else {
+                    continue;
+                };
+                let Ok(txid) = txid.parse() else {
+                    continue;
+                };
+                if weight > 0. {
+                    confirmations.push(Confirmation {
+                        txid,
+                        height: file.height,
+                        confirmed_at: file.timestamp,
+                        fee_rate_sat_vb: fee / (weight / 4.),
+                        wait_blocks: wait,
+                        out_of_band,
+                    });
+                }
+            }
+        }
+        Ok(confirmations)
+    }
+
+    /// Going down from the highest fee rate in groups of [`MIN_SAMPLES`] confirmations, the
+    /// lowest fee rate of the last group of which `confidence` waited at most `target` blocks.
+    /// Like Bitcoin Core's estimator it stops at the first group that falls short.
+    fn historical(confirmations: &[Confirmation], target: u32, confidence: f64) -> Option<f64> {
+        let mut confirmations = confirmations.to_vec();
+        confirmations.sort_by(|a, b| b.fee_rate_sat_vb.total_cmp(&a.fee_rate_sat_vb));
+        let mut estimate = None;
+        for group in confirmations.chunks_exact(MIN_SAMPLES) {
+            let within = group
+                .iter()
+                .filter(|c| c.wait_blocks <= target as u64)
+                .count();
+            if (within as f64) < confidence * group.len() as f64 {
+                break;
+            }
+            estimate = group
+                .last()
+                .map(|c| c.fee_rate_sat_vb.max(MIN_RELAY_FEE_RATE));
+        }
+        estimate
+    }
+
+    /// Lowest fee rate (sat/vB) still included in block `target` of the projected blocks,
+    /// see [`Template::from_snapshot`]
+    pub fn block_cutoff(snapshot: &Snapshot, target: u32) -> f64 {
+        Self::projected_cutoff(&Template::from_snapshot(snapshot, target as usize), target)
+    }
+
+    /// Lowest fee rate (sat/vB) of block `target` of `template`, the relay fee when it has
+    /// room left
+    pub fn projected_cutoff(template: &Template, target: u32) -> f64 {
+        template
+            .fee_rate_for_block(target as usize)
+            .unwrap_or(MIN_RELAY_FEE_RATE)
+            .max(MIN_RELAY_FEE_RATE)
+    }
+}
+
+/// Fee estimates from a dataset, for programs embedding WhatTheFee. With a trained model, fee
+/// rates for a target come from it and the latest snapshot, like `serve --model-file`.
+pub struct Estimator {
+    dataset: Dataset,
+    model: Option<Trained>,
+}
+
+impl Estimator {
+    /// Estimate from the recorded `dataset` like `wtf calc`
+    pub fn new(dataset: Dataset) -> Self {
+        Estimator {
+            dataset,
+            model: None,
+        }
+    }
+
+    /// Estimate with `model` instead
+    pub fn with_model(mut self, model: Trained) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    pub fn dataset(&self) -> &Dataset {
+        &self.dataset
+    }
+
+    /// Fee rate in sat/vB confirming within `target` blocks at `confidence`
+    pub fn estimate(&self, target: u32, confidence: f64) -> Result<f64> {
+        let storage = self.dataset.storage();
+        match &self.model {
+            Some(model) => model.estimate(&self.dataset.snapshot_at(i64::MAX)?, target, confidence),
+            None => Calc::calc(storage, confidence, target),
+        }
+    }
+
+    /// [`Calc::targets`] of the dataset
+    pub fn targets(&self, targets: &[u32], confidences: &[f64]) -> Result<Vec<TargetEstimate>> {
+        Calc::targets(self.dataset.storage(), targets, confidences)
+    }
+
+    /// [`Calc::recommended`] of the dataset
+    pub fn recommended(&self, confidence: f64) -> Result<RecommendedFees> {
+        Calc::recommended(self.dataset.storage(), confidence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{replay::Transaction, storage::LocalStorage};
+    use bitcoin::{hashes::Hash, Network};
+    use chrono::{TimeZone, Utc};
+
+    /// Transactions of a quarter block each, paying `fee_rates` in sat/vB
+    fn snapshot(fee_rates: &[f64]) -> Snapshot {
+        let weight = BLOCK_TX_WEIGHT / 4.;
+        let mut snapshot = Snapshot::new(800_000, 1_700_000_000);
+        for (index, fee_rate) in fee_rates.iter().enumerate() {
+            let tx = Transaction {
+                weight,
+                fee_sat: fee_rate * weight / 4.,
+                first_seen_at: None,
+                effective_fee_rate: None,
+            };
+            snapshot.transactions.insert(format!("{index}"), tx);
+        }
+        snapshot
+    }
+
+    #[test]
+    fn estimate_is_the_cutoff_of_the_target_blocks() {
+        let full = snapshot(&[5., 50., 10., 20., 40., 30.]);
+        assert_eq!(Calc::block_cutoff(&full, 1), 20.);
+        // the mempool doesn't fill two blocks
+        assert_eq!(Calc::block_cutoff(&full, 2), MIN_RELAY_FEE_RATE);
+        assert_eq!(
+            Calc::block_cutoff(&snapshot(&[0.5; 4]), 1),
+            MIN_RELAY_FEE_RATE
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(dir.path(), Network::Regtest);
+        let now = Utc.timestamp_opt(full.timestamp, 0).unwrap();
+        storage
+            .write(
+                now,
+                full.height,
+                FileKind::Full,
+                &mut full.to_frame().unwrap(),
+            )
+            .unwrap();
+        assert_eq!(Calc::calc(&storage, 0.5, 1).unwrap(), 20.);
+        assert!(Calc::calc(&storage, 0., 1).is_err());
+        assert!(Calc::calc(&storage, 0.5, 0).is_err());
+    }
+
+    #[test]
+    fn confidences_are_within_zero_and_one() {
+        assert!(Calc::check_confidences(&[0.5, 0.95, 1.]).is_ok());
+        assert!(Calc::check_confidences(&[]).is_err());
+        assert!(Calc::check_confidences(&[0.5, 0.]).is_err());
+        assert!(Calc::check_confidences(&[1.5]).is_err());
+        assert!(Calc::check_confidences(&[f64::NAN]).is_err());
+    }
+
+    #[test]
+    fn quantile_is_the_value_that_many_are_at_or_below() {
+        let values = [1., 2., 3., 4.];
+        assert_eq!(Calc::quantile(&values, 0.25), 1.);
+        assert_eq!(Calc::quantile(&values, 0.5), 2.);
+        assert_eq!(Calc::quantile(&values, 0.51), 3.);
+        assert_eq!(Calc::quantile(&values, 1.), 4.);
+        assert_eq!(Calc::quantile(&values, 0.01), 1.);
+        assert_eq!(Calc::quantile(&[7.], 0.95), 7.);
+    }
+
+    #[test]
+    fn historical_stops_at_the_first_group_waiting_too_long() {
+        // the 50 paying the most waited a block, the 50 below them five
+        let confirmations: Vec<Confirmation> = (1..=100)
+            .rev()
+            .map(|fee_rate| Confirmation {
+                txid: Txid::all_zeros(),
+                height: 800_000,
+                confirmed_at: 1_700_000_000,
+                fee_rate_sat_vb: fee_rate as f64,
+                wait_blocks: if fee_rate > 50 { 1 } else { 5 },
+                out_of_band: false,
+            })
+            .collect();
+        assert_eq!(Calc::historical(&confirmations, 1, 0.9), Some(51.));
+        assert_eq!(
+            Calc::historical(&confirmations, 6, 0.9),
+            Some(MIN_RELAY_FEE_RATE)
+        );
+        // not enough confirmations to judge by
+        assert_eq!(
+            Calc::historical(&confirmations[..MIN_SAMPLES - 1], 1, 0.5),
+            None
+        );
+    }
+
+    #[test]
+    fn histogram_snapshots_project_blocks_too() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(dir.path(), Network::Regtest);
+        // 600 transactions of 1,000 vB in each bucket, a block and a fifth in all
+        let mut histogram = DataFrame::new(vec![
+            Series::new("fee_rate_sat_vb", [50., 20.]),
+            Series::new("txs", [600u64, 600]),
+            Series::new("vsize", [600_000u64, 600_000]),
+        ])
+        .unwrap();
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        storage
+            .write(now, 800_000, FileKind::Histogram, &mut histogram)
+            .unwrap();
+        let estimates =
+            Calc::targets_excluding(&storage, &[1, 2], &[0.5], &HashSet::new()).unwrap();
+        assert_eq!(estimates[0].projected_sat_vb, 20.);
+        assert_eq!(estimates[0].fee_rate_sat_vb, 20.);
+        assert_eq!(estimates[1].projected_sat_vb, MIN_RELAY_FEE_RATE);
+    }
+}
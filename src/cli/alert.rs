@@ -0,0 +1,83 @@
+use super::Context;
+use crate::alert::FeeAlerts;
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct AlertArgs {
+    /// Confirmation target in blocks to watch [default: 1]
+    #[arg(short, long)]
+    target: Option<u32>,
+    /// Confidence of the estimate [default: 0.95]
+    #[arg(short, long)]
+    confidence: Option<f64>,
+    /// Alert when the estimate falls to this sat/vB or below, may be repeated
+    #[arg(long)]
+    below: Vec<f64>,
+    /// Alert when the estimate rises to this sat/vB or above, may be repeated
+    #[arg(long)]
+    above: Vec<f64>,
+    /// Alert when the estimate moves by this many percent within --within minutes
+    #[arg(long)]
+    change_pct: Option<f64>,
+    /// Minutes a --change-pct move has to happen within [default: 60]
+    #[arg(long)]
+    within: Option<u32>,
+    /// URL to POST each alert to as JSON
+    #[arg(long)]
+    webhook: Option<String>,
+    /// Shell command to run on each alert, with the alert as JSON in WTF_ALERT
+    #[arg(long)]
+    command: Option<String>,
+    /// Seconds between looks for a new snapshot [default: 10]
+    #[arg(long)]
+    poll: Option<u64>,
+}
+
+pub(crate) async fn run(args: AlertArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        ..
+    } = context;
+    let AlertArgs {
+        target,
+        confidence,
+        below,
+        above,
+        change_pct,
+        within,
+        webhook,
+        command,
+        poll,
+    } = args;
+    let alert = config.alert;
+    let below = if below.is_empty() { alert.below } else { below };
+    let above = if above.is_empty() { alert.above } else { above };
+    let within_minutes = within.or(alert.within_minutes).unwrap_or(60);
+    let change = change_pct
+        .or(alert.change_pct)
+        .map(|pct| (pct, within_minutes as i64 * 60));
+    let mut alerts = FeeAlerts::new(below, above, change)?;
+    if let Some(url) = webhook.or(alert.webhook) {
+        alerts = alerts.with_webhook(url);
+    }
+    if let Some(command) = command.or(alert.command) {
+        alerts = alerts.with_command(command);
+    }
+    let confidence = confidence
+        .or(alert.confidence)
+        .or(config.calc.confidence.first().copied())
+        .unwrap_or(0.95);
+    alerts
+        .watch(
+            storage_kind.open(&data_dir, network)?.as_ref(),
+            target.or(alert.target).unwrap_or(1),
+            confidence,
+            std::time::Duration::from_secs(poll.or(alert.poll).unwrap_or(10)),
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use super::{parse_timestamp, Context};
+use crate::{backtest::Backtest, calc::TARGETS};
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct BacktestArgs {
+    /// Unix timestamp or RFC 3339 date of the first estimate [default: the first snapshot]
+    #[arg(long, value_parser = parse_timestamp)]
+    from: Option<i64>,
+    /// Unix timestamp or RFC 3339 date of the last estimate [default: the last snapshot]
+    #[arg(long, value_parser = parse_timestamp)]
+    to: Option<i64>,
+    /// Seconds between estimates
+    #[arg(long, default_value_t = 600)]
+    every: i64,
+    /// Confirmation target in blocks, may be repeated [default: 1, 3, 6, 12 and 144]
+    #[arg(short, long)]
+    target: Vec<u32>,
+    /// Confidence percentage [default: 0.95]
+    #[arg(short, long)]
+    confidence: Option<f64>,
+}
+
+pub(crate) async fn run(args: BacktestArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let BacktestArgs {
+        from,
+        to,
+        every,
+        target,
+        confidence,
+    } = args;
+    let json = json_output;
+    let targets = if target.is_empty() {
+        TARGETS.to_vec()
+    } else {
+        target
+    };
+    let confidence = confidence
+        .or(config.calc.confidence.first().copied())
+        .unwrap_or(0.95);
+    let report = Backtest::run(
+        storage_kind.open(&data_dir, network)?.as_ref(),
+        from.unwrap_or(i64::MIN),
+        to.unwrap_or(i64::MAX),
+        every,
+        &targets,
+        confidence,
+    )?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!(
+            "confidence {}, estimates every {every} s",
+            report.confidence
+        );
+        let sources = [("wtf", &report.targets), ("core", &report.core)];
+        println!(
+            "{:>6} {:>6} {:>8} {:>8} {:>9} {:>13} {:>15} {:>9}",
+            "",
+            "target",
+            "scored",
+            "misses",
+            "miss rate",
+            "mean overpay",
+            "median overpay",
+            "unscored"
+        );
+        for (source, targets) in sources {
+            for target in targets {
+                println!(
+                    "{source:>6} {:>6} {:>8} {:>8} {:>8.1}% {:>12.1}% {:>14.1}% {:>9}",
+                    target.target,
+                    target.scored,
+                    target.misses,
+                    target.miss_rate * 100.,
+                    target.mean_overpay_pct,
+                    target.median_overpay_pct,
+                    target.unscored
+                );
+            }
+        }
+        for comparison in &report.comparisons {
+            println!(
+                "target {}: better than core {} times, worse {} times, out of {}",
+                comparison.target, comparison.better, comparison.worse, comparison.compared
+            );
+        }
+    }
+    Ok(())
+}
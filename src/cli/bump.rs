@@ -0,0 +1,99 @@
+use super::{Context, NodeArgs};
+use crate::{
+    bump::{Bump, Stuck},
+    calc::Calc,
+    model::Trained,
+    replay::Replay,
+};
+use anyhow::{bail, Result};
+use bitcoin::Txid;
+use clap::Args;
+use std::path::PathBuf;
+use tracing::warn;
+
+#[derive(Args)]
+pub(crate) struct BumpArgs {
+    /// Of the stuck transaction
+    #[arg(long)]
+    txid: Txid,
+    /// Blocks to confirm within
+    #[arg(short, long)]
+    target: u32,
+    /// Probability of confirming within the target [default: 0.95]
+    #[arg(short, long)]
+    confidence: Option<f64>,
+    /// Virtual size of the replacement once signed [default: the original's]
+    #[arg(long)]
+    vsize: Option<u64>,
+    /// Estimate with a model saved by `train` or an ONNX model taking the same features
+    #[arg(long)]
+    model_file: Option<PathBuf>,
+    #[command(flatten)]
+    node: NodeArgs,
+}
+
+pub(crate) async fn run(args: BumpArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let BumpArgs {
+        txid,
+        target,
+        confidence,
+        vsize,
+        model_file,
+        node,
+    } = args;
+    let json = json_output;
+    let confidence = confidence
+        .or(config.calc.confidence.first().copied())
+        .unwrap_or(0.95);
+    if !(confidence > 0. && confidence <= 1.) || target == 0 {
+        bail!("confidence must be in (0, 1] and target at least 1");
+    }
+    let node = node.or(&config.record).node(network, &data_dir).await?;
+    let Some(stuck) = Stuck::from_node(node.as_ref(), txid).await? else {
+        bail!("{txid} is not in the mempool");
+    };
+    let storage = storage_kind.open(&data_dir, network)?;
+    let fee_rate = match model_file.or(config.calc.model_file) {
+        Some(path) => Trained::load(&path)?.estimate(
+            &Replay::new(storage.as_ref())?.at(i64::MAX)?,
+            target,
+            confidence,
+        )?,
+        None => Calc::calc(storage.as_ref(), confidence, target)?,
+    };
+    let bump = Bump::new(&stuck, vsize, target, confidence, fee_rate)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&bump)?);
+        return Ok(());
+    }
+    println!(
+        "{}: {} vB paying {} sat, {:.2} sat/vB, {} sat with {} descendants",
+        bump.txid,
+        bump.vsize,
+        bump.fee,
+        bump.fee_rate,
+        bump.descendant_fee,
+        bump.descendant_count.saturating_sub(1)
+    );
+    println!(
+        "a replacement of {} vB relays from {} sat, {target} blocks at {confidence} needs \
+         {:.2} sat/vB",
+        bump.replacement_vsize, bump.relay_fee, bump.target_fee_rate
+    );
+    println!(
+        "pay {} sat, {:.2} sat/vB, {} sat more, set by the {}",
+        bump.replacement_fee, bump.replacement_fee_rate, bump.fee_delta, bump.bound_by
+    );
+    if !bump.replaceable {
+        warn!("the node doesn't replace it, it doesn't signal BIP125 or evicts too many");
+    }
+    Ok(())
+}
@@ -0,0 +1,484 @@
+use super::{confidences, price_feed, Context, NodeArgs};
+use crate::{
+    calc::{Band, Calc, Preset, MATRIX_CONFIDENCES, TARGETS},
+    eta::TxEta,
+    explain::Explain,
+    model::{Model, Trained, TRAIN_EVERY_SECS},
+    position::QueuePosition,
+    price::PriceFeed,
+    replay::Replay,
+    rollout::Rollout,
+    storage::Storage,
+    unit::FeeUnit,
+};
+use anyhow::{bail, Result};
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub(crate) struct CalcArgs {
+    /// Confidence percentage, several separated by commas [default: 0.95]
+    #[arg(short, long, value_delimiter = ',')]
+    confidence: Vec<f64>,
+    /// Preset confidence levels instead of --confidence
+    #[arg(long, value_enum, conflicts_with = "confidence")]
+    band: Option<Band>,
+    /// Confidence and target by name: economy (144 blocks at 0.8), standard (6 at 0.8),
+    /// priority (3 at 0.9), urgent (1 at 0.95) or one set in `[preset.NAME]` of the
+    /// config file
+    #[arg(long, conflicts_with_all = ["confidence", "band", "target", "matrix", "feerate"])]
+    preset: Option<String>,
+    /// Estimate the fee rate confirming within this many blocks (e.g. 1, 3, 6, 12 or 144)
+    /// from recorded wait times and projected blocks, may be repeated
+    #[arg(short, long)]
+    target: Vec<u32>,
+    /// How to estimate [default: cutoff]
+    #[arg(long, value_enum)]
+    model: Option<Model>,
+    /// Fee rates by confidence [default: 0.5, 0.8, 0.9 and 0.95] and time horizon from 30
+    /// minutes to a day, from recorded wait times and projected blocks
+    #[arg(long, conflicts_with_all = ["target", "model", "model_file"])]
+    matrix: bool,
+    /// Estimate with a model saved by `train` or an ONNX model taking the same features
+    /// instead of fitting one
+    #[arg(long, conflicts_with = "model")]
+    model_file: Option<PathBuf>,
+    /// The projected block a transaction paying this fee rate (sat/vB) lands in at the
+    /// latest snapshot, instead of estimating a fee rate
+    #[arg(long, conflicts_with_all = ["target", "model", "matrix", "model_file"])]
+    feerate: Option<f64>,
+    /// With --feerate, the distribution of its confirmation time from Monte Carlo rollouts
+    /// of blocks found at random and transactions arriving like during the last day, at
+    /// each confidence [default: 0.5, 0.8, 0.9 and 0.95]
+    #[arg(long, requires = "feerate")]
+    simulate: bool,
+    /// The projected block and confirmation ETA of this signed transaction, given as hex,
+    /// its fee looked up from the node's view of its prevouts
+    #[arg(long, conflicts_with_all = ["target", "matrix", "feerate", "preset", "band", "explain", "fiat", "unit"])]
+    rawtx: Option<String>,
+    #[command(flatten)]
+    node: NodeArgs,
+    /// Rollouts to simulate
+    #[arg(long, default_value_t = 10_000, requires = "simulate")]
+    rollouts: u32,
+    /// Seed of the rollouts, the same seed gives the same distribution
+    #[arg(long, default_value_t = 0, requires = "simulate")]
+    seed: u64,
+    /// Also tell what went into each estimate: the projected cutoff now and during the last
+    /// hour, the inflow, the cutoffs of the same weekday and hour of the previous weeks and
+    /// how far a model moved it
+    #[arg(long, conflicts_with_all = ["matrix", "feerate"])]
+    explain: bool,
+    /// Also tell what common transactions pay at each estimate in this currency, e.g. USD
+    /// or EUR, priced by the feed set in `[price]` of the config file [default: mempool.space]
+    #[arg(long, conflicts_with_all = ["matrix", "feerate", "explain"])]
+    fiat: Option<String>,
+    /// Unit of the fee rates, rounded up to it: sat/vB, sat/kvB, btc/kvB or sat/WU
+    /// [default: sat/vB]
+    #[arg(long, value_enum, ignore_case = true, conflicts_with_all = ["feerate", "explain", "fiat"])]
+    unit: Option<FeeUnit>,
+}
+
+pub(crate) async fn run(args: CalcArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let CalcArgs {
+        confidence,
+        band,
+        preset,
+        target,
+        model,
+        model_file,
+        matrix,
+        feerate,
+        simulate,
+        rawtx,
+        node,
+        rollouts,
+        seed,
+        explain,
+        fiat,
+        unit,
+    } = args;
+    let json = json_output;
+    let unit = unit.or(config.calc.unit).unwrap_or_default();
+    let (confidence, target) = match preset {
+        Some(name) => {
+            let preset = Preset::find(&Preset::all(&config.preset), &name)?;
+            (vec![preset.confidence], vec![preset.target])
+        }
+        None => (confidence, target),
+    };
+    let default: &[f64] = if matrix || simulate {
+        &MATRIX_CONFIDENCES
+    } else {
+        &[0.95]
+    };
+    let confidences = confidences(confidence, band, &config.calc, default);
+    let storage = storage_kind.open(&data_dir, network)?;
+    let price = price_feed(fiat, &config.price, config.record.proxy.as_deref())?;
+    if let Some(feerate) = feerate {
+        if !feerate.is_finite() || feerate < 0. {
+            bail!("fee rate must be at least 0, got {feerate}");
+        }
+        if !simulate {
+            let position = QueuePosition::from_recorded(storage.as_ref(), feerate)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&position)?);
+            } else {
+                println!("{feerate} sat/vB lands in block {}", position.block);
+            }
+            return Ok(());
+        }
+        let times = Rollout::run(storage.as_ref(), feerate, &confidences, rollouts, seed)?;
+        if json {
+            println!("{}", serde_json::to_string_pretty(&times)?);
+            return Ok(());
+        }
+        println!(
+            "{feerate} sat/vB at height {}: {:.2} MvB ahead, {:.0} vB/min arriving at or \
+             above it, {} rollouts",
+            times.height,
+            times.ahead_vsize as f64 / 1e6,
+            times.arrivals_vsize_per_minute,
+            times.rollouts
+        );
+        for quantile in &times.quantiles {
+            match (quantile.minutes, quantile.blocks) {
+                (Some(minutes), Some(blocks)) => println!(
+                    "{:>6}: {minutes:.0} min, {blocks} blocks",
+                    quantile.confidence
+                ),
+                _ => println!("{:>6}: unconfirmed", quantile.confidence),
+            }
+        }
+        let within: Vec<String> = times
+            .within
+            .iter()
+            .map(|within| {
+                let horizon = match within.horizon_minutes {
+                    m if m % 60 == 0 => format!("{}h", m / 60),
+                    m => format!("{m}m"),
+                };
+                format!("{horizon} {:.1}%", within.probability * 100.)
+            })
+            .collect();
+        println!("within {}", within.join(", "));
+        return Ok(());
+    }
+    if matrix {
+        let matrix = Calc::matrix(storage.as_ref(), &confidences)?;
+        if json {
+            println!("{}", serde_json::to_string_pretty(&matrix.convert(unit))?);
+            return Ok(());
+        }
+        let width = if unit == FeeUnit::BtcKvb { 12 } else { 9 };
+        let horizons: Vec<String> = matrix
+            .horizons_minutes
+            .iter()
+            .map(|minutes| match minutes {
+                m if m % 60 == 0 => format!("{}h", m / 60),
+                m => format!("{m}m"),
+            })
+            .collect();
+        println!(
+            "{:>6}{}",
+            "",
+            horizons
+                .iter()
+                .map(|h| format!("{h:>width$}"))
+                .collect::<String>()
+        );
+        for (confidence, row) in matrix.confidences.iter().zip(&matrix.fee_rates) {
+            let row: String = row
+                .iter()
+                .map(|rate| format!("{:>width$}", unit.number(*rate)))
+                .collect();
+            println!("{confidence:>6}{row}");
+        }
+        if unit != FeeUnit::SatVb {
+            println!("in {}", unit.as_str());
+        }
+        return Ok(());
+    }
+    let model_file = model_file.or(config.calc.model_file);
+    let trained = match (model_file, model.or(config.calc.model).unwrap_or_default()) {
+        (Some(path), _) => Some(Trained::load(&path)?),
+        (None, Model::Cutoff) => None,
+        (None, model) => {
+            let targets = match (&rawtx, target.is_empty()) {
+                (Some(_), _) => &TARGETS[..],
+                (None, true) => &[1][..],
+                (None, false) => &target,
+            };
+            Some(Trained::train(
+                storage.as_ref(),
+                model,
+                targets,
+                &confidences,
+                TRAIN_EVERY_SECS,
+            )?)
+        }
+    };
+    if let Some(rawtx) = rawtx {
+        let [confidence] = confidences[..] else {
+            bail!("--rawtx takes one confidence");
+        };
+        let tx = TxEta::decode(&rawtx)?;
+        let node = node.or(&config.record).node(network, &data_dir).await?;
+        let fee = TxEta::fee(node.as_ref(), &tx).await?;
+        let snapshot = Replay::new(storage.as_ref())?.at(i64::MAX)?;
+        let eta = TxEta::new(
+            storage.as_ref(),
+            &tx,
+            fee,
+            confidence,
+            |target| match &trained {
+                Some(trained) => trained.estimate(&snapshot, target, confidence),
+                None => Calc::calc(storage.as_ref(), confidence, target),
+            },
+        )?;
+        if json {
+            println!("{}", serde_json::to_string_pretty(&eta)?);
+            return Ok(());
+        }
+        println!(
+            "{}: {} vB paying {} sat, {:.2} sat/vB, lands in block {} of the snapshot at \
+             height {}",
+            eta.txid, eta.vsize, eta.fee, eta.fee_rate, eta.position.block, eta.position.height
+        );
+        match (eta.target, eta.eta_secs) {
+            (Some(target), Some(eta_secs)) => println!(
+                "confirms within {target} blocks, about {} min, at {confidence}",
+                eta_secs / 60
+            ),
+            _ => println!(
+                "pays less than the {} block estimate at {confidence}",
+                TARGETS[TARGETS.len() - 1]
+            ),
+        }
+        return Ok(());
+    }
+    if let Some(trained) = trained {
+        let snapshot = Replay::new(storage.as_ref())?.at(i64::MAX)?;
+        let targets = if target.is_empty() { vec![1] } else { target };
+        let mut estimates = Vec::new();
+        for target in targets {
+            for confidence in &confidences {
+                let fee_rate = trained.estimate(&snapshot, target, *confidence)?;
+                estimates.push((*confidence, target, fee_rate));
+            }
+        }
+        if explain {
+            print_explanations(storage.as_ref(), &estimates, Some(&trained), json)?;
+        } else if let Some(price) = &price {
+            print_costs(&estimates, price, json).await?;
+        } else if json {
+            let estimates: Vec<_> = estimates
+                .iter()
+                .map(|(confidence, target, fee_rate)| {
+                    let estimate = serde_json::json!({
+                        "confidence": confidence,
+                        "target": target,
+                        "fee_rate_sat_vb": fee_rate,
+                    });
+                    with_unit(estimate, *fee_rate, unit)
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&estimates)?);
+        } else if estimates.len() == 1 {
+            println!("{}", unit.format(estimates[0].2));
+        } else {
+            for (confidence, target, fee_rate) in &estimates {
+                println!(
+                    "{confidence:>6}, {target} blocks: {}",
+                    unit.format(*fee_rate)
+                );
+            }
+        }
+    } else if target.is_empty() {
+        let estimates = Calc::quantiles(storage.as_ref(), &confidences, 1)?;
+        let by_target: Vec<_> = confidences
+            .iter()
+            .zip(&estimates)
+            .map(|(confidence, fee_rate)| (*confidence, 1, *fee_rate))
+            .collect();
+        if explain {
+            print_explanations(storage.as_ref(), &by_target, None, json)?;
+        } else if let Some(price) = &price {
+            print_costs(&by_target, price, json).await?;
+        } else if json {
+            let estimates: Vec<_> = confidences
+                .iter()
+                .zip(&estimates)
+                .map(|(confidence, fee_rate)| {
+                    let estimate = serde_json::json!({
+                        "confidence": confidence,
+                        "target": 1,
+                        "fee_rate_sat_vb": fee_rate,
+                    });
+                    with_unit(estimate, *fee_rate, unit)
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&estimates)?);
+        } else if estimates.len() == 1 {
+            println!("{}", unit.format(estimates[0]));
+        } else {
+            for (confidence, estimate) in confidences.iter().zip(&estimates) {
+                println!("{confidence:>6}: {}", unit.format(*estimate));
+            }
+        }
+    } else {
+        let estimates = Calc::targets(storage.as_ref(), &target, &confidences)?;
+        let by_target: Vec<_> = estimates
+            .iter()
+            .map(|e| (e.confidence, e.target, e.fee_rate_sat_vb))
+            .collect();
+        if explain {
+            return print_explanations(storage.as_ref(), &by_target, None, json);
+        }
+        if let Some(price) = &price {
+            return print_costs(&by_target, price, json).await;
+        }
+        if json {
+            let estimates: Vec<_> = estimates
+                .iter()
+                .map(|estimate| {
+                    Ok(with_unit(
+                        serde_json::to_value(estimate)?,
+                        estimate.fee_rate_sat_vb,
+                        unit,
+                    ))
+                })
+                .collect::<Result<_>>()?;
+            println!("{}", serde_json::to_string_pretty(&estimates)?);
+            return Ok(());
+        }
+        for estimate in &estimates {
+            let historical = estimate
+                .historical_sat_vb
+                .map(|rate| unit.number(rate))
+                .unwrap_or_else(|| String::from("n/a"));
+            println!(
+                "{:>6}, {} blocks: {} (projected {}, historical {historical} from {} confirmations)",
+                estimate.confidence,
+                estimate.target,
+                unit.format(estimate.fee_rate_sat_vb),
+                unit.number(estimate.projected_sat_vb),
+                estimate.confirmations
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `estimate` with the fee rate in `unit` next to its sat/vB, for `calc --unit`
+fn with_unit(mut estimate: serde_json::Value, sat_vb: f64, unit: FeeUnit) -> serde_json::Value {
+    if unit != FeeUnit::SatVb {
+        estimate["fee_rate"] = unit.convert(sat_vb).into();
+        estimate["unit"] = unit.as_str().into();
+    }
+    estimate
+}
+
+/// `calc --fiat`
+async fn print_costs(estimates: &[(f64, u32, f64)], price: &PriceFeed, json: bool) -> Result<()> {
+    let mut costed = Vec::with_capacity(estimates.len());
+    for &(confidence, target, fee_rate) in estimates {
+        costed.push((confidence, target, fee_rate, price.costs(fee_rate).await?));
+    }
+    if json {
+        let costed: Vec<_> = costed
+            .iter()
+            .map(|(confidence, target, fee_rate, costs)| {
+                serde_json::json!({
+                    "confidence": confidence,
+                    "target": target,
+                    "fee_rate_sat_vb": fee_rate,
+                    "costs": costs,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&costed)?);
+        return Ok(());
+    }
+    for (confidence, target, fee_rate, costs) in &costed {
+        println!("{confidence:>6}, {target} blocks: {fee_rate:.2} sat/vB");
+        for tx in &costs.transactions {
+            println!(
+                "  {} ({} vB): {} sat, {:.2} {}",
+                tx.name, tx.vsize, tx.fee_sat, tx.fiat, costs.currency
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `calc --explain`
+fn print_explanations(
+    storage: &dyn Storage,
+    estimates: &[(f64, u32, f64)],
+    model: Option<&Trained>,
+    json: bool,
+) -> Result<()> {
+    let explanations = Explain::explain(storage, estimates, model)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&explanations)?);
+        return Ok(());
+    }
+    let rate = |rate: Option<f64>| {
+        rate.map(|rate| format!("{rate:.0}"))
+            .unwrap_or_else(|| String::from("n/a"))
+    };
+    for e in &explanations {
+        println!(
+            "{:>6}, {} blocks: {:.2} sat/vB",
+            e.confidence, e.target, e.fee_rate_sat_vb
+        );
+        println!(
+            "  projected cutoff {:.2} sat/vB at height {} with {:.2} MvB in the mempool",
+            e.projected_cutoff_sat_vb,
+            e.height,
+            e.mempool_vsize as f64 / 1e6
+        );
+        println!(
+            "  {:.2} sat/vB in {} of the last hour's snapshots",
+            e.recent_cutoff_sat_vb, e.confidence
+        );
+        match e.historical_sat_vb {
+            Some(historical) => println!(
+                "  {historical:.2} sat/vB confirmed within {} blocks in {} of {} recent confirmations",
+                e.target, e.confidence, e.confirmations
+            ),
+            None => println!(
+                "  too few of {} recent confirmations to tell from the waits",
+                e.confirmations
+            ),
+        }
+        println!(
+            "  {} vB/min arriving, {} vB/min paying at least the estimate",
+            rate(e.inflow_vsize_per_minute),
+            rate(e.inflow_above_vsize_per_minute)
+        );
+        match e.seasonal_cutoff_sat_vb {
+            Some(cutoff) => println!(
+                "  {cutoff:.2} sat/vB on {} {:02}:00 UTC of the previous weeks from {} snapshots",
+                e.weekday, e.hour, e.seasonal_snapshots
+            ),
+            None => println!(
+                "  nothing recorded on {} {:02}:00 UTC of the previous weeks",
+                e.weekday, e.hour
+            ),
+        }
+        if let (Some(model), Some(adjustment)) = (e.model, e.model_adjustment_sat_vb) {
+            println!("  {model} model: {adjustment:+.2} sat/vB from the recent cutoff");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use super::{Context, NodeArgs};
+use crate::{cln::ClnPlugin, model::Trained};
+use anyhow::{bail, Result};
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub(crate) struct ClnPluginArgs {
+    #[command(flatten)]
+    node: NodeArgs,
+    /// Confidence of the estimates, the wtf-confidence option of lightningd takes precedence
+    /// [default: 0.95]
+    #[arg(short, long)]
+    confidence: Option<f64>,
+    /// Estimate with a model saved by `train` or an ONNX model taking the same features
+    #[arg(long)]
+    model_file: Option<PathBuf>,
+}
+
+pub(crate) async fn run(args: ClnPluginArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        ..
+    } = context;
+    let ClnPluginArgs {
+        node,
+        confidence,
+        model_file,
+    } = args;
+    let Some(rpc) = node.or(&config.record).rpc(network)? else {
+        bail!("cln-plugin needs --rpc-endpoint, lightningd broadcasts through it");
+    };
+    let model = model_file
+        .or(config.calc.model_file)
+        .map(|path| Trained::load(&path))
+        .transpose()?;
+    let confidence = confidence
+        .or(config.calc.confidence.first().copied())
+        .unwrap_or(0.95);
+    ClnPlugin::new(
+        storage_kind.open(&data_dir, network)?,
+        rpc,
+        model,
+        network,
+        confidence,
+    )
+    .run()
+    .await?;
+    Ok(())
+}
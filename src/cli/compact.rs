@@ -0,0 +1,45 @@
+use super::Context;
+use crate::compact::Compact;
+use anyhow::Result;
+use chrono::{NaiveDate, Utc};
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct CompactArgs {
+    /// Only compact this day (YYYY-MM-DD) [default: every day before today]
+    #[arg(long)]
+    day: Option<NaiveDate>,
+    /// Keep the merged files instead of removing them
+    #[arg(long)]
+    keep_raw: bool,
+}
+
+pub(crate) async fn run(args: CompactArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let CompactArgs { day, keep_raw } = args;
+    let storage = storage_kind.open(&data_dir, network)?;
+    let days = match day {
+        Some(day) => vec![day],
+        // today is still being recorded
+        None => Compact::days(storage.as_ref(), Utc::now().date_naive())?,
+    };
+    let mut compacted = Vec::new();
+    for day in days {
+        if let Some(file) = Compact::compact(storage.as_ref(), day, keep_raw)? {
+            if !json_output {
+                println!("{}", file.path.display());
+            }
+            compacted.push(file.path);
+        }
+    }
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&compacted)?);
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use super::{format_timestamp, parse_timestamp, Context};
+use crate::{compare::CompareNodes, storage::StorageKind};
+use anyhow::{bail, Result};
+use clap::Args;
+use std::path::Path;
+
+#[derive(Args)]
+pub(crate) struct CompareNodesArgs {
+    /// Data directory of the other recorder, on the same network
+    other: String,
+    /// How the other dataset is stored [default: like this one]
+    #[arg(long, value_enum)]
+    other_storage: Option<StorageKind>,
+    /// Unix timestamp or RFC 3339 date of the first snapshot compared [default: the first]
+    #[arg(long, value_parser = parse_timestamp)]
+    from: Option<i64>,
+    /// Unix timestamp or RFC 3339 date of the last snapshot compared [default: the last]
+    #[arg(long, value_parser = parse_timestamp)]
+    to: Option<i64>,
+    /// Seconds between the snapshots compared
+    #[arg(long, default_value_t = 600)]
+    every: i64,
+}
+
+pub(crate) async fn run(args: CompareNodesArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let CompareNodesArgs {
+        other,
+        other_storage,
+        from,
+        to,
+        every,
+    } = args;
+    if Path::new(&other) == Path::new(&data_dir) {
+        bail!("{other} is this dataset already");
+    }
+    let storage = storage_kind.open(&data_dir, network)?;
+    let other = other_storage
+        .unwrap_or(storage_kind)
+        .open(&other, network)?;
+    let comparison = CompareNodes::run(
+        storage.as_ref(),
+        other.as_ref(),
+        from.unwrap_or(i64::MIN),
+        to.unwrap_or(i64::MAX),
+        every,
+    )?;
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&comparison)?);
+        return Ok(());
+    }
+    println!(
+        "{:>25} {:>8} {:>9} {:>10} {:>11} {:>11} {:>10} {:>8}",
+        "snapshot",
+        "shared",
+        "only here",
+        "only other",
+        "here vB",
+        "other vB",
+        "divergence",
+        "skew s"
+    );
+    for sample in &comparison.samples {
+        println!(
+            "{:>25} {:>8} {:>9} {:>10} {:>11} {:>11} {:>9.2}% {:>8}",
+            format_timestamp(sample.timestamp),
+            sample.shared,
+            sample.only_here,
+            sample.only_other,
+            sample.only_here_vsize,
+            sample.only_other_vsize,
+            sample.divergence * 100.,
+            sample
+                .median_first_seen_skew_secs
+                .map_or("-".to_string(), |skew| format!("{skew:.0}"))
+        );
+    }
+    if let Some(divergence) = comparison.mean_divergence {
+        println!(
+            "{} snapshots compared, {:.2}% divergent on average, {} without a match",
+            comparison.samples.len(),
+            divergence * 100.,
+            comparison.unmatched
+        );
+    } else {
+        println!(
+            "no snapshots to compare, {} without a match",
+            comparison.unmatched
+        );
+    }
+    if let (Some(median), Some(p90), Some(first)) = (
+        comparison.median_first_seen_skew_secs,
+        comparison.p90_abs_first_seen_skew_secs,
+        comparison.seen_first_here,
+    ) {
+        println!(
+            "{} shared transactions, the other saw them {median:.0} s later in the median, \
+             90% within {p90:.0} s, {:.1}% were seen here first",
+            comparison.compared_txs,
+            first * 100.
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use super::Context;
+use crate::{corerpc::CoreRpc, model::Trained};
+use anyhow::{bail, Result};
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub(crate) struct CoreRpcArgs {
+    /// Address to listen on [default: 127.0.0.1:8342]
+    #[arg(short, long)]
+    listen: Option<String>,
+    /// Confidence of conservative estimates [default: 0.95]
+    #[arg(short, long)]
+    confidence: Option<f64>,
+    /// Confidence of economical estimates
+    #[arg(long, default_value_t = 0.8)]
+    economical_confidence: f64,
+    /// Estimate with a model saved by `train` or an ONNX model taking the same features
+    #[arg(long)]
+    model_file: Option<PathBuf>,
+}
+
+pub(crate) async fn run(args: CoreRpcArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        ..
+    } = context;
+    let CoreRpcArgs {
+        listen,
+        confidence,
+        economical_confidence,
+        model_file,
+    } = args;
+    let listen = listen.unwrap_or_else(|| String::from("127.0.0.1:8342"));
+    let model = model_file
+        .or(config.calc.model_file)
+        .map(|path| Trained::load(&path))
+        .transpose()?;
+    let confidence = confidence
+        .or(config.calc.confidence.first().copied())
+        .unwrap_or(0.95);
+    for confidence in [confidence, economical_confidence] {
+        if !(confidence > 0. && confidence <= 1.) {
+            bail!("confidence must be in (0, 1], got {confidence}");
+        }
+    }
+    CoreRpc::serve(
+        storage_kind.open(&data_dir, network)?,
+        listen.parse()?,
+        model,
+        confidence,
+        economical_confidence,
+    )
+    .await?;
+    Ok(())
+}
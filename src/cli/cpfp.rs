@@ -0,0 +1,102 @@
+use super::{Context, NodeArgs};
+use crate::{
+    calc::Calc,
+    cpfp::{ChildFee, Parent, DEFAULT_CHILD_VSIZE},
+    model::Trained,
+    replay::Replay,
+};
+use anyhow::{bail, Result};
+use bitcoin::Txid;
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub(crate) struct CpfpArgs {
+    /// Of the parent
+    txid: Txid,
+    /// Blocks to confirm within
+    #[arg(short, long, default_value_t = 1)]
+    target: u32,
+    /// Probability of confirming within the target [default: 0.95]
+    #[arg(short, long)]
+    confidence: Option<f64>,
+    /// Virtual size of the child once signed
+    #[arg(long, default_value_t = DEFAULT_CHILD_VSIZE)]
+    child_vsize: u64,
+    /// Estimate with a model saved by `train` or an ONNX model taking the same features
+    #[arg(long)]
+    model_file: Option<PathBuf>,
+    #[command(flatten)]
+    node: NodeArgs,
+    /// Look the parent up in the latest recorded snapshot instead of asking the node, its
+    /// ancestors aren't known then
+    #[arg(long)]
+    recorded: bool,
+}
+
+pub(crate) async fn run(args: CpfpArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let CpfpArgs {
+        txid,
+        target,
+        confidence,
+        child_vsize,
+        model_file,
+        node,
+        recorded,
+    } = args;
+    let json = json_output;
+    let confidence = confidence
+        .or(config.calc.confidence.first().copied())
+        .unwrap_or(0.95);
+    if !(confidence > 0. && confidence <= 1.) || target == 0 {
+        bail!("confidence must be in (0, 1] and target at least 1");
+    }
+    let storage = storage_kind.open(&data_dir, network)?;
+    let parent = if recorded {
+        Parent::from_recorded(storage.as_ref(), txid)?
+    } else {
+        let node = node.or(&config.record).node(network, &data_dir).await?;
+        Parent::from_node(node.as_ref(), txid).await?
+    };
+    let Some(parent) = parent else {
+        bail!("{txid} is not in the mempool");
+    };
+    let fee_rate = match model_file.or(config.calc.model_file) {
+        Some(path) => Trained::load(&path)?.estimate(
+            &Replay::new(storage.as_ref())?.at(i64::MAX)?,
+            target,
+            confidence,
+        )?,
+        None => Calc::calc(storage.as_ref(), confidence, target)?,
+    };
+    let child = ChildFee::new(&parent, child_vsize, target, confidence, fee_rate)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&child)?);
+        return Ok(());
+    }
+    println!(
+        "{}: {} vB over {} transactions paying {} sat, {:.2} sat/vB",
+        child.parent,
+        child.package_vsize,
+        child.package_txs,
+        child.package_fee,
+        child.package_fee_rate
+    );
+    println!(
+        "{target} blocks at {confidence} needs {:.2} sat/vB, a child of {} vB pays {} sat, \
+         {:.2} sat/vB",
+        child.target_fee_rate, child.child_vsize, child.child_fee, child.child_fee_rate
+    );
+    if child.parent_sufficient {
+        println!("the parent pays enough already, the child only needs to keep up");
+    }
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use super::Context;
+use crate::dashboard::Dashboard;
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct DashboardArgs {
+    /// Confidence of the estimates [default: 0.95]
+    #[arg(short, long)]
+    confidence: Option<f64>,
+    /// Seconds between looks for a new snapshot
+    #[arg(long, default_value_t = 1)]
+    poll: u64,
+}
+
+pub(crate) async fn run(args: DashboardArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        ..
+    } = context;
+    let DashboardArgs { confidence, poll } = args;
+    let confidence = confidence
+        .or(config.calc.confidence.first().copied())
+        .unwrap_or(0.95);
+    Dashboard::run(
+        storage_kind.open(&data_dir, network)?.as_ref(),
+        confidence,
+        std::time::Duration::from_secs(poll),
+    )?;
+    Ok(())
+}
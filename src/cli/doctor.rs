@@ -0,0 +1,52 @@
+use super::{Context, NodeArgs};
+use crate::doctor::{Doctor, Status};
+use anyhow::{bail, Result};
+use clap::Args;
+use std::path::Path;
+
+#[derive(Args)]
+pub(crate) struct DoctorArgs {
+    #[command(flatten)]
+    node: NodeArgs,
+}
+
+pub(crate) async fn run(args: DoctorArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        json_output,
+        ..
+    } = context;
+    let DoctorArgs { node } = args;
+    let node_args = node.or(&config.record);
+    let rpc = node_args.rpc(network)?;
+    let checks = Doctor::run(
+        &node_args.rest()?[0],
+        rpc.as_ref(),
+        &node_args.zmq_endpoint,
+        Path::new(&data_dir),
+        network,
+    )
+    .await;
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&checks)?);
+    } else {
+        for check in &checks {
+            println!(
+                "[{}] {}: {}",
+                check.status.as_str(),
+                check.name,
+                check.detail
+            );
+            if let Some(hint) = &check.hint {
+                println!("       {hint}");
+            }
+        }
+    }
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    if failed > 0 {
+        bail!("{failed} checks failed");
+    }
+    Ok(())
+}
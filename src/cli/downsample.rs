@@ -0,0 +1,41 @@
+use super::Context;
+use crate::downsample::Downsample;
+use anyhow::Result;
+use chrono::Utc;
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct DownsampleArgs {
+    /// Downsample data older than this many days
+    #[arg(long)]
+    older_than_days: u32,
+    /// Keep a histogram every this many seconds of each height
+    #[arg(long, default_value_t = 60)]
+    every: u32,
+}
+
+pub(crate) async fn run(args: DownsampleArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let DownsampleArgs {
+        older_than_days,
+        every,
+    } = args;
+    let storage = storage_kind.open(&data_dir, network)?;
+    let before = Utc::now().timestamp() - older_than_days as i64 * 24 * 60 * 60;
+    let downsampled = Downsample::downsample(storage.as_ref(), before, every as i64)?;
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&downsampled)?);
+    } else {
+        println!(
+            "replaced {} files with {} histograms",
+            downsampled.removed, downsampled.histograms
+        );
+    }
+    Ok(())
+}
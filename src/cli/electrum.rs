@@ -0,0 +1,52 @@
+use super::Context;
+use crate::{electrum::Electrum, model::Trained};
+use anyhow::{bail, Result};
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub(crate) struct ElectrumArgs {
+    /// Address to listen on [default: 127.0.0.1:50001]
+    #[arg(short, long)]
+    listen: Option<String>,
+    /// Confidence of the estimates [default: 0.95]
+    #[arg(short, long)]
+    confidence: Option<f64>,
+    /// Estimate with a model saved by `train` or an ONNX model taking the same features
+    #[arg(long)]
+    model_file: Option<PathBuf>,
+}
+
+pub(crate) async fn run(args: ElectrumArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        ..
+    } = context;
+    let ElectrumArgs {
+        listen,
+        confidence,
+        model_file,
+    } = args;
+    let listen = listen.unwrap_or_else(|| String::from("127.0.0.1:50001"));
+    let model = model_file
+        .or(config.calc.model_file)
+        .map(|path| Trained::load(&path))
+        .transpose()?;
+    let confidence = confidence
+        .or(config.calc.confidence.first().copied())
+        .unwrap_or(0.95);
+    if !(confidence > 0. && confidence <= 1.) {
+        bail!("confidence must be in (0, 1], got {confidence}");
+    }
+    Electrum::serve(
+        storage_kind.open(&data_dir, network)?,
+        listen.parse()?,
+        model,
+        confidence,
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use super::{parse_timestamp, Context};
+use crate::{
+    dataset::FileKind,
+    export::{Export, ExportFormat},
+    storage::{LocalStorage, StorageKind},
+    unit::FeeUnit,
+};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+use std::{io::BufWriter, path::PathBuf};
+use tracing::info;
+
+#[derive(Args)]
+pub(crate) struct ExportArgs {
+    /// Unix timestamp or RFC 3339 date of the first file to include [default: the first]
+    #[arg(long, value_parser = parse_timestamp)]
+    from: Option<i64>,
+    /// Unix timestamp or RFC 3339 date of the last file to include [default: the last]
+    #[arg(long, value_parser = parse_timestamp)]
+    to: Option<i64>,
+    /// Files to include, may be repeated [default: full and delta]
+    #[arg(short, long, value_parser = parse_kind)]
+    kind: Vec<FileKind>,
+    /// Format of the output
+    #[arg(short = 'f', long = "file-format", value_enum, default_value_t = ExportFormat::Csv)]
+    format: ExportFormat,
+    /// Unit of the fee rate columns, renamed after it like `fee_rate_sat_kvb`
+    #[arg(long, value_enum, ignore_case = true, default_value_t = FeeUnit::SatVb)]
+    unit: FeeUnit,
+    /// Write to this file instead of stdout. DuckDB databases need one, their views take
+    /// every file recorded so --from, --to, --kind and --unit don't apply.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// With --file-format duckdb, also materialize the `daily_meta` and `daily_blocks` tables
+    #[arg(long)]
+    daily: bool,
+}
+
+pub(crate) async fn run(args: ExportArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        ..
+    } = context;
+    let ExportArgs {
+        from,
+        to,
+        kind,
+        format,
+        unit,
+        output,
+        daily,
+    } = args;
+    if format == ExportFormat::Duckdb {
+        let Some(output) = output else {
+            bail!("duckdb exports write a database, pass --output");
+        };
+        let storage = match storage_kind {
+            StorageKind::Parquet => LocalStorage::new(&data_dir, network),
+            StorageKind::Hive => LocalStorage::hive(&data_dir, network),
+            StorageKind::Sqlite => {
+                bail!("duckdb views read parquet files, sqlite datasets have none")
+            }
+            // the views glob the directory, removed files not yet deleted would count
+            StorageKind::Table => {
+                bail!("duckdb views read every parquet file, tables list theirs in a log")
+            }
+        };
+        let created = Export::duckdb(&storage, &output, daily)?;
+        info!("created {} in {}", created.join(", "), output.display());
+        return Ok(());
+    }
+    let kinds = if kind.is_empty() {
+        vec![FileKind::Full, FileKind::Delta]
+    } else {
+        kind
+    };
+    let storage = storage_kind.open(&data_dir, network)?;
+    let (from, to) = (from.unwrap_or(i64::MIN), to.unwrap_or(i64::MAX));
+    let rows = match output {
+        Some(output) => {
+            let file = BufWriter::new(std::fs::File::create(output)?);
+            Export::export(storage.as_ref(), &kinds, from, to, format, unit, file)?
+        }
+        None => Export::export(
+            storage.as_ref(),
+            &kinds,
+            from,
+            to,
+            format,
+            unit,
+            std::io::stdout().lock(),
+        )?,
+    };
+    info!("exported {rows} rows");
+    Ok(())
+}
+
+fn parse_kind(s: &str) -> Result<FileKind> {
+    FileKind::parse(s).ok_or_else(|| anyhow!("unknown file kind {s}"))
+}
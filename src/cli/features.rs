@@ -0,0 +1,49 @@
+use super::{parse_timestamp, Context};
+use crate::features::Features;
+use anyhow::Result;
+use clap::Args;
+use polars::prelude::ParquetWriter;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub(crate) struct FeaturesArgs {
+    /// Unix timestamp or RFC 3339 date of the first snapshot to include [default: the first]
+    #[arg(long, value_parser = parse_timestamp)]
+    from: Option<i64>,
+    /// Unix timestamp or RFC 3339 date of the last snapshot to include [default: the last]
+    #[arg(long, value_parser = parse_timestamp)]
+    to: Option<i64>,
+    /// Seconds between the snapshots sampled at least
+    #[arg(long, default_value_t = 60)]
+    every: i64,
+    /// Parquet file to write
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+pub(crate) async fn run(args: FeaturesArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let FeaturesArgs {
+        from,
+        to,
+        every,
+        output,
+    } = args;
+    let storage = storage_kind.open(&data_dir, network)?;
+    let (from, to) = (from.unwrap_or(i64::MIN), to.unwrap_or(i64::MAX));
+    let mut frame = Features::extract(storage.as_ref(), from, to, every)?;
+    let file = std::fs::File::create(&output)?;
+    ParquetWriter::new(file).finish(&mut frame)?;
+    if json_output {
+        println!("{}", serde_json::json!({ "rows": frame.height() }));
+    } else {
+        println!("wrote {} rows to {}", frame.height(), output.display());
+    }
+    Ok(())
+}
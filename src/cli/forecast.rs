@@ -0,0 +1,77 @@
+use super::{format_timestamp, parse_hours, Context};
+use crate::{
+    model::{Trained, TRAIN_EVERY_SECS},
+    replay::Replay,
+    seasonal::Seasonality,
+};
+use anyhow::{bail, Result};
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub(crate) struct ForecastArgs {
+    /// How far ahead in hours, or with a unit like 6h or 2d
+    #[arg(long, default_value = "24h", value_parser = parse_hours)]
+    horizon: u32,
+    /// Quantile of the low end of the band
+    #[arg(long, default_value_t = 0.1)]
+    low: f64,
+    /// Quantile of the high end of the band
+    #[arg(long, default_value_t = 0.9)]
+    high: f64,
+    /// Forecast with a seasonal model saved by `train --model seasonal` instead of fitting one
+    #[arg(long)]
+    model_file: Option<PathBuf>,
+}
+
+pub(crate) async fn run(args: ForecastArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let ForecastArgs {
+        horizon,
+        low,
+        high,
+        model_file,
+    } = args;
+    let json = json_output;
+    if !(0. ..=1.).contains(&low) || !(low..=1.).contains(&high) {
+        bail!("low and high must be quantiles in [0, 1] with low at most high");
+    }
+    let storage = storage_kind.open(&data_dir, network)?;
+    let seasonality = match model_file {
+        Some(path) => match Trained::load(&path)? {
+            Trained::Seasonal(seasonality) => seasonality,
+            trained => bail!(
+                "{} is a {} model, forecasts need a seasonal one",
+                path.display(),
+                trained.as_str()
+            ),
+        },
+        None => Box::new(Seasonality::fit(storage.as_ref(), TRAIN_EVERY_SECS)?),
+    };
+    let snapshot = Replay::new(storage.as_ref())?.at(i64::MAX)?;
+    let forecast = seasonality.forecast(&snapshot, horizon, low, high);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&forecast)?);
+        return Ok(());
+    }
+    println!(
+        "from {} samples over {:.1} days, sat/vB between quantiles {low} and {high}",
+        seasonality.samples, seasonality.days
+    );
+    for hour in &forecast {
+        println!(
+            "{}  {:>8.2} {:>8.2} {:>8.2}",
+            format_timestamp(hour.timestamp),
+            hour.low,
+            hour.median,
+            hour.high
+        );
+    }
+    Ok(())
+}
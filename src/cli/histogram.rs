@@ -0,0 +1,59 @@
+use super::{format_timestamp, Context, NodeArgs};
+use crate::histogram::Histogram;
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct HistogramArgs {
+    #[command(flatten)]
+    node: NodeArgs,
+    /// Use the latest recorded snapshot instead of asking the node
+    #[arg(long)]
+    recorded: bool,
+}
+
+pub(crate) async fn run(args: HistogramArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let HistogramArgs { node, recorded } = args;
+    let json = json_output;
+    let histogram = if recorded {
+        Histogram::from_recorded(storage_kind.open(&data_dir, network)?.as_ref())?
+    } else {
+        let node = node.or(&config.record).node(network, &data_dir).await?;
+        Histogram::from_node(node.as_ref()).await?
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&histogram)?);
+    } else {
+        println!(
+            "height {}, {} ({}): {} transactions, {:.2} MvB",
+            histogram.height,
+            format_timestamp(histogram.timestamp),
+            histogram.source,
+            histogram.txs,
+            histogram.vsize as f64 / 1e6
+        );
+        println!(
+            "{:>10} {:>8} {:>12} {:>12} {:>6}",
+            "sat/vB", "txs", "vsize", "cumulative", "block"
+        );
+        for bucket in &histogram.buckets {
+            println!(
+                "{:>10} {:>8} {:>12} {:>12} {:>6}",
+                format!(">= {}", bucket.fee_rate),
+                bucket.txs,
+                bucket.vsize,
+                bucket.cumulative_vsize,
+                bucket.block
+            );
+        }
+    }
+    Ok(())
+}
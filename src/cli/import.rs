@@ -0,0 +1,114 @@
+use super::{format_timestamp, parse_timestamp, Context, NodeArgs};
+use crate::{
+    import::{Import, MempoolDat, MempoolSpace, MEMPOOL_SPACE_API},
+    node::http_client,
+};
+use anyhow::{bail, Result};
+use bitcoin::Network;
+use chrono::{TimeZone, Utc};
+use clap::{Args, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Subcommand)]
+pub(crate) enum ImportSource {
+    /// Bitcoin Core's persisted mempool with first-seen times and fee deltas, written on
+    /// shutdown or by `savemempool`. Fees come from the node, so it needs the mempool or
+    /// `-txindex` for the parents of transactions that are gone.
+    MempoolDat {
+        path: PathBuf,
+        /// Unix timestamp or RFC 3339 date of the snapshot [default: when the file was written]
+        #[arg(long, value_parser = parse_timestamp)]
+        at: Option<i64>,
+        #[command(flatten)]
+        node: NodeArgs,
+    },
+    /// mempool.space's history of the mempool's fee rate histogram and the fee rates of the
+    /// blocks since, enough for calc and backtest. Run it again to resume, files already
+    /// there are skipped. Training needs recorded confirmations still.
+    MempoolSpace {
+        /// Root of the API, e.g. of a self-hosted instance
+        #[arg(long, default_value = MEMPOOL_SPACE_API)]
+        endpoint: String,
+        /// How far back: 2h, 24h, 1w, 1m, 3m, 6m, 1y, 2y or 3y. The further, the fewer
+        /// histograms a day.
+        #[arg(long, default_value = "1m")]
+        period: String,
+        /// Milliseconds to wait before each request
+        #[arg(long, default_value_t = 1000)]
+        delay_ms: u64,
+        /// SOCKS5 proxy to connect through, e.g. Tor at socks5://127.0.0.1:9050
+        #[arg(long)]
+        proxy: Option<String>,
+    },
+}
+
+#[derive(Args)]
+pub(crate) struct ImportArgs {
+    #[command(subcommand)]
+    source: ImportSource,
+}
+
+pub(crate) async fn run(args: ImportArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    match args.source {
+        ImportSource::MempoolDat { path, at, node } => {
+            let dat = MempoolDat::read(&path)?;
+            let at = match at {
+                Some(at) => Utc.timestamp_opt(at, 0).unwrap(),
+                None => Import::modified(&path)?,
+            };
+            let node = node.or(&config.record).node(network, &data_dir).await?;
+            let storage = storage_kind.open(&data_dir, network)?;
+            let imported = Import::mempool_dat(storage.as_ref(), node.as_ref(), &dat, at).await?;
+            if json_output {
+                println!("{}", serde_json::to_string_pretty(&imported)?);
+                return Ok(());
+            }
+            println!(
+                "imported {} transactions at height {}, {}",
+                imported.txs,
+                imported.height,
+                format_timestamp(imported.timestamp)
+            );
+            if imported.skipped > 0 {
+                println!(
+                    "left out {} whose inputs the node doesn't know",
+                    imported.skipped
+                );
+            }
+        }
+        ImportSource::MempoolSpace {
+            endpoint,
+            period,
+            delay_ms,
+            proxy,
+        } => {
+            if network != Network::Bitcoin {
+                bail!("mempool.space's statistics are of mainnet, not {network}");
+            }
+            let api = MempoolSpace::new(
+                endpoint,
+                http_client(proxy.as_deref())?,
+                std::time::Duration::from_millis(delay_ms),
+            );
+            let storage = storage_kind.open(&data_dir, network)?;
+            let backfill = Import::mempool_space(storage.as_ref(), &api, &period).await?;
+            if json_output {
+                println!("{}", serde_json::to_string_pretty(&backfill)?);
+                return Ok(());
+            }
+            println!(
+                "imported {} blocks and {} histograms, {} were there already",
+                backfill.blocks, backfill.histograms, backfill.skipped
+            );
+        }
+    }
+    Ok(())
+}
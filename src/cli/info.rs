@@ -0,0 +1,78 @@
+use super::{format_timestamp, Context};
+use crate::info::Info;
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct InfoArgs {
+    /// Seconds without a snapshot that count as a gap [default: three snapshot intervals]
+    #[arg(long)]
+    max_gap: Option<i64>,
+}
+
+pub(crate) async fn run(args: InfoArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let InfoArgs { max_gap } = args;
+    let coverage = Info::coverage(storage_kind.open(&data_dir, network)?.as_ref(), max_gap)?;
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&coverage)?);
+        return Ok(());
+    }
+    println!(
+        "{} to {}: {} snapshots, every {} s",
+        format_timestamp(coverage.first),
+        format_timestamp(coverage.last),
+        coverage.snapshots,
+        coverage.interval_secs
+    );
+    println!(
+        "gaps longer than {} s: {}",
+        coverage.max_gap_secs,
+        coverage.gaps.len()
+    );
+    for gap in &coverage.gaps {
+        println!(
+            "  {} to {}, {} s, {}",
+            format_timestamp(gap.from),
+            format_timestamp(gap.to),
+            gap.to - gap.from,
+            if gap.clean_stop {
+                "stopped cleanly"
+            } else {
+                "no shutdown marker"
+            }
+        );
+    }
+    if !coverage.missing_heights.is_empty() {
+        let heights: Vec<String> = coverage
+            .missing_heights
+            .iter()
+            .map(|(from, to)| {
+                if from == to {
+                    from.to_string()
+                } else {
+                    format!("{from}-{to}")
+                }
+            })
+            .collect();
+        println!("heights without full snapshot: {}", heights.join(", "));
+    }
+    println!("continuous ranges: {}", coverage.ranges.len());
+    for range in &coverage.ranges {
+        println!(
+            "  {} to {}, heights {}-{}, {} snapshots",
+            format_timestamp(range.from),
+            format_timestamp(range.to),
+            range.first_height,
+            range.last_height,
+            range.snapshots
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use super::{parse_timestamp, Context};
+use crate::{
+    export::{Export, ExportFormat},
+    label::Label,
+};
+use anyhow::Result;
+use clap::Args;
+use std::{io::BufWriter, path::PathBuf};
+use tracing::info;
+
+#[derive(Args)]
+pub(crate) struct LabelArgs {
+    /// Unix timestamp or RFC 3339 date of the first snapshot to include [default: the first]
+    #[arg(long, value_parser = parse_timestamp)]
+    from: Option<i64>,
+    /// Unix timestamp or RFC 3339 date of the last snapshot to include [default: the last]
+    #[arg(long, value_parser = parse_timestamp)]
+    to: Option<i64>,
+    /// Also label transactions that weren't confirmed, with empty waits
+    #[arg(long)]
+    censored: bool,
+    /// Format of the output
+    #[arg(short = 'f', long = "file-format", value_enum, default_value_t = ExportFormat::Csv)]
+    format: ExportFormat,
+    /// Write to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+pub(crate) async fn run(args: LabelArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        ..
+    } = context;
+    let LabelArgs {
+        from,
+        to,
+        censored,
+        format,
+        output,
+    } = args;
+    let storage = storage_kind.open(&data_dir, network)?;
+    let (from, to) = (from.unwrap_or(i64::MIN), to.unwrap_or(i64::MAX));
+    let mut frame = Label::label(storage.as_ref(), from, to, censored)?;
+    match output {
+        Some(output) => {
+            let file = BufWriter::new(std::fs::File::create(output)?);
+            Export::write(&mut frame, format, file)?;
+        }
+        None => Export::write(&mut frame, format, std::io::stdout().lock())?,
+    }
+    info!("labeled {} transactions", frame.height());
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use super::{format_timestamp, Context};
+use crate::{
+    export::{Export, ExportFormat},
+    query::Query,
+    trace::{TraceEvent, TxTrace},
+};
+use anyhow::{bail, Result};
+use bitcoin::Txid;
+use clap::Args;
+use polars::prelude::{NamedFrom, Series};
+
+#[derive(Args)]
+pub(crate) struct LookupArgs {
+    txid: Txid,
+    /// Print the recorded rows naming it instead
+    #[arg(long)]
+    rows: bool,
+}
+
+pub(crate) async fn run(args: LookupArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let LookupArgs { txid, rows } = args;
+    let storage = storage_kind.open(&data_dir, network)?;
+    if !rows {
+        let trace = TxTrace::find(storage.as_ref(), &txid.to_string())?;
+        if json_output {
+            println!("{}", serde_json::to_string_pretty(&trace)?);
+            return Ok(());
+        }
+        if let (Some(fee), Some(vsize), Some(fee_rate)) =
+            (trace.fee_sat, trace.vsize, trace.fee_rate_sat_vb)
+        {
+            println!("{txid}: {fee} sat, {vsize} vB, {fee_rate:.2} sat/vB");
+        }
+        if let Some(first_seen_at) = trace.first_seen_at {
+            println!("first seen {}", format_timestamp(first_seen_at as i64));
+        }
+        if let (Some((first_height, first)), Some((last_height, last))) =
+            (trace.snapshots.first(), trace.snapshots.last())
+        {
+            println!(
+                "in {} snapshots, {} at height {first_height} to {} at height {last_height}",
+                trace.snapshots.len(),
+                format_timestamp(*first),
+                format_timestamp(*last)
+            );
+        }
+        for event in &trace.events {
+            let description = match event {
+                TraceEvent::Entered {
+                    height,
+                    effective_fee_rate,
+                    queue_percentile,
+                    ..
+                } => {
+                    let mut entered = format!("entered the mempool at height {height}");
+                    if let Some(rate) = effective_fee_rate {
+                        entered += &format!(", {rate:.2} sat/vB as a package");
+                    }
+                    if let Some(percentile) = queue_percentile {
+                        entered += &format!(", {percentile:.1}% of the mempool ahead");
+                    }
+                    entered
+                }
+                TraceEvent::Replaces { txid, .. } => format!("replaced {txid}"),
+                TraceEvent::Left { height, reason, .. } => format!(
+                    "left the mempool at height {height}: {}",
+                    reason.as_deref().unwrap_or("unknown")
+                ),
+                TraceEvent::ReplacedBy {
+                    txid,
+                    fee_rate_sat_vb,
+                    ..
+                } => match fee_rate_sat_vb {
+                    Some(rate) => format!("replaced by {txid} at {rate:.2} sat/vB"),
+                    None => format!("replaced by {txid}"),
+                },
+                TraceEvent::Confirmed {
+                    height,
+                    wait_blocks,
+                    ..
+                } => match wait_blocks {
+                    Some(blocks) => {
+                        format!("confirmed in block {height} after {blocks} blocks")
+                    }
+                    None => format!("confirmed in block {height}"),
+                },
+                TraceEvent::Rejected { .. } => {
+                    String::from("relayed but never accepted to the mempool")
+                }
+            };
+            println!("{}  {description}", format_timestamp(event.timestamp()));
+        }
+        return Ok(());
+    }
+    let found = Query::lookup(storage.as_ref(), &txid.to_string())?;
+    if found.is_empty() {
+        bail!("{txid} is not in the recorded dataset");
+    }
+    for (table, mut frame) in found {
+        if json_output {
+            frame.with_column(Series::new("table", vec![table; frame.height()]))?;
+            Export::write(&mut frame, ExportFormat::Ndjson, std::io::stdout().lock())?;
+        } else {
+            println!("{table}:\n{frame}");
+        }
+    }
+    Ok(())
+}
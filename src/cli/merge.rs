@@ -0,0 +1,54 @@
+use super::Context;
+use crate::{merge::Merge, storage::StorageKind};
+use anyhow::{bail, Result};
+use clap::Args;
+use std::path::Path;
+
+#[derive(Args)]
+pub(crate) struct MergeArgs {
+    /// Data directory of the other recorder, on the same network
+    other: String,
+    /// How the other dataset is stored [default: like this one]
+    #[arg(long, value_enum)]
+    other_storage: Option<StorageKind>,
+    /// Only count what would be copied and rewritten
+    #[arg(long)]
+    dry_run: bool,
+}
+
+pub(crate) async fn run(args: MergeArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let MergeArgs {
+        other,
+        other_storage,
+        dry_run,
+    } = args;
+    if Path::new(&other) == Path::new(&data_dir) {
+        bail!("{other} is this dataset already");
+    }
+    let storage = storage_kind.open(&data_dir, network)?;
+    let other = other_storage
+        .unwrap_or(storage_kind)
+        .open(&other, network)?;
+    let merged = Merge::run(storage.as_ref(), other.as_ref(), dry_run)?;
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&merged)?);
+        return Ok(());
+    }
+    let verb = if dry_run { "would copy" } else { "copied" };
+    println!(
+        "{verb} {} files and {} full snapshots made up from deltas into the gaps",
+        merged.copied, merged.reconstructed
+    );
+    println!(
+        "{} rows in {} files were seen earlier by the other recorder",
+        merged.earlier_first_seen, merged.rewritten
+    );
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use super::Context;
+use crate::{
+    migrate::Migrate,
+    storage::{LocalStorage, StorageKind, SCHEMA_VERSION},
+};
+use anyhow::{bail, Result};
+use clap::Args;
+use std::path::Path;
+
+#[derive(Args)]
+pub(crate) struct MigrateArgs {
+    /// Only count the files that would be rewritten
+    #[arg(long)]
+    dry_run: bool,
+    /// Then move the files into the directories of this storage, like hive partitions from
+    /// the daily layout of parquet. Record with --storage set to it afterwards.
+    #[arg(long, value_name = "STORAGE")]
+    to: Option<StorageKind>,
+}
+
+pub(crate) async fn run(args: MigrateArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let MigrateArgs { dry_run, to } = args;
+    let local = |kind| match kind {
+        StorageKind::Parquet => Ok(LocalStorage::new(&data_dir, network)),
+        StorageKind::Hive => Ok(LocalStorage::hive(&data_dir, network)),
+        StorageKind::Sqlite => {
+            bail!("only parquet datasets are migrated, sqlite tables gain columns as written")
+        }
+        // files are rewritten under the names the log has for them
+        StorageKind::Table => Ok(LocalStorage::new(&data_dir, network)),
+    };
+    let storage = local(storage_kind)?;
+    let migration = Migrate::run(&storage, Path::new(&data_dir), dry_run)?;
+    let relayout = match to.filter(|to| *to != storage_kind) {
+        Some(to) if storage_kind == StorageKind::Table || to == StorageKind::Table => {
+            bail!("tables keep the parquet layout, pass --storage table to record into one")
+        }
+        Some(to) => {
+            let target = local(to)?;
+            let moved = Migrate::relayout(&storage, &target, dry_run)?;
+            Some((moved, target.root().to_path_buf()))
+        }
+        None => None,
+    };
+    if json_output {
+        let mut report = serde_json::to_value(&migration)?;
+        report["schema_version"] = SCHEMA_VERSION.into();
+        report["dry_run"] = dry_run.into();
+        report["relayout"] = relayout
+            .as_ref()
+            .map(|(moved, root)| serde_json::json!({ "moved": moved, "root": root }))
+            .into();
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    let verb = if dry_run { "would upgrade" } else { "upgraded" };
+    println!(
+        "{verb} {} of {} files to schema version {SCHEMA_VERSION}",
+        migration.outdated, migration.files
+    );
+    if migration.moved > 0 {
+        println!("{} of them from {data_dir}/YYYY/MM/DD", migration.moved);
+    }
+    if let Some((moved, root)) = relayout {
+        let verb = if dry_run { "would move" } else { "moved" };
+        println!("{verb} {moved} files below {}", root.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use super::Context;
+use crate::nostr::NostrPublisher;
+use anyhow::Result;
+use clap::Args;
+use std::path::{Path, PathBuf};
+
+#[derive(Args)]
+pub(crate) struct NostrArgs {
+    /// Relay to publish to, e.g. wss://relay.damus.io, may be repeated
+    #[arg(long)]
+    relay: Vec<String>,
+    /// File holding the secret key, hex or nsec, created if missing
+    /// [default: <data-dir>/nostr.key]
+    #[arg(long)]
+    secret_key_file: Option<PathBuf>,
+    /// Seconds between events at least [default: 60]
+    #[arg(long)]
+    every: Option<u64>,
+    /// Seconds between looks for a new snapshot [default: 10]
+    #[arg(long)]
+    poll: Option<u64>,
+}
+
+pub(crate) async fn run(args: NostrArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        ..
+    } = context;
+    let NostrArgs {
+        relay,
+        secret_key_file,
+        every,
+        poll,
+    } = args;
+    let nostr = config.nostr;
+    let relays = if relay.is_empty() { nostr.relay } else { relay };
+    let secret_key_file = secret_key_file
+        .or(nostr.secret_key_file)
+        .unwrap_or_else(|| Path::new(&data_dir).join("nostr.key"));
+    let publisher = NostrPublisher::start(&relays, &secret_key_file, network)?;
+    publisher
+        .watch(
+            storage_kind.open(&data_dir, network)?.as_ref(),
+            std::time::Duration::from_secs(poll.or(nostr.poll).unwrap_or(10)),
+            std::time::Duration::from_secs(every.or(nostr.every).unwrap_or(60)),
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use super::{parse_timestamp, Context};
+use crate::plot::{Plot, PlotKind};
+use anyhow::Result;
+use clap::Args;
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Args)]
+pub(crate) struct PlotArgs {
+    /// What to plot
+    #[arg(value_enum)]
+    kind: PlotKind,
+    /// Image file to write, SVG or PNG by its extension
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Unix timestamp or RFC 3339 date to start at [default: the first snapshot]
+    #[arg(long, value_parser = parse_timestamp)]
+    from: Option<i64>,
+    /// Unix timestamp or RFC 3339 date to end at [default: the last snapshot]
+    #[arg(long, value_parser = parse_timestamp)]
+    to: Option<i64>,
+    /// Seconds between the snapshots plotted [default: 300 heatmap columns, estimates every
+    /// 600 s]
+    #[arg(long)]
+    every: Option<i64>,
+    /// Confirmation target in blocks of the estimates
+    #[arg(short, long, default_value_t = 1)]
+    target: u32,
+    /// Confidence of the estimates [default: 0.95]
+    #[arg(short, long)]
+    confidence: Option<f64>,
+}
+
+pub(crate) async fn run(args: PlotArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        ..
+    } = context;
+    let PlotArgs {
+        kind,
+        output,
+        from,
+        to,
+        every,
+        target,
+        confidence,
+    } = args;
+    let confidence = confidence
+        .or(config.calc.confidence.first().copied())
+        .unwrap_or(0.95);
+    Plot::render(
+        storage_kind.open(&data_dir, network)?.as_ref(),
+        kind,
+        &output,
+        from.unwrap_or(i64::MIN),
+        to.unwrap_or(i64::MAX),
+        every,
+        target,
+        confidence,
+    )?;
+    info!("wrote {}", output.display());
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use super::{format_timestamp, Context, NodeArgs};
+use crate::position::QueuePosition;
+use anyhow::{bail, Result};
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct PositionArgs {
+    /// sat/vB
+    #[arg(long)]
+    feerate: f64,
+    #[command(flatten)]
+    node: NodeArgs,
+    /// Use the latest recorded snapshot instead of asking the node
+    #[arg(long)]
+    recorded: bool,
+}
+
+pub(crate) async fn run(args: PositionArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let PositionArgs {
+        feerate,
+        node,
+        recorded,
+    } = args;
+    let json = json_output;
+    if !feerate.is_finite() || feerate < 0. {
+        bail!("fee rate must be at least 0, got {feerate}");
+    }
+    let position = if recorded {
+        QueuePosition::from_recorded(storage_kind.open(&data_dir, network)?.as_ref(), feerate)?
+    } else {
+        let node = node.or(&config.record).node(network, &data_dir).await?;
+        QueuePosition::from_node(node.as_ref(), feerate).await?
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&position)?);
+    } else {
+        println!(
+            "height {}, {} ({}): {} transactions, {:.2} MvB at or above {} sat/vB, \
+             {} full projected blocks, a transaction paying it lands in block {}",
+            position.height,
+            format_timestamp(position.timestamp),
+            position.source,
+            position.txs,
+            position.vsize as f64 / 1e6,
+            position.fee_rate,
+            position.blocks,
+            position.block
+        );
+    }
+    Ok(())
+}
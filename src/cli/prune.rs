@@ -0,0 +1,53 @@
+use super::{parse_size, Context};
+use crate::{
+    prune::Retention,
+    storage::{LocalStorage, Storage},
+};
+use anyhow::Result;
+use chrono::Utc;
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct PruneArgs {
+    /// Keep this many days of data
+    #[arg(long, required_unless_present = "max_dataset_size")]
+    retention_days: Option<u32>,
+    /// Remove the oldest files, compacted days first, while the dataset takes up more
+    /// than this, e.g. 50GB or 500MiB
+    #[arg(long, value_parser = parse_size)]
+    max_dataset_size: Option<u64>,
+    /// Move pruned files to this data directory instead of deleting them
+    #[arg(long)]
+    archive_dir: Option<String>,
+}
+
+pub(crate) async fn run(args: PruneArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let PruneArgs {
+        retention_days,
+        max_dataset_size,
+        archive_dir,
+    } = args;
+    let retention = Retention {
+        days: retention_days,
+        max_bytes: max_dataset_size,
+        archive: archive_dir
+            .map(|dir| Box::new(LocalStorage::new(dir, network)) as Box<dyn Storage>),
+    };
+    let pruned = retention.prune(
+        storage_kind.open(&data_dir, network)?.as_ref(),
+        Utc::now().timestamp(),
+    )?;
+    if json_output {
+        println!("{}", serde_json::json!({ "pruned": pruned }));
+    } else {
+        println!("pruned {pruned} files");
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use super::Context;
+use crate::{
+    calc::Calc,
+    model::Trained,
+    psbt::{ChangeAdjustment, PsbtFee},
+    replay::Replay,
+};
+use anyhow::{bail, Result};
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub(crate) struct PsbtArgs {
+    /// PSBT file, binary or base64
+    #[arg(short, long)]
+    file: PathBuf,
+    /// Blocks to confirm within
+    #[arg(short, long)]
+    target: u32,
+    /// Probability of confirming within the target [default: 0.95]
+    #[arg(short, long)]
+    confidence: Option<f64>,
+    /// Output paying the change [default: the only one with key origins]
+    #[arg(long)]
+    change: Option<usize>,
+    /// Estimate with a model saved by `train` or an ONNX model taking the same features
+    #[arg(long)]
+    model_file: Option<PathBuf>,
+}
+
+pub(crate) async fn run(args: PsbtArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let PsbtArgs {
+        file,
+        target,
+        confidence,
+        change,
+        model_file,
+    } = args;
+    let json = json_output;
+    let confidence = confidence
+        .or(config.calc.confidence.first().copied())
+        .unwrap_or(0.95);
+    if !(confidence > 0. && confidence <= 1.) {
+        bail!("confidence must be in (0, 1], got {confidence}");
+    }
+    let psbt = PsbtFee::read(&file)?;
+    let storage = storage_kind.open(&data_dir, network)?;
+    let fee_rate = match model_file.or(config.calc.model_file) {
+        Some(path) => Trained::load(&path)?.estimate(
+            &Replay::new(storage.as_ref())?.at(i64::MAX)?,
+            target,
+            confidence,
+        )?,
+        None => Calc::calc(storage.as_ref(), confidence, target)?,
+    };
+    let change = change.or_else(|| PsbtFee::change_output(&psbt));
+    let suggestion = PsbtFee::suggest(&psbt, change, target, confidence, fee_rate)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&suggestion)?);
+        return Ok(());
+    }
+    println!(
+        "{}: about {} vB signed, paying {} sat, {:.2} sat/vB",
+        suggestion.txid, suggestion.vsize, suggestion.fee, suggestion.fee_rate
+    );
+    println!(
+        "{target} blocks at {confidence} needs {:.2} sat/vB, {} sat, {:+} sat",
+        suggestion.target_fee_rate, suggestion.required_fee, suggestion.fee_delta
+    );
+    match &suggestion.change {
+        Some(ChangeAdjustment {
+            index,
+            value,
+            adjusted: Some(adjusted),
+            dust,
+        }) => println!(
+            "change output {index}: {value} sat becomes {adjusted} sat{}",
+            if *dust { ", dust" } else { "" }
+        ),
+        Some(ChangeAdjustment { index, value, .. }) => {
+            println!("change output {index} holds {value} sat, too little to pay it")
+        }
+        None => println!("no change output found, pass --change"),
+    }
+    Ok(())
+}
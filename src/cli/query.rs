@@ -0,0 +1,51 @@
+use super::Context;
+use crate::{
+    export::{Export, ExportFormat},
+    query::Query,
+};
+use anyhow::Result;
+use clap::Args;
+use std::{io::BufWriter, path::PathBuf};
+
+#[derive(Args)]
+pub(crate) struct QueryArgs {
+    sql: String,
+    /// Write all rows in this format instead of printing a table
+    #[arg(short = 'f', long = "file-format", value_enum)]
+    format: Option<ExportFormat>,
+    /// Write to this file instead of stdout
+    #[arg(short, long, requires = "format")]
+    output: Option<PathBuf>,
+}
+
+pub(crate) async fn run(args: QueryArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let QueryArgs {
+        sql,
+        format,
+        output,
+    } = args;
+    let mut frame = Query::query(storage_kind.open(&data_dir, network)?.as_ref(), &sql)?;
+    match (format, output) {
+        (Some(format), Some(output)) => {
+            let file = BufWriter::new(std::fs::File::create(output)?);
+            Export::write(&mut frame, format, file)?;
+        }
+        (Some(format), None) => Export::write(&mut frame, format, std::io::stdout().lock())?,
+        (None, Some(output)) if json_output => {
+            let file = BufWriter::new(std::fs::File::create(output)?);
+            Export::write(&mut frame, ExportFormat::Ndjson, file)?;
+        }
+        (None, None) if json_output => {
+            Export::write(&mut frame, ExportFormat::Ndjson, std::io::stdout().lock())?
+        }
+        (None, _) => println!("{frame}"),
+    }
+    Ok(())
+}
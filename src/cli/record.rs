@@ -0,0 +1,26 @@
+use super::{record, Context, RecordArgs};
+use crate::config::Config;
+use anyhow::Result;
+
+pub(crate) async fn run(args: RecordArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        config_file,
+        data_dir,
+        network,
+        storage_kind,
+        ..
+    } = context;
+    let configs = Config::on_hangup(config_file)?;
+    record(
+        args,
+        config.record,
+        &data_dir,
+        network,
+        storage_kind,
+        None,
+        configs,
+    )
+    .await?;
+    Ok(())
+}
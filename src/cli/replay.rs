@@ -0,0 +1,51 @@
+use super::{parse_timestamp, Context};
+use crate::replay::Replay;
+use anyhow::Result;
+use clap::Args;
+use polars::prelude::ParquetWriter;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub(crate) struct ReplayArgs {
+    /// Unix timestamp or RFC 3339 date, the last snapshot up to then is replayed
+    #[arg(short, long, value_parser = parse_timestamp)]
+    at: i64,
+    /// Write the transactions to this parquet file
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+pub(crate) async fn run(args: ReplayArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let ReplayArgs { at, output } = args;
+    let snapshot = Replay::new(storage_kind.open(&data_dir, network)?.as_ref())?.at(at)?;
+    let weight: f64 = snapshot.transactions.values().map(|tx| tx.weight).sum();
+    if json_output {
+        let summary = serde_json::json!({
+            "height": snapshot.height,
+            "timestamp": snapshot.timestamp,
+            "transactions": snapshot.transactions.len(),
+            "weight": weight,
+        });
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    } else {
+        println!(
+            "height {}, snapshot {}: {} transactions, {:.0} WU",
+            snapshot.height,
+            snapshot.timestamp,
+            snapshot.transactions.len(),
+            weight
+        );
+    }
+    if let Some(output) = output {
+        let file = std::fs::File::create(&output)?;
+        ParquetWriter::new(file).finish(&mut snapshot.to_frame()?)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use super::{record, Context, RecordArgs, ServeArgs};
+use crate::config::Config;
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct RunArgs {
+    #[command(flatten)]
+    record: RecordArgs,
+    #[command(flatten)]
+    serve: ServeArgs,
+}
+
+pub(crate) async fn run(args: RunArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        config_file,
+        data_dir,
+        network,
+        storage_kind,
+        ..
+    } = context;
+    let RunArgs {
+        record: args,
+        serve,
+    } = args;
+    let configs = Config::on_hangup(config_file)?;
+    let (listen, mut options) = serve
+        .options(
+            &config,
+            configs.clone(),
+            args.node.clone(),
+            network,
+            &data_dir,
+        )
+        .await?;
+    // the recorder tells systemd
+    options.notify = false;
+    let serve = (listen, options);
+    record(
+        args,
+        config.record,
+        &data_dir,
+        network,
+        storage_kind,
+        Some(serve),
+        configs,
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use super::{Context, NodeArgs, ServeArgs};
+use crate::{
+    config::Config,
+    record::POLL_INTERVAL_SECS,
+    serve::Serve,
+    storage::{CachedStorage, StorageKind},
+};
+use anyhow::Result;
+use tracing::warn;
+
+pub(crate) async fn run(serve: ServeArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        config_file,
+        data_dir,
+        network,
+        storage_kind,
+        ..
+    } = context;
+    let configs = Config::on_hangup(config_file)?;
+    let (listen, mut options) = serve
+        .options(&config, configs, NodeArgs::default(), network, &data_dir)
+        .await?;
+    options
+        .cache_ttl
+        .get_or_insert(std::time::Duration::from_secs(POLL_INTERVAL_SECS as u64));
+    // another process records, new files are read as they appear
+    let storage = CachedStorage::new(storage_kind.open(&data_dir, network)?)?;
+    let _watcher = match storage_kind {
+        StorageKind::Sqlite => {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                let mut poll = tokio::time::interval(std::time::Duration::from_secs(
+                    POLL_INTERVAL_SECS as u64,
+                ));
+                loop {
+                    poll.tick().await;
+                    let refreshing = storage.clone();
+                    match tokio::task::spawn_blocking(move || refreshing.refresh()).await {
+                        Ok(Err(e)) => warn!("refreshing the dataset failed: {e:#}"),
+                        Err(e) => warn!("refreshing the dataset failed: {e}"),
+                        Ok(Ok(_)) => {}
+                    }
+                }
+            });
+            None
+        }
+        _ => Some(storage.watch(&storage_kind.root(&data_dir, network))?),
+    };
+    Serve::serve(Box::new(storage), listen, options).await?;
+    Ok(())
+}
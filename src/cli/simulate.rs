@@ -0,0 +1,81 @@
+use super::{Context, NodeArgs};
+use crate::simulate::{FeeDistribution, Scenario, Simulate};
+use anyhow::{bail, Result};
+use bitcoin::Network;
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct SimulateArgs {
+    #[command(flatten)]
+    node: NodeArgs,
+    /// Blocks to mine
+    #[arg(long, default_value_t = 10)]
+    blocks: u32,
+    /// Transactions sent before each block
+    #[arg(long, default_value_t = 20)]
+    transactions_per_block: u32,
+    /// Seconds between blocks, the transactions are spread over them
+    #[arg(long, default_value_t = 30)]
+    block_interval: u64,
+    /// Fee rates in sat/vB, fixed:RATE, uniform:MIN:MAX or lognormal:MEDIAN:SIGMA
+    #[arg(long, default_value = "lognormal:5:0.8")]
+    fee_rates: FeeDistribution,
+    /// Lower fee rates are raised to this, sat/vB
+    #[arg(long, default_value_t = 1.)]
+    min_fee_rate: f64,
+    /// Mine only the transactions paying the most up to this many vbytes per block, so
+    /// the mempool backs up [default: everything]
+    #[arg(long)]
+    block_vsize: Option<u64>,
+    /// The same seed sends the same fee rates
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Wallet to send from, created and funded if need be
+    #[arg(long, default_value = "wtf-simulate")]
+    wallet: String,
+}
+
+pub(crate) async fn run(args: SimulateArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        network,
+        json_output,
+        ..
+    } = context;
+    let SimulateArgs {
+        node,
+        blocks,
+        transactions_per_block,
+        block_interval,
+        fee_rates,
+        min_fee_rate,
+        block_vsize,
+        seed,
+        wallet,
+    } = args;
+    if network != Network::Regtest {
+        bail!("only regtest can be simulated, pass --network regtest");
+    }
+    let Some(rpc) = node.or(&config.record).rpc(network)? else {
+        bail!("simulate needs rpc_endpoint, REST has no wallet");
+    };
+    let scenario = Scenario {
+        blocks,
+        transactions_per_block,
+        block_interval: std::time::Duration::from_secs(block_interval),
+        fee_rates,
+        min_fee_rate,
+        block_vsize,
+        seed,
+    };
+    let simulated = Simulate::run(&rpc.with_wallet(&wallet), &wallet, &scenario).await?;
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&simulated)?);
+        return Ok(());
+    }
+    println!(
+        "mined {} blocks, sent {} transactions, {} failed, {} left in the mempool",
+        simulated.blocks, simulated.transactions, simulated.failed, simulated.left
+    );
+    Ok(())
+}
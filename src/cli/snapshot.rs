@@ -0,0 +1,71 @@
+use super::{Context, NodeArgs};
+use crate::{
+    export::{Export, ExportFormat},
+    record::{DeltaFrame, MempoolEntry},
+};
+use anyhow::{bail, Result};
+use chrono::Utc;
+use clap::Args;
+use polars::prelude::{NamedFrom, ParquetWriter, Series};
+use std::{io::BufWriter, path::PathBuf};
+use tracing::info;
+
+#[derive(Args)]
+pub(crate) struct SnapshotArgs {
+    #[command(flatten)]
+    node: NodeArgs,
+    /// Format of the file, CSV, newline-delimited JSON or Arrow IPC [default: parquet]
+    #[arg(short = 'f', long = "file-format", value_enum)]
+    format: Option<ExportFormat>,
+    /// Write to this file instead of stdout, parquet needs one
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+pub(crate) async fn run(args: SnapshotArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        ..
+    } = context;
+    let SnapshotArgs {
+        node,
+        format,
+        output,
+    } = args;
+    if format == Some(ExportFormat::Duckdb) {
+        bail!("a snapshot is one table, use parquet, csv, ndjson or arrow");
+    }
+    if format.is_none() && output.is_none() {
+        bail!("parquet snapshots are written to a file, pass --output");
+    }
+    let node = node.or(&config.record).node(network, &data_dir).await?;
+    let chain = node.get_chain_info().await?;
+    let mempool = MempoolEntry::mempool(node.get_mempool().await?);
+    let timestamp = Utc::now().timestamp();
+    let frame = DeltaFrame::full(&mempool, &chain.best_block_hash).frame;
+    // tagged like the rows `wtf query` answers with, the file stands on its own
+    let rows = frame.height();
+    let mut frame = frame.hstack(&[
+        Series::new("height", vec![chain.blocks; rows]),
+        Series::new("snapshot_timestamp", vec![timestamp; rows]),
+    ])?;
+    match (format, output) {
+        (None, Some(output)) => {
+            let file = std::fs::File::create(&output)?;
+            ParquetWriter::new(file).finish(&mut frame)?;
+        }
+        (Some(format), Some(output)) => {
+            let file = BufWriter::new(std::fs::File::create(output)?);
+            Export::write(&mut frame, format, file)?;
+        }
+        (Some(format), None) => Export::write(&mut frame, format, std::io::stdout().lock())?,
+        (None, None) => unreachable!(),
+    }
+    info!(
+        "height: {}, timestamp: {timestamp}, transactions: {rows}",
+        chain.blocks
+    );
+    Ok(())
+}
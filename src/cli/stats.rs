@@ -0,0 +1,74 @@
+use super::Context;
+use crate::stats::{Stats, PERCENTILES};
+use anyhow::Result;
+
+pub(crate) async fn run(context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let days = Stats::days(storage_kind.open(&data_dir, network)?.as_ref())?;
+    if json_output {
+        let days: Vec<_> = days
+            .iter()
+            .map(|day| {
+                let churn = day.churn_per_hour();
+                serde_json::json!({
+                    "day": day.day.to_string(),
+                    "files": day.files,
+                    "bytes": day.bytes,
+                    "full_rows": day.full_rows,
+                    "delta_rows": day.delta_rows,
+                    "block_rows": day.block_rows,
+                    "added": day.added,
+                    "removed": day.removed,
+                    "recorded_secs": day.recorded_secs,
+                    "fee_rate_percentiles": PERCENTILES,
+                    "fee_rates_sat_vb": day.fee_rates,
+                    "added_per_hour": churn.map(|(added, _)| added),
+                    "removed_per_hour": churn.map(|(_, removed)| removed),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&days)?);
+        return Ok(());
+    }
+    for day in &days {
+        let size = day
+            .bytes
+            .map(|b| format!("{:.1} MB", b as f64 / 1e6))
+            .unwrap_or_else(|| String::from("size unknown"));
+        println!(
+            "{}: {} files, {size}, rows {} full / {} delta / {} block",
+            day.day, day.files, day.full_rows, day.delta_rows, day.block_rows
+        );
+        if !day.fee_rates.is_empty() {
+            let rates: Vec<String> = PERCENTILES
+                .iter()
+                .zip(&day.fee_rates)
+                .map(|(p, rate)| format!("p{:.0} {rate:.2}", p * 100.))
+                .collect();
+            println!("  fee rate sat/vB: {}", rates.join(", "));
+        }
+        if let Some((added, removed)) = day.churn_per_hour() {
+            println!(
+                "  churn tx/h: +{added:.0} -{removed:.0} over {:.1} h",
+                day.recorded_secs as f64 / 3600.
+            );
+        }
+    }
+    let files: usize = days.iter().map(|d| d.files).sum();
+    let bytes: Option<u64> = days.iter().map(|d| d.bytes).sum();
+    match bytes {
+        Some(bytes) => println!(
+            "total: {} days, {files} files, {:.1} MB",
+            days.len(),
+            bytes as f64 / 1e6
+        ),
+        None => println!("total: {} days, {files} files", days.len()),
+    }
+    Ok(())
+}
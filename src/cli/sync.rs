@@ -0,0 +1,76 @@
+use super::{parse_timestamp, Context};
+use crate::{
+    node::http_client,
+    sync::{FileFilter, PeerSync},
+};
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct SyncArgs {
+    /// Address of the other instance, e.g. http://other-recorder:3000
+    #[arg(long)]
+    from: String,
+    /// Unix timestamp or RFC 3339 date of the first file [default: the first one there]
+    #[arg(long, value_parser = parse_timestamp)]
+    since: Option<i64>,
+    /// Unix timestamp or RFC 3339 date of the last file [default: the last one there]
+    #[arg(long, value_parser = parse_timestamp)]
+    until: Option<i64>,
+    /// Only files of this block height or later
+    #[arg(long)]
+    min_height: Option<u64>,
+    /// Only files of this block height or earlier
+    #[arg(long)]
+    max_height: Option<u64>,
+    /// Keep pulling new files, this many seconds apart
+    #[arg(long)]
+    every: Option<u64>,
+    /// Reach the other instance through this SOCKS5 proxy, e.g. Tor at socks5://127.0.0.1:9050
+    #[arg(long)]
+    proxy: Option<String>,
+}
+
+pub(crate) async fn run(args: SyncArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let SyncArgs {
+        from,
+        since,
+        until,
+        min_height,
+        max_height,
+        every,
+        proxy,
+    } = args;
+    let peer = PeerSync::new(from, http_client(proxy.as_deref())?);
+    let filter = FileFilter {
+        from: since,
+        to: until,
+        min_height,
+        max_height,
+    };
+    let storage = storage_kind.open(&data_dir, network)?;
+    loop {
+        let synced = peer.pull(storage.as_ref(), &filter, network).await?;
+        if json_output {
+            // a line per pull with --every
+            println!("{}", serde_json::to_string(&synced)?);
+        } else {
+            println!(
+                "pulled {} of {} files, {} bytes",
+                synced.pulled, synced.listed, synced.bytes
+            );
+        }
+        let Some(every) = every else {
+            break;
+        };
+        tokio::time::sleep(std::time::Duration::from_secs(every)).await;
+    }
+    Ok(())
+}
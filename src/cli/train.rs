@@ -0,0 +1,125 @@
+use super::{confidences, Context};
+use crate::{
+    calc::{Band, TARGETS},
+    crossval::CrossValidation,
+    model::{Model, QuantileRegression, Trained, TRAIN_EVERY_SECS},
+};
+use anyhow::{bail, Result};
+use clap::Args;
+use polars::prelude::{ParquetReader, SerReader};
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Args)]
+pub(crate) struct TrainArgs {
+    /// Model to fit
+    #[arg(long, value_enum, default_value_t = Model::Survival)]
+    model: Model,
+    /// Confirmation target in blocks quantile regression is fitted for, may be repeated
+    /// [default: 1, 3, 6, 12 and 144]
+    #[arg(short, long)]
+    target: Vec<u32>,
+    /// Confidence percentages quantile regression is fitted for, separated by commas
+    /// [default: 0.95]
+    #[arg(short, long, value_delimiter = ',')]
+    confidence: Vec<f64>,
+    /// Preset confidence levels instead of --confidence
+    #[arg(long, value_enum, conflicts_with = "confidence")]
+    band: Option<Band>,
+    /// Seconds between the snapshots quantile regression and seasonality are fitted to
+    #[arg(long, default_value_t = TRAIN_EVERY_SECS)]
+    every: i64,
+    /// Model file to write, ONNX if it ends in `.onnx` (quantile regression only)
+    #[arg(short, long, required_unless_present = "cv")]
+    output: Option<PathBuf>,
+    /// Fit and score the model on chronological folds first, e.g. walk-forward:8, and
+    /// report how each fold's estimates fared on the window after it
+    #[arg(long, value_parser = CrossValidation::parse)]
+    cv: Option<CrossValidation>,
+    /// Fit quantile regression to a table written by `wtf features` instead of replaying
+    /// the dataset
+    #[arg(long, conflicts_with = "cv")]
+    features: Option<PathBuf>,
+}
+
+pub(crate) async fn run(args: TrainArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let TrainArgs {
+        model,
+        target,
+        confidence,
+        band,
+        every,
+        output,
+        cv,
+        features,
+    } = args;
+    let targets = if target.is_empty() {
+        TARGETS.to_vec()
+    } else {
+        target
+    };
+    let confidences = confidences(confidence, band, &config.calc, &[0.95]);
+    let storage = storage_kind.open(&data_dir, network)?;
+    if let Some(cv) = cv {
+        let folds = cv.run(storage.as_ref(), model, &targets, &confidences, every)?;
+        if json_output {
+            println!("{}", serde_json::to_string_pretty(&folds)?);
+        } else {
+            println!(
+                "{:>4} {:>10} {:>6} {:>6} {:>8} {:>9} {:>13} {:>15}",
+                "fold",
+                "confidence",
+                "target",
+                "scored",
+                "misses",
+                "miss rate",
+                "mean overpay",
+                "median overpay"
+            );
+            for fold in &folds {
+                for target in &fold.targets {
+                    println!(
+                        "{:>4} {:>10} {:>6} {:>6} {:>8} {:>8.1}% {:>12.1}% {:>14.1}%",
+                        fold.fold,
+                        fold.confidence,
+                        target.target,
+                        target.scored,
+                        target.misses,
+                        target.miss_rate * 100.,
+                        target.mean_overpay_pct,
+                        target.median_overpay_pct
+                    );
+                }
+            }
+        }
+    }
+    if let Some(output) = output {
+        let trained = match features {
+            Some(path) => {
+                if model != Model::Quantile {
+                    bail!("only quantile regression is fitted to a features table");
+                }
+                let file = std::fs::File::open(&path)?;
+                let table = ParquetReader::new(file).finish()?;
+                Trained::Quantile(QuantileRegression::fit_table(
+                    storage.as_ref(),
+                    &table,
+                    &targets,
+                    &confidences,
+                )?)
+            }
+            None => Trained::train(storage.as_ref(), model, &targets, &confidences, every)?,
+        };
+        trained.save(&output)?;
+        info!("saved the {model:?} model to {}", output.display());
+    }
+    Ok(())
+}
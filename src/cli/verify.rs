@@ -0,0 +1,86 @@
+use super::{format_timestamp, Context};
+use crate::verify::Verify;
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct VerifyArgs {
+    /// Only verify this day (YYYY-MM-DD) [default: every day]
+    #[arg(long)]
+    day: Option<NaiveDate>,
+    /// Compare the checksum of every file with the manifest, not only its size
+    #[arg(long)]
+    checksums: bool,
+    /// Only compare the files with the manifest, without replaying them
+    #[arg(long)]
+    manifest_only: bool,
+}
+
+pub(crate) async fn run(args: VerifyArgs, context: Context) -> Result<()> {
+    let Context {
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let VerifyArgs {
+        day,
+        checksums,
+        manifest_only,
+    } = args;
+    let storage = storage_kind.open(&data_dir, network)?;
+    let manifest = Verify::manifest(storage.as_ref(), day, checksums)?;
+    if manifest_only && manifest.is_none() {
+        bail!("the dataset has no manifest");
+    }
+    let mut verified = if manifest_only {
+        Default::default()
+    } else {
+        Verify::run(storage.as_ref(), day)?
+    };
+    if let Some((_, problems)) = &manifest {
+        verified.problems.extend(problems.iter().cloned());
+    }
+    if json_output {
+        let mut report = serde_json::to_value(&verified)?;
+        report["manifest_files"] = manifest.as_ref().map(|(files, _)| *files).into();
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        if !verified.problems.is_empty() {
+            bail!("{} problems found", verified.problems.len());
+        }
+        return Ok(());
+    }
+    for problem in &verified.problems {
+        println!(
+            "[{}] {} {} at {}: {}",
+            problem.issue.as_str(),
+            problem.kind.as_str(),
+            format_timestamp(problem.timestamp),
+            problem.height,
+            problem.detail
+        );
+        println!("       {}", problem.path);
+    }
+    if !manifest_only {
+        println!(
+            "verified {} snapshots of {} days",
+            verified.snapshots, verified.days
+        );
+    }
+    match &manifest {
+        Some((files, _)) => println!("compared {files} files with the manifest"),
+        None => println!("no manifest to compare the files with"),
+    }
+    if verified.unchecked > 0 {
+        println!(
+            "{} deltas after unreadable snapshots couldn't be checked",
+            verified.unchecked
+        );
+    }
+    if !verified.problems.is_empty() {
+        bail!("{} problems found", verified.problems.len());
+    }
+    Ok(())
+}
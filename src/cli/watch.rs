@@ -0,0 +1,94 @@
+use super::{format_timestamp, Context, NodeArgs};
+use crate::watch::{Departure, Source, TxPosition};
+use anyhow::{bail, Result};
+use bitcoin::Txid;
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct WatchArgs {
+    txid: Txid,
+    #[command(flatten)]
+    node: NodeArgs,
+    /// Look in the latest recorded snapshot instead of asking the node
+    #[arg(long)]
+    recorded: bool,
+    /// Keep watching and print every change until the transaction leaves the mempool, a
+    /// line of JSON each with `--format json`
+    #[arg(short, long)]
+    follow: bool,
+    /// Seconds between looks when following
+    #[arg(long, default_value_t = 10)]
+    poll: u64,
+}
+
+pub(crate) async fn run(args: WatchArgs, context: Context) -> Result<()> {
+    let Context {
+        config,
+        data_dir,
+        network,
+        storage_kind,
+        json_output,
+        ..
+    } = context;
+    let WatchArgs {
+        txid,
+        node,
+        recorded,
+        follow,
+        poll,
+    } = args;
+    let json = json_output;
+    let (storage, node) = if recorded {
+        (Some(storage_kind.open(&data_dir, network)?), None)
+    } else {
+        (
+            None,
+            Some(node.or(&config.record).node(network, &data_dir).await?),
+        )
+    };
+    let source = match (&storage, &node) {
+        (Some(storage), _) => Source::Recorded(storage.as_ref()),
+        (_, Some(node)) => Source::Node(node.as_ref()),
+        _ => unreachable!(),
+    };
+    let print = |position: &TxPosition| {
+        if json {
+            println!("{}", serde_json::to_string(position).unwrap_or_default());
+        } else {
+            println!(
+                "height {}, {} ({}): {:.2} sat/vB effective ({:.2} own), {:.0} kvB ahead, \
+                 projected block {}, expected in {} minutes",
+                position.height,
+                format_timestamp(position.timestamp),
+                position.source,
+                position.effective_fee_rate,
+                position.fee_rate,
+                position.vsize_ahead as f64 / 1e3,
+                position.block,
+                position.eta_secs / 60
+            );
+        }
+    };
+    if !follow {
+        match TxPosition::find(source, &txid).await? {
+            Some(position) => print(&position),
+            None => bail!("{txid} is not in the mempool"),
+        }
+    } else {
+        let departure =
+            TxPosition::follow(source, &txid, std::time::Duration::from_secs(poll), print).await?;
+        if json {
+            println!("{}", serde_json::to_string(&departure)?);
+        } else {
+            match departure {
+                Departure::Confirmed { height } => {
+                    println!("{txid} confirmed in block {height}")
+                }
+                Departure::Left { height } => {
+                    println!("{txid} left the mempool unconfirmed at height {height}")
+                }
+            }
+        }
+    }
+    Ok(())
+}
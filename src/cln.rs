@@ -0,0 +1,256 @@
+use crate::{
+    calc::{Calc, MIN_RELAY_FEE_RATE},
+    model::Trained,
+    replay::Replay,
+    rpc::RpcClient,
+    storage::Storage,
+};
+use anyhow::{anyhow, bail, Result};
+use bitcoin::Network;
+use serde_json::{json, Value};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use tracing::{debug, info, warn};
+
+/// Targets `estimatefees` answers for, the ones `bcli` uses
+const TARGETS: [u32; 4] = [2, 6, 12, 100];
+const CONFIDENCE_OPTION: &str = "wtf-confidence";
+
+/// Core Lightning's Bitcoin backend, run by `lightningd` in place of `bcli`
+/// (`disable-plugin=bcli`). It talks CLN's plugin protocol, JSON-RPC over stdin and stdout,
+/// answers `estimatefees` from the dataset and passes the chain access the backend is also
+/// responsible for through to Bitcoin Core's JSON-RPC.
+pub struct ClnPlugin {
+    storage: Box<dyn Storage>,
+    rpc: RpcClient,
+    model: Option<Trained>,
+    network: Network,
+    confidence: f64,
+}
+
+impl ClnPlugin {
+    pub fn new(
+        storage: Box<dyn Storage>,
+        rpc: RpcClient,
+        model: Option<Trained>,
+        network: Network,
+        confidence: f64,
+    ) -> Self {
+        ClnPlugin {
+            storage,
+            rpc,
+            model,
+            network,
+            confidence,
+        }
+    }
+
+    /// Answer requests until `lightningd` closes stdin
+    pub async fn run(mut self) -> Result<()> {
+        // lightningd separates messages with blank lines, a streaming parser doesn't care
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            let stdin = std::io::stdin();
+            for message in serde_json::Deserializer::from_reader(stdin.lock()).into_iter::<Value>()
+            {
+                match message {
+                    Ok(message) => {
+                        if sender.send(message).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        warn!("unreadable message from lightningd: {e}");
+                        break;
+                    }
+                }
+            }
+        });
+
+        let mut stdout = tokio::io::stdout();
+        while let Some(request) = receiver.recv().await {
+            let method = request["method"].as_str().unwrap_or_default().to_string();
+            // notifications need no answer
+            let Some(id) = request.get("id").cloned() else {
+                continue;
+            };
+            debug!("lightningd called {method}");
+            let response = match self.handle(&method, &request["params"]).await {
+                Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                Err(e) => {
+                    warn!("{method} failed: {e:#}");
+                    json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {"code": -32600, "message": format!("{e:#}")},
+                    })
+                }
+            };
+            let mut message = serde_json::to_vec(&response)?;
+            message.extend(b"\n\n");
+            stdout.write_all(&message).await?;
+            stdout.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn handle(&mut self, method: &str, params: &Value) -> Result<Value> {
+        match method {
+            "getmanifest" => Ok(Self::manifest()),
+            "init" => self.init(params),
+            "estimatefees" => Ok(self.estimate_fees()),
+            "getchaininfo" => {
+                let info: Value = self.rpc.call("getblockchaininfo", json!([])).await?;
+                Ok(json!({
+                    "chain": info["chain"],
+                    "headercount": info["headers"],
+                    "blockcount": info["blocks"],
+                    "ibd": info["initialblockdownload"],
+                }))
+            }
+            "getrawblockbyheight" => {
+                let height = params["height"]
+                    .as_u64()
+                    .ok_or_else(|| anyhow!("missing height"))?;
+                // past the tip, lightningd asks again later
+                let Ok(hash) = self
+                    .rpc
+                    .call::<String>("getblockhash", json!([height]))
+                    .await
+                else {
+                    return Ok(json!({"blockhash": null, "block": null}));
+                };
+                let block: String = self.rpc.call("getblock", json!([hash, 0])).await?;
+                Ok(json!({"blockhash": hash, "block": block}))
+            }
+            "getutxout" => {
+                let txid = params["txid"]
+                    .as_str()
+                    .ok_or_else(|| anyhow!("missing txid"))?;
+                let vout = params["vout"]
+                    .as_u64()
+                    .ok_or_else(|| anyhow!("missing vout"))?;
+                let output: Value = self.rpc.call("gettxout", json!([txid, vout])).await?;
+                if output.is_null() {
+                    return Ok(json!({"amount": null, "script": null}));
+                }
+                let btc = output["value"]
+                    .as_f64()
+                    .ok_or_else(|| anyhow!("gettxout returned no value"))?;
+                Ok(json!({
+                    "amount": (btc * 100_000_000.).round() as u64,
+                    "script": output["scriptPubKey"]["hex"],
+                }))
+            }
+            "sendrawtransaction" => {
+                let tx = params["tx"].as_str().ok_or_else(|| anyhow!("missing tx"))?;
+                let mut args = vec![json!(tx)];
+                if params["allowhighfees"].as_bool() == Some(true) {
+                    // no limit on the fee rate
+                    args.push(json!(0));
+                }
+                match self
+                    .rpc
+                    .call::<Value>("sendrawtransaction", Value::Array(args))
+                    .await
+                {
+                    Ok(_) => Ok(json!({"success": true, "errmsg": ""})),
+                    Err(e) => Ok(json!({"success": false, "errmsg": format!("{e:#}")})),
+                }
+            }
+            _ => bail!("unknown method {method}"),
+        }
+    }
+
+    fn manifest() -> Value {
+        let method = |name: &str, usage: &str, description: &str| json!({"name": name, "usage": usage, "description": description});
+        json!({
+            "options": [{
+                "name": CONFIDENCE_OPTION,
+                "type": "string",
+                "default": "",
+                "description": "Confidence of the fee estimates, like wtf calc --confidence",
+            }],
+            "rpcmethods": [
+                method("getchaininfo", "[last_height]", "Chain, header and block count of Bitcoin Core"),
+                method("estimatefees", "", "Fee rates in sat/kVB for 2, 6, 12 and 100 blocks from WhatTheFee"),
+                method("getrawblockbyheight", "height", "Hash and raw block at height"),
+                method("getutxout", "txid vout", "Amount and script of an unspent output"),
+                method("sendrawtransaction", "tx [allowhighfees]", "Broadcast a raw transaction"),
+            ],
+            "dynamic": false,
+        })
+    }
+
+    fn init(&mut self, params: &Value) -> Result<Value> {
+        let network = params["configuration"]["network"].as_str();
+        if network.is_some_and(|network| network != self.network.to_string()) {
+            return Ok(json!({
+                "disable": format!(
+                    "lightningd runs on {}, the dataset is {}",
+                    network.unwrap_or_default(),
+                    self.network
+                ),
+            }));
+        }
+        if let Some(confidence) = params["options"][CONFIDENCE_OPTION]
+            .as_str()
+            .filter(|c| !c.is_empty())
+        {
+            self.confidence = confidence.parse()?;
+        }
+        if !(self.confidence > 0. && self.confidence <= 1.) {
+            bail!("confidence must be in (0, 1], got {}", self.confidence);
+        }
+        info!(
+            "estimating fees for lightningd at confidence {}",
+            self.confidence
+        );
+        Ok(json!({}))
+    }
+
+    /// Fee rates in sat/kVB. Without an estimate `feerates` is empty, which has lightningd fall
+    /// back to its defaults like it does when Bitcoin Core can't estimate.
+    fn estimate_fees(&self) -> Value {
+        let floor = Calc::minimum_fee(self.storage.as_ref()).unwrap_or_else(|e| {
+            warn!("reading the minimum fee: {e:#}");
+            MIN_RELAY_FEE_RATE
+        });
+        let sat_kvb = |fee_rate: f64| (fee_rate * 1000.).ceil() as u64;
+        let feerates = match self.estimates() {
+            Ok(estimates) => estimates
+                .into_iter()
+                .map(|(blocks, fee_rate)| {
+                    json!({"blocks": blocks, "feerate": sat_kvb(fee_rate.max(floor))})
+                })
+                .collect(),
+            Err(e) => {
+                warn!("estimating fees for lightningd: {e:#}");
+                Vec::new()
+            }
+        };
+        json!({"feerate_floor": sat_kvb(floor), "feerates": feerates})
+    }
+
+    /// sat/vB by target from the model if one is loaded, or like `calc`
+    fn estimates(&self) -> Result<Vec<(u32, f64)>> {
+        let storage = self.storage.as_ref();
+        match &self.model {
+            Some(model) => {
+                let snapshot = Replay::new(storage)?.at(i64::MAX)?;
+                TARGETS
+                    .iter()
+                    .map(|target| {
+                        Ok((
+                            *target,
+                            model.estimate(&snapshot, *target, self.confidence)?,
+                        ))
+                    })
+                    .collect()
+            }
+            None => Ok(Calc::targets(storage, &TARGETS, &[self.confidence])?
+                .into_iter()
+                .map(|estimate| (estimate.target, estimate.fee_rate_sat_vb))
+                .collect()),
+        }
+    }
+}
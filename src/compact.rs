@@ -0,0 +1,131 @@
+use crate::{
+    dataset::{FileKind, SnapshotFile},
+    storage::Storage,
+};
+use anyhow::Result;
+use chrono::NaiveDate;
+use polars::{functions, prelude::*};
+use std::collections::{BTreeSet, HashSet};
+use tracing::{debug, info};
+
+pub struct Compact;
+
+impl Compact {
+    /// Days before `until` that still have full or delta files of their own
+    pub fn days(storage: &dyn Storage, until: NaiveDate) -> Result<Vec<NaiveDate>> {
+        let days: BTreeSet<NaiveDate> = storage
+            .list()?
+            .iter()
+            .filter(|f| matches!(f.kind, FileKind::Full | FileKind::Delta))
+            .map(|f| f.day())
+            .filter(|day| *day < until)
+            .collect();
+        Ok(days.into_iter().collect())
+    }
+
+    /// Merge the full and delta files of `day` into one compact file, sorted by time and txid.
+    /// An existing compact file of the day is merged as well, snapshots found in both are only
+    /// kept once. Unless `keep_raw` is set the merged files are removed afterwards.
+    #[tracing::instrument(skip(storage))]
+    pub fn compact(
+        storage: &dyn Storage,
+        day: NaiveDate,
+        keep_raw: bool,
+    ) -> Result<Option<SnapshotFile>> {
+        let files: Vec<SnapshotFile> = storage
+            .list()?
+            .into_iter()
+            .filter(|f| f.day() == day)
+            .collect();
+        let previous: Vec<&SnapshotFile> = files
+            .iter()
+            .filter(|f| f.kind == FileKind::Compact)
+            .collect();
+        let raw: Vec<&SnapshotFile> = files
+            .iter()
+            .filter(|f| matches!(f.kind, FileKind::Full | FileKind::Delta))
+            .collect();
+        if raw.is_empty() {
+            return Ok(None);
+        }
+
+        let mut seen: HashSet<(i64, &str)> = HashSet::new();
+        let mut frames: Vec<DataFrame> = Vec::new();
+        for file in &previous {
+            let frame = storage.read(file)?;
+            let timestamps = frame.column("snapshot_timestamp")?.i64()?;
+            let kinds = frame.column("kind")?.utf8()?;
+            for (timestamp, kind) in timestamps.into_iter().zip(kinds) {
+                if let (Some(timestamp), Some(kind)) = (timestamp, kind.and_then(FileKind::parse)) {
+                    seen.insert((timestamp, kind.as_str()));
+                }
+            }
+            frames.push(frame);
+        }
+        for file in &raw {
+            if !seen.insert((file.timestamp, file.kind.as_str())) {
+                continue;
+            }
+            debug!("merging {}", file.path.display());
+            let frame = storage.read(file)?;
+            let frame = if frame.height() == 0 {
+                // keep empty snapshots as a row without a txid, they are samples all the same
+                let columns = frame
+                    .get_columns()
+                    .iter()
+                    .map(|c| Series::full_null(c.name(), 1, c.dtype()))
+                    .collect();
+                DataFrame::new(columns)?
+            } else {
+                frame
+            };
+            frames.push(file.tag(&frame)?);
+        }
+        if frames.is_empty() {
+            return Ok(None);
+        }
+        // files written before columns were added lack them, their rows get nulls
+        let mut merged = functions::diag_concat_df(&frames)?;
+        // full before delta when both were written in the same second, as in SnapshotFile::sort
+        merged = merged.sort(
+            ["snapshot_timestamp", "kind", "txid"],
+            vec![false, true, false],
+        )?;
+
+        let first = raw
+            .iter()
+            .chain(&previous)
+            .min_by_key(|f| (f.timestamp, f.kind.rank()))
+            .unwrap();
+        // readers see the compact file replace the merged ones at once where the storage
+        // keeps a log
+        let mut output = None;
+        storage.atomically("compact", &mut || {
+            let written = storage.write(
+                first.written_at(),
+                first.height,
+                FileKind::Compact,
+                &mut merged,
+            )?;
+            // a compact file starting at a different snapshot has been superseded
+            for file in &previous {
+                if file.timestamp != written.timestamp || file.height != written.height {
+                    storage.remove(file)?;
+                }
+            }
+            if !keep_raw {
+                for file in &raw {
+                    storage.remove(file)?;
+                }
+            }
+            output = Some(written);
+            Ok(())
+        })?;
+        info!(
+            "day: {day}, merged_files: {}, rows: {}",
+            raw.len(),
+            merged.height()
+        );
+        Ok(output)
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{
+    calc::Calc,
+    replay::{Replay, Snapshot},
+    storage::Storage,
+};
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use tracing::info;
+
+/// The other dataset's snapshot is compared only if taken at most this long before
+const MAX_SNAPSHOT_SKEW_SECS: i64 = 60;
+
+/// How the mempools of two recorders differed at one point in time
+#[derive(Debug, Clone, Serialize)]
+pub struct Divergence {
+    pub height: u64,
+    pub timestamp: i64,
+    /// Time of the other recorder's snapshot compared against
+    pub other_timestamp: i64,
+    pub shared: usize,
+    pub only_here: usize,
+    pub only_other: usize,
+    pub only_here_vsize: u64,
+    pub only_other_vsize: u64,
+    /// Share of the transactions in either mempool that are missing from one of them
+    pub divergence: f64,
+    /// Lowest fee rate (sat/vB) of the transactions only one recorder has, where relay
+    /// policies differ it's low
+    pub only_here_min_fee_rate: Option<f64>,
+    pub only_other_min_fee_rate: Option<f64>,
+    /// Median seconds the other recorder saw shared transactions after this one, negative
+    /// when it saw them first
+    pub median_first_seen_skew_secs: Option<f64>,
+}
+
+/// What `wtf compare-nodes` found
+#[derive(Debug, Clone, Serialize)]
+pub struct Comparison {
+    pub samples: Vec<Divergence>,
+    /// Snapshots here without one of the same height on the other side close enough
+    pub unmatched: usize,
+    pub mean_divergence: Option<f64>,
+    /// Shared transactions with a first-seen time on both sides, each counted once
+    pub compared_txs: usize,
+    /// Median and 90th percentile of the first-seen skew over all of them, seconds
+    pub median_first_seen_skew_secs: Option<f64>,
+    pub p90_abs_first_seen_skew_secs: Option<f64>,
+    /// Share of them this recorder saw first, ties not counted
+    pub seen_first_here: Option<f64>,
+}
+
+/// Quantifies how the mempools recorded by two nodes differ, in what each has that the other
+/// doesn't and in when each saw the transactions both have. Relay policies, peers and restarts
+/// set nodes apart, and so the estimates made from their recordings.
+pub struct CompareNodes;
+
+impl CompareNodes {
+    /// Compare the snapshots of `storage` within `from..=to`, one every `every` seconds, with
+    /// the latest snapshot `other` has of the same height
+    #[tracing::instrument(skip(storage, other))]
+    pub fn run(
+        storage: &dyn Storage,
+        other: &dyn Storage,
+        from: i64,
+        to: i64,
+        every: i64,
+    ) -> Result<Comparison> {
+        if every <= 0 {
+            bail!("every must be at least one second");
+        }
+        let replay = Replay::new(storage)?;
+        let other_replay = Replay::new(other)?;
+        if other_replay.entries().is_empty() {
+            bail!("the other dataset has no snapshots");
+        }
+
+        let mut samples = Vec::new();
+        let mut unmatched = 0;
+        // skew of every shared transaction, from the first sample it was in
+        let mut skews: HashMap<String, f64> = HashMap::new();
+        let mut last_sample = None;
+        replay.walk(from, to, |snapshot| {
+            let sample = snapshot.timestamp.div_euclid(every);
+            if last_sample == Some(sample) {
+                return Ok(());
+            }
+            last_sample = Some(sample);
+            let Ok(other) = other_replay.at(snapshot.timestamp) else {
+                unmatched += 1;
+                return Ok(());
+            };
+            if other.height != snapshot.height
+                || snapshot.timestamp - other.timestamp > MAX_SNAPSHOT_SKEW_SECS
+            {
+                unmatched += 1;
+                return Ok(());
+            }
+            samples.push(Self::compare(snapshot, &other, &mut skews));
+            Ok(())
+        })?;
+        info!("samples: {}, unmatched: {unmatched}", samples.len());
+
+        let mean_divergence = (!samples.is_empty()).then(|| {
+            samples.iter().map(|sample| sample.divergence).sum::<f64>() / samples.len() as f64
+        });
+        let mut skews: Vec<f64> = skews.into_values().collect();
+        skews.sort_by(f64::total_cmp);
+        let mut abs_skews: Vec<f64> = skews.iter().map(|skew| skew.abs()).collect();
+        abs_skews.sort_by(f64::total_cmp);
+        let earlier = skews.iter().filter(|skew| **skew > 0.).count();
+        let later = skews.iter().filter(|skew| **skew < 0.).count();
+        Ok(Comparison {
+            samples,
+            unmatched,
+            mean_divergence,
+            compared_txs: skews.len(),
+            median_first_seen_skew_secs: (!skews.is_empty()).then(|| Calc::quantile(&skews, 0.5)),
+            p90_abs_first_seen_skew_secs: (!abs_skews.is_empty())
+                .then(|| Calc::quantile(&abs_skews, 0.9)),
+            seen_first_here: (earlier + later > 0)
+                .then(|| earlier as f64 / (earlier + later) as f64),
+        })
+    }
+
+    fn compare(here: &Snapshot, other: &Snapshot, skews: &mut HashMap<String, f64>) -> Divergence {
+        let vsize = |weight: f64| (weight / 4.).ceil() as u64;
+        let (mut only_here, mut only_here_vsize, mut only_here_min) = (0, 0, None::<f64>);
+        let mut sample_skews = Vec::new();
+        for (txid, tx) in &here.transactions {
+            match other.transactions.get(txid) {
+                Some(other_tx) => {
+                    if let (Some(seen), Some(other_seen)) =
+                        (tx.first_seen_at, other_tx.first_seen_at)
+                    {
+                        let skew = other_seen as f64 - seen as f64;
+                        sample_skews.push(skew);
+                        skews.entry(txid.clone()).or_insert(skew);
+                    }
+                }
+                None => {
+                    only_here += 1;
+                    only_here_vsize += vsize(tx.weight);
+                    let rate = tx.fee_rate_sat_vb();
+                    only_here_min = Some(only_here_min.map_or(rate, |min| min.min(rate)));
+                }
+            }
+        }
+        let (mut only_other, mut only_other_vsize, mut only_other_min) = (0, 0, None::<f64>);
+        for (txid, tx) in &other.transactions {
+            if !here.transactions.contains_key(txid) {
+                only_other += 1;
+                only_other_vsize += vsize(tx.weight);
+                let rate = tx.fee_rate_sat_vb();
+                only_other_min = Some(only_other_min.map_or(rate, |min| min.min(rate)));
+            }
+        }
+        let shared = here.transactions.len() - only_here;
+        let union = shared + only_here + only_other;
+        sample_skews.sort_by(f64::total_cmp);
+        Divergence {
+            height: here.height,
+            timestamp: here.timestamp,
+            other_timestamp: other.timestamp,
+            shared,
+            only_here,
+            only_other,
+            only_here_vsize,
+            only_other_vsize,
+            divergence: if union == 0 {
+                0.
+            } else {
+                (only_here + only_other) as f64 / union as f64
+            },
+            only_here_min_fee_rate: only_here_min,
+            only_other_min_fee_rate: only_other_min,
+            median_first_seen_skew_secs: (!sample_skews.is_empty())
+                .then(|| Calc::quantile(&sample_skews, 0.5)),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{
+    calc::{Band, Presets},
+    log::{LogFormat, LogRotation},
+    model::Model,
+    node::NodeSource,
+    storage::StorageKind,
+    unit::FeeUnit,
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+use tracing::{info, warn};
+
+/// Read from the working directory when `--config` isn't given
+pub const DEFAULT_CONFIG_FILE: &str = "wtf.toml";
+
+/// Settings from `wtf.toml`. Keys are named after the command line flags, which take
+/// precedence over anything set here.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub data_dir: Option<String>,
+    pub network: Option<String>,
+    pub storage: Option<StorageKind>,
+    pub record: RecordConfig,
+    pub calc: CalcConfig,
+    /// `[preset.NAME]` tables next to the built-in presets
+    pub preset: Presets,
+    pub serve: ServeConfig,
+    pub price: PriceConfig,
+    pub alert: AlertConfig,
+    pub nostr: NostrConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordConfig {
+    pub source: Option<NodeSource>,
+    pub p2p_peer: Option<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub bitcoin_core_endpoint: Vec<String>,
+    pub rpc_endpoint: Option<String>,
+    pub rpc_user: Option<String>,
+    pub rpc_password: Option<String>,
+    pub rpc_cookie: Option<PathBuf>,
+    pub rpc_concurrency: Option<usize>,
+    pub zmq_endpoint: Vec<String>,
+    pub proxy: Option<String>,
+    pub interval: Option<u32>,
+    pub no_align: bool,
+    pub burst_interval: Option<u32>,
+    pub metrics_listen: Option<String>,
+    pub retention_days: Option<u32>,
+    /// e.g. "50GB"
+    pub max_dataset_size: Option<String>,
+    pub archive_dir: Option<String>,
+    pub s3_endpoint: Option<String>,
+    pub s3_bucket: Option<String>,
+    pub s3_prefix: Option<String>,
+    pub s3_region: Option<String>,
+    pub sink: Vec<String>,
+    pub no_parquet: bool,
+    pub publish: Vec<String>,
+    pub core_estimates: bool,
+    pub peer_feefilters: bool,
+    pub congestion_alerts: bool,
+    pub congestion_threshold: Option<f64>,
+    pub congestion_webhook: Option<String>,
+    pub aggregate: bool,
+    pub degrade_after: Option<u32>,
+    pub min_feerate: Option<f64>,
+    pub max_entries_per_bucket: Option<usize>,
+    pub hash_txids: Option<String>,
+    /// Optional columns of full and delta files, `lean`, `all` or their names
+    pub columns: Vec<String>,
+    pub compression: Option<String>,
+    pub row_group_size: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CalcConfig {
+    #[serde(deserialize_with = "one_or_many")]
+    pub confidence: Vec<f64>,
+    pub band: Option<Band>,
+    pub model: Option<Model>,
+    pub model_file: Option<PathBuf>,
+    pub unit: Option<FeeUnit>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServeConfig {
+    pub listen: Option<String>,
+    pub model_file: Option<PathBuf>,
+    pub api_key_file: Option<PathBuf>,
+    pub rate_limit: Option<u32>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub grpc_listen: Option<String>,
+    pub flight_listen: Option<String>,
+    pub cache_ttl: Option<u64>,
+    pub alert_threshold: Option<f64>,
+    pub alert_cooldown: Option<u64>,
+    pub alert_webhook: Option<String>,
+}
+
+/// Where `--fiat` gets bitcoin's price
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PriceConfig {
+    pub fiat: Option<String>,
+    /// Answering JSON [default: mempool.space's prices]
+    pub url: Option<String>,
+    /// JSON pointer to the price in the answer [default: /CURRENCY]
+    pub pointer: Option<String>,
+    pub cache_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertConfig {
+    pub target: Option<u32>,
+    pub confidence: Option<f64>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub below: Vec<f64>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub above: Vec<f64>,
+    pub change_pct: Option<f64>,
+    pub within_minutes: Option<u32>,
+    pub webhook: Option<String>,
+    pub command: Option<String>,
+    pub poll: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NostrConfig {
+    #[serde(deserialize_with = "one_or_many")]
+    pub relay: Vec<String>,
+    pub secret_key_file: Option<PathBuf>,
+    pub every: Option<u64>,
+    pub poll: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: Option<LogFormat>,
+    pub file: Option<PathBuf>,
+    pub rotation: Option<LogRotation>,
+    /// `[log.levels]` like `"wtf::record" = "debug"`, for the whole run unlike `-v`
+    pub levels: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Load `path`, or `wtf.toml` if it exists. Without either every setting is left unset.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Path::new(DEFAULT_CONFIG_FILE),
+            None => return Ok(Config::default()),
+        };
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config file {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("parsing config file {}", path.display()))
+    }
+
+    /// The config loaded again like [`Config::load`] on every SIGHUP, for daemons to pick up
+    /// changes without restarting. Receivers see a change once it is reloaded, a file that
+    /// doesn't load is reported and skipped.
+    pub fn on_hangup(path: Option<PathBuf>) -> Result<watch::Receiver<Arc<Config>>> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        let (sender, receiver) = watch::channel(Arc::new(Config::default()));
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                match Config::load(path.as_deref()) {
+                    Ok(config) => {
+                        info!("config reloaded");
+                        if sender.send(Arc::new(config)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => warn!("keeping the running config: {e:#}"),
+                }
+            }
+        });
+        Ok(receiver)
+    }
+}
+
+/// Accept a single value where a list is expected, as older config files have it
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
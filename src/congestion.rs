@@ -0,0 +1,210 @@
+use crate::template::BLOCK_WEIGHT;
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::collections::VecDeque;
+use tracing::{info, warn};
+
+/// Seconds of snapshots compared against the baseline
+const WINDOW_SECS: i64 = 600;
+/// Seconds of snapshots before the window making up the baseline
+const BASELINE_SECS: i64 = 3 * 3600;
+/// Fewer snapshots than these in the window or the baseline tell nothing yet
+const MIN_WINDOW_SNAPSHOTS: usize = 3;
+const MIN_BASELINE_SNAPSHOTS: usize = 10;
+/// Spread below which a flat baseline counts as this spread, so noise on a quiet mempool
+/// doesn't look significant. The cutoff is compared on a log scale, 0.05 is about 5%.
+const MIN_LOG_CUTOFF_SPREAD: f64 = 0.05;
+const MIN_DEPTH_SPREAD_BLOCKS: f64 = 0.25;
+
+/// Which way congestion moved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shift {
+    /// The next-block cutoff rose well above the baseline
+    FeeSpike,
+    /// The next-block cutoff or the backlog fell well below the baseline
+    Clearing,
+}
+
+impl Shift {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Shift::FeeSpike => "fee_spike",
+            Shift::Clearing => "clearing",
+        }
+    }
+}
+
+/// A shift of the last minutes away from the hours before
+#[derive(Debug, Clone, Serialize)]
+pub struct CongestionEvent {
+    /// Time of the snapshot it was noticed in
+    pub timestamp: i64,
+    pub shift: Shift,
+    /// sat/vB, geometric mean of the window
+    pub cutoff_sat_vb: f64,
+    pub baseline_cutoff_sat_vb: f64,
+    /// Baseline standard deviations the window's log cutoff is away from the baseline's
+    pub cutoff_z: f64,
+    /// Blocks' worth of the mempool, mean of the window
+    pub depth_blocks: f64,
+    pub baseline_depth_blocks: f64,
+    pub depth_z: f64,
+}
+
+/// Watches the next-block cutoff and the depth of the mempool across snapshots and reports
+/// when the last ten minutes depart from the three hours before by more than a number of
+/// standard deviations, once per shift
+pub struct Congestion {
+    threshold: f64,
+    webhook: Option<String>,
+    client: reqwest::Client,
+    /// Time, log cutoff and depth in blocks, oldest first
+    history: VecDeque<(i64, f64, f64)>,
+    /// The shift reported last, until both series are back near the baseline
+    active: Option<Shift>,
+}
+
+impl Congestion {
+    /// Report shifts of at least `threshold` standard deviations
+    pub fn new(threshold: f64) -> Result<Self> {
+        if threshold <= 0. {
+            bail!("the congestion threshold must be over 0 standard deviations");
+        }
+        Ok(Congestion {
+            threshold,
+            webhook: None,
+            client: reqwest::Client::new(),
+            history: VecDeque::new(),
+            active: None,
+        })
+    }
+
+    /// POST every event as JSON to `url`
+    pub fn with_webhook(mut self, url: impl Into<String>) -> Self {
+        self.webhook = Some(url.into());
+        self
+    }
+
+    /// Feed the snapshot taken at `timestamp`, with the fee rate (sat/vB) the next block
+    /// takes and the virtual size of the mempool
+    pub fn observe(
+        &mut self,
+        timestamp: i64,
+        cutoff_sat_vb: f64,
+        mempool_vsize: u64,
+    ) -> Option<CongestionEvent> {
+        let depth = mempool_vsize as f64 / (BLOCK_WEIGHT / 4) as f64;
+        self.history
+            .push_back((timestamp, cutoff_sat_vb.max(f64::MIN_POSITIVE).ln(), depth));
+        while self
+            .history
+            .front()
+            .is_some_and(|(time, _, _)| *time < timestamp - WINDOW_SECS - BASELINE_SECS)
+        {
+            self.history.pop_front();
+        }
+
+        let (baseline, window): (Vec<_>, Vec<_>) = self
+            .history
+            .iter()
+            .partition(|(time, _, _)| *time < timestamp - WINDOW_SECS);
+        if window.len() < MIN_WINDOW_SNAPSHOTS || baseline.len() < MIN_BASELINE_SNAPSHOTS {
+            return None;
+        }
+        let (cutoff, baseline_cutoff, cutoff_z) = Self::compare(
+            &window,
+            &baseline,
+            |(_, cutoff, _)| *cutoff,
+            MIN_LOG_CUTOFF_SPREAD,
+        );
+        let (depth, baseline_depth, depth_z) = Self::compare(
+            &window,
+            &baseline,
+            |(_, _, depth)| *depth,
+            MIN_DEPTH_SPREAD_BLOCKS,
+        );
+
+        let shift = if cutoff_z >= self.threshold {
+            Some(Shift::FeeSpike)
+        } else if cutoff_z <= -self.threshold || depth_z <= -self.threshold {
+            Some(Shift::Clearing)
+        } else {
+            None
+        };
+        match shift {
+            Some(shift) if self.active != Some(shift) => {
+                self.active = Some(shift);
+                Some(CongestionEvent {
+                    timestamp,
+                    shift,
+                    cutoff_sat_vb: cutoff.exp(),
+                    baseline_cutoff_sat_vb: baseline_cutoff.exp(),
+                    cutoff_z,
+                    depth_blocks: depth,
+                    baseline_depth_blocks: baseline_depth,
+                    depth_z,
+                })
+            }
+            Some(_) => None,
+            None => {
+                // half way back, so a shift hovering at the threshold fires once
+                if cutoff_z.abs().max(depth_z.abs()) < self.threshold / 2. {
+                    self.active = None;
+                }
+                None
+            }
+        }
+    }
+
+    /// Emit the event as a structured log event and to the webhook, if configured. The
+    /// webhook is called on a task of its own, the recorder doesn't wait for it.
+    pub fn notify(&self, event: &CongestionEvent) {
+        info!(
+            shift = event.shift.as_str(),
+            cutoff_sat_vb = event.cutoff_sat_vb,
+            baseline_cutoff_sat_vb = event.baseline_cutoff_sat_vb,
+            depth_blocks = event.depth_blocks,
+            baseline_depth_blocks = event.baseline_depth_blocks,
+            "congestion_shift"
+        );
+
+        if let Some(url) = &self.webhook {
+            let request = self.client.post(url).json(event);
+            tokio::spawn(async move {
+                let result = request
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                if let Err(e) = result {
+                    warn!("congestion webhook failed: {e}");
+                }
+            });
+        }
+    }
+
+    /// Mean of the window, mean of the baseline and how many of the baseline's standard
+    /// deviations, at least `min_spread`, they are apart
+    fn compare(
+        window: &[&(i64, f64, f64)],
+        baseline: &[&(i64, f64, f64)],
+        value: impl Fn(&(i64, f64, f64)) -> f64,
+        min_spread: f64,
+    ) -> (f64, f64, f64) {
+        let mean = |samples: &[&(i64, f64, f64)]| {
+            samples.iter().map(|sample| value(sample)).sum::<f64>() / samples.len() as f64
+        };
+        let (window_mean, baseline_mean) = (mean(window), mean(baseline));
+        let variance = baseline
+            .iter()
+            .map(|sample| (value(sample) - baseline_mean).powi(2))
+            .sum::<f64>()
+            / (baseline.len() - 1) as f64;
+        let spread = variance.sqrt().max(min_spread);
+        (
+            window_mean,
+            baseline_mean,
+            (window_mean - baseline_mean) / spread,
+        )
+    }
+}
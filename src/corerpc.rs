@@ -0,0 +1,265 @@
+use crate::{
+    calc::{Calc, MIN_RELAY_FEE_RATE},
+    model::Trained,
+    replay::Replay,
+    storage::Storage,
+};
+use anyhow::{anyhow, Result};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use serde_json::{json, Value};
+use std::{net::SocketAddr, sync::Arc};
+use tracing::{info, warn};
+
+/// Highest `conf_target` Bitcoin Core accepts
+const MAX_CONF_TARGET: u64 = 1008;
+/// `threshold` of `estimaterawfee` when none is given, as in Bitcoin Core
+const DEFAULT_THRESHOLD: f64 = 0.95;
+/// Horizons of `estimaterawfee`: name, highest target, decay and scale of Bitcoin Core's
+/// estimator, answered as they are
+const HORIZONS: [(&str, u32, f64, u32); 3] = [
+    ("short", 12, 0.962, 1),
+    ("medium", 48, 0.9952, 2),
+    ("long", 1008, 0.99931, 24),
+];
+/// JSON-RPC errors Bitcoin Core answers with
+const RPC_METHOD_NOT_FOUND: i64 = -32601;
+const RPC_PARSE_ERROR: i64 = -32700;
+const RPC_INVALID_PARAMETER: i64 = -8;
+const RPC_MISC_ERROR: i64 = -1;
+
+/// An error to send back, with the JSON-RPC code
+struct RpcError(i64, String);
+
+struct CoreState {
+    storage: Box<dyn Storage>,
+    model: Option<Trained>,
+    /// Of `conservative` and `unset` estimates
+    confidence: f64,
+    /// Of `economical` estimates
+    economical_confidence: f64,
+}
+
+/// Bitcoin Core's `estimatesmartfee` and `estimaterawfee` over its JSON-RPC, in the same
+/// shape and units, for software that only asks bitcoind. Authentication isn't checked, keep
+/// it on a private address. Everything else is refused as an unknown method.
+pub struct CoreRpc;
+
+impl CoreRpc {
+    pub async fn serve(
+        storage: Box<dyn Storage>,
+        listen: SocketAddr,
+        model: Option<Trained>,
+        confidence: f64,
+        economical_confidence: f64,
+    ) -> Result<()> {
+        let state = Arc::new(CoreState {
+            storage,
+            model,
+            confidence,
+            economical_confidence,
+        });
+        // wallet endpoints too, clients configured with a wallet post there
+        let app = Router::new()
+            .route("/", post(Self::rpc))
+            .route("/wallet/*wallet", post(Self::rpc))
+            .with_state(state);
+        let server = axum::Server::try_bind(&listen)?;
+        info!("bitcoind JSON-RPC listening on {listen}");
+        server.serve(app.into_make_service()).await?;
+        Ok(())
+    }
+
+    /// Single requests fail with the HTTP status bitcoind uses, batches always answer 200
+    async fn rpc(State(state): State<Arc<CoreState>>, body: Bytes) -> Response {
+        match serde_json::from_slice::<Value>(&body) {
+            Ok(Value::Array(requests)) => {
+                let mut responses = Vec::with_capacity(requests.len());
+                for request in requests {
+                    let (_, Json(response)) = Self::respond(&state, request).await;
+                    responses.push(response);
+                }
+                Json(Value::Array(responses)).into_response()
+            }
+            Ok(request) => Self::respond(&state, request).await.into_response(),
+            Err(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "result": null,
+                    "error": {"code": RPC_PARSE_ERROR, "message": format!("Parse error: {e}")},
+                    "id": null,
+                })),
+            )
+                .into_response(),
+        }
+    }
+
+    async fn respond(state: &Arc<CoreState>, request: Value) -> (StatusCode, Json<Value>) {
+        let id = request["id"].clone();
+        let method = request["method"].as_str().unwrap_or_default().to_string();
+        // positional or named, as bitcoind takes them
+        let params = match &request["params"] {
+            Value::Object(named) => [
+                named.get("conf_target"),
+                named.get("estimate_mode").or(named.get("threshold")),
+            ]
+            .map(|param| param.cloned().unwrap_or(Value::Null))
+            .to_vec(),
+            Value::Array(positional) => positional.clone(),
+            _ => Vec::new(),
+        };
+        match Self::call(state, &method, &params).await {
+            Ok(result) => (
+                StatusCode::OK,
+                Json(json!({"result": result, "error": null, "id": id})),
+            ),
+            Err(RpcError(code, message)) => {
+                let status = if code == RPC_METHOD_NOT_FOUND {
+                    StatusCode::NOT_FOUND
+                } else {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                };
+                let error = json!({"code": code, "message": message});
+                (
+                    status,
+                    Json(json!({"result": null, "error": error, "id": id})),
+                )
+            }
+        }
+    }
+
+    async fn call(
+        state: &Arc<CoreState>,
+        method: &str,
+        params: &[Value],
+    ) -> Result<Value, RpcError> {
+        match method {
+            "estimatesmartfee" => {
+                let target = Self::conf_target(params)?;
+                let confidence = match params.get(1).and_then(Value::as_str) {
+                    None => state.confidence,
+                    Some(mode) => match mode.to_ascii_lowercase().as_str() {
+                        "unset" | "conservative" => state.confidence,
+                        "economical" => state.economical_confidence,
+                        _ => {
+                            return Err(RpcError(
+                                RPC_INVALID_PARAMETER,
+                                String::from(
+                                    "Invalid estimate_mode parameter, must be one of: \"unset\", \"economical\", \"conservative\"",
+                                ),
+                            ))
+                        }
+                    },
+                };
+                let state = state.clone();
+                let estimate = Self::blocking(move || state.estimate(target, confidence)).await;
+                Ok(match estimate {
+                    Ok(fee_rate) => json!({"feerate": Self::btc_kvb(fee_rate), "blocks": target}),
+                    Err(e) => {
+                        warn!("estimatesmartfee {target}: {e:#}");
+                        json!({
+                            "errors": ["Insufficient data or no feerate found"],
+                            "blocks": 0,
+                        })
+                    }
+                })
+            }
+            "estimaterawfee" => {
+                let target = Self::conf_target(params)?;
+                let threshold = match params.get(1) {
+                    None | Some(Value::Null) => DEFAULT_THRESHOLD,
+                    Some(threshold) => threshold
+                        .as_f64()
+                        .filter(|threshold| *threshold > 0. && *threshold <= 1.)
+                        .ok_or_else(|| {
+                            RpcError(RPC_INVALID_PARAMETER, String::from("Invalid threshold"))
+                        })?,
+                };
+                let state = state.clone();
+                let estimate = Self::blocking(move || state.estimate(target, threshold)).await;
+                let mut horizons = serde_json::Map::new();
+                // the horizons reaching that far, all answered with the one estimate
+                for (name, max_target, decay, scale) in HORIZONS {
+                    if target > max_target {
+                        continue;
+                    }
+                    let horizon = match &estimate {
+                        Ok(fee_rate) => json!({
+                            "feerate": Self::btc_kvb(*fee_rate),
+                            "decay": decay,
+                            "scale": scale,
+                        }),
+                        Err(_) => json!({
+                            "decay": decay,
+                            "scale": scale,
+                            "errors": ["Insufficient data or no feerate found which meets threshold"],
+                        }),
+                    };
+                    horizons.insert(name.to_string(), horizon);
+                }
+                if let Err(e) = &estimate {
+                    warn!("estimaterawfee {target}: {e:#}");
+                }
+                Ok(Value::Object(horizons))
+            }
+            _ => Err(RpcError(
+                RPC_METHOD_NOT_FOUND,
+                String::from("Method not found"),
+            )),
+        }
+    }
+
+    /// The first parameter, 1 to 1008 blocks
+    fn conf_target(params: &[Value]) -> Result<u32, RpcError> {
+        match params.first().and_then(Value::as_u64) {
+            Some(target @ 1..=MAX_CONF_TARGET) => Ok(target as u32),
+            Some(_) => Err(RpcError(
+                RPC_INVALID_PARAMETER,
+                format!("Invalid conf_target, must be between 1 and {MAX_CONF_TARGET}"),
+            )),
+            None => Err(RpcError(
+                RPC_MISC_ERROR,
+                String::from("conf_target must be a number of blocks"),
+            )),
+        }
+    }
+
+    /// sat/vB as BTC/kvB, rounded up to a whole sat/kvB like bitcoind's amounts
+    fn btc_kvb(fee_rate: f64) -> f64 {
+        (fee_rate * 1000.).ceil() / 100_000_000.
+    }
+
+    async fn blocking<T: Send + 'static>(
+        work: impl FnOnce() -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        tokio::task::spawn_blocking(work)
+            .await
+            .map_err(|e| anyhow!("{e}"))?
+    }
+}
+
+impl CoreState {
+    /// sat/vB for `target` from the model if one is loaded, or like `calc`, no lower than
+    /// the mempool's minimum fee as bitcoind's estimates are
+    fn estimate(&self, target: u32, confidence: f64) -> Result<f64> {
+        let storage = self.storage.as_ref();
+        let fee_rate = match &self.model {
+            Some(model) => {
+                let snapshot = Replay::new(storage)?.at(i64::MAX)?;
+                model.estimate(&snapshot, target, confidence)?
+            }
+            None => Calc::calc(storage, confidence, target)?,
+        };
+        let floor = Calc::minimum_fee(storage).unwrap_or_else(|e| {
+            warn!("reading the minimum fee: {e:#}");
+            MIN_RELAY_FEE_RATE
+        });
+        Ok(fee_rate.max(floor))
+    }
+}
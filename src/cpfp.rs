@@ -0,0 +1,111 @@
+use crate::{node::Node, replay::Replay, storage::Storage};
+use anyhow::{bail, Result};
+use bitcoin::Txid;
+use serde::Serialize;
+
+/// Virtual size of a child spending one P2WPKH output to one P2WPKH output
+pub const DEFAULT_CHILD_VSIZE: u64 = 110;
+
+/// An unconfirmed transaction a child is to pull into a block
+#[derive(Debug, Clone, Copy)]
+pub struct Parent {
+    pub txid: Txid,
+    /// Of the parent and its unconfirmed ancestors, all of which the child has to pay for
+    pub package_vsize: u64,
+    /// sat, with `prioritisetransaction` applied
+    pub package_fee: u64,
+    pub package_txs: u64,
+}
+
+impl Parent {
+    /// `txid` as in the mempool of `node`, `None` when it isn't there
+    pub async fn from_node(node: &dyn Node, txid: Txid) -> Result<Option<Self>> {
+        let entries = node.get_mempool_entries(&[txid]).await?;
+        Ok(entries.get(&txid).map(|entry| Parent {
+            txid,
+            package_vsize: entry.ancestor_size,
+            package_fee: entry.fees.ancestor.to_sat(),
+            package_txs: entry.ancestor_count,
+        }))
+    }
+
+    /// `txid` as in the latest recorded snapshot, `None` when it isn't there. Dependencies
+    /// aren't recorded, unconfirmed ancestors of the parent go unpaid for.
+    pub fn from_recorded(storage: &dyn Storage, txid: Txid) -> Result<Option<Self>> {
+        let snapshot = Replay::new(storage)?.at(i64::MAX)?;
+        Ok(snapshot
+            .transactions
+            .get(&txid.to_string())
+            .map(|tx| Parent {
+                txid,
+                package_vsize: (tx.weight / 4.).ceil() as u64,
+                package_fee: tx.fee_sat.round() as u64,
+                package_txs: 1,
+            }))
+    }
+}
+
+/// What a child has to pay so the block assembler picks its parent at a fee rate
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ChildFee {
+    #[schema(value_type = String)]
+    pub parent: Txid,
+    /// Of the parent and its unconfirmed ancestors
+    pub package_vsize: u64,
+    /// sat
+    pub package_fee: u64,
+    pub package_txs: u64,
+    /// sat/vB the parent's package pays on its own
+    pub package_fee_rate: f64,
+    pub target: u32,
+    pub confidence: f64,
+    /// sat/vB confirming within `target` at `confidence`
+    pub target_fee_rate: f64,
+    pub child_vsize: u64,
+    /// sat the child has to pay
+    pub child_fee: u64,
+    /// sat/vB of the child on its own
+    pub child_fee_rate: f64,
+    /// The package pays the target fee rate already, the child only needs to pay it itself
+    pub parent_sufficient: bool,
+}
+
+impl ChildFee {
+    /// The fee a child of `child_vsize` spending `parent` pays for the package to reach
+    /// `target_fee_rate`: the target fee rate on the vsize of the package with the child,
+    /// less what the package pays. Never less than the target fee rate on the child itself,
+    /// a well paying parent is mined on its own and leaves the child behind.
+    pub fn new(
+        parent: &Parent,
+        child_vsize: u64,
+        target: u32,
+        confidence: f64,
+        target_fee_rate: f64,
+    ) -> Result<Self> {
+        if child_vsize == 0 {
+            bail!("the child has to be at least one vbyte");
+        }
+        if !target_fee_rate.is_finite() || target_fee_rate < 0. {
+            bail!("fee rate must be at least 0, got {target_fee_rate}");
+        }
+        // rounded up, the fee rate is met and not missed by a fraction of a sat
+        let total = (target_fee_rate * (parent.package_vsize + child_vsize) as f64).ceil() as u64;
+        let own = (target_fee_rate * child_vsize as f64).ceil() as u64;
+        let child_fee = total.saturating_sub(parent.package_fee).max(own);
+        let package_fee_rate = parent.package_fee as f64 / parent.package_vsize.max(1) as f64;
+        Ok(ChildFee {
+            parent: parent.txid,
+            package_vsize: parent.package_vsize,
+            package_fee: parent.package_fee,
+            package_txs: parent.package_txs,
+            package_fee_rate,
+            target,
+            confidence,
+            target_fee_rate,
+            child_vsize,
+            child_fee,
+            child_fee_rate: child_fee as f64 / child_vsize as f64,
+            parent_sufficient: package_fee_rate >= target_fee_rate,
+        })
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{
+    backtest::{Backtest, TargetReport},
+    model::{Model, Trained},
+    replay::Replay,
+    storage::{Storage, TruncatedStorage},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+use tracing::info;
+
+/// Folds of `walk-forward` without a number
+const DEFAULT_FOLDS: usize = 5;
+
+/// How `train --cv` checks a model on data it wasn't fitted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrossValidation {
+    /// The recording is cut into `folds + 1` windows of equal time. Fold `k` is fitted to
+    /// everything up to the end of window `k` and scored on window `k + 1`, so it never sees
+    /// what came after the estimates it is scored on.
+    WalkForward(usize),
+}
+
+/// How a model fitted up to `train_to` fared on the window after
+#[derive(Debug, Clone, Serialize)]
+pub struct Fold {
+    pub fold: usize,
+    pub train_from: i64,
+    pub train_to: i64,
+    pub test_to: i64,
+    pub confidence: f64,
+    pub targets: Vec<TargetReport>,
+}
+
+impl CrossValidation {
+    /// `walk-forward` or `walk-forward:FOLDS`
+    pub fn parse(s: &str) -> Result<Self> {
+        let (scheme, folds) = match s.split_once(':') {
+            Some((scheme, folds)) => (scheme, Some(folds)),
+            None => (s, None),
+        };
+        if scheme != "walk-forward" {
+            bail!("unknown cross-validation {scheme}, use walk-forward:FOLDS");
+        }
+        let folds = match folds {
+            Some(folds) => folds
+                .parse()
+                .map_err(|_| anyhow!("folds must be a number, got {folds}"))?,
+            None => DEFAULT_FOLDS,
+        };
+        if folds == 0 {
+            bail!("walk-forward needs at least one fold");
+        }
+        Ok(CrossValidation::WalkForward(folds))
+    }
+
+    /// Fit `model` as [`Trained::train`] would on each fold and score it on the window after,
+    /// for every confidence, estimating every `every_secs`
+    #[tracing::instrument(skip(storage))]
+    pub fn run(
+        &self,
+        storage: &dyn Storage,
+        model: Model,
+        targets: &[u32],
+        confidences: &[f64],
+        every_secs: i64,
+    ) -> Result<Vec<Fold>> {
+        let CrossValidation::WalkForward(folds) = *self;
+        let replay = Replay::new(storage)?;
+        let (Some(first), Some(last)) = (replay.entries().first(), replay.entries().last()) else {
+            bail!("no recorded snapshots found");
+        };
+        let (first, last) = (first.timestamp, last.timestamp);
+        let window = (last - first) / (folds as i64 + 1);
+        if window <= 0 {
+            bail!("the recording is too short for {folds} folds");
+        }
+
+        let floors = Backtest::floors(storage)?;
+        let mut reports = Vec::new();
+        for fold in 1..=folds {
+            let train_to = first + window * fold as i64;
+            let test_to = if fold == folds {
+                last
+            } else {
+                train_to + window
+            };
+            let trained = Trained::train(
+                &TruncatedStorage::new(storage, train_to),
+                model,
+                targets,
+                confidences,
+                every_secs,
+            )
+            .with_context(|| format!("fitting fold {fold} of {folds}"))?;
+            for &confidence in confidences {
+                let scores = Backtest::score(
+                    storage,
+                    &floors,
+                    &trained,
+                    train_to + 1,
+                    test_to,
+                    every_secs,
+                    targets,
+                    confidence,
+                )?;
+                reports.push(Fold {
+                    fold,
+                    train_from: first,
+                    train_to,
+                    test_to,
+                    confidence,
+                    targets: scores,
+                });
+            }
+            info!("fold: {fold}, train_to: {train_to}, test_to: {test_to}");
+        }
+        Ok(reports)
+    }
+}
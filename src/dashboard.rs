@@ -0,0 +1,251 @@
+use crate::{
+    calc::{Calc, TargetEstimate, TARGETS},
+    dataset::FileKind,
+    histogram::Histogram,
+    replay::Replay,
+    storage::Storage,
+    template::Template,
+};
+use anyhow::Result;
+use polars::prelude::TakeRandom;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Style},
+    text::Line,
+    widgets::{Bar, BarChart, Block, Paragraph, Row, Table},
+    DefaultTerminal, Frame,
+};
+use std::time::Duration;
+
+/// Projected blocks shown
+const PROJECTED_BLOCKS: usize = 8;
+/// Snapshots this many intervals late mark the recorder as stalled
+const STALLED_INTERVALS: i64 = 3;
+
+/// How the recorder is doing, from the latest snapshot and its meta
+struct Health {
+    timestamp: i64,
+    height: u64,
+    interval_secs: Option<u32>,
+    source: Option<String>,
+    mempool_min_fee_sat_vb: Option<f64>,
+}
+
+/// Everything drawn, rebuilt on each new snapshot
+struct View {
+    health: Health,
+    histogram: Histogram,
+    boundaries: Vec<Option<f64>>,
+    /// Estimates or why there are none
+    estimates: Result<Vec<TargetEstimate>, String>,
+}
+
+pub struct Dashboard;
+
+impl Dashboard {
+    /// Draw the latest recorded snapshot until `q` or Esc is pressed, looking for a new one
+    /// every `poll`
+    pub fn run(storage: &dyn Storage, confidence: f64, poll: Duration) -> Result<()> {
+        let mut terminal = ratatui::init();
+        let result = Self::run_loop(&mut terminal, storage, confidence, poll);
+        ratatui::restore();
+        result
+    }
+
+    fn run_loop(
+        terminal: &mut DefaultTerminal,
+        storage: &dyn Storage,
+        confidence: f64,
+        poll: Duration,
+    ) -> Result<()> {
+        let mut view: Option<View> = None;
+        let mut error: Option<String> = None;
+        loop {
+            let latest = Replay::new(storage)?.latest();
+            if latest.is_some() && latest != view.as_ref().map(|v| v.health.timestamp) {
+                match Self::view(storage, confidence) {
+                    Ok(next) => {
+                        view = Some(next);
+                        error = None;
+                    }
+                    Err(e) => error = Some(format!("{e:#}")),
+                }
+            }
+            terminal
+                .draw(|frame| Self::draw(frame, view.as_ref(), error.as_deref(), confidence))?;
+
+            if event::poll(poll)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press
+                        && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                    {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn view(storage: &dyn Storage, confidence: f64) -> Result<View> {
+        let snapshot = Replay::new(storage)?.at(i64::MAX)?;
+        let mut health = Health {
+            timestamp: snapshot.timestamp,
+            height: snapshot.height,
+            interval_secs: None,
+            source: None,
+            mempool_min_fee_sat_vb: None,
+        };
+        let meta = storage
+            .list()?
+            .into_iter()
+            .filter(|file| file.kind == FileKind::Meta && file.timestamp <= snapshot.timestamp)
+            .max_by_key(|file| file.timestamp);
+        if let Some(file) = meta {
+            let frame = storage.read(&file)?;
+            health.interval_secs = frame.column("interval_secs")?.u32()?.get(0);
+            health.source = frame.column("source")?.utf8()?.get(0).map(String::from);
+            health.mempool_min_fee_sat_vb = frame.column("mempool_min_fee_sat_vb")?.f64()?.get(0);
+        }
+        Ok(View {
+            health,
+            histogram: Histogram::from_snapshot(&snapshot),
+            boundaries: Template::from_snapshot(&snapshot, PROJECTED_BLOCKS).boundaries(),
+            estimates: Calc::targets(storage, &TARGETS, &[confidence])
+                .map_err(|e| format!("{e:#}")),
+        })
+    }
+
+    fn draw(frame: &mut Frame, view: Option<&View>, error: Option<&str>, confidence: f64) {
+        let [top, main] =
+            Layout::vertical([Constraint::Length(4), Constraint::Min(0)]).areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(main);
+        let [estimates_area, blocks_area] = Layout::vertical([
+            Constraint::Length(TARGETS.len() as u16 + 3),
+            Constraint::Min(0),
+        ])
+        .areas(right);
+
+        let Some(view) = view else {
+            let text = error.unwrap_or("waiting for the first snapshot");
+            frame.render_widget(
+                Paragraph::new(text).block(Block::bordered().title(" wtf dashboard ")),
+                frame.area(),
+            );
+            return;
+        };
+
+        let health = &view.health;
+        let age = chrono::Utc::now().timestamp() - health.timestamp;
+        let stalled = health
+            .interval_secs
+            .is_some_and(|interval| age > STALLED_INTERVALS * interval as i64);
+        let mut lines = vec![Line::styled(
+            format!(
+                "height {}, last snapshot {age}s ago{}{}",
+                health.height,
+                health
+                    .interval_secs
+                    .map(|i| format!(", every {i}s"))
+                    .unwrap_or_default(),
+                if stalled { ", STALLED" } else { "" },
+            ),
+            Style::default().fg(if stalled { Color::Red } else { Color::Green }),
+        )];
+        lines.push(Line::from(format!(
+            "{} txs, {} vB{}{}",
+            view.histogram.txs,
+            view.histogram.vsize,
+            health
+                .mempool_min_fee_sat_vb
+                .map(|fee| format!(", mempool min fee {fee:.2} sat/vB"))
+                .unwrap_or_default(),
+            health
+                .source
+                .as_ref()
+                .map(|source| format!(", from {source}"))
+                .unwrap_or_default(),
+        )));
+        if let Some(error) = error {
+            lines.push(Line::styled(
+                error.to_string(),
+                Style::default().fg(Color::Red),
+            ));
+        }
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" recorder (q to quit) ")),
+            top,
+        );
+
+        let bars: Vec<Bar> = view
+            .histogram
+            .buckets
+            .iter()
+            .map(|bucket| {
+                Bar::with_label(format!(">= {}", bucket.fee_rate), bucket.vsize)
+                    .text_value(format!("{} vB, block {}", bucket.vsize, bucket.block))
+            })
+            .collect();
+        frame.render_widget(
+            BarChart::horizontal(bars)
+                .bar_width(1)
+                .bar_gap(0)
+                .bar_style(Style::default().fg(Color::Yellow))
+                .block(Block::bordered().title(" sat/vB histogram ")),
+            left,
+        );
+
+        let rows: Vec<Row> = match &view.estimates {
+            Ok(estimates) => estimates
+                .iter()
+                .map(|e| {
+                    Row::new([
+                        format!("{}", e.target),
+                        format!("{:.2}", e.fee_rate_sat_vb),
+                        format!("{:.2}", e.projected_sat_vb),
+                        e.historical_sat_vb
+                            .map(|rate| format!("{rate:.2}"))
+                            .unwrap_or_else(|| String::from("n/a")),
+                    ])
+                })
+                .collect(),
+            Err(error) => vec![Row::new([error.clone()])],
+        };
+        frame.render_widget(
+            Table::new(
+                rows,
+                [
+                    Constraint::Length(7),
+                    Constraint::Length(10),
+                    Constraint::Length(10),
+                    Constraint::Length(10),
+                ],
+            )
+            .header(Row::new(["blocks", "sat/vB", "projected", "historical"]))
+            .block(Block::bordered().title(format!(" estimates at {confidence} "))),
+            estimates_area,
+        );
+
+        let rows: Vec<Row> = view
+            .boundaries
+            .iter()
+            .enumerate()
+            .map(|(index, boundary)| {
+                Row::new([
+                    format!("{}", index + 1),
+                    boundary
+                        .map(|rate| format!("{rate:.2}"))
+                        .unwrap_or_else(|| String::from("not full")),
+                ])
+            })
+            .collect();
+        frame.render_widget(
+            Table::new(rows, [Constraint::Length(7), Constraint::Length(10)])
+                .header(Row::new(["block", "min sat/vB"]))
+                .block(Block::bordered().title(" projected blocks ")),
+            blocks_area,
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{
+    record::DeltaFrame,
+    replay::{Replay, Snapshot},
+    storage::{Storage, StorageKind},
+};
+use anyhow::Result;
+use bitcoin::Network;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use polars::prelude::*;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// A recorded dataset, for reading and writing it from other programs the way `wtf` does
+pub struct Dataset {
+    storage: Box<dyn Storage>,
+}
+
+impl Dataset {
+    /// The dataset of `network` below `data_dir`, like `wtf --data-dir --network --storage`
+    pub fn open(data_dir: &str, network: Network, kind: StorageKind) -> Result<Self> {
+        Ok(Self::new(kind.open(data_dir, network)?))
+    }
+
+    /// A dataset kept in `storage`
+    pub fn new(storage: Box<dyn Storage>) -> Self {
+        Dataset { storage }
+    }
+
+    pub fn storage(&self) -> &dyn Storage {
+        self.storage.as_ref()
+    }
+
+    /// Unix timestamp of the latest snapshot, `None` while nothing is recorded
+    pub fn latest(&self) -> Result<Option<i64>> {
+        Ok(Replay::new(self.storage())?.latest())
+    }
+
+    /// The mempool as of the last snapshot up to `timestamp`
+    pub fn snapshot_at(&self, timestamp: i64) -> Result<Snapshot> {
+        Replay::new(self.storage())?.at(timestamp)
+    }
+
+    /// Persist `delta` as the snapshot taken at `now` and `height`
+    pub fn write(
+        &self,
+        now: DateTime<Utc>,
+        height: u64,
+        delta: &mut DeltaFrame,
+    ) -> Result<SnapshotFile> {
+        self.storage
+            .write(now, height, delta.kind, &mut delta.frame)
+    }
+}
+
+/// What a recorded file contains, the last part of its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileKind {
+    /// The complete mempool at the start of a height
+    Full,
+    /// Changes since the previous snapshot of the same height
+    Delta,
+    /// How the snapshot written in the same second was taken
+    Meta,
+    /// Recorded transactions confirmed by a block
+    Block,
+    /// Blocks confirmations were recorded for that are no longer on the chain
+    Reorg,
+    /// ZMQ notifications received since the previous snapshot
+    Events,
+    /// Transactions notified with ZMQ `rawtx` since the previous snapshot that never were in
+    /// the mempool
+    Rejected,
+    /// Bitcoin Core's `estimatesmartfee` answers at the time of the snapshot
+    CoreEstimates,
+    /// How many of the node's peers advertise each minimum fee rate with `feefilter`
+    #[serde(rename = "peer-feefilters")]
+    PeerFeeFilters,
+    /// Written when the recorder stopped cleanly
+    Shutdown,
+    /// Written on starting when the previous file is more than an interval old, what was
+    /// recorded last and how long ago
+    Gap,
+    /// The full and delta files of a day merged into one, rows tagged like [`SnapshotFile::tag`]
+    Compact,
+    /// Transactions and virtual size by fee rate bucket, what `record --aggregate` writes
+    /// instead of full and delta files
+    Histogram,
+    /// Fee rates a block paid by percentile, imported from an explorer for blocks without
+    /// recorded confirmations
+    BlockFees,
+    /// A block's time, weight, fees and coinbase, how the miner filled it
+    BlockHeader,
+}
+
+impl FileKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileKind::Full => "full",
+            FileKind::Delta => "delta",
+            FileKind::Meta => "meta",
+            FileKind::Block => "block",
+            FileKind::Reorg => "reorg",
+            FileKind::Events => "events",
+            FileKind::Rejected => "rejected",
+            FileKind::CoreEstimates => "core-estimates",
+            FileKind::PeerFeeFilters => "peer-feefilters",
+            FileKind::Shutdown => "shutdown",
+            FileKind::Gap => "gap",
+            FileKind::Compact => "compact",
+            FileKind::Histogram => "histogram",
+            FileKind::BlockFees => "block-fees",
+            FileKind::BlockHeader => "block-header",
+        }
+    }
+
+    /// Order of files written within the same second
+    pub fn rank(&self) -> u8 {
+        match self {
+            FileKind::Full | FileKind::Compact => 0,
+            FileKind::Shutdown => 2,
+            _ => 1,
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "full" => FileKind::Full,
+            "delta" => FileKind::Delta,
+            "meta" => FileKind::Meta,
+            "block" => FileKind::Block,
+            "reorg" => FileKind::Reorg,
+            "events" => FileKind::Events,
+            "rejected" => FileKind::Rejected,
+            "core-estimates" => FileKind::CoreEstimates,
+            "peer-feefilters" => FileKind::PeerFeeFilters,
+            "shutdown" => FileKind::Shutdown,
+            "gap" => FileKind::Gap,
+            "compact" => FileKind::Compact,
+            "histogram" => FileKind::Histogram,
+            "block-fees" => FileKind::BlockFees,
+            "block-header" => FileKind::BlockHeader,
+            _ => return None,
+        })
+    }
+}
+
+/// A file of the dataset, named `{height}_{timestamp}_{kind}.parquet`
+#[derive(Debug, Clone)]
+pub struct SnapshotFile {
+    pub height: u64,
+    pub timestamp: i64,
+    pub kind: FileKind,
+    pub path: PathBuf,
+}
+
+impl SnapshotFile {
+    /// Relative location of a file: `YYYY/MM/DD/{height}_{timestamp}_{kind}.parquet`
+    pub fn path_for(now: DateTime<Utc>, height: u64, kind: FileKind) -> PathBuf {
+        let mut filename = PathBuf::new();
+        let day = now.format("%Y/%m/%d").to_string();
+        let timestamp = now.timestamp();
+        filename.extend(day.split('/'));
+        filename.push(format!("{height}_{timestamp}_{}.parquet", kind.as_str()));
+        filename
+    }
+
+    pub fn parse(path: &Path) -> Option<Self> {
+        if path.extension()? != "parquet" {
+            return None;
+        }
+        let stem = path.file_stem()?.to_str()?;
+        let mut parts = stem.split('_');
+        let height = parts.next()?.parse().ok()?;
+        let timestamp = parts.next()?.parse().ok()?;
+        let kind = FileKind::parse(parts.next()?)?;
+        Some(SnapshotFile {
+            height,
+            timestamp,
+            kind,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// UTC day the file was written on, the directory it is stored in
+    pub fn day(&self) -> NaiveDate {
+        self.written_at().date_naive()
+    }
+
+    pub fn written_at(&self) -> DateTime<Utc> {
+        Utc.timestamp_opt(self.timestamp, 0).unwrap()
+    }
+
+    /// Prefix every row of `frame`, read from this file, with `height`, `snapshot_timestamp`
+    /// and `kind` so rows of many files can be told apart once combined
+    pub fn tag(&self, frame: &DataFrame) -> Result<DataFrame> {
+        let n = frame.height();
+        let mut columns = vec![
+            Series::new("height", vec![self.height; n]),
+            Series::new("snapshot_timestamp", vec![self.timestamp; n]),
+            Series::new("kind", vec![self.kind.as_str(); n]),
+        ];
+        columns.extend(frame.get_columns().iter().cloned());
+        Ok(DataFrame::new(columns)?)
+    }
+
+    /// Order by time, a full snapshot before anything else written in the same second, then by
+    /// height
+    pub fn sort(files: &mut [Self]) {
+        files.sort_by_key(|f| (f.timestamp, f.kind.rank(), f.height));
+    }
+}
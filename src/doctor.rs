@@ -0,0 +1,397 @@
+use crate::{
+    node::{Node, RestClient},
+    rpc::RpcClient,
+};
+use bitcoin::Network;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{path::Path, time::Duration};
+use tokio::net::TcpStream;
+
+/// Notifications the recorder subscribes to
+const ZMQ_TOPICS: [&str; 3] = ["sequence", "rawtx", "hashblock"];
+/// Clock differences beyond this are reported
+const MAX_CLOCK_SKEW_SECS: i64 = 30;
+/// Less free space than this in the data directory is reported
+const MIN_FREE_BYTES: u64 = 1 << 30;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        }
+    }
+}
+
+/// Outcome of a single diagnostic
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    /// What to do about it
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Check {
+            name,
+            status: Status::Ok,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            name,
+            status: Status::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            name,
+            status: Status::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+pub struct Doctor;
+
+impl Doctor {
+    /// Check everything recording depends on: the node over REST (and JSON-RPC when `rpc` is
+    /// given), its chain, mempool and indexes, ZMQ, the clock and the data directory
+    pub async fn run(
+        rest: &RestClient,
+        rpc: Option<&RpcClient>,
+        zmq_endpoints: &[String],
+        data_dir: &Path,
+        network: Network,
+    ) -> Vec<Check> {
+        let mut checks = Vec::new();
+
+        let (rest_check, server_time) = Self::check_rest(rest, rpc.is_some()).await;
+        let rest_ok = rest_check.status == Status::Ok;
+        checks.push(rest_check);
+        let node: Option<&dyn Node> = match rpc {
+            Some(rpc) => match rpc.call::<Value>("getblockchaininfo", json!([])).await {
+                Ok(_) => {
+                    checks.push(Check::ok("rpc", "reachable"));
+                    Some(rpc)
+                }
+                Err(e) => {
+                    checks.push(Check::fail(
+                        "rpc",
+                        format!("{e:#}"),
+                        "check --rpc-endpoint and the credentials, bitcoind needs -server=1 \
+                        and an -rpcallowip covering this machine",
+                    ));
+                    None
+                }
+            },
+            None if rest_ok => Some(rest),
+            None => None,
+        };
+
+        if let Some(node) = node {
+            checks.extend(Self::check_node(node, network).await);
+            checks.push(Self::check_txindex(rest, rest_ok, node, rpc).await);
+        }
+        checks.extend(Self::check_zmq(rpc, zmq_endpoints).await);
+        checks.push(Self::check_clock(server_time));
+        checks.push(Self::check_disk(data_dir));
+        checks.push(Self::check_writable(&data_dir.join(network.to_string())));
+        checks
+    }
+
+    /// Whether REST answers, and the time of the node's machine from the response
+    async fn check_rest(rest: &RestClient, has_rpc: bool) -> (Check, Option<DateTime<Utc>>) {
+        let response = tokio::time::timeout(TIMEOUT, rest.get("chaininfo.json")).await;
+        let error = match response {
+            Ok(Ok(response)) if response.status().is_success() => {
+                let date = response
+                    .headers()
+                    .get(reqwest::header::DATE)
+                    .and_then(|date| date.to_str().ok())
+                    .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+                    .map(|date| date.with_timezone(&Utc));
+                return (
+                    Check::ok("rest", format!("reachable at {}", rest.endpoint())),
+                    date,
+                );
+            }
+            Ok(Ok(response)) => format!(
+                "{}chaininfo.json returned {}",
+                rest.endpoint(),
+                response.status()
+            ),
+            Ok(Err(e)) => format!("{}: {}", rest.endpoint(), e.root_cause()),
+            Err(_) => format!("{}: timed out", rest.endpoint()),
+        };
+        let check = if has_rpc {
+            Check::warn(
+                "rest",
+                error,
+                "recording goes through JSON-RPC, REST is only used when --rpc-endpoint is left out",
+            )
+        } else {
+            Check::fail(
+                "rest",
+                error,
+                "start bitcoind with -rest=1, or pass --bitcoin-core-endpoint or --rpc-endpoint",
+            )
+        };
+        (check, None)
+    }
+
+    async fn check_node(node: &dyn Node, network: Network) -> Vec<Check> {
+        let mut checks = Vec::new();
+        match node.get_chain_info().await {
+            Ok(info) => {
+                checks.push(match Network::from_core_arg(&info.chain) {
+                    Ok(chain) if chain == network => Check::ok("chain", info.chain.clone()),
+                    _ => Check::fail(
+                        "chain",
+                        format!("node is on {}, expected {network}", info.chain),
+                        format!("pass --network {}", info.chain),
+                    ),
+                });
+                checks.push(if info.initial_block_download {
+                    Check::warn(
+                        "sync",
+                        format!(
+                            "initial block download at {:.1}%",
+                            info.verification_progress * 100.
+                        ),
+                        "wait for the node to catch up, its mempool isn't representative yet",
+                    )
+                } else {
+                    Check::ok("sync", format!("synced at height {}", info.blocks))
+                });
+            }
+            Err(e) => checks.push(Check::fail(
+                "chain",
+                format!("{e:#}"),
+                "the node answers but not with chain info, is this Bitcoin Core?",
+            )),
+        }
+        checks.push(match node.get_mempool_info().await {
+            Ok(info) if !info.loaded => Check::warn(
+                "mempool",
+                format!("{} transactions, still loading", info.size),
+                "wait until the node has loaded mempool.dat",
+            ),
+            Ok(info) if info.size == 0 => Check::warn(
+                "mempool",
+                "empty",
+                "make sure the node relays transactions: not -blocksonly and connected to peers",
+            ),
+            Ok(info) => Check::ok(
+                "mempool",
+                format!("{} transactions, {} vB", info.size, info.bytes),
+            ),
+            Err(e) => Check::fail("mempool", format!("{e:#}"), "check the node's logs"),
+        });
+        checks.push(match node.get_mempool_txids_and_sequence().await {
+            Ok(mempool) => Check::ok(
+                "mempool sequence",
+                format!("at {}", mempool.mempool_sequence),
+            ),
+            Err(e) => Check::fail(
+                "mempool sequence",
+                format!("{e:#}"),
+                "the recorder needs mempool sequence numbers, upgrade Bitcoin Core",
+            ),
+        });
+        checks
+    }
+
+    /// Not needed for recording, reported as it changes what the node can look up
+    async fn check_txindex(
+        rest: &RestClient,
+        rest_ok: bool,
+        node: &dyn Node,
+        rpc: Option<&RpcClient>,
+    ) -> Check {
+        let enabled = match rpc {
+            Some(rpc) => rpc
+                .call::<Value>("getindexinfo", json!([]))
+                .await
+                .map(|indexes| indexes.get("txindex").is_some()),
+            // REST only finds confirmed transactions by txid with the index
+            None if rest_ok => match Self::tip_coinbase(node).await {
+                Ok(txid) => rest
+                    .get(&format!("tx/{txid}.bin"))
+                    .await
+                    .map(|response| response.status().is_success()),
+                Err(e) => Err(e),
+            },
+            None => return Check::ok("txindex", "unknown, REST is unreachable"),
+        };
+        match enabled {
+            Ok(true) => Check::ok("txindex", "enabled"),
+            Ok(false) => Check::ok("txindex", "disabled, recording doesn't need it"),
+            Err(e) => Check::ok("txindex", format!("unknown: {e:#}")),
+        }
+    }
+
+    async fn tip_coinbase(node: &dyn Node) -> anyhow::Result<bitcoin::Txid> {
+        let tip = node.get_chain_info().await?.best_block_hash;
+        let block = node.get_block(&tip).await?;
+        match block.txdata.first() {
+            Some(coinbase) => Ok(coinbase.txid()),
+            None => anyhow::bail!("block {tip} has no transactions"),
+        }
+    }
+
+    async fn check_zmq(rpc: Option<&RpcClient>, endpoints: &[String]) -> Vec<Check> {
+        let mut checks = Vec::new();
+        // only JSON-RPC tells what the node publishes
+        let published: Option<Vec<(String, String)>> = match rpc {
+            Some(rpc) => rpc
+                .call::<Vec<Value>>("getzmqnotifications", json!([]))
+                .await
+                .ok()
+                .map(|notifications| {
+                    notifications
+                        .iter()
+                        .filter_map(|n| {
+                            let topic = n.get("type")?.as_str()?.trim_start_matches("pub");
+                            Some((topic.to_string(), n.get("address")?.as_str()?.to_string()))
+                        })
+                        .collect()
+                }),
+            None => None,
+        };
+        if let Some(published) = &published {
+            let missing: Vec<&str> = ZMQ_TOPICS
+                .into_iter()
+                .filter(|topic| !published.iter().any(|(t, _)| t == topic))
+                .collect();
+            checks.push(if missing.is_empty() {
+                Check::ok(
+                    "zmq notifications",
+                    format!("{} published", ZMQ_TOPICS.join(", ")),
+                )
+            } else {
+                Check::warn(
+                    "zmq notifications",
+                    format!("not published: {}", missing.join(", ")),
+                    format!(
+                        "start bitcoind with {}",
+                        missing
+                            .iter()
+                            .map(|topic| format!("-zmqpub{topic}=tcp://127.0.0.1:28332"))
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    ),
+                )
+            });
+        }
+
+        if endpoints.is_empty() {
+            let hint = match published.as_ref().and_then(|p| p.first()) {
+                Some((_, address)) => format!("pass --zmq-endpoint {address}"),
+                None => String::from(
+                    "start bitcoind with -zmqpubsequence=tcp://127.0.0.1:28332 and pass \
+                    --zmq-endpoint tcp://127.0.0.1:28332 to see every transaction",
+                ),
+            };
+            checks.push(Check {
+                hint: Some(hint),
+                ..Check::ok(
+                    "zmq",
+                    "not configured, changes between snapshots are missed",
+                )
+            });
+        }
+        for endpoint in endpoints {
+            let address = endpoint.trim_start_matches("tcp://");
+            let connected =
+                tokio::time::timeout(TIMEOUT, TcpStream::connect(address.to_string())).await;
+            checks.push(match connected {
+                Ok(Ok(_)) => Check::ok("zmq", format!("{endpoint} accepts connections")),
+                Ok(Err(e)) => Check::fail(
+                    "zmq",
+                    format!("{endpoint}: {e}"),
+                    "check -zmqpubsequence and friends in bitcoin.conf match --zmq-endpoint",
+                ),
+                Err(_) => Check::fail(
+                    "zmq",
+                    format!("{endpoint}: timed out"),
+                    "check that no firewall is in between",
+                ),
+            });
+        }
+        checks
+    }
+
+    fn check_clock(server_time: Option<DateTime<Utc>>) -> Check {
+        let Some(server_time) = server_time else {
+            return Check::ok("clock", "unknown, the node sent no time");
+        };
+        let skew = (Utc::now() - server_time).num_seconds();
+        if skew.abs() > MAX_CLOCK_SKEW_SECS {
+            Check::warn(
+                "clock",
+                format!("{skew} s off from the node"),
+                "sync both clocks with NTP, snapshots are timestamped with this machine's clock",
+            )
+        } else {
+            Check::ok("clock", format!("{skew} s off from the node"))
+        }
+    }
+
+    fn check_disk(data_dir: &Path) -> Check {
+        // the data directory may not exist before the first recording
+        let Some(existing) = data_dir.ancestors().find(|dir| dir.exists()) else {
+            return Check::ok("disk", "unknown");
+        };
+        match fs4::available_space(existing) {
+            Ok(free) if free < MIN_FREE_BYTES => Check::warn(
+                "disk",
+                format!("{:.1} GB free", free as f64 / 1e9),
+                "free up space, run wtf compact or prune, or pass another --data-dir",
+            ),
+            Ok(free) => Check::ok("disk", format!("{:.1} GB free", free as f64 / 1e9)),
+            Err(e) => Check::warn(
+                "disk",
+                format!("{}: {e}", existing.display()),
+                "check the data directory",
+            ),
+        }
+    }
+
+    fn check_writable(dir: &Path) -> Check {
+        let probe = dir.join(".wtf-doctor");
+        let written = std::fs::create_dir_all(dir)
+            .and_then(|_| std::fs::write(&probe, b"wtf"))
+            .and_then(|_| std::fs::remove_file(&probe));
+        match written {
+            Ok(()) => Check::ok("data dir", format!("{} is writable", dir.display())),
+            Err(e) => Check::fail(
+                "data dir",
+                format!("{}: {e}", dir.display()),
+                "fix the permissions or pass another --data-dir",
+            ),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{
+    dataset::{FileKind, SnapshotFile},
+    histogram::Histogram,
+    prune::Prune,
+    replay::Replay,
+    storage::Storage,
+};
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use serde::Serialize;
+use std::collections::HashSet;
+use tracing::{debug, info};
+
+/// What [`Downsample::downsample`] did
+#[derive(Debug, Default, Serialize)]
+pub struct Downsampled {
+    /// Full, delta and compact files removed
+    pub removed: usize,
+    /// Histogram files written in their place
+    pub histograms: usize,
+}
+
+/// Thins out old data: the mempool of each height is kept as a histogram every `every`
+/// seconds instead of transaction by transaction. Replays, and so the seasonal model and
+/// backtests, still see the fee rate buckets, but first-seen times and txids are gone.
+pub struct Downsample;
+
+impl Downsample {
+    /// Replace the full, delta and compact files written before `before` with histograms.
+    /// Files of a height that still has files after `before` are left alone, like
+    /// [`Prune::prune`] does.
+    #[tracing::instrument(skip(storage))]
+    pub fn downsample(storage: &dyn Storage, before: i64, every: i64) -> Result<Downsampled> {
+        let expired: Vec<SnapshotFile> = Prune::expired(storage, before)?
+            .into_iter()
+            .filter(|f| matches!(f.kind, FileKind::Full | FileKind::Delta | FileKind::Compact))
+            .collect();
+        let Some(from) = expired.iter().map(|f| f.timestamp).min() else {
+            return Ok(Downsampled::default());
+        };
+        let mut heights = HashSet::new();
+        for file in &expired {
+            match file.kind {
+                FileKind::Compact => heights.extend(Prune::heights(storage, file)?),
+                _ => {
+                    heights.insert(file.height);
+                }
+            }
+        }
+
+        // all histograms are written before anything is removed, an interrupted run leaves
+        // both behind rather than neither. A table commits them together.
+        let mut histograms = 0;
+        storage.atomically("downsample", &mut || {
+            let mut last_sample = None;
+            Replay::new(storage)?.walk(from, before - 1, |snapshot| {
+                let sample = snapshot.timestamp.div_euclid(every.max(1));
+                if !heights.contains(&snapshot.height)
+                    || last_sample == Some((snapshot.height, sample))
+                {
+                    return Ok(());
+                }
+                last_sample = Some((snapshot.height, sample));
+                let mut frame = Histogram::from_snapshot(snapshot).to_frame();
+                let at = Utc.timestamp_opt(snapshot.timestamp, 0).unwrap();
+                storage.write(at, snapshot.height, FileKind::Histogram, &mut frame)?;
+                histograms += 1;
+                Ok(())
+            })?;
+
+            for file in &expired {
+                debug!("downsampled {}", file.path.display());
+                storage.remove(file)?;
+            }
+            Ok(())
+        })?;
+        info!(
+            "downsampled_files: {}, histograms: {histograms}",
+            expired.len()
+        );
+        Ok(Downsampled {
+            removed: expired.len(),
+            histograms,
+        })
+    }
+}
@@ -0,0 +1,172 @@
+use crate::{calc::Calc, histogram::Histogram, model::Trained, replay::Replay, storage::Storage};
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, info, warn};
+
+/// Protocol version answered to `server.version`, the one with `mempool.get_fee_histogram`
+const PROTOCOL_VERSION: &str = "1.4";
+
+/// An error to send back, with the JSON-RPC code
+struct RpcError(i64, String);
+
+struct State {
+    storage: Box<dyn Storage>,
+    model: Option<Trained>,
+    /// Electrum asks for no confidence
+    confidence: f64,
+}
+
+/// The fee methods of the Electrum server protocol, newline-delimited JSON-RPC over TCP:
+/// `blockchain.estimatefee`, `blockchain.relayfee` and `mempool.get_fee_histogram`, plus the
+/// `server.*` calls clients make on connecting. Everything else is refused, so route only fee
+/// queries here.
+pub struct Electrum;
+
+impl Electrum {
+    pub async fn serve(
+        storage: Box<dyn Storage>,
+        listen: SocketAddr,
+        model: Option<Trained>,
+        confidence: f64,
+    ) -> Result<()> {
+        let state = Arc::new(State {
+            storage,
+            model,
+            confidence,
+        });
+        let listener = TcpListener::bind(listen).await?;
+        info!("electrum listening on {listen}");
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let state = state.clone();
+            tokio::spawn(async move {
+                if let Err(e) = Self::session(stream, state).await {
+                    debug!("electrum client {peer}: {e:#}");
+                }
+            });
+        }
+    }
+
+    async fn session(stream: TcpStream, state: Arc<State>) -> Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str::<Value>(&line) {
+                // batches are answered with an array
+                Ok(Value::Array(requests)) => {
+                    let mut responses = Vec::new();
+                    for request in requests {
+                        responses.push(Self::respond(&state, request).await);
+                    }
+                    Value::Array(responses)
+                }
+                Ok(request) => Self::respond(&state, request).await,
+                Err(e) => json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": {"code": -32700, "message": format!("parse error: {e}")},
+                }),
+            };
+            let mut message = serde_json::to_vec(&response)?;
+            message.push(b'\n');
+            write.write_all(&message).await?;
+        }
+        Ok(())
+    }
+
+    async fn respond(state: &Arc<State>, request: Value) -> Value {
+        let id = request["id"].clone();
+        let method = request["method"].as_str().unwrap_or_default().to_string();
+        let params = request["params"].clone();
+        match Self::call(state, &method, params).await {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(RpcError(code, message)) => {
+                json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+            }
+        }
+    }
+
+    async fn call(state: &Arc<State>, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "server.version" => Ok(json!([
+                concat!("wtf ", env!("CARGO_PKG_VERSION")),
+                PROTOCOL_VERSION
+            ])),
+            "server.ping" => Ok(Value::Null),
+            "server.banner" => Ok(json!("WhatTheFee, fee estimates only")),
+            "server.donation_address" => Ok(json!("")),
+            "server.peers.subscribe" => Ok(json!([])),
+            "blockchain.estimatefee" => {
+                let target = params
+                    .get(0)
+                    .and_then(Value::as_u64)
+                    .ok_or_else(|| RpcError(-32602, String::from("missing number of blocks")))?;
+                let state = state.clone();
+                let estimate = Self::blocking(move || state.estimate(target.max(1) as u32)).await;
+                // BTC/kB, and -1 like electrs when there's no estimate
+                Ok(match estimate {
+                    Ok(fee_rate) => json!(fee_rate / 100_000.),
+                    Err(e) => {
+                        warn!("electrum estimatefee {target}: {e:#}");
+                        json!(-1)
+                    }
+                })
+            }
+            "blockchain.relayfee" => {
+                let state = state.clone();
+                let minimum = Self::blocking(move || Calc::minimum_fee(state.storage.as_ref()))
+                    .await
+                    .map_err(|e| RpcError(-32603, format!("{e:#}")))?;
+                Ok(json!(minimum / 100_000.))
+            }
+            "mempool.get_fee_histogram" => {
+                let state = state.clone();
+                let histogram =
+                    Self::blocking(move || Histogram::from_recorded(state.storage.as_ref()))
+                        .await
+                        .map_err(|e| RpcError(-32603, format!("{e:#}")))?;
+                // [fee rate, vsize] from the highest fee rate down, leaving out empty buckets
+                Ok(histogram
+                    .buckets
+                    .iter()
+                    .filter(|bucket| bucket.vsize > 0)
+                    .map(|bucket| json!([bucket.fee_rate, bucket.vsize]))
+                    .collect())
+            }
+            _ => Err(RpcError(
+                -32601,
+                format!("{method} is not supported, only fee estimates are served here"),
+            )),
+        }
+    }
+
+    async fn blocking<T: Send + 'static>(
+        work: impl FnOnce() -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        tokio::task::spawn_blocking(work)
+            .await
+            .map_err(|e| anyhow!("{e}"))?
+    }
+}
+
+impl State {
+    /// sat/vB for `target` from the model if one is loaded, or like the Esplora endpoint
+    fn estimate(&self, target: u32) -> Result<f64> {
+        let storage = self.storage.as_ref();
+        match &self.model {
+            Some(model) => {
+                let snapshot = Replay::new(storage)?.at(i64::MAX)?;
+                model.estimate(&snapshot, target, self.confidence)
+            }
+            None => Ok(Calc::targets(storage, &[target], &[self.confidence])?[0].fee_rate_sat_vb),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{calc::TARGETS, node::Node, position::QueuePosition, storage::Storage};
+use anyhow::{anyhow, bail, Context, Result};
+use bitcoin::{consensus::deserialize, hashes::hex::FromHex, Transaction, Txid};
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// Expected time between blocks
+const BLOCK_INTERVAL_SECS: i64 = 600;
+
+/// When a transaction not broadcast yet would confirm
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct TxEta {
+    #[schema(value_type = String)]
+    pub txid: Txid,
+    pub vsize: u64,
+    /// sat
+    pub fee: u64,
+    /// sat/vB
+    pub fee_rate: f64,
+    /// Where it lands in the projected blocks of the latest recorded snapshot
+    pub position: QueuePosition,
+    pub confidence: f64,
+    /// Fewest blocks of [`TARGETS`] the estimate at `confidence` is met within, none if it
+    /// pays less than even the last one asks for
+    pub target: Option<u32>,
+    /// Expected wait for `target`, ten minutes a block
+    pub eta_secs: Option<i64>,
+}
+
+impl TxEta {
+    /// A transaction as hex, like `getrawtransaction` answers it
+    pub fn decode(hex: &str) -> Result<Transaction> {
+        let bytes = Vec::<u8>::from_hex(hex.trim()).context("the transaction isn't hex")?;
+        deserialize(&bytes).context("decoding the transaction")
+    }
+
+    /// What `tx` pays, its prevouts looked up by `node`. Confirmed ones need a node with
+    /// `-txindex`, unconfirmed ones come from its mempool.
+    pub async fn fee(node: &dyn Node, tx: &Transaction) -> Result<u64> {
+        if tx.is_coin_base() {
+            bail!("a coinbase transaction pays no fee");
+        }
+        let txids: Vec<Txid> = tx
+            .input
+            .iter()
+            .map(|input| input.previous_output.txid)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let parents = node.get_transactions(&txids).await?;
+        let mut spent = 0;
+        for input in &tx.input {
+            let outpoint = input.previous_output;
+            let output = parents
+                .get(&outpoint.txid)
+                .ok_or_else(|| {
+                    anyhow!("prevout {outpoint} not found, confirmed ones need -txindex")
+                })?
+                .output
+                .get(outpoint.vout as usize)
+                .ok_or_else(|| anyhow!("prevout {outpoint} does not exist"))?;
+            spent += output.value;
+        }
+        let sent: u64 = tx.output.iter().map(|output| output.value).sum();
+        spent
+            .checked_sub(sent)
+            .ok_or_else(|| anyhow!("the outputs spend {sent} sat but the inputs hold {spent}"))
+    }
+
+    /// `tx` paying `fee` against the dataset in `storage`, `estimate` answering the fee rate
+    /// of a target at `confidence` under the current model
+    pub fn new(
+        storage: &dyn Storage,
+        tx: &Transaction,
+        fee: u64,
+        confidence: f64,
+        mut estimate: impl FnMut(u32) -> Result<f64>,
+    ) -> Result<Self> {
+        let vsize = tx.vsize() as u64;
+        let fee_rate = fee as f64 / vsize as f64;
+        let position = QueuePosition::from_recorded(storage, fee_rate)?;
+        let mut target = None;
+        for candidate in TARGETS {
+            if estimate(candidate)? <= fee_rate {
+                target = Some(candidate);
+                break;
+            }
+        }
+        Ok(TxEta {
+            txid: tx.txid(),
+            vsize,
+            fee,
+            fee_rate,
+            position,
+            confidence,
+            target,
+            eta_secs: target.map(|target| target as i64 * BLOCK_INTERVAL_SECS),
+        })
+    }
+}
@@ -0,0 +1,154 @@
+use crate::{
+    calc::{Calc, WINDOW_SECS},
+    model::Trained,
+    replay::Replay,
+    rollout::MAX_GAP_SECS,
+    storage::Storage,
+};
+use anyhow::{bail, Result};
+use chrono::{Datelike, TimeZone, Timelike, Utc};
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// Weeks back the snapshots of the same weekday and hour are looked at
+const SEASONAL_WEEKS: i64 = 4;
+const WEEK_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// What went into an estimate, so it needn't be taken on trust
+#[derive(Debug, Clone, Serialize)]
+pub struct Explanation {
+    pub confidence: f64,
+    pub target: u32,
+    /// The estimate explained, sat/vB
+    pub fee_rate_sat_vb: f64,
+    pub height: u64,
+    /// Unix timestamp of the latest snapshot
+    pub timestamp: i64,
+    /// Lowest fee rate in the last of the projected `target` blocks of the latest snapshot
+    pub projected_cutoff_sat_vb: f64,
+    pub mempool_vsize: u64,
+    /// The cutoff `confidence` of the snapshots of the last hour were at or below, what
+    /// `calc` estimates without a model
+    pub recent_cutoff_sat_vb: f64,
+    /// Lowest fee rate at which `confidence` of the transactions confirmed during the last week
+    /// waited at most `target` blocks, if enough were recorded
+    pub historical_sat_vb: Option<f64>,
+    pub confirmations: usize,
+    /// Virtual size arriving per minute during the last hour
+    pub inflow_vsize_per_minute: Option<f64>,
+    /// Of which paying at least the estimate
+    pub inflow_above_vsize_per_minute: Option<f64>,
+    /// UTC weekday and hour of the latest snapshot, e.g. `Tue` and 14
+    pub weekday: String,
+    pub hour: u32,
+    /// The cutoff `confidence` of the snapshots taken on the same weekday and hour during the
+    /// previous weeks were at or below, if any were recorded
+    pub seasonal_cutoff_sat_vb: Option<f64>,
+    pub seasonal_snapshots: usize,
+    /// Model the estimate came from, none for the cutoff
+    pub model: Option<&'static str>,
+    /// How far the model moved the estimate from the recent cutoff, sat/vB
+    pub model_adjustment_sat_vb: Option<f64>,
+}
+
+pub struct Explain;
+
+impl Explain {
+    /// Explain each `(confidence, target, fee rate)` estimated from the dataset, by `model`
+    /// if one was used
+    #[tracing::instrument(skip(storage, model))]
+    pub fn explain(
+        storage: &dyn Storage,
+        estimates: &[(f64, u32, f64)],
+        model: Option<&Trained>,
+    ) -> Result<Vec<Explanation>> {
+        let replay = Replay::new(storage)?;
+        let Some(latest) = replay.latest() else {
+            bail!("no recorded snapshots found");
+        };
+        let snapshot = replay.at(latest)?;
+        let mempool_vsize = (snapshot
+            .transactions
+            .values()
+            .map(|tx| tx.weight)
+            .sum::<f64>()
+            / 4.)
+            .ceil() as u64;
+
+        // (fee rate, vsize) of what arrived between consecutive snapshots and the time covered
+        let mut arrivals: Vec<(f64, f64)> = Vec::new();
+        let mut covered = 0.;
+        let mut previous: Option<(i64, HashSet<String>)> = None;
+        replay.walk(latest - WINDOW_SECS, latest, |snapshot| {
+            if let Some((timestamp, txids)) = &previous {
+                let gap = (snapshot.timestamp - timestamp) as f64;
+                if gap > 0. && gap <= MAX_GAP_SECS {
+                    covered += gap;
+                    arrivals.extend(
+                        snapshot
+                            .transactions
+                            .iter()
+                            .filter(|(txid, _)| !txids.contains(*txid))
+                            .map(|(_, tx)| (tx.fee_rate_sat_vb(), tx.weight / 4.)),
+                    );
+                }
+            }
+            previous = Some((
+                snapshot.timestamp,
+                snapshot.transactions.keys().cloned().collect(),
+            ));
+            Ok(())
+        })?;
+        let inflow = |fee_rate: f64| {
+            (covered > 0.).then(|| {
+                arrivals
+                    .iter()
+                    .filter(|(rate, _)| *rate >= fee_rate)
+                    .map(|(_, vsize)| vsize)
+                    .sum::<f64>()
+                    / covered
+                    * 60.
+            })
+        };
+
+        let at = Utc.timestamp_opt(latest, 0).unwrap();
+        let hour_start = latest - latest.rem_euclid(60 * 60);
+        let mut explanations = Vec::with_capacity(estimates.len());
+        for &(confidence, target, fee_rate_sat_vb) in estimates {
+            let mut seasonal = Vec::new();
+            for week in 1..=SEASONAL_WEEKS {
+                let from = hour_start - week * WEEK_SECS;
+                replay.walk(from, from + 60 * 60 - 1, |snapshot| {
+                    seasonal.push(Calc::block_cutoff(snapshot, target));
+                    Ok(())
+                })?;
+            }
+            seasonal.sort_by(f64::total_cmp);
+            let seasonal_cutoff_sat_vb =
+                (!seasonal.is_empty()).then(|| Calc::quantile(&seasonal, confidence));
+            let recent_cutoff_sat_vb = Calc::calc(storage, confidence, target)?;
+            let historical = &Calc::targets(storage, &[target], &[confidence])?[0];
+            explanations.push(Explanation {
+                confidence,
+                target,
+                fee_rate_sat_vb,
+                height: snapshot.height,
+                timestamp: snapshot.timestamp,
+                projected_cutoff_sat_vb: Calc::block_cutoff(&snapshot, target),
+                mempool_vsize,
+                recent_cutoff_sat_vb,
+                historical_sat_vb: historical.historical_sat_vb,
+                confirmations: historical.confirmations,
+                inflow_vsize_per_minute: inflow(f64::NEG_INFINITY),
+                inflow_above_vsize_per_minute: inflow(fee_rate_sat_vb),
+                weekday: at.weekday().to_string(),
+                hour: at.hour(),
+                seasonal_cutoff_sat_vb,
+                seasonal_snapshots: seasonal.len(),
+                model: model.map(Trained::as_str),
+                model_adjustment_sat_vb: model.map(|_| fee_rate_sat_vb - recent_cutoff_sat_vb),
+            });
+        }
+        Ok(explanations)
+    }
+}
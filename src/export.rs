@@ -0,0 +1,272 @@
+use crate::{
+    dataset::FileKind,
+    query::TABLES,
+    replay::Replay,
+    storage::{LocalStorage, Storage},
+    unit::FeeUnit,
+};
+use anyhow::{bail, Context, Result};
+use polars::prelude::*;
+use std::{io::Write, path::Path};
+use tracing::debug;
+
+/// Seconds of a UTC day, what compacted files span
+const DAY_SECS: i64 = 86_400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    /// Newline-delimited JSON, one object per row
+    Ndjson,
+    /// Arrow IPC file
+    Arrow,
+    /// DuckDB database with a view per table of `wtf query` over the parquet files, see
+    /// [`Export::duckdb`]
+    Duckdb,
+}
+
+pub struct Export;
+
+impl Export {
+    /// Write the rows of all `kinds` files recorded within `from..=to` to `writer`, each
+    /// prefixed with the `height`, `snapshot_timestamp` and `kind` of the file it came from.
+    /// The kinds need to share a schema, like full and delta files do. Compacted snapshots are
+    /// exported as if they were still separate files. Fee rate columns are converted to `unit`.
+    #[tracing::instrument(skip(storage, writer))]
+    pub fn export(
+        storage: &dyn Storage,
+        kinds: &[FileKind],
+        from: i64,
+        to: i64,
+        format: ExportFormat,
+        unit: FeeUnit,
+        writer: impl Write,
+    ) -> Result<usize> {
+        let mut sink = Sink::new(format, writer)?;
+        let mut rows = 0;
+        let mut exported = 0;
+        let mut schema = None;
+
+        // full and delta snapshots may have been compacted, replay knows where to find them
+        let replay = Replay::new(storage)?;
+        for entry in replay.entries() {
+            if kinds.contains(&entry.kind) && entry.timestamp >= from && entry.timestamp <= to {
+                let frame = unit.convert_frame(replay.read(entry)?)?;
+                let mut frame = Self::conform(frame, &mut schema)?;
+                sink.write(&mut frame)?;
+                rows += frame.height();
+                exported += 1;
+            }
+        }
+        let others = storage.list()?.into_iter().filter(|f| {
+            !matches!(f.kind, FileKind::Full | FileKind::Delta | FileKind::Compact)
+                && kinds.contains(&f.kind)
+                && f.timestamp >= from
+                && f.timestamp <= to
+        });
+        for file in others {
+            debug!("exporting {}", file.path.display());
+            let frame = unit.convert_frame(file.tag(&storage.read(&file)?)?)?;
+            let mut frame = Self::conform(frame, &mut schema)?;
+            sink.write(&mut frame)?;
+            rows += frame.height();
+            exported += 1;
+        }
+        if exported == 0 {
+            bail!("no files recorded between {from} and {to}");
+        }
+        sink.finish()?;
+        Ok(rows)
+    }
+
+    /// Give `frame` the columns of the first frame exported, `schema`. Files recorded before a
+    /// column was added get nulls for it, columns the first frame lacks are left out.
+    fn conform(frame: DataFrame, schema: &mut Option<Schema>) -> Result<DataFrame> {
+        let schema = schema.get_or_insert_with(|| frame.schema());
+        let columns = schema
+            .iter()
+            .map(|(name, dtype)| match frame.column(name) {
+                Ok(column) => column.clone(),
+                Err(_) => Series::full_null(name, frame.height(), dtype),
+            })
+            .collect();
+        Ok(DataFrame::new(columns)?)
+    }
+
+    /// Write a single frame, like the result of a query
+    pub fn write(frame: &mut DataFrame, format: ExportFormat, writer: impl Write) -> Result<()> {
+        let mut sink = Sink::new(format, writer)?;
+        sink.write(frame)?;
+        sink.finish()
+    }
+
+    /// Create or update the DuckDB database `database` with a view per table of `wtf query`
+    /// over the parquet files of `storage`, rows tagged with the `height`,
+    /// `snapshot_timestamp` and `kind` of their file. Views match the files when queried, so
+    /// the database keeps up with the recorder and can be attached next to other data. With
+    /// `daily` the `daily_meta` and `daily_blocks` tables are materialized too, a row per UTC
+    /// day. Returns the views and tables created.
+    #[tracing::instrument(skip(storage))]
+    pub fn duckdb(storage: &LocalStorage, database: &Path, daily: bool) -> Result<Vec<String>> {
+        let files = storage.list()?;
+        if files.is_empty() {
+            bail!("no recorded files found");
+        }
+        let root = storage
+            .root()
+            .canonicalize()
+            .with_context(|| format!("resolving {}", storage.root().display()))?;
+        let glob = |kind: FileKind| {
+            Self::quote(&root.join("**").join(format!("*_{}.parquet", kind.as_str())))
+        };
+        let recorded = |kind: FileKind| files.iter().any(|f| f.kind == kind);
+
+        let connection = duckdb::Connection::open(database)
+            .with_context(|| format!("opening {}", database.display()))?;
+        let mut created = Vec::new();
+        for (table, kinds) in TABLES {
+            let raw: Vec<String> = kinds
+                .iter()
+                .filter(|kind| **kind != FileKind::Compact && recorded(**kind))
+                .map(|kind| glob(*kind))
+                .collect();
+            let compacted = kinds.contains(&FileKind::Compact) && recorded(FileKind::Compact);
+            let mut selects = Vec::new();
+            if !raw.is_empty() {
+                // the file name is all there is of the tag, its columns win like in `wtf query`
+                let mut select = format!(
+                    "SELECT CAST(split_part(wtf_file, '_', 1) AS UBIGINT) AS height,
+                        CAST(split_part(wtf_file, '_', 2) AS BIGINT) AS snapshot_timestamp,
+                        split_part(wtf_file, '_', 3) AS kind,
+                        COLUMNS(c -> c NOT IN (
+                            'filename', 'wtf_file', 'height', 'snapshot_timestamp', 'kind'
+                        ))
+                    FROM (
+                        SELECT *, parse_filename(filename, true) AS wtf_file
+                        FROM read_parquet(
+                            [{}],
+                            filename = true, union_by_name = true, hive_partitioning = false
+                        )
+                    )",
+                    raw.join(", ")
+                );
+                if compacted {
+                    // compacted with --keep-raw, the raw copies would count twice
+                    select.push_str(&format!(
+                        " WHERE snapshot_timestamp // {DAY_SECS} NOT IN (
+                            SELECT CAST(split_part(parse_filename(file, true), '_', 2) AS BIGINT)
+                                // {DAY_SECS}
+                            FROM glob({})
+                        )",
+                        glob(FileKind::Compact)
+                    ));
+                }
+                selects.push(select);
+            }
+            if compacted {
+                // already tagged, empty snapshots are kept as a placeholder row without txid
+                selects.push(format!(
+                    "SELECT * FROM read_parquet({}, union_by_name = true, hive_partitioning = false)
+                    WHERE txid IS NOT NULL",
+                    glob(FileKind::Compact)
+                ));
+            }
+            if selects.is_empty() {
+                continue;
+            }
+            debug!("view: {table}");
+            connection.execute_batch(&format!(
+                "CREATE OR REPLACE VIEW {table} AS {}",
+                selects.join(" UNION ALL BY NAME ")
+            ))?;
+            created.push(table.to_string());
+        }
+
+        if daily && created.iter().any(|table| table == "meta") {
+            connection.execute_batch(
+                "CREATE OR REPLACE TABLE daily_meta AS
+                SELECT CAST(epoch_ms(snapshot_timestamp * 1000) AS DATE) AS day,
+                    count(*) AS snapshots,
+                    avg(mempool_txs) AS avg_mempool_txs,
+                    max(mempool_txs) AS max_mempool_txs,
+                    avg(mempool_bytes) AS avg_mempool_bytes,
+                    max(mempool_bytes) AS max_mempool_bytes,
+                    max(mempool_min_fee_sat_vb) AS max_mempool_min_fee_sat_vb
+                FROM meta GROUP BY day ORDER BY day",
+            )?;
+            created.push(String::from("daily_meta"));
+        }
+        if daily && created.iter().any(|table| table == "block_headers") {
+            // a block's header file is written once, by the snapshot that saw it first
+            connection.execute_batch(
+                "CREATE OR REPLACE TABLE daily_blocks AS
+                SELECT CAST(epoch_ms(snapshot_timestamp * 1000) AS DATE) AS day,
+                    count(*) AS blocks,
+                    sum(tx_count) AS txs,
+                    sum(total_fees_sat) AS total_fees_sat,
+                    avg(weight) AS avg_weight,
+                    count(*) FILTER (WHERE empty) AS empty_blocks
+                FROM block_headers GROUP BY day ORDER BY day",
+            )?;
+            created.push(String::from("daily_blocks"));
+        }
+        Ok(created)
+    }
+
+    /// `path` as a DuckDB string literal
+    fn quote(path: &Path) -> String {
+        format!("'{}'", path.display().to_string().replace('\'', "''"))
+    }
+}
+
+/// Streams frames out one file at a time instead of concatenating them in memory
+enum Sink<W: Write> {
+    Csv { writer: W, header: bool },
+    Ndjson(W),
+    Arrow(Option<W>, Option<Box<polars::io::ipc::BatchedWriter<W>>>),
+}
+
+impl<W: Write> Sink<W> {
+    fn new(format: ExportFormat, writer: W) -> Result<Self> {
+        Ok(match format {
+            ExportFormat::Csv => Sink::Csv {
+                writer,
+                header: true,
+            },
+            ExportFormat::Ndjson => Sink::Ndjson(writer),
+            ExportFormat::Arrow => Sink::Arrow(Some(writer), None),
+            ExportFormat::Duckdb => bail!("duckdb exports are a database, not a stream of rows"),
+        })
+    }
+
+    fn write(&mut self, frame: &mut DataFrame) -> Result<()> {
+        match self {
+            Sink::Csv { writer, header } => {
+                CsvWriter::new(writer).has_header(*header).finish(frame)?;
+                *header = false;
+            }
+            Sink::Ndjson(writer) => {
+                JsonWriter::new(writer)
+                    .with_json_format(JsonFormat::JsonLines)
+                    .finish(frame)?;
+            }
+            Sink::Arrow(writer, batched) => {
+                if batched.is_none() {
+                    let writer = writer.take().expect("writer is set until the first frame");
+                    *batched = Some(Box::new(IpcWriter::new(writer).batched(&frame.schema())?));
+                }
+                batched.as_mut().unwrap().write_batch(frame)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Sink::Csv { mut writer, .. } | Sink::Ndjson(mut writer) => writer.flush()?,
+            Sink::Arrow(_, Some(mut batched)) => batched.finish()?,
+            Sink::Arrow(_, None) => {}
+        }
+        Ok(())
+    }
+}
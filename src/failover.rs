@@ -0,0 +1,200 @@
+use crate::node::Node;
+use anyhow::Result;
+use async_trait::async_trait;
+use bitcoin::{Block, BlockHash, Transaction, Txid};
+use bitcoincore_rest::{
+    responses::{GetBlockchainInfoResult, GetMempoolEntryResult, GetMempoolInfoResult},
+    GetMempoolTxidsAndSequenceResult,
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// A failed node is left alone this long, doubling with every failure in a row
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+type Call<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+struct Candidate {
+    name: String,
+    node: Box<dyn Node>,
+    /// Failures in a row and when the node may be tried again
+    health: Mutex<(u32, Option<Instant>)>,
+}
+
+impl Candidate {
+    fn available(&self) -> bool {
+        let (_, retry_at) = *self.health.lock().unwrap();
+        retry_at.is_none_or(|at| Instant::now() >= at)
+    }
+
+    fn succeeded(&self) {
+        *self.health.lock().unwrap() = (0, None);
+    }
+
+    fn failed(&self) -> Duration {
+        let mut health = self.health.lock().unwrap();
+        let backoff = INITIAL_BACKOFF
+            .saturating_mul(1 << health.0.min(16))
+            .min(MAX_BACKOFF);
+        *health = (health.0 + 1, Some(Instant::now() + backoff));
+        backoff
+    }
+}
+
+/// Several nodes in order of preference. Calls go to the active node; when it fails the next
+/// available one takes over and the failed node is backed off. At the start of every snapshot
+/// the preferred nodes are checked again and the first healthy one becomes active.
+pub struct FailoverNode {
+    nodes: Vec<Candidate>,
+    active: AtomicUsize,
+}
+
+impl FailoverNode {
+    pub fn new(nodes: Vec<(String, Box<dyn Node>)>) -> Self {
+        assert!(!nodes.is_empty(), "failover needs at least one node");
+        FailoverNode {
+            nodes: nodes
+                .into_iter()
+                .map(|(name, node)| Candidate {
+                    name,
+                    node,
+                    health: Mutex::new((0, None)),
+                })
+                .collect(),
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    /// Endpoint of the node calls currently go to
+    pub fn active(&self) -> &str {
+        &self.nodes[self.active.load(Ordering::Relaxed)].name
+    }
+
+    /// Switch back to a node preferred over the active one once it answers again
+    async fn restore(&self) {
+        let active = self.active.load(Ordering::Relaxed);
+        for (index, candidate) in self.nodes[..active].iter().enumerate() {
+            if !candidate.available() {
+                continue;
+            }
+            match candidate.node.get_chain_info().await {
+                Ok(_) => {
+                    candidate.succeeded();
+                    info!(
+                        "node {} is back, switching from {}",
+                        candidate.name,
+                        self.active()
+                    );
+                    self.active.store(index, Ordering::Relaxed);
+                    return;
+                }
+                Err(e) => {
+                    let backoff = candidate.failed();
+                    warn!(
+                        "node {} still failing, retrying in {}s: {e:#}",
+                        candidate.name,
+                        backoff.as_secs()
+                    );
+                }
+            }
+        }
+    }
+
+    async fn call<'a, T>(&'a self, call: impl Fn(&'a dyn Node) -> Call<'a, T>) -> Result<T> {
+        let active = self.active.load(Ordering::Relaxed);
+        let mut error = None;
+        for offset in 0..self.nodes.len() {
+            let index = (active + offset) % self.nodes.len();
+            let candidate = &self.nodes[index];
+            // the active node is always tried, the others once their backoff is over
+            if offset > 0 && !candidate.available() {
+                continue;
+            }
+            match call(candidate.node.as_ref()).await {
+                Ok(result) => {
+                    candidate.succeeded();
+                    if index != active {
+                        warn!("failed over from {} to {}", self.active(), candidate.name);
+                        self.active.store(index, Ordering::Relaxed);
+                    }
+                    return Ok(result);
+                }
+                Err(e) => {
+                    if self.nodes.len() > 1 {
+                        let backoff = candidate.failed();
+                        warn!(
+                            "node {} failed, retrying in {}s: {e:#}",
+                            candidate.name,
+                            backoff.as_secs()
+                        );
+                    }
+                    error = Some(e);
+                }
+            }
+        }
+        Err(error.expect("the active node is always tried"))
+    }
+}
+
+#[async_trait]
+impl Node for FailoverNode {
+    async fn get_chain_info(&self) -> Result<GetBlockchainInfoResult> {
+        // the first call of every snapshot
+        self.restore().await;
+        self.call(|node| node.get_chain_info()).await
+    }
+
+    async fn get_mempool(&self) -> Result<HashMap<Txid, GetMempoolEntryResult>> {
+        self.call(|node| node.get_mempool()).await
+    }
+
+    async fn get_mempool_info(&self) -> Result<GetMempoolInfoResult> {
+        self.call(|node| node.get_mempool_info()).await
+    }
+
+    async fn get_mempool_txids_and_sequence(&self) -> Result<GetMempoolTxidsAndSequenceResult> {
+        self.call(|node| node.get_mempool_txids_and_sequence())
+            .await
+    }
+
+    async fn get_mempool_entries(
+        &self,
+        txids: &[Txid],
+    ) -> Result<HashMap<Txid, GetMempoolEntryResult>> {
+        self.call(|node| node.get_mempool_entries(txids)).await
+    }
+
+    async fn get_transactions(&self, txids: &[Txid]) -> Result<HashMap<Txid, Transaction>> {
+        self.call(|node| node.get_transactions(txids)).await
+    }
+
+    async fn get_block_hash(&self, height: u64) -> Result<BlockHash> {
+        self.call(|node| node.get_block_hash(height)).await
+    }
+
+    async fn get_block(&self, hash: &BlockHash) -> Result<Block> {
+        self.call(|node| node.get_block(hash)).await
+    }
+
+    async fn estimate_smart_fees(&self, targets: &[u32]) -> Result<Vec<Option<f64>>> {
+        self.call(|node| node.estimate_smart_fees(targets)).await
+    }
+
+    async fn get_peer_fee_filters(&self) -> Result<Vec<f64>> {
+        self.call(|node| node.get_peer_fee_filters()).await
+    }
+
+    fn source(&self) -> Option<String> {
+        Some(self.active().to_string())
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{
+    calc::{Calc, TARGETS},
+    dataset::{FileKind, SnapshotFile},
+    record::{Record, FEE_BANDS},
+    replay::Replay,
+    storage::Storage,
+    template::Template,
+};
+use anyhow::{bail, Result};
+use chrono::{Datelike, TimeZone, Timelike, Utc};
+use polars::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    f64::consts::TAU,
+};
+use tracing::info;
+
+/// Model inputs of one snapshot
+struct Row {
+    timestamp: i64,
+    height: u64,
+    mempool_txs: u64,
+    mempool_vsize: f64,
+    bands: [f64; FEE_BANDS.len()],
+    cutoffs: [f64; TARGETS.len()],
+    inflow_txs_per_min: Option<f64>,
+    inflow_vsize_per_min: Option<f64>,
+    secs_since_block: Option<i64>,
+    block_interval_secs: Option<i64>,
+    mempool_min_fee: Option<f64>,
+}
+
+/// Per-snapshot model inputs computed once, so training runs and outside tools read a table
+/// instead of replaying the dataset every time
+pub struct Features;
+
+impl Features {
+    /// One row per snapshot within `from..=to`, at least `every_secs` apart: `timestamp`,
+    /// `height`, `mempool_txs`, `mempool_vsize`, the virtual size in each fee rate band like
+    /// the meta files' `vsize_1_2`, the projected cutoff for each of [`TARGETS`] as `cutoff_1`
+    /// to `cutoff_144`, what arrived per minute since the previous row as
+    /// `inflow_txs_per_min` and `inflow_vsize_per_min`, `secs_since_block` and
+    /// `block_interval_secs` between the last two blocks, `mempool_min_fee_sat_vb` from the
+    /// meta file and the hour of the day and day of the week as sine and cosine pairs
+    #[tracing::instrument(skip(storage))]
+    pub fn extract(
+        storage: &dyn Storage,
+        from: i64,
+        to: i64,
+        every_secs: i64,
+    ) -> Result<DataFrame> {
+        if every_secs <= 0 {
+            bail!("samples must be at least a second apart");
+        }
+        let files = storage.list()?;
+        let blocks: Vec<i64> = files
+            .iter()
+            .filter(|f| f.kind == FileKind::Block)
+            .map(|f| f.timestamp)
+            .collect();
+        let metas: HashMap<i64, &SnapshotFile> = files
+            .iter()
+            .filter(|f| f.kind == FileKind::Meta)
+            .map(|f| (f.timestamp, f))
+            .collect();
+
+        let mut rows: Vec<Row> = Vec::new();
+        let mut previous: Option<(i64, HashSet<String>)> = None;
+        let mut next_sample = i64::MIN;
+        Replay::new(storage)?.walk(from, to, |snapshot| {
+            if snapshot.timestamp < next_sample {
+                return Ok(());
+            }
+            next_sample = snapshot.timestamp + every_secs;
+
+            let mut bands = [0.; FEE_BANDS.len()];
+            for tx in snapshot.transactions.values() {
+                let band = FEE_BANDS
+                    .partition_point(|bound| *bound <= tx.fee_rate_sat_vb())
+                    .max(1)
+                    - 1;
+                bands[band] += tx.weight / 4.;
+            }
+            let (inflow_txs_per_min, inflow_vsize_per_min) = match &previous {
+                Some((at, txids)) if snapshot.timestamp > *at => {
+                    let minutes = (snapshot.timestamp - at) as f64 / 60.;
+                    let arrived: Vec<f64> = snapshot
+                        .transactions
+                        .iter()
+                        .filter(|(txid, _)| !txids.contains(*txid))
+                        .map(|(_, tx)| tx.weight / 4.)
+                        .collect();
+                    (
+                        Some(arrived.len() as f64 / minutes),
+                        Some(arrived.iter().sum::<f64>() / minutes),
+                    )
+                }
+                _ => (None, None),
+            };
+            let last_block = blocks.partition_point(|at| *at <= snapshot.timestamp);
+            let mempool_min_fee = match metas.get(&snapshot.timestamp) {
+                Some(meta) => storage
+                    .read(meta)?
+                    .column("mempool_min_fee_sat_vb")
+                    .ok()
+                    .and_then(|column| column.f64().ok()?.get(0)),
+                None => None,
+            };
+            // packed once for all targets
+            let max_target = TARGETS.iter().copied().max().unwrap_or(1);
+            let template = Template::from_snapshot(snapshot, max_target as usize);
+            rows.push(Row {
+                timestamp: snapshot.timestamp,
+                height: snapshot.height,
+                mempool_txs: snapshot.transactions.len() as u64,
+                mempool_vsize: bands.iter().sum(),
+                bands,
+                cutoffs: TARGETS.map(|target| Calc::projected_cutoff(&template, target)),
+                inflow_txs_per_min,
+                inflow_vsize_per_min,
+                secs_since_block: last_block
+                    .checked_sub(1)
+                    .map(|last| snapshot.timestamp - blocks[last]),
+                block_interval_secs: last_block
+                    .checked_sub(2)
+                    .map(|before| blocks[before + 1] - blocks[before]),
+                mempool_min_fee,
+            });
+            previous = Some((
+                snapshot.timestamp,
+                snapshot.transactions.keys().cloned().collect(),
+            ));
+            Ok(())
+        })?;
+        if rows.is_empty() {
+            bail!("no snapshots recorded between {from} and {to}");
+        }
+        info!("rows: {}", rows.len());
+        Self::frame(&rows)
+    }
+
+    fn frame(rows: &[Row]) -> Result<DataFrame> {
+        let values = |value: &dyn Fn(&Row) -> f64| -> Vec<f64> { rows.iter().map(value).collect() };
+        let mut columns = vec![
+            Series::new(
+                "timestamp",
+                rows.iter().map(|r| r.timestamp).collect::<Vec<_>>(),
+            ),
+            Series::new("height", rows.iter().map(|r| r.height).collect::<Vec<_>>()),
+            Series::new(
+                "mempool_txs",
+                rows.iter().map(|r| r.mempool_txs).collect::<Vec<_>>(),
+            ),
+            Series::new("mempool_vsize", values(&|r| r.mempool_vsize)),
+        ];
+        for index in 0..FEE_BANDS.len() {
+            columns.push(Series::new(
+                &Record::fee_band_column(index),
+                values(&|r| r.bands[index]),
+            ));
+        }
+        for (index, target) in TARGETS.iter().enumerate() {
+            columns.push(Series::new(
+                &format!("cutoff_{target}"),
+                values(&|r| r.cutoffs[index]),
+            ));
+        }
+        columns.extend([
+            Series::new(
+                "inflow_txs_per_min",
+                rows.iter()
+                    .map(|r| r.inflow_txs_per_min)
+                    .collect::<Vec<_>>(),
+            ),
+            Series::new(
+                "inflow_vsize_per_min",
+                rows.iter()
+                    .map(|r| r.inflow_vsize_per_min)
+                    .collect::<Vec<_>>(),
+            ),
+            Series::new(
+                "secs_since_block",
+                rows.iter().map(|r| r.secs_since_block).collect::<Vec<_>>(),
+            ),
+            Series::new(
+                "block_interval_secs",
+                rows.iter()
+                    .map(|r| r.block_interval_secs)
+                    .collect::<Vec<_>>(),
+            ),
+            Series::new(
+                "mempool_min_fee_sat_vb",
+                rows.iter().map(|r| r.mempool_min_fee).collect::<Vec<_>>(),
+            ),
+        ]);
+        // cyclic, so 23:00 sits next to 00:00 and Sunday next to Monday
+        let hour = |r: &Row| {
+            let at = Utc.timestamp_opt(r.timestamp, 0).unwrap();
+            (at.hour() as f64 + at.minute() as f64 / 60.) / 24. * TAU
+        };
+        let weekday = |r: &Row| {
+            let at = Utc.timestamp_opt(r.timestamp, 0).unwrap();
+            at.weekday().num_days_from_monday() as f64 / 7. * TAU
+        };
+        columns.extend([
+            Series::new("hour_sin", values(&|r| hour(r).sin())),
+            Series::new("hour_cos", values(&|r| hour(r).cos())),
+            Series::new("weekday_sin", values(&|r| weekday(r).sin())),
+            Series::new("weekday_cos", values(&|r| weekday(r).cos())),
+        ]);
+        Ok(DataFrame::new(columns)?)
+    }
+}
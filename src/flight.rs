@@ -0,0 +1,270 @@
+use crate::{access::Access, grpc::Grpc, query::Query, serve::AppState, storage::Storage};
+use anyhow::{anyhow, Result};
+use arrow_flight::{
+    encode::FlightDataEncoderBuilder,
+    error::FlightError,
+    flight_descriptor::DescriptorType,
+    flight_service_server::{FlightService, FlightServiceServer},
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint, FlightInfo,
+    HandshakeRequest, HandshakeResponse, PollInfo, PutResult, SchemaAsIpc, SchemaResult, Ticket,
+};
+use arrow_ipc::{reader::FileReader, writer::IpcWriteOptions};
+use chrono::{NaiveDate, TimeZone, Utc};
+use futures_util::{stream, TryStreamExt};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{io::Cursor, net::SocketAddr, pin::Pin, sync::Arc};
+use tokio_stream::Stream;
+use tonic::{transport::Server, Request, Response, Status, Streaming};
+use tracing::{debug, info};
+
+type FlightStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// What a ticket or a command descriptor asks for, as JSON: a table of `wtf query`, only the
+/// rows of files recorded within `from..=to`, on the UTC `date` (`YYYY-MM-DD`) or within
+/// `min_height..=max_height` if given
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Slice {
+    pub table: String,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub date: Option<String>,
+    pub min_height: Option<u64>,
+    pub max_height: Option<u64>,
+}
+
+/// The recorded dataset over Arrow Flight, for notebooks and BI tools to stream slices of
+/// without the files. A flight per table of `wtf query`, `list_flights` names them and
+/// `do_get` takes a [`Slice`] as ticket. Read only, behind the same API keys and rate limit
+/// as the HTTP API, and like gRPC plain HTTP/2 only.
+pub struct Flight {
+    state: Arc<AppState>,
+}
+
+impl Flight {
+    pub(crate) async fn serve(
+        state: Arc<AppState>,
+        access: Arc<Access>,
+        listen: SocketAddr,
+    ) -> Result<()> {
+        let service = FlightServiceServer::with_interceptor(Flight { state }, move |request| {
+            Grpc::admit(&access, request)
+        });
+        info!("Arrow Flight listening on {listen}");
+        Server::builder().add_service(service).serve(listen).await?;
+        Ok(())
+    }
+
+    /// The slice a command descriptor asks for, or the table of a path descriptor
+    fn slice(descriptor: &FlightDescriptor) -> Result<Slice, Status> {
+        match descriptor.r#type() {
+            DescriptorType::Cmd => Self::parse(&descriptor.cmd),
+            DescriptorType::Path => match descriptor.path.as_slice() {
+                [table] => Ok(Slice {
+                    table: table.clone(),
+                    ..Default::default()
+                }),
+                _ => Err(Status::invalid_argument("the path is a table name")),
+            },
+            DescriptorType::Unknown => Err(Status::invalid_argument("unknown descriptor type")),
+        }
+    }
+
+    fn parse(json: &[u8]) -> Result<Slice, Status> {
+        serde_json::from_slice(json)
+            .map_err(|e| Status::invalid_argument(format!("expected a slice as JSON: {e}")))
+    }
+
+    /// The rows of `slice`, at most `limit` of them
+    fn read(storage: &dyn Storage, slice: &Slice, limit: Option<u32>) -> Result<DataFrame> {
+        let mut context = Query::context(storage)?;
+        if !context.get_tables().contains(&slice.table) {
+            return Err(anyhow!("no table {}", slice.table));
+        }
+        let mut frame = context.execute(&format!("SELECT * FROM {}", slice.table))?;
+        let (mut from, mut to) = (slice.from, slice.to);
+        if let Some(date) = &slice.date {
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")?;
+            let start = Utc
+                .from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+                .timestamp();
+            from = Some(from.map_or(start, |from| from.max(start)));
+            to = Some(to.map_or(start + 86_399, |to| to.min(start + 86_399)));
+        }
+        if let Some(from) = from {
+            frame = frame.filter(col("snapshot_timestamp").gt_eq(lit(from)));
+        }
+        if let Some(to) = to {
+            frame = frame.filter(col("snapshot_timestamp").lt_eq(lit(to)));
+        }
+        if let Some(min_height) = slice.min_height {
+            frame = frame.filter(col("height").gt_eq(lit(min_height)));
+        }
+        if let Some(max_height) = slice.max_height {
+            frame = frame.filter(col("height").lt_eq(lit(max_height)));
+        }
+        if let Some(limit) = limit {
+            frame = frame.limit(limit);
+        }
+        Ok(frame.collect()?)
+    }
+
+    /// `frame` in Arrow IPC, what Flight clients read
+    fn ipc(mut frame: DataFrame) -> Result<FileReader<Cursor<Vec<u8>>>> {
+        let mut bytes = Vec::new();
+        IpcWriter::new(&mut bytes).finish(&mut frame)?;
+        Ok(FileReader::try_new(Cursor::new(bytes), None)?)
+    }
+
+    /// Description of the flight of `slice`, fetched with the slice as ticket
+    fn info(storage: &dyn Storage, slice: &Slice) -> Result<FlightInfo> {
+        let reader = Self::ipc(Self::read(storage, slice, Some(0))?)?;
+        let ticket = serde_json::to_vec(slice)?;
+        Ok(FlightInfo::new()
+            .try_with_schema(reader.schema().as_ref())?
+            .with_descriptor(FlightDescriptor::new_cmd(ticket.clone()))
+            .with_endpoint(FlightEndpoint::new().with_ticket(Ticket::new(ticket))))
+    }
+
+    /// Run `work` on the dataset off the async workers, it reads parquet files
+    async fn blocking<T: Send + 'static>(
+        &self,
+        work: impl FnOnce(&dyn Storage) -> Result<T> + Send + 'static,
+    ) -> Result<T, Status> {
+        let state = self.state.clone();
+        tokio::task::spawn_blocking(move || work(state.storage.as_ref()))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(|e| Status::not_found(format!("{e:#}")))
+    }
+}
+
+#[tonic::async_trait]
+impl FlightService for Flight {
+    type HandshakeStream = FlightStream<HandshakeResponse>;
+    type ListFlightsStream = FlightStream<FlightInfo>;
+    type DoGetStream = FlightStream<FlightData>;
+    type DoPutStream = FlightStream<PutResult>;
+    type DoExchangeStream = FlightStream<FlightData>;
+    type DoActionStream = FlightStream<arrow_flight::Result>;
+    type ListActionsStream = FlightStream<ActionType>;
+
+    /// API keys go in the headers of every call, there is nothing to exchange
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        let response = HandshakeResponse::default();
+        Ok(Response::new(Box::pin(stream::once(async {
+            Ok::<_, Status>(response)
+        }))))
+    }
+
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        let flights = self
+            .blocking(|storage| {
+                let tables = Query::context(storage)?.get_tables();
+                tables
+                    .into_iter()
+                    .map(|table| {
+                        let slice = Slice {
+                            table,
+                            ..Default::default()
+                        };
+                        Self::info(storage, &slice)
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .await?;
+        Ok(Response::new(Box::pin(stream::iter(
+            flights.into_iter().map(Ok::<_, Status>),
+        ))))
+    }
+
+    async fn get_flight_info(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let slice = Self::slice(request.get_ref())?;
+        let info = self
+            .blocking(move |storage| Self::info(storage, &slice))
+            .await?;
+        Ok(Response::new(info))
+    }
+
+    async fn poll_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<PollInfo>, Status> {
+        Err(Status::unimplemented(
+            "flights are ready right away, use get_flight_info",
+        ))
+    }
+
+    async fn get_schema(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        let slice = Self::slice(request.get_ref())?;
+        let reader = self
+            .blocking(move |storage| Self::ipc(Self::read(storage, &slice, Some(0))?))
+            .await?;
+        let schema: SchemaResult =
+            SchemaAsIpc::new(reader.schema().as_ref(), &IpcWriteOptions::default())
+                .try_into()
+                .map_err(|e| Status::internal(format!("{e}")))?;
+        Ok(Response::new(schema))
+    }
+
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        let slice = Self::parse(&request.get_ref().ticket)?;
+        debug!("do_get {slice:?}");
+        let batches = self
+            .blocking(move |storage| {
+                Ok(Self::ipc(Self::read(storage, &slice, None)?)?.collect::<Vec<_>>())
+            })
+            .await?;
+        let batches = stream::iter(batches).map_err(FlightError::from);
+        let data = FlightDataEncoderBuilder::new()
+            .build(batches)
+            .map_err(Status::from);
+        Ok(Response::new(Box::pin(data)))
+    }
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(Status::unimplemented("the dataset is read only"))
+    }
+
+    async fn do_exchange(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(Status::unimplemented("the dataset is read only"))
+    }
+
+    async fn do_action(
+        &self,
+        _request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented("no actions"))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Ok(Response::new(Box::pin(stream::empty::<
+            Result<ActionType, Status>,
+        >())))
+    }
+}
@@ -0,0 +1,183 @@
+use crate::{
+    access::{Access, Denied},
+    histogram::Histogram,
+    replay::Replay,
+    serve::{AppState, Serve, StreamEstimate},
+};
+use anyhow::Result;
+use std::{net::SocketAddr, pin::Pin, sync::Arc, time::Duration};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tonic::{transport::Server, Request, Response, Status};
+use tracing::{debug, info};
+
+/// Generated from `proto/wtf.proto`
+pub mod proto {
+    tonic::include_proto!("wtf.v1");
+}
+
+use proto::fee_estimator_server::{FeeEstimator, FeeEstimatorServer};
+
+/// The fee estimates of `serve` over gRPC, for clients that would rather stream than poll.
+/// Plain HTTP/2 only, `--tls-cert` covers the HTTP API but not this.
+pub struct Grpc {
+    state: Arc<AppState>,
+}
+
+impl Grpc {
+    pub(crate) async fn serve(
+        state: Arc<AppState>,
+        access: Arc<Access>,
+        listen: SocketAddr,
+    ) -> Result<()> {
+        let service = FeeEstimatorServer::with_interceptor(Grpc { state }, move |request| {
+            Self::admit(&access, request)
+        });
+        info!("gRPC listening on {listen}");
+        Server::builder().add_service(service).serve(listen).await?;
+        Ok(())
+    }
+
+    /// The same API keys and rate limit as the HTTP API, the key in `authorization: Bearer KEY`
+    /// or `x-api-key: KEY` metadata
+    pub(crate) fn admit(access: &Access, request: Request<()>) -> Result<Request<()>, Status> {
+        if access.is_open() {
+            return Ok(request);
+        }
+        let Some(address) = request.remote_addr() else {
+            return Err(Status::internal("the client's address is unknown"));
+        };
+        let metadata = request.metadata();
+        let bearer = metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let header_key = metadata
+            .get("x-api-key")
+            .and_then(|value| value.to_str().ok());
+        match access.check(bearer.or(header_key), address) {
+            Ok(_) => Ok(request),
+            Err(Denied::NoKey) => {
+                debug!("{address}: no valid API key");
+                Err(Status::unauthenticated("a valid API key is required"))
+            }
+            Err(Denied::RateLimited(retry_after)) => {
+                debug!("{address}: rate limited for {retry_after} s");
+                Err(Status::resource_exhausted(format!(
+                    "too many requests, retry in {retry_after} s"
+                )))
+            }
+        }
+    }
+
+    /// Confidence and target of a request, the HTTP API's defaults when unset
+    fn query(confidence: Option<f64>, target: Option<u32>) -> Result<(f64, u32), Status> {
+        let (confidence, target) = (confidence.unwrap_or(0.95), target.unwrap_or(1));
+        if !(confidence > 0. && confidence <= 1.) || target == 0 {
+            return Err(Status::invalid_argument(
+                "confidence must be in (0, 1] and target at least 1",
+            ));
+        }
+        Ok((confidence, target))
+    }
+}
+
+impl From<StreamEstimate> for proto::FeeEstimate {
+    fn from(estimate: StreamEstimate) -> Self {
+        proto::FeeEstimate {
+            timestamp: estimate.timestamp,
+            confidence: estimate.confidence,
+            target: estimate.target,
+            fee_rate_sat_vb: estimate.fee_rate_sat_vb,
+        }
+    }
+}
+
+impl From<Histogram> for proto::Histogram {
+    fn from(histogram: Histogram) -> Self {
+        proto::Histogram {
+            height: histogram.height,
+            timestamp: histogram.timestamp,
+            txs: histogram.txs as u64,
+            vsize: histogram.vsize,
+            buckets: histogram
+                .buckets
+                .into_iter()
+                .map(|bucket| proto::Bucket {
+                    fee_rate: bucket.fee_rate,
+                    txs: bucket.txs as u64,
+                    vsize: bucket.vsize,
+                    cumulative_vsize: bucket.cumulative_vsize,
+                    block: bucket.block,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl FeeEstimator for Grpc {
+    type StreamFeesStream = Pin<Box<dyn Stream<Item = Result<proto::FeeEstimate, Status>> + Send>>;
+
+    async fn get_fee(
+        &self,
+        request: Request<proto::FeeRequest>,
+    ) -> Result<Response<proto::FeeEstimate>, Status> {
+        let request = request.into_inner();
+        let (confidence, target) = Self::query(request.confidence, request.target)?;
+        let state = self.state.clone();
+        // estimation reads parquet files, keep it off the async workers
+        let estimate = tokio::task::spawn_blocking(move || -> Result<StreamEstimate> {
+            let timestamp = Replay::new(state.storage.as_ref())?
+                .latest()
+                .unwrap_or_default();
+            Ok(StreamEstimate {
+                timestamp,
+                confidence,
+                target,
+                fee_rate_sat_vb: state.estimate(confidence, target)?,
+            })
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(|e| Status::unavailable(e.to_string()))?;
+        Ok(Response::new(estimate.into()))
+    }
+
+    async fn stream_fees(
+        &self,
+        request: Request<proto::StreamFeesRequest>,
+    ) -> Result<Response<Self::StreamFeesStream>, Status> {
+        let request = request.into_inner();
+        let (confidence, target) = Self::query(request.confidence, request.target)?;
+        let every = request.every_secs.map(Duration::from_secs);
+        let mut receiver = Serve::estimates(self.state.clone(), confidence, target, every);
+        let (sender, estimates) = mpsc::channel(1);
+        tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                // the first error ends the stream, the client reconnects rather than waiting
+                let end = message.is_err();
+                let message = message
+                    .map(proto::FeeEstimate::from)
+                    .map_err(Status::unavailable);
+                if sender.send(message).await.is_err() || end {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(estimates))))
+    }
+
+    async fn get_histogram(
+        &self,
+        _request: Request<proto::HistogramRequest>,
+    ) -> Result<Response<proto::Histogram>, Status> {
+        let state = self.state.clone();
+        let histogram =
+            tokio::task::spawn_blocking(move || Histogram::from_recorded(state.storage.as_ref()))
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?
+                .map_err(|e| Status::unavailable(e.to_string()))?;
+        Ok(Response::new(histogram.into()))
+    }
+}
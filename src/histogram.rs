@@ -0,0 +1,137 @@
+use crate::{
+    node::Node,
+    replay::{Replay, Snapshot},
+    storage::Storage,
+};
+use anyhow::Result;
+use bitcoin::Denomination;
+use polars::prelude::{DataFrame, NamedFrom, Series};
+use serde::Serialize;
+
+/// Lower bounds of the buckets in sat/vB, the fee ranges mempool.space shows
+pub const BUCKETS: [f64; 39] = [
+    0., 1., 2., 3., 4., 5., 6., 8., 10., 12., 15., 20., 30., 40., 50., 60., 70., 80., 90., 100.,
+    125., 150., 175., 200., 250., 300., 350., 400., 500., 600., 700., 800., 900., 1000., 1200.,
+    1400., 1600., 1800., 2000.,
+];
+/// Virtual size of a full block
+const BLOCK_VSIZE: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Bucket {
+    /// Lowest fee rate in the bucket, sat/vB
+    pub fee_rate: f64,
+    pub txs: usize,
+    pub vsize: u64,
+    /// Virtual size of this bucket and all higher ones, what gets mined before anything below
+    pub cumulative_vsize: u64,
+    /// Projected block the end of the bucket lands in, 1 for the next one
+    pub block: u64,
+}
+
+/// Fee rate histogram of a mempool
+#[derive(Debug, Clone, Serialize)]
+pub struct Histogram {
+    pub height: u64,
+    /// Unix timestamp of the mempool
+    pub timestamp: i64,
+    /// `node` or `recorded`
+    pub source: &'static str,
+    pub txs: usize,
+    pub vsize: u64,
+    /// Highest fee rate first, empty buckets left out
+    pub buckets: Vec<Bucket>,
+}
+
+impl Histogram {
+    /// Bucket transactions given as `(fee rate in sat/vB, vsize)`
+    pub fn new(
+        height: u64,
+        timestamp: i64,
+        source: &'static str,
+        transactions: impl IntoIterator<Item = (f64, u64)>,
+    ) -> Self {
+        let mut counts = [(0usize, 0u64); BUCKETS.len()];
+        for (fee_rate, vsize) in transactions {
+            let bucket = BUCKETS.partition_point(|bound| *bound <= fee_rate).max(1) - 1;
+            counts[bucket].0 += 1;
+            counts[bucket].1 += vsize;
+        }
+
+        let mut buckets = Vec::new();
+        let mut cumulative_vsize = 0;
+        for (fee_rate, (txs, vsize)) in BUCKETS.iter().zip(counts).rev() {
+            if txs == 0 {
+                continue;
+            }
+            cumulative_vsize += vsize;
+            buckets.push(Bucket {
+                fee_rate: *fee_rate,
+                txs,
+                vsize,
+                cumulative_vsize,
+                block: cumulative_vsize.div_ceil(BLOCK_VSIZE),
+            });
+        }
+        Histogram {
+            height,
+            timestamp,
+            source,
+            txs: buckets.iter().map(|b| b.txs).sum(),
+            vsize: cumulative_vsize,
+            buckets,
+        }
+    }
+
+    /// The buckets as rows of a histogram file, as `record --aggregate` writes them
+    pub fn to_frame(&self) -> DataFrame {
+        let buckets = &self.buckets;
+        DataFrame::new(vec![
+            Series::new(
+                "fee_rate_sat_vb",
+                buckets.iter().map(|b| b.fee_rate).collect::<Vec<_>>(),
+            ),
+            Series::new(
+                "txs",
+                buckets.iter().map(|b| b.txs as u64).collect::<Vec<_>>(),
+            ),
+            Series::new("vsize", buckets.iter().map(|b| b.vsize).collect::<Vec<_>>()),
+        ])
+        .unwrap()
+    }
+
+    /// The node's mempool right now
+    pub async fn from_node(node: &dyn Node) -> Result<Self> {
+        let height = node.get_chain_info().await?.blocks;
+        let mempool = node.get_mempool().await?;
+        let transactions = mempool.values().map(|entry| {
+            let fee = entry.fees.base.to_float_in(Denomination::Satoshi);
+            (fee / entry.vsize.max(1) as f64, entry.vsize)
+        });
+        Ok(Self::new(
+            height,
+            chrono::Utc::now().timestamp(),
+            "node",
+            transactions,
+        ))
+    }
+
+    /// The mempool of the latest recorded snapshot
+    pub fn from_recorded(storage: &dyn Storage) -> Result<Self> {
+        Ok(Self::from_snapshot(&Replay::new(storage)?.at(i64::MAX)?))
+    }
+
+    /// A recorded mempool
+    pub fn from_snapshot(snapshot: &Snapshot) -> Self {
+        let transactions = snapshot
+            .transactions
+            .values()
+            .map(|tx| (tx.fee_rate_sat_vb(), (tx.weight / 4.).ceil() as u64));
+        Self::new(
+            snapshot.height,
+            snapshot.timestamp,
+            "recorded",
+            transactions,
+        )
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{
+    calc::Calc,
+    record::{Record, FEE_BANDS},
+    replay::Replay,
+    stats::PERCENTILES,
+    storage::Storage,
+};
+use anyhow::{bail, Result};
+use serde::Serialize;
+use tracing::info;
+
+/// Buckets answered at most, 7 days at 5 minutes fit
+pub const MAX_BUCKETS: i64 = 4096;
+
+/// The mempool during one bucket of time, from the first snapshot taken in it
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct HistoryBucket {
+    /// Unix timestamp the bucket starts at
+    pub start: i64,
+    /// Of the snapshot sampled
+    pub timestamp: i64,
+    pub height: u64,
+    pub mempool_txs: usize,
+    pub mempool_vsize: u64,
+    /// sat/vB at each of the percentiles, `None` for an empty mempool
+    pub fee_rates: Option<Vec<f64>>,
+    /// Virtual size in each fee rate band
+    pub depth_vsize: Vec<u64>,
+}
+
+/// Fee rates and mempool depth over time, for charts
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct FeeHistory {
+    pub from: i64,
+    pub to: i64,
+    pub resolution_secs: i64,
+    /// Of the transactions in the mempool, in (0, 1]
+    pub percentiles: Vec<f64>,
+    /// Lower bounds of the bands `depth_vsize` is split into, sat/vB
+    pub bands: Vec<f64>,
+    /// Like the meta files' `vsize_1_2`, a name for each band
+    pub band_names: Vec<String>,
+    /// Oldest first, buckets without a snapshot left out
+    pub buckets: Vec<HistoryBucket>,
+}
+
+pub struct History;
+
+impl History {
+    /// Sample the snapshots within `from..=to` once every `resolution_secs`, buckets aligned to
+    /// multiples of it: the fee rates at [`PERCENTILES`] of the transactions and the virtual
+    /// size in each of [`FEE_BANDS`]
+    #[tracing::instrument(skip(storage))]
+    pub fn buckets(
+        storage: &dyn Storage,
+        from: i64,
+        to: i64,
+        resolution_secs: i64,
+    ) -> Result<FeeHistory> {
+        Self::check(from, to, resolution_secs)?;
+        let mut buckets: Vec<HistoryBucket> = Vec::new();
+        Replay::new(storage)?.walk(from, to, |snapshot| {
+            let start = snapshot.timestamp - snapshot.timestamp.rem_euclid(resolution_secs);
+            if buckets.last().is_some_and(|last| last.start == start) {
+                return Ok(());
+            }
+            let mut fee_rates = Vec::with_capacity(snapshot.transactions.len());
+            let mut depth_vsize = vec![0; FEE_BANDS.len()];
+            let mut mempool_vsize = 0;
+            for tx in snapshot.transactions.values() {
+                let vsize = (tx.weight / 4.).ceil() as u64;
+                let fee_rate = tx.fee_rate_sat_vb();
+                let band = FEE_BANDS.partition_point(|bound| *bound <= fee_rate).max(1) - 1;
+                depth_vsize[band] += vsize;
+                mempool_vsize += vsize;
+                fee_rates.push(fee_rate);
+            }
+            fee_rates.sort_by(f64::total_cmp);
+            buckets.push(HistoryBucket {
+                start,
+                timestamp: snapshot.timestamp,
+                height: snapshot.height,
+                mempool_txs: snapshot.transactions.len(),
+                mempool_vsize,
+                fee_rates: (!fee_rates.is_empty()).then(|| {
+                    PERCENTILES
+                        .iter()
+                        .map(|percentile| Calc::quantile(&fee_rates, *percentile))
+                        .collect()
+                }),
+                depth_vsize,
+            });
+            Ok(())
+        })?;
+        info!("buckets: {}", buckets.len());
+
+        Ok(FeeHistory {
+            from,
+            to,
+            resolution_secs,
+            percentiles: PERCENTILES.to_vec(),
+            bands: FEE_BANDS.to_vec(),
+            band_names: (0..FEE_BANDS.len()).map(Record::fee_band_column).collect(),
+            buckets,
+        })
+    }
+
+    /// Fail unless `from..=to` splits into at most [`MAX_BUCKETS`] of `resolution_secs`
+    pub fn check(from: i64, to: i64, resolution_secs: i64) -> Result<()> {
+        if resolution_secs <= 0 {
+            bail!("resolution must be at least one second");
+        }
+        if from > to {
+            bail!("from must not be after to");
+        }
+        if (to - from) / resolution_secs >= MAX_BUCKETS {
+            bail!("at most {MAX_BUCKETS} buckets, choose a coarser resolution or a shorter range");
+        }
+        Ok(())
+    }
+
+    /// Seconds of a resolution like `30s`, `5m`, `1h` or `1d`, plain numbers are seconds
+    pub fn parse_resolution(resolution: &str) -> Result<i64> {
+        let resolution = resolution.trim();
+        let (number, unit) = match resolution.find(|c: char| !c.is_ascii_digit()) {
+            Some(index) => resolution.split_at(index),
+            None => (resolution, "s"),
+        };
+        let multiplier = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => bail!("unknown unit in resolution {resolution}, use s, m, h or d"),
+        };
+        let Ok(number) = number.parse::<i64>() else {
+            bail!("resolution {resolution} isn't a number of s, m, h or d");
+        };
+        match number.checked_mul(multiplier) {
+            Some(secs) if secs > 0 => Ok(secs),
+            _ => bail!("resolution must be at least one second and fit in a timestamp"),
+        }
+    }
+}
@@ -0,0 +1,547 @@
+use crate::{
+    dataset::FileKind,
+    histogram::BUCKETS,
+    node::Node,
+    record::{Mempool, MempoolEntry, Record},
+    score::Score,
+    storage::Storage,
+};
+use anyhow::{bail, Context, Result};
+use bitcoin::{
+    consensus::{encode::VarInt, Decodable},
+    Amount, Transaction, Txid,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use polars::prelude::*;
+use reqwest::{header::RETRY_AFTER, Client, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Cursor,
+    path::Path,
+    time::Duration,
+};
+use tracing::{debug, info, warn};
+
+/// mempool.space's public API, self-hosted instances serve the same below their own `/api`
+pub const MEMPOOL_SPACE_API: &str = "https://mempool.space/api";
+/// Periods mempool.space keeps statistics for, the longer the fewer there are a day
+pub const STATISTICS_PERIODS: [&str; 9] = ["2h", "24h", "1w", "1m", "3m", "6m", "1y", "2y", "3y"];
+/// `/v1/blocks/{height}` answers with the block at the height and the ones below it
+const BLOCKS_PER_PAGE: u64 = 15;
+/// Tries of a rate limited request before giving up
+const RATE_LIMITED_ATTEMPTS: u32 = 5;
+
+/// Written before Bitcoin Core 28.0
+const VERSION_NO_XOR_KEY: u64 = 1;
+/// The rest of the file is XORed with a key that follows the version
+const VERSION_XOR_KEY: u64 = 2;
+
+/// A transaction of `mempool.dat`
+#[derive(Debug, Clone)]
+pub struct DatEntry {
+    pub tx: Transaction,
+    /// Unix time it entered the node's mempool
+    pub time: i64,
+    /// sat added to its fee by `prioritisetransaction`
+    pub fee_delta: i64,
+}
+
+/// The mempool Bitcoin Core persists on shutdown, `mempool.dat` in its data directory
+#[derive(Debug, Clone)]
+pub struct MempoolDat {
+    pub entries: Vec<DatEntry>,
+    /// Transactions submitted locally and not yet announced to a peer
+    pub unbroadcast: HashSet<Txid>,
+}
+
+/// What `wtf import` wrote
+#[derive(Debug, Serialize)]
+pub struct Imported {
+    pub height: u64,
+    pub timestamp: i64,
+    pub txs: usize,
+    /// Transactions left out because the value of an input couldn't be found
+    pub skipped: usize,
+}
+
+/// What `wtf import mempool-space` wrote
+#[derive(Debug, Default, Serialize)]
+pub struct Backfill {
+    pub blocks: usize,
+    pub histograms: usize,
+    /// Imported by an earlier run already
+    pub skipped: usize,
+}
+
+/// A mempool.space statistic, the mempool's virtual size by fee rate at a time
+#[derive(Debug, Deserialize)]
+struct Statistic {
+    added: i64,
+    /// By the upper bound of the buckets of `wtf histogram`, the last one is everything from
+    /// 1800 sat/vB up
+    vsizes: Vec<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockExtras {
+    /// sat/vB: the lowest, the 10th, 25th, 50th, 75th and 90th percentile and the highest
+    fee_range: Vec<f64>,
+    median_fee: f64,
+    total_fees: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExplorerBlock {
+    id: String,
+    height: u64,
+    timestamp: i64,
+    tx_count: u64,
+    weight: u64,
+    extras: BlockExtras,
+}
+
+/// Client for mempool.space's API, waiting `delay` before every request to stay below its
+/// rate limit
+pub struct MempoolSpace {
+    client: Client,
+    endpoint: String,
+    delay: Duration,
+}
+
+impl MempoolSpace {
+    /// `endpoint` is the root of the API like [`MEMPOOL_SPACE_API`]
+    pub fn new(endpoint: impl Into<String>, client: Client, delay: Duration) -> Self {
+        MempoolSpace {
+            client,
+            endpoint: endpoint.into().trim_end_matches('/').to_string(),
+            delay,
+        }
+    }
+
+    /// GET `path` below the API root, retrying when rate limited as long as it says to wait
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let url = format!("{}/{path}", self.endpoint);
+        let mut wait = self.delay;
+        for _ in 0..RATE_LIMITED_ATTEMPTS {
+            tokio::time::sleep(self.delay).await;
+            let response = self
+                .client
+                .get(&url)
+                .send()
+                .await
+                .with_context(|| format!("GET {url}"))?;
+            if response.status() != StatusCode::TOO_MANY_REQUESTS {
+                let response = response.error_for_status()?;
+                return response
+                    .json()
+                    .await
+                    .with_context(|| format!("decoding {url}"));
+            }
+            wait = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok()?.parse().ok())
+                .map_or(wait * 2, Duration::from_secs);
+            warn!("rate limited by {}, waiting {wait:?}", self.endpoint);
+            tokio::time::sleep(wait).await;
+        }
+        bail!("{url} is still rate limited after {RATE_LIMITED_ATTEMPTS} tries")
+    }
+}
+
+impl MempoolDat {
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::parse(bytes).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    pub fn parse(mut bytes: Vec<u8>) -> Result<Self> {
+        let mut cursor = Cursor::new(bytes.as_slice());
+        let version = u64::consensus_decode(&mut cursor)?;
+        match version {
+            VERSION_NO_XOR_KEY => {}
+            VERSION_XOR_KEY => {
+                let key = Vec::<u8>::consensus_decode(&mut cursor)?;
+                let start = cursor.position() as usize;
+                // the key lines up with the position in the file, not after it
+                if !key.is_empty() {
+                    for (position, byte) in bytes.iter_mut().enumerate().skip(start) {
+                        *byte ^= key[position % key.len()];
+                    }
+                }
+                cursor = Cursor::new(bytes.as_slice());
+                cursor.set_position(start as u64);
+            }
+            _ => bail!("unsupported mempool.dat version {version}"),
+        }
+
+        let count = u64::consensus_decode(&mut cursor)?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            entries.push(DatEntry {
+                tx: Transaction::consensus_decode(&mut cursor)?,
+                time: i64::consensus_decode(&mut cursor)?,
+                fee_delta: i64::consensus_decode(&mut cursor)?,
+            });
+        }
+        // fee deltas of transactions that weren't in the mempool, there is nothing to import
+        for _ in 0..VarInt::consensus_decode(&mut cursor)?.0 {
+            Txid::consensus_decode(&mut cursor)?;
+            i64::consensus_decode(&mut cursor)?;
+        }
+        // older versions end before the unbroadcast set
+        let mut unbroadcast = HashSet::new();
+        if (cursor.position() as usize) < bytes.len() {
+            for _ in 0..VarInt::consensus_decode(&mut cursor)?.0 {
+                unbroadcast.insert(Txid::consensus_decode(&mut cursor)?);
+            }
+        }
+        Ok(MempoolDat {
+            entries,
+            unbroadcast,
+        })
+    }
+}
+
+/// Bootstraps a dataset from what was seen before recording started
+pub struct Import;
+
+impl Import {
+    /// Write the transactions of `dat` as a full snapshot taken `at`, at the node's current
+    /// height. `mempool.dat` has no fees, they come from the other transactions in it, the
+    /// node's mempool or its transaction index, and transactions without them are left out.
+    pub async fn mempool_dat(
+        storage: &dyn Storage,
+        node: &dyn Node,
+        dat: &MempoolDat,
+        at: DateTime<Utc>,
+    ) -> Result<Imported> {
+        let chain_info = node.get_chain_info().await?;
+        let txids: Vec<Txid> = dat.entries.iter().map(|entry| entry.tx.txid()).collect();
+        let fees = Self::fees(node, dat, &txids).await?;
+
+        let mut mempool: Mempool = dat
+            .entries
+            .iter()
+            .zip(&txids)
+            .filter_map(|(entry, txid)| {
+                let fee = *fees.get(txid)?;
+                let vsize = entry.tx.vsize() as u64;
+                let modified_fee = Amount::from_sat(fee.saturating_add_signed(entry.fee_delta));
+                let fee = Amount::from_sat(fee);
+                let entry = MempoolEntry {
+                    wtxid: entry.tx.wtxid(),
+                    vsize,
+                    weight: entry.tx.weight().to_wu(),
+                    time: entry.time.max(0) as u64,
+                    height: chain_info.blocks,
+                    fee,
+                    modified_fee,
+                    ancestor_fees: modified_fee,
+                    descendant_fees: modified_fee,
+                    ancestor_count: 1,
+                    descendant_count: 1,
+                    descendant_size: vsize,
+                    bip125_replaceable: entry.tx.is_explicitly_rbf(),
+                    unbroadcast: Some(dat.unbroadcast.contains(txid)),
+                    depends: entry
+                        .tx
+                        .input
+                        .iter()
+                        .map(|input| input.previous_output.txid)
+                        .filter(|parent| fees.contains_key(parent))
+                        .collect::<HashSet<_>>()
+                        .into_iter()
+                        .collect(),
+                    spent_by: Box::new([]),
+                };
+                Some((*txid, entry))
+            })
+            .collect();
+        Self::link(&mut mempool);
+
+        let packages = Score::packages(&mempool);
+        let mut full = Record::create_full(
+            &mempool,
+            &chain_info.best_block_hash.to_string(),
+            &Score::fee_rates(&packages),
+            &Score::queue_percentiles(&mempool, &packages),
+        );
+        storage.write(at, chain_info.blocks, FileKind::Full, &mut full)?;
+        let imported = Imported {
+            height: chain_info.blocks,
+            timestamp: at.timestamp(),
+            txs: mempool.len(),
+            skipped: dat.entries.len() - mempool.len(),
+        };
+        info!(
+            "imported {} transactions from mempool.dat, {} without a fee",
+            imported.txs, imported.skipped
+        );
+        Ok(imported)
+    }
+
+    /// sat paid by each transaction that has all its inputs in the file, the node's mempool or
+    /// the node's transaction index
+    async fn fees(node: &dyn Node, dat: &MempoolDat, txids: &[Txid]) -> Result<HashMap<Txid, u64>> {
+        // still in the mempool, the node knows best
+        let mut fees: HashMap<Txid, u64> = node
+            .get_mempool_entries(txids)
+            .await?
+            .into_iter()
+            .map(|(txid, entry)| (txid, entry.fees.base.to_sat()))
+            .collect();
+
+        let mut transactions: HashMap<Txid, &Transaction> = dat
+            .entries
+            .iter()
+            .zip(txids)
+            .map(|(entry, txid)| (*txid, &entry.tx))
+            .collect();
+        let missing: HashSet<Txid> = dat
+            .entries
+            .iter()
+            .zip(txids)
+            .filter(|(_, txid)| !fees.contains_key(*txid))
+            .flat_map(|(entry, _)| {
+                entry
+                    .tx
+                    .input
+                    .iter()
+                    .map(|input| input.previous_output.txid)
+            })
+            .filter(|parent| !transactions.contains_key(parent))
+            .collect();
+        let missing: Vec<Txid> = missing.into_iter().collect();
+        let parents = node.get_transactions(&missing).await?;
+        debug!("looked up {} of {} parents", parents.len(), missing.len());
+        transactions.extend(parents.iter().map(|(txid, tx)| (*txid, tx)));
+
+        for (entry, txid) in dat.entries.iter().zip(txids) {
+            if fees.contains_key(txid) {
+                continue;
+            }
+            let inputs: Option<u64> = entry
+                .tx
+                .input
+                .iter()
+                .map(|input| {
+                    let outpoint = input.previous_output;
+                    let parent = transactions.get(&outpoint.txid)?;
+                    Some(parent.output.get(outpoint.vout as usize)?.value)
+                })
+                .sum();
+            let outputs: u64 = entry.tx.output.iter().map(|output| output.value).sum();
+            if let Some(fee) = inputs.and_then(|inputs| inputs.checked_sub(outputs)) {
+                fees.insert(*txid, fee);
+            }
+        }
+        Ok(fees)
+    }
+
+    /// Fill in children and the ancestor and descendant totals from the dependencies
+    pub(crate) fn link(mempool: &mut Mempool) {
+        let mut children: HashMap<Txid, Vec<Txid>> = HashMap::new();
+        for (txid, entry) in mempool.iter() {
+            for parent in entry.depends.iter() {
+                children.entry(*parent).or_default().push(*txid);
+            }
+        }
+        let related = |start: &Txid, next: &dyn Fn(&Txid) -> Vec<Txid>| {
+            let mut seen = HashSet::new();
+            let mut stack = next(start);
+            while let Some(txid) = stack.pop() {
+                if seen.insert(txid) {
+                    stack.extend(next(&txid));
+                }
+            }
+            seen
+        };
+        let parents_of = |txid: &Txid| mempool[txid].depends.to_vec();
+        let children_of = |txid: &Txid| children.get(txid).cloned().unwrap_or_default();
+
+        let mut totals = HashMap::with_capacity(mempool.len());
+        for txid in mempool.keys() {
+            let ancestors = related(txid, &parents_of);
+            let descendants = related(txid, &children_of);
+            let sum = |txids: &HashSet<Txid>| {
+                txids
+                    .iter()
+                    .map(|txid| mempool[txid].modified_fee)
+                    .sum::<Amount>()
+            };
+            let replaceable = ancestors
+                .iter()
+                .any(|txid| mempool[txid].bip125_replaceable);
+            totals.insert(
+                *txid,
+                (
+                    ancestors.len() as u32,
+                    sum(&ancestors),
+                    descendants.len() as u32,
+                    sum(&descendants),
+                    descendants
+                        .iter()
+                        .map(|txid| mempool[txid].vsize)
+                        .sum::<u64>(),
+                    replaceable,
+                ),
+            );
+        }
+        for (txid, entry) in mempool.iter_mut() {
+            let (
+                ancestors,
+                ancestor_fees,
+                descendants,
+                descendant_fees,
+                descendant_size,
+                replaceable,
+            ) = totals[txid];
+            // both include the transaction itself, like Core's
+            entry.ancestor_count += ancestors;
+            entry.ancestor_fees += ancestor_fees;
+            entry.descendant_count += descendants;
+            entry.descendant_fees += descendant_fees;
+            entry.descendant_size += descendant_size;
+            // signalling is inherited from unconfirmed ancestors
+            entry.bip125_replaceable |= replaceable;
+            entry.spent_by = children.remove(txid).unwrap_or_default().into();
+        }
+    }
+
+    /// Write the histograms mempool.space kept over `period` and the fees of the blocks since
+    /// the first of them, for calc and backtest to work with before there are recordings.
+    /// Files are written as they come in, another run resumes with what is still missing.
+    pub async fn mempool_space(
+        storage: &dyn Storage,
+        api: &MempoolSpace,
+        period: &str,
+    ) -> Result<Backfill> {
+        if !STATISTICS_PERIODS.contains(&period) {
+            bail!(
+                "no statistics for {period}, expected one of {}",
+                STATISTICS_PERIODS.join(", ")
+            );
+        }
+        let files = storage.list()?;
+        // height -> time of the blocks imported so far
+        let mut blocks: BTreeMap<u64, i64> = files
+            .iter()
+            .filter(|file| file.kind == FileKind::BlockFees)
+            .map(|file| (file.height, file.timestamp))
+            .collect();
+        let histograms: HashSet<i64> = files
+            .iter()
+            .filter(|file| file.kind == FileKind::Histogram)
+            .map(|file| file.timestamp)
+            .collect();
+
+        let statistics: Vec<Statistic> = api.get(&format!("v1/statistics/{period}")).await?;
+        let Some(first) = statistics.iter().map(|statistic| statistic.added).min() else {
+            bail!("mempool.space has no statistics for {period}");
+        };
+
+        // from the tip back to the block before the first histogram
+        let mut backfill = Backfill::default();
+        let mut height: u64 = api.get("blocks/tip/height").await?;
+        loop {
+            let lowest = height.saturating_sub(BLOCKS_PER_PAGE - 1);
+            if (lowest..=height).all(|height| blocks.contains_key(&height)) {
+                backfill.skipped += (height - lowest + 1) as usize;
+            } else {
+                let page: Vec<ExplorerBlock> = api.get(&format!("v1/blocks/{height}")).await?;
+                for block in &page {
+                    if blocks.contains_key(&block.height) {
+                        backfill.skipped += 1;
+                        continue;
+                    }
+                    let at = Utc.timestamp_opt(block.timestamp, 0).unwrap();
+                    let mut frame = Self::create_block_fees(block);
+                    storage.write(at, block.height, FileKind::BlockFees, &mut frame)?;
+                    blocks.insert(block.height, block.timestamp);
+                    backfill.blocks += 1;
+                }
+                debug!("imported blocks {lowest} to {height}");
+            }
+            match blocks.range(lowest..=height).next() {
+                Some((lowest, timestamp)) if *timestamp >= first && *lowest > 0 => {
+                    height = lowest - 1
+                }
+                _ => break,
+            }
+        }
+
+        // every histogram at the height of the last block before it
+        let heights: BTreeMap<i64, u64> = blocks
+            .into_iter()
+            .map(|(height, timestamp)| (timestamp, height))
+            .collect();
+        for statistic in &statistics {
+            if histograms.contains(&statistic.added) {
+                backfill.skipped += 1;
+                continue;
+            }
+            let Some((_, height)) = heights.range(..=statistic.added).next_back() else {
+                continue;
+            };
+            let at = Utc.timestamp_opt(statistic.added, 0).unwrap();
+            let mut frame = Self::create_histogram(&statistic.vsizes);
+            storage.write(at, *height, FileKind::Histogram, &mut frame)?;
+            backfill.histograms += 1;
+        }
+        info!(
+            "imported {} blocks and {} histograms from mempool.space, {} were already there",
+            backfill.blocks, backfill.histograms, backfill.skipped
+        );
+        Ok(backfill)
+    }
+
+    /// One row like the histograms `record --aggregate` writes, without the number of
+    /// transactions. A bucket's lowest fee rate is the upper bound of the one below it.
+    fn create_histogram(vsizes: &[u64]) -> DataFrame {
+        let buckets: Vec<(f64, u64)> = BUCKETS
+            .iter()
+            .zip(vsizes)
+            .filter(|(_, vsize)| **vsize > 0)
+            .map(|(fee_rate, vsize)| (*fee_rate, *vsize))
+            .rev()
+            .collect();
+        DataFrame::new(vec![
+            Series::new(
+                "fee_rate_sat_vb",
+                buckets.iter().map(|b| b.0).collect::<Vec<_>>(),
+            ),
+            Series::new("txs", vec![None::<u64>; buckets.len()]),
+            Series::new("vsize", buckets.iter().map(|b| b.1).collect::<Vec<_>>()),
+        ])
+        .unwrap()
+    }
+
+    fn create_block_fees(block: &ExplorerBlock) -> DataFrame {
+        let percentile = |index: usize| [block.extras.fee_range.get(index).copied()];
+        DataFrame::new(vec![
+            Series::new("block_hash", [block.id.as_str()]),
+            Series::new("tx_count", [block.tx_count]),
+            Series::new("weight", [block.weight]),
+            Series::new("total_fee_sat", [block.extras.total_fees]),
+            Series::new("median_fee_rate", [block.extras.median_fee]),
+            Series::new("fee_rate_min", percentile(0)),
+            Series::new("fee_rate_p10", percentile(1)),
+            Series::new("fee_rate_p25", percentile(2)),
+            Series::new("fee_rate_p50", percentile(3)),
+            Series::new("fee_rate_p75", percentile(4)),
+            Series::new("fee_rate_p90", percentile(5)),
+            Series::new("fee_rate_max", percentile(6)),
+        ])
+        .unwrap()
+    }
+
+    /// When Core wrote the file, on shutdown or `savemempool`
+    pub fn modified(path: &Path) -> Result<DateTime<Utc>> {
+        let modified = std::fs::metadata(path)?.modified()?;
+        let secs = modified.duration_since(std::time::UNIX_EPOCH)?.as_secs() as i64;
+        Ok(Utc.timestamp_opt(secs, 0).unwrap())
+    }
+}
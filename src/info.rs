@@ -0,0 +1,148 @@
+use crate::{dataset::FileKind, replay::Replay, storage::Storage};
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// Gaps are at least this long, however short the snapshot interval
+const MIN_GAP_SECS: i64 = 60;
+/// Without `max_gap`, a pause this many times the usual interval is a gap
+const GAP_INTERVALS: i64 = 3;
+
+/// Time without snapshots
+#[derive(Debug, Clone, Serialize)]
+pub struct Gap {
+    /// Last snapshot before and first snapshot after the gap
+    pub from: i64,
+    pub to: i64,
+    /// The recorder wrote its shutdown marker before the gap, it didn't crash
+    pub clean_stop: bool,
+}
+
+/// Snapshots that can be replayed one after the other: no gap in between and every height
+/// starting with its full snapshot
+#[derive(Debug, Clone, Serialize)]
+pub struct Range {
+    pub from: i64,
+    pub to: i64,
+    pub first_height: u64,
+    pub last_height: u64,
+    pub snapshots: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Coverage {
+    pub first: i64,
+    pub last: i64,
+    pub snapshots: usize,
+    /// Median seconds between snapshots
+    pub interval_secs: i64,
+    /// Pauses longer than this count as gaps
+    pub max_gap_secs: i64,
+    pub gaps: Vec<Gap>,
+    /// Heights between the first and the last one without a full snapshot, as inclusive ranges
+    pub missing_heights: Vec<(u64, u64)>,
+    pub ranges: Vec<Range>,
+}
+
+pub struct Info;
+
+impl Info {
+    /// Find the gaps and the usable continuous ranges of the dataset. Pauses longer than
+    /// `max_gap_secs` are gaps, by default three times the median snapshot interval.
+    #[tracing::instrument(skip(storage))]
+    pub fn coverage(storage: &dyn Storage, max_gap_secs: Option<i64>) -> Result<Coverage> {
+        let replay = Replay::new(storage)?;
+        let entries = replay.entries();
+        let (Some(first), Some(last)) = (entries.first(), entries.last()) else {
+            bail!("no recorded snapshots found");
+        };
+        let shutdowns: HashSet<i64> = storage
+            .list()?
+            .into_iter()
+            .filter(|f| f.kind == FileKind::Shutdown)
+            .map(|f| f.timestamp)
+            .collect();
+
+        // several files of a snapshot share its timestamp
+        let mut times: Vec<i64> = entries.iter().map(|e| e.timestamp).collect();
+        times.dedup();
+        let mut intervals: Vec<i64> = times.windows(2).map(|w| w[1] - w[0]).collect();
+        intervals.sort_unstable();
+        let interval_secs = intervals.get(intervals.len() / 2).copied().unwrap_or(0);
+        let max_gap_secs =
+            max_gap_secs.unwrap_or_else(|| (interval_secs * GAP_INTERVALS).max(MIN_GAP_SECS));
+
+        let gaps: Vec<Gap> = times
+            .windows(2)
+            .filter(|w| w[1] - w[0] > max_gap_secs)
+            .map(|w| Gap {
+                from: w[0],
+                to: w[1],
+                clean_stop: shutdowns.contains(&w[0]),
+            })
+            .collect();
+
+        let full_heights: HashSet<u64> = entries
+            .iter()
+            .filter(|e| matches!(e.kind, FileKind::Full | FileKind::Histogram))
+            .map(|e| e.height)
+            .collect();
+        let mut missing_heights: Vec<(u64, u64)> = Vec::new();
+        for height in first.height..=last.height {
+            if full_heights.contains(&height) {
+                continue;
+            }
+            match missing_heights.last_mut() {
+                Some((_, end)) if *end + 1 == height => *end = height,
+                _ => missing_heights.push((height, height)),
+            }
+        }
+
+        let mut ranges: Vec<Range> = Vec::new();
+        let mut current: Option<Range> = None;
+        let mut previous: Option<(i64, u64)> = None;
+        for entry in entries {
+            let continues = match previous {
+                Some((timestamp, height)) => {
+                    entry.timestamp - timestamp <= max_gap_secs
+                        && (entry.height == height
+                            || matches!(entry.kind, FileKind::Full | FileKind::Histogram))
+                }
+                None => false,
+            };
+            previous = Some((entry.timestamp, entry.height));
+            match current.as_mut() {
+                Some(range) if continues => {
+                    range.to = entry.timestamp;
+                    range.last_height = entry.height;
+                    range.snapshots += 1;
+                    continue;
+                }
+                _ => ranges.extend(current.take()),
+            }
+            // deltas can only be replayed on top of the full snapshot of their height, histograms
+            // stand on their own
+            if matches!(entry.kind, FileKind::Full | FileKind::Histogram) {
+                current = Some(Range {
+                    from: entry.timestamp,
+                    to: entry.timestamp,
+                    first_height: entry.height,
+                    last_height: entry.height,
+                    snapshots: 1,
+                });
+            }
+        }
+        ranges.extend(current);
+
+        Ok(Coverage {
+            first: first.timestamp,
+            last: last.timestamp,
+            snapshots: times.len(),
+            interval_secs,
+            max_gap_secs,
+            gaps,
+            missing_heights,
+            ranges,
+        })
+    }
+}
@@ -0,0 +1,164 @@
+use crate::{
+    calc::{Calc, Confirmation},
+    model::Survival,
+    replay::Replay,
+    storage::Storage,
+};
+use anyhow::{bail, Result};
+use bitcoin::Txid;
+use polars::prelude::*;
+use std::collections::{HashMap, HashSet};
+use tracing::info;
+
+/// Mempool context of a transaction is taken from snapshots this far apart
+const SAMPLE_SECS: i64 = 60;
+
+/// A transaction as first sampled
+struct Seen {
+    txid: String,
+    first_seen_at: i64,
+    height: u64,
+    fee_rate: f64,
+    vsize: f64,
+    percentile: f64,
+    depth: f64,
+    mempool_txs: usize,
+    mempool_vsize: f64,
+}
+
+/// Confirmation labels for fee models: every transaction that entered the mempool while
+/// recording, with where it stood when first sampled and how long it waited. Transactions
+/// already waiting in the first snapshot are left out, their context on arrival is unknown.
+pub struct Label;
+
+impl Label {
+    /// One row per transaction first seen within `from..=to`: `txid`, `first_seen_at`,
+    /// `height`, `fee_rate_sat_vb`, `vsize`, `percentile` and `depth_blocks` within the
+    /// mempool, `mempool_txs`, `mempool_vsize`, then `confirmed_height`, `wait_blocks`,
+    /// `wait_seconds` and `out_of_band` for confirmations paying well below their projected
+    /// block, to leave out or weigh down when training. Unless `censored` is set only
+    /// confirmed transactions are labeled, otherwise the others follow with empty waits.
+    #[tracing::instrument(skip(storage))]
+    pub fn label(storage: &dyn Storage, from: i64, to: i64, censored: bool) -> Result<DataFrame> {
+        let replay = Replay::new(storage)?;
+        let mut seen: Vec<Seen> = Vec::new();
+        let mut known: HashSet<String> = HashSet::new();
+        let mut first = true;
+        let mut next_sample = i64::MIN;
+        replay.walk(from, to, |snapshot| {
+            if snapshot.timestamp < next_sample {
+                return Ok(());
+            }
+            next_sample = snapshot.timestamp + SAMPLE_SECS;
+            let positions = Survival::positions(snapshot);
+            let mempool_vsize: f64 = snapshot
+                .transactions
+                .values()
+                .map(|tx| tx.weight / 4.)
+                .sum();
+            for (txid, position) in positions {
+                if !known.insert(txid.clone()) || first {
+                    continue;
+                }
+                let tx = &snapshot.transactions[txid];
+                seen.push(Seen {
+                    txid: txid.clone(),
+                    first_seen_at: tx
+                        .first_seen_at
+                        .map_or(snapshot.timestamp, |time| time as i64),
+                    height: snapshot.height,
+                    fee_rate: position.fee_rate,
+                    vsize: tx.weight / 4.,
+                    percentile: position.percentile,
+                    depth: position.depth,
+                    mempool_txs: snapshot.transactions.len(),
+                    mempool_vsize,
+                });
+            }
+            first = false;
+            Ok(())
+        })?;
+        if first {
+            bail!("no snapshots recorded between {from} and {to}");
+        }
+
+        let confirmed: HashMap<Txid, Confirmation> = Calc::confirmations(storage, from)?
+            .into_iter()
+            .map(|c| (c.txid, c))
+            .collect();
+        let rows: Vec<(&Seen, Option<&Confirmation>)> = seen
+            .iter()
+            .map(|seen| {
+                let confirmation = seen
+                    .txid
+                    .parse::<Txid>()
+                    .ok()
+                    .and_then(|txid| confirmed.get(&txid));
+                (seen, confirmation)
+            })
+            .filter(|(_, confirmation)| censored || confirmation.is_some())
+            .collect();
+        info!(
+            "transactions: {}, confirmed: {}",
+            seen.len(),
+            rows.iter().filter(|(_, c)| c.is_some()).count()
+        );
+
+        let seen_values = |value: fn(&Seen) -> f64| -> Vec<f64> {
+            rows.iter().map(|(seen, _)| value(seen)).collect()
+        };
+        Ok(DataFrame::new(vec![
+            Series::new(
+                "txid",
+                rows.iter()
+                    .map(|(s, _)| s.txid.as_str())
+                    .collect::<Vec<_>>(),
+            ),
+            Series::new(
+                "first_seen_at",
+                rows.iter()
+                    .map(|(s, _)| s.first_seen_at)
+                    .collect::<Vec<_>>(),
+            ),
+            Series::new(
+                "height",
+                rows.iter().map(|(s, _)| s.height).collect::<Vec<_>>(),
+            ),
+            Series::new("fee_rate_sat_vb", seen_values(|s| s.fee_rate)),
+            Series::new("vsize", seen_values(|s| s.vsize)),
+            Series::new("percentile", seen_values(|s| s.percentile)),
+            Series::new("depth_blocks", seen_values(|s| s.depth)),
+            Series::new(
+                "mempool_txs",
+                rows.iter()
+                    .map(|(s, _)| s.mempool_txs as u64)
+                    .collect::<Vec<_>>(),
+            ),
+            Series::new("mempool_vsize", seen_values(|s| s.mempool_vsize)),
+            Series::new(
+                "confirmed_height",
+                rows.iter()
+                    .map(|(_, c)| c.map(|c| c.height))
+                    .collect::<Vec<_>>(),
+            ),
+            Series::new(
+                "wait_blocks",
+                rows.iter()
+                    .map(|(_, c)| c.map(|c| c.wait_blocks))
+                    .collect::<Vec<_>>(),
+            ),
+            Series::new(
+                "wait_seconds",
+                rows.iter()
+                    .map(|(s, c)| c.map(|c| c.confirmed_at - s.first_seen_at))
+                    .collect::<Vec<_>>(),
+            ),
+            Series::new(
+                "out_of_band",
+                rows.iter()
+                    .map(|(_, c)| c.map(|c| c.out_of_band))
+                    .collect::<Vec<_>>(),
+            ),
+        ])?)
+    }
+}
@@ -1 +1,105 @@
-pub mod record;
+//! Records Bitcoin Core's mempool and estimates fees from the recording. Besides the `wtf`
+//! binary, other programs can embed both:
+//!
+//! - [`Dataset`] opens a recorded dataset to read snapshots from and write them to
+//! - [`MempoolSnapshot`] is the mempool at one point in time, replayed or taken from a node
+//! - [`DeltaFrame`] holds the rows of a full or delta file, what changed between two mempools
+//! - [`Estimator`] answers fee rates for a confirmation target from a dataset
+//! - [`Record`] runs the recorder itself against a [`Node`], like [`RestClient`]
+//!
+//! The types their signatures take are exported next to them, the modules are internal.
+
+pub(crate) mod access;
+pub(crate) mod alert;
+pub(crate) mod backtest;
+pub(crate) mod bump;
+pub(crate) mod bus;
+pub(crate) mod calc;
+mod cli;
+pub(crate) mod cln;
+pub(crate) mod compact;
+pub(crate) mod compare;
+pub(crate) mod config;
+pub(crate) mod congestion;
+pub(crate) mod corerpc;
+pub(crate) mod cpfp;
+pub(crate) mod crossval;
+pub(crate) mod dashboard;
+pub(crate) mod dataset;
+pub(crate) mod doctor;
+pub(crate) mod downsample;
+pub(crate) mod electrum;
+pub(crate) mod eta;
+pub(crate) mod explain;
+pub(crate) mod export;
+pub(crate) mod failover;
+pub(crate) mod features;
+pub(crate) mod flight;
+pub(crate) mod grpc;
+pub(crate) mod histogram;
+pub(crate) mod history;
+pub(crate) mod import;
+pub(crate) mod info;
+pub(crate) mod label;
+pub(crate) mod lock;
+pub(crate) mod log;
+pub(crate) mod manifest;
+pub(crate) mod merge;
+pub(crate) mod metrics;
+pub(crate) mod migrate;
+pub(crate) mod model;
+pub(crate) mod mqtt;
+pub(crate) mod node;
+pub(crate) mod nostr;
+pub(crate) mod onnx;
+pub(crate) mod openapi;
+pub(crate) mod p2p;
+pub(crate) mod plot;
+pub(crate) mod position;
+pub(crate) mod postgres;
+pub(crate) mod price;
+pub(crate) mod prune;
+pub(crate) mod psbt;
+pub(crate) mod query;
+pub(crate) mod rbf;
+pub(crate) mod record;
+pub(crate) mod replay;
+pub(crate) mod rollout;
+pub(crate) mod rpc;
+pub(crate) mod s3;
+pub(crate) mod score;
+pub(crate) mod seasonal;
+pub(crate) mod serve;
+pub(crate) mod simulate;
+pub(crate) mod sink;
+pub(crate) mod sqlite;
+pub(crate) mod stats;
+pub(crate) mod storage;
+pub(crate) mod sync;
+pub(crate) mod systemd;
+pub(crate) mod table;
+pub(crate) mod template;
+pub(crate) mod tls;
+pub(crate) mod trace;
+pub(crate) mod unit;
+pub(crate) mod verify;
+pub(crate) mod watch;
+pub(crate) mod web;
+pub(crate) mod writer;
+pub(crate) mod zmq;
+
+pub use calc::{Estimator, RecommendedFees, TargetEstimate};
+#[doc(hidden)]
+pub use cli::run;
+pub use congestion::Congestion;
+pub use dataset::{Dataset, FileKind, SnapshotFile};
+pub use manifest::Manifest;
+pub use metrics::Metrics;
+pub use model::Trained;
+pub use node::{Node, RestClient};
+pub use prune::Retention;
+pub use record::{
+    Cadence, DeltaFrame, Filter, Mempool, MempoolEntry, Record, RecordOptions, Settings,
+};
+pub use replay::{Snapshot as MempoolSnapshot, Transaction as SnapshotTransaction};
+pub use storage::{Storage, StorageKind};
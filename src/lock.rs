@@ -0,0 +1,61 @@
+use anyhow::{bail, Context, Result};
+use bitcoin::Network;
+use chrono::{SecondsFormat, Utc};
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io::{Read, Seek, Write},
+    path::Path,
+};
+use tracing::debug;
+
+/// Advisory lock on the dataset of a network, held by the recorder writing to it. Two
+/// recorders in the same directory would interleave their files. The lock goes away with the
+/// process, however it ends, the file is left behind naming the last owner.
+#[derive(Debug)]
+pub struct DataDirLock {
+    _file: File,
+}
+
+impl DataDirLock {
+    /// Take the lock of `network` in `data_dir`, failing right away if another process has it
+    pub fn acquire(data_dir: &Path, network: Network) -> Result<Self> {
+        std::fs::create_dir_all(data_dir)
+            .with_context(|| format!("creating {}", data_dir.display()))?;
+        let path = data_dir.join(format!("{network}.lock"));
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut owner = String::new();
+                let _ = file.read_to_string(&mut owner);
+                let owner = owner.trim();
+                let owner = if owner.is_empty() { "unknown" } else { owner };
+                bail!(
+                    "another recorder is writing the {network} dataset in {} ({owner}), stop it \
+                     first or record to another data directory",
+                    data_dir.display()
+                );
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("locking {}", path.display()))
+            }
+        }
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(
+            file,
+            "pid {} since {}",
+            std::process::id(),
+            Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+        )?;
+        file.sync_all()?;
+        debug!("locked {}", path.display());
+        Ok(DataDirLock { _file: file })
+    }
+}
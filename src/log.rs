@@ -0,0 +1,125 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{
+    filter::{Directive, LevelFilter},
+    fmt::MakeWriter,
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// For people, colored on a terminal
+    #[default]
+    Text,
+    /// One object per line with the fields of each event, for log aggregation
+    Json,
+}
+
+/// When a log file is started anew, the old ones keep the date and time as a suffix
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    /// Append to the one file, for logrotate and the like
+    Never,
+}
+
+impl LogRotation {
+    fn rotation(self) -> Rotation {
+        match self {
+            LogRotation::Minutely => Rotation::MINUTELY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+/// Where and how the commands log, set up once at start
+#[derive(Debug, Clone)]
+pub struct Logging {
+    /// Of everything not named in `levels`
+    pub level: LevelFilter,
+    pub format: LogFormat,
+    /// Log to this file instead of the console
+    pub file: Option<PathBuf>,
+    pub rotation: LogRotation,
+    /// Level by module like `wtf::record` = `debug`, directives from RUST_LOG still win
+    pub levels: BTreeMap<String, String>,
+    /// Log to stderr instead of stdout, which is left to the command's output
+    pub stderr: bool,
+    /// Color the console
+    pub ansi: bool,
+}
+
+impl Logging {
+    /// Install the subscriber for the rest of the process
+    pub fn init(self) -> Result<()> {
+        let filter = self.filter()?;
+        let layer = match &self.file {
+            Some(path) => {
+                let directory = match path.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent,
+                    _ => Path::new("."),
+                };
+                let Some(prefix) = path.file_name() else {
+                    anyhow::bail!("log file {} has no file name", path.display());
+                };
+                std::fs::create_dir_all(directory)
+                    .with_context(|| format!("creating {}", directory.display()))?;
+                let appender =
+                    RollingFileAppender::new(self.rotation.rotation(), directory, prefix);
+                Self::layer(self.format, appender, false)
+            }
+            None if self.stderr => Self::layer(self.format, std::io::stderr, self.ansi),
+            None => Self::layer(self.format, std::io::stdout, self.ansi),
+        };
+        tracing_subscriber::registry()
+            .with(layer.with_filter(filter))
+            .try_init()?;
+        Ok(())
+    }
+
+    /// `level`, then `levels`, then RUST_LOG, each overriding the ones before
+    fn filter(&self) -> Result<EnvFilter> {
+        let mut filter = EnvFilter::default().add_directive(self.level.into());
+        for (target, level) in &self.levels {
+            let directive: Directive = format!("{target}={level}")
+                .parse()
+                .with_context(|| format!("invalid log level {level} for {target}"))?;
+            filter = filter.add_directive(directive);
+        }
+        // like `EnvFilter::from_env_lossy`, a directive that doesn't parse is left out
+        if let Ok(env) = std::env::var(EnvFilter::DEFAULT_ENV) {
+            for directive in env.split(',').filter_map(|d| d.trim().parse().ok()) {
+                filter = filter.add_directive(directive);
+            }
+        }
+        Ok(filter)
+    }
+
+    fn layer<W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<Registry> + Send + Sync>
+    where
+        W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+    {
+        let layer = tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(ansi);
+        match format {
+            LogFormat::Text => layer.boxed(),
+            LogFormat::Json => layer.json().boxed(),
+        }
+    }
+}
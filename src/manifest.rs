@@ -0,0 +1,117 @@
+use crate::dataset::SnapshotFile;
+use anyhow::{bail, Context, Result};
+use bitcoin::hashes::{sha256, Hash};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+/// Name of the manifest in the directory of a network's files
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// A file as the manifest describes it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub height: u64,
+    pub timestamp: i64,
+    pub kind: String,
+    pub rows: usize,
+    pub bytes: u64,
+    /// Hex SHA-256 of the file
+    pub sha256: String,
+}
+
+/// Every file of a local dataset with its row count and checksum, kept up to date as files
+/// are written and removed, so the dataset can be checked without reading every file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Unix timestamps of the first and the last file
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    /// By file name, `{height}_{timestamp}_{kind}.parquet`
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// The manifest in `dir`, `None` if there is none yet
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(MANIFEST_FILE);
+        let json = match std::fs::read(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+        };
+        let manifest =
+            serde_json::from_slice(&json).with_context(|| format!("parsing {}", path.display()))?;
+        Ok(Some(manifest))
+    }
+
+    /// Write to `dir`, through a temporary file renamed into place
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(MANIFEST_FILE);
+        let partial = dir.join(format!("{MANIFEST_FILE}.tmp"));
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(&partial, serde_json::to_vec(self)?)
+            .with_context(|| format!("writing {}", partial.display()))?;
+        std::fs::rename(&partial, &path).with_context(|| format!("renaming {}", partial.display()))
+    }
+
+    /// Hex SHA-256 of `bytes`
+    pub fn checksum(bytes: &[u8]) -> String {
+        sha256::Hash::hash(bytes).to_string()
+    }
+
+    fn name(file: &SnapshotFile) -> String {
+        file.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, file: &SnapshotFile) -> Option<&ManifestEntry> {
+        self.files.get(&Self::name(file))
+    }
+
+    /// Add `file`, holding `rows` rows, as written with `bytes`
+    pub fn insert(&mut self, file: &SnapshotFile, rows: usize, bytes: &[u8]) {
+        let entry = ManifestEntry {
+            height: file.height,
+            timestamp: file.timestamp,
+            kind: file.kind.as_str().to_string(),
+            rows,
+            bytes: bytes.len() as u64,
+            sha256: Self::checksum(bytes),
+        };
+        self.files.insert(Self::name(file), entry);
+        self.from = Some(
+            self.from
+                .map_or(file.timestamp, |from| from.min(file.timestamp)),
+        );
+        self.to = Some(self.to.map_or(file.timestamp, |to| to.max(file.timestamp)));
+    }
+
+    pub fn remove(&mut self, file: &SnapshotFile) {
+        if self.files.remove(&Self::name(file)).is_none() {
+            return;
+        }
+        let timestamps = self.files.values().map(|entry| entry.timestamp);
+        self.from = timestamps.clone().min();
+        self.to = timestamps.max();
+    }
+
+    /// Read `file` and fail unless it matches its entry. Files not listed pass.
+    pub fn check(&self, file: &SnapshotFile) -> Result<()> {
+        let Some(entry) = self.get(file) else {
+            return Ok(());
+        };
+        let bytes = std::fs::read(&file.path)
+            .with_context(|| format!("reading {}", file.path.display()))?;
+        let checksum = Self::checksum(&bytes);
+        if checksum != entry.sha256 {
+            bail!(
+                "{} has checksum {checksum}, the manifest has {}",
+                file.path.display(),
+                entry.sha256
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,312 @@
+use crate::{
+    dataset::{FileKind, SnapshotFile},
+    info::Info,
+    replay::{Entry, Replay},
+    storage::Storage,
+};
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use polars::{functions, prelude::*};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+use tracing::{debug, info};
+
+/// Columns [`Replay::read`] adds to the rows of a snapshot
+const TAG_COLUMNS: [&str; 3] = ["height", "snapshot_timestamp", "kind"];
+
+/// What `wtf merge` found and did
+#[derive(Debug, Default, Serialize)]
+pub struct Merged {
+    /// Files of the other dataset copied into a gap, or in place of a block missing here
+    pub copied: usize,
+    /// Full snapshots made up from the other dataset where a gap starts between two of its
+    /// snapshots of a height
+    pub reconstructed: usize,
+    /// Files here rewritten with earlier first-seen times
+    pub rewritten: usize,
+    /// Rows whose transaction the other recorder saw first
+    pub earlier_first_seen: usize,
+}
+
+/// Combines the dataset of another recorder into this one. Where this one has snapshots they
+/// are kept, where it has gaps the other's fill in. Transactions are matched by txid, both
+/// keep the earlier of the two first-seen times. With manifests on both sides, files of the
+/// same checksum aren't read twice, and what is copied has to match the other's manifest.
+pub struct Merge;
+
+impl Merge {
+    /// Merge `other` into `storage`. With `dry_run` nothing is written, only counted.
+    #[tracing::instrument(skip(storage, other))]
+    pub fn run(storage: &dyn Storage, other: &dyn Storage, dry_run: bool) -> Result<Merged> {
+        let mut merged = Merged::default();
+        let files = storage.list()?;
+        let other_files = other.list()?;
+        // replicas share files, those of the same checksum are only read here
+        let other_manifest = other.manifest()?;
+        let identical: HashSet<&str> = match (storage.manifest()?, &other_manifest) {
+            (Some(manifest), Some(other_manifest)) => other_files
+                .iter()
+                .filter(|file| {
+                    let (Some(ours), Some(theirs)) = (manifest.get(file), other_manifest.get(file))
+                    else {
+                        return false;
+                    };
+                    ours.sha256 == theirs.sha256
+                })
+                .filter_map(|file| file.path.file_name()?.to_str())
+                .collect(),
+            _ => HashSet::new(),
+        };
+        let distinct: Vec<SnapshotFile> = other_files
+            .iter()
+            .filter(|file| {
+                !file
+                    .path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| identical.contains(name))
+            })
+            .cloned()
+            .collect();
+        let first_seen = Self::first_seen(storage, &files, other, &distinct)?;
+        // what is copied has to match the other's manifest
+        let mut checked: HashSet<PathBuf> = HashSet::new();
+        let mut check = |file: &SnapshotFile| -> Result<()> {
+            if let Some(manifest) = &other_manifest {
+                if checked.insert(file.path.clone()) {
+                    manifest.check(file)?;
+                }
+            }
+            Ok(())
+        };
+
+        // snapshots here stay, the other's fill in between
+        let replay = Replay::new(storage)?;
+        let covered: Vec<(i64, i64)> = if replay.entries().is_empty() {
+            Vec::new()
+        } else {
+            Info::coverage(storage, None)?
+                .ranges
+                .iter()
+                .map(|range| (range.from, range.to))
+                .collect()
+        };
+        let is_covered = |timestamp: i64| {
+            covered
+                .iter()
+                .any(|(from, to)| (*from..=*to).contains(&timestamp))
+        };
+
+        // rewrite before copying, what is copied has the earlier times already
+        for file in &files {
+            if !Self::has_first_seen(file.kind) {
+                continue;
+            }
+            let frame = storage.read(file)?;
+            let (mut frame, earlier) = Self::reconcile(frame, &first_seen)?;
+            if earlier == 0 {
+                continue;
+            }
+            merged.rewritten += 1;
+            merged.earlier_first_seen += earlier;
+            if !dry_run {
+                storage.write(file.written_at(), file.height, file.kind, &mut frame)?;
+                debug!("rewrote {}", file.path.display());
+            }
+        }
+
+        let other_replay = Replay::new(other)?;
+        let entries = other_replay.entries();
+        // the height whose snapshots are being copied into the current gap
+        let mut copying: Option<u64> = None;
+        for (index, entry) in entries.iter().enumerate() {
+            if is_covered(entry.timestamp) {
+                copying = None;
+                continue;
+            }
+            check(other_replay.file(entry))?;
+            let frame = match entry.kind {
+                FileKind::Delta if copying != Some(entry.height) => {
+                    // deltas here would apply to a mempool this dataset never had
+                    let Some(mut frame) = Self::reconstruct(&other_replay, &entries[..=index])?
+                    else {
+                        continue;
+                    };
+                    merged.reconstructed += 1;
+                    if !dry_run {
+                        let at = Utc.timestamp_opt(entry.timestamp, 0).unwrap();
+                        storage.write(at, entry.height, FileKind::Full, &mut frame)?;
+                    }
+                    copying = Some(entry.height);
+                    continue;
+                }
+                FileKind::Delta => Self::untag(other_replay.read(entry)?)?,
+                _ => {
+                    copying = Some(entry.height);
+                    Self::untag(other_replay.read(entry)?)?
+                }
+            };
+            merged.copied += 1;
+            if !dry_run {
+                let (mut frame, _) = Self::reconcile(frame, &first_seen)?;
+                let at = Utc.timestamp_opt(entry.timestamp, 0).unwrap();
+                storage.write(at, entry.height, entry.kind, &mut frame)?;
+            }
+        }
+
+        // blocks are labelled once per height, anything else goes with the snapshots of a gap
+        let heights: HashSet<(u64, &str)> = files
+            .iter()
+            .map(|file| (file.height, file.kind.as_str()))
+            .collect();
+        for file in &other_files {
+            let copy = match file.kind {
+                FileKind::Block | FileKind::Reorg | FileKind::BlockFees | FileKind::BlockHeader => {
+                    !heights.contains(&(file.height, file.kind.as_str()))
+                }
+                FileKind::Meta
+                | FileKind::CoreEstimates
+                | FileKind::PeerFeeFilters
+                | FileKind::Events
+                | FileKind::Rejected => !is_covered(file.timestamp),
+                // snapshots are copied above, markers belong with their own recorder
+                _ => false,
+            };
+            if !copy {
+                continue;
+            }
+            check(file)?;
+            merged.copied += 1;
+            if !dry_run {
+                let (mut frame, _) = Self::reconcile(other.read(file)?, &first_seen)?;
+                storage.write(file.written_at(), file.height, file.kind, &mut frame)?;
+            }
+        }
+        info!(
+            "copied: {}, reconstructed: {}, rewritten: {}, earlier_first_seen: {}",
+            merged.copied, merged.reconstructed, merged.rewritten, merged.earlier_first_seen
+        );
+        Ok(merged)
+    }
+
+    fn has_first_seen(kind: FileKind) -> bool {
+        matches!(
+            kind,
+            FileKind::Full | FileKind::Delta | FileKind::Compact | FileKind::Block
+        )
+    }
+
+    /// The earliest time either recorder saw each transaction
+    fn first_seen(
+        storage: &dyn Storage,
+        files: &[SnapshotFile],
+        other: &dyn Storage,
+        other_files: &[SnapshotFile],
+    ) -> Result<HashMap<String, u64>> {
+        let mut first_seen: HashMap<String, u64> = HashMap::new();
+        let datasets = [(storage, files), (other, other_files)];
+        for (storage, files) in datasets {
+            for file in files.iter().filter(|file| Self::has_first_seen(file.kind)) {
+                let frame = storage.read(file)?;
+                let (Ok(txids), Ok(times)) = (frame.column("txid"), frame.column("first_seen_at"))
+                else {
+                    continue;
+                };
+                for (txid, time) in txids.utf8()?.into_iter().zip(times.u64()?) {
+                    let (Some(txid), Some(time)) = (txid, time) else {
+                        continue;
+                    };
+                    first_seen
+                        .entry(txid.to_string())
+                        .and_modify(|first| *first = (*first).min(time))
+                        .or_insert(time);
+                }
+            }
+        }
+        Ok(first_seen)
+    }
+
+    /// `frame` with the earliest first-seen times, and how many rows had a later one. The wait
+    /// of a confirmation grows by as much.
+    fn reconcile(
+        mut frame: DataFrame,
+        first_seen: &HashMap<String, u64>,
+    ) -> Result<(DataFrame, usize)> {
+        let (Ok(txids), Ok(times)) = (frame.column("txid"), frame.column("first_seen_at")) else {
+            return Ok((frame, 0));
+        };
+        let mut earlier = 0;
+        let mut earlier_by: Vec<u64> = Vec::with_capacity(frame.height());
+        let times: Vec<Option<u64>> = txids
+            .utf8()?
+            .into_iter()
+            .zip(times.u64()?)
+            .map(|(txid, time)| {
+                let first = txid.and_then(|txid| first_seen.get(txid)).copied();
+                match (time, first) {
+                    (Some(time), Some(first)) if first < time => {
+                        earlier += 1;
+                        earlier_by.push(time - first);
+                        Some(first)
+                    }
+                    _ => {
+                        earlier_by.push(0);
+                        time
+                    }
+                }
+            })
+            .collect();
+        if earlier == 0 {
+            return Ok((frame, 0));
+        }
+        if let Ok(waits) = frame.column("wait_secs") {
+            let waits: Vec<Option<i64>> = waits
+                .i64()?
+                .into_iter()
+                .zip(&earlier_by)
+                .map(|(wait, by)| wait.map(|wait| wait + *by as i64))
+                .collect();
+            frame.with_column(Series::new("wait_secs", waits))?;
+        }
+        frame.with_column(Series::new("first_seen_at", times))?;
+        Ok((frame, earlier))
+    }
+
+    /// The mempool as of the last of `entries`, as the rows of a full snapshot: the latest row
+    /// of every transaction since the full snapshot of its height, unless it was removed.
+    /// `None` if the other recorder has no full snapshot of the height either.
+    fn reconstruct(replay: &Replay, entries: &[Entry]) -> Result<Option<DataFrame>> {
+        let Some(last) = entries.last() else {
+            return Ok(None);
+        };
+        let Some(start) = entries
+            .iter()
+            .rposition(|entry| entry.height != last.height || entry.kind == FileKind::Full)
+            .filter(|start| entries[*start].kind == FileKind::Full)
+        else {
+            return Ok(None);
+        };
+        let frames = entries[start..]
+            .iter()
+            .map(|entry| replay.read(entry))
+            .collect::<Result<Vec<_>>>()?;
+        let rows = functions::diag_concat_df(&frames)?.unique_stable(
+            Some(&[String::from("txid")]),
+            UniqueKeepStrategy::Last,
+            None,
+        )?;
+        let present = rows.column("weight")?.gt(0.)?;
+        let mut frame = Self::untag(rows.filter(&present)?)?;
+        // added once, not removed by a delta
+        let added = Series::full_null("removal_reason", frame.height(), &DataType::Utf8);
+        frame.with_column(added)?;
+        Ok(Some(frame))
+    }
+
+    fn untag(frame: DataFrame) -> Result<DataFrame> {
+        Ok(frame.drop_many(&TAG_COLUMNS))
+    }
+}
@@ -0,0 +1,346 @@
+use crate::congestion::Shift;
+use anyhow::Result;
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use chrono::Utc;
+use serde::Serialize;
+use std::{
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing::info;
+
+/// Without a snapshot for this many intervals the recorder is stalled
+const STALE_INTERVALS: i64 = 3;
+/// However short the interval, snapshots this recent aren't stalled
+const MIN_STALE_SECS: i64 = 60;
+
+/// What `/healthz` and `/readyz` answer. Alive means the process isn't stuck, ready that its
+/// estimates are current.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Health {
+    pub alive: bool,
+    pub ready: bool,
+    /// Seconds since the node last answered, recorder only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_answered_secs_ago: Option<i64>,
+    /// Seconds since the recorder's last snapshot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_snapshot_secs_ago: Option<i64>,
+    /// Seconds since the latest snapshot in the dataset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dataset_age_secs: Option<i64>,
+    /// Older than this is stale
+    pub max_age_secs: i64,
+}
+
+impl Health {
+    /// `/healthz`, 200 while alive
+    pub fn healthz(self) -> (StatusCode, Json<Health>) {
+        let status = if self.alive {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        (status, Json(self))
+    }
+
+    /// `/readyz`, 200 while ready
+    pub fn readyz(self) -> (StatusCode, Json<Health>) {
+        let status = if self.ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        (status, Json(self))
+    }
+}
+
+/// Recorder health, exposed in the Prometheus text format
+#[derive(Debug)]
+pub struct Metrics {
+    interval_secs: u32,
+    started_timestamp: i64,
+    node_answered_timestamp: AtomicI64,
+    snapshots: AtomicU64,
+    snapshot_duration_millis: AtomicU64,
+    last_snapshot_timestamp: AtomicI64,
+    mempool_transactions: AtomicU64,
+    delta_rows: AtomicU64,
+    write_failures: AtomicU64,
+    ticks_skipped: AtomicU64,
+    degraded: AtomicBool,
+    write_queue: AtomicU64,
+    write_waits: AtomicU64,
+    write_wait_millis: AtomicU64,
+    next_block_cutoff_msat_vb: AtomicU64,
+    fee_spikes: AtomicU64,
+    clearings: AtomicU64,
+    purged: AtomicU64,
+    purged_vsize: AtomicU64,
+}
+
+impl Metrics {
+    /// For a recorder taking a snapshot every `interval_secs`
+    pub fn new(interval_secs: u32) -> Self {
+        Metrics {
+            interval_secs,
+            started_timestamp: Utc::now().timestamp(),
+            node_answered_timestamp: AtomicI64::new(0),
+            snapshots: AtomicU64::new(0),
+            snapshot_duration_millis: AtomicU64::new(0),
+            last_snapshot_timestamp: AtomicI64::new(0),
+            mempool_transactions: AtomicU64::new(0),
+            delta_rows: AtomicU64::new(0),
+            write_failures: AtomicU64::new(0),
+            ticks_skipped: AtomicU64::new(0),
+            degraded: AtomicBool::new(false),
+            write_queue: AtomicU64::new(0),
+            write_waits: AtomicU64::new(0),
+            write_wait_millis: AtomicU64::new(0),
+            next_block_cutoff_msat_vb: AtomicU64::new(0),
+            fee_spikes: AtomicU64::new(0),
+            clearings: AtomicU64::new(0),
+            purged: AtomicU64::new(0),
+            purged_vsize: AtomicU64::new(0),
+        }
+    }
+
+    pub fn node_answered(&self, timestamp: i64) {
+        self.node_answered_timestamp
+            .store(timestamp, Ordering::Relaxed);
+    }
+
+    pub fn observe_snapshot(
+        &self,
+        duration: Duration,
+        timestamp: i64,
+        mempool_transactions: usize,
+        delta_rows: usize,
+    ) {
+        self.snapshots.fetch_add(1, Ordering::Relaxed);
+        self.snapshot_duration_millis
+            .store(duration.as_millis() as u64, Ordering::Relaxed);
+        self.last_snapshot_timestamp
+            .store(timestamp, Ordering::Relaxed);
+        self.mempool_transactions
+            .store(mempool_transactions as u64, Ordering::Relaxed);
+        self.delta_rows
+            .fetch_add(delta_rows as u64, Ordering::Relaxed);
+    }
+
+    pub fn write_failed(&self) {
+        self.write_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn ticks_skipped(&self, ticks: u64) {
+        self.ticks_skipped.fetch_add(ticks, Ordering::Relaxed);
+    }
+
+    /// Recording histograms instead of transactions, snapshots kept overrunning the interval
+    pub fn degraded(&self, degraded: bool) {
+        self.degraded.store(degraded, Ordering::Relaxed);
+    }
+
+    /// Files waiting for the writer
+    pub fn write_queued(&self, files: usize) {
+        self.write_queue.store(files as u64, Ordering::Relaxed);
+    }
+
+    /// The recorder waited this long for room in the full write queue
+    pub fn write_waited(&self, duration: Duration) {
+        self.write_waits.fetch_add(1, Ordering::Relaxed);
+        self.write_wait_millis
+            .fetch_add(duration.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Fee rate (sat/vB) the next block takes, recorded with congestion alerts
+    pub fn next_block_cutoff(&self, fee_rate: f64) {
+        self.next_block_cutoff_msat_vb
+            .store((fee_rate * 1000.).round() as u64, Ordering::Relaxed);
+    }
+
+    pub fn congestion_shift(&self, shift: Shift) {
+        match shift {
+            Shift::FeeSpike => self.fee_spikes.fetch_add(1, Ordering::Relaxed),
+            Shift::Clearing => self.clearings.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    /// Transactions the node evicted from its full mempool, and their virtual size
+    pub fn purged(&self, txs: u64, vsize: u64) {
+        self.purged.fetch_add(txs, Ordering::Relaxed);
+        self.purged_vsize.fetch_add(vsize, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let load = |value: &AtomicU64| value.load(Ordering::Relaxed);
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            let _ = writeln!(out, "{name} {value}");
+        };
+        metric(
+            "wtf_snapshots_total",
+            "counter",
+            "Snapshots taken",
+            load(&self.snapshots).to_string(),
+        );
+        metric(
+            "wtf_snapshot_duration_seconds",
+            "gauge",
+            "Time the last snapshot took",
+            (load(&self.snapshot_duration_millis) as f64 / 1000.).to_string(),
+        );
+        metric(
+            "wtf_last_snapshot_timestamp_seconds",
+            "gauge",
+            "Unix time of the last snapshot",
+            self.last_snapshot_timestamp
+                .load(Ordering::Relaxed)
+                .to_string(),
+        );
+        metric(
+            "wtf_mempool_transactions",
+            "gauge",
+            "Transactions in the recorded mempool",
+            load(&self.mempool_transactions).to_string(),
+        );
+        metric(
+            "wtf_delta_rows_total",
+            "counter",
+            "Rows written to full and delta files",
+            load(&self.delta_rows).to_string(),
+        );
+        metric(
+            "wtf_parquet_write_failures_total",
+            "counter",
+            "Files that could not be written",
+            load(&self.write_failures).to_string(),
+        );
+        metric(
+            "wtf_ticks_skipped_total",
+            "counter",
+            "Cadence ticks missed because a snapshot ran late",
+            load(&self.ticks_skipped).to_string(),
+        );
+        metric(
+            "wtf_recording_degraded",
+            "gauge",
+            "1 while histograms are recorded instead of transactions because of overload",
+            u8::from(self.degraded.load(Ordering::Relaxed)).to_string(),
+        );
+        metric(
+            "wtf_write_queue_files",
+            "gauge",
+            "Files waiting to be written",
+            load(&self.write_queue).to_string(),
+        );
+        metric(
+            "wtf_write_queue_full_total",
+            "counter",
+            "Times the recorder waited for the writer to catch up",
+            load(&self.write_waits).to_string(),
+        );
+        metric(
+            "wtf_write_queue_wait_seconds_total",
+            "counter",
+            "Time the recorder waited for the writer to catch up",
+            (load(&self.write_wait_millis) as f64 / 1000.).to_string(),
+        );
+        metric(
+            "wtf_next_block_cutoff_sat_vb",
+            "gauge",
+            "Fee rate the next projected block takes, with congestion alerts on",
+            (load(&self.next_block_cutoff_msat_vb) as f64 / 1000.).to_string(),
+        );
+        metric(
+            "wtf_fee_spikes_total",
+            "counter",
+            "Fee spikes the congestion detector noticed",
+            load(&self.fee_spikes).to_string(),
+        );
+        metric(
+            "wtf_mempool_clearings_total",
+            "counter",
+            "Mempool clearings the congestion detector noticed",
+            load(&self.clearings).to_string(),
+        );
+        metric(
+            "wtf_purged_transactions_total",
+            "counter",
+            "Transactions the node evicted from its full mempool, they never confirm",
+            load(&self.purged).to_string(),
+        );
+        metric(
+            "wtf_purged_vsize_total",
+            "counter",
+            "Virtual size the node evicted from its full mempool",
+            load(&self.purged_vsize).to_string(),
+        );
+        out
+    }
+
+    /// Alive while snapshots keep coming, or a new recorder hasn't been running for long,
+    /// ready once it has a recent one and the node answers
+    pub fn health(&self, now: i64) -> Health {
+        let max_age_secs = (STALE_INTERVALS * self.interval_secs as i64).max(MIN_STALE_SECS);
+        let ago = |timestamp: &AtomicI64| match timestamp.load(Ordering::Relaxed) {
+            0 => None,
+            timestamp => Some(now - timestamp),
+        };
+        let node_answered_secs_ago = ago(&self.node_answered_timestamp);
+        let last_snapshot_secs_ago = ago(&self.last_snapshot_timestamp);
+        let recent = |ago: Option<i64>| ago.is_some_and(|ago| ago <= max_age_secs);
+        let ready = recent(last_snapshot_secs_ago) && recent(node_answered_secs_ago);
+        Health {
+            alive: ready || now - self.started_timestamp <= max_age_secs,
+            ready,
+            node_answered_secs_ago,
+            last_snapshot_secs_ago,
+            dataset_age_secs: None,
+            max_age_secs,
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn serve(self: Arc<Self>, listen: SocketAddr) -> Result<()> {
+        let app = Router::new()
+            .route("/metrics", get(Self::metrics))
+            .route("/healthz", get(Self::healthz))
+            .route("/readyz", get(Self::readyz))
+            .with_state(self);
+
+        info!("serving metrics on {listen}");
+        axum::Server::bind(&listen)
+            .serve(app.into_make_service())
+            .await?;
+        Ok(())
+    }
+
+    async fn metrics(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+        (
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            metrics.render(),
+        )
+    }
+
+    async fn healthz(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+        metrics.health(Utc::now().timestamp()).healthz()
+    }
+
+    async fn readyz(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+        metrics.health(Utc::now().timestamp()).readyz()
+    }
+}
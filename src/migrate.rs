@@ -0,0 +1,230 @@
+use crate::{
+    dataset::{FileKind, SnapshotFile},
+    manifest::MANIFEST_FILE,
+    storage::{LocalStorage, Storage, SCHEMA_VERSION, SCHEMA_VERSION_COLUMN},
+};
+use anyhow::{Context, Result};
+use polars::prelude::*;
+use serde::Serialize;
+use std::path::Path;
+use tracing::debug;
+
+/// Removal reason of schema 2 that 3 calls `purged`
+const PURGED_BEFORE: &str = "evicted-below-minfee";
+
+/// What `wtf migrate` found and did
+#[derive(Debug, Default, Serialize)]
+pub struct Migration {
+    pub files: usize,
+    /// Written with an older schema, rewritten unless it was a dry run
+    pub outdated: usize,
+    /// Of those, recorded before files went below a directory per network
+    pub moved: usize,
+}
+
+/// Upgrades recorded files to [`SCHEMA_VERSION`], rewriting them in place
+pub struct Migrate;
+
+impl Migrate {
+    /// The schema version `frame` was written with
+    pub fn version(frame: &DataFrame) -> Result<u32> {
+        let Ok(column) = frame.column(SCHEMA_VERSION_COLUMN) else {
+            return Ok(1);
+        };
+        // an empty file has the column at least, it came with version 2
+        Ok(column.u32()?.into_iter().flatten().min().unwrap_or(2))
+    }
+
+    /// Upgrade every outdated file of the dataset, including those the first recorder wrote to
+    /// `{data_dir}/YYYY/MM/DD`, which move below the directory of `storage`'s network. With
+    /// `dry_run` nothing is written.
+    pub fn run(storage: &LocalStorage, data_dir: &Path, dry_run: bool) -> Result<Migration> {
+        let mut migration = Migration::default();
+        for file in storage.list()? {
+            migration.files += 1;
+            let frame = storage.read(&file)?;
+            if Self::version(&frame)? >= SCHEMA_VERSION {
+                continue;
+            }
+            migration.outdated += 1;
+            if !dry_run {
+                Self::rewrite(storage, &file, frame)?;
+                debug!("upgraded {}", file.path.display());
+            }
+        }
+        for file in Self::legacy(data_dir)? {
+            migration.files += 1;
+            migration.outdated += 1;
+            migration.moved += 1;
+            if dry_run {
+                continue;
+            }
+            let reader = std::fs::File::open(&file.path)
+                .with_context(|| format!("opening {}", file.path.display()))?;
+            let frame = ParquetReader::new(reader).finish()?;
+            let written = Self::rewrite(storage, &file, frame)?;
+            std::fs::remove_file(&file.path)?;
+            Self::remove_empty(file.path.parent(), data_dir);
+            debug!(
+                "moved {} to {}",
+                file.path.display(),
+                written.path.display()
+            );
+        }
+        Ok(migration)
+    }
+
+    /// Move every file of `from` to where `to` keeps it, the files themselves stay as they
+    /// are. Returns how many there were.
+    pub fn relayout(from: &LocalStorage, to: &LocalStorage, dry_run: bool) -> Result<usize> {
+        let files = from.list()?;
+        if dry_run {
+            return Ok(files.len());
+        }
+        for file in &files {
+            let path = to.path_for(file.written_at(), file.height, file.kind);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::rename(&file.path, &path)
+                .with_context(|| format!("moving {}", file.path.display()))?;
+            Self::remove_empty(file.path.parent(), from.root());
+            debug!("moved {} to {}", file.path.display(), path.display());
+        }
+        // listed by file name, which stays the same
+        let manifest = from.root().join(MANIFEST_FILE);
+        if manifest.exists() {
+            std::fs::create_dir_all(to.root())?;
+            std::fs::rename(&manifest, to.root().join(MANIFEST_FILE))
+                .with_context(|| format!("moving {}", manifest.display()))?;
+        }
+        let _ = std::fs::remove_dir(from.root());
+        Ok(files.len())
+    }
+
+    fn rewrite(
+        storage: &LocalStorage,
+        file: &SnapshotFile,
+        frame: DataFrame,
+    ) -> Result<SnapshotFile> {
+        let mut frame = Self::upgrade(frame, file.kind)?;
+        // tags the current version
+        storage.write(file.written_at(), file.height, file.kind, &mut frame)
+    }
+
+    /// `frame` of a `kind` file with the columns of the current schema
+    pub fn upgrade(frame: DataFrame, kind: FileKind) -> Result<DataFrame> {
+        let version = Self::version(&frame)?;
+        let mut frame = frame;
+        // 1 to 2: full and delta files get every column derivable from the first four, nulls
+        // for what the node was never asked about
+        if version < 2 && matches!(kind, FileKind::Full | FileKind::Delta | FileKind::Compact) {
+            frame = Self::delta_columns(frame)?;
+        }
+        // 2 to 3: purged removals were labelled by what the node's minimum fee did to them
+        if version < 3 && matches!(kind, FileKind::Full | FileKind::Delta | FileKind::Compact) {
+            frame = Self::rename_purged(frame)?;
+        }
+        Ok(frame)
+    }
+
+    fn rename_purged(mut frame: DataFrame) -> Result<DataFrame> {
+        let Ok(reasons) = frame.column("removal_reason") else {
+            return Ok(frame);
+        };
+        let mut renamed = reasons
+            .utf8()?
+            .into_iter()
+            .map(|reason| match reason {
+                Some(PURGED_BEFORE) => Some("purged"),
+                reason => reason,
+            })
+            .collect::<Utf8Chunked>()
+            .into_series();
+        renamed.rename("removal_reason");
+        frame.with_column(renamed)?;
+        Ok(frame)
+    }
+
+    fn delta_columns(frame: DataFrame) -> Result<DataFrame> {
+        let weights: Vec<Option<f64>> = frame.column("weight")?.f64()?.into_iter().collect();
+        let fees: Vec<Option<f64>> = frame.column("fee_sat")?.f64()?.into_iter().collect();
+        // removals were written with negative weight
+        let rate = |divisor: f64| -> Vec<Option<f64>> {
+            weights
+                .iter()
+                .zip(&fees)
+                .map(|(weight, fee)| Some(fee.as_ref()? / (weight.as_ref()?.abs() / divisor)))
+                .collect()
+        };
+        let n = frame.height();
+        let derived = [
+            Series::new("block_hash", vec![None::<&str>; n]),
+            Series::new(
+                "removal_reason",
+                weights
+                    .iter()
+                    .map(|weight| weight.filter(|w| *w < 0.).map(|_| "unknown"))
+                    .collect::<Vec<_>>(),
+            ),
+            Series::new("fee_rate_sat_vb", rate(4.)),
+            Series::new("fee_rate_sat_wu", rate(1.)),
+            Series::new("effective_fee_rate", vec![None::<f64>; n]),
+            Series::new("queue_percentile", vec![None::<f64>; n]),
+            Series::new("replaces_txid", vec![None::<&str>; n]),
+            Series::new(
+                "vsize",
+                weights
+                    .iter()
+                    .map(|weight| weight.map(|w| (w.abs() / 4.).ceil() as u64))
+                    .collect::<Vec<_>>(),
+            ),
+            Series::new("ancestor_count", vec![None::<u64>; n]),
+            Series::new("descendant_count", vec![None::<u64>; n]),
+            Series::new("ancestor_fees", vec![None::<f64>; n]),
+            Series::new("descendant_fees", vec![None::<f64>; n]),
+            Series::new("bip125_replaceable", vec![None::<bool>; n]),
+            Series::new("unbroadcast", vec![None::<bool>; n]),
+        ];
+        let mut frame = frame;
+        for column in derived {
+            if frame.column(column.name()).is_err() {
+                frame.with_column(column)?;
+            }
+        }
+        Ok(frame)
+    }
+
+    /// Remove `dir` and its parents below `data_dir` for as long as they are empty
+    fn remove_empty(mut dir: Option<&Path>, data_dir: &Path) {
+        while let Some(path) = dir.filter(|path| *path != data_dir) {
+            if std::fs::remove_dir(path).is_err() {
+                break;
+            }
+            dir = path.parent();
+        }
+    }
+
+    /// Files below `{data_dir}/YYYY`, where they went before the network was part of the path
+    fn legacy(data_dir: &Path) -> Result<Vec<SnapshotFile>> {
+        let mut paths = Vec::new();
+        if data_dir.exists() {
+            for entry in std::fs::read_dir(data_dir)? {
+                let path = entry?.path();
+                let is_year = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| {
+                        name.len() == 4 && name.bytes().all(|b| b.is_ascii_digit())
+                    });
+                if path.is_dir() && is_year {
+                    LocalStorage::find(&path, &mut paths)?;
+                }
+            }
+        }
+        let mut files: Vec<SnapshotFile> = paths
+            .iter()
+            .filter_map(|path| SnapshotFile::parse(path))
+            .collect();
+        SnapshotFile::sort(&mut files);
+        Ok(files)
+    }
+}
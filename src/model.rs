@@ -0,0 +1,556 @@
+use crate::{
+    backtest::Backtest,
+    calc::{Calc, MIN_RELAY_FEE_RATE},
+    onnx::OnnxModel,
+    replay::{Replay, Snapshot},
+    seasonal::Seasonality,
+    storage::Storage,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use bitcoin::Txid;
+use polars::prelude::{DataFrame, TakeRandom};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path};
+use tracing::info;
+
+/// Transactions seen this recently are fitted
+const FIT_SECS: i64 = 2 * 24 * 60 * 60;
+/// Fee rate percentile and depth of the transactions are taken from snapshots this far apart
+const SAMPLE_SECS: i64 = 60;
+/// Fee rate percentiles are binned in steps of 1 / `PERCENTILE_BINS`
+const PERCENTILE_BINS: usize = 10;
+/// Upper bounds of the depth bins in blocks of virtual size ahead, the last bin is open
+const DEPTH_BOUNDS: [f64; 5] = [0.5, 1., 2., 4., 8.];
+const DEPTH_BINS: usize = DEPTH_BOUNDS.len() + 1;
+/// Transaction-blocks at risk a bin needs before its hazard rate is trusted
+const MIN_EXPOSURE: u64 = 50;
+/// Virtual size of a full block
+const BLOCK_VSIZE: f64 = 1_000_000.;
+/// Seconds between the snapshots quantile regression is fitted to
+pub const TRAIN_EVERY_SECS: i64 = 600;
+/// Scored snapshots a quantile regression needs per target
+const MIN_TRAIN_SAMPLES: usize = 10;
+/// Rounds of iteratively reweighted least squares
+const IRLS_ROUNDS: usize = 50;
+/// Added to the diagonal so features that never changed get no weight instead of no solution
+const RIDGE: f64 = 1e-6;
+/// Intercept, log of the projected cutoff and log of the mempool size in blocks
+const FEATURES: usize = 3;
+
+/// How `calc` estimates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Model {
+    /// Fee rate that made it into the projected blocks in the snapshots of the last hour
+    #[default]
+    Cutoff,
+    /// Confirmation hazard rates fitted to the recorded waits by fee rate percentile and
+    /// mempool depth, which also see the transactions that arrive while waiting
+    Survival,
+    /// Linear quantile regression of the fee rate the following blocks took on the projected
+    /// cutoff and the size of the mempool
+    Quantile,
+    /// Projected cutoff scaled by the time-of-week seasonality of the recorded history
+    Seasonal,
+}
+
+/// Where a transaction stood in a snapshot
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Position {
+    pub(crate) fee_rate: f64,
+    /// Share of the mempool paying less
+    pub(crate) percentile: f64,
+    /// Virtual size paying more, in blocks
+    pub(crate) depth: f64,
+}
+
+/// A transaction from the first sample it was in
+#[derive(Debug, Clone, Copy)]
+struct Seen {
+    height: u64,
+    last_height: u64,
+    bin: (usize, usize),
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Bin {
+    confirmed: u64,
+    /// Blocks the transactions waited through, the one that confirmed them included
+    exposure: u64,
+}
+
+impl Bin {
+    fn hazard(&self) -> Option<f64> {
+        (self.exposure >= MIN_EXPOSURE).then(|| self.confirmed as f64 / self.exposure as f64)
+    }
+}
+
+/// Discrete-time survival model of the wait for confirmation. The hazard rate, the chance to
+/// confirm in the next block while still waiting, is fitted per fee rate percentile and mempool
+/// depth bin from the transactions recorded during the last two days: those confirmed count as
+/// events, those still waiting or dropped are censored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Survival {
+    bins: [[Bin; DEPTH_BINS]; PERCENTILE_BINS],
+    /// Transactions the model was fitted to
+    pub transactions: usize,
+}
+
+impl Survival {
+    #[tracing::instrument(skip(storage))]
+    pub fn fit(storage: &dyn Storage) -> Result<Self> {
+        let replay = Replay::new(storage)?;
+        let Some(latest) = replay.latest() else {
+            bail!("no recorded snapshots found");
+        };
+        let from = latest - FIT_SECS;
+        let confirmed: HashMap<Txid, u64> = Calc::confirmations(storage, from)?
+            .into_iter()
+            .map(|c| (c.txid, c.height))
+            .collect();
+
+        let mut seen: HashMap<String, Seen> = HashMap::new();
+        let mut next_sample = i64::MIN;
+        replay.walk(from, latest, |snapshot| {
+            if snapshot.timestamp < next_sample {
+                return Ok(());
+            }
+            next_sample = snapshot.timestamp + SAMPLE_SECS;
+            for (txid, position) in Self::positions(snapshot) {
+                seen.entry(txid.clone())
+                    .and_modify(|seen| seen.last_height = snapshot.height)
+                    .or_insert(Seen {
+                        height: snapshot.height,
+                        last_height: snapshot.height,
+                        bin: Self::bin(&position),
+                    });
+            }
+            Ok(())
+        })?;
+
+        let mut bins = [[Bin::default(); DEPTH_BINS]; PERCENTILE_BINS];
+        for (txid, seen) in &seen {
+            let bin = &mut bins[seen.bin.0][seen.bin.1];
+            match txid
+                .parse::<Txid>()
+                .ok()
+                .and_then(|txid| confirmed.get(&txid))
+            {
+                Some(&height) if height > seen.height => {
+                    bin.confirmed += 1;
+                    bin.exposure += height - seen.height;
+                }
+                // confirmed before it was sampled
+                Some(_) => {}
+                None => bin.exposure += seen.last_height - seen.height,
+            }
+        }
+        info!(
+            "transactions: {}, confirmed: {}",
+            seen.len(),
+            bins.iter().flatten().map(|b| b.confirmed).sum::<u64>()
+        );
+        Ok(Survival {
+            bins,
+            transactions: seen.len(),
+        })
+    }
+
+    /// Lowest fee rate (sat/vB) of `snapshot` that confirms within `target` blocks with
+    /// probability `confidence`. Going down from the highest fee rate, it stops at the first
+    /// one that falls short; bins with too little data are skipped.
+    pub fn estimate(&self, snapshot: &Snapshot, target: u32, confidence: f64) -> Option<f64> {
+        let positions = Self::positions(snapshot);
+        if positions.is_empty() {
+            return Some(MIN_RELAY_FEE_RATE);
+        }
+        let mut estimate = None;
+        for (_, position) in positions {
+            let Some(hazard) = self.hazard(&position) else {
+                continue;
+            };
+            if 1. - (1. - hazard).powi(target as i32) < confidence {
+                break;
+            }
+            estimate = Some(position.fee_rate.max(MIN_RELAY_FEE_RATE));
+        }
+        estimate
+    }
+
+    /// Hazard rate of the bin, or of all depths at the percentile when the bin has too little
+    fn hazard(&self, position: &Position) -> Option<f64> {
+        let (percentile, depth) = Self::bin(position);
+        self.bins[percentile][depth].hazard().or_else(|| {
+            let pooled = self.bins[percentile]
+                .iter()
+                .fold(Bin::default(), |pooled, bin| Bin {
+                    confirmed: pooled.confirmed + bin.confirmed,
+                    exposure: pooled.exposure + bin.exposure,
+                });
+            pooled.hazard()
+        })
+    }
+
+    fn bin(position: &Position) -> (usize, usize) {
+        let percentile =
+            ((position.percentile * PERCENTILE_BINS as f64) as usize).min(PERCENTILE_BINS - 1);
+        let depth = DEPTH_BOUNDS.partition_point(|bound| *bound <= position.depth);
+        (percentile, depth)
+    }
+
+    /// Transactions of `snapshot`, highest fee rate first
+    pub(crate) fn positions(snapshot: &Snapshot) -> Vec<(&String, Position)> {
+        let mut entries: Vec<(&String, f64, f64)> = snapshot
+            .transactions
+            .iter()
+            .filter(|(_, tx)| tx.weight > 0.)
+            .map(|(txid, tx)| (txid, tx.fee_rate_sat_vb(), tx.weight / 4.))
+            .collect();
+        entries.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let count = entries.len() as f64;
+        let mut ahead = 0.;
+        entries
+            .into_iter()
+            .enumerate()
+            .map(|(rank, (txid, fee_rate, vsize))| {
+                let position = Position {
+                    fee_rate,
+                    percentile: (count - 1. - rank as f64) / count,
+                    depth: ahead / BLOCK_VSIZE,
+                };
+                ahead += vsize;
+                (txid, position)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct QuantileFit {
+    pub(crate) target: u32,
+    pub(crate) confidence: f64,
+    pub(crate) coefficients: [f64; FEATURES],
+}
+
+/// Log of the fee rate needed to confirm within a target, the lowest floor of the blocks that
+/// followed as scored by `backtest`, regressed on features of the mempool at the time. One fit
+/// per target and confidence, each the `confidence` quantile.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuantileRegression {
+    pub(crate) fits: Vec<QuantileFit>,
+    /// Snapshots the model was fitted to
+    pub samples: usize,
+}
+
+impl QuantileRegression {
+    /// Fit every pair of `targets` and `confidences` to snapshots `every_secs` apart
+    #[tracing::instrument(skip(storage))]
+    pub fn fit(
+        storage: &dyn Storage,
+        targets: &[u32],
+        confidences: &[f64],
+        every_secs: i64,
+    ) -> Result<Self> {
+        if every_secs <= 0 {
+            bail!("samples must be at least a second apart");
+        }
+        let replay = Replay::new(storage)?;
+        let mut samples: Vec<(u64, Vec<[f64; FEATURES]>)> = Vec::new();
+        let mut next_sample = i64::MIN;
+        replay.walk(i64::MIN, i64::MAX, |snapshot| {
+            if snapshot.timestamp < next_sample {
+                return Ok(());
+            }
+            next_sample = snapshot.timestamp + every_secs;
+            let features = targets
+                .iter()
+                .map(|target| Self::features(snapshot, *target))
+                .collect();
+            samples.push((snapshot.height, features));
+            Ok(())
+        })?;
+        Self::fit_samples(storage, &samples, targets, confidences)
+    }
+
+    /// Fit to the rows of a table written by `wtf features` instead of replaying the dataset,
+    /// which is only read for the blocks that followed. Targets need a `cutoff_TARGET` column.
+    #[tracing::instrument(skip(storage, table))]
+    pub fn fit_table(
+        storage: &dyn Storage,
+        table: &DataFrame,
+        targets: &[u32],
+        confidences: &[f64],
+    ) -> Result<Self> {
+        let heights = table.column("height")?.u64()?;
+        let vsizes = table.column("mempool_vsize")?.f64()?;
+        let mut cutoffs = Vec::new();
+        for target in targets {
+            let column = table
+                .column(&format!("cutoff_{target}"))
+                .map_err(|_| anyhow!("the features table has no cutoff for target {target}"))?;
+            cutoffs.push(column.f64()?);
+        }
+        let samples: Vec<(u64, Vec<[f64; FEATURES]>)> = (0..table.height())
+            .filter_map(|row| {
+                let (height, vsize) = (heights.get(row)?, vsizes.get(row)?);
+                let features = cutoffs
+                    .iter()
+                    .map(|cutoff| Some(Self::row(cutoff.get(row)?, vsize)))
+                    .collect::<Option<_>>()?;
+                Some((height, features))
+            })
+            .collect();
+        Self::fit_samples(storage, &samples, targets, confidences)
+    }
+
+    /// Fit the features of each sample, one per target, to what the blocks after its height
+    /// needed
+    fn fit_samples(
+        storage: &dyn Storage,
+        samples: &[(u64, Vec<[f64; FEATURES]>)],
+        targets: &[u32],
+        confidences: &[f64],
+    ) -> Result<Self> {
+        let floors = Backtest::floors(storage)?;
+        let mut fits = Vec::new();
+        for (index, &target) in targets.iter().enumerate() {
+            let rows: Vec<([f64; FEATURES], f64)> = samples
+                .iter()
+                .filter_map(|(height, features)| {
+                    let needed = Backtest::needed(&floors, *height, target)?;
+                    Some((features[index], needed.max(MIN_RELAY_FEE_RATE).ln()))
+                })
+                .collect();
+            if rows.len() < MIN_TRAIN_SAMPLES {
+                bail!(
+                    "{} snapshots could be scored for target {target}, {MIN_TRAIN_SAMPLES} are needed",
+                    rows.len()
+                );
+            }
+            for &confidence in confidences {
+                fits.push(QuantileFit {
+                    target,
+                    confidence,
+                    coefficients: Self::regress(&rows, confidence),
+                });
+            }
+        }
+        info!("samples: {}, fits: {}", samples.len(), fits.len());
+        Ok(QuantileRegression {
+            fits,
+            samples: samples.len(),
+        })
+    }
+
+    /// Fee rate (sat/vB) for `target` at `confidence` in `snapshot`, `None` if the target
+    /// wasn't fitted or `confidence` lies outside the fitted ones. Separately fitted quantiles
+    /// may cross, so each is raised to the ones below it; between two fitted confidences the
+    /// log fee rate is interpolated.
+    pub fn estimate(&self, snapshot: &Snapshot, target: u32, confidence: f64) -> Option<f64> {
+        let mut fits: Vec<&QuantileFit> = self
+            .fits
+            .iter()
+            .filter(|fit| fit.target == target)
+            .collect();
+        fits.sort_by(|a, b| a.confidence.total_cmp(&b.confidence));
+        let features = Self::features(snapshot, target);
+        let mut band: Vec<(f64, f64)> = Vec::with_capacity(fits.len());
+        for fit in fits {
+            let log_fee_rate: f64 = features
+                .iter()
+                .zip(fit.coefficients)
+                .map(|(feature, coefficient)| feature * coefficient)
+                .sum();
+            let below = band.last().map_or(f64::NEG_INFINITY, |(_, log)| *log);
+            band.push((fit.confidence, log_fee_rate.max(below)));
+        }
+
+        let above = band.partition_point(|(fitted, _)| *fitted < confidence);
+        let (high, log_high) = *band.get(above)?;
+        let log_fee_rate = match above.checked_sub(1).map(|below| band[below]) {
+            Some((low, log_low)) if high > confidence => {
+                log_low + (log_high - log_low) * (confidence - low) / (high - low)
+            }
+            _ if high > confidence => return None,
+            _ => log_high,
+        };
+        Some(log_fee_rate.exp().max(MIN_RELAY_FEE_RATE))
+    }
+
+    pub(crate) fn features(snapshot: &Snapshot, target: u32) -> [f64; FEATURES] {
+        let vsize: f64 = snapshot
+            .transactions
+            .values()
+            .map(|tx| tx.weight / 4.)
+            .sum();
+        Self::row(Calc::block_cutoff(snapshot, target), vsize)
+    }
+
+    /// Features of a mempool of `vsize` whose projected cutoff for the target is `cutoff`
+    fn row(cutoff: f64, vsize: f64) -> [f64; FEATURES] {
+        [1., cutoff.ln(), (1. + vsize / BLOCK_VSIZE).ln()]
+    }
+
+    /// Minimise the pinball loss of `quantile` by iteratively reweighted least squares, each
+    /// residual weighted by its side of the quantile over its size
+    fn regress(rows: &[([f64; FEATURES], f64)], quantile: f64) -> [f64; FEATURES] {
+        let mut coefficients = [0.; FEATURES];
+        for _ in 0..IRLS_ROUNDS {
+            let mut xtx = [[0.; FEATURES]; FEATURES];
+            let mut xty = [0.; FEATURES];
+            for (x, y) in rows {
+                let residual = y - x.iter().zip(coefficients).map(|(x, c)| x * c).sum::<f64>();
+                let side = if residual >= 0. {
+                    quantile
+                } else {
+                    1. - quantile
+                };
+                let weight = side / residual.abs().max(1e-6);
+                for i in 0..FEATURES {
+                    xty[i] += weight * x[i] * y;
+                    for j in 0..FEATURES {
+                        xtx[i][j] += weight * x[i] * x[j];
+                    }
+                }
+            }
+            for (i, row) in xtx.iter_mut().enumerate() {
+                row[i] += RIDGE;
+            }
+            match Self::solve(xtx, xty) {
+                Some(next) => coefficients = next,
+                None => break,
+            }
+        }
+        coefficients
+    }
+
+    /// Solve `a x = b` by Gaussian elimination with partial pivoting
+    #[allow(clippy::needless_range_loop)]
+    fn solve(
+        mut a: [[f64; FEATURES]; FEATURES],
+        mut b: [f64; FEATURES],
+    ) -> Option<[f64; FEATURES]> {
+        for column in 0..FEATURES {
+            let pivot = (column..FEATURES)
+                .max_by(|i, j| a[*i][column].abs().total_cmp(&a[*j][column].abs()))?;
+            if a[pivot][column].abs() < f64::EPSILON {
+                return None;
+            }
+            a.swap(column, pivot);
+            b.swap(column, pivot);
+            for row in column + 1..FEATURES {
+                let factor = a[row][column] / a[column][column];
+                for k in column..FEATURES {
+                    a[row][k] -= factor * a[column][k];
+                }
+                b[row] -= factor * b[column];
+            }
+        }
+        let mut x = [0.; FEATURES];
+        for row in (0..FEATURES).rev() {
+            let rest: f64 = (row + 1..FEATURES).map(|k| a[row][k] * x[k]).sum();
+            x[row] = (b[row] - rest) / a[row][row];
+        }
+        Some(x)
+    }
+}
+
+/// A model fitted by `wtf train` and saved to a model file, so estimating only needs the
+/// latest snapshot. Model files ending in `.onnx` are ONNX, the others JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "model", rename_all = "lowercase")]
+pub enum Trained {
+    Survival(Box<Survival>),
+    Quantile(QuantileRegression),
+    Seasonal(Box<Seasonality>),
+    /// Loaded from ONNX, ours or trained elsewhere
+    #[serde(skip)]
+    Onnx(OnnxModel),
+}
+
+impl Trained {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Trained::Survival(_) => "survival",
+            Trained::Quantile(_) => "quantile",
+            Trained::Seasonal(_) => "seasonal",
+            Trained::Onnx(_) => "onnx",
+        }
+    }
+
+    /// Fit `model` to the recorded dataset. Quantile regression is fitted for every pair of
+    /// `targets` and `confidences`, the survival and seasonal models answer any of them.
+    pub fn train(
+        storage: &dyn Storage,
+        model: Model,
+        targets: &[u32],
+        confidences: &[f64],
+        every_secs: i64,
+    ) -> Result<Self> {
+        match model {
+            Model::Cutoff => {
+                bail!("the cutoff model estimates from the snapshots, there is nothing to train")
+            }
+            Model::Survival => Ok(Trained::Survival(Box::new(Survival::fit(storage)?))),
+            Model::Quantile => Ok(Trained::Quantile(QuantileRegression::fit(
+                storage,
+                targets,
+                confidences,
+                every_secs,
+            )?)),
+            Model::Seasonal => Ok(Trained::Seasonal(Box::new(Seasonality::fit(
+                storage, every_secs,
+            )?))),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        if Self::is_onnx(path) {
+            return Ok(Trained::Onnx(OnnxModel::load(path)?));
+        }
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("reading model file {}", path.display()))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("parsing model file {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if Self::is_onnx(path) {
+            return match self {
+                Trained::Quantile(model) => OnnxModel::export(model, path),
+                _ => bail!("only quantile regression can be exported to ONNX"),
+            };
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("writing model file {}", path.display()))
+    }
+
+    /// Fee rate (sat/vB) confirming within `target` blocks at `confidence` in `snapshot`
+    pub fn estimate(&self, snapshot: &Snapshot, target: u32, confidence: f64) -> Result<f64> {
+        if !(confidence > 0. && confidence <= 1.) {
+            bail!("confidence must be in (0, 1], got {confidence}");
+        }
+        if target == 0 {
+            bail!("target must be at least one block");
+        }
+        match self {
+            Trained::Survival(model) => model.estimate(snapshot, target, confidence).ok_or_else(|| {
+                anyhow!(
+                    "no fee rate in the mempool confirms within {target} blocks at {confidence} with hazard rates fitted to {} transactions",
+                    model.transactions
+                )
+            }),
+            Trained::Quantile(model) => model.estimate(snapshot, target, confidence).ok_or_else(|| {
+                anyhow!("no fit for {target} blocks around {confidence}, train with `-t {target} -c {confidence}`")
+            }),
+            Trained::Seasonal(model) => Ok(model.estimate(snapshot, target, confidence)),
+            Trained::Onnx(model) => model.estimate(snapshot, target, confidence),
+        }
+    }
+
+    fn is_onnx(path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|extension| extension == "onnx")
+    }
+}
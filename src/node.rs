@@ -0,0 +1,251 @@
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use bitcoin::{
+    consensus::{deserialize, Decodable},
+    Block, BlockHash, Transaction, Txid,
+};
+use bitcoincore_rest::{
+    responses::{GetBlockchainInfoResult, GetMempoolEntryResult, GetMempoolInfoResult},
+    GetMempoolTxidsAndSequenceResult,
+};
+use bytes::Bytes;
+use futures_util::{stream, StreamExt};
+use hyper::Response;
+use hyperlocal::{UnixConnector, Uri};
+use reqwest::{Client, Proxy, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    time::Duration,
+};
+use tracing::warn;
+
+/// Transactions fetched over REST at once
+const REST_TX_CONCURRENCY: usize = 16;
+/// Time a snapshot spends fetching transactions over REST at most, well within an interval
+const REST_TX_BUDGET: Duration = Duration::from_secs(5);
+
+/// Where mempools come from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeSource {
+    /// Bitcoin Core's REST or JSON-RPC
+    #[default]
+    Core,
+    /// A peer on the Bitcoin P2P network, experimental
+    P2p,
+}
+
+/// The subset of Bitcoin Core's interface the recorder relies on, independent of transport
+#[async_trait]
+pub trait Node: Send + Sync {
+    async fn get_chain_info(&self) -> Result<GetBlockchainInfoResult>;
+    async fn get_mempool(&self) -> Result<HashMap<Txid, GetMempoolEntryResult>>;
+    async fn get_mempool_info(&self) -> Result<GetMempoolInfoResult>;
+    async fn get_mempool_txids_and_sequence(&self) -> Result<GetMempoolTxidsAndSequenceResult>;
+    /// Entries for `txids`, transactions that left the mempool in the meantime are omitted
+    async fn get_mempool_entries(
+        &self,
+        txids: &[Txid],
+    ) -> Result<HashMap<Txid, GetMempoolEntryResult>>;
+    /// Transactions `txids` from the mempool, those that left it in the meantime are omitted
+    async fn get_transactions(&self, txids: &[Txid]) -> Result<HashMap<Txid, Transaction>>;
+    async fn get_block_hash(&self, height: u64) -> Result<BlockHash>;
+    async fn get_block(&self, hash: &BlockHash) -> Result<Block>;
+    /// Bitcoin Core's own `estimatesmartfee` in sat/vB for each of `targets`, `None` where it
+    /// has no estimate. Only JSON-RPC offers it.
+    async fn estimate_smart_fees(&self, _targets: &[u32]) -> Result<Vec<Option<f64>>> {
+        Err(anyhow!(
+            "estimatesmartfee is only available through JSON-RPC"
+        ))
+    }
+    /// The minimum fee rate each peer of the node advertises with `feefilter`, sat/vB. Only
+    /// JSON-RPC offers `getpeerinfo`.
+    async fn get_peer_fee_filters(&self) -> Result<Vec<f64>> {
+        Err(anyhow!("getpeerinfo is only available through JSON-RPC"))
+    }
+    /// Which node answered the last call, if there is a choice
+    fn source(&self) -> Option<String> {
+        None
+    }
+}
+
+/// HTTP client for REST and JSON-RPC, connecting through `proxy` if given. Only SOCKS5 proxies
+/// are supported, they resolve host names themselves so Tor can reach .onion addresses.
+pub fn http_client(proxy: Option<&str>) -> Result<Client> {
+    let Some(proxy) = proxy else {
+        return Ok(Client::new());
+    };
+    let url = match proxy.split_once("://") {
+        Some(("socks5" | "socks5h", address)) => format!("socks5h://{address}"),
+        _ => bail!("unsupported proxy {proxy}, expected socks5://host:port"),
+    };
+    let proxy = Proxy::all(url).with_context(|| format!("invalid proxy {proxy}"))?;
+    Ok(Client::builder().proxy(proxy).build()?)
+}
+
+/// REST on TCP goes through reqwest, which has no unix socket support
+#[derive(Clone)]
+enum Transport {
+    Http(Client),
+    Unix(hyper::Client<UnixConnector>, PathBuf),
+}
+
+/// Bitcoin Core REST client over HTTP or a unix socket
+#[derive(Clone)]
+pub struct RestClient {
+    transport: Transport,
+    endpoint: String,
+}
+
+impl RestClient {
+    /// `endpoint` is in the format `"http://{host}:{port}/rest/"`, or `"unix:///path/to/socket"`
+    /// for REST served on a local socket, e.g. by a socat bridge. `client` is only used for HTTP.
+    pub fn new(endpoint: impl Into<String>, client: Client) -> Self {
+        let endpoint = endpoint.into();
+        let transport = match endpoint.strip_prefix("unix://") {
+            Some(socket) => Transport::Unix(
+                hyper::Client::builder().build(UnixConnector),
+                PathBuf::from(socket),
+            ),
+            None => Transport::Http(client),
+        };
+        RestClient {
+            transport,
+            endpoint,
+        }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// GET `path` below the REST root, whatever the status
+    pub async fn get(&self, path: &str) -> Result<Response<Bytes>> {
+        match &self.transport {
+            Transport::Http(client) => {
+                let url = format!("{}{}", self.endpoint, path);
+                let response = client
+                    .get(&url)
+                    .send()
+                    .await
+                    .with_context(|| format!("GET {url}"))?;
+                let mut builder = Response::builder().status(response.status());
+                for (name, value) in response.headers() {
+                    builder = builder.header(name, value);
+                }
+                Ok(builder.body(response.bytes().await?)?)
+            }
+            Transport::Unix(client, socket) => {
+                let uri = Uri::new(socket, &format!("/rest/{path}"));
+                let response = client
+                    .get(uri.into())
+                    .await
+                    .with_context(|| format!("GET /rest/{path} on {}", socket.display()))?;
+                let (parts, body) = response.into_parts();
+                Ok(Response::from_parts(
+                    parts,
+                    hyper::body::to_bytes(body).await?,
+                ))
+            }
+        }
+    }
+
+    async fn get_ok(&self, path: &str) -> Result<Bytes> {
+        let response = self.get(path).await?;
+        if response.status() != StatusCode::OK {
+            bail!("GET {path} returned {}", response.status());
+        }
+        Ok(response.into_body())
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let body = self.get_ok(path).await?;
+        serde_json::from_slice(&body).with_context(|| format!("decoding {path}"))
+    }
+
+    async fn get_bin<T: Decodable>(&self, path: &str) -> Result<T> {
+        let body = self.get_ok(path).await?;
+        deserialize(&body).with_context(|| format!("decoding {path}"))
+    }
+}
+
+#[async_trait]
+impl Node for RestClient {
+    async fn get_chain_info(&self) -> Result<GetBlockchainInfoResult> {
+        self.get_json("chaininfo.json").await
+    }
+
+    async fn get_mempool(&self) -> Result<HashMap<Txid, GetMempoolEntryResult>> {
+        self.get_json("mempool/contents.json").await
+    }
+
+    async fn get_mempool_info(&self) -> Result<GetMempoolInfoResult> {
+        self.get_json("mempool/info.json").await
+    }
+
+    async fn get_mempool_txids_and_sequence(&self) -> Result<GetMempoolTxidsAndSequenceResult> {
+        self.get_json("mempool/contents.json?mempool_sequence=true&verbose=false")
+            .await
+    }
+
+    /// REST has no per-entry endpoint, so this still loads the verbose mempool
+    async fn get_mempool_entries(
+        &self,
+        txids: &[Txid],
+    ) -> Result<HashMap<Txid, GetMempoolEntryResult>> {
+        if txids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let mut mempool = self.get_mempool().await?;
+        let wanted: HashSet<&Txid> = txids.iter().collect();
+        mempool.retain(|txid, _| wanted.contains(txid));
+        Ok(mempool)
+    }
+
+    /// A request per transaction, [`REST_TX_CONCURRENCY`] at once. Those not fetched within
+    /// [`REST_TX_BUDGET`] are omitted like those that left the mempool, and so are those that
+    /// don't decode.
+    async fn get_transactions(&self, txids: &[Txid]) -> Result<HashMap<Txid, Transaction>> {
+        let deadline = tokio::time::Instant::now() + REST_TX_BUDGET;
+        let mut responses = stream::iter(txids.iter().copied())
+            .map(|txid| async move { (txid, self.get(&format!("tx/{txid}.bin")).await) })
+            .buffer_unordered(REST_TX_CONCURRENCY);
+        let mut transactions = HashMap::with_capacity(txids.len());
+        loop {
+            let (txid, response) = match tokio::time::timeout_at(deadline, responses.next()).await {
+                Ok(Some(next)) => next,
+                Ok(None) => break,
+                Err(_) => {
+                    warn!(
+                        "only {} of {} transactions fetched in time, skipping the rest",
+                        transactions.len(),
+                        txids.len()
+                    );
+                    break;
+                }
+            };
+            let response = response?;
+            if response.status() != StatusCode::OK {
+                continue;
+            }
+            match deserialize(response.body()) {
+                Ok(tx) => {
+                    transactions.insert(txid, tx);
+                }
+                Err(e) => warn!("skipping tx/{txid}.bin, it doesn't decode: {e}"),
+            }
+        }
+        Ok(transactions)
+    }
+
+    async fn get_block_hash(&self, height: u64) -> Result<BlockHash> {
+        self.get_bin(&format!("blockhashbyheight/{height}.bin"))
+            .await
+    }
+
+    async fn get_block(&self, hash: &BlockHash) -> Result<Block> {
+        self.get_bin(&format!("block/{hash}.bin")).await
+    }
+}
@@ -0,0 +1,242 @@
+use crate::{
+    calc::{Calc, Matrix, MATRIX_CONFIDENCES},
+    replay::Replay,
+    storage::Storage,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use bitcoin::{
+    bech32::{self, FromBase32},
+    hashes::{sha256, Hash},
+    secp256k1::{rand, KeyPair, Message, Secp256k1, SecretKey},
+    Network,
+};
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+    fs,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+    time::{Duration, Instant},
+};
+use tokio::{net::TcpStream, sync::watch};
+use tokio_tungstenite::{tungstenite, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, info, warn};
+
+/// NIP-78 application data, replaceable per `d` tag
+const KIND: u32 = 30078;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+/// How long a relay gets to answer an event with `OK`
+const OK_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Connection = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// A signed NIP-01 event
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub id: String,
+    pub pubkey: String,
+    pub created_at: i64,
+    pub kind: u32,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: String,
+}
+
+/// Signs the estimate matrix as a replaceable event and sends it to Nostr relays, so clients
+/// can subscribe to it by author, kind 30078 and the `d` tag `wtf-fee-matrix-<network>`.
+/// Every relay gets a task of its own that only ever sends the latest event.
+pub struct NostrPublisher {
+    keys: KeyPair,
+    network: String,
+    sender: watch::Sender<Option<Event>>,
+}
+
+impl NostrPublisher {
+    /// Connect to `relays` and sign with the key in `secret_key_file`, hex or `nsec`. A new key
+    /// is written there if the file doesn't exist yet.
+    pub fn start(relays: &[String], secret_key_file: &Path, network: Network) -> Result<Self> {
+        if relays.is_empty() {
+            bail!("no nostr relays configured");
+        }
+        let keys = Self::keys(secret_key_file)?;
+        info!(
+            "publishing to nostr as {}",
+            keys.x_only_public_key().0.to_string()
+        );
+        let (sender, receiver) = watch::channel(None);
+        for relay in relays {
+            tokio::spawn(Self::relay(relay.clone(), receiver.clone()));
+        }
+        Ok(NostrPublisher {
+            keys,
+            network: network.to_string(),
+            sender,
+        })
+    }
+
+    fn keys(path: &Path) -> Result<KeyPair> {
+        let secp = Secp256k1::new();
+        if !path.exists() {
+            let secret = SecretKey::new(&mut rand::thread_rng());
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(path)
+                .with_context(|| format!("creating nostr key {}", path.display()))?;
+            writeln!(file, "{}", secret.display_secret())?;
+            info!("wrote a new nostr key to {}", path.display());
+            return Ok(KeyPair::from_secret_key(&secp, &secret));
+        }
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("reading nostr key {}", path.display()))?;
+        let text = text.trim();
+        let secret = if text.starts_with("nsec1") {
+            let (_, data, _) = bech32::decode(text)?;
+            SecretKey::from_slice(&Vec::<u8>::from_base32(&data)?)?
+        } else {
+            text.parse::<SecretKey>()?
+        };
+        Ok(KeyPair::from_secret_key(&secp, &secret))
+    }
+
+    /// Sign `matrix` of the snapshot taken at `timestamp` and hand it to the relays
+    pub fn publish(&self, matrix: &Matrix, timestamp: i64) -> Result<Event> {
+        let content = json!({
+            "network": self.network,
+            "timestamp": timestamp,
+            "confidences": matrix.confidences,
+            "horizons_minutes": matrix.horizons_minutes,
+            "fee_rates": matrix.fee_rates,
+        });
+        let tags = vec![
+            vec![
+                String::from("d"),
+                format!("wtf-fee-matrix-{}", self.network),
+            ],
+            vec![String::from("network"), self.network.clone()],
+        ];
+        let event = self.sign(chrono::Utc::now().timestamp(), tags, content.to_string())?;
+        self.sender.send_replace(Some(event.clone()));
+        Ok(event)
+    }
+
+    fn sign(&self, created_at: i64, tags: Vec<Vec<String>>, content: String) -> Result<Event> {
+        let pubkey = self.keys.x_only_public_key().0.to_string();
+        let serialized =
+            serde_json::to_string(&json!([0, pubkey, created_at, KIND, tags, content]))?;
+        let id = sha256::Hash::hash(serialized.as_bytes());
+        let sig = Secp256k1::new().sign_schnorr(&Message::from_slice(id.as_ref())?, &self.keys);
+        Ok(Event {
+            id: id.to_string(),
+            pubkey,
+            created_at,
+            kind: KIND,
+            tags,
+            content,
+            sig: sig.to_string(),
+        })
+    }
+
+    async fn relay(url: String, mut receiver: watch::Receiver<Option<Event>>) {
+        let mut connection: Option<Connection> = None;
+        while receiver.changed().await.is_ok() {
+            let mut delay = Duration::from_secs(1);
+            loop {
+                let Some(event) = receiver.borrow_and_update().clone() else {
+                    break;
+                };
+                match Self::send(&mut connection, &url, &event).await {
+                    Ok(()) => break,
+                    Err(e) => {
+                        connection = None;
+                        warn!(
+                            "nostr relay {url} failed, retrying in {}s: {e:#}",
+                            delay.as_secs()
+                        );
+                        tokio::time::sleep(delay).await;
+                        delay = (delay * 2).min(MAX_RETRY_DELAY);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send `event` and wait for the relay to take it, connecting first if needed
+    async fn send(connection: &mut Option<Connection>, url: &str, event: &Event) -> Result<()> {
+        if connection.is_none() {
+            let (stream, _) = tokio_tungstenite::connect_async(url).await?;
+            info!("connected to nostr relay {url}");
+            *connection = Some(stream);
+        }
+        let stream = connection.as_mut().unwrap();
+        let message = json!(["EVENT", event]).to_string();
+        stream.send(tungstenite::Message::Text(message)).await?;
+
+        let answer = tokio::time::timeout(OK_TIMEOUT, async {
+            while let Some(message) = stream.next().await {
+                let tungstenite::Message::Text(text) = message? else {
+                    continue;
+                };
+                let Ok(Value::Array(fields)) = serde_json::from_str::<Value>(&text) else {
+                    continue;
+                };
+                match fields.first().and_then(Value::as_str) {
+                    Some("OK") if fields.get(1).and_then(Value::as_str) == Some(&event.id) => {
+                        let accepted = fields.get(2).and_then(Value::as_bool).unwrap_or(false);
+                        let reason = fields.get(3).and_then(Value::as_str).unwrap_or_default();
+                        return Ok((accepted, reason.to_string()));
+                    }
+                    Some("NOTICE") => warn!("nostr relay {url}: {text}"),
+                    _ => {}
+                }
+            }
+            Err(anyhow!("connection closed"))
+        })
+        .await
+        .map_err(|_| anyhow!("no answer within {}s", OK_TIMEOUT.as_secs()))??;
+        match answer {
+            (true, _) => debug!("nostr relay {url} took event {}", event.id),
+            // sending it again won't change the relay's mind
+            (false, reason) => warn!("nostr relay {url} rejected event {}: {reason}", event.id),
+        }
+        Ok(())
+    }
+
+    /// Publish the matrix of each new snapshot that changes it, looking for one every `poll`
+    /// and publishing at most once per `every`. Runs until interrupted.
+    pub async fn watch(
+        &self,
+        storage: &dyn Storage,
+        poll: Duration,
+        every: Duration,
+    ) -> Result<()> {
+        let mut seen: Option<i64> = None;
+        let mut pending: Option<(i64, Matrix)> = None;
+        let mut published: Option<(Instant, Vec<Vec<f64>>)> = None;
+        loop {
+            let latest = Replay::new(storage)?.latest();
+            if let Some(timestamp) = latest.filter(|_| latest != seen) {
+                seen = latest;
+                match Calc::matrix(storage, &MATRIX_CONFIDENCES) {
+                    Ok(matrix) => pending = Some((timestamp, matrix)),
+                    Err(e) => warn!("estimating for nostr: {e:#}"),
+                }
+            }
+            if let Some((timestamp, matrix)) = pending.take() {
+                match &published {
+                    Some((_, fee_rates)) if *fee_rates == matrix.fee_rates => {}
+                    Some((at, _)) if at.elapsed() < every => pending = Some((timestamp, matrix)),
+                    _ => {
+                        let event = self.publish(&matrix, timestamp)?;
+                        debug!("published nostr event {}", event.id);
+                        published = Some((Instant::now(), matrix.fee_rates));
+                    }
+                }
+            }
+            tokio::time::sleep(poll).await;
+        }
+    }
+}
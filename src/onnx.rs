@@ -0,0 +1,236 @@
+use crate::{calc::MIN_RELAY_FEE_RATE, model::QuantileRegression, replay::Snapshot};
+use anyhow::{anyhow, bail, Context, Result};
+use prost::Message;
+use std::{fmt, fs, path::Path, sync::Arc};
+use tract_onnx::{
+    pb::{
+        attribute_proto::AttributeType, tensor_proto::DataType, tensor_shape_proto::dimension,
+        tensor_shape_proto::Dimension, type_proto, AttributeProto, GraphProto, ModelProto,
+        NodeProto, OperatorSetIdProto, TensorProto, TensorShapeProto, TypeProto, ValueInfoProto,
+    },
+    prelude::*,
+};
+
+/// Columns of the `features` input: target in blocks, confidence, log of the fee rate cutting
+/// off the projected `target` blocks and log of one plus the mempool size in blocks
+pub const INPUT_FEATURES: usize = 4;
+/// ONNX opset of exported models, the last one with `ReduceSum` axes as an attribute
+const OPSET: i64 = 12;
+const IR_VERSION: i64 = 7;
+
+/// A model in ONNX format. It takes a float tensor `features` of shape `[N, 4]`, see
+/// [`INPUT_FEATURES`], and its first output holds the fee rate in sat/vB for each row, so models
+/// trained elsewhere can be served as long as they follow the same layout.
+#[derive(Clone)]
+pub struct OnnxModel {
+    plan: Arc<TypedRunnableModel>,
+}
+
+impl fmt::Debug for OnnxModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OnnxModel").finish_non_exhaustive()
+    }
+}
+
+impl OnnxModel {
+    pub fn load(path: &Path) -> Result<Self> {
+        let plan = tract_onnx::onnx()
+            .model_for_path(path)
+            .and_then(|model| model.with_input_fact(0, f32::fact([1, INPUT_FEATURES]).into()))
+            .and_then(|model| model.into_optimized())
+            .and_then(|model| model.into_runnable())
+            .map_err(|e| anyhow!("loading ONNX model {}: {e:#}", path.display()))?;
+        Ok(OnnxModel { plan })
+    }
+
+    /// Fee rate (sat/vB) for `target` at `confidence` in `snapshot`
+    pub fn estimate(&self, snapshot: &Snapshot, target: u32, confidence: f64) -> Result<f64> {
+        let [_, log_cutoff, log_size] = QuantileRegression::features(snapshot, target);
+        let features = [target as f64, confidence, log_cutoff, log_size].map(|f| f as f32);
+        let input = tract_ndarray::Array2::from_shape_vec((1, INPUT_FEATURES), features.to_vec())?;
+        let outputs = self
+            .plan
+            .run(tvec!(Tensor::from(input).into()))
+            .map_err(|e| anyhow!("running ONNX model: {e:#}"))?;
+        let fee_rate = outputs
+            .first()
+            .and_then(|output| output.cast_to_scalar::<f32>().ok())
+            .ok_or_else(|| anyhow!("ONNX model has no single float output"))?;
+        if !fee_rate.is_finite() {
+            bail!("ONNX model has no estimate for {target} blocks at {confidence}");
+        }
+        Ok((fee_rate as f64).max(MIN_RELAY_FEE_RATE))
+    }
+
+    /// Write `model` as ONNX. Each row takes the fit of its target and confidence, rows
+    /// without one come out as NaN.
+    pub fn export(model: &QuantileRegression, path: &Path) -> Result<()> {
+        let fits = &model.fits;
+        if fits.is_empty() {
+            bail!("the model has no fits to export");
+        }
+        let k = fits.len() as i64;
+        // `features` times these picks each fit's log fee rate, its target and its confidence
+        let mut weights = vec![0.; INPUT_FEATURES * fits.len()];
+        let mut target_picks = vec![0.; INPUT_FEATURES * fits.len()];
+        let mut confidence_picks = vec![0.; INPUT_FEATURES * fits.len()];
+        for (column, fit) in fits.iter().enumerate() {
+            weights[2 * fits.len() + column] = fit.coefficients[1] as f32;
+            weights[3 * fits.len() + column] = fit.coefficients[2] as f32;
+            target_picks[column] = 1.;
+            confidence_picks[fits.len() + column] = 1.;
+        }
+        let columns = [INPUT_FEATURES as i64, k];
+        let initializer = vec![
+            Self::tensor("weights", &columns, weights),
+            Self::tensor(
+                "intercepts",
+                &[k],
+                fits.iter().map(|f| f.coefficients[0] as f32).collect(),
+            ),
+            Self::tensor("target_picks", &columns, target_picks),
+            Self::tensor("confidence_picks", &columns, confidence_picks),
+            Self::tensor(
+                "targets",
+                &[k],
+                fits.iter().map(|f| f.target as f32).collect(),
+            ),
+            Self::tensor(
+                "confidences",
+                &[k],
+                fits.iter().map(|f| f.confidence as f32).collect(),
+            ),
+        ];
+        let reduce = || {
+            vec![
+                AttributeProto {
+                    name: String::from("axes"),
+                    r#type: AttributeType::Ints as i32,
+                    ints: vec![1],
+                    ..Default::default()
+                },
+                AttributeProto {
+                    name: String::from("keepdims"),
+                    r#type: AttributeType::Int as i32,
+                    i: 1,
+                    ..Default::default()
+                },
+            ]
+        };
+        let node = vec![
+            Self::node("MatMul", &["features", "weights"], "slopes", vec![]),
+            Self::node("Add", &["slopes", "intercepts"], "log_fee_rates", vec![]),
+            Self::node(
+                "MatMul",
+                &["features", "target_picks"],
+                "row_targets",
+                vec![],
+            ),
+            Self::node("Equal", &["row_targets", "targets"], "target_match", vec![]),
+            Self::node(
+                "MatMul",
+                &["features", "confidence_picks"],
+                "row_confidences",
+                vec![],
+            ),
+            Self::node(
+                "Equal",
+                &["row_confidences", "confidences"],
+                "confidence_match",
+                vec![],
+            ),
+            Self::node(
+                "And",
+                &["target_match", "confidence_match"],
+                "matches",
+                vec![],
+            ),
+            Self::node(
+                "Cast",
+                &["matches"],
+                "mask",
+                vec![AttributeProto {
+                    name: String::from("to"),
+                    r#type: AttributeType::Int as i32,
+                    i: DataType::Float as i64,
+                    ..Default::default()
+                }],
+            ),
+            Self::node("Mul", &["log_fee_rates", "mask"], "picked", vec![]),
+            Self::node("ReduceSum", &["picked"], "picked_sum", reduce()),
+            Self::node("ReduceSum", &["mask"], "mask_sum", reduce()),
+            // 0 / 0 for rows without a fit
+            Self::node("Div", &["picked_sum", "mask_sum"], "log_fee_rate", vec![]),
+            Self::node("Exp", &["log_fee_rate"], "fee_rate", vec![]),
+        ];
+        let model = ModelProto {
+            ir_version: IR_VERSION,
+            opset_import: vec![OperatorSetIdProto {
+                domain: String::new(),
+                version: OPSET,
+            }],
+            producer_name: String::from("wtf"),
+            producer_version: env!("CARGO_PKG_VERSION").to_string(),
+            doc_string: String::from(
+                "fee rate in sat/vB from [target, confidence, ln(projected cutoff), ln(1 + mempool blocks)]",
+            ),
+            graph: Some(GraphProto {
+                node,
+                name: String::from("quantile_regression"),
+                initializer,
+                input: vec![Self::value_info("features", INPUT_FEATURES as i64)],
+                output: vec![Self::value_info("fee_rate", 1)],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        fs::write(path, model.encode_to_vec())
+            .with_context(|| format!("writing ONNX model {}", path.display()))
+    }
+
+    fn tensor(name: &str, dims: &[i64], data: Vec<f32>) -> TensorProto {
+        TensorProto {
+            name: name.to_string(),
+            dims: dims.to_vec(),
+            data_type: DataType::Float as i32,
+            float_data: data,
+            ..Default::default()
+        }
+    }
+
+    fn node(op: &str, inputs: &[&str], output: &str, attribute: Vec<AttributeProto>) -> NodeProto {
+        NodeProto {
+            input: inputs.iter().map(|i| i.to_string()).collect(),
+            output: vec![output.to_string()],
+            name: output.to_string(),
+            op_type: op.to_string(),
+            attribute,
+            ..Default::default()
+        }
+    }
+
+    /// Float tensor of shape `[N, columns]`
+    fn value_info(name: &str, columns: i64) -> ValueInfoProto {
+        let dim = vec![
+            Dimension {
+                value: Some(dimension::Value::DimParam(String::from("N"))),
+                ..Default::default()
+            },
+            Dimension {
+                value: Some(dimension::Value::DimValue(columns)),
+                ..Default::default()
+            },
+        ];
+        ValueInfoProto {
+            name: name.to_string(),
+            r#type: Some(TypeProto {
+                value: Some(type_proto::Value::TensorType(type_proto::Tensor {
+                    elem_type: DataType::Float as i32,
+                    shape: Some(TensorShapeProto { dim }),
+                })),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,394 @@
+use crate::{
+    calc::{Matrix, RecommendedFees, TargetEstimate},
+    cpfp::ChildFee,
+    eta::TxEta,
+    history::{FeeHistory, HistoryBucket},
+    metrics::Health,
+    position::QueuePosition,
+    price::{FiatCosts, TxCost},
+    serve::{ErrorResponse, EtaRequest, FeeResponse, LndFees, StreamEstimate, TargetsResponse},
+    sync::RemoteFile,
+    unit::FeeUnit,
+};
+use utoipa::{
+    openapi::{
+        self,
+        security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+        SecurityRequirement,
+    },
+    OpenApi,
+};
+
+/// Swagger UI of `/openapi.json`. Its scripts come from unpkg, the browser showing it needs to
+/// reach the internet.
+pub const SWAGGER_UI: &str = r##"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>WhatTheFee API</title>
+<link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5.17.14/swagger-ui.css">
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script src="https://unpkg.com/swagger-ui-dist@5.17.14/swagger-ui-bundle.js"></script>
+<script>SwaggerUIBundle({ url: "openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>
+"##;
+
+/// The API of `serve` as OpenAPI 3. Schemas are derived from the types the handlers answer
+/// with, the routes are described in [`routes`] since the handlers are methods of
+/// [`Serve`](crate::serve::Serve) the annotations can't go on.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "WhatTheFee",
+        description = "Bitcoin fee rate estimates from recorded mempools and confirmations"
+    ),
+    paths(
+        routes::fee,
+        routes::preset,
+        routes::targets,
+        routes::matrix,
+        routes::position,
+        routes::history,
+        routes::cpfp,
+        routes::eta,
+        routes::stream,
+        routes::recommended,
+        routes::fee_estimates,
+        routes::api_fee_estimates,
+        routes::lnd_fees,
+        routes::files,
+        routes::file,
+        routes::healthz,
+        routes::readyz,
+    ),
+    components(schemas(
+        ChildFee,
+        ErrorResponse,
+        EtaRequest,
+        FeeHistory,
+        FeeResponse,
+        FeeUnit,
+        FiatCosts,
+        Health,
+        HistoryBucket,
+        LndFees,
+        Matrix,
+        QueuePosition,
+        RecommendedFees,
+        RemoteFile,
+        StreamEstimate,
+        TargetEstimate,
+        TargetsResponse,
+        TxCost,
+        TxEta,
+    )),
+    tags(
+        (name = "estimates", description = "Fee rates in the API's own shape"),
+        (name = "compatible", description = "Drop-in answers for mempool.space, Esplora and LND"),
+        (name = "sync", description = "The recorded files, with `--serve-files`"),
+        (name = "health", description = "Probes, answered without an API key"),
+    )
+)]
+pub struct ApiDoc;
+
+impl ApiDoc {
+    /// The document for a server with `files` served and keys required or not
+    pub fn build(files: bool, keys: bool) -> openapi::OpenApi {
+        let mut doc = ApiDoc::openapi();
+        // taken from Cargo.toml, which names none
+        doc.info.license = None;
+        if !files {
+            doc.paths
+                .paths
+                .retain(|path, _| !path.starts_with("/v1/files"));
+        }
+        if keys {
+            if let Some(components) = doc.components.as_mut() {
+                components.add_security_scheme(
+                    "bearer",
+                    SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+                );
+                components.add_security_scheme(
+                    "api_key",
+                    SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))),
+                );
+            }
+            doc.security = Some(vec![
+                SecurityRequirement::new("bearer", Vec::<String>::new()),
+                SecurityRequirement::new("api_key", Vec::<String>::new()),
+            ]);
+        }
+        doc
+    }
+}
+
+/// Stubs carrying the annotations of the routes
+#[allow(dead_code)]
+mod routes {
+    use crate::{
+        serve::{
+            ConfidenceQuery, CpfpQuery, FeeQuery, HistoryQuery, PositionQuery, StreamQuery,
+            TargetsQuery, UnitQuery,
+        },
+        sync::FileFilter,
+    };
+
+    /// Fee rate to confirm within a target
+    ///
+    /// From the last hour of the dataset, or the latest snapshot when serving a model.
+    #[utoipa::path(
+        get,
+        path = "/v1/fee",
+        tag = "estimates",
+        params(FeeQuery),
+        responses(
+            (status = 200, body = FeeResponse),
+            (status = 400, description = "Confidence or target out of range", body = ErrorResponse),
+            (status = 503, description = "The dataset can't be estimated from", body = ErrorResponse),
+        )
+    )]
+    fn fee() {}
+
+    /// `/v1/fee` at the confidence and target of a preset
+    #[utoipa::path(
+        get,
+        path = "/v1/fee/{preset}",
+        tag = "estimates",
+        params(
+            ("preset" = String, Path, description = "Like `fast` or `economy`, or one from the config"),
+            UnitQuery,
+        ),
+        responses(
+            (status = 200, body = FeeResponse),
+            (status = 404, description = "No such preset", body = ErrorResponse),
+            (status = 503, description = "The dataset can't be estimated from", body = ErrorResponse),
+        )
+    )]
+    fn preset() {}
+
+    /// Fee rates confirming within each target
+    ///
+    /// From wait times recorded and the projected blocks of the latest snapshot, without the
+    /// transactions excluded.
+    #[utoipa::path(
+        get,
+        path = "/v1/targets",
+        tag = "estimates",
+        params(TargetsQuery),
+        responses(
+            (status = 200, body = TargetsResponse),
+            (status = 400, description = "Invalid confidence, target or txid", body = ErrorResponse),
+            (status = 503, description = "The dataset can't be estimated from", body = ErrorResponse),
+        )
+    )]
+    fn targets() {}
+
+    /// Fee rates by confidence and time horizon
+    #[utoipa::path(
+        get,
+        path = "/v1/matrix",
+        tag = "estimates",
+        params(UnitQuery),
+        responses(
+            (status = 200, body = Matrix),
+            (status = 503, description = "The dataset can't be estimated from", body = ErrorResponse),
+        )
+    )]
+    fn matrix() {}
+
+    /// Where a transaction paying a fee rate stands in the latest mempool
+    #[utoipa::path(
+        get,
+        path = "/v1/position",
+        tag = "estimates",
+        params(PositionQuery),
+        responses(
+            (status = 200, body = QueuePosition),
+            (status = 400, description = "Negative fee rate", body = ErrorResponse),
+            (status = 503, description = "No snapshot recorded", body = ErrorResponse),
+        )
+    )]
+    fn position() {}
+
+    /// Fee rate percentiles and mempool depth over time, for charts
+    ///
+    /// A bucket per `resolution` from the first snapshot taken in it, the last day by default.
+    #[utoipa::path(
+        get,
+        path = "/v1/history",
+        tag = "estimates",
+        params(HistoryQuery),
+        responses(
+            (status = 200, body = FeeHistory),
+            (status = 400, description = "Unknown resolution or too many buckets", body = ErrorResponse),
+            (status = 503, description = "No snapshot recorded", body = ErrorResponse),
+        )
+    )]
+    fn history() {}
+
+    /// The fee a child pays for its unconfirmed parent to confirm within a target
+    ///
+    /// What the parent and its unconfirmed ancestors pay is looked up by the node `serve`
+    /// reaches, or in the latest snapshot without one, where ancestors aren't known.
+    #[utoipa::path(
+        get,
+        path = "/v1/cpfp",
+        tag = "estimates",
+        params(CpfpQuery),
+        responses(
+            (status = 200, body = ChildFee),
+            (status = 400, description = "Not a txid, or confidence, target or child vsize out of range", body = ErrorResponse),
+            (status = 404, description = "The parent isn't in the mempool", body = ErrorResponse),
+            (status = 503, description = "The node or the dataset can't be asked", body = ErrorResponse),
+        )
+    )]
+    fn cpfp() {}
+
+    /// Where a raw transaction lands in the latest mempool and when it confirms
+    ///
+    /// Its fee is looked up from the prevouts by the node `serve` reaches unless given.
+    #[utoipa::path(
+        post,
+        path = "/v1/eta",
+        tag = "estimates",
+        request_body = EtaRequest,
+        responses(
+            (status = 200, body = TxEta),
+            (status = 400, description = "Undecodable transaction, confidence out of range or no fee without a node", body = ErrorResponse),
+            (status = 422, description = "Prevouts not found or spending more than they hold", body = ErrorResponse),
+            (status = 503, description = "No snapshot recorded", body = ErrorResponse),
+        )
+    )]
+    fn eta() {}
+
+    /// The `/v1/fee` estimate pushed on every new snapshot
+    ///
+    /// Over WebSocket when the client asks for an upgrade, as server-sent events otherwise.
+    #[utoipa::path(
+        get,
+        path = "/v1/stream",
+        tag = "estimates",
+        params(StreamQuery),
+        responses(
+            (status = 200, description = "An event per estimate", content_type = "text/event-stream", body = StreamEstimate),
+            (status = 101, description = "A text message per estimate", body = StreamEstimate),
+            (status = 400, description = "Confidence or target out of range", body = ErrorResponse),
+        )
+    )]
+    fn stream() {}
+
+    /// mempool.space's recommended fees, whole sat/vB
+    #[utoipa::path(
+        get,
+        path = "/api/v1/fees/recommended",
+        tag = "compatible",
+        params(ConfidenceQuery),
+        responses(
+            (status = 200, body = RecommendedFees),
+            (status = 400, description = "Confidence out of range", body = ErrorResponse),
+            (status = 503, description = "The dataset can't be estimated from", body = ErrorResponse),
+        )
+    )]
+    fn recommended() {}
+
+    /// Esplora's fee estimates, sat/vB by target in blocks
+    #[utoipa::path(
+        get,
+        path = "/fee-estimates",
+        tag = "compatible",
+        params(ConfidenceQuery),
+        responses(
+            (status = 200, body = BTreeMap<u32, f64>),
+            (status = 400, description = "Confidence out of range", body = ErrorResponse),
+            (status = 503, description = "The dataset can't be estimated from", body = ErrorResponse),
+        )
+    )]
+    fn fee_estimates() {}
+
+    /// `/fee-estimates` where Esplora's API sits under `/api`
+    #[utoipa::path(
+        get,
+        path = "/api/fee-estimates",
+        tag = "compatible",
+        params(ConfidenceQuery),
+        responses(
+            (status = 200, body = BTreeMap<u32, f64>),
+            (status = 400, description = "Confidence out of range", body = ErrorResponse),
+            (status = 503, description = "The dataset can't be estimated from", body = ErrorResponse),
+        )
+    )]
+    fn api_fee_estimates() {}
+
+    /// Fee rates for LND's `--fee.url`, sat/kvB
+    #[utoipa::path(
+        get,
+        path = "/lnd/fee-estimates",
+        tag = "compatible",
+        params(ConfidenceQuery),
+        responses(
+            (status = 200, body = LndFees),
+            (status = 400, description = "Confidence out of range", body = ErrorResponse),
+            (status = 503, description = "The dataset can't be estimated from", body = ErrorResponse),
+        )
+    )]
+    fn lnd_fees() {}
+
+    /// The recorded files, for `wtf sync`
+    #[utoipa::path(
+        get,
+        path = "/v1/files",
+        tag = "sync",
+        params(FileFilter),
+        responses(
+            (status = 200, body = [RemoteFile]),
+            (status = 503, description = "The dataset can't be listed", body = ErrorResponse),
+        )
+    )]
+    fn files() {}
+
+    /// One recorded file as parquet
+    #[utoipa::path(
+        get,
+        path = "/v1/files/{height}/{timestamp}/{kind}",
+        tag = "sync",
+        params(
+            ("height" = u64, Path, description = "Block height of the file"),
+            ("timestamp" = i64, Path, description = "Unix timestamp"),
+            ("kind" = String, Path, description = "Like `full` or `delta`, as `/v1/files` lists it"),
+        ),
+        responses(
+            (status = 200, content_type = "application/vnd.apache.parquet", body = Vec<u8>,
+                headers(("x-wtf-sha256" = String, description = "SHA-256 of the file, hex"))),
+            (status = 404, description = "No such file", body = ErrorResponse),
+            (status = 503, description = "The dataset can't be read", body = ErrorResponse),
+        )
+    )]
+    fn file() {}
+
+    /// Alive while the dataset can be read
+    #[utoipa::path(
+        get,
+        path = "/healthz",
+        tag = "health",
+        responses(
+            (status = 200, body = Health),
+            (status = 503, body = Health),
+        )
+    )]
+    fn healthz() {}
+
+    /// Ready while the latest snapshot is recent
+    #[utoipa::path(
+        get,
+        path = "/readyz",
+        tag = "health",
+        responses(
+            (status = 200, body = Health),
+            (status = 503, body = Health),
+        )
+    )]
+    fn readyz() {}
+}
@@ -0,0 +1,1118 @@
+use crate::{
+    import::Import,
+    node::Node,
+    record::{Mempool, MempoolEntry},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use bitcoin::{
+    block::Header,
+    consensus::{
+        encode::{deserialize, serialize},
+        Params,
+    },
+    constants::genesis_block,
+    hashes::Hash,
+    network::{
+        address::Address,
+        constants::ServiceFlags,
+        message::{NetworkMessage, RawNetworkMessage},
+        message_blockdata::{GetHeadersMessage, Inventory},
+        message_network::VersionMessage,
+    },
+    Amount, Block, BlockHash, CompactTarget, Network, OutPoint, Target, Transaction, Txid, Work,
+};
+use bitcoincore_rest::{
+    responses::{GetBlockchainInfoResult, GetMempoolEntryResult, GetMempoolInfoResult},
+    GetMempoolTxidsAndSequenceResult,
+};
+use bitcoincore_rpc_json::GetMempoolEntryResultFees;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::OpenOptions,
+    io::Write,
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{tcp::OwnedReadHalf, TcpStream},
+    sync::{mpsc, oneshot, watch},
+};
+use tracing::{debug, info, warn};
+
+/// 70015 has sendheaders and feefilter, peers don't announce by wtxid before 70016
+const PROTOCOL_VERSION: u32 = 70015;
+const USER_AGENT: &str = concat!("/wtf:", env!("CARGO_PKG_VERSION"), "/");
+/// A `headers` message holds at most this many, fewer mean the peer has no more
+const MAX_HEADERS: usize = 2000;
+/// Blocks a reorg may go back, a deeper one is ignored
+const REORG_WINDOW: usize = 100;
+/// Blocks whose outputs are kept, to find the fees of transactions spending them
+const OUTPUT_BLOCKS: usize = 144;
+/// Recent blocks kept for the recorder to label
+const BLOCK_CACHE: usize = 16;
+/// Transactions are dropped after this long without confirming, like Core's -mempoolexpiry
+const MEMPOOL_EXPIRY_SECS: u64 = 336 * 60 * 60;
+/// Larger messages are refused, blocks are at most 4 MB
+const MAX_PAYLOAD: usize = 32 * 1024 * 1024;
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(60);
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Port peers of `network` listen on unless told otherwise
+pub fn default_port(network: Network) -> u16 {
+    match network {
+        Network::Bitcoin => 8333,
+        Network::Testnet => 18333,
+        Network::Signet => 38333,
+        _ => 18444,
+    }
+}
+
+/// A transaction of the mempool view
+struct PoolTx {
+    tx: Transaction,
+    /// Unix time it was first announced
+    time: u64,
+    height: u64,
+    fee: Amount,
+}
+
+/// What is kept of a header to follow the chain
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Link {
+    hash: BlockHash,
+    bits: CompactTarget,
+    time: u32,
+}
+
+impl From<&Header> for Link {
+    fn from(header: &Header) -> Self {
+        Link {
+            hash: header.block_hash(),
+            bits: header.bits,
+            time: header.time,
+        }
+    }
+}
+
+/// Work of `links` together
+fn chain_work(links: &[Link]) -> Work {
+    links
+        .iter()
+        .map(|link| Target::from_compact(link.bits).to_work())
+        .fold(Work::from_le_bytes([0; 32]), |total, work| total + work)
+}
+
+/// `bits` the header after `chain` must carry at `time`, as Bitcoin Core's
+/// `GetNextWorkRequired` works them out
+fn next_bits(chain: &[Link], time: u32, params: &Params) -> CompactTarget {
+    let limit = Target::from_le_bytes(params.pow_limit.to_le_bytes());
+    let last = chain.last().expect("the chain starts at genesis");
+    let interval = params.difficulty_adjustment_interval();
+    let height = chain.len() as u64;
+    if !height.is_multiple_of(interval) {
+        if !params.allow_min_difficulty_blocks {
+            return last.bits;
+        }
+        // testnet allows a block at the minimum difficulty after 20 minutes without one
+        if time as u64 > last.time as u64 + 2 * params.pow_target_spacing {
+            return limit.to_compact_lossy();
+        }
+        return chain
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(height, link)| {
+                (*height as u64).is_multiple_of(interval) || link.bits != limit.to_compact_lossy()
+            })
+            .map_or(last.bits, |(_, link)| link.bits);
+    }
+    if params.no_pow_retargeting {
+        return last.bits;
+    }
+    let first = &chain[(height - interval) as usize];
+    let timespan = (last.time as u64).saturating_sub(first.time as u64).clamp(
+        params.pow_target_timespan / 4,
+        params.pow_target_timespan * 4,
+    );
+    retarget(
+        Target::from_compact(last.bits),
+        timespan,
+        params.pow_target_timespan,
+    )
+    .min(limit)
+    .to_compact_lossy()
+}
+
+/// `target` times `timespan` over `expected` in 256 bits, saturating
+fn retarget(target: Target, timespan: u64, expected: u64) -> Target {
+    let mut limbs = [0u64; 5];
+    for (limb, bytes) in limbs.iter_mut().zip(target.to_le_bytes().chunks_exact(8)) {
+        *limb = u64::from_le_bytes(bytes.try_into().unwrap());
+    }
+    let mut carry = 0u128;
+    for limb in &mut limbs {
+        let product = *limb as u128 * timespan as u128 + carry;
+        *limb = product as u64;
+        carry = product >> 64;
+    }
+    let mut remainder = 0u128;
+    for limb in limbs.iter_mut().rev() {
+        let dividend = remainder << 64 | *limb as u128;
+        *limb = (dividend / expected as u128) as u64;
+        remainder = dividend % expected as u128;
+    }
+    if limbs[4] != 0 {
+        return Target::from_le_bytes([0xff; 32]);
+    }
+    let mut bytes = [0; 32];
+    for (chunk, limb) in bytes.chunks_exact_mut(8).zip(limbs) {
+        chunk.copy_from_slice(&limb.to_le_bytes());
+    }
+    Target::from_le_bytes(bytes)
+}
+
+#[derive(Default)]
+struct State {
+    /// Every header, by height
+    headers: Vec<Link>,
+    /// Height the mempool view is at, blocks above it are still on their way
+    height: u64,
+    pool: HashMap<Txid, PoolTx>,
+    /// The pool transaction spending each outpoint
+    spends: HashMap<OutPoint, Txid>,
+    /// Values of the outputs of recent blocks, with the outputs of each block oldest first
+    outputs: HashMap<OutPoint, u64>,
+    outputs_by_block: VecDeque<Vec<OutPoint>>,
+    /// Announced transactions asked for, with the time they were announced
+    requested: HashMap<Txid, u64>,
+    blocks: VecDeque<Block>,
+    /// Blocks that came before their parent was applied
+    pending: HashMap<BlockHash, Block>,
+    waiting: HashMap<BlockHash, Vec<oneshot::Sender<Block>>>,
+    sequence: u64,
+    /// Minimum fee rate the peer relays, sat/kvB
+    fee_filter: Option<i64>,
+    /// Transactions left out because the value of an input is unknown
+    unresolved: u64,
+}
+
+impl State {
+    fn tip(&self) -> BlockHash {
+        self.headers.last().unwrap().hash
+    }
+
+    /// Hashes to start `getheaders` from, dense near the tip
+    fn locator(&self) -> Vec<BlockHash> {
+        let mut locator = Vec::new();
+        let mut index = self.headers.len() - 1;
+        let mut step = 1;
+        loop {
+            locator.push(self.headers[index].hash);
+            if index == 0 {
+                break;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            index = index.saturating_sub(step);
+        }
+        locator
+    }
+
+    fn input_value(&self, outpoint: &OutPoint) -> Option<u64> {
+        match self.pool.get(&outpoint.txid) {
+            Some(parent) => parent
+                .tx
+                .output
+                .get(outpoint.vout as usize)
+                .map(|output| output.value),
+            None => self.outputs.get(outpoint).copied(),
+        }
+    }
+
+    /// Add `tx`, announced at `time`, evicting what it replaces. Left out unless the value
+    /// of every input is known.
+    fn accept(&mut self, tx: Transaction, time: u64) {
+        let txid = tx.txid();
+        if self.pool.contains_key(&txid) {
+            return;
+        }
+        let inputs: Option<u64> = tx
+            .input
+            .iter()
+            .map(|input| self.input_value(&input.previous_output))
+            .sum();
+        let outputs: u64 = tx.output.iter().map(|output| output.value).sum();
+        let Some(fee) = inputs.and_then(|inputs| inputs.checked_sub(outputs)) else {
+            debug!("leaving out {txid}, an input is unknown");
+            self.unresolved += 1;
+            return;
+        };
+        let replaced: HashSet<Txid> = tx
+            .input
+            .iter()
+            .filter_map(|input| self.spends.get(&input.previous_output).copied())
+            .collect();
+        for replaced in replaced {
+            debug!("{txid} replaces {replaced}");
+            self.evict(replaced);
+        }
+        for input in &tx.input {
+            self.spends.insert(input.previous_output, txid);
+        }
+        self.pool.insert(
+            txid,
+            PoolTx {
+                tx,
+                time,
+                height: self.height,
+                fee: Amount::from_sat(fee),
+            },
+        );
+        self.sequence += 1;
+    }
+
+    /// Remove `txid` for good, with its descendants
+    fn evict(&mut self, txid: Txid) {
+        let mut stack = vec![txid];
+        while let Some(txid) = stack.pop() {
+            let Some(removed) = self.forget(&txid) else {
+                continue;
+            };
+            for vout in 0..removed.tx.output.len() as u32 {
+                if let Some(child) = self.spends.get(&OutPoint { txid, vout }) {
+                    stack.push(*child);
+                }
+            }
+        }
+    }
+
+    fn forget(&mut self, txid: &Txid) -> Option<PoolTx> {
+        let removed = self.pool.remove(txid)?;
+        for input in &removed.tx.input {
+            if self.spends.get(&input.previous_output) == Some(txid) {
+                self.spends.remove(&input.previous_output);
+            }
+        }
+        self.sequence += 1;
+        Some(removed)
+    }
+
+    /// Take the transactions of `block` at `height` out of the mempool view, with whatever
+    /// conflicts with them, and keep its outputs
+    fn apply(&mut self, height: u64, block: &Block) {
+        let mut confirmed = 0;
+        for tx in &block.txdata {
+            if self.forget(&tx.txid()).is_some() {
+                confirmed += 1;
+            }
+            for input in &tx.input {
+                if let Some(conflict) = self.spends.get(&input.previous_output).copied() {
+                    self.evict(conflict);
+                }
+                self.outputs.remove(&input.previous_output);
+            }
+        }
+        let mut created = Vec::new();
+        for tx in &block.txdata {
+            let txid = tx.txid();
+            for (vout, output) in tx.output.iter().enumerate() {
+                let outpoint = OutPoint {
+                    txid,
+                    vout: vout as u32,
+                };
+                self.outputs.insert(outpoint, output.value);
+                created.push(outpoint);
+            }
+        }
+        self.outputs_by_block.push_back(created);
+        if self.outputs_by_block.len() > OUTPUT_BLOCKS {
+            for outpoint in self.outputs_by_block.pop_front().unwrap() {
+                self.outputs.remove(&outpoint);
+            }
+        }
+
+        let expired: Vec<Txid> = self
+            .pool
+            .iter()
+            .filter(|(_, pooled)| pooled.time + MEMPOOL_EXPIRY_SECS < now())
+            .map(|(txid, _)| *txid)
+            .collect();
+        for txid in expired {
+            self.evict(txid);
+        }
+        self.height = height;
+        self.blocks.push_back(block.clone());
+        if self.blocks.len() > BLOCK_CACHE {
+            self.blocks.pop_front();
+        }
+        info!(
+            "block {height} confirmed {confirmed} of {} transactions, {} left, {} left out \
+             for unknown inputs so far",
+            block.txdata.len(),
+            self.pool.len(),
+            self.unresolved
+        );
+    }
+
+    /// Entries like Core's of the whole view, ancestors and descendants linked
+    fn entries(&self) -> HashMap<Txid, GetMempoolEntryResult> {
+        let mut mempool: Mempool = self
+            .pool
+            .iter()
+            .map(|(txid, pooled)| {
+                let vsize = pooled.tx.vsize() as u64;
+                let depends: Vec<Txid> = pooled
+                    .tx
+                    .input
+                    .iter()
+                    .map(|input| input.previous_output.txid)
+                    .filter(|parent| self.pool.contains_key(parent))
+                    .collect::<HashSet<_>>()
+                    .into_iter()
+                    .collect();
+                let entry = MempoolEntry {
+                    wtxid: pooled.tx.wtxid(),
+                    vsize,
+                    weight: pooled.tx.weight().to_wu(),
+                    time: pooled.time,
+                    height: pooled.height,
+                    fee: pooled.fee,
+                    modified_fee: pooled.fee,
+                    ancestor_fees: pooled.fee,
+                    descendant_fees: pooled.fee,
+                    ancestor_count: 1,
+                    descendant_count: 1,
+                    descendant_size: vsize,
+                    bip125_replaceable: pooled.tx.is_explicitly_rbf(),
+                    unbroadcast: None,
+                    depends: depends.into(),
+                    spent_by: Box::new([]),
+                };
+                (*txid, entry)
+            })
+            .collect();
+        Import::link(&mut mempool);
+        mempool
+            .into_iter()
+            .map(|(txid, entry)| {
+                let result = GetMempoolEntryResult {
+                    vsize: entry.vsize,
+                    weight: Some(entry.weight),
+                    time: entry.time,
+                    height: entry.height,
+                    descendant_count: entry.descendant_count as u64,
+                    descendant_size: entry.descendant_size,
+                    ancestor_count: entry.ancestor_count as u64,
+                    // the recorder doesn't keep it
+                    ancestor_size: entry.vsize,
+                    wtxid: Txid::from_raw_hash(entry.wtxid.to_raw_hash()),
+                    fees: GetMempoolEntryResultFees {
+                        base: entry.fee,
+                        modified: entry.modified_fee,
+                        ancestor: entry.ancestor_fees,
+                        descendant: entry.descendant_fees,
+                    },
+                    depends: entry.depends.into(),
+                    spent_by: entry.spent_by.into(),
+                    bip125_replaceable: entry.bip125_replaceable,
+                    unbroadcast: None,
+                };
+                (txid, result)
+            })
+            .collect()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Experimental: a node of its own made of a single peer on the Bitcoin P2P network instead
+/// of Bitcoin Core's REST or JSON-RPC. It follows the headers, keeps a mempool view of the
+/// transactions the peer announces with the time each was first announced, and takes
+/// confirmed and conflicting ones out as blocks come in.
+///
+/// Headers are checked to link up and carry the proof of work their chain requires, and the
+/// chain with the most work is followed, but blocks aren't validated, connect to a peer you
+/// trust. Fees are worked out from the inputs, so transactions spending outputs older than
+/// the view are left out until the view has been up for a while. Peers serving bloom filters,
+/// Bitcoin Core with `-peerbloomfilters`, are asked for their whole mempool to start with.
+pub struct P2pNode {
+    peer: String,
+    network: Network,
+    state: Arc<Mutex<State>>,
+    outbox: Arc<Mutex<Option<mpsc::UnboundedSender<NetworkMessage>>>>,
+}
+
+impl P2pNode {
+    /// Connect to `peer` at host:port and follow it, keeping the headers in `headers_file`
+    /// between runs. Returns once the headers are in sync, reconnects in the background.
+    pub async fn connect(peer: &str, network: Network, headers_file: PathBuf) -> Result<Self> {
+        let headers = Self::load_headers(&headers_file, network)?;
+        info!(
+            "{} headers cached in {}",
+            headers.len(),
+            headers_file.display()
+        );
+        let state = Arc::new(Mutex::new(State {
+            headers,
+            ..Default::default()
+        }));
+        let outbox = Arc::new(Mutex::new(None));
+        let (synced, mut connected) = watch::channel(false);
+        let session = Session {
+            peer: peer.to_string(),
+            network,
+            state: state.clone(),
+            outbox: outbox.clone(),
+            headers_file,
+            synced,
+        };
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = session.run().await {
+                    warn!("peer {}: {e:#}", session.peer);
+                }
+                *session.outbox.lock().unwrap() = None;
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        });
+        connected.wait_for(|synced| *synced).await?;
+        Ok(P2pNode {
+            peer: peer.to_string(),
+            network,
+            state,
+            outbox,
+        })
+    }
+
+    /// Headers from `path`, from the genesis block if there is no file yet
+    fn load_headers(path: &PathBuf, network: Network) -> Result<Vec<Link>> {
+        let genesis = genesis_block(network).header;
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                std::fs::write(path, serialize(&genesis))
+                    .with_context(|| format!("writing {}", path.display()))?;
+                return Ok(vec![Link::from(&genesis)]);
+            }
+            Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+        };
+        let mut headers: Vec<Link> = Vec::with_capacity(bytes.len() / 80);
+        for chunk in bytes.chunks_exact(80) {
+            let header: Header = deserialize(chunk)?;
+            if headers
+                .last()
+                .is_some_and(|tip| tip.hash != header.prev_blockhash)
+            {
+                bail!("{} is broken at {}", path.display(), headers.len());
+            }
+            headers.push(Link::from(&header));
+        }
+        if headers.first() != Some(&Link::from(&genesis)) {
+            bail!("{} has headers of another network", path.display());
+        }
+        Ok(headers)
+    }
+
+    fn send(&self, message: NetworkMessage) -> Result<()> {
+        self.outbox
+            .lock()
+            .unwrap()
+            .as_ref()
+            .ok_or_else(|| anyhow!("not connected to {}", self.peer))?
+            .send(message)
+            .map_err(|_| anyhow!("not connected to {}", self.peer))
+    }
+}
+
+#[async_trait]
+impl Node for P2pNode {
+    async fn get_chain_info(&self) -> Result<GetBlockchainInfoResult> {
+        let state = self.state.lock().unwrap();
+        Ok(GetBlockchainInfoResult {
+            chain: self.network.to_core_arg().to_string(),
+            blocks: state.height,
+            headers: state.headers.len() as u64 - 1,
+            best_block_hash: state.headers[state.height as usize].hash,
+            difficulty: 0.,
+            median_time: 0,
+            verification_progress: 1.,
+            initial_block_download: false,
+            chain_work: Vec::new(),
+            size_on_disk: 0,
+            pruned: false,
+            prune_height: None,
+            automatic_pruning: None,
+            prune_target_size: None,
+            softforks: HashMap::new(),
+            warnings: String::new(),
+        })
+    }
+
+    async fn get_mempool(&self) -> Result<HashMap<Txid, GetMempoolEntryResult>> {
+        Ok(self.state.lock().unwrap().entries())
+    }
+
+    /// Sizes of the view, the minimum fee is the peer's `feefilter`
+    async fn get_mempool_info(&self) -> Result<GetMempoolInfoResult> {
+        let state = self.state.lock().unwrap();
+        let relay = Amount::from_sat(1000);
+        Ok(GetMempoolInfoResult {
+            loaded: true,
+            size: state.pool.len(),
+            bytes: state.pool.values().map(|pooled| pooled.tx.vsize()).sum(),
+            usage: 0,
+            total_fee: state.pool.values().map(|pooled| pooled.fee).sum(),
+            max_mempool: 0,
+            mempool_min_fee: state
+                .fee_filter
+                .map_or(relay, |rate| Amount::from_sat(rate.max(0) as u64)),
+            min_relay_tx_fee: relay,
+            incremental_relay_fee: relay,
+            unbroadcast_count: 0,
+            full_rbf: true,
+        })
+    }
+
+    async fn get_mempool_txids_and_sequence(&self) -> Result<GetMempoolTxidsAndSequenceResult> {
+        let state = self.state.lock().unwrap();
+        Ok(GetMempoolTxidsAndSequenceResult {
+            txids: state.pool.keys().copied().collect(),
+            mempool_sequence: state.sequence,
+        })
+    }
+
+    async fn get_mempool_entries(
+        &self,
+        txids: &[Txid],
+    ) -> Result<HashMap<Txid, GetMempoolEntryResult>> {
+        if txids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let mut entries = self.state.lock().unwrap().entries();
+        let wanted: HashSet<&Txid> = txids.iter().collect();
+        entries.retain(|txid, _| wanted.contains(txid));
+        Ok(entries)
+    }
+
+    async fn get_transactions(&self, txids: &[Txid]) -> Result<HashMap<Txid, Transaction>> {
+        let state = self.state.lock().unwrap();
+        Ok(txids
+            .iter()
+            .filter_map(|txid| Some((*txid, state.pool.get(txid)?.tx.clone())))
+            .collect())
+    }
+
+    async fn get_block_hash(&self, height: u64) -> Result<BlockHash> {
+        let state = self.state.lock().unwrap();
+        state
+            .headers
+            .get(height as usize)
+            .map(|link| link.hash)
+            .ok_or_else(|| anyhow!("no block at height {height} yet"))
+    }
+
+    /// Recent blocks are at hand, older ones are asked from the peer
+    async fn get_block(&self, hash: &BlockHash) -> Result<Block> {
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(block) = state
+                .blocks
+                .iter()
+                .find(|block| block.block_hash() == *hash)
+            {
+                return Ok(block.clone());
+            }
+            state.waiting.entry(*hash).or_default().push(sender);
+        }
+        self.send(NetworkMessage::GetData(vec![Inventory::WitnessBlock(
+            *hash,
+        )]))?;
+        tokio::time::timeout(BLOCK_TIMEOUT, receiver)
+            .await
+            .with_context(|| format!("block {hash} didn't come from {}", self.peer))?
+            .map_err(|_| anyhow!("disconnected from {} waiting for block {hash}", self.peer))
+    }
+
+    /// Only the one peer's
+    async fn get_peer_fee_filters(&self) -> Result<Vec<f64>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .fee_filter
+            .map(|rate| rate as f64 / 1000.)
+            .into_iter()
+            .collect())
+    }
+
+    fn source(&self) -> Option<String> {
+        Some(self.peer.clone())
+    }
+}
+
+/// A connection to the peer, the next one starts where it left off
+struct Session {
+    peer: String,
+    network: Network,
+    state: Arc<Mutex<State>>,
+    outbox: Arc<Mutex<Option<mpsc::UnboundedSender<NetworkMessage>>>>,
+    headers_file: PathBuf,
+    /// Set once the headers have caught up with the peer's
+    synced: watch::Sender<bool>,
+}
+
+impl Session {
+    async fn run(&self) -> Result<()> {
+        let stream = TcpStream::connect(&self.peer)
+            .await
+            .with_context(|| format!("connecting to {}", self.peer))?;
+        let address = stream.peer_addr()?;
+        let (mut reader, mut writer) = stream.into_split();
+        let (outbox, mut messages) = mpsc::unbounded_channel::<NetworkMessage>();
+        let magic = self.network.magic();
+        tokio::spawn(async move {
+            while let Some(payload) = messages.recv().await {
+                let bytes = serialize(&RawNetworkMessage { magic, payload });
+                if writer.write_all(&bytes).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut version = VersionMessage::new(
+            ServiceFlags::NONE,
+            now() as i64,
+            Address::new(&address, ServiceFlags::NONE),
+            Address::new(
+                &SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                ServiceFlags::NONE,
+            ),
+            bitcoin::secp256k1::rand::random(),
+            USER_AGENT.to_string(),
+            self.state.lock().unwrap().headers.len() as i32 - 1,
+        );
+        version.version = PROTOCOL_VERSION;
+        version.relay = true;
+        outbox.send(NetworkMessage::Version(version))?;
+        *self.outbox.lock().unwrap() = Some(outbox.clone());
+
+        loop {
+            let Some(message) = Self::read(&mut reader, self.network).await? else {
+                continue;
+            };
+            self.handle(message, &outbox)?;
+        }
+    }
+
+    /// The next message, `None` if it can't be decoded
+    async fn read(reader: &mut OwnedReadHalf, network: Network) -> Result<Option<NetworkMessage>> {
+        let mut bytes = vec![0; 24];
+        reader
+            .read_exact(&mut bytes)
+            .await
+            .context("reading from peer")?;
+        let length = u32::from_le_bytes(bytes[16..20].try_into().unwrap()) as usize;
+        if length > MAX_PAYLOAD {
+            bail!("peer sent a message of {length} bytes");
+        }
+        bytes.resize(24 + length, 0);
+        reader
+            .read_exact(&mut bytes[24..])
+            .await
+            .context("reading from peer")?;
+        let command = String::from_utf8_lossy(&bytes[4..16])
+            .trim_end_matches('\0')
+            .to_string();
+        match deserialize::<RawNetworkMessage>(&bytes) {
+            Ok(message) if message.magic != network.magic() => {
+                bail!("peer is on another network than {network}")
+            }
+            Ok(message) => Ok(Some(message.payload)),
+            Err(e) => {
+                debug!("skipping {command} message: {e}");
+                Ok(None)
+            }
+        }
+    }
+
+    fn handle(
+        &self,
+        message: NetworkMessage,
+        outbox: &mpsc::UnboundedSender<NetworkMessage>,
+    ) -> Result<()> {
+        match message {
+            NetworkMessage::Version(version) => {
+                info!(
+                    "connected to {} {} at height {}",
+                    self.peer, version.user_agent, version.start_height
+                );
+                if !version.relay {
+                    warn!("{} doesn't relay transactions", self.peer);
+                }
+                outbox.send(NetworkMessage::Verack)?;
+                // others disconnect on `mempool`, unless whitelisted for it
+                if version.services.has(ServiceFlags::BLOOM) {
+                    outbox.send(NetworkMessage::MemPool)?;
+                }
+            }
+            NetworkMessage::Verack => {
+                let locator = self.state.lock().unwrap().locator();
+                outbox.send(NetworkMessage::SendHeaders)?;
+                outbox.send(NetworkMessage::GetHeaders(GetHeadersMessage::new(
+                    locator,
+                    BlockHash::all_zeros(),
+                )))?;
+            }
+            NetworkMessage::Ping(nonce) => outbox.send(NetworkMessage::Pong(nonce))?,
+            NetworkMessage::FeeFilter(rate) => self.state.lock().unwrap().fee_filter = Some(rate),
+            NetworkMessage::Inv(inventory) => {
+                let mut state = self.state.lock().unwrap();
+                let mut wanted = Vec::new();
+                let mut new_block = false;
+                for item in inventory {
+                    match item {
+                        Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid)
+                            if !state.pool.contains_key(&txid)
+                                && !state.requested.contains_key(&txid) =>
+                        {
+                            state.requested.insert(txid, now());
+                            wanted.push(Inventory::WitnessTransaction(txid));
+                        }
+                        Inventory::Block(_) | Inventory::WitnessBlock(_) => new_block = true,
+                        _ => {}
+                    }
+                }
+                if !wanted.is_empty() {
+                    outbox.send(NetworkMessage::GetData(wanted))?;
+                }
+                if new_block {
+                    outbox.send(NetworkMessage::GetHeaders(GetHeadersMessage::new(
+                        state.locator(),
+                        BlockHash::all_zeros(),
+                    )))?;
+                }
+            }
+            NetworkMessage::NotFound(inventory) => {
+                let mut state = self.state.lock().unwrap();
+                for item in inventory {
+                    if let Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid) = item
+                    {
+                        state.requested.remove(&txid);
+                    }
+                }
+            }
+            NetworkMessage::Tx(tx) => {
+                let mut state = self.state.lock().unwrap();
+                let time = state.requested.remove(&tx.txid()).unwrap_or_else(now);
+                state.accept(tx, time);
+            }
+            NetworkMessage::Headers(headers) => self.headers(headers, outbox)?,
+            NetworkMessage::Block(block) => self.block(block),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Extend the headers, or follow a branch with more work, and ask for the blocks the view
+    /// is missing
+    fn headers(
+        &self,
+        headers: Vec<Header>,
+        outbox: &mpsc::UnboundedSender<NetworkMessage>,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let count = headers.len();
+        let window = state.headers.len().saturating_sub(REORG_WINDOW);
+        let known = headers
+            .iter()
+            .take_while(|header| {
+                let hash = header.block_hash();
+                state.headers[window..].iter().any(|link| link.hash == hash)
+            })
+            .count();
+        let branch = &headers[known..];
+        if let Some(first) = branch.first() {
+            match state.headers[window..]
+                .iter()
+                .rposition(|link| link.hash == first.prev_blockhash)
+            {
+                Some(position) => self.follow(&mut state, window + position, branch)?,
+                None => debug!("header {} doesn't connect", first.block_hash()),
+            }
+        }
+        let height = state.headers.len() - 1;
+
+        if count == MAX_HEADERS {
+            if height % 20_000 < MAX_HEADERS {
+                info!("synced headers to {height}");
+            }
+            outbox.send(NetworkMessage::GetHeaders(GetHeadersMessage::new(
+                state.locator(),
+                BlockHash::all_zeros(),
+            )))?;
+            return Ok(());
+        }
+        if !*self.synced.borrow() {
+            // the view starts at the tip, earlier blocks aren't needed
+            state.height = height as u64;
+            info!("headers synced at {height}");
+            self.synced.send_replace(true);
+            return Ok(());
+        }
+        let missing: Vec<Inventory> = state.headers[state.height as usize + 1..]
+            .iter()
+            .filter(|link| !state.pending.contains_key(&link.hash))
+            .map(|link| Inventory::WitnessBlock(link.hash))
+            .collect();
+        if !missing.is_empty() {
+            outbox.send(NetworkMessage::GetData(missing))?;
+        }
+        Ok(())
+    }
+
+    /// Put `branch` on top of the header at `fork`, if it has more work than the headers it
+    /// replaces
+    fn follow(&self, state: &mut State, fork: usize, branch: &[Header]) -> Result<()> {
+        let params = Params::new(self.network);
+        let replaced = state.headers.split_off(fork + 1);
+        let mut linked = Ok(());
+        for header in branch {
+            let hash = header.block_hash();
+            let bits = next_bits(&state.headers, header.time, &params);
+            if state.tip() != header.prev_blockhash {
+                linked = Err(anyhow!("header {hash} doesn't link up with the one before"));
+            } else if header.validate_pow(Target::from_compact(bits)).is_err() {
+                linked = Err(anyhow!("header {hash} lacks the proof of work it requires"));
+            }
+            if linked.is_err() {
+                break;
+            }
+            state.headers.push(Link::from(header));
+        }
+        if linked.is_err() || chain_work(&state.headers[fork + 1..]) <= chain_work(&replaced) {
+            state.headers.truncate(fork + 1);
+            state.headers.extend(replaced);
+            linked?;
+            debug!("branch at {fork} has no more work than the headers, ignoring it");
+            return Ok(());
+        }
+        if !replaced.is_empty() {
+            warn!("reorg of {} blocks at height {fork}", replaced.len());
+            state.height = state.height.min(fork as u64);
+            self.truncate_file(fork + 1)?;
+        }
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&self.headers_file)
+            .with_context(|| format!("opening {}", self.headers_file.display()))?;
+        for header in branch {
+            file.write_all(&serialize(header))?;
+        }
+        Ok(())
+    }
+
+    fn truncate_file(&self, headers: usize) -> Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .open(&self.headers_file)?
+            .set_len(headers as u64 * 80)
+            .with_context(|| format!("truncating {}", self.headers_file.display()))
+    }
+
+    /// Hand `block` to whoever waits for it and apply it, and those after it, to the view
+    fn block(&self, block: Block) {
+        let mut state = self.state.lock().unwrap();
+        let hash = block.block_hash();
+        for waiter in state.waiting.remove(&hash).unwrap_or_default() {
+            let _ = waiter.send(block.clone());
+        }
+        let Some(height) = state.headers.iter().rposition(|link| link.hash == hash) else {
+            return;
+        };
+        if height as u64 <= state.height {
+            return;
+        }
+        state.pending.insert(hash, block);
+        while let Some(next) = state
+            .headers
+            .get(state.height as usize + 1)
+            .map(|link| link.hash)
+        {
+            let Some(block) = state.pending.remove(&next) else {
+                break;
+            };
+            let height = state.height + 1;
+            state.apply(height, &block);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{block::Version, hash_types::TxMerkleNode};
+    use std::path::Path;
+
+    fn session(dir: &Path) -> Session {
+        let headers_file = dir.join("headers");
+        let headers = P2pNode::load_headers(&headers_file, Network::Regtest).unwrap();
+        Session {
+            peer: "peer".to_string(),
+            network: Network::Regtest,
+            state: Arc::new(Mutex::new(State {
+                headers,
+                ..Default::default()
+            })),
+            outbox: Arc::new(Mutex::new(None)),
+            headers_file,
+            synced: watch::channel(true).0,
+        }
+    }
+
+    /// `blocks` regtest headers on top of `prev`, `salt` tells branches apart
+    fn mine(prev: &Header, blocks: usize, bits: u32, salt: u32) -> Vec<Header> {
+        let mut headers: Vec<Header> = Vec::new();
+        for _ in 0..blocks {
+            let prev = headers.last().unwrap_or(prev);
+            let mut header = Header {
+                version: Version::TWO,
+                prev_blockhash: prev.block_hash(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: prev.time + 600 + salt,
+                bits: CompactTarget::from_consensus(bits),
+                nonce: 0,
+            };
+            while !header.target().is_met_by(header.block_hash()) {
+                header.nonce += 1;
+            }
+            headers.push(header);
+        }
+        headers
+    }
+
+    fn handle(session: &Session, headers: Vec<Header>) -> Result<()> {
+        let (outbox, _messages) = mpsc::unbounded_channel();
+        session.headers(headers, &outbox)
+    }
+
+    fn tip(session: &Session) -> BlockHash {
+        session.state.lock().unwrap().tip()
+    }
+
+    fn links(headers: &[Header]) -> Vec<Link> {
+        headers.iter().map(Link::from).collect()
+    }
+
+    #[test]
+    fn headers_that_link_up_extend_the_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let session = session(dir.path());
+        let genesis = genesis_block(Network::Regtest).header;
+        let headers = mine(&genesis, 3, 0x207fffff, 0);
+        handle(&session, headers.clone()).unwrap();
+        // headers already known are skipped
+        handle(&session, headers[1..].to_vec()).unwrap();
+
+        assert_eq!(tip(&session), headers[2].block_hash());
+        let cached = P2pNode::load_headers(&session.headers_file, Network::Regtest).unwrap();
+        assert_eq!(cached, session.state.lock().unwrap().headers);
+        assert_eq!(cached.len(), 4);
+    }
+
+    #[test]
+    fn headers_without_the_required_bits_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let session = session(dir.path());
+        let genesis = genesis_block(Network::Regtest).header;
+        let easier = mine(&genesis, 1, 0x207ffffe, 0);
+
+        assert!(handle(&session, easier).is_err());
+        assert_eq!(tip(&session), genesis.block_hash());
+    }
+
+    #[test]
+    fn branches_are_followed_with_more_work_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let session = session(dir.path());
+        let genesis = genesis_block(Network::Regtest).header;
+        let main = mine(&genesis, 2, 0x207fffff, 0);
+        handle(&session, main.clone()).unwrap();
+        session.state.lock().unwrap().height = 2;
+
+        handle(&session, mine(&genesis, 1, 0x207fffff, 1)).unwrap();
+        handle(&session, mine(&genesis, 2, 0x207fffff, 2)).unwrap();
+        assert_eq!(tip(&session), main[1].block_hash());
+        assert_eq!(session.state.lock().unwrap().height, 2);
+
+        let branch = mine(&genesis, 3, 0x207fffff, 3);
+        handle(&session, branch.clone()).unwrap();
+        assert_eq!(tip(&session), branch[2].block_hash());
+        // the view goes back to the fork to apply the blocks of the branch
+        assert_eq!(session.state.lock().unwrap().height, 0);
+        let cached = P2pNode::load_headers(&session.headers_file, Network::Regtest).unwrap();
+        assert_eq!(cached[1..], links(&branch));
+    }
+
+    #[test]
+    fn a_branch_with_a_header_lacking_work_leaves_the_chain_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let session = session(dir.path());
+        let genesis = genesis_block(Network::Regtest).header;
+        let main = mine(&genesis, 1, 0x207fffff, 0);
+        handle(&session, main.clone()).unwrap();
+
+        let mut branch = mine(&genesis, 2, 0x207fffff, 1);
+        branch.extend(mine(&branch[1], 1, 0x207ffffe, 1));
+        assert!(handle(&session, branch).is_err());
+        assert_eq!(session.state.lock().unwrap().headers[1..], links(&main));
+    }
+
+    fn link(bits: u32, time: u32) -> Link {
+        Link {
+            hash: BlockHash::all_zeros(),
+            bits: CompactTarget::from_consensus(bits),
+            time,
+        }
+    }
+
+    #[test]
+    fn bits_retarget_every_2016_blocks() {
+        let params = Params::new(Network::Bitcoin);
+        let mut chain = vec![link(0x1d00ffff, 0); 2016];
+        assert_eq!(
+            next_bits(&chain[..2015], 0, &params).to_consensus(),
+            0x1d00ffff
+        );
+
+        // a week instead of two, the target halves
+        chain[2015].time = 7 * 24 * 60 * 60;
+        assert_eq!(next_bits(&chain, 0, &params).to_consensus(), 0x1c7fff80);
+        // it changes 4 times at most
+        chain[2015].time = 0;
+        assert_eq!(next_bits(&chain, 0, &params).to_consensus(), 0x1c3fffc0);
+        // and never beyond the limit
+        chain[2015].time = 28 * 24 * 60 * 60;
+        assert_eq!(next_bits(&chain, 0, &params).to_consensus(), 0x1d00ffff);
+    }
+
+    #[test]
+    fn testnet_allows_minimum_difficulty_after_twenty_minutes() {
+        let params = Params::new(Network::Testnet);
+        let mut chain = vec![link(0x1d00ffff, 0), link(0x1c7fff80, 1000)];
+        assert_eq!(next_bits(&chain, 1600, &params).to_consensus(), 0x1c7fff80);
+        assert_eq!(next_bits(&chain, 2201, &params).to_consensus(), 0x1d00ffff);
+
+        // blocks at the minimum difficulty don't count for the next one
+        chain.push(link(0x1d00ffff, 2201));
+        assert_eq!(next_bits(&chain, 2300, &params).to_consensus(), 0x1c7fff80);
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{
+    backtest::Backtest,
+    histogram::{Histogram, BUCKETS},
+    replay::Replay,
+    storage::Storage,
+};
+use anyhow::{anyhow, bail, Result};
+use chrono::{TimeZone, Utc};
+use plotters::{coord::Shift, prelude::*};
+use std::path::Path;
+
+/// Size of the rendered image in pixels
+const SIZE: (u32, u32) = (1200, 600);
+/// Columns of a heatmap when no sampling interval is given
+const HEATMAP_COLUMNS: i64 = 300;
+
+/// What to plot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PlotKind {
+    /// Virtual size by fee rate bucket over time
+    Heatmap,
+    /// The estimates `backtest` scores against the least the blocks that followed needed
+    Estimates,
+}
+
+pub struct Plot;
+
+impl Plot {
+    /// Render `kind` of `from..=to` to `path`, SVG or PNG by its extension. Snapshots are taken
+    /// `every_secs` apart, by default so the heatmap gets 300 columns and estimates are ten
+    /// minutes apart.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        storage: &dyn Storage,
+        kind: PlotKind,
+        path: &Path,
+        from: i64,
+        to: i64,
+        every_secs: Option<i64>,
+        target: u32,
+        confidence: f64,
+    ) -> Result<()> {
+        let replay = Replay::new(storage)?;
+        let (Some(first), Some(last)) = (replay.entries().first(), replay.latest()) else {
+            bail!("no recorded snapshots found");
+        };
+        let (from, to) = (from.max(first.timestamp), to.min(last));
+        if from > to {
+            bail!("no snapshots recorded between {from} and {to}");
+        }
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("svg") => {
+                let root = SVGBackend::new(path, SIZE).into_drawing_area();
+                Self::draw(
+                    root, storage, kind, from, to, every_secs, target, confidence,
+                )
+            }
+            Some("png") => {
+                let root = BitMapBackend::new(path, SIZE).into_drawing_area();
+                Self::draw(
+                    root, storage, kind, from, to, every_secs, target, confidence,
+                )
+            }
+            _ => bail!("plots are written as .svg or .png, not {}", path.display()),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn draw<DB: DrawingBackend>(
+        root: DrawingArea<DB, Shift>,
+        storage: &dyn Storage,
+        kind: PlotKind,
+        from: i64,
+        to: i64,
+        every_secs: Option<i64>,
+        target: u32,
+        confidence: f64,
+    ) -> Result<()> {
+        root.fill(&WHITE).map_err(|e| anyhow!("{e}"))?;
+        match kind {
+            PlotKind::Heatmap => {
+                let every_secs = every_secs.unwrap_or(((to - from) / HEATMAP_COLUMNS).max(1));
+                Self::heatmap(&root, storage, from, to, every_secs)?;
+            }
+            PlotKind::Estimates => {
+                let every_secs = every_secs.unwrap_or(600);
+                Self::estimates(&root, storage, from, to, every_secs, target, confidence)?;
+            }
+        }
+        root.present().map_err(|e| anyhow!("{e}"))
+    }
+
+    fn heatmap<DB: DrawingBackend>(
+        root: &DrawingArea<DB, Shift>,
+        storage: &dyn Storage,
+        from: i64,
+        to: i64,
+        every_secs: i64,
+    ) -> Result<()> {
+        // virtual size by bucket of each sampled snapshot
+        let mut columns: Vec<(i64, Vec<(usize, u64)>)> = Vec::new();
+        let mut next_sample = from;
+        Replay::new(storage)?.walk(from, to, |snapshot| {
+            if snapshot.timestamp < next_sample {
+                return Ok(());
+            }
+            next_sample = snapshot.timestamp + every_secs;
+            let histogram = Histogram::from_snapshot(snapshot);
+            let buckets = histogram
+                .buckets
+                .iter()
+                .filter_map(|bucket| {
+                    let index = BUCKETS.iter().position(|b| *b == bucket.fee_rate)?;
+                    Some((index, bucket.vsize))
+                })
+                .collect();
+            columns.push((snapshot.timestamp, buckets));
+            Ok(())
+        })?;
+        let max_vsize = columns
+            .iter()
+            .flat_map(|(_, buckets)| buckets.iter().map(|(_, vsize)| *vsize))
+            .max()
+            .unwrap_or(1)
+            .max(2);
+
+        let mut chart = ChartBuilder::on(root)
+            .caption("mempool virtual size by fee rate", ("sans-serif", 24))
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(60)
+            .build_cartesian_2d(from..to + every_secs, 0..BUCKETS.len())
+            .map_err(|e| anyhow!("{e}"))?;
+        chart
+            .configure_mesh()
+            .disable_mesh()
+            .x_label_formatter(&Self::time_label)
+            .y_labels(BUCKETS.len())
+            .y_label_formatter(&|index| {
+                BUCKETS
+                    .get(*index)
+                    .map(|rate| format!("{rate}"))
+                    .unwrap_or_default()
+            })
+            .y_desc("sat/vB")
+            .draw()
+            .map_err(|e| anyhow!("{e}"))?;
+
+        let ends = columns
+            .iter()
+            .skip(1)
+            .map(|(timestamp, _)| *timestamp)
+            .chain([columns
+                .last()
+                .map_or(to, |(timestamp, _)| timestamp + every_secs)]);
+        let cells = columns
+            .iter()
+            .zip(ends)
+            .flat_map(|((start, buckets), end)| {
+                buckets.iter().map(move |(index, vsize)| {
+                    // log scale, so the bottom of the mempool doesn't drown out the rest
+                    let shade = (*vsize as f64).ln() / (max_vsize as f64).ln();
+                    let color = HSLColor(0.66 - 0.66 * shade.clamp(0., 1.), 0.9, 0.5);
+                    Rectangle::new([(*start, *index), (end, index + 1)], color.filled())
+                })
+            });
+        chart.draw_series(cells).map_err(|e| anyhow!("{e}"))?;
+        Ok(())
+    }
+
+    fn estimates<DB: DrawingBackend>(
+        root: &DrawingArea<DB, Shift>,
+        storage: &dyn Storage,
+        from: i64,
+        to: i64,
+        every_secs: i64,
+        target: u32,
+        confidence: f64,
+    ) -> Result<()> {
+        let series = Backtest::series(storage, from, to, every_secs, target, confidence)?;
+        if series.is_empty() {
+            bail!("no estimates between {from} and {to}");
+        }
+        let max_fee_rate = series
+            .iter()
+            .flat_map(|(_, estimate, needed)| [Some(*estimate), *needed])
+            .flatten()
+            .fold(1., f64::max);
+
+        let mut chart = ChartBuilder::on(root)
+            .caption(
+                format!(
+                    "estimates for {target} blocks at {confidence} against the fee rate needed"
+                ),
+                ("sans-serif", 24),
+            )
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(60)
+            .build_cartesian_2d(from..to, 0f64..max_fee_rate * 1.1)
+            .map_err(|e| anyhow!("{e}"))?;
+        chart
+            .configure_mesh()
+            .x_label_formatter(&Self::time_label)
+            .y_desc("sat/vB")
+            .draw()
+            .map_err(|e| anyhow!("{e}"))?;
+
+        chart
+            .draw_series(LineSeries::new(
+                series
+                    .iter()
+                    .map(|(timestamp, estimate, _)| (*timestamp, *estimate)),
+                &BLUE,
+            ))
+            .map_err(|e| anyhow!("{e}"))?
+            .label("estimate")
+            .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], BLUE));
+        chart
+            .draw_series(
+                series
+                    .iter()
+                    .filter_map(|(timestamp, _, needed)| Some((*timestamp, (*needed)?)))
+                    .map(|point| Circle::new(point, 2, RED.filled())),
+            )
+            .map_err(|e| anyhow!("{e}"))?
+            .label("needed")
+            .legend(|(x, y)| Circle::new((x + 10, y), 2, RED.filled()));
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()
+            .map_err(|e| anyhow!("{e}"))?;
+        Ok(())
+    }
+
+    fn time_label(timestamp: &i64) -> String {
+        Utc.timestamp_opt(*timestamp, 0)
+            .single()
+            .map(|time| time.format("%m-%d %H:%M").to_string())
+            .unwrap_or_default()
+    }
+}
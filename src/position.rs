@@ -0,0 +1,107 @@
+use crate::{
+    node::Node,
+    record::MempoolEntry,
+    replay::{Replay, Snapshot},
+    score::Score,
+    storage::Storage,
+    template::Template,
+};
+use anyhow::Result;
+use bitcoin::Denomination;
+use serde::Serialize;
+
+/// How much of a mempool is mined before a transaction paying a fee rate
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct QueuePosition {
+    pub height: u64,
+    /// Unix timestamp of the mempool
+    pub timestamp: i64,
+    /// `node` or `recorded`
+    pub source: &'static str,
+    /// The fee rate asked about, sat/vB
+    pub fee_rate: f64,
+    /// Transactions at or above it, by the rate of their package when the node knows it
+    pub txs: usize,
+    pub vsize: u64,
+    /// Full projected blocks at or above it
+    pub blocks: usize,
+    /// Projected block a transaction paying `fee_rate` lands in, 1 for the next one
+    pub block: usize,
+}
+
+impl QueuePosition {
+    /// Transactions given as `(fee rate in sat/vB, vsize)`, `template` packed from the same
+    /// mempool
+    pub fn new(
+        height: u64,
+        timestamp: i64,
+        source: &'static str,
+        fee_rate: f64,
+        transactions: impl IntoIterator<Item = (f64, u64)>,
+        template: &Template,
+    ) -> Self {
+        let (txs, vsize) = transactions
+            .into_iter()
+            .filter(|(rate, _)| *rate >= fee_rate)
+            .fold((0, 0), |(txs, total), (_, vsize)| (txs + 1, total + vsize));
+        let blocks = template
+            .blocks
+            .iter()
+            .take_while(|block| block.full && block.min_fee_rate >= fee_rate)
+            .count();
+        QueuePosition {
+            height,
+            timestamp,
+            source,
+            fee_rate,
+            txs,
+            vsize,
+            blocks,
+            block: blocks + 1,
+        }
+    }
+
+    /// The node's mempool right now
+    pub async fn from_node(node: &dyn Node, fee_rate: f64) -> Result<Self> {
+        let height = node.get_chain_info().await?.blocks;
+        let mempool = MempoolEntry::mempool(node.get_mempool().await?);
+        let effective = Score::effective_fee_rates(&mempool);
+        let transactions = mempool.iter().map(|(txid, entry)| {
+            let fee = entry.fee.to_float_in(Denomination::Satoshi);
+            let own = fee / entry.vsize.max(1) as f64;
+            (effective.get(txid).copied().unwrap_or(own), entry.vsize)
+        });
+        Ok(Self::new(
+            height,
+            chrono::Utc::now().timestamp(),
+            "node",
+            fee_rate,
+            transactions,
+            &Template::build(&mempool, usize::MAX),
+        ))
+    }
+
+    /// The mempool of the latest recorded snapshot
+    pub fn from_recorded(storage: &dyn Storage, fee_rate: f64) -> Result<Self> {
+        Ok(Self::from_snapshot(
+            &Replay::new(storage)?.at(i64::MAX)?,
+            fee_rate,
+        ))
+    }
+
+    /// A recorded mempool
+    pub fn from_snapshot(snapshot: &Snapshot, fee_rate: f64) -> Self {
+        let transactions = snapshot
+            .transactions
+            .values()
+            .map(|tx| (tx.fee_rate_sat_vb(), (tx.weight / 4.).ceil() as u64));
+        Self::new(
+            snapshot.height,
+            snapshot.timestamp,
+            "recorded",
+            fee_rate,
+            transactions,
+            &Template::from_snapshot(snapshot, usize::MAX),
+        )
+    }
+}
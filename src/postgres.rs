@@ -0,0 +1,190 @@
+use crate::{
+    dataset::{FileKind, SnapshotFile},
+    sink::Sink,
+};
+use anyhow::Result;
+use bitcoin::Network;
+use chrono::{DateTime, Utc};
+use polars::prelude::*;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_postgres::{Client, NoTls};
+use tracing::{debug, info, warn};
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS mempool_deltas (
+    time timestamptz NOT NULL,
+    network text NOT NULL,
+    height bigint NOT NULL,
+    kind text NOT NULL,
+    txid text NOT NULL,
+    weight double precision NOT NULL,
+    fee_sat double precision NOT NULL,
+    first_seen_at bigint,
+    block_hash text,
+    removal_reason text
+)";
+
+const INSERT: &str = "INSERT INTO mempool_deltas SELECT * FROM UNNEST(
+    $1::timestamptz[], $2::text[], $3::bigint[], $4::text[], $5::text[],
+    $6::double precision[], $7::double precision[], $8::bigint[], $9::text[], $10::text[]
+)";
+
+/// The rows of one full or delta file
+struct Batch {
+    time: DateTime<Utc>,
+    height: u64,
+    kind: FileKind,
+    txids: Vec<String>,
+    weights: Vec<f64>,
+    fees: Vec<f64>,
+    first_seen: Vec<Option<i64>>,
+    block_hashes: Vec<Option<String>>,
+    removal_reasons: Vec<Option<String>>,
+}
+
+/// Inserts the rows of full and delta files into a `mempool_deltas` table, a TimescaleDB
+/// hypertable when the extension is installed. Rows are queued in memory while the database
+/// is unreachable.
+pub struct PostgresSink {
+    sender: UnboundedSender<Batch>,
+}
+
+impl PostgresSink {
+    pub fn start(url: String, network: Network) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(Self::run(url, network, receiver));
+        PostgresSink { sender }
+    }
+
+    async fn connect(url: &str) -> Result<Client> {
+        let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                warn!("postgres connection closed: {e}");
+            }
+        });
+        client.batch_execute(CREATE_TABLE).await?;
+        let timescale = client
+            .query_opt(
+                "SELECT 1 FROM pg_extension WHERE extname = 'timescaledb'",
+                &[],
+            )
+            .await?
+            .is_some();
+        if timescale {
+            client
+                .execute(
+                    "SELECT create_hypertable('mempool_deltas', 'time', if_not_exists => TRUE)",
+                    &[],
+                )
+                .await?;
+        }
+        info!("connected to postgres, timescaledb: {timescale}");
+        Ok(client)
+    }
+
+    async fn run(url: String, network: Network, mut receiver: UnboundedReceiver<Batch>) {
+        let network = network.to_string();
+        let mut client: Option<Client> = None;
+        while let Some(batch) = receiver.recv().await {
+            // keep trying the same batch, later ones wait in the channel
+            let mut delay = Duration::from_secs(1);
+            while let Err(e) = Self::deliver(&mut client, &url, &network, &batch).await {
+                warn!(
+                    "postgres sink failed, retrying in {}s: {e:#}",
+                    delay.as_secs()
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+        }
+    }
+
+    /// Insert a batch, (re)connecting first if needed
+    async fn deliver(
+        client: &mut Option<Client>,
+        url: &str,
+        network: &str,
+        batch: &Batch,
+    ) -> Result<()> {
+        if client.is_none() {
+            *client = Some(Self::connect(url).await?);
+        }
+        let result = Self::insert(client.as_ref().unwrap(), network, batch).await;
+        if result.is_err() {
+            *client = None;
+        }
+        result
+    }
+
+    async fn insert(client: &Client, network: &str, batch: &Batch) -> Result<()> {
+        let n = batch.txids.len();
+        let times = vec![batch.time; n];
+        let networks = vec![network; n];
+        let heights = vec![batch.height as i64; n];
+        let kinds = vec![batch.kind.as_str(); n];
+        client
+            .execute(
+                INSERT,
+                &[
+                    &times,
+                    &networks,
+                    &heights,
+                    &kinds,
+                    &batch.txids,
+                    &batch.weights,
+                    &batch.fees,
+                    &batch.first_seen,
+                    &batch.block_hashes,
+                    &batch.removal_reasons,
+                ],
+            )
+            .await?;
+        debug!("inserted {n} rows");
+        Ok(())
+    }
+}
+
+impl Sink for PostgresSink {
+    fn name(&self) -> &str {
+        "postgres"
+    }
+
+    fn accept(&self, file: &SnapshotFile, frame: &DataFrame) -> Result<()> {
+        if !matches!(file.kind, FileKind::Full | FileKind::Delta) || frame.height() == 0 {
+            return Ok(());
+        }
+        let utf8 = |name: &str| -> Result<Vec<Option<String>>> {
+            Ok(frame
+                .column(name)?
+                .utf8()?
+                .into_iter()
+                .map(|v| v.map(str::to_string))
+                .collect())
+        };
+        let batch = Batch {
+            time: file.written_at(),
+            height: file.height,
+            kind: file.kind,
+            txids: utf8("txid")?.into_iter().flatten().collect(),
+            weights: frame.column("weight")?.f64()?.into_no_null_iter().collect(),
+            fees: frame
+                .column("fee_sat")?
+                .f64()?
+                .into_no_null_iter()
+                .collect(),
+            first_seen: frame
+                .column("first_seen_at")?
+                .u64()?
+                .into_iter()
+                .map(|v| v.map(|v| v as i64))
+                .collect(),
+            block_hashes: utf8("block_hash")?,
+            removal_reasons: utf8("removal_reason")?,
+        };
+        self.sender.send(batch)?;
+        Ok(())
+    }
+}
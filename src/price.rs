@@ -0,0 +1,120 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client;
+use serde::Serialize;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+/// mempool.space's prices, an object with a field per currency
+pub const DEFAULT_PRICE_URL: &str = "https://mempool.space/api/v1/prices";
+/// How long a fetched price is used before asking the feed again
+pub const DEFAULT_CACHE_SECS: u64 = 300;
+
+/// Virtual sizes of common transactions, what costs are told for
+pub const TX_SIZES: [(&str, u64); 4] = [
+    ("1-in-1-out P2WPKH", 110),
+    ("1-in-2-out P2WPKH", 141),
+    ("2-in-2-out P2WPKH", 209),
+    ("1-in-2-out P2TR", 154),
+];
+
+/// What a transaction of one of [`TX_SIZES`] pays at a fee rate
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct TxCost {
+    pub name: &'static str,
+    pub vsize: u64,
+    pub fee_sat: u64,
+    pub fiat: f64,
+}
+
+/// Costs at a fee rate in a currency
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct FiatCosts {
+    pub currency: String,
+    /// Of one bitcoin
+    pub price: f64,
+    pub transactions: Vec<TxCost>,
+}
+
+/// Bitcoin's price from an HTTP source answering JSON, kept for a while so estimates served
+/// often don't ask it every time. Any source works that has the price at a JSON pointer.
+pub struct PriceFeed {
+    client: Client,
+    url: String,
+    /// JSON pointer to the price, like `/USD`
+    pointer: String,
+    currency: String,
+    cache: Duration,
+    cached: Mutex<Option<(Instant, f64)>>,
+}
+
+impl PriceFeed {
+    /// `pointer` defaults to the field named after `currency`, as in [`DEFAULT_PRICE_URL`]
+    pub fn new(
+        client: Client,
+        currency: &str,
+        url: Option<String>,
+        pointer: Option<String>,
+        cache: Duration,
+    ) -> Self {
+        let currency = currency.to_uppercase();
+        PriceFeed {
+            client,
+            url: url.unwrap_or_else(|| DEFAULT_PRICE_URL.to_string()),
+            pointer: pointer.unwrap_or_else(|| format!("/{currency}")),
+            currency,
+            cache,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Of one bitcoin, from the cache if it's recent enough
+    pub async fn price(&self) -> Result<f64> {
+        if let Some((at, price)) = *self.cached.lock().unwrap() {
+            if at.elapsed() < self.cache {
+                return Ok(price);
+            }
+        }
+        let response: serde_json::Value = self
+            .client
+            .get(&self.url)
+            .send()
+            .await
+            .with_context(|| format!("GET {}", self.url))?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("decoding {}", self.url))?;
+        let price = response
+            .pointer(&self.pointer)
+            .and_then(|price| price.as_f64())
+            .ok_or_else(|| anyhow!("no price at {} in the answer of {}", self.pointer, self.url))?;
+        debug!("{} {price}", self.currency);
+        *self.cached.lock().unwrap() = Some((Instant::now(), price));
+        Ok(price)
+    }
+
+    /// What each of [`TX_SIZES`] pays at `fee_rate` (sat/vB)
+    pub async fn costs(&self, fee_rate: f64) -> Result<FiatCosts> {
+        let price = self.price().await?;
+        let transactions = TX_SIZES
+            .iter()
+            .map(|&(name, vsize)| {
+                let fee_sat = (fee_rate * vsize as f64).ceil() as u64;
+                TxCost {
+                    name,
+                    vsize,
+                    fee_sat,
+                    fiat: fee_sat as f64 / 1e8 * price,
+                }
+            })
+            .collect();
+        Ok(FiatCosts {
+            currency: self.currency.clone(),
+            price,
+            transactions,
+        })
+    }
+}
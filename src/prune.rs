@@ -0,0 +1,174 @@
+use crate::{
+    dataset::{FileKind, SnapshotFile},
+    storage::Storage,
+};
+use anyhow::Result;
+use std::collections::HashSet;
+use tracing::{debug, info, warn};
+
+/// How long recorded files are kept, and how much of them
+pub struct Retention {
+    pub days: Option<u32>,
+    /// Bytes the dataset may take up, the oldest files go first past it
+    pub max_bytes: Option<u64>,
+    /// Files are moved here instead of being deleted
+    pub archive: Option<Box<dyn Storage>>,
+}
+
+impl Retention {
+    pub fn prune(&self, storage: &dyn Storage, now: i64) -> Result<usize> {
+        let mut pruned = 0;
+        if let Some(days) = self.days {
+            let before = now - days as i64 * 24 * 60 * 60;
+            pruned += Prune::prune(storage, before, self.archive.as_deref())?;
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            pruned += Prune::prune_to_size(storage, max_bytes, self.archive.as_deref())?;
+        }
+        Ok(pruned)
+    }
+}
+
+pub struct Prune;
+
+impl Prune {
+    /// Remove, or move to `archive`, the files written before `before`. Files of a height
+    /// that still has files after `before` are kept, later deltas can't be replayed without
+    /// them.
+    #[tracing::instrument(skip(storage, archive))]
+    pub fn prune(
+        storage: &dyn Storage,
+        before: i64,
+        archive: Option<&dyn Storage>,
+    ) -> Result<usize> {
+        let expired = Self::expired(storage, before)?;
+        let mut pruned = 0;
+        storage.atomically("prune", &mut || {
+            for file in &expired {
+                if let Some(archive) = archive {
+                    let mut frame = storage.read(file)?;
+                    archive.write(file.written_at(), file.height, file.kind, &mut frame)?;
+                }
+                debug!("pruning {}", file.path.display());
+                storage.remove(file)?;
+                pruned += 1;
+            }
+            Ok(())
+        })?;
+        if pruned > 0 {
+            info!("pruned_files: {pruned}");
+        }
+        Ok(pruned)
+    }
+
+    /// The files written before `before` that no later file needs
+    pub(crate) fn expired(storage: &dyn Storage, before: i64) -> Result<Vec<SnapshotFile>> {
+        let files = storage.list()?;
+        let (old, kept): (Vec<SnapshotFile>, Vec<SnapshotFile>) = files
+            .into_iter()
+            .partition(|f| Self::last_timestamp(f) < before);
+        if old.is_empty() {
+            return Ok(old);
+        }
+        let mut needed: HashSet<u64> = kept.iter().map(|f| f.height).collect();
+        // heights continue past the start of a compacted day
+        for file in kept.iter().filter(|f| f.kind == FileKind::Compact) {
+            needed.extend(Self::heights(storage, file)?);
+        }
+
+        let mut expired = Vec::new();
+        for file in old {
+            let keep = match file.kind {
+                FileKind::Compact => Self::heights(storage, &file)?
+                    .iter()
+                    .any(|h| needed.contains(h)),
+                _ => needed.contains(&file.height),
+            };
+            if !keep {
+                expired.push(file);
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Remove, or move to `archive`, the oldest files until the dataset takes up at most
+    /// `max_bytes`. Compacted days go first, then whole heights of full and delta files, the
+    /// height being recorded is always kept. Deltas continuing a removed height into the
+    /// next day can't be replayed anymore and are skipped by readers.
+    #[tracing::instrument(skip(storage, archive))]
+    pub fn prune_to_size(
+        storage: &dyn Storage,
+        max_bytes: u64,
+        archive: Option<&dyn Storage>,
+    ) -> Result<usize> {
+        let files = storage.list()?;
+        let mut total: u64 = files.iter().filter_map(|f| storage.size(f)).sum();
+        if total <= max_bytes {
+            return Ok(0);
+        }
+        let latest = files.iter().map(|f| f.height).max();
+        let (mut compact, mut raw): (Vec<&SnapshotFile>, Vec<&SnapshotFile>) = files
+            .iter()
+            .filter(|f| matches!(f.kind, FileKind::Compact | FileKind::Full | FileKind::Delta))
+            .partition(|f| f.kind == FileKind::Compact);
+        compact.sort_by_key(|f| f.timestamp);
+        raw.retain(|f| Some(f.height) != latest);
+        raw.sort_by_key(|f| (f.height, f.timestamp));
+
+        let mut pruned = 0;
+        let mut files = compact.into_iter().chain(raw).peekable();
+        storage.atomically("prune", &mut || {
+            while total > max_bytes {
+                let Some(file) = files.next() else {
+                    warn!("{total} bytes left with only the latest height, more than {max_bytes}");
+                    break;
+                };
+                // a height goes as a whole, its deltas are nothing without its full snapshot
+                let mut height = vec![file];
+                if file.kind != FileKind::Compact {
+                    while let Some(next) = files.next_if(|f| f.height == file.height) {
+                        height.push(next);
+                    }
+                }
+                for file in height {
+                    let size = storage.size(file).unwrap_or_default();
+                    if let Some(archive) = archive {
+                        let mut frame = storage.read(file)?;
+                        archive.write(file.written_at(), file.height, file.kind, &mut frame)?;
+                    }
+                    debug!("pruning {} ({size} bytes)", file.path.display());
+                    storage.remove(file)?;
+                    total = total.saturating_sub(size);
+                    pruned += 1;
+                }
+            }
+            Ok(())
+        })?;
+        if pruned > 0 {
+            info!("pruned_files: {pruned}, dataset_bytes: {total}");
+        }
+        Ok(pruned)
+    }
+
+    /// Compact files hold snapshots up to the end of their day
+    fn last_timestamp(file: &SnapshotFile) -> i64 {
+        match file.kind {
+            FileKind::Compact => {
+                let next_day = file.day().succ_opt().unwrap();
+                next_day.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp() - 1
+            }
+            _ => file.timestamp,
+        }
+    }
+
+    /// Heights with rows in a compact file
+    pub(crate) fn heights(storage: &dyn Storage, file: &SnapshotFile) -> Result<HashSet<u64>> {
+        let frame = storage.read(file)?;
+        Ok(frame
+            .column("height")?
+            .u64()?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+}
@@ -0,0 +1,274 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bitcoin::{psbt::PartiallySignedTransaction, Script, Txid};
+use serde::Serialize;
+use std::path::Path;
+
+/// What a binary PSBT starts with
+const MAGIC: &[u8] = b"psbt\xff";
+/// Change below this many sat is dust to most outputs and better left to the fee
+const DUST_SAT: u64 = 546;
+/// A DER signature with its sighash byte at its largest, and the push of it
+const SIGNATURE_LEN: usize = 1 + 72;
+/// A compressed public key and the push of it
+const PUBKEY_LEN: usize = 1 + 33;
+/// A Schnorr signature with the default sighash and the push of it
+const SCHNORR_LEN: usize = 1 + 64;
+
+/// The change output of a PSBT at the suggested fee
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangeAdjustment {
+    pub index: usize,
+    /// sat
+    pub value: u64,
+    /// sat, none if the change can't pay the difference
+    pub adjusted: Option<u64>,
+    /// Whether what's left is below [`DUST_SAT`]
+    pub dust: bool,
+}
+
+/// The fee a PSBT needs to confirm within a target
+#[derive(Debug, Clone, Serialize)]
+pub struct PsbtFee {
+    pub txid: Txid,
+    /// Once every input is signed
+    pub vsize: u64,
+    /// sat
+    pub fee: u64,
+    /// sat/vB at `vsize`
+    pub fee_rate: f64,
+    pub target: u32,
+    pub confidence: f64,
+    /// sat/vB
+    pub target_fee_rate: f64,
+    /// sat
+    pub required_fee: u64,
+    /// What the fee has to grow by, negative when it pays more than needed
+    pub fee_delta: i64,
+    pub change: Option<ChangeAdjustment>,
+}
+
+impl PsbtFee {
+    /// A PSBT file, binary or base64 as most wallets export it
+    pub fn read(path: &Path) -> Result<PartiallySignedTransaction> {
+        let contents =
+            std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let bytes = if contents.starts_with(MAGIC) {
+            contents
+        } else {
+            let text =
+                String::from_utf8(contents).context("the PSBT is neither binary nor base64")?;
+            STANDARD
+                .decode(text.trim())
+                .context("the PSBT is neither binary nor base64")?
+        };
+        PartiallySignedTransaction::deserialize(&bytes).context("decoding the PSBT")
+    }
+
+    /// Sat spent by the inputs minus sat sent by the outputs. Every input has to carry the
+    /// output it spends.
+    pub fn fee(psbt: &PartiallySignedTransaction) -> Result<u64> {
+        let mut spent = 0;
+        for (index, (input, txin)) in psbt.inputs.iter().zip(&psbt.unsigned_tx.input).enumerate() {
+            spent += Self::spent_output(psbt, index)?.value;
+            if let Some(tx) = &input.non_witness_utxo {
+                if tx.txid() != txin.previous_output.txid {
+                    bail!("the previous transaction of input {index} isn't the one it spends");
+                }
+            }
+        }
+        let sent: u64 = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .map(|output| output.value)
+            .sum();
+        spent
+            .checked_sub(sent)
+            .ok_or_else(|| anyhow!("the outputs send {sent} sat but the inputs hold {spent}"))
+    }
+
+    /// Weight of the transaction once every input is signed. Finalized inputs count as they
+    /// are, the others as their script type is usually satisfied: single key P2PKH, P2WPKH,
+    /// P2SH-P2WPKH and P2TR key path, and multisig behind P2SH, P2WSH or P2SH-P2WSH.
+    pub fn weight(psbt: &PartiallySignedTransaction) -> Result<u64> {
+        let mut weight = psbt.unsigned_tx.weight().to_wu();
+        let mut witnesses = Vec::with_capacity(psbt.inputs.len());
+        for (index, input) in psbt.inputs.iter().enumerate() {
+            let (script_sig, witness) = match (&input.final_script_sig, &input.final_script_witness)
+            {
+                (None, None) => Self::satisfaction(psbt, index)?,
+                (script_sig, witness) => (
+                    script_sig.as_ref().map_or(0, |script| script.len()),
+                    witness.as_ref().map(|witness| witness.serialized_len()),
+                ),
+            };
+            // the unsigned transaction already has the length of an empty scriptSig
+            weight += 4 * (Self::compact_size(script_sig) - 1 + script_sig) as u64;
+            witnesses.push(witness);
+        }
+        if witnesses.iter().any(Option::is_some) {
+            // marker and flag, and an empty stack for each input without a witness
+            weight += 2;
+            weight += witnesses
+                .iter()
+                .map(|witness| witness.unwrap_or(1) as u64)
+                .sum::<u64>();
+        }
+        Ok(weight)
+    }
+
+    /// The output paying the change: the only one carrying key origins, which wallets add
+    /// to the outputs they own
+    pub fn change_output(psbt: &PartiallySignedTransaction) -> Option<usize> {
+        let mut owned = psbt.outputs.iter().enumerate().filter(|(_, output)| {
+            !output.bip32_derivation.is_empty() || !output.tap_key_origins.is_empty()
+        });
+        match (owned.next(), owned.next()) {
+            (Some((index, _)), None) => Some(index),
+            _ => None,
+        }
+    }
+
+    /// The fee `psbt` needs to pay `target_fee_rate`, taken from the change output at `change`
+    pub fn suggest(
+        psbt: &PartiallySignedTransaction,
+        change: Option<usize>,
+        target: u32,
+        confidence: f64,
+        target_fee_rate: f64,
+    ) -> Result<Self> {
+        let vsize = Self::weight(psbt)?.div_ceil(4);
+        let fee = Self::fee(psbt)?;
+        let required_fee = (target_fee_rate * vsize as f64).ceil() as u64;
+        let fee_delta = required_fee as i64 - fee as i64;
+        let change = match change {
+            Some(index) => {
+                let output = psbt
+                    .unsigned_tx
+                    .output
+                    .get(index)
+                    .ok_or_else(|| anyhow!("the PSBT has no output {index}"))?;
+                let adjusted = u64::try_from(output.value as i64 - fee_delta).ok();
+                Some(ChangeAdjustment {
+                    index,
+                    value: output.value,
+                    adjusted,
+                    dust: adjusted.is_none_or(|value| value < DUST_SAT),
+                })
+            }
+            None => None,
+        };
+        Ok(PsbtFee {
+            txid: psbt.unsigned_tx.txid(),
+            vsize,
+            fee,
+            fee_rate: fee as f64 / vsize as f64,
+            target,
+            confidence,
+            target_fee_rate,
+            required_fee,
+            fee_delta,
+            change,
+        })
+    }
+
+    /// The output input `index` spends, from its witness or previous transaction
+    fn spent_output(psbt: &PartiallySignedTransaction, index: usize) -> Result<bitcoin::TxOut> {
+        let input = &psbt.inputs[index];
+        if let Some(output) = &input.witness_utxo {
+            return Ok(output.clone());
+        }
+        let vout = psbt.unsigned_tx.input[index].previous_output.vout as usize;
+        input
+            .non_witness_utxo
+            .as_ref()
+            .and_then(|tx| tx.output.get(vout).cloned())
+            .ok_or_else(|| {
+                anyhow!("input {index} carries neither the output nor the transaction it spends")
+            })
+    }
+
+    /// Bytes of the scriptSig and of the witness, if any, that will sign input `index`
+    fn satisfaction(
+        psbt: &PartiallySignedTransaction,
+        index: usize,
+    ) -> Result<(usize, Option<usize>)> {
+        let input = &psbt.inputs[index];
+        let script_pubkey = Self::spent_output(psbt, index)?.script_pubkey;
+        let single_key = 1 + SIGNATURE_LEN + PUBKEY_LEN;
+        let push = |script: &Script| Self::push_len(script.len()) + script.len();
+        let witness_multisig = |script: &Script| -> Option<usize> {
+            let signatures = Self::multisig_signatures(script)?;
+            // the empty item CHECKMULTISIG pops one too many, the signatures and the script
+            Some(
+                Self::compact_size(signatures + 2)
+                    + 1
+                    + signatures * SIGNATURE_LEN
+                    + Self::compact_size(script.len())
+                    + script.len(),
+            )
+        };
+        let satisfaction = if script_pubkey.is_p2pkh() {
+            Some((SIGNATURE_LEN + PUBKEY_LEN, None))
+        } else if script_pubkey.is_v0_p2wpkh() {
+            Some((0, Some(single_key)))
+        } else if script_pubkey.is_v1_p2tr() {
+            // key path, script paths can't be told apart before signing
+            Some((0, Some(1 + SCHNORR_LEN)))
+        } else if script_pubkey.is_v0_p2wsh() {
+            input
+                .witness_script
+                .as_deref()
+                .and_then(witness_multisig)
+                .map(|witness| (0, Some(witness)))
+        } else if script_pubkey.is_p2sh() {
+            match input.redeem_script.as_deref() {
+                Some(redeem) if redeem.is_v0_p2wpkh() => Some((push(redeem), Some(single_key))),
+                Some(redeem) if redeem.is_v0_p2wsh() => input
+                    .witness_script
+                    .as_deref()
+                    .and_then(witness_multisig)
+                    .map(|witness| (push(redeem), Some(witness))),
+                Some(redeem) => Self::multisig_signatures(redeem)
+                    .map(|signatures| (1 + signatures * SIGNATURE_LEN + push(redeem), None)),
+                None => None,
+            }
+        } else {
+            None
+        };
+        satisfaction.ok_or_else(|| {
+            anyhow!("can't tell how input {index} will be signed, finalize it or add its scripts")
+        })
+    }
+
+    /// Signatures a bare `OP_m <keys> OP_n OP_CHECKMULTISIG` script takes
+    fn multisig_signatures(script: &Script) -> Option<usize> {
+        let bytes = script.as_bytes();
+        match (bytes.first(), bytes.last()) {
+            // OP_PUSHNUM_1 to OP_PUSHNUM_16, OP_CHECKMULTISIG
+            (Some(m @ 0x51..=0x60), Some(0xae)) => Some((m - 0x50) as usize),
+            _ => None,
+        }
+    }
+
+    /// Bytes of the opcode pushing `len` bytes onto the stack
+    fn push_len(len: usize) -> usize {
+        match len {
+            0..=75 => 1,
+            76..=0xff => 2,
+            0x100..=0xffff => 3,
+            _ => 5,
+        }
+    }
+
+    /// Bytes of a Bitcoin varint holding `n`
+    fn compact_size(n: usize) -> usize {
+        match n {
+            0..=0xfc => 1,
+            0xfd..=0xffff => 3,
+            0x10000..=0xffff_ffff => 5,
+            _ => 9,
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{
+    dataset::{FileKind, SnapshotFile},
+    storage::Storage,
+};
+use anyhow::{bail, Result};
+use polars::{prelude::*, sql::SQLContext};
+use std::collections::HashSet;
+use tracing::debug;
+
+/// Columns added by [`SnapshotFile::tag`]
+const TAG_COLUMNS: [&str; 3] = ["height", "snapshot_timestamp", "kind"];
+
+/// Tables registered for queries and the files each is made of
+pub(crate) const TABLES: [(&str, &[FileKind]); 13] = [
+    (
+        "deltas",
+        &[FileKind::Full, FileKind::Delta, FileKind::Compact],
+    ),
+    ("blocks", &[FileKind::Block]),
+    ("reorgs", &[FileKind::Reorg]),
+    ("meta", &[FileKind::Meta]),
+    ("events", &[FileKind::Events]),
+    ("rejected", &[FileKind::Rejected]),
+    ("core_estimates", &[FileKind::CoreEstimates]),
+    ("peer_feefilters", &[FileKind::PeerFeeFilters]),
+    ("shutdowns", &[FileKind::Shutdown]),
+    ("gaps", &[FileKind::Gap]),
+    ("histograms", &[FileKind::Histogram]),
+    ("block_fees", &[FileKind::BlockFees]),
+    ("block_headers", &[FileKind::BlockHeader]),
+];
+
+pub struct Query;
+
+impl Query {
+    /// SQL context with a table per kind of file: `deltas` (full and delta files), `blocks`,
+    /// `reorgs`, `meta`, `events`, `rejected`, `core_estimates`, `peer_feefilters`, `shutdowns`,
+    /// `gaps`, `histograms`, `block_fees` and `block_headers`. Rows are tagged with the
+    /// `height`, `snapshot_timestamp` and `kind` of their file, which is only read once a query
+    /// needs it.
+    pub fn context(storage: &dyn Storage) -> Result<SQLContext> {
+        let files = storage.list()?;
+        let mut context = SQLContext::new();
+        for (table, kinds) in TABLES {
+            let files: Vec<&SnapshotFile> =
+                files.iter().filter(|f| kinds.contains(&f.kind)).collect();
+            if files.is_empty() {
+                continue;
+            }
+            let frames = files
+                .iter()
+                .map(|file| Self::scan(storage, file))
+                .collect::<Result<Vec<_>>>()?;
+            let mut frame = diag_concat_lf(frames, false, false)?;
+            if Self::overlaps(&files) {
+                // compacted with --keep-raw, the raw copies would count twice
+                frame = frame.unique_stable(
+                    Some(vec![
+                        "snapshot_timestamp".into(),
+                        "kind".into(),
+                        "txid".into(),
+                    ]),
+                    UniqueKeepStrategy::First,
+                );
+            }
+            debug!("table: {table}, files: {}", files.len());
+            context.register(table, frame);
+        }
+        Ok(context)
+    }
+
+    /// Run `sql` against the recorded dataset
+    #[tracing::instrument(skip(storage))]
+    pub fn query(storage: &dyn Storage, sql: &str) -> Result<DataFrame> {
+        let mut context = Self::context(storage)?;
+        if context.get_tables().is_empty() {
+            bail!("no recorded files found");
+        }
+        Ok(context.execute(sql)?.collect()?)
+    }
+
+    /// The rows naming `txid` in `deltas`, `blocks` and `rejected`, by table. Only row groups
+    /// whose statistics may hold it are read, the recorder writes txids sorted.
+    #[tracing::instrument(skip(storage))]
+    pub fn lookup(storage: &dyn Storage, txid: &str) -> Result<Vec<(&'static str, DataFrame)>> {
+        let mut context = Self::context(storage)?;
+        let tables = context.get_tables();
+        let mut found = Vec::new();
+        for table in ["deltas", "blocks", "rejected"] {
+            if !tables.iter().any(|registered| registered == table) {
+                continue;
+            }
+            let frame = context
+                .execute(&format!("SELECT * FROM {table}"))?
+                .filter(col("txid").eq(lit(txid)))
+                .collect()?;
+            if frame.height() > 0 {
+                found.push((table, frame));
+            }
+        }
+        Ok(found)
+    }
+
+    fn scan(storage: &dyn Storage, file: &SnapshotFile) -> Result<LazyFrame> {
+        let frame = storage.scan(file)?;
+        Ok(match file.kind {
+            // already tagged, empty snapshots are kept as a placeholder row without txid
+            FileKind::Compact => frame.filter(col("txid").is_not_null()),
+            // literals in a select would collapse to a single row once the other columns are
+            // pruned, added as columns they take the height of the file
+            _ => frame
+                .with_columns([
+                    lit(file.height).alias("height"),
+                    lit(file.timestamp).alias("snapshot_timestamp"),
+                    lit(file.kind.as_str()).alias("kind"),
+                ])
+                .select([cols(TAG_COLUMNS), all().exclude(TAG_COLUMNS)]),
+        })
+    }
+
+    /// Whether raw files were kept for a day that has been compacted
+    fn overlaps(files: &[&SnapshotFile]) -> bool {
+        let compacted: HashSet<_> = files
+            .iter()
+            .filter(|f| f.kind == FileKind::Compact)
+            .map(|f| f.day())
+            .collect();
+        files
+            .iter()
+            .any(|f| f.kind != FileKind::Compact && compacted.contains(&f.day()))
+    }
+}
@@ -0,0 +1,37 @@
+use bitcoin::{OutPoint, Transaction, Txid};
+use std::collections::HashMap;
+
+/// Outpoints spent by the transactions in the mempool, to tell which new transaction replaced
+/// which removed one. Only transactions whose inputs were looked up are known.
+#[derive(Debug, Default)]
+pub struct Spends {
+    inputs: HashMap<Txid, Vec<OutPoint>>,
+}
+
+impl Spends {
+    /// Forget `removed` and index `added`. Returns the new transactions that spend an outpoint
+    /// of a removed one, with one of the transactions they replaced.
+    pub fn update(
+        &mut self,
+        removed: &[Txid],
+        added: &HashMap<Txid, Transaction>,
+    ) -> HashMap<Txid, Txid> {
+        let mut freed: HashMap<OutPoint, Txid> = HashMap::new();
+        for txid in removed {
+            for outpoint in self.inputs.remove(txid).unwrap_or_default() {
+                freed.insert(outpoint, *txid);
+            }
+        }
+
+        let mut replaces = HashMap::new();
+        for (txid, tx) in added {
+            let outpoints: Vec<OutPoint> =
+                tx.input.iter().map(|input| input.previous_output).collect();
+            if let Some(replaced) = outpoints.iter().find_map(|outpoint| freed.get(outpoint)) {
+                replaces.insert(*txid, *replaced);
+            }
+            self.inputs.insert(*txid, outpoints);
+        }
+        replaces
+    }
+}
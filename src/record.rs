@@ -1,49 +1,914 @@
-use bitcoin::{Denomination, Txid};
-use bitcoincore_rest::{responses::GetMempoolEntryResult, Error, RestApi, RestClient};
-use chrono::Timelike;
+use crate::{
+    calc::{MIN_RELAY_FEE_RATE, TARGETS},
+    congestion::Congestion,
+    dataset::{FileKind, SnapshotFile},
+    histogram::{Histogram, BUCKETS},
+    metrics::Metrics,
+    node::Node,
+    prune::Retention,
+    rbf::Spends,
+    score::Score,
+    storage::Storage,
+    systemd::Notifier,
+    template::Template,
+    writer::Writer,
+    zmq::{Event, ZmqListener},
+};
+use anyhow::{bail, Result};
+use bitcoin::{Amount, Block, BlockHash, Denomination, Network, Txid, Wtxid};
+use bitcoincore_rest::responses::{GetMempoolEntryResult, GetMempoolInfoResult};
+use chrono::{DateTime, Utc};
 use polars::prelude::*;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
     time::Instant,
 };
-use tracing::info;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::mpsc,
+};
+use tracing::{error, info, warn};
+
+/// Mempool entries by txid, what the recorder keeps of Bitcoin Core's verbose `getrawmempool`
+pub type Mempool = HashMap<Txid, MempoolEntry>;
+
+/// The fields of a mempool entry that are recorded or estimated from. Against the whole
+/// `getmempoolentry` answer it drops the ancestor size, narrows the counts and keeps the links
+/// to other transactions in boxed slices: 152 bytes an entry instead of 192.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MempoolEntry {
+    /// Commits to the witness too, what package relay and witness data are keyed by
+    pub wtxid: Wtxid,
+    pub vsize: u64,
+    pub weight: u64,
+    /// Unix time it entered the mempool
+    pub time: u64,
+    /// Height the chain had when it entered
+    pub height: u64,
+    pub fee: Amount,
+    /// With `prioritisetransaction` applied, what miners see
+    pub modified_fee: Amount,
+    /// Of the transaction and its in-mempool ancestors
+    pub ancestor_fees: Amount,
+    /// Of the transaction and its in-mempool descendants
+    pub descendant_fees: Amount,
+    pub ancestor_count: u32,
+    pub descendant_count: u32,
+    pub descendant_size: u64,
+    pub bip125_replaceable: bool,
+    pub unbroadcast: Option<bool>,
+    /// In-mempool parents
+    pub depends: Box<[Txid]>,
+    /// In-mempool children
+    pub spent_by: Box<[Txid]>,
+}
+
+impl From<GetMempoolEntryResult> for MempoolEntry {
+    fn from(entry: GetMempoolEntryResult) -> Self {
+        MempoolEntry {
+            // the REST client types it as a txid
+            wtxid: Wtxid::from_raw_hash(entry.wtxid.to_raw_hash()),
+            vsize: entry.vsize,
+            weight: entry.weight.unwrap_or(entry.vsize * 4),
+            time: entry.time,
+            height: entry.height,
+            fee: entry.fees.base,
+            modified_fee: entry.fees.modified,
+            ancestor_fees: entry.fees.ancestor,
+            descendant_fees: entry.fees.descendant,
+            ancestor_count: entry.ancestor_count as u32,
+            descendant_count: entry.descendant_count as u32,
+            descendant_size: entry.descendant_size,
+            bip125_replaceable: entry.bip125_replaceable,
+            unbroadcast: entry.unbroadcast,
+            depends: entry.depends.into_boxed_slice(),
+            spent_by: entry.spent_by.into_boxed_slice(),
+        }
+    }
+}
+
+impl MempoolEntry {
+    /// Entries the way a [`Node`] returns them, converted as they come in
+    pub fn mempool(entries: HashMap<Txid, GetMempoolEntryResult>) -> Mempool {
+        entries
+            .into_iter()
+            .map(|(txid, entry)| (txid, entry.into()))
+            .collect()
+    }
+}
+
+/// Seconds between snapshots when polling only
+pub const POLL_INTERVAL_SECS: u32 = 15;
+/// Seconds between reconciliation snapshots when ZMQ notifications are recorded
+pub const ZMQ_INTERVAL_SECS: u32 = 60;
+const MAX_INTERVAL_SECS: u32 = 60 * 60;
+/// Seconds after a new block that snapshots are taken at the burst interval
+const BURST_SECS: i64 = 60;
+/// Inputs are looked up for at most this many new transactions per snapshot, so the initial
+/// mempool isn't fetched one transaction at a time. Replacements of those go undetected.
+const MAX_INPUT_LOOKUPS: usize = 5_000;
+/// With more of the mempool new than this, after startup or a long gap, the whole verbose
+/// mempool is fetched at once instead of asking for each new entry
+const FULL_FETCH_FRACTION: f64 = 0.5;
+/// Blocks kept to label their transactions again after a reorg, deeper ones are only reported
+const REORG_DEPTH: usize = 10;
+/// Numbers the snapshots, see [`Record::stamp`]
+const SEQUENCE_COLUMN: &str = "snapshot_sequence";
+/// A transaction confirmed paying less than this fraction of its projected block's cutoff was
+/// likely prioritised or accelerated out of band
+const OUT_OF_BAND_FRACTION: f64 = 0.5;
+/// Lower bounds of the fee rate bands whose virtual size goes into the meta file, sat/vB
+pub(crate) const FEE_BANDS: [f64; 11] = [0., 1., 2., 5., 10., 20., 50., 100., 200., 500., 1000.];
+
+/// How taking a snapshot went, kept in the meta file to follow the recorder over months
+struct Telemetry {
+    /// Fetching the txids and the entries of new transactions
+    load_mempool_duration_millis: u64,
+    /// Entries the recorder holds, Core's own count is `mempool_txs`
+    mempool_entries: u64,
+    /// Of the full, delta or histogram file
+    delta_rows: u64,
+    /// Cadence ticks missed right before the snapshot, the previous one ran late
+    skipped_ticks: u64,
+    /// A histogram was written instead of transactions, see [`Overload`]
+    degraded: bool,
+}
+
+/// Tracks snapshots taking longer than the interval. Once `after` in a row did, recording
+/// degrades to a histogram per snapshot, which is cheap to write, instead of missing ticks
+/// without a trace. It recovers with a full snapshot once as many in a row ran on time.
+struct Overload {
+    after: Option<u32>,
+    in_a_row: u32,
+    degraded: bool,
+}
+
+impl Overload {
+    fn new(after: Option<u32>) -> Self {
+        Overload {
+            after: after.filter(|after| *after > 0),
+            in_a_row: 0,
+            degraded: false,
+        }
+    }
+
+    /// Note whether the last snapshot overran the interval, `Some` with the new state when
+    /// recording degrades or recovers
+    fn observe(&mut self, overran: bool) -> Option<bool> {
+        let after = self.after?;
+        // counts overruns while recording in full and snapshots on time while degraded
+        if overran != self.degraded {
+            self.in_a_row += 1;
+        } else {
+            self.in_a_row = 0;
+        }
+        if self.in_a_row < after {
+            return None;
+        }
+        self.in_a_row = 0;
+        self.degraded = !self.degraded;
+        Some(self.degraded)
+    }
+}
+
+/// A block of the chain the recording follows
+struct ChainBlock {
+    hash: BlockHash,
+    /// Recorded entries of the transactions it confirmed
+    confirmed: Mempool,
+}
+
+/// When snapshots are taken
+#[derive(Debug, Clone, Copy)]
+pub struct Cadence {
+    interval_secs: u32,
+    /// Snapshot on multiples of the interval since the epoch (e.g. :00/:15/:30/:45) rather
+    /// than counting from the previous snapshot
+    aligned: bool,
+    /// Seconds between snapshots for the first [`BURST_SECS`] after a new block
+    burst_interval_secs: Option<u32>,
+}
+
+impl Cadence {
+    pub fn new(interval_secs: u32, aligned: bool) -> Result<Self> {
+        if !(1..=MAX_INTERVAL_SECS).contains(&interval_secs) {
+            bail!(
+                "interval must be between 1 and {MAX_INTERVAL_SECS} seconds, got {interval_secs}"
+            );
+        }
+        Ok(Cadence {
+            interval_secs,
+            aligned,
+            burst_interval_secs: None,
+        })
+    }
+
+    /// Snapshot every `burst_interval_secs` for a minute after each new block, when the fee
+    /// rates move the most, then go back to the interval
+    pub fn with_burst(mut self, burst_interval_secs: u32) -> Result<Self> {
+        if !(1..self.interval_secs).contains(&burst_interval_secs) {
+            bail!(
+                "burst interval must be shorter than the interval of {} seconds, got {burst_interval_secs}",
+                self.interval_secs
+            );
+        }
+        self.burst_interval_secs = Some(burst_interval_secs);
+        Ok(self)
+    }
 
-type Mempool = HashMap<Txid, GetMempoolEntryResult>;
+    pub fn interval_secs(&self) -> u32 {
+        self.interval_secs
+    }
+
+    /// Seconds between snapshots while they're taken the most often
+    pub fn shortest_interval_secs(&self) -> u32 {
+        self.burst_interval_secs.unwrap_or(self.interval_secs)
+    }
+
+    fn is_due(&self, now: i64, prev_timestamp: i64) -> bool {
+        if self.aligned {
+            now % self.interval_secs as i64 == 0
+        } else {
+            now - prev_timestamp >= self.interval_secs as i64
+        }
+    }
+
+    /// Whether a burst snapshot is due, `block_seen_at` being when the last new block was
+    fn is_burst_due(&self, now: i64, prev_timestamp: i64, block_seen_at: Option<i64>) -> bool {
+        match (self.burst_interval_secs, block_seen_at) {
+            (Some(burst), Some(seen)) => {
+                now - seen < BURST_SECS && now - prev_timestamp >= burst as i64
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Why a snapshot was taken when it was
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Trigger {
+    Cadence,
+    /// A block notification, ahead of the cadence
+    Block,
+    /// The burst after a new block, see [`Cadence::with_burst`]
+    Burst,
+}
+
+/// Transactions left out of the full and delta files to keep the dataset small. The meta
+/// file still covers the whole mempool and counts what was left out, to correct for later.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Filter {
+    /// Effective fee rate below which transactions aren't recorded, sat/vB
+    pub min_fee_rate: Option<f64>,
+    /// Transactions recorded per fee rate bucket of [`BUCKETS`], those arriving once a bucket
+    /// is full aren't
+    pub max_entries_per_bucket: Option<usize>,
+}
+
+impl Filter {
+    pub fn is_active(&self) -> bool {
+        self.min_fee_rate.is_some() || self.max_entries_per_bucket.is_some()
+    }
+}
+
+/// Which transactions of the mempool a [`Filter`] left out so far
+struct Filtered {
+    filter: Filter,
+    skipped: HashSet<Txid>,
+    /// Bucket of each recorded transaction, only kept with a cap per bucket
+    buckets: HashMap<Txid, usize>,
+    recorded: [usize; BUCKETS.len()],
+}
+
+impl Filtered {
+    fn new(filter: Filter) -> Self {
+        Filtered {
+            filter,
+            skipped: HashSet::new(),
+            buckets: HashMap::new(),
+            recorded: [0; BUCKETS.len()],
+        }
+    }
+
+    /// Start over, a full snapshot decides again for the whole mempool
+    fn reset(&mut self) {
+        self.skipped.clear();
+        self.buckets.clear();
+        self.recorded = [0; BUCKETS.len()];
+    }
+
+    /// Whether a transaction entering the mempool at `fee_rate` is recorded
+    fn admit(&mut self, txid: &Txid, fee_rate: f64) -> bool {
+        if self.filter.min_fee_rate.is_some_and(|min| fee_rate < min) {
+            self.skipped.insert(*txid);
+            return false;
+        }
+        if let Some(max) = self.filter.max_entries_per_bucket {
+            let bucket = BUCKETS.partition_point(|bound| *bound <= fee_rate).max(1) - 1;
+            if self.recorded[bucket] >= max {
+                self.skipped.insert(*txid);
+                return false;
+            }
+            self.recorded[bucket] += 1;
+            self.buckets.insert(*txid, bucket);
+        }
+        true
+    }
+
+    /// Whether the removal of a transaction is recorded, only if its arrival was
+    fn remove(&mut self, txid: &Txid) -> bool {
+        if self.skipped.remove(txid) {
+            return false;
+        }
+        if let Some(bucket) = self.buckets.remove(txid) {
+            self.recorded[bucket] -= 1;
+        }
+        true
+    }
+
+    /// Transactions and virtual size of `mempool` left out
+    fn totals(&self, mempool: &Mempool) -> (u64, u64) {
+        self.skipped
+            .iter()
+            .filter_map(|txid| mempool.get(txid))
+            .fold((0, 0), |(txs, vsize), entry| (txs + 1, vsize + entry.vsize))
+    }
+}
+
+/// Core's default `-mempoolexpiry` of 336 hours
+const MEMPOOL_EXPIRY_SECS: u64 = 336 * 60 * 60;
+
+/// Why a transaction left the mempool between two snapshots of the same height
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RemovalReason {
+    /// Confirmed by a block connected while the snapshot was taken
+    Mined,
+    /// Replaceable and otherwise unexplained, so most likely replaced (or conflicted by a replacement)
+    RbfReplaced,
+    Expired,
+    /// Evicted by the node trimming its full mempool: the minimum mempool fee rose above the
+    /// relay fee and its descendant fee rate is below it. Written `evicted-below-minfee` by
+    /// recorders before, `wtf migrate` renames those.
+    Purged,
+    Unknown,
+}
+
+impl RemovalReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RemovalReason::Mined => "mined",
+            RemovalReason::RbfReplaced => "rbf-replaced",
+            RemovalReason::Expired => "expired",
+            RemovalReason::Purged => "purged",
+            RemovalReason::Unknown => "unknown",
+        }
+    }
+}
+
+/// What is known about the node's state when classifying removals
+struct RemovalContext {
+    mined: HashSet<Txid>,
+    /// Spent an outpoint that a new transaction spends now
+    replaced: HashSet<Txid>,
+    mempool_min_fee_sat_vb: f64,
+    min_relay_fee_sat_vb: f64,
+    full_rbf: bool,
+    now: u64,
+}
+
+impl RemovalContext {
+    /// The node's mempool is full, it evicts the lowest paying packages to make room and raises
+    /// its minimum fee above the relay fee to match
+    fn purging(&self) -> bool {
+        self.mempool_min_fee_sat_vb > self.min_relay_fee_sat_vb
+    }
+
+    fn classify(&self, txid: &Txid, entry: &MempoolEntry) -> RemovalReason {
+        let descendant_fee_rate = entry.descendant_fees.to_float_in(Denomination::Satoshi)
+            / entry.descendant_size.max(1) as f64;
+        if self.mined.contains(txid) {
+            RemovalReason::Mined
+        } else if self.replaced.contains(txid) {
+            RemovalReason::RbfReplaced
+        } else if self.now.saturating_sub(entry.time) >= MEMPOOL_EXPIRY_SECS {
+            RemovalReason::Expired
+        } else if self.purging() && descendant_fee_rate < self.mempool_min_fee_sat_vb {
+            RemovalReason::Purged
+        } else if entry.bip125_replaceable || self.full_rbf {
+            RemovalReason::RbfReplaced
+        } else {
+            RemovalReason::Unknown
+        }
+    }
+}
+
+/// The rows of a full or delta file, what [`Record::record`] writes for every snapshot.
+/// Embedders polling a node themselves build these and hand them to
+/// [`Dataset::write`](crate::dataset::Dataset::write).
+#[derive(Debug, Clone)]
+pub struct DeltaFrame {
+    pub kind: FileKind,
+    pub frame: DataFrame,
+}
+
+impl DeltaFrame {
+    /// Every transaction of `mempool`, the first snapshot after the block `block_hash`
+    pub fn full(mempool: &Mempool, block_hash: &BlockHash) -> Self {
+        let context = RemovalContext {
+            mined: HashSet::new(),
+            replaced: HashSet::new(),
+            mempool_min_fee_sat_vb: 0.,
+            min_relay_fee_sat_vb: 0.,
+            full_rbf: false,
+            now: Utc::now().timestamp() as u64,
+        };
+        let packages = Score::packages(mempool);
+        DeltaFrame {
+            kind: FileKind::Full,
+            frame: Record::create_delta(
+                &[],
+                mempool.iter(),
+                &block_hash.to_string(),
+                &context,
+                &Score::fee_rates(&packages),
+                &Score::queue_percentiles(mempool, &packages),
+                &HashMap::new(),
+            ),
+        }
+    }
+
+    /// What changed from `previous` to `current`, both at the block `block_hash`. Removals
+    /// in `mined` are labelled mined, the rest expired, purged or replaced like the recorder
+    /// does without looking at inputs.
+    pub fn between(
+        previous: &Mempool,
+        current: &Mempool,
+        block_hash: &BlockHash,
+        mined: &HashSet<Txid>,
+    ) -> Self {
+        let context = RemovalContext {
+            mined: mined.clone(),
+            replaced: HashSet::new(),
+            mempool_min_fee_sat_vb: 0.,
+            min_relay_fee_sat_vb: 0.,
+            full_rbf: false,
+            now: Utc::now().timestamp() as u64,
+        };
+        let removed: Vec<(Txid, MempoolEntry)> = previous
+            .iter()
+            .filter(|(txid, _)| !current.contains_key(*txid))
+            .map(|(txid, entry)| (*txid, entry.clone()))
+            .collect();
+        let added = current
+            .iter()
+            .filter(|(txid, _)| !previous.contains_key(*txid));
+        // removed transactions keep the rate and place they had in the previous mempool
+        let (previous_packages, packages) = (Score::packages(previous), Score::packages(current));
+        let mut rates = Score::fee_rates(&previous_packages);
+        rates.extend(Score::fee_rates(&packages));
+        let mut percentiles = Score::queue_percentiles(previous, &previous_packages);
+        percentiles.extend(Score::queue_percentiles(current, &packages));
+        DeltaFrame {
+            kind: FileKind::Delta,
+            frame: Record::create_delta(
+                &removed,
+                added,
+                &block_hash.to_string(),
+                &context,
+                &rates,
+                &percentiles,
+                &HashMap::new(),
+            ),
+        }
+    }
+}
+
+/// What a recording may switch to while it runs, see [`Record::record`]
+pub struct Settings {
+    pub node: Box<dyn Node>,
+    pub cadence: Cadence,
+    pub retention: Option<Retention>,
+}
+
+/// How a recording goes besides its [`Settings`], see [`Record::record`]
+#[derive(Default)]
+pub struct RecordOptions {
+    /// Take snapshots as the node announces changes instead of polling
+    pub zmq_endpoints: Vec<String>,
+    /// Record Bitcoin Core's `estimatesmartfee` for every snapshot too, to compare against
+    pub core_estimates: bool,
+    /// Record the minimum fee rates the node's peers relay
+    pub peer_fee_filters: bool,
+    /// Write no txids, only a histogram of the mempool by fee rate per snapshot
+    pub aggregate: bool,
+    /// Switch to histograms after that many snapshots in a row overran the interval, and back
+    /// once as many ran on time
+    pub degrade_after: Option<u32>,
+    /// When active, only the transactions it lets through are written
+    pub filter: Filter,
+    /// Watches every snapshot's next-block cutoff and depth for fee spikes and clearings
+    pub congestion: Option<Congestion>,
+    /// Settings switched to between snapshots, keeping the mempool held
+    pub reloads: Option<mpsc::Receiver<Settings>>,
+}
 
 pub struct Record;
 
 impl Record {
-    #[tracing::instrument]
-    pub async fn record(data_dir: String, bitcoin_core_endpoint: String) -> Result<(), Error> {
-        let rest_client = RestClient::new(bitcoin_core_endpoint);
+    /// Record until stopped, as `options` ask. Under systemd the service is ready once the
+    /// first snapshot is taken, and the watchdog is fed as long as snapshots keep being taken
+    /// on time.
+    #[tracing::instrument(skip(storage, settings, metrics, options))]
+    pub async fn record(
+        storage: Box<dyn Storage>,
+        settings: Settings,
+        network: Network,
+        metrics: Arc<Metrics>,
+        options: RecordOptions,
+    ) -> Result<()> {
+        let Settings {
+            mut node,
+            mut cadence,
+            mut retention,
+        } = settings;
+        let RecordOptions {
+            zmq_endpoints,
+            core_estimates,
+            peer_fee_filters,
+            aggregate,
+            degrade_after,
+            filter,
+            mut congestion,
+            mut reloads,
+        } = options;
+        if aggregate && filter.is_active() {
+            bail!("filters pick the transactions to record, aggregate records none");
+        }
+        let chain = node.get_chain_info().await?.chain;
+        if Network::from_core_arg(&chain).ok() != Some(network) {
+            bail!(
+                "node is on {chain}, expected {}, pass the matching --network",
+                network.to_core_arg()
+            );
+        }
+        Self::recover(storage.as_ref());
+        let storage: Arc<dyn Storage> = Arc::from(storage);
+        let writer = Writer::start(storage.clone(), metrics.clone());
+        let previous = Self::last_file(storage.as_ref());
+        Self::check_previous_shutdown(previous.as_ref());
+        // snapshots are numbered on from the previous run
+        let mut sequence = previous
+            .as_ref()
+            .map_or(0, |previous| Self::sequence(storage.as_ref(), previous));
+        // after downtime the first snapshot is taken right away, without waiting for the cadence
+        let mut catch_up = false;
+        if let Some(previous) = &previous {
+            let now = Utc::now();
+            if now.timestamp() - previous.timestamp > cadence.interval_secs as i64 {
+                let height = node.get_chain_info().await?.blocks;
+                let gap = Self::create_gap(previous, &cadence, now);
+                info!(
+                    "nothing recorded since height {} at {}, taking a full snapshot",
+                    previous.height, previous.timestamp
+                );
+                sequence += 1;
+                Self::write(&writer, now, sequence, height, FileKind::Gap, gap).await;
+                catch_up = true;
+            }
+        }
+        Self::apply_retention(storage.as_ref(), retention.as_ref());
+        let mut terminate = signal(SignalKind::terminate())?;
+
+        let mut events = if zmq_endpoints.is_empty() {
+            None
+        } else {
+            Some(ZmqListener::subscribe(&zmq_endpoints).await?)
+        };
+        let mut pending_events: Vec<Event> = Vec::new();
+        let mut snapshot_due = false;
 
         let mut prev_height = 0u64;
         let mut this_height;
-        let mut prev_mempool: Mempool = HashMap::new();
-        let mut this_mempool: Mempool;
+        let mut prev_hash: Option<BlockHash> = None;
+        let mut this_hash;
+        // only one copy of the mempool is held, updated in place from txid diffs
+        let mut mempool: Mempool = HashMap::new();
+        let mut effective_fee_rates: HashMap<Txid, f64> = HashMap::new();
+        let mut queue_percentiles: HashMap<Txid, f64> = HashMap::new();
+        let mut spends = Spends::default();
+        let mut prev_sequence: Option<u64> = None;
         let mut prev_timestamp = 0i64;
+        let mut prev_source: Option<String> = None;
+        let mut block_seen_at: Option<i64> = None;
+        let mut chain: BTreeMap<u64, ChainBlock> = BTreeMap::new();
+        let mut filtered = Filtered::new(filter);
+        let mut overload = Overload::new(degrade_after);
+        let mut notifier = Notifier::from_env();
 
         loop {
-            // execute once per quarter-minute (:00/:15/:30/:45), preventing double execution
+            // a node call hanging stops this, and systemd restarts the recorder
+            notifier.watchdog();
+
+            // block notifications trigger a snapshot right away instead of waiting for the cadence
+            if let Some(receiver) = events.as_mut() {
+                while let Ok(event) = receiver.try_recv() {
+                    snapshot_due |= event.kind.is_block();
+                    pending_events.push(event);
+                }
+            }
+
+            // execute once per cadence, preventing double execution
             let now = chrono::Utc::now();
-            if now.second() % 15 != 0 || prev_timestamp == now.timestamp() {
-                tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+            let on_cadence = cadence.is_due(now.timestamp(), prev_timestamp);
+            let on_burst = cadence.is_burst_due(now.timestamp(), prev_timestamp, block_seen_at);
+            if !(on_cadence || on_burst || snapshot_due || catch_up)
+                || prev_timestamp == now.timestamp()
+            {
+                let stop = tokio::select! {
+                    _ = tokio::time::sleep(tokio::time::Duration::from_millis(50)) => false,
+                    _ = tokio::signal::ctrl_c() => true,
+                    _ = terminate.recv() => true,
+                    Some(settings) = Self::reloaded(&mut reloads) => {
+                        // a node with another source starts over with a full snapshot
+                        (node, cadence, retention) =
+                            (settings.node, settings.cadence, settings.retention);
+                        info!(
+                            "reloaded: interval_secs: {}, source: {}",
+                            cadence.interval_secs,
+                            node.source().as_deref().unwrap_or("unknown")
+                        );
+                        false
+                    }
+                };
+                if stop {
+                    notifier.stopping();
+                    writer.finish().await;
+                    if prev_timestamp != 0 {
+                        Self::shutdown(
+                            storage.as_ref(),
+                            prev_height,
+                            &prev_hash,
+                            &mempool,
+                            &effective_fee_rates,
+                            &queue_percentiles,
+                            &pending_events,
+                            aggregate,
+                            &filtered.skipped,
+                            sequence + 1,
+                        )?;
+                    }
+                    return Ok(());
+                }
                 continue;
             }
+            let trigger = if on_cadence || catch_up {
+                Trigger::Cadence
+            } else if snapshot_due {
+                Trigger::Block
+            } else {
+                Trigger::Burst
+            };
+            snapshot_due = false;
+            catch_up = false;
+            let mut skipped_ticks = 0;
+            if on_cadence && prev_timestamp != 0 {
+                let elapsed = (now.timestamp() - prev_timestamp) as u64;
+                skipped_ticks = (elapsed / cadence.interval_secs as u64).saturating_sub(1);
+                if skipped_ticks > 0 {
+                    warn!("snapshot ran late, skipped {skipped_ticks} tick(s)");
+                    metrics.ticks_skipped(skipped_ticks);
+                }
+            }
+            // histograms only while overloaded
+            let histogram = aggregate || overload.degraded;
+            let tick_start = Instant::now();
+            sequence += 1;
+            // a lost file breaks the chain of deltas, start over with a full snapshot
+            if writer.take_failed() {
+                prev_hash = None;
+            }
+
+            // check height and tip, a different hash at the same height means a reorg
+            let chain_info = node.get_chain_info().await?;
+            metrics.node_answered(Utc::now().timestamp());
+            this_height = chain_info.blocks;
+            this_hash = chain_info.best_block_hash;
+            // another node has its own mempool and sequence, start over with a full snapshot
+            let source = node.source();
+            if prev_source.is_some() && source != prev_source {
+                warn!(
+                    "recording from {} instead of {}",
+                    source.as_deref().unwrap_or("unknown"),
+                    prev_source.as_deref().unwrap_or("unknown")
+                );
+                prev_hash = None;
+                prev_sequence = None;
+            }
 
-            // check height
-            this_height = rest_client.get_chain_info().await?.blocks;
-            let is_new_height = prev_height != this_height;
+            // blocks confirmations were written for must still be on the node's chain
+            let mut label_from = prev_height + 1;
+            let mut disconnected: Mempool = HashMap::new();
+            if let Some(fork_height) =
+                Self::find_fork(node.as_ref(), &chain, this_height, &this_hash).await?
+            {
+                let blocks = chain.split_off(&(fork_height + 1));
+                warn!(
+                    "reorg: {} block(s) above height {fork_height} disconnected, new tip {this_hash}",
+                    blocks.len()
+                );
+                let reorg = Self::create_reorg(&blocks, fork_height, this_height, &this_hash, now);
+                Self::write(&writer, now, sequence, this_height, FileKind::Reorg, reorg).await;
+                disconnected = blocks
+                    .into_values()
+                    .flat_map(|block| block.confirmed)
+                    .collect();
+                label_from = fork_height + 1;
+                prev_hash = None;
+            }
+
+            let is_new_height = Self::is_new_tip(prev_height, prev_hash, this_height, this_hash);
+            if is_new_height && prev_height != 0 {
+                block_seen_at = Some(now.timestamp());
+            }
             if is_new_height {
-                prev_mempool = HashMap::new();
-                info!("new_height: {:?}", this_height);
+                // label what we saw in the mempool with the block(s) that confirmed it
+                if prev_height != 0 {
+                    // the blocks the mempool before them was projected into
+                    let template =
+                        Template::build(&mempool, (this_height + 1 - label_from) as usize);
+                    for height in label_from..=this_height {
+                        let hash = node.get_block_hash(height).await?;
+                        let block = node.get_block(&hash).await?;
+                        let mut confirmed = Self::confirmed(&mempool, &disconnected, &block);
+                        confirmed.retain(|txid, _| !filtered.skipped.contains(txid));
+                        let header = Self::create_block_header(
+                            &block,
+                            height,
+                            network,
+                            confirmed.len(),
+                            now,
+                        );
+                        Self::write(
+                            &writer,
+                            now,
+                            sequence,
+                            height,
+                            FileKind::BlockHeader,
+                            header,
+                        )
+                        .await;
+                        let confirmations = Self::create_confirmations(
+                            &confirmed,
+                            &block,
+                            height,
+                            template.fee_rate_for_block((height + 1 - label_from) as usize),
+                            &effective_fee_rates,
+                            now,
+                        );
+                        info!(
+                            "block: {height}, confirmed_seen: {}",
+                            confirmations.height()
+                        );
+                        if !aggregate {
+                            Self::write(
+                                &writer,
+                                now,
+                                sequence,
+                                height,
+                                FileKind::Block,
+                                confirmations,
+                            )
+                            .await;
+                        }
+                        chain.insert(height, ChainBlock { hash, confirmed });
+                    }
+                }
+                chain.entry(this_height).or_insert_with(|| ChainBlock {
+                    hash: this_hash,
+                    confirmed: HashMap::new(),
+                });
+                while chain.len() > REORG_DEPTH {
+                    chain.pop_first();
+                }
+                info!("new_height: {:?}, block_hash: {}", this_height, this_hash);
             }
 
+            // an unchanged mempool sequence means there is nothing to diff
             let start = Instant::now();
-            this_mempool = rest_client.get_mempool().await?;
+            let txids = node.get_mempool_txids_and_sequence().await?;
+            let (keys_removed, keys_added) = if prev_sequence == Some(txids.mempool_sequence) {
+                (Vec::new(), Vec::new())
+            } else {
+                Self::delta_of_keys(&mempool, &txids.txids)
+            };
+            let entries =
+                if keys_added.len() as f64 > FULL_FETCH_FRACTION * txids.txids.len() as f64 {
+                    let wanted: HashSet<&Txid> = keys_added.iter().collect();
+                    let mut entries = node.get_mempool().await?;
+                    entries.retain(|txid, _| wanted.contains(txid));
+                    entries
+                } else {
+                    node.get_mempool_entries(&keys_added).await?
+                };
+            let added = MempoolEntry::mempool(entries);
             let duration = start.elapsed();
 
-            let delta = Self::create_delta_from_pools(&prev_mempool, &this_mempool);
+            // replaced transactions are gone by now, their inputs were looked up on arrival
+            let transactions = if added.len() <= MAX_INPUT_LOOKUPS {
+                let txids: Vec<Txid> = added.keys().copied().collect();
+                node.get_transactions(&txids).await?
+            } else {
+                HashMap::new()
+            };
+            let replaces = spends.update(&keys_removed, &transactions);
+
+            let mempool_info = node.get_mempool_info().await?;
+            let context = Self::removal_context(
+                node.as_ref(),
+                &mempool_info,
+                replaces.values().copied().collect(),
+                &keys_removed,
+                this_height,
+                now.timestamp() as u64,
+            )
+            .await?;
+            let removed: Vec<(Txid, MempoolEntry)> = keys_removed
+                .iter()
+                .filter_map(|txid| mempool.remove_entry(txid))
+                .collect();
+            // at a new height the block's own transactions aren't known to be mined, skip it
+            if !is_new_height {
+                let (purged, purged_vsize) = removed
+                    .iter()
+                    .filter(|(txid, entry)| context.classify(txid, entry) == RemovalReason::Purged)
+                    .fold((0, 0), |(txs, vsize), (_, entry)| {
+                        (txs + 1, vsize + entry.vsize)
+                    });
+                metrics.purged(purged, purged_vsize);
+            }
+            mempool.extend(added);
+
+            // packages only change with the mempool
+            if is_new_height || !keys_added.is_empty() || !removed.is_empty() {
+                let packages = Score::packages(&mempool);
+                let mut rates = Score::fee_rates(&packages);
+                let mut percentiles = Score::queue_percentiles(&mempool, &packages);
+                // removed transactions keep the rate and place they had when last seen
+                rates.extend(
+                    removed
+                        .iter()
+                        .filter_map(|(txid, _)| Some((*txid, *effective_fee_rates.get(txid)?))),
+                );
+                percentiles.extend(
+                    removed
+                        .iter()
+                        .filter_map(|(txid, _)| Some((*txid, *queue_percentiles.get(txid)?))),
+                );
+                effective_fee_rates = rates;
+                queue_percentiles = percentiles;
+            }
+
+            // a new height starts over with the complete mempool
+            let delta = if histogram {
+                Self::create_histogram(&mempool, &effective_fee_rates)
+            } else if is_new_height {
+                filtered.reset();
+                let recorded = mempool.iter().filter(|(txid, entry)| {
+                    filtered.admit(txid, Self::fee_rate(txid, entry, &effective_fee_rates))
+                });
+                Self::create_delta(
+                    &[],
+                    recorded,
+                    &this_hash.to_string(),
+                    &context,
+                    &effective_fee_rates,
+                    &queue_percentiles,
+                    &replaces,
+                )
+            } else {
+                // only what was recorded arriving is recorded leaving
+                let removed: Vec<(Txid, MempoolEntry)> = removed
+                    .iter()
+                    .filter(|(txid, _)| filtered.remove(txid))
+                    .cloned()
+                    .collect();
+                let added = keys_added
+                    .iter()
+                    .filter_map(|txid| mempool.get_key_value(txid))
+                    .filter(|(txid, entry)| {
+                        filtered.admit(txid, Self::fee_rate(txid, entry, &effective_fee_rates))
+                    });
+                Self::create_delta(
+                    &removed,
+                    added,
+                    &this_hash.to_string(),
+                    &context,
+                    &effective_fee_rates,
+                    &queue_percentiles,
+                    &replaces,
+                )
+            };
 
             info!(
                 "delta_height: {:?}, load_mempool_duration_millis: {:?}",
@@ -51,91 +916,1121 @@ impl Record {
                 duration.as_millis()
             );
 
-            let mut filename = std::path::PathBuf::new();
-            let day = now.format("%Y/%m/%d").to_string();
-            let timestamp = now.timestamp();
-            let suffix = if is_new_height { "full" } else { "delta" };
-            filename.push("data");
-            filename.extend(day.split('/'));
-            filename.push(format!("{this_height}_{timestamp}_{suffix}.parquet"));
-            Self::save_dataframe_delta_to_parquet(delta, filename);
+            let kind = Self::snapshot_kind(histogram, is_new_height);
+            let delta_rows = delta.height();
+            Self::write(&writer, now, sequence, this_height, kind, delta).await;
+
+            // a full snapshot is a good moment, everything before it in the height is done
+            if is_new_height {
+                Self::apply_retention(storage.as_ref(), retention.as_ref());
+            }
+
+            // calls fail over mid-snapshot too, tag it with the node that answered last
+            let meta = Self::create_meta(
+                &cadence,
+                trigger,
+                node.source(),
+                &mempool_info,
+                &Self::fee_bands(&mempool, &effective_fee_rates),
+                filtered.totals(&mempool),
+                &Telemetry {
+                    load_mempool_duration_millis: duration.as_millis() as u64,
+                    mempool_entries: mempool.len() as u64,
+                    delta_rows: delta_rows as u64,
+                    skipped_ticks,
+                    degraded: overload.degraded,
+                },
+            );
+            Self::write(&writer, now, sequence, this_height, FileKind::Meta, meta).await;
+
+            if core_estimates {
+                match node.estimate_smart_fees(&TARGETS).await {
+                    Ok(estimates) => {
+                        let estimates = Self::create_core_estimates(&TARGETS, &estimates);
+                        Self::write(
+                            &writer,
+                            now,
+                            sequence,
+                            this_height,
+                            FileKind::CoreEstimates,
+                            estimates,
+                        )
+                        .await;
+                    }
+                    Err(e) => warn!("estimatesmartfee failed: {e:#}"),
+                }
+            }
+            if peer_fee_filters {
+                match node.get_peer_fee_filters().await {
+                    Ok(rates) => {
+                        let filters = Self::create_peer_fee_filters(&rates);
+                        Self::write(
+                            &writer,
+                            now,
+                            sequence,
+                            this_height,
+                            FileKind::PeerFeeFilters,
+                            filters,
+                        )
+                        .await;
+                    }
+                    Err(e) => warn!("getpeerinfo failed: {e:#}"),
+                }
+            }
+
+            // notifications name transactions too
+            if aggregate {
+                pending_events.clear();
+            }
+            if !pending_events.is_empty() {
+                let events = ZmqListener::create_events_frame(&pending_events);
+                Self::write(
+                    &writer,
+                    now,
+                    sequence,
+                    this_height,
+                    FileKind::Events,
+                    events,
+                )
+                .await;
+                // a block's rawtx may come after the snapshot its transactions left the mempool in
+                let removed: HashSet<&Txid> = removed.iter().map(|(txid, _)| txid).collect();
+                let rejected = ZmqListener::rejected(&pending_events, |hash| {
+                    hash.parse::<Txid>().is_ok_and(|txid| {
+                        mempool.contains_key(&txid)
+                            || removed.contains(&txid)
+                            || chain
+                                .values()
+                                .any(|block| block.confirmed.contains_key(&txid))
+                    })
+                });
+                if !rejected.is_empty() {
+                    info!("{} transactions never were in the mempool", rejected.len());
+                    let rejected = ZmqListener::create_rejected_frame(&rejected);
+                    Self::write(
+                        &writer,
+                        now,
+                        sequence,
+                        this_height,
+                        FileKind::Rejected,
+                        rejected,
+                    )
+                    .await;
+                }
+                pending_events.clear();
+            }
+            if let Some(congestion) = congestion.as_mut() {
+                // an empty next block takes anything the node relays
+                let cutoff = Template::build(&mempool, 1)
+                    .fee_rate_for_block(1)
+                    .unwrap_or(MIN_RELAY_FEE_RATE);
+                let vsize = mempool.values().map(|entry| entry.vsize).sum();
+                metrics.next_block_cutoff(cutoff);
+                if let Some(event) = congestion.observe(now.timestamp(), cutoff, vsize) {
+                    metrics.congestion_shift(event.shift);
+                    congestion.notify(&event);
+                }
+            }
+            metrics.observe_snapshot(
+                tick_start.elapsed(),
+                now.timestamp(),
+                mempool.len(),
+                delta_rows,
+            );
+            notifier.ready(&format!(
+                "recorded height {this_height}, {} transactions",
+                mempool.len()
+            ));
 
             prev_height = this_height;
-            prev_mempool = this_mempool;
+            prev_hash = Some(this_hash);
+            let overran = tick_start.elapsed().as_secs() >= cadence.interval_secs as u64;
+            match overload.observe(overran) {
+                Some(true) => {
+                    warn!("snapshots keep overrunning the interval, recording histograms")
+                }
+                Some(false) => {
+                    info!("snapshots are on time again, recording transactions");
+                    // deltas need a full snapshot to apply to
+                    prev_hash = None;
+                }
+                None => {}
+            }
+            metrics.degraded(overload.degraded);
+            prev_sequence = Some(txids.mempool_sequence);
             prev_timestamp = now.timestamp();
+            prev_source = node.source();
+        }
+    }
+
+    /// The next settings to switch to, never without a channel to receive them on
+    async fn reloaded(reloads: &mut Option<mpsc::Receiver<Settings>>) -> Option<Settings> {
+        match reloads {
+            Some(reloads) => reloads.recv().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Queue a file of the snapshot numbered `sequence` for the writer
+    async fn write(
+        writer: &Writer,
+        now: DateTime<Utc>,
+        sequence: u64,
+        height: u64,
+        kind: FileKind,
+        mut frame: DataFrame,
+    ) {
+        match Self::stamp(&mut frame, now, sequence) {
+            Ok(()) => writer.write(now, height, kind, frame).await,
+            Err(e) => error!("stamping {} file failed: {e:#}", kind.as_str()),
         }
     }
 
-    fn save_dataframe_delta_to_parquet(mut delta: DataFrame, filename: std::path::PathBuf) {
-        // make sure the folder exists
-        std::fs::create_dir_all(filename.parent().unwrap()).unwrap();
-        let file = std::fs::File::create(filename).unwrap();
-        ParquetWriter::new(file)
-            .with_compression(polars::prelude::ParquetCompression::Zstd(Default::default()))
-            .with_statistics(true)
-            .finish(&mut delta)
-            .map_err(PolarsError::from)
-            .unwrap();
+    /// Tag every row with the snapshot it belongs to, `snapshot_sequence` counting up across
+    /// restarts, and the millisecond it was taken at as `captured_at_ms`. File names only
+    /// have the second.
+    fn stamp(frame: &mut DataFrame, now: DateTime<Utc>, sequence: u64) -> Result<()> {
+        let rows = frame.height();
+        frame.with_column(Series::new(SEQUENCE_COLUMN, vec![sequence; rows]))?;
+        frame.with_column(Series::new(
+            "captured_at_ms",
+            vec![now.timestamp_millis(); rows],
+        ))?;
+        Ok(())
     }
 
-    fn create_delta_from_pools(prev_mempool: &Mempool, this_mempool: &Mempool) -> DataFrame {
-        let (keys_removed, keys_added) = Self::delta_of_keys(prev_mempool, this_mempool);
+    /// Sequence number of the snapshot `file` belongs to, 0 if it has none
+    fn sequence(storage: &dyn Storage, file: &SnapshotFile) -> u64 {
+        storage
+            .read(file)
+            .ok()
+            .and_then(|frame| frame.column(SEQUENCE_COLUMN).ok()?.u64().ok()?.max())
+            .unwrap_or(0)
+    }
+
+    fn apply_retention(storage: &dyn Storage, retention: Option<&Retention>) {
+        if let Some(retention) = retention {
+            if let Err(e) = retention.prune(storage, Utc::now().timestamp()) {
+                warn!("pruning old files failed: {e:#}");
+            }
+        }
+    }
+
+    /// One row describing how the snapshot was taken, with the virtual size of each of
+    /// [`FEE_BANDS`] as `vsize_1_2`, `vsize_2_5` up to `vsize_1000_up` and the transactions
+    /// and virtual size a [`Filter`] kept out of the files, then the [`Telemetry`] of the
+    /// snapshot
+    fn create_meta(
+        cadence: &Cadence,
+        trigger: Trigger,
+        source: Option<String>,
+        mempool_info: &GetMempoolInfoResult,
+        bands: &[u64; FEE_BANDS.len()],
+        (filtered_txs, filtered_vsize): (u64, u64),
+        telemetry: &Telemetry,
+    ) -> DataFrame {
+        let mut columns = vec![
+            Series::new("interval_secs", [cadence.interval_secs]),
+            Series::new("aligned", [cadence.aligned]),
+            Series::new("triggered_by_block", [trigger == Trigger::Block]),
+            Series::new("burst", [trigger == Trigger::Burst]),
+            Series::new("source", [source]),
+            // whether low fee transactions were purged rather than mined depends on these
+            Series::new(
+                "mempool_min_fee_sat_vb",
+                [Self::sat_vb(mempool_info.mempool_min_fee)],
+            ),
+            Series::new(
+                "min_relay_tx_fee_sat_vb",
+                [Self::sat_vb(mempool_info.min_relay_tx_fee)],
+            ),
+            // the sum of the virtual sizes, as Bitcoin Core reports it
+            Series::new("mempool_bytes", [mempool_info.bytes as u64]),
+            Series::new("mempool_txs", [mempool_info.size as u64]),
+            Series::new("filtered_txs", [filtered_txs]),
+            Series::new("filtered_vsize", [filtered_vsize]),
+            Series::new(
+                "load_mempool_duration_millis",
+                [telemetry.load_mempool_duration_millis],
+            ),
+            Series::new("mempool_entries", [telemetry.mempool_entries]),
+            Series::new("delta_rows", [telemetry.delta_rows]),
+            Series::new("skipped_ticks", [telemetry.skipped_ticks]),
+            Series::new("degraded", [telemetry.degraded]),
+        ];
+        for (index, vsize) in bands.iter().enumerate() {
+            columns.push(Series::new(&Self::fee_band_column(index), [*vsize]));
+        }
+        DataFrame::new(columns).unwrap()
+    }
+
+    /// `vsize_1_2` and so on up to `vsize_1000_up`, the column of a band of [`FEE_BANDS`]
+    pub(crate) fn fee_band_column(index: usize) -> String {
+        match FEE_BANDS.get(index + 1) {
+            Some(upper) => format!("vsize_{}_{upper}", FEE_BANDS[index]),
+            None => format!("vsize_{}_up", FEE_BANDS[index]),
+        }
+    }
+
+    /// Transactions and vsize by bucket of effective fee rate, like `wtf histogram`, leaving
+    /// out empty buckets
+    fn create_histogram(mempool: &Mempool, effective_fee_rates: &HashMap<Txid, f64>) -> DataFrame {
+        let transactions = mempool.iter().map(|(txid, entry)| {
+            (
+                Self::fee_rate(txid, entry, effective_fee_rates),
+                entry.vsize,
+            )
+        });
+        Histogram::new(0, 0, "node", transactions).to_frame()
+    }
+
+    /// Virtual size of the mempool by band of effective fee rate
+    fn fee_bands(
+        mempool: &Mempool,
+        effective_fee_rates: &HashMap<Txid, f64>,
+    ) -> [u64; FEE_BANDS.len()] {
+        let mut bands = [0; FEE_BANDS.len()];
+        for (txid, entry) in mempool {
+            let fee_rate = Self::fee_rate(txid, entry, effective_fee_rates);
+            let band = FEE_BANDS.partition_point(|bound| *bound <= fee_rate).max(1) - 1;
+            bands[band] += entry.vsize;
+        }
+        bands
+    }
+
+    /// Effective fee rate of a transaction, its own where the package is unknown
+    fn fee_rate(
+        txid: &Txid,
+        entry: &MempoolEntry,
+        effective_fee_rates: &HashMap<Txid, f64>,
+    ) -> f64 {
+        effective_fee_rates.get(txid).copied().unwrap_or_else(|| {
+            entry.fee.to_float_in(Denomination::Satoshi) / entry.vsize.max(1) as f64
+        })
+    }
+
+    /// One row per confirmation target, without a fee rate where Core has no estimate
+    fn create_core_estimates(targets: &[u32], estimates: &[Option<f64>]) -> DataFrame {
+        DataFrame::new(vec![
+            Series::new("target", targets),
+            Series::new("fee_rate_sat_vb", estimates),
+        ])
+        .unwrap()
+    }
+
+    /// One row per fee rate some peer advertises, lowest first, with how many peers do
+    fn create_peer_fee_filters(rates: &[f64]) -> DataFrame {
+        // sat/kvB, what's on the wire
+        let mut peers: BTreeMap<u64, u32> = BTreeMap::new();
+        for rate in rates {
+            *peers.entry((rate * 1000.).round() as u64).or_default() += 1;
+        }
+        DataFrame::new(vec![
+            Series::new(
+                "fee_rate_sat_vb",
+                peers
+                    .keys()
+                    .map(|rate| *rate as f64 / 1000.)
+                    .collect::<Vec<f64>>(),
+            ),
+            Series::new("peers", peers.values().copied().collect::<Vec<u32>>()),
+        ])
+        .unwrap()
+    }
+
+    /// The last file of the previous run
+    fn last_file(storage: &dyn Storage) -> Option<SnapshotFile> {
+        match storage.list() {
+            Ok(mut files) => files.pop(),
+            Err(e) => {
+                warn!("could not inspect previous recording: {e}");
+                None
+            }
+        }
+    }
+
+    /// Warn if the last run ended without writing its shutdown marker
+    fn check_previous_shutdown(last: Option<&SnapshotFile>) {
+        if let Some(last) = last.filter(|last| last.kind != FileKind::Shutdown) {
+            warn!(
+                "previous recording ended without a clean shutdown after {}, data may be truncated",
+                last.path.display()
+            );
+        }
+    }
 
-        let capacity = keys_removed.len() + keys_added.len();
+    /// One row about the time nothing was recorded, from the `previous` file to `now`
+    fn create_gap(previous: &SnapshotFile, cadence: &Cadence, now: DateTime<Utc>) -> DataFrame {
+        let gap_secs = now.timestamp() - previous.timestamp;
+        DataFrame::new(vec![
+            Series::new("last_height", [previous.height]),
+            Series::new("last_timestamp", [previous.timestamp]),
+            Series::new("gap_secs", [gap_secs]),
+            Series::new(
+                "missed_snapshots",
+                [(gap_secs / cadence.interval_secs as i64 - 1).max(0) as u64],
+            ),
+            Series::new("clean_stop", [previous.kind == FileKind::Shutdown]),
+        ])
+        .unwrap()
+    }
+
+    /// Clean up after a crash, removing partial writes and quarantining unreadable files
+    fn recover(storage: &dyn Storage) {
+        match storage.recover() {
+            Ok(recovered) => {
+                for path in recovered {
+                    warn!("recovered after an interrupted write: {}", path.display());
+                }
+            }
+            Err(e) => warn!("could not check for interrupted writes: {e:#}"),
+        }
+    }
+
+    /// Fee rates come as BTC/kvB
+    fn sat_vb(fee_rate: Amount) -> f64 {
+        fee_rate.to_float_in(Denomination::Satoshi) / 1000.
+    }
+
+    /// Flush everything held in memory, then mark the dataset as cleanly closed
+    #[allow(clippy::too_many_arguments)]
+    fn shutdown(
+        storage: &dyn Storage,
+        height: u64,
+        hash: &Option<BlockHash>,
+        mempool: &Mempool,
+        effective_fee_rates: &HashMap<Txid, f64>,
+        queue_percentiles: &HashMap<Txid, f64>,
+        pending_events: &[Event],
+        aggregate: bool,
+        skipped: &HashSet<Txid>,
+        sequence: u64,
+    ) -> Result<()> {
+        let now = chrono::Utc::now();
+        let block_hash = hash.map(|h| h.to_string()).unwrap_or_default();
+        info!("shutting down, writing final snapshot at height {height}");
+        let mut marker = DataFrame::new(vec![
+            Series::new("block_hash", [block_hash.clone()]),
+            Series::new("stopped_at", [now.timestamp()]),
+        ])
+        .unwrap();
+        let write = |kind: FileKind, frame: &mut DataFrame| -> Result<()> {
+            Self::stamp(frame, now, sequence)?;
+            storage.write(now, height, kind, frame)?;
+            Ok(())
+        };
+        if aggregate {
+            let mut histogram = Self::create_histogram(mempool, effective_fee_rates);
+            write(FileKind::Histogram, &mut histogram)?;
+            write(FileKind::Shutdown, &mut marker)?;
+            return Ok(());
+        }
+
+        let recorded: Mempool = mempool
+            .iter()
+            .filter(|(txid, _)| !skipped.contains(*txid))
+            .map(|(txid, entry)| (*txid, entry.clone()))
+            .collect();
+        let mut full = Self::create_full(
+            &recorded,
+            &block_hash,
+            effective_fee_rates,
+            queue_percentiles,
+        );
+        write(FileKind::Full, &mut full)?;
+
+        if !pending_events.is_empty() {
+            let mut events = ZmqListener::create_events_frame(pending_events);
+            write(FileKind::Events, &mut events)?;
+        }
+        write(FileKind::Shutdown, &mut marker)?;
+        Ok(())
+    }
+
+    /// Fetch the policy info needed to tell removals apart. A block connected while the
+    /// mempool was fetched shows up as removals at the old height, so if the tip moved in the
+    /// meantime its transactions are collected as well.
+    async fn removal_context(
+        node: &dyn Node,
+        mempool_info: &GetMempoolInfoResult,
+        replaced: HashSet<Txid>,
+        keys_removed: &[Txid],
+        height: u64,
+        now: u64,
+    ) -> Result<RemovalContext> {
+        let mut mined = HashSet::new();
+        if !keys_removed.is_empty() {
+            let tip = node.get_chain_info().await?.blocks;
+            for height in height + 1..=tip {
+                let hash = node.get_block_hash(height).await?;
+                let block = node.get_block(&hash).await?;
+                mined.extend(block.txdata.iter().map(|tx| tx.txid()));
+            }
+        }
+
+        Ok(RemovalContext {
+            mined,
+            replaced,
+            // BTC/kvB to sat/vB
+            mempool_min_fee_sat_vb: Self::sat_vb(mempool_info.mempool_min_fee),
+            min_relay_fee_sat_vb: Self::sat_vb(mempool_info.min_relay_tx_fee),
+            full_rbf: mempool_info.full_rbf,
+            now,
+        })
+    }
+
+    /// The rows of a full snapshot of `mempool`, without a delta nothing was removed or replaced
+    pub(crate) fn create_full(
+        mempool: &Mempool,
+        block_hash: &str,
+        effective_fee_rates: &HashMap<Txid, f64>,
+        queue_percentiles: &HashMap<Txid, f64>,
+    ) -> DataFrame {
+        let context = RemovalContext {
+            mined: HashSet::new(),
+            replaced: HashSet::new(),
+            mempool_min_fee_sat_vb: 0.,
+            min_relay_fee_sat_vb: 0.,
+            full_rbf: false,
+            now: Utc::now().timestamp() as u64,
+        };
+        Self::create_delta(
+            &[],
+            mempool.iter(),
+            block_hash,
+            &context,
+            effective_fee_rates,
+            queue_percentiles,
+            &HashMap::new(),
+        )
+    }
+
+    #[tracing::instrument(level = "trace", skip_all)]
+    fn create_delta<'a>(
+        removed: &[(Txid, MempoolEntry)],
+        added: impl Iterator<Item = (&'a Txid, &'a MempoolEntry)>,
+        block_hash: &str,
+        context: &RemovalContext,
+        effective_fee_rates: &HashMap<Txid, f64>,
+        queue_percentiles: &HashMap<Txid, f64>,
+        replaces: &HashMap<Txid, Txid>,
+    ) -> DataFrame {
+        let capacity = removed.len() + added.size_hint().0;
 
         let mut txid_values: Vec<String> = Vec::with_capacity(capacity);
+        let mut wtxid_values: Vec<String> = Vec::with_capacity(capacity);
         let mut weight_values: Vec<f64> = Vec::with_capacity(capacity);
         let mut fee_sat_values: Vec<f64> = Vec::with_capacity(capacity);
         let mut first_seen_timestamp_values: Vec<u64> = Vec::with_capacity(capacity);
+        let mut removal_reason_values: Vec<Option<&str>> = Vec::with_capacity(capacity);
+        let mut fee_rate_sat_vb_values: Vec<f64> = Vec::with_capacity(capacity);
+        let mut fee_rate_sat_wu_values: Vec<f64> = Vec::with_capacity(capacity);
+        let mut effective_fee_rate_values: Vec<Option<f64>> = Vec::with_capacity(capacity);
+        let mut queue_percentile_values: Vec<Option<f64>> = Vec::with_capacity(capacity);
+        let mut replaces_txid_values: Vec<Option<String>> = Vec::with_capacity(capacity);
+        let mut vsize_values: Vec<u64> = Vec::with_capacity(capacity);
+        let mut ancestor_count_values: Vec<u64> = Vec::with_capacity(capacity);
+        let mut descendant_count_values: Vec<u64> = Vec::with_capacity(capacity);
+        let mut ancestor_fees_values: Vec<f64> = Vec::with_capacity(capacity);
+        let mut descendant_fees_values: Vec<f64> = Vec::with_capacity(capacity);
+        let mut bip125_replaceable_values: Vec<bool> = Vec::with_capacity(capacity);
+        let mut unbroadcast_values: Vec<Option<bool>> = Vec::with_capacity(capacity);
 
-        for txid in keys_removed.iter() {
-            let entry = prev_mempool.get(txid).unwrap();
-            let weight: f64 = entry.weight.unwrap() as f64 * (-1.);
+        // removed transactions are described as they were last seen
+        let removed = removed
+            .iter()
+            .map(|(txid, entry)| (txid, entry, Some(context.classify(txid, entry).as_str())));
+        let added = added.map(|(txid, entry)| (txid, entry, None));
+        for (txid, entry, removal_reason) in removed.chain(added) {
+            let weight = entry.weight as f64;
+            let fee_sat = entry.fee.to_float_in(Denomination::Satoshi);
 
             txid_values.push(txid.to_string());
-            weight_values.push(weight);
-            fee_sat_values.push(entry.fees.base.to_float_in(Denomination::Satoshi));
+            wtxid_values.push(entry.wtxid.to_string());
+            weight_values.push(if removal_reason.is_some() {
+                -weight
+            } else {
+                weight
+            });
+            fee_sat_values.push(fee_sat);
             first_seen_timestamp_values.push(entry.time);
+            removal_reason_values.push(removal_reason);
+            // from the unsigned weight, removals keep the fee rate they had
+            fee_rate_sat_vb_values.push(fee_sat / (weight / 4.));
+            fee_rate_sat_wu_values.push(fee_sat / weight);
+            effective_fee_rate_values.push(effective_fee_rates.get(txid).copied());
+            queue_percentile_values.push(queue_percentiles.get(txid).copied());
+            replaces_txid_values.push(replaces.get(txid).map(Txid::to_string));
+            vsize_values.push(entry.vsize);
+            ancestor_count_values.push(entry.ancestor_count.into());
+            descendant_count_values.push(entry.descendant_count.into());
+            // in sat like fee_sat, both include the fee of the transaction itself
+            ancestor_fees_values.push(entry.ancestor_fees.to_float_in(Denomination::Satoshi));
+            descendant_fees_values.push(entry.descendant_fees.to_float_in(Denomination::Satoshi));
+            bip125_replaceable_values.push(entry.bip125_replaceable);
+            unbroadcast_values.push(entry.unbroadcast);
         }
 
-        for txid in keys_added.iter() {
-            let entry = this_mempool.get(txid).unwrap();
-            let weight = entry.weight.unwrap() as f64;
+        let rows = txid_values.len();
+        DataFrame::new(vec![
+            Series::new("txid", &txid_values),
+            Series::new("wtxid", &wtxid_values),
+            Series::new("weight", &weight_values),
+            Series::new("fee_sat", &fee_sat_values),
+            Series::new("first_seen_at", first_seen_timestamp_values),
+            Series::new("block_hash", vec![block_hash; rows]),
+            Series::new("removal_reason", removal_reason_values),
+            Series::new("fee_rate_sat_vb", fee_rate_sat_vb_values),
+            Series::new("fee_rate_sat_wu", fee_rate_sat_wu_values),
+            // sat/vB of the package it would be mined with, see Score
+            Series::new("effective_fee_rate", effective_fee_rate_values),
+            // % of the mempool's vsize mined before it, 0 at the front, see Score
+            Series::new("queue_percentile", queue_percentile_values),
+            // a removed transaction with an input in common, if there was one
+            Series::new("replaces_txid", replaces_txid_values),
+            Series::new("vsize", vsize_values),
+            Series::new("ancestor_count", ancestor_count_values),
+            Series::new("descendant_count", descendant_count_values),
+            Series::new("ancestor_fees", ancestor_fees_values),
+            Series::new("descendant_fees", descendant_fees_values),
+            Series::new("bip125_replaceable", bip125_replaceable_values),
+            Series::new("unbroadcast", unbroadcast_values),
+        ])
+        .unwrap()
+    }
 
+    /// Height of the last block the node's chain has in common with `chain`, if blocks recorded
+    /// above it were disconnected. Without a common block all of `chain` was disconnected.
+    async fn find_fork(
+        node: &dyn Node,
+        chain: &BTreeMap<u64, ChainBlock>,
+        tip_height: u64,
+        tip_hash: &BlockHash,
+    ) -> Result<Option<u64>> {
+        let (Some((&first_height, _)), Some((&last_height, last))) =
+            (chain.first_key_value(), chain.last_key_value())
+        else {
+            return Ok(None);
+        };
+        if last_height == tip_height && last.hash == *tip_hash {
+            return Ok(None);
+        }
+        for (&height, block) in chain.range(..=tip_height).rev() {
+            let hash = if height == tip_height {
+                *tip_hash
+            } else {
+                node.get_block_hash(height).await?
+            };
+            if hash == block.hash {
+                return Ok((height < last_height).then_some(height));
+            }
+        }
+        warn!("reorg deeper than the {REORG_DEPTH} blocks kept, older labels may be stale");
+        Ok(Some(first_height.saturating_sub(1)))
+    }
+
+    /// One row per block disconnected by a reorg
+    fn create_reorg(
+        blocks: &BTreeMap<u64, ChainBlock>,
+        fork_height: u64,
+        tip_height: u64,
+        tip_hash: &BlockHash,
+        now: DateTime<Utc>,
+    ) -> DataFrame {
+        let count = blocks.len();
+        DataFrame::new(vec![
+            Series::new(
+                "disconnected_height",
+                blocks.keys().copied().collect::<Vec<u64>>(),
+            ),
+            Series::new(
+                "disconnected_hash",
+                blocks
+                    .values()
+                    .map(|block| block.hash.to_string())
+                    .collect::<Vec<String>>(),
+            ),
+            // confirmations recorded for the block, labelled again from the new chain
+            Series::new(
+                "confirmed_seen",
+                blocks
+                    .values()
+                    .map(|block| block.confirmed.len() as u64)
+                    .collect::<Vec<u64>>(),
+            ),
+            Series::new("fork_height", vec![fork_height; count]),
+            Series::new("new_tip_height", vec![tip_height; count]),
+            Series::new("new_tip_hash", vec![tip_hash.to_string(); count]),
+            Series::new("detected_at", vec![now.timestamp(); count]),
+        ])
+        .unwrap()
+    }
+
+    /// Recorded entries of the transactions of `block`, from the mempool or from blocks
+    /// disconnected since
+    fn confirmed(mempool: &Mempool, disconnected: &Mempool, block: &Block) -> Mempool {
+        block
+            .txdata
+            .iter()
+            .filter_map(|tx| {
+                let txid = tx.txid();
+                let entry = mempool.get(&txid).or_else(|| disconnected.get(&txid))?;
+                Some((txid, entry.clone()))
+            })
+            .collect()
+    }
+
+    /// What the miner put into `block`: its header time, weight, the fees its coinbase claims
+    /// beyond the subsidy, the readable part of the coinbase script and how many of its
+    /// transactions were seen in the mempool first
+    fn create_block_header(
+        block: &Block,
+        height: u64,
+        network: Network,
+        txs_seen: usize,
+        now: DateTime<Utc>,
+    ) -> DataFrame {
+        let halvings = height / Self::halving_interval(network);
+        let subsidy_sat = (50 * 100_000_000u64)
+            .checked_shr(halvings as u32)
+            .unwrap_or(0);
+        let (claimed_sat, script) = match block.txdata.first() {
+            Some(coinbase) => (
+                coinbase.output.iter().map(|output| output.value).sum(),
+                coinbase
+                    .input
+                    .first()
+                    .map(|input| input.script_sig.as_bytes().to_vec())
+                    .unwrap_or_default(),
+            ),
+            None => (0, Vec::new()),
+        };
+        DataFrame::new(vec![
+            Series::new("height", [height]),
+            Series::new("block_hash", [block.block_hash().to_string()]),
+            Series::new("block_time", [block.header.time as i64]),
+            Series::new("seen_at", [now.timestamp()]),
+            Series::new("tx_count", [block.txdata.len() as u64]),
+            Series::new("weight", [block.weight().to_wu()]),
+            Series::new("total_fees_sat", [claimed_sat.saturating_sub(subsidy_sat)]),
+            Series::new("subsidy_sat", [subsidy_sat]),
+            Series::new("coinbase_tag", [Self::coinbase_tag(&script)]),
+            Series::new(
+                "coinbase_script",
+                [script
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect::<String>()],
+            ),
+            Series::new("txs_seen", [txs_seen as u64]),
+            // nothing but the coinbase
+            Series::new("empty", [block.txdata.len() <= 1]),
+        ])
+        .unwrap()
+    }
+
+    /// Blocks between halvings of the subsidy
+    fn halving_interval(network: Network) -> u64 {
+        match network {
+            Network::Regtest => 150,
+            _ => 210_000,
+        }
+    }
+
+    /// The printable ASCII of a coinbase script after the height BIP 34 pushes first, where
+    /// pools put their name
+    fn coinbase_tag(script: &[u8]) -> String {
+        let rest = match script.first() {
+            Some(len @ 1..=75) => script.get(1 + *len as usize..).unwrap_or_default(),
+            _ => script,
+        };
+        rest.iter()
+            .filter(|byte| (0x20..0x7f).contains(*byte))
+            .map(|byte| *byte as char)
+            .collect::<String>()
+            .trim()
+            .to_string()
+    }
+
+    /// One row per transaction of `block` that was in the recorded mempool, flagged
+    /// `out_of_band` when it paid well below `cutoff`, the fee rate its block was projected to
+    /// take
+    fn create_confirmations(
+        mempool: &Mempool,
+        block: &Block,
+        height: u64,
+        cutoff: Option<f64>,
+        effective_fee_rates: &HashMap<Txid, f64>,
+        now: DateTime<Utc>,
+    ) -> DataFrame {
+        let mut txid_values: Vec<String> = Vec::new();
+        let mut weight_values: Vec<f64> = Vec::new();
+        let mut fee_sat_values: Vec<f64> = Vec::new();
+        let mut first_seen_timestamp_values: Vec<u64> = Vec::new();
+        let mut wait_blocks_values: Vec<u64> = Vec::new();
+        let mut wait_secs_values: Vec<i64> = Vec::new();
+        let mut fee_rate_sat_vb_values: Vec<f64> = Vec::new();
+        let mut fee_rate_sat_wu_values: Vec<f64> = Vec::new();
+        let mut out_of_band_values: Vec<bool> = Vec::new();
+
+        let confirmed_at = now.timestamp();
+        for tx in block.txdata.iter() {
+            let txid = tx.txid();
+            let Some(entry) = mempool.get(&txid) else {
+                continue;
+            };
+            let weight = entry.weight as f64;
+            let fee_sat = entry.fee.to_float_in(Denomination::Satoshi);
             txid_values.push(txid.to_string());
             weight_values.push(weight);
-            fee_sat_values.push(entry.fees.base.to_float_in(Denomination::Satoshi));
+            fee_sat_values.push(fee_sat);
             first_seen_timestamp_values.push(entry.time);
+            fee_rate_sat_vb_values.push(fee_sat / (weight / 4.));
+            fee_rate_sat_wu_values.push(fee_sat / weight);
+            // entry.height is the tip when the tx entered the mempool
+            wait_blocks_values.push(height.saturating_sub(entry.height));
+            wait_secs_values.push(confirmed_at - entry.time as i64);
+            let fee_rate = Self::fee_rate(&txid, entry, effective_fee_rates);
+            out_of_band_values
+                .push(cutoff.is_some_and(|cutoff| fee_rate < cutoff * OUT_OF_BAND_FRACTION));
         }
 
+        let count = txid_values.len();
         DataFrame::new(vec![
             Series::new("txid", &txid_values),
             Series::new("weight", &weight_values),
             Series::new("fee_sat", &fee_sat_values),
             Series::new("first_seen_at", first_seen_timestamp_values),
+            Series::new("confirmed_height", vec![height; count]),
+            // tells labels from a block later disconnected apart, see the reorg files
+            Series::new("block_hash", vec![block.block_hash().to_string(); count]),
+            Series::new("confirmed_at", vec![confirmed_at; count]),
+            Series::new("wait_blocks", wait_blocks_values),
+            Series::new("wait_secs", wait_secs_values),
+            Series::new("fee_rate_sat_vb", fee_rate_sat_vb_values),
+            Series::new("fee_rate_sat_wu", fee_rate_sat_wu_values),
+            Series::new("out_of_band", out_of_band_values),
         ])
         .unwrap()
     }
 
-    fn delta_of_keys(map1: &Mempool, map2: &Mempool) -> (Vec<Txid>, Vec<Txid>) {
-        let mut intersection = HashSet::new();
-        let mut keys_added = Vec::new();
-        let mut keys_removed = Vec::new();
+    /// Keys of `mempool` missing from `txids`, and `txids` not yet in `mempool`
+    fn delta_of_keys(mempool: &Mempool, txids: &[Txid]) -> (Vec<Txid>, Vec<Txid>) {
+        let current: HashSet<&Txid> = txids.iter().collect();
+        let keys_removed = mempool
+            .keys()
+            .filter(|txid| !current.contains(txid))
+            .copied()
+            .collect();
+        let keys_added = txids
+            .iter()
+            .filter(|txid| !mempool.contains_key(*txid))
+            .copied()
+            .collect();
 
-        for key in map1.keys() {
-            if map2.contains_key(key) {
-                intersection.insert(key);
-            } else {
-                keys_removed.push(*key);
-            }
+        (keys_removed, keys_added)
+    }
+
+    /// Whether the tip is another than the one of the snapshot before, at another height or
+    /// at the same one after a reorg, or unknown
+    fn is_new_tip(
+        prev_height: u64,
+        prev_hash: Option<BlockHash>,
+        height: u64,
+        hash: BlockHash,
+    ) -> bool {
+        prev_height != height || prev_hash != Some(hash)
+    }
+
+    /// The file a snapshot is written as, a new tip starts over with the complete mempool
+    fn snapshot_kind(histogram: bool, is_new_tip: bool) -> FileKind {
+        if histogram {
+            FileKind::Histogram
+        } else if is_new_tip {
+            FileKind::Full
+        } else {
+            FileKind::Delta
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoincore_rest::{responses::GetBlockchainInfoResult, GetMempoolTxidsAndSequenceResult};
 
-        for key in map2.keys() {
-            if !intersection.contains(key) {
-                keys_added.push(*key);
+    /// A transaction without relatives, entering at height 800,000
+    fn entry(vsize: u64, fee_sat: u64) -> MempoolEntry {
+        let fee = Amount::from_sat(fee_sat);
+        MempoolEntry {
+            wtxid: Wtxid::all_zeros(),
+            vsize,
+            weight: vsize * 4,
+            time: 1_700_000_000,
+            height: 800_000,
+            fee,
+            modified_fee: fee,
+            ancestor_fees: fee,
+            descendant_fees: fee,
+            ancestor_count: 1,
+            descendant_count: 1,
+            descendant_size: vsize,
+            bip125_replaceable: false,
+            unbroadcast: None,
+            depends: Box::new([]),
+            spent_by: Box::new([]),
+        }
+    }
+
+    fn txid(name: &str) -> Txid {
+        Txid::hash(name.as_bytes())
+    }
+
+    /// The node's chain by height, it answers nothing else
+    struct Chain(BTreeMap<u64, BlockHash>);
+
+    fn unanswered<T>() -> Result<T> {
+        bail!("the test chain only answers block hashes")
+    }
+
+    #[async_trait::async_trait]
+    impl Node for Chain {
+        async fn get_chain_info(&self) -> Result<GetBlockchainInfoResult> {
+            unanswered()
+        }
+        async fn get_mempool(&self) -> Result<HashMap<Txid, GetMempoolEntryResult>> {
+            unanswered()
+        }
+        async fn get_mempool_info(&self) -> Result<GetMempoolInfoResult> {
+            unanswered()
+        }
+        async fn get_mempool_txids_and_sequence(&self) -> Result<GetMempoolTxidsAndSequenceResult> {
+            unanswered()
+        }
+        async fn get_mempool_entries(
+            &self,
+            _txids: &[Txid],
+        ) -> Result<HashMap<Txid, GetMempoolEntryResult>> {
+            unanswered()
+        }
+        async fn get_transactions(
+            &self,
+            _txids: &[Txid],
+        ) -> Result<HashMap<Txid, bitcoin::Transaction>> {
+            unanswered()
+        }
+        async fn get_block_hash(&self, height: u64) -> Result<BlockHash> {
+            match self.0.get(&height) {
+                Some(hash) => Ok(*hash),
+                None => bail!("no block at height {height}"),
             }
         }
+        async fn get_block(&self, _hash: &BlockHash) -> Result<Block> {
+            unanswered()
+        }
+    }
 
-        (keys_removed, keys_added)
+    fn hash(name: &str) -> BlockHash {
+        BlockHash::hash(name.as_bytes())
+    }
+
+    /// Where `find_fork` finds the chain kept to fork from the node's `chain`, at its tip
+    async fn fork(kept: &[(u64, &str)], chain: &[(u64, &str)]) -> Option<u64> {
+        let kept: BTreeMap<u64, ChainBlock> = kept
+            .iter()
+            .map(|(height, name)| {
+                let block = ChainBlock {
+                    hash: hash(name),
+                    confirmed: HashMap::new(),
+                };
+                (*height, block)
+            })
+            .collect();
+        let node = Chain(
+            chain
+                .iter()
+                .map(|(height, name)| (*height, hash(name)))
+                .collect(),
+        );
+        let (&height, &tip) = node.0.last_key_value().unwrap();
+        Record::find_fork(&node, &kept, height, &tip).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn fork_is_the_highest_block_kept_still_on_the_chain() {
+        let kept = [(10, "a10"), (11, "a11"), (12, "a12")];
+        assert_eq!(fork(&kept, &kept).await, None);
+        assert_eq!(fork(&kept, &[(12, "a12"), (13, "a13")]).await, None);
+        assert_eq!(fork(&[], &[(12, "a12")]).await, None);
+        // the tip replaced at the same height
+        let forked = [(10, "a10"), (11, "a11"), (12, "b12")];
+        assert_eq!(fork(&kept, &forked).await, Some(11));
+        let forked = [(10, "a10"), (11, "b11"), (12, "b12"), (13, "b13")];
+        assert_eq!(fork(&kept, &forked).await, Some(10));
+        // nothing kept is on the chain anymore
+        let deeper = [(10, "b10"), (11, "b11"), (12, "b12")];
+        assert_eq!(fork(&kept, &deeper).await, Some(9));
+    }
+
+    #[test]
+    fn coinbase_tag_skips_the_height_and_keeps_printable_text() {
+        // BIP 34 height 800,000 pushed as 3 bytes, then the pool's tag and an extranonce
+        let mut script = vec![0x03, 0x00, 0x35, 0x0c];
+        script.extend(b"  /Foundry USA Pool #dropgold/");
+        script.extend([0x00, 0xfa, 0xbe, 0x6d, 0x6d]);
+        assert_eq!(
+            Record::coinbase_tag(&script),
+            "/Foundry USA Pool #dropgold/mm"
+        );
+        assert_eq!(Record::coinbase_tag(&[0x05, 0x01]), "");
+        assert_eq!(Record::coinbase_tag(&[]), "");
+        // no push of the height first
+        assert_eq!(Record::coinbase_tag(b"\x4c/tag/"), "L/tag/");
+    }
+
+    #[test]
+    fn cadence_counts_from_the_previous_snapshot_or_aligns() {
+        let every_15 = Cadence::new(15, false).unwrap();
+        assert!(!every_15.is_due(1_014, 1_000));
+        assert!(every_15.is_due(1_015, 1_000));
+        assert!(every_15.is_due(1_100, 1_000));
+        let aligned = Cadence::new(15, true).unwrap();
+        assert!(aligned.is_due(1_005, 1_004));
+        assert!(!aligned.is_due(1_014, 1_005));
+        assert!(Cadence::new(0, false).is_err());
+        assert!(Cadence::new(MAX_INTERVAL_SECS + 1, false).is_err());
+    }
+
+    #[test]
+    fn burst_follows_a_new_block_for_a_minute() {
+        let cadence = Cadence::new(60, false).unwrap();
+        assert!(cadence.with_burst(0).is_err());
+        assert!(cadence.with_burst(60).is_err());
+        assert!(!cadence.is_burst_due(1_010, 1_000, Some(1_000)));
+        assert_eq!(cadence.shortest_interval_secs(), 60);
+
+        let burst = cadence.with_burst(5).unwrap();
+        assert_eq!(burst.shortest_interval_secs(), 5);
+        assert!(!burst.is_burst_due(1_004, 1_000, Some(1_000)));
+        assert!(burst.is_burst_due(1_005, 1_000, Some(1_000)));
+        assert!(!burst.is_burst_due(1_065, 1_060, Some(1_000)));
+        assert!(!burst.is_burst_due(1_005, 1_000, None));
+    }
+
+    #[test]
+    fn overload_degrades_and_recovers_after_as_many_in_a_row() {
+        let mut overload = Overload::new(Some(2));
+        assert_eq!(overload.observe(true), None);
+        // on time again before the second overrun
+        assert_eq!(overload.observe(false), None);
+        assert_eq!(overload.observe(true), None);
+        assert_eq!(overload.observe(true), Some(true));
+        assert!(overload.degraded);
+        assert_eq!(overload.observe(true), None);
+        assert_eq!(overload.observe(false), None);
+        assert_eq!(overload.observe(false), Some(false));
+        assert!(!overload.degraded);
+
+        let mut never = Overload::new(Some(0));
+        assert!((0..10).all(|_| never.observe(true).is_none()));
+    }
+
+    #[test]
+    fn filter_leaves_out_the_low_fee_tail_and_full_buckets() {
+        let mut filtered = Filtered::new(Filter {
+            min_fee_rate: Some(2.),
+            max_entries_per_bucket: Some(1),
+        });
+        assert!(!filtered.admit(&txid("low"), 1.5));
+        // 10 and 11 sat/vB share the bucket from 10, 12 starts the next
+        assert!(filtered.admit(&txid("a"), 10.));
+        assert!(!filtered.admit(&txid("b"), 11.));
+        assert!(filtered.admit(&txid("c"), 12.));
+
+        let mempool: Mempool = [("low", 100), ("b", 250), ("a", 400)]
+            .into_iter()
+            .map(|(name, vsize)| (txid(name), entry(vsize, vsize * 10)))
+            .collect();
+        assert_eq!(filtered.totals(&mempool), (2, 350));
+
+        // a removal is only recorded if the arrival was, and frees its place in the bucket
+        assert!(!filtered.remove(&txid("b")));
+        assert!(filtered.remove(&txid("a")));
+        assert!(filtered.admit(&txid("d"), 11.));
+        filtered.reset();
+        assert!(filtered.admit(&txid("e"), 10.));
+        assert!(!Filter::default().is_active());
+    }
+
+    #[test]
+    fn removals_are_classified_in_order_of_certainty() {
+        let context = RemovalContext {
+            mined: HashSet::from([txid("mined")]),
+            replaced: HashSet::from([txid("replaced")]),
+            mempool_min_fee_sat_vb: 1.,
+            min_relay_fee_sat_vb: 1.,
+            full_rbf: false,
+            now: 1_700_000_000 + 60,
+        };
+        let plain = entry(200, 2_000);
+        let classify = |context: &RemovalContext, name, entry| context.classify(&txid(name), entry);
+        assert_eq!(classify(&context, "mined", &plain), RemovalReason::Mined);
+        assert_eq!(
+            classify(&context, "replaced", &plain),
+            RemovalReason::RbfReplaced
+        );
+        assert_eq!(classify(&context, "other", &plain), RemovalReason::Unknown);
+        let old = MempoolEntry {
+            time: 1_700_000_000 + 60 - MEMPOOL_EXPIRY_SECS,
+            ..plain.clone()
+        };
+        assert_eq!(classify(&context, "other", &old), RemovalReason::Expired);
+        let replaceable = MempoolEntry {
+            bip125_replaceable: true,
+            ..plain.clone()
+        };
+        assert_eq!(
+            classify(&context, "other", &replaceable),
+            RemovalReason::RbfReplaced
+        );
+
+        // only below the raised minimum fee of a full mempool
+        let cheap = entry(200, 600);
+        assert_eq!(classify(&context, "other", &cheap), RemovalReason::Unknown);
+        let purging = RemovalContext {
+            mempool_min_fee_sat_vb: 5.,
+            ..context
+        };
+        assert_eq!(classify(&purging, "other", &cheap), RemovalReason::Purged);
+        assert_eq!(classify(&purging, "other", &plain), RemovalReason::Unknown);
+        let full_rbf = RemovalContext {
+            full_rbf: true,
+            ..purging
+        };
+        assert_eq!(
+            classify(&full_rbf, "other", &plain),
+            RemovalReason::RbfReplaced
+        );
+    }
+
+    #[test]
+    fn fork_at_the_same_height_writes_a_full_snapshot() {
+        let (a, b) = (BlockHash::hash(b"a"), BlockHash::hash(b"b"));
+        let kind = |prev_hash, hash| {
+            Record::snapshot_kind(false, Record::is_new_tip(800_000, prev_hash, 800_000, hash))
+        };
+        assert_eq!(kind(Some(a), a), FileKind::Delta);
+        assert_eq!(kind(Some(a), b), FileKind::Full);
+        // after a lost file or a switch of nodes
+        assert_eq!(kind(None, a), FileKind::Full);
+        assert_eq!(
+            Record::snapshot_kind(false, Record::is_new_tip(800_000, Some(a), 800_001, a)),
+            FileKind::Full
+        );
+        assert_eq!(
+            Record::snapshot_kind(true, Record::is_new_tip(800_000, Some(a), 800_000, b)),
+            FileKind::Histogram
+        );
     }
 }
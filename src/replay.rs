@@ -0,0 +1,402 @@
+use crate::{
+    dataset::{FileKind, SnapshotFile},
+    record::Mempool,
+    score::Score,
+    storage::Storage,
+};
+use anyhow::{bail, Result};
+use bitcoin::Denomination;
+use polars::prelude::*;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+use tracing::debug;
+
+/// A transaction as recorded in full and delta files
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    pub weight: f64,
+    pub fee_sat: f64,
+    pub first_seen_at: Option<u64>,
+    /// Fee rate of the package it is mined with, sat/vB, see [`Score::effective_fee_rates`].
+    /// Unknown for histograms and files without the column.
+    pub effective_fee_rate: Option<f64>,
+}
+
+impl Transaction {
+    pub fn fee_rate_sat_vb(&self) -> f64 {
+        self.fee_sat / (self.weight / 4.)
+    }
+
+    /// What a miner picks it by, the package's fee rate if recorded and its own otherwise
+    pub fn mining_fee_rate(&self) -> f64 {
+        self.effective_fee_rate
+            .unwrap_or_else(|| self.fee_rate_sat_vb())
+    }
+}
+
+/// The mempool as it was when a snapshot was taken
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub height: u64,
+    /// Tip the mempool was recorded at, unknown for histograms and files without the column
+    pub block_hash: Option<String>,
+    pub timestamp: i64,
+    /// txid -> transaction
+    pub transactions: HashMap<String, Transaction>,
+}
+
+impl Snapshot {
+    /// An empty mempool at `height` and `timestamp`
+    pub fn new(height: u64, timestamp: i64) -> Self {
+        Snapshot {
+            height,
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    /// The node's mempool as `getrawmempool` returned it at `height` and `timestamp`
+    pub fn from_mempool(height: u64, timestamp: i64, mempool: &Mempool) -> Self {
+        let effective_fee_rates = Score::effective_fee_rates(mempool);
+        let transactions = mempool
+            .iter()
+            .map(|(txid, entry)| {
+                let transaction = Transaction {
+                    weight: entry.weight as f64,
+                    fee_sat: entry.fee.to_float_in(Denomination::Satoshi),
+                    first_seen_at: Some(entry.time),
+                    effective_fee_rate: effective_fee_rates.get(txid).copied(),
+                };
+                (txid.to_string(), transaction)
+            })
+            .collect();
+        Snapshot {
+            height,
+            block_hash: None,
+            timestamp,
+            transactions,
+        }
+    }
+
+    /// The tip the rows of a full or delta snapshot were recorded at, the same in every row
+    pub fn block_hash_of(df: &DataFrame) -> Result<Option<String>> {
+        let Ok(hashes) = df.column("block_hash") else {
+            return Ok(None);
+        };
+        Ok(hashes
+            .utf8()?
+            .into_iter()
+            .flatten()
+            .find(|hash| !hash.is_empty())
+            .map(str::to_string))
+    }
+
+    pub fn to_frame(&self) -> Result<DataFrame> {
+        let mut rows: Vec<(&String, &Transaction)> = self.transactions.iter().collect();
+        rows.sort_by(|a, b| a.0.cmp(b.0));
+        Ok(DataFrame::new(vec![
+            Series::new(
+                "txid",
+                rows.iter()
+                    .map(|(txid, _)| txid.as_str())
+                    .collect::<Vec<_>>(),
+            ),
+            Series::new(
+                "weight",
+                rows.iter().map(|(_, tx)| tx.weight).collect::<Vec<_>>(),
+            ),
+            Series::new(
+                "fee_sat",
+                rows.iter().map(|(_, tx)| tx.fee_sat).collect::<Vec<_>>(),
+            ),
+            Series::new(
+                "first_seen_at",
+                rows.iter()
+                    .map(|(_, tx)| tx.first_seen_at)
+                    .collect::<Vec<_>>(),
+            ),
+        ])?)
+    }
+
+    /// Apply the rows of a full or delta snapshot, negative weights remove a transaction
+    fn apply(&mut self, df: &DataFrame, timestamp: i64) -> Result<()> {
+        let txids = df.column("txid")?.utf8()?;
+        let weights = df.column("weight")?.f64()?;
+        let fees = df.column("fee_sat")?.f64()?;
+        let first_seen = df.column("first_seen_at")?.u64()?;
+        // not in files of the first schema
+        let effective_fee_rates: Vec<Option<f64>> = match df.column("effective_fee_rate") {
+            Ok(column) => column.f64()?.into_iter().collect(),
+            Err(_) => vec![None; df.height()],
+        };
+
+        let rows = txids
+            .into_iter()
+            .zip(weights)
+            .zip(fees)
+            .zip(first_seen)
+            .zip(effective_fee_rates);
+        for ((((txid, weight), fee_sat), first_seen_at), effective_fee_rate) in rows {
+            let (Some(txid), Some(weight), Some(fee_sat)) = (txid, weight, fee_sat) else {
+                continue;
+            };
+            if weight < 0. {
+                self.transactions.remove(txid);
+            } else {
+                self.transactions.insert(
+                    txid.to_string(),
+                    Transaction {
+                        weight,
+                        fee_sat,
+                        first_seen_at,
+                        effective_fee_rate,
+                    },
+                );
+            }
+        }
+        self.timestamp = timestamp;
+        Ok(())
+    }
+
+    /// Stand in for the mempool of a histogram with its buckets split into transactions of the
+    /// average size, paying the lowest fee rate of the bucket. They are named
+    /// `{fee rate}:{index}` as the real txids aren't known.
+    fn apply_histogram(&mut self, df: &DataFrame, timestamp: i64) -> Result<()> {
+        let fee_rates = df.column("fee_rate_sat_vb")?.f64()?;
+        let txs = df.column("txs")?.u64()?;
+        let vsizes = df.column("vsize")?.u64()?;
+        self.transactions.clear();
+        for ((fee_rate, txs), vsize) in fee_rates.into_iter().zip(txs).zip(vsizes) {
+            let (Some(fee_rate), Some(vsize @ 1..)) = (fee_rate, vsize) else {
+                continue;
+            };
+            let txs = txs
+                .filter(|txs| *txs > 0)
+                .unwrap_or_else(|| vsize.div_ceil(HISTOGRAM_TX_VSIZE))
+                .min(vsize);
+            for index in 0..txs {
+                // the remainder goes to the first ones
+                let size = vsize / txs + u64::from(index < vsize % txs);
+                self.transactions.insert(
+                    format!("{fee_rate}:{index}"),
+                    Transaction {
+                        weight: size as f64 * 4.,
+                        fee_sat: fee_rate * size as f64,
+                        first_seen_at: None,
+                        effective_fee_rate: None,
+                    },
+                );
+            }
+        }
+        self.timestamp = timestamp;
+        Ok(())
+    }
+}
+
+/// Histogram buckets replay as transactions of this many vB if the number in them is unknown
+const HISTOGRAM_TX_VSIZE: u64 = 1000;
+
+/// A full or delta snapshot, stored in a file of its own or as rows of a compacted day
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub height: u64,
+    pub timestamp: i64,
+    pub kind: FileKind,
+    file: usize,
+    /// Offset and length within a compact file
+    rows: Option<(i64, usize)>,
+}
+
+/// Reconstructs the mempool from full, delta, compact and histogram files
+pub struct Replay<'a> {
+    storage: &'a dyn Storage,
+    files: Vec<SnapshotFile>,
+    entries: Vec<Entry>,
+    /// The compact file read last, consecutive entries usually come from the same day
+    cached: RefCell<Option<(usize, DataFrame)>>,
+}
+
+impl<'a> Replay<'a> {
+    /// Index the snapshots of the dataset. Compact files are read to find the snapshots they
+    /// contain, a snapshot present both compacted and as its own file is used once. Histograms
+    /// are snapshots too, see [`Snapshot::apply_histogram`].
+    pub fn new(storage: &'a dyn Storage) -> Result<Self> {
+        let files: Vec<SnapshotFile> = storage
+            .list()?
+            .into_iter()
+            .filter(|f| {
+                matches!(
+                    f.kind,
+                    FileKind::Full | FileKind::Delta | FileKind::Compact | FileKind::Histogram
+                )
+            })
+            .collect();
+
+        let mut entries = Vec::new();
+        for (index, file) in files.iter().enumerate() {
+            if file.kind != FileKind::Compact {
+                entries.push(Entry {
+                    height: file.height,
+                    timestamp: file.timestamp,
+                    kind: file.kind,
+                    file: index,
+                    rows: None,
+                });
+                continue;
+            }
+            let frame = storage.read(file)?;
+            let heights = frame.column("height")?.u64()?;
+            let timestamps = frame.column("snapshot_timestamp")?.i64()?;
+            let kinds = frame.column("kind")?.utf8()?;
+            let mut offset = 0;
+            for (row, ((height, timestamp), kind)) in
+                heights.into_iter().zip(timestamps).zip(kinds).enumerate()
+            {
+                let (Some(height), Some(timestamp), Some(kind)) =
+                    (height, timestamp, kind.and_then(FileKind::parse))
+                else {
+                    bail!(
+                        "{} has a row without height, timestamp or kind",
+                        file.path.display()
+                    );
+                };
+                match entries.last_mut() {
+                    Some(last @ Entry { rows: Some(_), .. })
+                        if last.file == index
+                            && last.timestamp == timestamp
+                            && last.kind == kind =>
+                    {
+                        last.rows = Some((offset, row - offset as usize + 1));
+                    }
+                    _ => {
+                        offset = row as i64;
+                        entries.push(Entry {
+                            height,
+                            timestamp,
+                            kind,
+                            file: index,
+                            rows: Some((offset, 1)),
+                        });
+                    }
+                }
+            }
+        }
+        // listing order isn't stable, so ties don't depend on it
+        entries.sort_by_key(|e| (e.timestamp, e.kind.rank(), e.height));
+        entries.dedup_by_key(|e| (e.timestamp, e.kind));
+
+        Ok(Replay {
+            storage,
+            files,
+            entries,
+            cached: RefCell::new(None),
+        })
+    }
+
+    /// All snapshots, ordered by time
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Time of the latest snapshot
+    pub fn latest(&self) -> Option<i64> {
+        self.entries.last().map(|e| e.timestamp)
+    }
+
+    /// The file a snapshot is stored in, a compact one for several of them
+    pub fn file(&self, entry: &Entry) -> &SnapshotFile {
+        &self.files[entry.file]
+    }
+
+    /// Rows of a snapshot, tagged like [`SnapshotFile::tag`]
+    pub fn read(&self, entry: &Entry) -> Result<DataFrame> {
+        let file = &self.files[entry.file];
+        let Some((offset, len)) = entry.rows else {
+            debug!("reading {}", file.path.display());
+            return file.tag(&self.storage.read(file)?);
+        };
+        let mut cached = self.cached.borrow_mut();
+        if !matches!(cached.as_ref(), Some((index, _)) if *index == entry.file) {
+            debug!("reading {}", file.path.display());
+            *cached = Some((entry.file, self.storage.read(file)?));
+        }
+        let frame = cached.as_ref().unwrap().1.slice(offset, len);
+        // empty snapshots are kept as a single row without a txid
+        let present = frame.column("txid")?.is_not_null();
+        Ok(frame.filter(&present)?)
+    }
+
+    /// Call `visit` with the mempool of every snapshot taken within `from..=to`
+    pub fn walk(
+        &self,
+        from: i64,
+        to: i64,
+        mut visit: impl FnMut(&Snapshot) -> Result<()>,
+    ) -> Result<()> {
+        // only heights with snapshots inside the range need to be reconstructed
+        let heights: HashSet<u64> = self
+            .entries
+            .iter()
+            .filter(|e| e.timestamp >= from && e.timestamp <= to)
+            .map(|e| e.height)
+            .collect();
+
+        let mut state: Option<Snapshot> = None;
+        for entry in self.entries.iter().filter(|e| heights.contains(&e.height)) {
+            if entry.timestamp > to {
+                break;
+            }
+            if matches!(entry.kind, FileKind::Full | FileKind::Histogram) {
+                state = Some(Snapshot {
+                    height: entry.height,
+                    ..Default::default()
+                });
+            }
+            // deltas are only meaningful on top of the full snapshot of their height
+            let Some(snapshot) = state.as_mut().filter(|s| s.height == entry.height) else {
+                continue;
+            };
+            let frame = self.read(entry)?;
+            if entry.kind == FileKind::Histogram {
+                snapshot.apply_histogram(&frame, entry.timestamp)?;
+            } else {
+                // and of its block, those of another one at the same height wait for its own
+                let block_hash = Snapshot::block_hash_of(&frame)?;
+                match (snapshot.block_hash.as_ref(), block_hash) {
+                    (Some(anchor), Some(block_hash)) if *anchor != block_hash => {
+                        debug!(
+                            "skipping the delta at {} of block {block_hash}, not {anchor}",
+                            entry.timestamp
+                        );
+                        continue;
+                    }
+                    (None, block_hash) => snapshot.block_hash = block_hash,
+                    _ => {}
+                }
+                snapshot.apply(&frame, entry.timestamp)?;
+            }
+            if entry.timestamp >= from {
+                visit(snapshot)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The mempool as of the last snapshot taken at or before `timestamp`
+    pub fn at(&self, timestamp: i64) -> Result<Snapshot> {
+        let Some(last) = self.entries.iter().rev().find(|e| e.timestamp <= timestamp) else {
+            bail!("no snapshot recorded at or before {timestamp}");
+        };
+        let mut found = None;
+        self.walk(last.timestamp, timestamp, |snapshot| {
+            found = Some(snapshot.clone());
+            Ok(())
+        })?;
+        match found {
+            Some(snapshot) => Ok(snapshot),
+            None => bail!("no full snapshot of height {} to replay from", last.height),
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use crate::{
+    calc::{Calc, BLOCK_TX_WEIGHT, HORIZONS_MINUTES},
+    replay::Replay,
+    storage::Storage,
+};
+use anyhow::{bail, Result};
+use bitcoin::secp256k1::rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+use std::collections::HashSet;
+use tracing::info;
+
+/// Snapshots this recent tell how fast transactions arrive
+const ARRIVALS_SECS: i64 = 24 * 60 * 60;
+/// Longer between two snapshots and the recorder was down, what arrived meanwhile is unknown
+pub(crate) const MAX_GAP_SECS: f64 = 10. * 60.;
+/// Mean time between blocks
+const BLOCK_INTERVAL_SECS: f64 = 600.;
+/// A rollout still unconfirmed after this many blocks is given up on
+const MAX_BLOCKS: u32 = 1008;
+/// Weight of the transaction paying the fee rate, one input and two outputs P2WPKH
+const TX_WEIGHT: f64 = 564.;
+
+/// When a rollout confirms the transaction, at a quantile of the rollouts
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfirmationQuantile {
+    pub confidence: f64,
+    /// `None` if the rollouts at this quantile gave up unconfirmed
+    pub minutes: Option<f64>,
+    pub blocks: Option<u32>,
+}
+
+/// Share of the rollouts confirming the transaction within a time horizon
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfirmationWithin {
+    pub horizon_minutes: u32,
+    pub probability: f64,
+}
+
+/// Distribution of the confirmation time of a transaction paying a fee rate
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfirmationTimes {
+    pub fee_rate_sat_vb: f64,
+    pub rollouts: u32,
+    pub height: u64,
+    /// Unix timestamp of the snapshot the rollouts start from
+    pub timestamp: i64,
+    /// Virtual size in the mempool at or above the fee rate when the rollouts start
+    pub ahead_vsize: u64,
+    /// Mean virtual size arriving at or above the fee rate per minute, recorded
+    pub arrivals_vsize_per_minute: f64,
+    pub quantiles: Vec<ConfirmationQuantile>,
+    pub within: Vec<ConfirmationWithin>,
+    /// Share of the rollouts confirming within [`MAX_BLOCKS`]
+    pub confirmed: f64,
+}
+
+/// The recorded inflow at or above a fee rate, laid end to end leaving out the gaps of the
+/// recording, so that a stretch of any length can be drawn from it
+struct Arrivals {
+    /// Seconds since the start at the end of each step
+    ends: Vec<f64>,
+    /// Weight arrived since the start at the end of each step
+    weights: Vec<f64>,
+}
+
+impl Arrivals {
+    fn duration(&self) -> f64 {
+        self.ends.last().copied().unwrap_or_default()
+    }
+
+    fn total(&self) -> f64 {
+        self.weights.last().copied().unwrap_or_default()
+    }
+
+    /// Weight arrived from the start until `at`, spread evenly within a step
+    fn until(&self, at: f64) -> f64 {
+        let step = self.ends.partition_point(|end| *end < at);
+        if step >= self.ends.len() {
+            return self.total();
+        }
+        let (start, before) = match step {
+            0 => (0., 0.),
+            step => (self.ends[step - 1], self.weights[step - 1]),
+        };
+        let length = self.ends[step] - start;
+        let share = if length > 0. {
+            (at - start) / length
+        } else {
+            1.
+        };
+        before + (self.weights[step] - before) * share
+    }
+
+    /// Weight arriving over `secs` from `start`, wrapping around the end of the recording
+    fn over(&self, start: f64, secs: f64) -> f64 {
+        let duration = self.duration();
+        let laps = (secs / duration).floor();
+        let rest = secs - laps * duration;
+        let end = start + rest;
+        let partial = if end <= duration {
+            self.until(end) - self.until(start)
+        } else {
+            self.total() - self.until(start) + self.until(end - duration)
+        };
+        laps * self.total() + partial
+    }
+}
+
+/// Monte Carlo rollouts of the confirmation of a transaction paying a fee rate: from the
+/// latest snapshot, blocks are found at random like proof of work finds them, each mining a
+/// block's worth of what pays at least as much, while transactions keep arriving like they
+/// did during the last day and those paying more go ahead
+pub struct Rollout;
+
+impl Rollout {
+    #[tracing::instrument(skip(storage))]
+    pub fn run(
+        storage: &dyn Storage,
+        fee_rate: f64,
+        confidences: &[f64],
+        rollouts: u32,
+        seed: u64,
+    ) -> Result<ConfirmationTimes> {
+        if rollouts == 0 {
+            bail!("rollouts must be at least one");
+        }
+        let replay = Replay::new(storage)?;
+        let Some(latest) = replay.latest() else {
+            bail!("no recorded snapshots found");
+        };
+        let snapshot = replay.at(latest)?;
+        let ahead: f64 = snapshot
+            .transactions
+            .values()
+            .filter(|tx| tx.fee_rate_sat_vb() >= fee_rate)
+            .map(|tx| tx.weight)
+            .sum();
+        let arrivals = Self::arrivals(&replay, latest - ARRIVALS_SECS, latest, fee_rate)?;
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        // (seconds, blocks) of each rollout, infinite if it gave up
+        let mut confirmations: Vec<(f64, u32)> = (0..rollouts)
+            .map(|_| Self::rollout(&mut rng, ahead, &arrivals))
+            .collect();
+        confirmations.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let secs: Vec<f64> = confirmations.iter().map(|(secs, _)| *secs).collect();
+        let mut blocks: Vec<u32> = confirmations.iter().map(|(_, blocks)| *blocks).collect();
+        blocks.sort_unstable();
+        let blocks: Vec<f64> = blocks.into_iter().map(f64::from).collect();
+
+        let quantiles = confidences
+            .iter()
+            .map(|&confidence| {
+                let secs = Calc::quantile(&secs, confidence);
+                let blocks = Calc::quantile(&blocks, confidence);
+                ConfirmationQuantile {
+                    confidence,
+                    minutes: secs.is_finite().then_some(secs / 60.),
+                    blocks: secs.is_finite().then_some(blocks as u32),
+                }
+            })
+            .collect();
+        let share = |count: usize| count as f64 / rollouts as f64;
+        let within = HORIZONS_MINUTES
+            .iter()
+            .map(|&horizon_minutes| ConfirmationWithin {
+                horizon_minutes,
+                probability: share(secs.partition_point(|s| *s <= horizon_minutes as f64 * 60.)),
+            })
+            .collect();
+        let confirmed = share(secs.iter().filter(|s| s.is_finite()).count());
+        let arrivals_vsize_per_minute = arrivals.total() / 4. / arrivals.duration() * 60.;
+        info!(
+            "ahead_vsize: {:.0}, arrivals_vsize_per_minute: {arrivals_vsize_per_minute:.0}, \
+             confirmed: {confirmed:.3}",
+            ahead / 4.
+        );
+        Ok(ConfirmationTimes {
+            fee_rate_sat_vb: fee_rate,
+            rollouts,
+            height: snapshot.height,
+            timestamp: snapshot.timestamp,
+            ahead_vsize: (ahead / 4.).ceil() as u64,
+            arrivals_vsize_per_minute,
+            quantiles,
+            within,
+            confirmed,
+        })
+    }
+
+    /// What arrived at or above `fee_rate` between consecutive snapshots within `from..=to`
+    fn arrivals(replay: &Replay, from: i64, to: i64, fee_rate: f64) -> Result<Arrivals> {
+        let mut arrivals = Arrivals {
+            ends: Vec::new(),
+            weights: Vec::new(),
+        };
+        let mut previous: Option<(i64, HashSet<String>)> = None;
+        replay.walk(from, to, |snapshot| {
+            if let Some((timestamp, txids)) = &previous {
+                let gap = (snapshot.timestamp - timestamp) as f64;
+                if gap > 0. && gap <= MAX_GAP_SECS {
+                    let weight: f64 = snapshot
+                        .transactions
+                        .iter()
+                        .filter(|(txid, tx)| {
+                            !txids.contains(*txid) && tx.fee_rate_sat_vb() >= fee_rate
+                        })
+                        .map(|(_, tx)| tx.weight)
+                        .sum();
+                    arrivals.ends.push(arrivals.duration() + gap);
+                    arrivals.weights.push(arrivals.total() + weight);
+                }
+            }
+            previous = Some((
+                snapshot.timestamp,
+                snapshot.transactions.keys().cloned().collect(),
+            ));
+            Ok(())
+        })?;
+        if arrivals.duration() <= 0. {
+            bail!("arrivals need at least two snapshots recorded within {MAX_GAP_SECS} s");
+        }
+        Ok(arrivals)
+    }
+
+    /// Seconds and blocks until the transaction confirms, infinite seconds if it doesn't
+    /// within [`MAX_BLOCKS`]
+    fn rollout(rng: &mut StdRng, mut ahead: f64, arrivals: &Arrivals) -> (f64, u32) {
+        let mut elapsed = 0.;
+        for block in 1..=MAX_BLOCKS {
+            let interval = -rng.gen_range(f64::EPSILON..1.).ln() * BLOCK_INTERVAL_SECS;
+            let start = rng.gen_range(0. ..arrivals.duration());
+            ahead += arrivals.over(start, interval);
+            elapsed += interval;
+            if ahead + TX_WEIGHT <= BLOCK_TX_WEIGHT {
+                return (elapsed, block);
+            }
+            ahead -= BLOCK_TX_WEIGHT;
+        }
+        (f64::INFINITY, MAX_BLOCKS)
+    }
+}
@@ -0,0 +1,285 @@
+use crate::node::Node;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use bitcoin::{
+    consensus::deserialize, hashes::hex::FromHex, Block, BlockHash, Network, Transaction, Txid,
+};
+use bitcoincore_rest::{
+    responses::{GetBlockchainInfoResult, GetMempoolEntryResult, GetMempoolInfoResult},
+    GetMempoolTxidsAndSequenceResult,
+};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+use tracing::debug;
+
+pub enum RpcAuth {
+    UserPass(String, String),
+    /// Read on every call, bitcoind writes a new cookie on each start
+    Cookie(PathBuf),
+}
+
+impl RpcAuth {
+    /// `~/.bitcoin[/<network>]/.cookie`, the location used by a bitcoind with the default datadir
+    pub fn default_cookie(network: Network) -> Result<Self> {
+        let home = std::env::var("HOME").context("HOME is not set, pass --rpc-cookie")?;
+        let mut path = PathBuf::from(home).join(".bitcoin");
+        match network {
+            Network::Bitcoin => {}
+            Network::Testnet => path.push("testnet3"),
+            other => path.push(other.to_string()),
+        }
+        Ok(RpcAuth::Cookie(path.join(".cookie")))
+    }
+
+    fn credentials(&self) -> Result<(String, String)> {
+        match self {
+            RpcAuth::UserPass(user, password) => Ok((user.clone(), password.clone())),
+            RpcAuth::Cookie(path) => {
+                let cookie = std::fs::read_to_string(path)
+                    .with_context(|| format!("reading cookie file {}", path.display()))?;
+                let (user, password) = cookie
+                    .trim()
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("malformed cookie file {}", path.display()))?;
+                Ok((user.to_string(), password.to_string()))
+            }
+        }
+    }
+}
+
+/// Calls per JSON-RPC batch request
+const BATCH_SIZE: usize = 500;
+/// Batch requests in flight at once unless configured, as many as bitcoind has RPC threads
+pub const DEFAULT_CONCURRENCY: usize = 4;
+/// Tries per batch request, bitcoind turns requests away while its work queue is full
+const BATCH_ATTEMPTS: u32 = 4;
+/// Wait before the first retry, doubled for every further one
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// What `estimatesmartfee` returns, without a fee rate when it has too little data
+#[derive(Deserialize)]
+struct EstimateSmartFeeResult {
+    /// BTC/kvB
+    feerate: Option<f64>,
+}
+
+/// The part of a `getpeerinfo` entry recorded
+#[derive(Deserialize)]
+struct PeerInfo {
+    /// BTC/kvB, 0 if the peer sent no `feefilter`
+    minfeefilter: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<Value>,
+    #[serde(default)]
+    id: Value,
+}
+
+/// Bitcoin Core JSON-RPC client, for nodes that don't run with `-rest`
+pub struct RpcClient {
+    client: reqwest::Client,
+    endpoint: String,
+    auth: RpcAuth,
+    concurrency: usize,
+}
+
+impl RpcClient {
+    pub fn new(endpoint: impl Into<String>, auth: RpcAuth, client: reqwest::Client) -> Self {
+        RpcClient {
+            client,
+            endpoint: endpoint.into(),
+            auth,
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    /// Call the wallet `name` of the node, for nodes with more than one loaded
+    pub fn with_wallet(mut self, name: &str) -> Self {
+        self.endpoint = format!("{}/wallet/{name}", self.endpoint.trim_end_matches('/'));
+        self
+    }
+
+    /// Send up to `concurrency` batch requests at once
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
+        let (user, password) = self.auth.credentials()?;
+        let body = json!({"jsonrpc": "1.0", "id": "wtf", "method": method, "params": params});
+        let response = self
+            .client
+            .post(&self.endpoint)
+            .basic_auth(user, Some(password))
+            .json(&body)
+            .send()
+            .await?;
+
+        // bitcoind reports RPC errors with a non-OK status but a JSON body
+        let status = response.status();
+        let response: RpcResponse<T> = response
+            .json()
+            .await
+            .with_context(|| format!("{method} returned {status}"))?;
+        match (response.result, response.error) {
+            (_, Some(error)) if !error.is_null() => Err(anyhow!("{method} failed: {error}")),
+            (Some(result), _) => Ok(result),
+            (None, _) => Err(anyhow!("{method} returned no result")),
+        }
+    }
+
+    /// Call `method` once per set of params in JSON-RPC batches, several of them in flight at
+    /// once, each tried again with backoff when the request fails. Calls that fail (e.g. a
+    /// transaction that left the mempool) come back as `None`.
+    pub async fn batch<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Vec<Value>,
+    ) -> Result<Vec<Option<T>>> {
+        let requests: Vec<_> = params
+            .chunks(BATCH_SIZE)
+            .map(|chunk| self.batch_with_retries(method, chunk))
+            .collect();
+        let chunks: Vec<Vec<Option<T>>> = stream::iter(requests)
+            .buffered(self.concurrency)
+            .try_collect()
+            .await?;
+        Ok(chunks.into_iter().flatten().collect())
+    }
+
+    async fn batch_with_retries<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        chunk: &[Value],
+    ) -> Result<Vec<Option<T>>> {
+        let mut delay = RETRY_DELAY;
+        for _ in 1..BATCH_ATTEMPTS {
+            match self.send_batch(method, chunk).await {
+                Ok(results) => return Ok(results),
+                Err(e) => {
+                    debug!("{method} batch failed, retrying in {delay:?}: {e:#}");
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+        }
+        self.send_batch(method, chunk).await
+    }
+
+    async fn send_batch<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        chunk: &[Value],
+    ) -> Result<Vec<Option<T>>> {
+        let (user, password) = self.auth.credentials()?;
+        let body: Vec<Value> = chunk
+            .iter()
+            .enumerate()
+            .map(|(id, params)| {
+                json!({"jsonrpc": "1.0", "id": id, "method": method, "params": params})
+            })
+            .collect();
+        let response = self
+            .client
+            .post(&self.endpoint)
+            .basic_auth(user, Some(password))
+            .json(&body)
+            .send()
+            .await?;
+        let status = response.status();
+        let responses: Vec<RpcResponse<T>> = response
+            .json()
+            .await
+            .with_context(|| format!("{method} batch returned {status}"))?;
+
+        // responses may come back in any order
+        let mut chunk_results: Vec<Option<T>> = chunk.iter().map(|_| None).collect();
+        for response in responses {
+            let ok = response.error.is_none_or(|e| e.is_null());
+            if let (true, Some(id), Some(result)) = (ok, response.id.as_u64(), response.result) {
+                if let Some(slot) = chunk_results.get_mut(id as usize) {
+                    *slot = Some(result);
+                }
+            }
+        }
+        Ok(chunk_results)
+    }
+}
+
+#[async_trait]
+impl Node for RpcClient {
+    async fn get_chain_info(&self) -> Result<GetBlockchainInfoResult> {
+        self.call("getblockchaininfo", json!([])).await
+    }
+
+    async fn get_mempool(&self) -> Result<HashMap<Txid, GetMempoolEntryResult>> {
+        self.call("getrawmempool", json!([true])).await
+    }
+
+    async fn get_mempool_info(&self) -> Result<GetMempoolInfoResult> {
+        self.call("getmempoolinfo", json!([])).await
+    }
+
+    async fn get_mempool_txids_and_sequence(&self) -> Result<GetMempoolTxidsAndSequenceResult> {
+        self.call("getrawmempool", json!([false, true])).await
+    }
+
+    async fn get_mempool_entries(
+        &self,
+        txids: &[Txid],
+    ) -> Result<HashMap<Txid, GetMempoolEntryResult>> {
+        let params = txids.iter().map(|txid| json!([txid])).collect();
+        let entries = self.batch("getmempoolentry", params).await?;
+        Ok(txids
+            .iter()
+            .zip(entries)
+            .filter_map(|(txid, entry)| Some((*txid, entry?)))
+            .collect())
+    }
+
+    async fn get_transactions(&self, txids: &[Txid]) -> Result<HashMap<Txid, Transaction>> {
+        let params = txids.iter().map(|txid| json!([txid, false])).collect();
+        let transactions: Vec<Option<String>> = self.batch("getrawtransaction", params).await?;
+        let mut decoded = HashMap::with_capacity(txids.len());
+        for (txid, hex) in txids.iter().zip(transactions) {
+            if let Some(hex) = hex {
+                decoded.insert(*txid, deserialize(&Vec::<u8>::from_hex(&hex)?)?);
+            }
+        }
+        Ok(decoded)
+    }
+
+    async fn get_block_hash(&self, height: u64) -> Result<BlockHash> {
+        self.call("getblockhash", json!([height])).await
+    }
+
+    async fn get_block(&self, hash: &BlockHash) -> Result<Block> {
+        let hex: String = self.call("getblock", json!([hash, 0])).await?;
+        Ok(deserialize(&Vec::<u8>::from_hex(&hex)?)?)
+    }
+
+    async fn estimate_smart_fees(&self, targets: &[u32]) -> Result<Vec<Option<f64>>> {
+        let params = targets.iter().map(|target| json!([target])).collect();
+        let estimates: Vec<Option<EstimateSmartFeeResult>> =
+            self.batch("estimatesmartfee", params).await?;
+        // BTC/kvB to sat/vB
+        Ok(estimates
+            .into_iter()
+            .map(|estimate| Some(estimate?.feerate? * 1e5))
+            .collect())
+    }
+
+    async fn get_peer_fee_filters(&self) -> Result<Vec<f64>> {
+        let peers: Vec<PeerInfo> = self.call("getpeerinfo", json!([])).await?;
+        Ok(peers
+            .into_iter()
+            .map(|peer| peer.minfeefilter.unwrap_or_default() * 1e5)
+            .collect())
+    }
+}
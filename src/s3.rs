@@ -0,0 +1,243 @@
+use crate::{dataset::SnapshotFile, sink::Sink};
+use anyhow::{anyhow, bail, Context, Result};
+use bitcoin::{
+    hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine},
+    Network,
+};
+use chrono::Utc;
+use polars::prelude::DataFrame;
+use reqwest::Url;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+/// Spooled files are looked for this often even without new writes
+const RESCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Where and how to upload
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-central-1.amazonaws.com` or a MinIO URL, addressed path-style
+    pub endpoint: String,
+    pub bucket: String,
+    /// Prepended to `{network}/YYYY/MM/DD/{file}`
+    pub prefix: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+impl S3Config {
+    /// Credentials come from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`
+    pub fn from_env(
+        endpoint: String,
+        bucket: String,
+        prefix: String,
+        region: String,
+    ) -> Result<Self> {
+        let var = |name: &str| std::env::var(name).with_context(|| format!("{name} is not set"));
+        Ok(S3Config {
+            endpoint,
+            bucket,
+            prefix,
+            region,
+            access_key: var("AWS_ACCESS_KEY_ID")?,
+            secret_key: var("AWS_SECRET_ACCESS_KEY")?,
+        })
+    }
+}
+
+/// Uploads every written file to S3-compatible object storage. Files are linked into a spool
+/// directory first and only removed from it once uploaded, so nothing is lost when the
+/// storage is unreachable or the recorder restarts.
+pub struct S3Sink {
+    spool: PathBuf,
+    network: Network,
+    notify: Arc<Notify>,
+}
+
+impl S3Sink {
+    /// Start uploading, including whatever is left in `spool` from a previous run
+    pub fn start(config: S3Config, spool: PathBuf, network: Network) -> Result<Self> {
+        std::fs::create_dir_all(&spool)
+            .with_context(|| format!("creating spool directory {}", spool.display()))?;
+        let notify = Arc::new(Notify::new());
+        let uploader = Uploader {
+            client: reqwest::Client::new(),
+            endpoint: Url::parse(&config.endpoint)?,
+            config,
+            spool: spool.clone(),
+        };
+        tokio::spawn(uploader.run(notify.clone()));
+        Ok(S3Sink {
+            spool,
+            network,
+            notify,
+        })
+    }
+}
+
+impl Sink for S3Sink {
+    fn name(&self) -> &str {
+        "s3"
+    }
+
+    fn accept(&self, file: &SnapshotFile, _frame: &DataFrame) -> Result<()> {
+        let key = Path::new(&self.network.to_string()).join(SnapshotFile::path_for(
+            file.written_at(),
+            file.height,
+            file.kind,
+        ));
+        let spooled = self.spool.join(key);
+        std::fs::create_dir_all(spooled.parent().unwrap())?;
+        // the recorded file is never rewritten, a hard link saves the copy where possible
+        if std::fs::hard_link(&file.path, &spooled).is_err() {
+            std::fs::copy(&file.path, &spooled)?;
+        }
+        self.notify.notify_one();
+        Ok(())
+    }
+}
+
+struct Uploader {
+    client: reqwest::Client,
+    endpoint: Url,
+    config: S3Config,
+    spool: PathBuf,
+}
+
+impl Uploader {
+    async fn run(self, notify: Arc<Notify>) {
+        let mut delay = Duration::from_secs(1);
+        loop {
+            match self.upload_spooled().await {
+                Ok(uploaded) => {
+                    if uploaded > 0 {
+                        info!("uploaded_files: {uploaded}");
+                    }
+                    delay = Duration::from_secs(1);
+                    let _ = tokio::time::timeout(RESCAN_INTERVAL, notify.notified()).await;
+                }
+                Err(e) => {
+                    warn!("s3 upload failed, retrying in {}s: {e:#}", delay.as_secs());
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+            }
+        }
+    }
+
+    /// Upload and unlink every spooled file, oldest directories first
+    async fn upload_spooled(&self) -> Result<usize> {
+        let mut files = Vec::new();
+        Self::find(&self.spool, &mut files)?;
+        files.sort();
+        for path in &files {
+            let key = path
+                .strip_prefix(&self.spool)?
+                .to_str()
+                .ok_or_else(|| anyhow!("non UTF-8 spool path {}", path.display()))?;
+            let body = std::fs::read(path)?;
+            self.put(key, body).await?;
+            debug!("uploaded {key}");
+            std::fs::remove_file(path)?;
+        }
+        Ok(files.len())
+    }
+
+    fn find(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                Self::find(&path, files)?;
+            } else {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<()> {
+        let key = format!("{}{key}", self.config.prefix);
+        let path = format!(
+            "{}/{}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            self.config.bucket,
+            uri_encode(&key)
+        );
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        let now = Utc::now();
+        let date = now.format("%Y%m%d").to_string();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = format!("{:x}", sha256::Hash::hash(&body));
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "PUT\n{}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{timestamp}\n\n{signed_headers}\n{payload_hash}",
+            url.path()
+        );
+        let scope = format!("{date}/{}/s3/aws4_request", self.config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{:x}",
+            sha256::Hash::hash(canonical_request.as_bytes())
+        );
+        let key_date = hmac(
+            format!("AWS4{}", self.config.secret_key).as_bytes(),
+            date.as_bytes(),
+        );
+        let key_region = hmac(&key_date, self.config.region.as_bytes());
+        let key_service = hmac(&key_region, b"s3");
+        let signing_key = hmac(&key_service, b"aws4_request");
+        let signature = hmac(&signing_key, string_to_sign.as_bytes())
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.config.access_key
+        );
+
+        let response = self
+            .client
+            .put(url)
+            .header("x-amz-date", timestamp)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            bail!("PUT {key} returned {status}: {}", response.text().await?);
+        }
+        Ok(())
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(key);
+    engine.input(data);
+    Hmac::from_engine(engine).to_byte_array()
+}
+
+/// Percent-encode everything but unreserved characters and `/`, as SigV4 expects
+fn uri_encode(key: &str) -> String {
+    key.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
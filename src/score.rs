@@ -0,0 +1,241 @@
+use crate::record::{Mempool, MempoolEntry};
+use bitcoin::{Denomination, Txid};
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+/// A package waiting to be mined, ordered by its fee rate
+struct Candidate {
+    fee_rate: f64,
+    txid: Txid,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.fee_rate
+            .total_cmp(&other.fee_rate)
+            .then_with(|| other.txid.cmp(&self.txid))
+    }
+}
+
+/// Packages in a row that may not fit before a nearly full block is given up on, like Bitcoin
+/// Core's block assembler does
+const MAX_CONSECUTIVE_FAILURES: usize = 1000;
+/// A block is nearly full once within this weight of its limit
+const NEARLY_FULL_WEIGHT: u64 = 4_000;
+
+/// Transactions mined together, `txids` ancestors first
+#[derive(Debug, Clone)]
+pub struct Package {
+    pub fee_rate: f64,
+    pub txids: Vec<Txid>,
+}
+
+pub struct Score;
+
+impl Score {
+    /// Effective fee rate (sat/vB) of every transaction in `mempool`, as Bitcoin Core's block
+    /// assembler sees it: the package with the best ancestor score (fees over vsize of the
+    /// transaction and its unmined ancestors) is mined first, all its transactions at that rate,
+    /// then the ancestor scores of the remaining descendants are updated. A low fee parent
+    /// carried by a high fee child gets the rate of the pair, and so does the child.
+    pub fn effective_fee_rates(mempool: &Mempool) -> HashMap<Txid, f64> {
+        Self::fee_rates(&Self::packages(mempool))
+    }
+
+    /// Effective fee rate of every transaction of `packages`, see [`Score::effective_fee_rates`]
+    pub fn fee_rates(packages: &[Package]) -> HashMap<Txid, f64> {
+        packages
+            .iter()
+            .flat_map(|package| package.txids.iter().map(|txid| (*txid, package.fee_rate)))
+            .collect()
+    }
+
+    /// Where every transaction of `packages` waits in the queue the block assembler works
+    /// through: the percentage of the vsize of `mempool` picked before its package, 0 at the
+    /// front. A transaction moves up as what is ahead of it gets mined and falls back as
+    /// better paying ones arrive.
+    pub fn queue_percentiles(mempool: &Mempool, packages: &[Package]) -> HashMap<Txid, f64> {
+        let total = mempool
+            .values()
+            .map(|entry| entry.vsize)
+            .sum::<u64>()
+            .max(1) as f64;
+        let mut ahead = 0;
+        let mut percentiles = HashMap::with_capacity(mempool.len());
+        for package in packages {
+            let percentile = ahead as f64 / total * 100.;
+            for txid in &package.txids {
+                percentiles.insert(*txid, percentile);
+                ahead += mempool.get(txid).map_or(0, |entry| entry.vsize);
+            }
+        }
+        percentiles
+    }
+
+    /// The packages of `mempool` in the order the block assembler picks them, as if they all
+    /// went into one block
+    pub fn packages(mempool: &Mempool) -> Vec<Package> {
+        Assembler::new(mempool).next_block(u64::MAX).0
+    }
+
+    /// Fees over vsize of `txid` and its ancestors that aren't mined yet
+    fn package_fee_rate(mempool: &Mempool, mined: &HashSet<Txid>, txid: &Txid) -> f64 {
+        let (fees, vsize) = Self::unmined_ancestors(mempool, mined, txid)
+            .iter()
+            .filter_map(|tx| mempool.get(tx))
+            .fold((0., 0), |(fees, vsize), entry| {
+                // modified fees, as prioritisetransaction changes what miners see
+                let fee = entry.modified_fee.to_float_in(Denomination::Satoshi);
+                (fees + fee, vsize + entry.vsize)
+            });
+        fees / vsize.max(1) as f64
+    }
+
+    /// `txid` with its in-mempool ancestors, leaving out those already mined
+    fn unmined_ancestors(mempool: &Mempool, mined: &HashSet<Txid>, txid: &Txid) -> Vec<Txid> {
+        Self::walk(mempool, mined, txid, |entry| &entry.depends)
+    }
+
+    /// `txid` with its in-mempool descendants, leaving out those already mined
+    fn unmined_descendants(mempool: &Mempool, mined: &HashSet<Txid>, txid: &Txid) -> Vec<Txid> {
+        Self::walk(mempool, mined, txid, |entry| &entry.spent_by)
+    }
+
+    fn walk(
+        mempool: &Mempool,
+        mined: &HashSet<Txid>,
+        txid: &Txid,
+        next: impl Fn(&MempoolEntry) -> &[Txid],
+    ) -> Vec<Txid> {
+        let mut seen: HashSet<Txid> = HashSet::from([*txid]);
+        let mut pending = vec![*txid];
+        let mut found = Vec::new();
+        while let Some(tx) = pending.pop() {
+            let Some(entry) = mempool.get(&tx) else {
+                continue;
+            };
+            if mined.contains(&tx) {
+                continue;
+            }
+            found.push(tx);
+            for related in next(entry) {
+                if seen.insert(*related) {
+                    pending.push(*related);
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Fills blocks from a mempool one after the other, as Bitcoin Core's block assembler would if
+/// every block were mined from what the previous ones left. The package with the best ancestor
+/// score goes in if it fits, then the ancestor scores of its remaining descendants are updated,
+/// so a large CPFP chain is picked as a whole where it fits and its children are rated without
+/// the parents already in a block. A package that doesn't fit is tried again in the next block.
+pub struct Assembler<'a> {
+    mempool: &'a Mempool,
+    mined: HashSet<Txid>,
+    heap: BinaryHeap<Candidate>,
+}
+
+impl<'a> Assembler<'a> {
+    pub fn new(mempool: &'a Mempool) -> Self {
+        let mined = HashSet::new();
+        let heap = mempool
+            .keys()
+            .map(|txid| Candidate {
+                fee_rate: Score::package_fee_rate(mempool, &mined, txid),
+                txid: *txid,
+            })
+            .collect();
+        Assembler {
+            mempool,
+            mined,
+            heap,
+        }
+    }
+
+    /// The packages of the next block of at most `max_weight`, in the order they are picked,
+    /// and whether one was left for a later block for not fitting. Empty once nothing is left
+    /// that fits.
+    pub fn next_block(&mut self, max_weight: u64) -> (Vec<Package>, bool) {
+        let mempool = self.mempool;
+        let mut packages = Vec::new();
+        let mut weight = 0;
+        let mut failed: HashSet<Txid> = HashSet::new();
+        let mut failures = 0;
+
+        while let Some(Candidate { fee_rate, txid }) = self.heap.pop() {
+            if self.mined.contains(&txid) || failed.contains(&txid) {
+                continue;
+            }
+            // an ancestor was mined in the meantime, a fresher candidate was queued for it
+            if Score::package_fee_rate(mempool, &self.mined, &txid) != fee_rate {
+                continue;
+            }
+            let mut package = Score::unmined_ancestors(mempool, &self.mined, &txid);
+            let package_weight: u64 = package.iter().map(|tx| mempool[tx].weight).sum();
+            if package_weight > max_weight - weight {
+                failed.insert(txid);
+                failures += 1;
+                if failures > MAX_CONSECUTIVE_FAILURES
+                    && weight > max_weight.saturating_sub(NEARLY_FULL_WEIGHT)
+                {
+                    break;
+                }
+                continue;
+            }
+            failures = 0;
+            weight += package_weight;
+            self.mined.extend(package.iter().copied());
+            // the walk starts at the child, a parent always has fewer ancestors than its child
+            package.sort_by_key(|tx| mempool[tx].ancestor_count);
+            // whatever is left of their packages became cheaper or better
+            let descendants: HashSet<Txid> = package
+                .iter()
+                .filter_map(|tx| mempool.get(tx))
+                .flat_map(|entry| entry.spent_by.iter().copied())
+                .filter(|tx| mempool.contains_key(tx) && !self.mined.contains(tx))
+                .collect();
+            for tx in descendants {
+                for descendant in Score::unmined_descendants(mempool, &self.mined, &tx) {
+                    self.heap.push(Candidate {
+                        fee_rate: Score::package_fee_rate(mempool, &self.mined, &descendant),
+                        txid: descendant,
+                    });
+                }
+            }
+            packages.push(Package {
+                fee_rate,
+                txids: package,
+            });
+        }
+
+        // what didn't fit is tried again in the next block
+        let left_out = !failed.is_empty();
+        for txid in failed {
+            self.heap.push(Candidate {
+                fee_rate: Score::package_fee_rate(mempool, &self.mined, &txid),
+                txid,
+            });
+        }
+        (packages, left_out)
+    }
+}
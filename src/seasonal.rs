@@ -0,0 +1,183 @@
+use crate::{
+    calc::{Calc, MIN_RELAY_FEE_RATE},
+    replay::{Replay, Snapshot},
+    storage::Storage,
+};
+use anyhow::{bail, Result};
+use chrono::{Datelike, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// Hours of the week, each with its own profile
+const SLOTS: usize = 7 * 24;
+/// Profiles keep the quantiles at every 1 / `STEPS` of the fee rates
+const STEPS: usize = 20;
+/// Samples an hour of the week needs before its own profile is trusted over the whole week's
+const MIN_SLOT_SAMPLES: usize = 3;
+/// Samples the seasonality needs at all
+const MIN_SAMPLES: usize = 10;
+/// Hours it takes the current mempool's deviation from the season to fade to about a third
+const DECAY_HOURS: f64 = 6.;
+
+/// Next-block fee rates recorded at an hour of the week
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Profile {
+    samples: usize,
+    /// Logarithms of the fee rates at quantiles 0, 1 / `STEPS`, ..., 1
+    quantiles: Vec<f64>,
+}
+
+impl Profile {
+    fn new(mut log_fee_rates: Vec<f64>) -> Self {
+        log_fee_rates.sort_by(f64::total_cmp);
+        let quantiles = match log_fee_rates.len() {
+            0 => Vec::new(),
+            n => (0..=STEPS)
+                .map(|step| log_fee_rates[(step * (n - 1) + STEPS / 2) / STEPS])
+                .collect(),
+        };
+        Profile {
+            samples: log_fee_rates.len(),
+            quantiles,
+        }
+    }
+
+    /// Fee rate at `quantile`, interpolated between the steps
+    fn fee_rate(&self, quantile: f64) -> f64 {
+        let at = quantile.clamp(0., 1.) * STEPS as f64;
+        let (below, share) = (at.floor() as usize, at.fract());
+        let above = (below + 1).min(STEPS);
+        let log = self.quantiles[below] * (1. - share) + self.quantiles[above] * share;
+        log.exp()
+    }
+}
+
+/// Likely next-block fee rates during an hour
+#[derive(Debug, Clone, Serialize)]
+pub struct ForecastHour {
+    /// Unix timestamp the hour starts at
+    pub timestamp: i64,
+    /// sat/vB at the low quantile of the band
+    pub low: f64,
+    pub median: f64,
+    pub high: f64,
+}
+
+/// Time-of-week seasonality of the next-block fee rate: weekends and the hours of the day
+/// differ, so each hour of the week keeps the quantiles of the projected cutoff recorded in it
+/// over the whole history. As a model it scales the current cutoff by how the hour the
+/// transaction is expected to confirm in compares with the present one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Seasonality {
+    /// By hour of the week in UTC, Monday 00:00 first
+    slots: Vec<Profile>,
+    /// All samples, for hours of the week recorded too rarely
+    week: Profile,
+    /// Snapshots the seasonality was fitted to
+    pub samples: usize,
+    /// Days between the first and the last of them
+    pub days: f64,
+}
+
+impl Seasonality {
+    /// Fit to snapshots `every_secs` apart
+    #[tracing::instrument(skip(storage))]
+    pub fn fit(storage: &dyn Storage, every_secs: i64) -> Result<Self> {
+        if every_secs <= 0 {
+            bail!("samples must be at least a second apart");
+        }
+        let mut slots = vec![Vec::new(); SLOTS];
+        let mut week = Vec::new();
+        let (mut first, mut last) = (i64::MAX, i64::MIN);
+        let mut next_sample = i64::MIN;
+        Replay::new(storage)?.walk(i64::MIN, i64::MAX, |snapshot| {
+            if snapshot.timestamp < next_sample {
+                return Ok(());
+            }
+            next_sample = snapshot.timestamp + every_secs;
+            let log_fee_rate = Calc::block_cutoff(snapshot, 1).ln();
+            slots[Self::slot(snapshot.timestamp)].push(log_fee_rate);
+            week.push(log_fee_rate);
+            first = first.min(snapshot.timestamp);
+            last = last.max(snapshot.timestamp);
+            Ok(())
+        })?;
+        if week.len() < MIN_SAMPLES {
+            bail!(
+                "{} snapshots could be sampled, {MIN_SAMPLES} are needed",
+                week.len()
+            );
+        }
+        let samples = week.len();
+        let days = (last - first) as f64 / (24. * 60. * 60.);
+        let slots: Vec<Profile> = slots.into_iter().map(Profile::new).collect();
+        info!(
+            "samples: {samples}, days: {days:.1}, hours_of_week_fitted: {}",
+            slots
+                .iter()
+                .filter(|s| s.samples >= MIN_SLOT_SAMPLES)
+                .count()
+        );
+        Ok(Seasonality {
+            slots,
+            week: Profile::new(week),
+            samples,
+            days,
+        })
+    }
+
+    /// Hour of the week of `timestamp`
+    fn slot(timestamp: i64) -> usize {
+        let at = Utc.timestamp_opt(timestamp, 0).unwrap();
+        at.weekday().num_days_from_monday() as usize * 24 + at.hour() as usize
+    }
+
+    fn profile(&self, timestamp: i64) -> &Profile {
+        match &self.slots[Self::slot(timestamp)] {
+            slot if slot.samples >= MIN_SLOT_SAMPLES => slot,
+            _ => &self.week,
+        }
+    }
+
+    /// Next-block fee rate (sat/vB) recorded at `quantile` in the hour of the week of
+    /// `timestamp`
+    pub fn fee_rate(&self, timestamp: i64, quantile: f64) -> f64 {
+        self.profile(timestamp).fee_rate(quantile)
+    }
+
+    /// Fee rate (sat/vB) for `target` at `confidence` in `snapshot`: its cutoff, scaled from
+    /// the median of its hour of the week to `confidence` of the hour `target` blocks later
+    pub fn estimate(&self, snapshot: &Snapshot, target: u32, confidence: f64) -> f64 {
+        let later = snapshot.timestamp + target as i64 * 600;
+        let ratio = self.fee_rate(later, confidence) / self.fee_rate(snapshot.timestamp, 0.5);
+        (Calc::block_cutoff(snapshot, target) * ratio).max(MIN_RELAY_FEE_RATE)
+    }
+
+    /// The band between quantiles `low` and `high` of the next-block fee rate for each of the
+    /// `hours` after `snapshot`. The first hours lean on how far the snapshot's cutoff is from
+    /// its season, later ones on the season alone.
+    pub fn forecast(
+        &self,
+        snapshot: &Snapshot,
+        hours: u32,
+        low: f64,
+        high: f64,
+    ) -> Vec<ForecastHour> {
+        let now = snapshot.timestamp;
+        let deviation = Calc::block_cutoff(snapshot, 1).ln() - self.fee_rate(now, 0.5).ln();
+        (0..hours)
+            .map(|hour| {
+                let timestamp = now - now.rem_euclid(3600) + hour as i64 * 3600;
+                let level = (deviation * (-(hour as f64) / DECAY_HOURS).exp()).exp();
+                let fee_rate =
+                    |quantile| (self.fee_rate(timestamp, quantile) * level).max(MIN_RELAY_FEE_RATE);
+                ForecastHour {
+                    timestamp,
+                    low: fee_rate(low),
+                    median: fee_rate(0.5),
+                    high: fee_rate(high),
+                }
+            })
+            .collect()
+    }
+}
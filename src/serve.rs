@@ -0,0 +1,1312 @@
+use crate::{
+    access::Access,
+    alert::EstimateWatch,
+    calc::{
+        Calc, Matrix, Preset, Presets, RecommendedFees, TargetEstimate, MATRIX_CONFIDENCES, TARGETS,
+    },
+    cpfp::{ChildFee, Parent, DEFAULT_CHILD_VSIZE},
+    dataset::FileKind,
+    eta::TxEta,
+    flight::Flight,
+    grpc::Grpc,
+    history::{FeeHistory, History},
+    manifest::Manifest,
+    metrics::Health,
+    model::Trained,
+    node::Node,
+    openapi::{ApiDoc, SWAGGER_UI},
+    position::QueuePosition,
+    price::{FiatCosts, PriceFeed},
+    replay::Replay,
+    storage::Storage,
+    sync::{FileFilter, PeerSync, RemoteFile, CHECKSUM_HEADER},
+    systemd::Notifier,
+    tls::Tls,
+    unit::FeeUnit,
+    web::DASHBOARD,
+};
+use anyhow::Result;
+use axum::{
+    body::{boxed, Body, Bytes, Empty, Full},
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use bitcoin::{
+    hashes::{sha256, Hash},
+    Txid,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use tracing::{debug, error, info, warn};
+
+/// How often `/v1/stream` looks for a new snapshot
+const STREAM_POLL: Duration = Duration::from_secs(2);
+/// `/readyz` fails when the latest snapshot is older, nothing records the dataset anymore
+const MAX_DATASET_AGE_SECS: i64 = 30 * 60;
+/// Estimates kept at most with `cache_ttl`
+const MAX_CACHED_ESTIMATES: usize = 1024;
+/// Hex digits of the SHA-256 in an ETag
+const ETAG_HEX_LEN: usize = 16;
+/// Answers kept at most per version of the dataset
+const MAX_CACHED_RESPONSES: usize = 1024;
+/// Transactions `/v1/targets` excludes at most per request
+const MAX_EXCLUDED: usize = 1000;
+
+/// Target, confidence bits and generation of a cached estimate
+type EstimateKey = (u32, u64, u64);
+
+pub(crate) struct AppState {
+    pub(crate) storage: Box<dyn Storage>,
+    /// Answers `/v1/fee` from the latest snapshot instead of the last hour when loaded
+    model: RwLock<Option<Trained>>,
+    /// Answered by `/v1/fee/:preset`
+    presets: RwLock<Presets>,
+    price: Option<PriceFeed>,
+    /// Looks up the prevouts of transactions posted to `/v1/eta` and the parents asked about
+    /// at `/v1/cpfp`
+    node: Option<Box<dyn Node>>,
+    /// `/openapi.json`, rendered once
+    openapi: String,
+    /// Estimates by target, confidence bits and generation, reused for `cache_ttl` and dropped
+    /// when the model is reloaded
+    cache: Mutex<HashMap<EstimateKey, (Instant, f64)>>,
+    cache_ttl: Option<Duration>,
+    /// Bumped whenever the model and presets are reloaded, part of every cache key
+    generation: AtomicU64,
+    /// Answers of the routes computed from the dataset by request, see [`Serve::cached`]
+    responses: Mutex<HashMap<String, CachedResponse>>,
+}
+
+/// An answer and the version of the dataset and model it was computed from
+struct CachedResponse {
+    /// Latest snapshot and generation
+    version: (Option<i64>, u64),
+    content_type: Option<HeaderValue>,
+    body: Bytes,
+}
+
+/// What `serve` answers with besides the dataset
+#[derive(Default)]
+pub struct ServeOptions {
+    /// Answers `/v1/fee` from the latest snapshot instead of the last hour
+    pub model: Option<Trained>,
+    /// Answered by `/v1/fee/:preset`
+    pub presets: Presets,
+    /// Adds what common transactions cost to `/v1/fee`
+    pub price: Option<PriceFeed>,
+    /// Looks up the prevouts of transactions posted to `/v1/eta`, which have to come with
+    /// their fee without one, and the parents asked about at `/v1/cpfp`
+    pub node: Option<Box<dyn Node>>,
+    /// Serve the recorded files themselves too, for `wtf sync`
+    pub files: bool,
+    /// Tell systemd once listening and feed its watchdog, unless a recorder alongside does
+    pub notify: bool,
+    /// API keys and rate limit, anyone may ask as often as they like by default
+    pub access: Access,
+    /// Serve HTTPS with this certificate instead of plain HTTP
+    pub tls: Option<Tls>,
+    /// Also answer gRPC on this address
+    pub grpc: Option<SocketAddr>,
+    /// Also serve the dataset over Arrow Flight on this address
+    pub flight: Option<SocketAddr>,
+    /// Switch to the model and presets arriving here, e.g. from a config reloaded on SIGHUP
+    pub reloads: Option<mpsc::Receiver<Reloaded>>,
+    /// Answer the same estimate for this long instead of computing it for every request, best
+    /// no longer than snapshots are apart. Not cached by default or when zero. Whole answers
+    /// are kept until the next snapshot regardless, see [`Serve::cached`].
+    pub cache_ttl: Option<Duration>,
+    /// Reports moves of the `/v1/fee` default estimate, 1 block at 0.95, between snapshots
+    pub estimate_watch: Option<EstimateWatch>,
+}
+
+/// What `serve` answers with that may change while it runs
+pub struct Reloaded {
+    pub model: Option<Trained>,
+    pub presets: Presets,
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct FeeQuery {
+    /// Probability of confirming within the target, in (0, 1], 0.95 by default
+    #[serde(default = "default_confidence")]
+    confidence: f64,
+    /// Blocks to confirm within, 1 by default
+    #[serde(default = "default_target")]
+    target: u32,
+    /// Also answer the fee rate in this unit
+    unit: Option<FeeUnit>,
+}
+
+/// `unit` of the endpoints taking nothing else
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct UnitQuery {
+    unit: Option<FeeUnit>,
+}
+
+fn default_confidence() -> f64 {
+    0.95
+}
+
+fn default_target() -> u32 {
+    1
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct StreamQuery {
+    /// Probability of confirming within the target, in (0, 1], 0.95 by default
+    #[serde(default = "default_confidence")]
+    confidence: f64,
+    /// Blocks to confirm within, 1 by default
+    #[serde(default = "default_target")]
+    target: u32,
+    /// Also send the estimate this many seconds after the last one when no snapshot came in
+    every: Option<u64>,
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct TargetsQuery {
+    /// Probability of confirming within the target, in (0, 1], 0.95 by default
+    #[serde(default = "default_confidence")]
+    confidence: f64,
+    /// Only this target instead of all of [`TARGETS`]
+    target: Option<u32>,
+    /// Comma separated txids left out of the projected blocks, like the caller's own
+    /// transactions it is about to replace
+    exclude: Option<String>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct TargetsResponse {
+    confidence: f64,
+    estimates: Vec<TargetEstimate>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct FeeResponse {
+    confidence: f64,
+    target: u32,
+    fee_rate_sat_vb: f64,
+    /// In the `unit` asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fee_rate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<FeeUnit>)]
+    unit: Option<&'static str>,
+    /// With a price feed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    costs: Option<FiatCosts>,
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ConfidenceQuery {
+    /// Probability of confirming within the target, in (0, 1], 0.95 by default
+    #[serde(default = "default_confidence")]
+    confidence: f64,
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct PositionQuery {
+    /// sat/vB
+    fee_rate: f64,
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct HistoryQuery {
+    /// Unix timestamp, a day before `to` by default
+    from: Option<i64>,
+    /// Unix timestamp, the latest snapshot by default
+    to: Option<i64>,
+    /// Bucket size like `30s`, `5m`, `1h` or `1d`, 5 minutes by default
+    #[serde(default = "default_resolution")]
+    resolution: String,
+}
+
+fn default_resolution() -> String {
+    "5m".to_string()
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct CpfpQuery {
+    /// Of the unconfirmed parent
+    txid: String,
+    /// Probability of confirming within the target, in (0, 1], 0.95 by default
+    #[serde(default = "default_confidence")]
+    confidence: f64,
+    /// Blocks to confirm within, 1 by default
+    #[serde(default = "default_target")]
+    target: u32,
+    /// Virtual size of the child once signed, 110 by default
+    child_vsize: Option<u64>,
+}
+
+/// A transaction to tell the confirmation of
+#[derive(Deserialize, utoipa::ToSchema)]
+pub(crate) struct EtaRequest {
+    /// The signed transaction as hex
+    rawtx: String,
+    /// Probability of confirming within the ETA, in (0, 1], 0.95 by default
+    #[serde(default = "default_confidence")]
+    confidence: f64,
+    /// What it pays in sat, looked up from its prevouts by the node when omitted
+    fee: Option<u64>,
+}
+
+/// Targets Esplora's `/fee-estimates` answers for, in blocks
+const ESPLORA_TARGETS: [u32; 28] = [
+    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 144,
+    504, 1008,
+];
+
+/// LND's `--fee.url` answer, sat/kvB
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct LndFees {
+    fee_by_block_target: BTreeMap<u32, u64>,
+    min_relay_feerate: u64,
+}
+
+/// Pushed by `/v1/stream`
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct StreamEstimate {
+    /// Unix timestamp of the latest snapshot
+    pub(crate) timestamp: i64,
+    pub(crate) confidence: f64,
+    pub(crate) target: u32,
+    pub(crate) fee_rate_sat_vb: f64,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct ErrorResponse {
+    error: String,
+}
+
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(ErrorResponse { error: self.1 })).into_response()
+    }
+}
+
+impl AppState {
+    pub(crate) fn estimate(&self, confidence: f64, target: u32) -> Result<f64> {
+        let Some(ttl) = self.cache_ttl else {
+            return self.compute(confidence, target);
+        };
+        let key = (
+            target,
+            confidence.to_bits(),
+            self.generation.load(Ordering::Acquire),
+        );
+        if let Some((at, fee_rate)) = self.cache.lock().unwrap().get(&key) {
+            if at.elapsed() < ttl {
+                return Ok(*fee_rate);
+            }
+        }
+        let fee_rate = self.compute(confidence, target)?;
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (at, _)| at.elapsed() < ttl);
+        // confidences come from the query, don't let odd ones pile up
+        if cache.len() < MAX_CACHED_ESTIMATES {
+            cache.insert(key, (Instant::now(), fee_rate));
+        }
+        Ok(fee_rate)
+    }
+
+    fn compute(&self, confidence: f64, target: u32) -> Result<f64> {
+        match &*self.model.read().unwrap() {
+            Some(model) => {
+                let snapshot = Replay::new(self.storage.as_ref())?.at(i64::MAX)?;
+                model.estimate(&snapshot, target, confidence)
+            }
+            None => Calc::calc(self.storage.as_ref(), confidence, target),
+        }
+    }
+}
+
+pub struct Serve;
+
+impl Serve {
+    #[tracing::instrument(skip(storage, options))]
+    pub async fn serve(
+        storage: Box<dyn Storage>,
+        listen: SocketAddr,
+        options: ServeOptions,
+    ) -> Result<()> {
+        let ServeOptions {
+            model,
+            presets,
+            price,
+            node,
+            files,
+            notify,
+            access,
+            tls,
+            grpc,
+            flight,
+            reloads,
+            cache_ttl,
+            estimate_watch,
+        } = options;
+        let access = Arc::new(access);
+        let openapi = ApiDoc::build(files, access.requires_key()).to_json()?;
+        let state = Arc::new(AppState {
+            storage,
+            model: RwLock::new(model),
+            presets: RwLock::new(presets),
+            price,
+            node,
+            openapi,
+            cache: Mutex::new(HashMap::new()),
+            cache_ttl: cache_ttl.filter(|ttl| !ttl.is_zero()),
+            generation: AtomicU64::new(0),
+            responses: Mutex::new(HashMap::new()),
+        });
+        if let Some(mut reloads) = reloads {
+            let state = state.clone();
+            tokio::spawn(async move {
+                while let Some(reloaded) = reloads.recv().await {
+                    *state.model.write().unwrap() = reloaded.model;
+                    *state.presets.write().unwrap() = reloaded.presets;
+                    state.generation.fetch_add(1, Ordering::AcqRel);
+                    state.cache.lock().unwrap().clear();
+                    state.responses.lock().unwrap().clear();
+                    info!("reloaded the model and presets");
+                }
+            });
+        }
+        if let Some(watch) = estimate_watch {
+            tokio::spawn(Self::watch_estimate(state.clone(), watch));
+        }
+        if let Some(grpc) = grpc {
+            let (state, access) = (state.clone(), access.clone());
+            tokio::spawn(async move {
+                if let Err(e) = Grpc::serve(state, access, grpc).await {
+                    error!("serving gRPC failed: {e:#}");
+                }
+            });
+        }
+        if let Some(flight) = flight {
+            let (state, access) = (state.clone(), access.clone());
+            tokio::spawn(async move {
+                if let Err(e) = Flight::serve(state, access, flight).await {
+                    error!("serving Arrow Flight failed: {e:#}");
+                }
+            });
+        }
+        let mut app = Router::new()
+            .route("/v1/fee", get(Self::fee))
+            .route("/v1/fee/:preset", get(Self::preset))
+            .route("/v1/targets", get(Self::targets))
+            .route("/v1/matrix", get(Self::matrix))
+            .route("/v1/position", get(Self::position))
+            .route("/v1/history", get(Self::history))
+            .route("/api/v1/fees/recommended", get(Self::recommended))
+            .route("/fee-estimates", get(Self::fee_estimates))
+            .route("/api/fee-estimates", get(Self::fee_estimates))
+            .route("/lnd/fee-estimates", get(Self::lnd_fees))
+            .route("/openapi.json", get(Self::openapi))
+            // the routes above answer from the dataset alone
+            .route_layer(middleware::from_fn_with_state(state.clone(), Self::cached))
+            // asks the node, whose mempool changes between snapshots
+            .route("/v1/cpfp", get(Self::cpfp))
+            // only the routes above, the stream and probes aren't worth tagging
+            .route_layer(middleware::from_fn(Self::etag))
+            .route("/v1/stream", get(Self::stream))
+            .route("/v1/eta", post(Self::eta))
+            .route("/healthz", get(Self::healthz))
+            .route("/readyz", get(Self::readyz))
+            .route("/docs", get(Self::docs))
+            .route("/", get(Self::dashboard));
+        if files {
+            app = app
+                .route("/v1/files", get(Self::files))
+                .route("/v1/files/:height/:timestamp/:kind", get(Self::file));
+        }
+        if !access.is_open() {
+            app = app.layer(middleware::from_fn_with_state(access, Access::middleware));
+        }
+        let app = app.with_state(state);
+
+        let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+        match tls {
+            Some(tls) => {
+                let incoming = tls.bind(listen).await?;
+                Self::listening(&format!("https://{listen}"), notify);
+                axum::Server::builder(incoming).serve(make_service).await?;
+            }
+            None => {
+                let server = axum::Server::try_bind(&listen)?;
+                Self::listening(&format!("http://{listen}"), notify);
+                server.serve(make_service).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Bound to `url`, tell systemd if asked to and keep its watchdog fed from now on
+    fn listening(url: &str, notify: bool) {
+        info!("listening on {url}");
+        if notify {
+            let mut notifier = Notifier::from_env();
+            notifier.ready(&format!("listening on {url}"));
+            if let Some(every) = notifier.watchdog_interval() {
+                tokio::spawn(async move {
+                    let mut ticks = tokio::time::interval(every / 2);
+                    loop {
+                        ticks.tick().await;
+                        notifier.watchdog();
+                    }
+                });
+            }
+        }
+    }
+
+    /// Answer from the body computed for the same request, latest snapshot and model if there
+    /// is one, and 304 Not Modified before computing anything when the client has it already.
+    /// The ETag is a hash of the request and that version rather than of the body. Fiat costs
+    /// are as of the first request after a snapshot.
+    async fn cached(
+        State(state): State<Arc<AppState>>,
+        request: Request<Body>,
+        next: Next<Body>,
+    ) -> Response {
+        let reading = state.clone();
+        let latest = tokio::task::spawn_blocking(move || {
+            Replay::new(reading.storage.as_ref()).map(|replay| replay.latest())
+        })
+        .await;
+        let Ok(Ok(latest)) = latest else {
+            // the handler reports what is wrong with the dataset
+            return next.run(request).await;
+        };
+        let version = (latest, state.generation.load(Ordering::Acquire));
+        let key = Self::cache_key(request.uri());
+        let hash = sha256::Hash::hash(format!("{key} {version:?}").as_bytes()).to_string();
+        let tag = format!("\"{}\"", &hash[..ETAG_HEX_LEN]);
+        let etag = HeaderValue::from_str(&tag).unwrap();
+        if Self::if_none_match(request.headers(), &tag) {
+            return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+        }
+        let cached = state
+            .responses
+            .lock()
+            .unwrap()
+            .get(&key)
+            .filter(|cached| cached.version == version)
+            .map(|cached| (cached.content_type.clone(), cached.body.clone()));
+        if let Some((content_type, body)) = cached {
+            let mut response = Response::new(boxed(Full::from(body)));
+            response.headers_mut().insert(header::ETAG, etag);
+            if let Some(content_type) = content_type {
+                response
+                    .headers_mut()
+                    .insert(header::CONTENT_TYPE, content_type);
+            }
+            return response;
+        }
+
+        let response = next.run(request).await;
+        if response.status() != StatusCode::OK {
+            return response;
+        }
+        let (mut parts, body) = response.into_parts();
+        let bytes = match hyper::body::to_bytes(body).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                return ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
+        };
+        parts.headers.insert(header::ETAG, etag);
+        let mut responses = state.responses.lock().unwrap();
+        responses.retain(|_, cached| cached.version == version);
+        // queries are the client's to vary, don't let odd ones pile up
+        if responses.len() < MAX_CACHED_RESPONSES {
+            responses.insert(
+                key,
+                CachedResponse {
+                    version,
+                    content_type: parts.headers.get(header::CONTENT_TYPE).cloned(),
+                    body: bytes.clone(),
+                },
+            );
+        }
+        Response::from_parts(parts, boxed(Full::from(bytes)))
+    }
+
+    /// The request an answer is the same for, whichever API key asks
+    fn cache_key(uri: &Uri) -> String {
+        let query: Vec<&str> = uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty() && !pair.starts_with("api_key="))
+            .collect();
+        format!("{}?{}", uri.path(), query.join("&"))
+    }
+
+    /// `If-None-Match` lists `tag`, or any
+    fn if_none_match(headers: &HeaderMap, tag: &str) -> bool {
+        headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| {
+                value
+                    .split(',')
+                    .map(|candidate| candidate.trim().trim_start_matches("W/"))
+                    .any(|candidate| candidate == tag || candidate == "*")
+            })
+    }
+
+    /// Tag successful answers not tagged by [`Serve::cached`] with a hash of their body, and
+    /// answer 304 Not Modified without one when the client already has it
+    async fn etag(request: Request<Body>, next: Next<Body>) -> Response {
+        let headers = request.headers().clone();
+        let response = next.run(request).await;
+        if response.status() != StatusCode::OK || response.headers().contains_key(header::ETAG) {
+            return response;
+        }
+        let (mut parts, body) = response.into_parts();
+        let bytes = match hyper::body::to_bytes(body).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                return ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
+        };
+        let hash = sha256::Hash::hash(&bytes).to_string();
+        let tag = format!("\"{}\"", &hash[..ETAG_HEX_LEN]);
+        parts
+            .headers
+            .insert(header::ETAG, HeaderValue::from_str(&tag).unwrap());
+        if Self::if_none_match(&headers, &tag) {
+            parts.status = StatusCode::NOT_MODIFIED;
+            parts.headers.remove(header::CONTENT_LENGTH);
+            return Response::from_parts(parts, boxed(Empty::new()));
+        }
+        Response::from_parts(parts, boxed(Full::from(bytes)))
+    }
+
+    async fn fee(
+        State(state): State<Arc<AppState>>,
+        Query(query): Query<FeeQuery>,
+    ) -> Result<Json<FeeResponse>, ApiError> {
+        if !(query.confidence > 0. && query.confidence <= 1.) || query.target == 0 {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                "confidence must be in (0, 1] and target at least 1".to_string(),
+            ));
+        }
+
+        Self::estimate(state, query.confidence, query.target, query.unit).await
+    }
+
+    /// `/v1/fee` at the confidence and target of a preset
+    async fn preset(
+        State(state): State<Arc<AppState>>,
+        Path(name): Path<String>,
+        Query(query): Query<UnitQuery>,
+    ) -> Result<Json<FeeResponse>, ApiError> {
+        let preset = Preset::find(&state.presets.read().unwrap(), &name)
+            .map_err(|e| ApiError(StatusCode::NOT_FOUND, e.to_string()))?;
+        Self::estimate(state, preset.confidence, preset.target, query.unit).await
+    }
+
+    async fn estimate(
+        state: Arc<AppState>,
+        confidence: f64,
+        target: u32,
+        unit: Option<FeeUnit>,
+    ) -> Result<Json<FeeResponse>, ApiError> {
+        // estimation reads parquet files, keep it off the async workers
+        let estimating = state.clone();
+        let estimate = tokio::task::spawn_blocking(move || estimating.estimate(confidence, target))
+            .await
+            .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+        // the estimate is still worth answering when the price feed is down
+        let costs = match &state.price {
+            Some(price) => match price.costs(estimate).await {
+                Ok(costs) => Some(costs),
+                Err(e) => {
+                    warn!("price feed failed: {e:#}");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        Ok(Json(FeeResponse {
+            confidence,
+            target,
+            fee_rate_sat_vb: estimate,
+            fee_rate: unit.map(|unit| unit.convert(estimate)),
+            unit: unit.map(FeeUnit::as_str),
+            costs,
+        }))
+    }
+
+    /// Push the `/v1/fee` estimate whenever a new snapshot is recorded, over WebSocket when the
+    /// client asks for an upgrade and as server-sent events otherwise
+    async fn stream(
+        State(state): State<Arc<AppState>>,
+        Query(query): Query<StreamQuery>,
+        upgrade: Option<WebSocketUpgrade>,
+    ) -> Result<Response, ApiError> {
+        if !(query.confidence > 0. && query.confidence <= 1.) || query.target == 0 {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                "confidence must be in (0, 1] and target at least 1".to_string(),
+            ));
+        }
+
+        let every = query.every.map(Duration::from_secs);
+        let receiver = Self::estimates(state, query.confidence, query.target, every);
+        match upgrade {
+            Some(upgrade) => Ok(upgrade.on_upgrade(|socket| Self::send(socket, receiver))),
+            None => {
+                let events = ReceiverStream::new(receiver).map(|message| {
+                    Ok::<_, Infallible>(match message {
+                        Ok(estimate) => {
+                            Event::default().data(serde_json::to_string(&estimate).unwrap())
+                        }
+                        Err(error) => Event::default()
+                            .event("error")
+                            .data(serde_json::to_string(&ErrorResponse { error }).unwrap()),
+                    })
+                });
+                Ok(Sse::new(events)
+                    .keep_alive(KeepAlive::default())
+                    .into_response())
+            }
+        }
+    }
+
+    /// The estimate whenever a new snapshot is recorded, and after `every` without one, with
+    /// errors on the `Err` side, until the receiver is dropped
+    pub(crate) fn estimates(
+        state: Arc<AppState>,
+        confidence: f64,
+        target: u32,
+        every: Option<Duration>,
+    ) -> mpsc::Receiver<Result<StreamEstimate, String>> {
+        let (sender, receiver) = mpsc::channel(1);
+        tokio::spawn(Self::produce(state, confidence, target, every, sender));
+        receiver
+    }
+
+    async fn produce(
+        state: Arc<AppState>,
+        confidence: f64,
+        target: u32,
+        every: Option<Duration>,
+        sender: mpsc::Sender<Result<StreamEstimate, String>>,
+    ) {
+        let mut poll = tokio::time::interval(STREAM_POLL);
+        let mut last: Option<(i64, Instant)> = None;
+        let mut last_error = None;
+        loop {
+            poll.tick().await;
+            let due = last.map(|(timestamp, sent)| {
+                (
+                    timestamp,
+                    every.is_some_and(|every| sent.elapsed() >= every),
+                )
+            });
+            let state = state.clone();
+            // estimation reads parquet files, keep it off the async workers
+            let result = tokio::task::spawn_blocking(move || -> Result<Option<StreamEstimate>> {
+                let Some(timestamp) = Replay::new(state.storage.as_ref())?.latest() else {
+                    return Ok(None);
+                };
+                if due.is_some_and(|(last, timer)| last == timestamp && !timer) {
+                    return Ok(None);
+                }
+                Ok(Some(StreamEstimate {
+                    timestamp,
+                    confidence,
+                    target,
+                    fee_rate_sat_vb: state.estimate(confidence, target)?,
+                }))
+            })
+            .await;
+
+            let error = match result {
+                Ok(Ok(None)) => continue,
+                Ok(Ok(Some(estimate))) => {
+                    last = Some((estimate.timestamp, Instant::now()));
+                    last_error = None;
+                    if sender.send(Ok(estimate)).await.is_err() {
+                        debug!("stream client went away");
+                        return;
+                    }
+                    continue;
+                }
+                Ok(Err(e)) => e.to_string(),
+                Err(e) => e.to_string(),
+            };
+            // the same error again every poll tells the client nothing new
+            if last_error.as_ref() == Some(&error) {
+                continue;
+            }
+            last_error = Some(error.clone());
+            if sender.send(Err(error)).await.is_err() {
+                debug!("stream client went away");
+                return;
+            }
+        }
+    }
+
+    /// Feed `watch` the `/v1/fee` default estimate of every new snapshot, with the fee rate
+    /// and weight of the transactions added and removed since the snapshot before
+    async fn watch_estimate(state: Arc<AppState>, mut watch: EstimateWatch) {
+        let mut estimates =
+            Self::estimates(state.clone(), default_confidence(), default_target(), None);
+        // fee rate and weight by txid
+        let mut previous: HashMap<String, (f64, f64)> = HashMap::new();
+        while let Some(estimate) = estimates.recv().await {
+            let estimate = match estimate {
+                Ok(estimate) => estimate,
+                Err(e) => {
+                    warn!("watching the estimate failed: {e}");
+                    continue;
+                }
+            };
+            let reading = state.clone();
+            let snapshot = tokio::task::spawn_blocking(move || {
+                Replay::new(reading.storage.as_ref())?.at(estimate.timestamp)
+            })
+            .await;
+            let snapshot = match snapshot {
+                Ok(Ok(snapshot)) => snapshot,
+                Ok(Err(e)) => {
+                    warn!("reading the snapshot of the estimate failed: {e:#}");
+                    continue;
+                }
+                Err(e) => {
+                    warn!("reading the snapshot of the estimate failed: {e}");
+                    continue;
+                }
+            };
+            let current: HashMap<String, (f64, f64)> = snapshot
+                .transactions
+                .iter()
+                .map(|(txid, tx)| (txid.clone(), (tx.fee_rate_sat_vb(), tx.weight)))
+                .collect();
+            let added = current
+                .iter()
+                .filter(|(txid, _)| !previous.contains_key(*txid))
+                .map(|(_, (fee_rate, weight))| (*fee_rate, *weight));
+            let removed = previous
+                .iter()
+                .filter(|(txid, _)| !current.contains_key(*txid))
+                .map(|(_, (fee_rate, weight))| (*fee_rate, -*weight));
+            let change = watch.observe(estimate.fee_rate_sat_vb, added.chain(removed));
+            previous = current;
+            if let Some(change) = change {
+                watch.notify(&change).await;
+            }
+        }
+    }
+
+    async fn send(
+        mut socket: WebSocket,
+        mut receiver: mpsc::Receiver<Result<StreamEstimate, String>>,
+    ) {
+        while let Some(message) = receiver.recv().await {
+            let json = match message {
+                Ok(estimate) => serde_json::to_string(&estimate),
+                Err(error) => serde_json::to_string(&ErrorResponse { error }),
+            }
+            .unwrap();
+            if socket.send(Message::Text(json)).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Alive while the dataset can be read, ready while its latest snapshot is recent
+    async fn health(state: Arc<AppState>) -> Health {
+        let latest = tokio::task::spawn_blocking(move || {
+            Replay::new(state.storage.as_ref()).map(|replay| replay.latest())
+        })
+        .await;
+        let now = chrono::Utc::now().timestamp();
+        let (alive, dataset_age_secs) = match latest {
+            Ok(Ok(latest)) => (true, latest.map(|latest| now - latest)),
+            Ok(Err(e)) => {
+                warn!("reading the dataset failed: {e:#}");
+                (false, None)
+            }
+            Err(_) => (false, None),
+        };
+        Health {
+            alive,
+            ready: dataset_age_secs.is_some_and(|age| age <= MAX_DATASET_AGE_SECS),
+            node_answered_secs_ago: None,
+            last_snapshot_secs_ago: None,
+            dataset_age_secs,
+            max_age_secs: MAX_DATASET_AGE_SECS,
+        }
+    }
+
+    async fn docs() -> Html<&'static str> {
+        Html(SWAGGER_UI)
+    }
+
+    async fn dashboard() -> Html<&'static str> {
+        Html(DASHBOARD)
+    }
+
+    async fn openapi(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+        (
+            [(header::CONTENT_TYPE, "application/json")],
+            state.openapi.clone(),
+        )
+    }
+
+    async fn healthz(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+        Self::health(state).await.healthz()
+    }
+
+    async fn readyz(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+        Self::health(state).await.readyz()
+    }
+
+    /// Fee rates by confidence and time horizon, in sat/vB unless the query asks for a unit
+    async fn matrix(
+        State(state): State<Arc<AppState>>,
+        Query(query): Query<UnitQuery>,
+    ) -> Result<Json<Matrix>, ApiError> {
+        let matrix = tokio::task::spawn_blocking(move || {
+            Calc::matrix(state.storage.as_ref(), &MATRIX_CONFIDENCES)
+        })
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+        Ok(Json(matrix.convert(query.unit.unwrap_or_default())))
+    }
+
+    /// The recorded files within the query's range
+    async fn files(
+        State(state): State<Arc<AppState>>,
+        Query(filter): Query<FileFilter>,
+    ) -> Result<Json<Vec<RemoteFile>>, ApiError> {
+        let files = tokio::task::spawn_blocking(move || -> Result<Vec<RemoteFile>> {
+            let storage = state.storage.as_ref();
+            let manifest = storage.manifest()?;
+            Ok(storage
+                .list()?
+                .into_iter()
+                .filter(|file| filter.matches(file))
+                .map(|file| {
+                    let entry = manifest.as_ref().and_then(|manifest| manifest.get(&file));
+                    RemoteFile {
+                        height: file.height,
+                        timestamp: file.timestamp,
+                        kind: file.kind.as_str().to_string(),
+                        size: entry
+                            .map(|entry| entry.bytes)
+                            .or_else(|| storage.size(&file)),
+                        rows: entry.map(|entry| entry.rows),
+                        sha256: entry.map(|entry| entry.sha256.clone()),
+                    }
+                })
+                .collect())
+        })
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+        Ok(Json(files))
+    }
+
+    /// One recorded file as parquet, with its checksum in [`CHECKSUM_HEADER`]. A file stored
+    /// on its own is sent as it is, for the peer to compare with the checksum `/v1/files`
+    /// listed from the manifest.
+    async fn file(
+        State(state): State<Arc<AppState>>,
+        Path((height, timestamp, kind)): Path<(u64, i64, String)>,
+    ) -> Result<Response, ApiError> {
+        let Some(kind) = FileKind::parse(&kind) else {
+            return Err(ApiError(
+                StatusCode::NOT_FOUND,
+                format!("no files of kind {kind}"),
+            ));
+        };
+        let encoded =
+            tokio::task::spawn_blocking(move || -> Result<Option<(Vec<u8>, String)>> {
+                let storage = state.storage.as_ref();
+                let Some(file) = storage.list()?.into_iter().find(|file| {
+                    (file.height, file.timestamp, file.kind) == (height, timestamp, kind)
+                }) else {
+                    return Ok(None);
+                };
+                if storage.size(&file).is_some() {
+                    let bytes = std::fs::read(&file.path)?;
+                    let checksum = Manifest::checksum(&bytes);
+                    return Ok(Some((bytes, checksum)));
+                }
+                PeerSync::encode(&mut storage.read(&file)?).map(Some)
+            })
+            .await
+            .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+        let Some((bytes, checksum)) = encoded else {
+            return Err(ApiError(
+                StatusCode::NOT_FOUND,
+                format!("no {} file at {height}, {timestamp}", kind.as_str()),
+            ));
+        };
+        Ok((
+            [
+                (
+                    header::CONTENT_TYPE,
+                    "application/vnd.apache.parquet".to_string(),
+                ),
+                (header::HeaderName::from_static(CHECKSUM_HEADER), checksum),
+            ],
+            bytes,
+        )
+            .into_response())
+    }
+
+    /// Virtual size and projected blocks at or above `fee_rate` in the latest snapshot
+    async fn position(
+        State(state): State<Arc<AppState>>,
+        Query(query): Query<PositionQuery>,
+    ) -> Result<Json<QueuePosition>, ApiError> {
+        if !query.fee_rate.is_finite() || query.fee_rate < 0. {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                "fee_rate must be at least 0".to_string(),
+            ));
+        }
+
+        let fee_rate = query.fee_rate;
+        let position = tokio::task::spawn_blocking(move || {
+            QueuePosition::from_recorded(state.storage.as_ref(), fee_rate)
+        })
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+        Ok(Json(position))
+    }
+
+    /// Fee rate percentiles and mempool depth by band over time, a bucket per `resolution`
+    async fn history(
+        State(state): State<Arc<AppState>>,
+        Query(query): Query<HistoryQuery>,
+    ) -> Result<Json<FeeHistory>, ApiError> {
+        let resolution = History::parse_resolution(&query.resolution)
+            .map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        let history = tokio::task::spawn_blocking(move || -> Result<Result<FeeHistory, String>> {
+            let storage = state.storage.as_ref();
+            let to = match query.to {
+                Some(to) => to,
+                None => match Replay::new(storage)?.latest() {
+                    Some(latest) => latest,
+                    None => anyhow::bail!("no snapshot recorded"),
+                },
+            };
+            let from = query.from.unwrap_or(to - 24 * 60 * 60);
+            // a range too long for the resolution is the client's to fix
+            if let Err(e) = History::check(from, to, resolution) {
+                return Ok(Err(e.to_string()));
+            }
+            History::buckets(storage, from, to, resolution).map(Ok)
+        })
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, e))?;
+        Ok(Json(history))
+    }
+
+    /// The fee a child pays for its unconfirmed parent to confirm within the target, the
+    /// parent looked up by the node or in the latest snapshot without one
+    async fn cpfp(
+        State(state): State<Arc<AppState>>,
+        Query(query): Query<CpfpQuery>,
+    ) -> Result<Json<ChildFee>, ApiError> {
+        let child_vsize = query.child_vsize.unwrap_or(DEFAULT_CHILD_VSIZE);
+        if !(query.confidence > 0. && query.confidence <= 1.)
+            || query.target == 0
+            || child_vsize == 0
+        {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                "confidence must be in (0, 1], target and child_vsize at least 1".to_string(),
+            ));
+        }
+        let txid: Txid = query.txid.parse().map_err(|_| {
+            ApiError(
+                StatusCode::BAD_REQUEST,
+                format!("{} isn't a txid", query.txid),
+            )
+        })?;
+
+        let parent = match &state.node {
+            Some(node) => Parent::from_node(node.as_ref(), txid).await,
+            None => {
+                let state = state.clone();
+                tokio::task::spawn_blocking(move || {
+                    Parent::from_recorded(state.storage.as_ref(), txid)
+                })
+                .await
+                .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            }
+        }
+        .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+        let Some(parent) = parent else {
+            return Err(ApiError(
+                StatusCode::NOT_FOUND,
+                format!("{txid} is not in the mempool"),
+            ));
+        };
+
+        let CpfpQuery {
+            confidence, target, ..
+        } = query;
+        let child = tokio::task::spawn_blocking(move || {
+            let fee_rate = state.estimate(confidence, target)?;
+            ChildFee::new(&parent, child_vsize, target, confidence, fee_rate)
+        })
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+        Ok(Json(child))
+    }
+
+    /// Projected block and confirmation ETA of a raw transaction under the served model
+    async fn eta(
+        State(state): State<Arc<AppState>>,
+        Json(request): Json<EtaRequest>,
+    ) -> Result<Json<TxEta>, ApiError> {
+        let confidence = request.confidence;
+        if !(confidence > 0. && confidence <= 1.) {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                "confidence must be in (0, 1]".to_string(),
+            ));
+        }
+        let tx = TxEta::decode(&request.rawtx)
+            .map_err(|e| ApiError(StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+        let fee = match (request.fee, &state.node) {
+            (Some(fee), _) => fee,
+            (None, Some(node)) => TxEta::fee(node.as_ref(), &tx)
+                .await
+                .map_err(|e| ApiError(StatusCode::UNPROCESSABLE_ENTITY, format!("{e:#}")))?,
+            (None, None) => {
+                return Err(ApiError(
+                    StatusCode::BAD_REQUEST,
+                    "no node to look up prevouts, the fee is required".to_string(),
+                ))
+            }
+        };
+
+        let eta = tokio::task::spawn_blocking(move || {
+            TxEta::new(state.storage.as_ref(), &tx, fee, confidence, |target| {
+                state.estimate(confidence, target)
+            })
+        })
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+        Ok(Json(eta))
+    }
+
+    /// Fee rates confirming within each target, from wait times and projected blocks
+    async fn targets(
+        State(state): State<Arc<AppState>>,
+        Query(query): Query<TargetsQuery>,
+    ) -> Result<Json<TargetsResponse>, ApiError> {
+        if !(query.confidence > 0. && query.confidence <= 1.) || query.target == Some(0) {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                "confidence must be in (0, 1] and target at least 1".to_string(),
+            ));
+        }
+
+        let TargetsQuery {
+            confidence,
+            target,
+            exclude,
+        } = query;
+        let exclude = match exclude {
+            Some(exclude) => Self::parse_excluded(&exclude)
+                .map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?,
+            None => HashSet::new(),
+        };
+        let targets = target.map_or_else(|| TARGETS.to_vec(), |target| vec![target]);
+        let estimates = tokio::task::spawn_blocking(move || {
+            Calc::targets_excluding(state.storage.as_ref(), &targets, &[confidence], &exclude)
+        })
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+
+        Ok(Json(TargetsResponse {
+            confidence,
+            estimates,
+        }))
+    }
+
+    /// The txids of a comma separated `exclude`
+    fn parse_excluded(exclude: &str) -> Result<HashSet<Txid>> {
+        let exclude = exclude
+            .split(',')
+            .map(str::trim)
+            .filter(|txid| !txid.is_empty())
+            .map(|txid| {
+                txid.parse()
+                    .map_err(|e| anyhow::anyhow!("invalid txid {txid}: {e}"))
+            })
+            .collect::<Result<HashSet<Txid>>>()?;
+        if exclude.len() > MAX_EXCLUDED {
+            anyhow::bail!("at most {MAX_EXCLUDED} transactions can be excluded");
+        }
+        Ok(exclude)
+    }
+
+    /// [`Calc::recommended`], so clients of a mempool.space instance can switch without changes
+    async fn recommended(
+        State(state): State<Arc<AppState>>,
+        Query(query): Query<ConfidenceQuery>,
+    ) -> Result<Json<RecommendedFees>, ApiError> {
+        if !(query.confidence > 0. && query.confidence <= 1.) {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                "confidence must be in (0, 1]".to_string(),
+            ));
+        }
+
+        let confidence = query.confidence;
+        let fees = tokio::task::spawn_blocking(move || {
+            Calc::recommended(state.storage.as_ref(), confidence)
+        })
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+        Ok(Json(fees))
+    }
+
+    /// Fee rates for LND's `--fee.url`, the Esplora targets in sat/kvB
+    async fn lnd_fees(
+        State(state): State<Arc<AppState>>,
+        Query(query): Query<ConfidenceQuery>,
+    ) -> Result<Json<LndFees>, ApiError> {
+        if !(query.confidence > 0. && query.confidence <= 1.) {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                "confidence must be in (0, 1]".to_string(),
+            ));
+        }
+
+        let confidence = query.confidence;
+        let (estimates, minimum) = tokio::task::spawn_blocking(move || {
+            let storage = state.storage.as_ref();
+            let estimates = Calc::targets(storage, &ESPLORA_TARGETS, &[confidence])?;
+            anyhow::Ok((estimates, Calc::minimum_fee(storage)?))
+        })
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+
+        let sat_kvb = |fee_rate: f64| (fee_rate * 1000.).ceil() as u64;
+        Ok(Json(LndFees {
+            fee_by_block_target: estimates
+                .into_iter()
+                .map(|estimate| (estimate.target, sat_kvb(estimate.fee_rate_sat_vb)))
+                .collect(),
+            min_relay_feerate: sat_kvb(minimum),
+        }))
+    }
+
+    /// Fee rate in sat/vB by target in the shape of Esplora, `{"1": 87.8, "2": 80.1, ...}`
+    async fn fee_estimates(
+        State(state): State<Arc<AppState>>,
+        Query(query): Query<ConfidenceQuery>,
+    ) -> Result<Json<BTreeMap<u32, f64>>, ApiError> {
+        if !(query.confidence > 0. && query.confidence <= 1.) {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                "confidence must be in (0, 1]".to_string(),
+            ));
+        }
+
+        let confidence = query.confidence;
+        let estimates = tokio::task::spawn_blocking(move || {
+            Calc::targets(state.storage.as_ref(), &ESPLORA_TARGETS, &[confidence])
+        })
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+
+        Ok(Json(
+            estimates
+                .into_iter()
+                .map(|estimate| (estimate.target, estimate.fee_rate_sat_vb))
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn if_none_match_lists_the_tag_or_any() {
+        let tag = "\"0123456789abcdef\"";
+        let matches = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(value).unwrap());
+            Serve::if_none_match(&headers, tag)
+        };
+        assert!(matches(tag));
+        assert!(matches("\"fedcba9876543210\", W/\"0123456789abcdef\""));
+        assert!(matches("*"));
+        assert!(!matches("\"fedcba9876543210\""));
+        assert!(!matches("0123456789abcdef"));
+        assert!(!Serve::if_none_match(&HeaderMap::new(), tag));
+    }
+
+    #[test]
+    fn cache_key_leaves_out_the_api_key() {
+        let key = |uri: &str| Serve::cache_key(&uri.parse().unwrap());
+        assert_eq!(key("/v1/targets"), "/v1/targets?");
+        assert_eq!(
+            key("/v1/fee?api_key=secret&target=3"),
+            key("/v1/fee?target=3&api_key=other")
+        );
+        assert_ne!(key("/v1/fee?target=3"), key("/v1/fee?target=6"));
+    }
+}
@@ -0,0 +1,250 @@
+use crate::rpc::RpcClient;
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    secp256k1::rand::{rngs::StdRng, Rng, SeedableRng},
+    Txid,
+};
+use bitcoincore_rest::responses::{GetBlockchainInfoResult, GetMempoolEntryResult};
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    time::Duration,
+};
+use tracing::{debug, info, warn};
+
+/// Blocks a coinbase output takes to mature
+const COINBASE_MATURITY: u32 = 100;
+/// What the wallet is assumed to earn per block mined to fund it, regtest halves every 150
+const FUNDING_PER_BLOCK_BTC: f64 = 25.;
+/// Each transaction spends one of these, so none has to wait for the change of another
+const COIN_BTC: f64 = 0.1;
+/// What each transaction sends
+const PAYMENT_BTC: f64 = 0.001;
+
+/// Fee rates of the simulated transactions, sat/vB
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeeDistribution {
+    Fixed(f64),
+    Uniform {
+        min: f64,
+        max: f64,
+    },
+    /// Around `median`, `sigma` the standard deviation of the logarithm
+    LogNormal {
+        median: f64,
+        sigma: f64,
+    },
+}
+
+impl FeeDistribution {
+    pub fn sample(&self, rng: &mut StdRng) -> f64 {
+        match *self {
+            FeeDistribution::Fixed(rate) => rate,
+            FeeDistribution::Uniform { min, max } => rng.gen_range(min..=max),
+            FeeDistribution::LogNormal { median, sigma } => {
+                // Box-Muller
+                let (u, v): (f64, f64) = (rng.gen_range(f64::EPSILON..1.), rng.gen());
+                let normal = (-2. * u.ln()).sqrt() * (2. * std::f64::consts::PI * v).cos();
+                median * (sigma * normal).exp()
+            }
+        }
+    }
+}
+
+/// `fixed:RATE`, `uniform:MIN:MAX` or `lognormal:MEDIAN:SIGMA`
+impl FromStr for FeeDistribution {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split(':');
+        let kind = parts.next().unwrap_or_default();
+        let numbers = parts
+            .map(|part| part.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow!("invalid fee rate distribution {s}: {e}"))?;
+        let distribution = match (kind, numbers.as_slice()) {
+            ("fixed", [rate]) => FeeDistribution::Fixed(*rate),
+            ("uniform", [min, max]) if min <= max => FeeDistribution::Uniform {
+                min: *min,
+                max: *max,
+            },
+            ("lognormal", [median, sigma]) if *sigma >= 0. => FeeDistribution::LogNormal {
+                median: *median,
+                sigma: *sigma,
+            },
+            _ => bail!(
+                "invalid fee rate distribution {s}, expected fixed:RATE, uniform:MIN:MAX or \
+                 lognormal:MEDIAN:SIGMA"
+            ),
+        };
+        if numbers
+            .iter()
+            .any(|number| !number.is_finite() || *number < 0.)
+        {
+            bail!("invalid fee rate distribution {s}, numbers must be at least 0");
+        }
+        Ok(distribution)
+    }
+}
+
+/// What to play on the regtest node
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub blocks: u32,
+    pub transactions_per_block: u32,
+    pub block_interval: Duration,
+    pub fee_rates: FeeDistribution,
+    /// Fee rates are raised to this, the node's minimum relay fee rate
+    pub min_fee_rate: f64,
+    /// Blocks take the transactions paying the most up to this size, everything by default
+    pub block_vsize: Option<u64>,
+    /// Fee rates are drawn from this, the same seed plays the same scenario
+    pub seed: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Simulated {
+    pub blocks: u32,
+    pub transactions: u32,
+    /// Transactions the wallet couldn't send
+    pub failed: u32,
+    /// In the mempool once the last block is mined
+    pub left: usize,
+}
+
+/// Drives a regtest node through a [`Scenario`], for a recorder to record alongside: the
+/// wallet sends transactions at fee rates drawn from a distribution and blocks are mined
+/// between them, so estimates can be compared across changes on the same mempool history
+pub struct Simulate;
+
+impl Simulate {
+    /// `rpc` must reach the node with the wallet to spend from, which is created and funded
+    /// first if need be
+    #[tracing::instrument(skip(rpc))]
+    pub async fn run(rpc: &RpcClient, wallet: &str, scenario: &Scenario) -> Result<Simulated> {
+        let chain: GetBlockchainInfoResult = rpc.call("getblockchaininfo", json!([])).await?;
+        if chain.chain != "regtest" {
+            bail!("node is on {}, only regtest can be simulated", chain.chain);
+        }
+        Self::open_wallet(rpc, wallet).await?;
+        let address: String = rpc.call("getnewaddress", json!([])).await?;
+        Self::fund(rpc, &address, scenario.transactions_per_block).await?;
+
+        let mut rng = StdRng::seed_from_u64(scenario.seed);
+        let mut simulated = Simulated::default();
+        let pause = scenario.block_interval / scenario.transactions_per_block.max(1);
+        for block in 1..=scenario.blocks {
+            for _ in 0..scenario.transactions_per_block {
+                let fee_rate = scenario
+                    .fee_rates
+                    .sample(&mut rng)
+                    .max(scenario.min_fee_rate);
+                // sat/vB with three decimals, more are refused
+                let fee_rate = (fee_rate * 1000.).round() / 1000.;
+                let params =
+                    json!({"address": address, "amount": PAYMENT_BTC, "fee_rate": fee_rate});
+                match rpc.call::<Txid>("sendtoaddress", params).await {
+                    Ok(txid) => {
+                        debug!("sent {txid} at {fee_rate} sat/vB");
+                        simulated.transactions += 1;
+                    }
+                    Err(e) => {
+                        warn!("sending at {fee_rate} sat/vB failed: {e:#}");
+                        simulated.failed += 1;
+                    }
+                }
+                tokio::time::sleep(pause).await;
+            }
+            let mined = Self::mine(rpc, &address, scenario.block_vsize).await?;
+            info!(
+                "block {block} of {} with {mined} transactions",
+                scenario.blocks
+            );
+            simulated.blocks += 1;
+        }
+        let mempool: Vec<Txid> = rpc.call("getrawmempool", json!([])).await?;
+        simulated.left = mempool.len();
+        Ok(simulated)
+    }
+
+    async fn open_wallet(rpc: &RpcClient, wallet: &str) -> Result<()> {
+        let created: Result<serde_json::Value> = rpc.call("createwallet", json!([wallet])).await;
+        if created.is_ok() {
+            info!("created wallet {wallet}");
+            return Ok(());
+        }
+        match rpc
+            .call::<serde_json::Value>("loadwallet", json!([wallet]))
+            .await
+        {
+            Ok(_) => Ok(()),
+            // loaded already
+            Err(e) if format!("{e}").contains("-35") => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Mine until the wallet has a mature coin for each of `transactions` in a block
+    async fn fund(rpc: &RpcClient, address: &str, transactions: u32) -> Result<()> {
+        let balance: f64 = rpc.call("getbalance", json!([])).await?;
+        let needed = transactions as f64 * COIN_BTC * 2.;
+        if balance < needed {
+            let blocks =
+                COINBASE_MATURITY + ((needed - balance) / FUNDING_PER_BLOCK_BTC).ceil() as u32;
+            info!("mining {blocks} blocks to fund the wallet");
+            let _: Vec<String> = rpc
+                .call("generatetoaddress", json!([blocks, address]))
+                .await?;
+        }
+        let unspent: Vec<serde_json::Value> = rpc.call("listunspent", json!([])).await?;
+        if unspent.len() >= transactions as usize {
+            return Ok(());
+        }
+        let mut outputs = HashMap::new();
+        for _ in unspent.len()..transactions as usize * 2 {
+            let address: String = rpc.call("getnewaddress", json!([])).await?;
+            outputs.insert(address, COIN_BTC);
+        }
+        info!("splitting the wallet's coins into {}", outputs.len());
+        let _: Txid = rpc.call("sendmany", json!(["", outputs])).await?;
+        let _: Vec<String> = rpc.call("generatetoaddress", json!([1, address])).await?;
+        Ok(())
+    }
+
+    /// A block of the mempool paying the most, up to `vsize` if given. Returns how many
+    /// transactions it has.
+    async fn mine(rpc: &RpcClient, address: &str, vsize: Option<u64>) -> Result<usize> {
+        let Some(vsize) = vsize else {
+            let before: Vec<Txid> = rpc.call("getrawmempool", json!([])).await?;
+            let _: Vec<String> = rpc.call("generatetoaddress", json!([1, address])).await?;
+            let after: Vec<Txid> = rpc.call("getrawmempool", json!([])).await?;
+            return Ok(before.len() - after.len().min(before.len()));
+        };
+        let mempool: HashMap<Txid, GetMempoolEntryResult> =
+            rpc.call("getrawmempool", json!([true])).await?;
+        let mut entries: Vec<(&Txid, &GetMempoolEntryResult)> = mempool.iter().collect();
+        let rate =
+            |entry: &GetMempoolEntryResult| entry.fees.base.to_sat() as f64 / entry.vsize as f64;
+        entries.sort_by(|a, b| rate(b.1).total_cmp(&rate(a.1)));
+        // parents go first, a child paying more than its parent waits for the next block
+        let mut included: HashSet<&Txid> = HashSet::new();
+        let mut selected = Vec::new();
+        let mut size = 0;
+        for (txid, entry) in entries {
+            if size + entry.vsize > vsize
+                || !entry.depends.iter().all(|parent| included.contains(parent))
+            {
+                continue;
+            }
+            size += entry.vsize;
+            included.insert(txid);
+            selected.push(txid.to_string());
+        }
+        let _: serde_json::Value = rpc
+            .call("generateblock", json!([address, selected]))
+            .await?;
+        Ok(selected.len())
+    }
+}
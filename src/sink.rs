@@ -0,0 +1,107 @@
+use crate::{
+    bus::BusSink,
+    dataset::{FileKind, SnapshotFile},
+    manifest::Manifest,
+    mqtt::MqttSink,
+    postgres::PostgresSink,
+    storage::Storage,
+};
+use anyhow::{bail, Result};
+use bitcoin::Network;
+use chrono::{DateTime, Utc};
+use polars::prelude::*;
+use std::{path::PathBuf, sync::Arc};
+use tracing::warn;
+
+/// Somewhere recorded files are forwarded to once they have been stored
+pub trait Sink: Send + Sync {
+    fn name(&self) -> &str;
+    /// Called after `file` has been written with the contents of `frame`. Slow work belongs on
+    /// a background task, this runs on the recording loop.
+    fn accept(&self, file: &SnapshotFile, frame: &DataFrame) -> Result<()>;
+}
+
+/// Start the sink for a `--sink` URL: `postgres://` (or `postgresql://`), `nats://`,
+/// `kafka+http(s)://` for a Kafka REST proxy or `mqtt://`. `dataset` opens the recorded dataset
+/// for sinks that estimate from it.
+pub fn from_url(
+    url: &str,
+    network: Network,
+    dataset: &dyn Fn() -> Result<Arc<dyn Storage>>,
+) -> Result<Box<dyn Sink>> {
+    match url.split_once("://").map(|(scheme, _)| scheme) {
+        Some("nats" | "kafka+http" | "kafka+https") => Ok(Box::new(BusSink::start(url, network)?)),
+        Some("mqtt") => Ok(Box::new(MqttSink::start(url, dataset()?)?)),
+        Some("postgres" | "postgresql") => {
+            Ok(Box::new(PostgresSink::start(url.to_string(), network)))
+        }
+        _ => bail!("unsupported sink {url}"),
+    }
+}
+
+/// Storage passing everything it writes on to sinks. A failing sink is logged and doesn't fail
+/// the write, the file is stored either way.
+pub struct SinkStorage {
+    inner: Box<dyn Storage>,
+    sinks: Vec<Box<dyn Sink>>,
+}
+
+impl SinkStorage {
+    pub fn new(inner: Box<dyn Storage>, sinks: Vec<Box<dyn Sink>>) -> Self {
+        SinkStorage { inner, sinks }
+    }
+}
+
+impl Storage for SinkStorage {
+    fn write(
+        &self,
+        now: DateTime<Utc>,
+        height: u64,
+        kind: FileKind,
+        frame: &mut DataFrame,
+    ) -> Result<SnapshotFile> {
+        let file = self.inner.write(now, height, kind, frame)?;
+        for sink in &self.sinks {
+            if let Err(e) = sink.accept(&file, frame) {
+                warn!(
+                    "{} sink failed for {}: {e:#}",
+                    sink.name(),
+                    file.path.display()
+                );
+            }
+        }
+        Ok(file)
+    }
+
+    fn list(&self) -> Result<Vec<SnapshotFile>> {
+        self.inner.list()
+    }
+
+    fn read(&self, file: &SnapshotFile) -> Result<DataFrame> {
+        self.inner.read(file)
+    }
+
+    fn scan(&self, file: &SnapshotFile) -> Result<LazyFrame> {
+        self.inner.scan(file)
+    }
+
+    fn size(&self, file: &SnapshotFile) -> Option<u64> {
+        self.inner.size(file)
+    }
+
+    fn remove(&self, file: &SnapshotFile) -> Result<()> {
+        self.inner.remove(file)
+    }
+
+    fn recover(&self) -> Result<Vec<PathBuf>> {
+        self.inner.recover()
+    }
+
+    fn manifest(&self) -> Result<Option<Manifest>> {
+        self.inner.manifest()
+    }
+
+    fn atomically(&self, operation: &str, change: &mut dyn FnMut() -> Result<()>) -> Result<()> {
+        self.inner.atomically(operation, change)
+    }
+}
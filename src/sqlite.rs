@@ -0,0 +1,359 @@
+use crate::{
+    dataset::{FileKind, SnapshotFile},
+    storage::{check_network, tag_frame, Storage},
+};
+use anyhow::{bail, Context, Result};
+use bitcoin::Network;
+use chrono::{DateTime, TimeZone, Utc};
+use polars::prelude::*;
+use rusqlite::{params, params_from_iter, types::Value, Connection, Transaction};
+use std::{collections::HashSet, path::PathBuf, sync::Mutex, time::Duration};
+
+/// Name of the database file in `{data_dir}/{network}/`
+pub const DATABASE_FILE: &str = "wtf.sqlite";
+
+/// Columns identifying the file a row belongs to, named like [`SnapshotFile::tag`]
+const KEY_COLUMNS: [&str; 3] = ["height", "snapshot_timestamp", "kind"];
+
+/// The dataset in a single WAL-mode SQLite database. Every written file is a row of `files`,
+/// its rows go to a table per kind (`deltas` for full and delta files, `blocks`, `reorgs`,
+/// `meta`, `events`, `rejected`, `core_estimates`, `peer_feefilters`, `shutdowns`, `gaps`,
+/// `histograms`, `block_fees` and `block_headers`) next to the [`KEY_COLUMNS`]. Columns are
+/// declared with their polars type (`UINT64`, `FLOAT64`, `TEXT`, ...) so frames read back as
+/// they were written.
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+    path: PathBuf,
+    network: Network,
+}
+
+impl SqliteStorage {
+    pub fn open(data_dir: impl Into<PathBuf>, network: Network) -> Result<Self> {
+        let dir = data_dir.into().join(network.to_string());
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(DATABASE_FILE);
+        let connection =
+            Connection::open(&path).with_context(|| format!("opening {}", path.display()))?;
+        // the recorder and readers like `wtf serve` share the database
+        connection.busy_timeout(Duration::from_secs(5))?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            PRAGMA synchronous = NORMAL;
+            CREATE TABLE IF NOT EXISTS files (
+                height INTEGER NOT NULL,
+                snapshot_timestamp INTEGER NOT NULL,
+                kind TEXT NOT NULL,
+                PRIMARY KEY (height, snapshot_timestamp, kind)
+            );
+            CREATE INDEX IF NOT EXISTS files_snapshot_timestamp ON files (snapshot_timestamp);",
+        )?;
+        Ok(SqliteStorage {
+            connection: Mutex::new(connection),
+            path,
+            network,
+        })
+    }
+
+    fn table(kind: FileKind) -> Result<&'static str> {
+        Ok(match kind {
+            FileKind::Full | FileKind::Delta => "deltas",
+            FileKind::Block => "blocks",
+            FileKind::Reorg => "reorgs",
+            FileKind::Meta => "meta",
+            FileKind::Events => "events",
+            FileKind::Rejected => "rejected",
+            FileKind::CoreEstimates => "core_estimates",
+            FileKind::PeerFeeFilters => "peer_feefilters",
+            FileKind::Shutdown => "shutdowns",
+            FileKind::Gap => "gaps",
+            FileKind::Histogram => "histograms",
+            FileKind::BlockFees => "block_fees",
+            FileKind::BlockHeader => "block_headers",
+            FileKind::Compact => bail!("sqlite storage is a single file already, not compacted"),
+        })
+    }
+
+    fn file(height: u64, timestamp: i64, kind: FileKind) -> SnapshotFile {
+        let written_at = Utc.timestamp_opt(timestamp, 0).unwrap();
+        SnapshotFile {
+            height,
+            timestamp,
+            kind,
+            path: SnapshotFile::path_for(written_at, height, kind),
+        }
+    }
+
+    /// Declared types of the columns of `table` besides the key, in table order
+    fn columns(connection: &Connection, table: &str) -> Result<Vec<(String, String)>> {
+        let mut statement = connection.prepare(&format!("PRAGMA table_info({table})"))?;
+        let columns = statement
+            .query_map([], |row| Ok((row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+        Ok(columns
+            .into_iter()
+            .filter(|(name, _)| !KEY_COLUMNS.contains(&name.as_str()))
+            .collect())
+    }
+
+    /// Create `table`, or add the columns of `schema` it lacks
+    fn prepare(transaction: &Transaction, table: &str, schema: &Schema) -> Result<()> {
+        let existing: HashSet<String> = Self::columns(transaction, table)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        let mut declared = Vec::new();
+        for (name, dtype) in schema.iter() {
+            if !existing.contains(name.as_str()) {
+                declared.push(format!("{} {}", quote(name), declared_type(dtype)?));
+            }
+        }
+        if existing.is_empty() {
+            // a table without columns besides the key doesn't exist yet
+            let has_txid = schema.get("txid").is_some();
+            transaction.execute_batch(&format!(
+                "CREATE TABLE {table} (
+                    height INTEGER NOT NULL,
+                    snapshot_timestamp INTEGER NOT NULL,
+                    kind TEXT NOT NULL,
+                    {}
+                );
+                CREATE INDEX {table}_file ON {table} (height, snapshot_timestamp{});",
+                declared.join(",\n                    "),
+                if has_txid { ", txid" } else { "" },
+            ))?;
+        } else {
+            for column in declared {
+                transaction.execute_batch(&format!("ALTER TABLE {table} ADD COLUMN {column}"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Storage for SqliteStorage {
+    #[tracing::instrument(level = "debug", skip(self, frame))]
+    fn write(
+        &self,
+        now: DateTime<Utc>,
+        height: u64,
+        kind: FileKind,
+        frame: &mut DataFrame,
+    ) -> Result<SnapshotFile> {
+        let table = Self::table(kind)?;
+        tag_frame(frame, self.network)?;
+        let (timestamp, kind_name) = (now.timestamp(), kind.as_str());
+
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        Self::prepare(&transaction, table, &frame.schema())?;
+        // writing the same file again replaces it, like overwriting a parquet file
+        transaction.execute(
+            &format!(
+                "DELETE FROM {table} WHERE height = ?1 AND snapshot_timestamp = ?2 AND kind = ?3"
+            ),
+            params![height as i64, timestamp, kind_name],
+        )?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO files (height, snapshot_timestamp, kind) VALUES (?1, ?2, ?3)",
+            params![height as i64, timestamp, kind_name],
+        )?;
+        {
+            let names: Vec<String> = frame.get_column_names().iter().map(|n| quote(n)).collect();
+            let mut insert = transaction.prepare(&format!(
+                "INSERT INTO {table} ({}, {}) VALUES (?1, ?2, ?3{})",
+                KEY_COLUMNS.join(", "),
+                names.join(", "),
+                (0..names.len())
+                    .map(|i| format!(", ?{}", i + 4))
+                    .collect::<String>(),
+            ))?;
+            let mut columns: Vec<_> = frame.get_columns().iter().map(|c| c.iter()).collect();
+            for _ in 0..frame.height() {
+                let mut row = vec![
+                    Value::Integer(height as i64),
+                    Value::Integer(timestamp),
+                    Value::Text(kind_name.to_string()),
+                ];
+                for column in columns.iter_mut() {
+                    row.push(to_sql(column.next().unwrap_or(AnyValue::Null))?);
+                }
+                insert.execute(params_from_iter(row))?;
+            }
+        }
+        transaction.commit()?;
+        Ok(Self::file(height, timestamp, kind))
+    }
+
+    fn list(&self) -> Result<Vec<SnapshotFile>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement =
+            connection.prepare("SELECT height, snapshot_timestamp, kind FROM files")?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get::<_, String>(2)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut files: Vec<SnapshotFile> = rows
+            .into_iter()
+            .filter_map(|(height, timestamp, kind)| {
+                Some(Self::file(
+                    height as u64,
+                    timestamp,
+                    FileKind::parse(&kind)?,
+                ))
+            })
+            .collect();
+        SnapshotFile::sort(&mut files);
+        Ok(files)
+    }
+
+    fn read(&self, file: &SnapshotFile) -> Result<DataFrame> {
+        let table = Self::table(file.kind)?;
+        let connection = self.connection.lock().unwrap();
+        let columns = Self::columns(&connection, table)?;
+        if columns.is_empty() {
+            bail!("{} is not in {}", file.path.display(), self.path.display());
+        }
+        let names: Vec<String> = columns.iter().map(|(name, _)| quote(name)).collect();
+        let mut statement = connection.prepare(&format!(
+            "SELECT {} FROM {table} WHERE height = ?1 AND snapshot_timestamp = ?2 AND kind = ?3
+            ORDER BY rowid",
+            names.join(", ")
+        ))?;
+        let mut values: Vec<Vec<Value>> = vec![Vec::new(); columns.len()];
+        let mut rows = statement.query(params![
+            file.height as i64,
+            file.timestamp,
+            file.kind.as_str()
+        ])?;
+        while let Some(row) = rows.next()? {
+            for (i, column) in values.iter_mut().enumerate() {
+                column.push(row.get(i)?);
+            }
+        }
+        let series = columns
+            .iter()
+            .zip(values)
+            .map(|((name, declared), values)| from_sql(name, declared, values))
+            .collect::<Result<Vec<_>>>()?;
+        let frame = DataFrame::new(series)?;
+        check_network(&frame, self.network, file)?;
+        Ok(frame)
+    }
+
+    fn remove(&self, file: &SnapshotFile) -> Result<()> {
+        let table = Self::table(file.kind)?;
+        let keys = params![file.height as i64, file.timestamp, file.kind.as_str()];
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "DELETE FROM files WHERE height = ?1 AND snapshot_timestamp = ?2 AND kind = ?3",
+            keys,
+        )?;
+        if !Self::columns(&transaction, table)?.is_empty() {
+            transaction.execute(
+                &format!(
+                    "DELETE FROM {table} WHERE height = ?1 AND snapshot_timestamp = ?2 AND kind = ?3"
+                ),
+                keys,
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn declared_type(dtype: &DataType) -> Result<&'static str> {
+    Ok(match dtype {
+        DataType::Boolean => "BOOLEAN",
+        DataType::UInt32 => "UINT32",
+        DataType::UInt64 => "UINT64",
+        DataType::Int32 => "INT32",
+        DataType::Int64 => "INT64",
+        DataType::Float32 => "FLOAT32",
+        DataType::Float64 => "FLOAT64",
+        DataType::Utf8 => "TEXT",
+        _ => bail!("can't store {dtype} columns in sqlite"),
+    })
+}
+
+fn to_sql(value: AnyValue) -> Result<Value> {
+    Ok(match value {
+        AnyValue::Null => Value::Null,
+        AnyValue::Boolean(v) => Value::Integer(v as i64),
+        AnyValue::UInt32(v) => Value::Integer(v as i64),
+        AnyValue::UInt64(v) => Value::Integer(v as i64),
+        AnyValue::Int32(v) => Value::Integer(v as i64),
+        AnyValue::Int64(v) => Value::Integer(v),
+        AnyValue::Float32(v) => Value::Real(v as f64),
+        AnyValue::Float64(v) => Value::Real(v),
+        AnyValue::Utf8(v) => Value::Text(v.to_string()),
+        other => bail!("can't store {other} in sqlite"),
+    })
+}
+
+fn from_sql(name: &str, declared: &str, values: Vec<Value>) -> Result<Series> {
+    fn integer(value: &Value) -> Option<i64> {
+        match value {
+            Value::Integer(v) => Some(*v),
+            Value::Real(v) => Some(*v as i64),
+            _ => None,
+        }
+    }
+    fn real(value: &Value) -> Option<f64> {
+        match value {
+            Value::Integer(v) => Some(*v as f64),
+            Value::Real(v) => Some(*v),
+            _ => None,
+        }
+    }
+    let values = values.iter();
+    Ok(match declared {
+        "BOOLEAN" => Series::new(
+            name,
+            values
+                .map(|v| integer(v).map(|v| v != 0))
+                .collect::<Vec<_>>(),
+        ),
+        "UINT32" => Series::new(
+            name,
+            values
+                .map(|v| integer(v).map(|v| v as u32))
+                .collect::<Vec<_>>(),
+        ),
+        "UINT64" => Series::new(
+            name,
+            values
+                .map(|v| integer(v).map(|v| v as u64))
+                .collect::<Vec<_>>(),
+        ),
+        "INT32" => Series::new(
+            name,
+            values
+                .map(|v| integer(v).map(|v| v as i32))
+                .collect::<Vec<_>>(),
+        ),
+        "INT64" => Series::new(name, values.map(integer).collect::<Vec<_>>()),
+        "FLOAT32" => Series::new(
+            name,
+            values
+                .map(|v| real(v).map(|v| v as f32))
+                .collect::<Vec<_>>(),
+        ),
+        "FLOAT64" => Series::new(name, values.map(real).collect::<Vec<_>>()),
+        "TEXT" => Series::new(
+            name,
+            values
+                .map(|v| match v {
+                    Value::Text(v) => Some(v.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        ),
+        _ => bail!("unexpected sqlite column {name} {declared}"),
+    })
+}
@@ -0,0 +1,120 @@
+use crate::{dataset::FileKind, replay::Replay, storage::Storage};
+use anyhow::{bail, Result};
+use chrono::{NaiveDate, TimeZone, Utc};
+use std::collections::BTreeMap;
+
+/// Percentiles of the fee rate distribution reported per day
+pub const PERCENTILES: [f64; 5] = [0.1, 0.25, 0.5, 0.75, 0.9];
+
+/// What was recorded on a UTC day
+#[derive(Debug, Default, Clone)]
+pub struct DayStats {
+    pub day: NaiveDate,
+    pub files: usize,
+    /// On-disk size of the files, if the storage knows it
+    pub bytes: Option<u64>,
+    pub full_rows: usize,
+    pub delta_rows: usize,
+    pub block_rows: usize,
+    /// Transactions entering and leaving the mempool, from the deltas
+    pub added: usize,
+    pub removed: usize,
+    /// Seconds between the first and the last snapshot of the day
+    pub recorded_secs: i64,
+    /// Fee rates (sat/vB) at [`PERCENTILES`] of every transaction in a full snapshot or added by
+    /// a delta
+    pub fee_rates: Vec<f64>,
+    first_snapshot: Option<i64>,
+    last_snapshot: Option<i64>,
+}
+
+impl DayStats {
+    /// Added and removed transactions per hour of recording
+    pub fn churn_per_hour(&self) -> Option<(f64, f64)> {
+        if self.recorded_secs <= 0 {
+            return None;
+        }
+        let hours = self.recorded_secs as f64 / 3600.;
+        Some((self.added as f64 / hours, self.removed as f64 / hours))
+    }
+}
+
+pub struct Stats;
+
+impl Stats {
+    /// Summarize the dataset per day. Compacted snapshots count as the snapshots they contain,
+    /// the files as they are stored.
+    #[tracing::instrument(skip(storage))]
+    pub fn days(storage: &dyn Storage) -> Result<Vec<DayStats>> {
+        let mut days: BTreeMap<NaiveDate, DayStats> = BTreeMap::new();
+        let mut rates: BTreeMap<NaiveDate, Vec<f64>> = BTreeMap::new();
+
+        for file in storage.list()? {
+            let day = days.entry(file.day()).or_insert_with(|| DayStats {
+                day: file.day(),
+                bytes: Some(0),
+                ..Default::default()
+            });
+            day.files += 1;
+            day.bytes = day.bytes.zip(storage.size(&file)).map(|(a, b)| a + b);
+            if file.kind == FileKind::Block {
+                day.block_rows += storage.read(&file)?.height();
+            }
+        }
+
+        let replay = Replay::new(storage)?;
+        for entry in replay.entries() {
+            let date = Utc.timestamp_opt(entry.timestamp, 0).unwrap().date_naive();
+            let day = days.entry(date).or_insert_with(|| DayStats {
+                day: date,
+                ..Default::default()
+            });
+            day.first_snapshot.get_or_insert(entry.timestamp);
+            day.last_snapshot = Some(entry.timestamp);
+
+            let frame = replay.read(entry)?;
+            let weights = frame.column("weight")?.f64()?;
+            let fees = frame.column("fee_sat")?.f64()?;
+            let rates = rates.entry(date).or_default();
+            for (weight, fee) in weights.into_iter().zip(fees) {
+                let (Some(weight), Some(fee)) = (weight, fee) else {
+                    continue;
+                };
+                // removals carry a negative weight
+                if weight < 0. {
+                    day.removed += 1;
+                } else if weight > 0. {
+                    if entry.kind == FileKind::Delta {
+                        day.added += 1;
+                    }
+                    rates.push(fee / (weight / 4.));
+                }
+            }
+            match entry.kind {
+                FileKind::Full => day.full_rows += frame.height(),
+                _ => day.delta_rows += frame.height(),
+            }
+        }
+        if days.is_empty() {
+            bail!("no recorded files found");
+        }
+
+        for (date, day) in days.iter_mut() {
+            if let (Some(first), Some(last)) = (day.first_snapshot, day.last_snapshot) {
+                day.recorded_secs = last - first;
+            }
+            let mut rates = rates.remove(date).unwrap_or_default();
+            rates.sort_by(f64::total_cmp);
+            if !rates.is_empty() {
+                day.fee_rates = PERCENTILES
+                    .iter()
+                    .map(|p| {
+                        let rank = ((p * rates.len() as f64).ceil() as usize).max(1) - 1;
+                        rates[rank]
+                    })
+                    .collect();
+            }
+        }
+        Ok(days.into_values().collect())
+    }
+}
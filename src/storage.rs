@@ -0,0 +1,988 @@
+use crate::{
+    calc::{HISTORY_SECS, WINDOW_SECS},
+    dataset::{FileKind, SnapshotFile},
+    manifest::Manifest,
+    sqlite::SqliteStorage,
+    table::TableStorage,
+};
+use anyhow::{bail, Context, Result};
+use bitcoin::{
+    hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine},
+    Network,
+};
+use chrono::{DateTime, Utc};
+use notify::{event::ModifyKind, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use polars::prelude::*;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+use tracing::{debug, info, warn};
+
+/// Where recorded files are written to and read back from
+pub trait Storage: Send + Sync {
+    /// Persist `frame` as the `kind` file of the snapshot taken at `now` and `height`
+    fn write(
+        &self,
+        now: DateTime<Utc>,
+        height: u64,
+        kind: FileKind,
+        frame: &mut DataFrame,
+    ) -> Result<SnapshotFile>;
+    /// All recorded files, ordered by time
+    fn list(&self) -> Result<Vec<SnapshotFile>>;
+    fn read(&self, file: &SnapshotFile) -> Result<DataFrame>;
+    /// Lazily read `file`, so queries only load what they need
+    fn scan(&self, file: &SnapshotFile) -> Result<LazyFrame> {
+        Ok(self.read(file)?.lazy())
+    }
+    /// Bytes `file` takes up, if it is stored on its own
+    fn size(&self, _file: &SnapshotFile) -> Option<u64> {
+        None
+    }
+    fn remove(&self, file: &SnapshotFile) -> Result<()>;
+    /// Set aside whatever an interrupted run left half written, returning what was affected
+    fn recover(&self) -> Result<Vec<PathBuf>> {
+        Ok(Vec::new())
+    }
+    /// Checksums and row counts of the files, if the storage keeps them
+    fn manifest(&self) -> Result<Option<Manifest>> {
+        Ok(None)
+    }
+    /// Make what `change` writes and removes one change of the dataset, which readers see all
+    /// or nothing of if the storage keeps a log of its changes. `operation` names it there.
+    fn atomically(&self, _operation: &str, change: &mut dyn FnMut() -> Result<()>) -> Result<()> {
+        change()
+    }
+}
+
+/// How the dataset is kept below the data directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    /// A parquet file per snapshot
+    #[default]
+    Parquet,
+    /// A single SQLite database
+    Sqlite,
+    /// A parquet file per snapshot in hive partitions, for polars, DuckDB or pyarrow to prune
+    Hive,
+    /// A parquet file per snapshot, listed in a transaction log so that readers never see a
+    /// compaction or pruning half done
+    Table,
+}
+
+impl StorageKind {
+    /// The directory the dataset of `network` is kept in
+    pub fn root(self, data_dir: &str, network: Network) -> PathBuf {
+        match self {
+            StorageKind::Hive => Path::new(data_dir).join(format!("network={network}")),
+            _ => Path::new(data_dir).join(network.to_string()),
+        }
+    }
+
+    pub fn open(self, data_dir: &str, network: Network) -> Result<Box<dyn Storage>> {
+        self.open_with(data_dir, network, WriteOptions::default())
+    }
+
+    /// Writing parquet files with `options`, SQLite has no use for them
+    pub fn open_with(
+        self,
+        data_dir: &str,
+        network: Network,
+        options: WriteOptions,
+    ) -> Result<Box<dyn Storage>> {
+        Ok(match self {
+            StorageKind::Parquet => {
+                Box::new(LocalStorage::new(data_dir, network).with_options(options))
+            }
+            StorageKind::Sqlite => Box::new(SqliteStorage::open(data_dir, network)?),
+            StorageKind::Hive => {
+                Box::new(LocalStorage::hive(data_dir, network).with_options(options))
+            }
+            StorageKind::Table => Box::new(TableStorage::open(
+                LocalStorage::new(data_dir, network).with_options(options),
+            )?),
+        })
+    }
+}
+
+/// How parquet files are encoded
+#[derive(Debug, Clone, Copy)]
+pub struct WriteOptions {
+    pub compression: ParquetCompression,
+    /// Rows per row group, polars picks when unset
+    pub row_group_size: Option<usize>,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            compression: ParquetCompression::Zstd(None),
+            row_group_size: None,
+        }
+    }
+}
+
+impl WriteOptions {
+    /// `zstd`, `zstd:<level>` with levels 1 to 22, `snappy`, `lz4` or `none`
+    pub fn parse_compression(s: &str) -> Result<ParquetCompression> {
+        Ok(match s.split_once(':') {
+            Some(("zstd", level)) => {
+                let level = level
+                    .parse()
+                    .with_context(|| format!("zstd level {level}"))?;
+                ParquetCompression::Zstd(Some(ZstdLevel::try_new(level)?))
+            }
+            None if s == "zstd" => ParquetCompression::Zstd(None),
+            None if s == "snappy" => ParquetCompression::Snappy,
+            None if s == "lz4" => ParquetCompression::Lz4Raw,
+            None if s == "none" => ParquetCompression::Uncompressed,
+            _ => bail!("unknown compression {s}, expected zstd[:level], snappy, lz4 or none"),
+        })
+    }
+}
+
+/// Suffix of files still being written, renamed into place once complete
+const PARTIAL_SUFFIX: &str = "tmp";
+/// Quiet time after a file appears before the listing is refreshed, a snapshot writes several
+const REFRESH_DEBOUNCE: Duration = Duration::from_millis(500);
+/// Suffix appended to files found unreadable, so they are no longer part of the dataset
+const QUARANTINE_SUFFIX: &str = "corrupt";
+
+/// Column every file is tagged with, so datasets of different networks can't be mixed up
+pub(crate) const NETWORK_COLUMN: &str = "network";
+pub(crate) const SCHEMA_VERSION_COLUMN: &str = "schema_version";
+/// Layout of the files written now. Files without a version column are 1, the four columns
+/// `txid`, `weight`, `fee_sat` and `first_seen_at` of the first recordings and whatever was
+/// added to them later, `wtf migrate` upgrades them. 3 labels removals `purged` that 2 labelled
+/// `evicted-below-minfee`.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// The columns of full and delta files every recording has, all estimates need
+pub const LEAN_COLUMNS: [&str; 4] = ["txid", "weight", "fee_sat", "first_seen_at"];
+/// The columns of full and delta files a recording may leave out, see [`ColumnStorage`]
+pub const OPTIONAL_COLUMNS: [&str; 15] = [
+    "wtxid",
+    "block_hash",
+    "removal_reason",
+    "fee_rate_sat_vb",
+    "fee_rate_sat_wu",
+    "effective_fee_rate",
+    "queue_percentile",
+    "replaces_txid",
+    "vsize",
+    "ancestor_count",
+    "descendant_count",
+    "ancestor_fees",
+    "descendant_fees",
+    "bip125_replaceable",
+    "unbroadcast",
+];
+/// Column of the meta files naming the optional columns the full and delta files of the
+/// snapshot were recorded with
+pub(crate) const RECORDED_COLUMNS_COLUMN: &str = "recorded_columns";
+
+/// Add the network and schema version columns to a frame about to be written
+pub(crate) fn tag_frame(frame: &mut DataFrame, network: Network) -> Result<()> {
+    let network = network.to_string();
+    frame.with_column(Series::new(
+        NETWORK_COLUMN,
+        vec![network.as_str(); frame.height()],
+    ))?;
+    frame.with_column(Series::new(
+        SCHEMA_VERSION_COLUMN,
+        vec![SCHEMA_VERSION; frame.height()],
+    ))?;
+    Ok(())
+}
+
+/// Fail if `frame`, read from `file`, was recorded on another network. Files written before
+/// network tagging carry no column and are trusted by location.
+pub(crate) fn check_network(
+    frame: &DataFrame,
+    network: Network,
+    file: &SnapshotFile,
+) -> Result<()> {
+    if let Ok(column) = frame.column(NETWORK_COLUMN) {
+        let expected = network.to_string();
+        if let Some(other) = column
+            .utf8()?
+            .into_iter()
+            .flatten()
+            .find(|n| *n != expected)
+        {
+            bail!(
+                "{} was recorded on {other}, not {expected}",
+                file.path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Parquet files below a local directory,
+/// `{data_dir}/{network}/YYYY/MM/DD/{height}_{timestamp}_{kind}.parquet`, or with `hive`
+/// `{data_dir}/network={network}/date=YYYY-MM-DD/height={height}/` for the directories. A
+/// [`Manifest`] next to them lists every file with its checksum.
+pub struct LocalStorage {
+    root: PathBuf,
+    network: Network,
+    hive: bool,
+    options: WriteOptions,
+    /// Held while the manifest is updated
+    manifest: Mutex<()>,
+}
+
+impl LocalStorage {
+    pub fn new(data_dir: impl Into<PathBuf>, network: Network) -> Self {
+        LocalStorage {
+            root: data_dir.into().join(network.to_string()),
+            network,
+            hive: false,
+            options: WriteOptions::default(),
+            manifest: Mutex::new(()),
+        }
+    }
+
+    pub fn hive(data_dir: impl Into<PathBuf>, network: Network) -> Self {
+        LocalStorage {
+            root: data_dir.into().join(format!("network={network}")),
+            network,
+            hive: true,
+            options: WriteOptions::default(),
+            manifest: Mutex::new(()),
+        }
+    }
+
+    pub fn with_options(mut self, options: WriteOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where the file of the snapshot taken at `now` and `height` goes
+    pub(crate) fn path_for(&self, now: DateTime<Utc>, height: u64, kind: FileKind) -> PathBuf {
+        if !self.hive {
+            return self.root.join(SnapshotFile::path_for(now, height, kind));
+        }
+        let name = SnapshotFile::path_for(now, height, kind);
+        self.root
+            .join(format!("date={}", now.format("%Y-%m-%d")))
+            .join(format!("height={height}"))
+            .join(name.file_name().unwrap())
+    }
+
+    pub(crate) fn find(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+        let entries =
+            std::fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                Self::find(&path, paths)?;
+            } else {
+                paths.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    fn paths(&self) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        if self.root.exists() {
+            Self::find(&self.root, &mut paths)?;
+        }
+        Ok(paths)
+    }
+
+    /// Write to a temporary file next to `filename` and rename it into place, so a crash never
+    /// leaves a partial file under the final name. Returns the bytes written.
+    fn write_atomic(&self, filename: &Path, frame: &mut DataFrame) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        ParquetWriter::new(&mut bytes)
+            .with_compression(self.options.compression)
+            .with_row_group_size(self.options.row_group_size)
+            .with_statistics(true)
+            .finish(frame)?;
+        let partial = Self::with_suffix(filename, PARTIAL_SUFFIX);
+        let written = std::fs::File::create(&partial)
+            .with_context(|| format!("creating {}", partial.display()))
+            .and_then(|mut file| {
+                file.write_all(&bytes)?;
+                file.sync_all()?;
+                Ok(())
+            })
+            .and_then(|_| {
+                std::fs::rename(&partial, filename)
+                    .with_context(|| format!("renaming {}", partial.display()))
+            });
+        if written.is_err() {
+            let _ = std::fs::remove_file(&partial);
+        }
+        written.map(|_| bytes)
+    }
+
+    /// Change the manifest with `update`. A dataset without one gets it, listing the files
+    /// there are already.
+    fn update_manifest(&self, update: impl FnOnce(&mut Manifest)) -> Result<()> {
+        let _lock = self.manifest.lock().unwrap();
+        let mut manifest = match Manifest::load(&self.root)? {
+            Some(manifest) => manifest,
+            None => {
+                let files = self.list()?;
+                info!("listing {} files in a new manifest", files.len());
+                let mut manifest = Manifest::default();
+                for file in &files {
+                    let bytes = std::fs::read(&file.path)
+                        .with_context(|| format!("reading {}", file.path.display()))?;
+                    let rows = ParquetReader::new(std::io::Cursor::new(&bytes)).num_rows()?;
+                    manifest.insert(file, rows, &bytes);
+                }
+                manifest
+            }
+        };
+        update(&mut manifest);
+        manifest.save(&self.root)
+    }
+
+    fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".");
+        name.push(suffix);
+        PathBuf::from(name)
+    }
+
+    /// Rows in txid order, so the statistics of each row group bound the txids in it and
+    /// lookups skip the others. Compact files keep their snapshots in a row, each sorted by
+    /// txid already.
+    fn sort_by_txid(kind: FileKind, frame: &mut DataFrame) -> Result<()> {
+        if kind != FileKind::Compact && frame.get_column_names().contains(&"txid") {
+            *frame = frame.sort(["txid"], false)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the footer of a parquet file can be read, truncated files can't
+    fn readable(path: &Path) -> bool {
+        std::fs::File::open(path)
+            .map_err(PolarsError::from)
+            .and_then(|file| ParquetReader::new(file).num_rows())
+            .is_ok()
+    }
+}
+
+impl Storage for LocalStorage {
+    #[tracing::instrument(level = "debug", skip(self, frame))]
+    fn write(
+        &self,
+        now: DateTime<Utc>,
+        height: u64,
+        kind: FileKind,
+        frame: &mut DataFrame,
+    ) -> Result<SnapshotFile> {
+        let filename = self.path_for(now, height, kind);
+        tag_frame(frame, self.network)?;
+        Self::sort_by_txid(kind, frame)?;
+        // make sure the folder exists
+        std::fs::create_dir_all(filename.parent().unwrap())?;
+        let bytes = self.write_atomic(&filename, frame)?;
+        let file = SnapshotFile {
+            height,
+            timestamp: now.timestamp(),
+            kind,
+            path: filename,
+        };
+        self.update_manifest(|manifest| manifest.insert(&file, frame.height(), &bytes))?;
+        Ok(file)
+    }
+
+    fn list(&self) -> Result<Vec<SnapshotFile>> {
+        let mut files: Vec<SnapshotFile> = self
+            .paths()?
+            .iter()
+            .filter_map(|path| SnapshotFile::parse(path))
+            .collect();
+        SnapshotFile::sort(&mut files);
+        Ok(files)
+    }
+
+    fn read(&self, file: &SnapshotFile) -> Result<DataFrame> {
+        let reader = std::fs::File::open(&file.path)
+            .with_context(|| format!("opening {}", file.path.display()))?;
+        let frame = ParquetReader::new(reader).finish()?;
+        check_network(&frame, self.network, file)?;
+        Ok(frame)
+    }
+
+    fn scan(&self, file: &SnapshotFile) -> Result<LazyFrame> {
+        Ok(LazyFrame::scan_parquet(
+            &file.path,
+            ScanArgsParquet::default(),
+        )?)
+    }
+
+    fn size(&self, file: &SnapshotFile) -> Option<u64> {
+        std::fs::metadata(&file.path).ok().map(|m| m.len())
+    }
+
+    fn remove(&self, file: &SnapshotFile) -> Result<()> {
+        std::fs::remove_file(&file.path)
+            .with_context(|| format!("removing {}", file.path.display()))?;
+        self.update_manifest(|manifest| manifest.remove(file))
+    }
+
+    /// Remove temporary files of writes that never completed and rename unreadable files
+    /// written since the last clean shutdown to `*.parquet.corrupt`. Older files were read
+    /// back by the run that followed.
+    fn recover(&self) -> Result<Vec<PathBuf>> {
+        let mut recovered = Vec::new();
+        for path in self.paths()? {
+            if path.extension().is_some_and(|e| e == PARTIAL_SUFFIX) {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("removing {}", path.display()))?;
+                recovered.push(path);
+            }
+        }
+        let files = self.list()?;
+        let since = files
+            .iter()
+            .rposition(|f| f.kind == FileKind::Shutdown)
+            .map_or(0, |last| last + 1);
+        let mut quarantined = Vec::new();
+        for file in &files[since..] {
+            if !Self::readable(&file.path) {
+                let path = Self::with_suffix(&file.path, QUARANTINE_SUFFIX);
+                std::fs::rename(&file.path, &path)
+                    .with_context(|| format!("renaming {}", file.path.display()))?;
+                recovered.push(path);
+                quarantined.push(file);
+            }
+        }
+        if !quarantined.is_empty() {
+            self.update_manifest(|manifest| {
+                for file in quarantined {
+                    manifest.remove(file);
+                }
+            })?;
+        }
+        Ok(recovered)
+    }
+
+    fn manifest(&self) -> Result<Option<Manifest>> {
+        Manifest::load(&self.root)
+    }
+}
+
+/// Keeps nothing, for recording into sinks only
+pub struct NullStorage;
+
+impl Storage for NullStorage {
+    fn write(
+        &self,
+        now: DateTime<Utc>,
+        height: u64,
+        kind: FileKind,
+        _frame: &mut DataFrame,
+    ) -> Result<SnapshotFile> {
+        Ok(SnapshotFile {
+            height,
+            timestamp: now.timestamp(),
+            kind,
+            path: SnapshotFile::path_for(now, height, kind),
+        })
+    }
+
+    fn list(&self) -> Result<Vec<SnapshotFile>> {
+        Ok(Vec::new())
+    }
+
+    fn read(&self, file: &SnapshotFile) -> Result<DataFrame> {
+        bail!(
+            "{} was not stored, parquet output is disabled",
+            file.path.display()
+        )
+    }
+
+    fn remove(&self, _file: &SnapshotFile) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The dataset as it was at `until`, leaving out everything recorded later, for fitting a
+/// model without looking ahead. Nothing can be written or removed.
+pub struct TruncatedStorage<'a> {
+    inner: &'a dyn Storage,
+    until: i64,
+}
+
+impl<'a> TruncatedStorage<'a> {
+    pub fn new(inner: &'a dyn Storage, until: i64) -> Self {
+        TruncatedStorage { inner, until }
+    }
+}
+
+impl Storage for TruncatedStorage<'_> {
+    fn write(
+        &self,
+        _now: DateTime<Utc>,
+        _height: u64,
+        kind: FileKind,
+        _frame: &mut DataFrame,
+    ) -> Result<SnapshotFile> {
+        bail!(
+            "a truncated dataset is read-only, not writing a {} file",
+            kind.as_str()
+        )
+    }
+
+    fn list(&self) -> Result<Vec<SnapshotFile>> {
+        let mut files = self.inner.list()?;
+        files.retain(|f| f.timestamp <= self.until);
+        Ok(files)
+    }
+
+    /// Compacted days are cut at `until` too
+    fn read(&self, file: &SnapshotFile) -> Result<DataFrame> {
+        let frame = self.inner.read(file)?;
+        if file.kind != FileKind::Compact {
+            return Ok(frame);
+        }
+        let recorded = frame.column("snapshot_timestamp")?.i64()?.lt_eq(self.until);
+        Ok(frame.filter(&recorded)?)
+    }
+
+    fn remove(&self, file: &SnapshotFile) -> Result<()> {
+        bail!(
+            "a truncated dataset is read-only, not removing {}",
+            file.path.display()
+        )
+    }
+}
+
+/// Identifies a file independent of where it is kept
+type FileKey = (u64, i64, &'static str);
+
+/// Files known to a [`CachedStorage`] and the frames of the recent ones
+struct Cache {
+    files: Vec<SnapshotFile>,
+    frames: HashMap<FileKey, DataFrame>,
+}
+
+/// Storage holding the recent frames in memory, for estimating from a recording without
+/// reading its files again for every estimate. Everything is still written to `inner`, what
+/// has dropped out of memory is read from there. The listing is taken once, files another
+/// process adds later are seen after [`CachedStorage::refresh`], which
+/// [`CachedStorage::watch`] calls once new files are in place.
+#[derive(Clone)]
+pub struct CachedStorage {
+    inner: Arc<dyn Storage>,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl CachedStorage {
+    pub fn new(inner: Box<dyn Storage>) -> Result<Self> {
+        let files = inner.list()?;
+        Ok(CachedStorage {
+            inner: Arc::from(inner),
+            cache: Arc::new(Mutex::new(Cache {
+                files,
+                frames: HashMap::new(),
+            })),
+        })
+    }
+
+    /// Pick up the files added to and removed from `inner` since it was last listed. New
+    /// files recent enough to be estimated from are read right away.
+    pub fn refresh(&self) -> Result<usize> {
+        let files = self.inner.list()?;
+        let known: HashSet<FileKey> = {
+            let cache = self.cache.lock().unwrap();
+            cache.files.iter().map(Self::key).collect()
+        };
+        let newest = files.last().map_or(0, |file| file.timestamp);
+        let mut added = 0;
+        let mut frames = Vec::new();
+        for file in files
+            .iter()
+            .filter(|file| !known.contains(&Self::key(file)))
+        {
+            added += 1;
+            if file.kind != FileKind::Compact && Self::is_recent(file, newest) {
+                frames.push((Self::key(file), self.inner.read(file)?));
+            }
+        }
+
+        let mut cache = self.cache.lock().unwrap();
+        let listed: HashSet<FileKey> = files.iter().map(Self::key).collect();
+        cache.frames.retain(|key, _| listed.contains(key));
+        cache.frames.extend(frames);
+        cache.files = files;
+        Self::evict(&mut cache, newest);
+        Ok(added)
+    }
+
+    /// Refresh once parquet files stop being renamed into place below `dir`, the dataset's
+    /// [`StorageKind::root`], until the returned watcher is dropped. SQLite datasets rename
+    /// nothing, they have to be refreshed on a timer.
+    pub fn watch(&self, dir: &Path) -> Result<RecommendedWatcher> {
+        let (sender, receiver) = mpsc::channel::<()>();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                // a file is written under a temporary name and renamed once complete
+                let completed = matches!(event.kind, EventKind::Modify(ModifyKind::Name(_)))
+                    && event
+                        .paths
+                        .iter()
+                        .any(|path| path.extension().is_some_and(|e| e == "parquet"));
+                if completed {
+                    let _ = sender.send(());
+                }
+            })?;
+        watcher
+            .watch(dir, RecursiveMode::Recursive)
+            .with_context(|| format!("watching {}", dir.display()))?;
+        let storage = self.clone();
+        // ends once the watcher, and with it the sender, is dropped
+        std::thread::spawn(move || {
+            while receiver.recv().is_ok() {
+                while receiver.recv_timeout(REFRESH_DEBOUNCE).is_ok() {}
+                match storage.refresh() {
+                    Ok(0) => {}
+                    Ok(added) => debug!("picked up {added} new files"),
+                    Err(e) => warn!("refreshing the dataset failed: {e:#}"),
+                }
+            }
+        });
+        info!("watching {} for new snapshots", dir.display());
+        Ok(watcher)
+    }
+
+    fn key(file: &SnapshotFile) -> FileKey {
+        (file.height, file.timestamp, file.kind.as_str())
+    }
+
+    /// How long frames of `kind` are kept, as long as estimates look back at them
+    fn keep_secs(kind: FileKind) -> i64 {
+        match kind {
+            FileKind::Block | FileKind::Reorg | FileKind::BlockFees | FileKind::BlockHeader => {
+                HISTORY_SECS
+            }
+            _ => 2 * WINDOW_SECS,
+        }
+    }
+
+    fn is_recent(file: &SnapshotFile, now: i64) -> bool {
+        file.timestamp >= now - Self::keep_secs(file.kind)
+    }
+
+    /// Drop frames too old to be read again, except the last full snapshot every replay
+    /// starts from
+    fn evict(cache: &mut Cache, now: i64) {
+        let last_full = cache
+            .files
+            .iter()
+            .rev()
+            .find(|file| file.kind == FileKind::Full)
+            .map(Self::key);
+        let Cache { files, frames } = cache;
+        for file in files.iter() {
+            let key = Self::key(file);
+            if !Self::is_recent(file, now) && Some(key) != last_full {
+                frames.remove(&key);
+            }
+        }
+    }
+}
+
+impl Storage for CachedStorage {
+    fn write(
+        &self,
+        now: DateTime<Utc>,
+        height: u64,
+        kind: FileKind,
+        frame: &mut DataFrame,
+    ) -> Result<SnapshotFile> {
+        let file = self.inner.write(now, height, kind, frame)?;
+        let mut cache = self.cache.lock().unwrap();
+        cache.frames.insert(Self::key(&file), frame.clone());
+        cache.files.push(file.clone());
+        SnapshotFile::sort(&mut cache.files);
+        Self::evict(&mut cache, now.timestamp());
+        Ok(file)
+    }
+
+    fn list(&self) -> Result<Vec<SnapshotFile>> {
+        Ok(self.cache.lock().unwrap().files.clone())
+    }
+
+    fn read(&self, file: &SnapshotFile) -> Result<DataFrame> {
+        let key = Self::key(file);
+        if let Some(frame) = self.cache.lock().unwrap().frames.get(&key) {
+            return Ok(frame.clone());
+        }
+        let frame = self.inner.read(file)?;
+        // read once more by the next estimate, unless it's too old
+        let mut cache = self.cache.lock().unwrap();
+        let newest = cache.files.last().map_or(0, |file| file.timestamp);
+        if file.kind != FileKind::Compact && Self::is_recent(file, newest) {
+            cache.frames.insert(key, frame.clone());
+        }
+        Ok(frame)
+    }
+
+    fn scan(&self, file: &SnapshotFile) -> Result<LazyFrame> {
+        if let Some(frame) = self.cache.lock().unwrap().frames.get(&Self::key(file)) {
+            return Ok(frame.clone().lazy());
+        }
+        self.inner.scan(file)
+    }
+
+    fn size(&self, file: &SnapshotFile) -> Option<u64> {
+        self.inner.size(file)
+    }
+
+    fn remove(&self, file: &SnapshotFile) -> Result<()> {
+        self.inner.remove(file)?;
+        let key = Self::key(file);
+        let mut cache = self.cache.lock().unwrap();
+        cache.frames.remove(&key);
+        cache.files.retain(|f| Self::key(f) != key);
+        Ok(())
+    }
+
+    fn recover(&self) -> Result<Vec<PathBuf>> {
+        let recovered = self.inner.recover()?;
+        // quarantined files are no longer part of the dataset
+        if !recovered.is_empty() {
+            self.cache.lock().unwrap().files = self.inner.list()?;
+        }
+        Ok(recovered)
+    }
+
+    fn manifest(&self) -> Result<Option<Manifest>> {
+        self.inner.manifest()
+    }
+
+    fn atomically(&self, operation: &str, change: &mut dyn FnMut() -> Result<()>) -> Result<()> {
+        self.inner.atomically(operation, change)
+    }
+}
+
+/// Storage replacing the txids of everything it writes with their HMAC-SHA256 under a salt,
+/// for datasets to share. A transaction gets the same pseudonym in every file, so additions,
+/// removals and confirmations still match up, but only who knows the salt can tell which
+/// transaction it is. Block hashes are kept.
+pub struct HashedStorage {
+    inner: Box<dyn Storage>,
+    salt: Vec<u8>,
+}
+
+impl HashedStorage {
+    pub fn new(inner: Box<dyn Storage>, salt: &str) -> Self {
+        HashedStorage {
+            inner,
+            salt: salt.as_bytes().to_vec(),
+        }
+    }
+
+    /// Hex of the HMAC of `txid`, the length of a txid
+    fn hash(&self, txid: &str) -> String {
+        let mut engine = HmacEngine::<sha256::Hash>::new(&self.salt);
+        engine.input(txid.as_bytes());
+        Hmac::<sha256::Hash>::from_engine(engine)
+            .to_byte_array()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    /// Hash the values of column `name` where `txids` holds, every value without it
+    fn hash_column(&self, frame: &mut DataFrame, name: &str, txids: Option<&[bool]>) -> Result<()> {
+        let Ok(column) = frame.column(name) else {
+            return Ok(());
+        };
+        let hashed: Utf8Chunked = column
+            .utf8()?
+            .into_iter()
+            .enumerate()
+            .map(|(row, value)| match value {
+                Some(value) if txids.is_none_or(|txids| txids[row]) => Some(self.hash(value)),
+                value => value.map(str::to_string),
+            })
+            .collect();
+        let mut hashed = hashed.into_series();
+        hashed.rename(name);
+        frame.replace(name, hashed)?;
+        Ok(())
+    }
+}
+
+impl Storage for HashedStorage {
+    fn write(
+        &self,
+        now: DateTime<Utc>,
+        height: u64,
+        kind: FileKind,
+        frame: &mut DataFrame,
+    ) -> Result<SnapshotFile> {
+        match kind {
+            FileKind::Full | FileKind::Delta | FileKind::Block | FileKind::Rejected => {
+                self.hash_column(frame, "txid", None)?;
+                self.hash_column(frame, "wtxid", None)?;
+                self.hash_column(frame, "replaces_txid", None)?;
+            }
+            // notifications carry block hashes too
+            FileKind::Events => {
+                let txids: Vec<bool> = frame
+                    .column("event")?
+                    .utf8()?
+                    .into_iter()
+                    .map(|event| !event.is_some_and(|event| event.starts_with("block_")))
+                    .collect();
+                self.hash_column(frame, "hash", Some(&txids))?;
+            }
+            _ => {}
+        }
+        self.inner.write(now, height, kind, frame)
+    }
+
+    fn list(&self) -> Result<Vec<SnapshotFile>> {
+        self.inner.list()
+    }
+
+    fn read(&self, file: &SnapshotFile) -> Result<DataFrame> {
+        self.inner.read(file)
+    }
+
+    fn scan(&self, file: &SnapshotFile) -> Result<LazyFrame> {
+        self.inner.scan(file)
+    }
+
+    fn size(&self, file: &SnapshotFile) -> Option<u64> {
+        self.inner.size(file)
+    }
+
+    fn remove(&self, file: &SnapshotFile) -> Result<()> {
+        self.inner.remove(file)
+    }
+
+    fn recover(&self) -> Result<Vec<PathBuf>> {
+        self.inner.recover()
+    }
+
+    fn manifest(&self) -> Result<Option<Manifest>> {
+        self.inner.manifest()
+    }
+
+    fn atomically(&self, operation: &str, change: &mut dyn FnMut() -> Result<()>) -> Result<()> {
+        self.inner.atomically(operation, change)
+    }
+}
+
+/// Leaves the optional columns outside a selection out of the full and delta files, so small
+/// setups keep to [`LEAN_COLUMNS`]. Every meta file names the selection, comma separated,
+/// which tells a column left out apart from one older files lack.
+pub struct ColumnStorage {
+    inner: Box<dyn Storage>,
+    columns: Vec<&'static str>,
+}
+
+impl ColumnStorage {
+    /// Record the optional `columns` only, `lean` for none of them and `all` for every one
+    pub fn new(inner: Box<dyn Storage>, columns: &[String]) -> Result<Self> {
+        let mut selected = Vec::new();
+        for column in columns {
+            match column.as_str() {
+                "lean" => {}
+                "all" => selected.extend(OPTIONAL_COLUMNS),
+                column => match OPTIONAL_COLUMNS.iter().find(|name| **name == column) {
+                    Some(name) => selected.push(*name),
+                    None if LEAN_COLUMNS.contains(&column) => {
+                        bail!("{column} is always recorded")
+                    }
+                    None => bail!(
+                        "unknown column {column}, expected lean, all or any of {}",
+                        OPTIONAL_COLUMNS.join(", ")
+                    ),
+                },
+            }
+        }
+        // in the order the recorder writes them
+        let columns = OPTIONAL_COLUMNS
+            .into_iter()
+            .filter(|name| selected.contains(name))
+            .collect();
+        Ok(ColumnStorage { inner, columns })
+    }
+}
+
+impl Storage for ColumnStorage {
+    fn write(
+        &self,
+        now: DateTime<Utc>,
+        height: u64,
+        kind: FileKind,
+        frame: &mut DataFrame,
+    ) -> Result<SnapshotFile> {
+        match kind {
+            FileKind::Full | FileKind::Delta => {
+                for name in OPTIONAL_COLUMNS {
+                    if !self.columns.contains(&name) && frame.column(name).is_ok() {
+                        let _ = frame.drop_in_place(name)?;
+                    }
+                }
+            }
+            FileKind::Meta => {
+                let columns = self.columns.join(",");
+                frame.with_column(Series::new(
+                    RECORDED_COLUMNS_COLUMN,
+                    vec![columns.as_str(); frame.height()],
+                ))?;
+            }
+            _ => {}
+        }
+        self.inner.write(now, height, kind, frame)
+    }
+
+    fn list(&self) -> Result<Vec<SnapshotFile>> {
+        self.inner.list()
+    }
+
+    fn read(&self, file: &SnapshotFile) -> Result<DataFrame> {
+        self.inner.read(file)
+    }
+
+    fn scan(&self, file: &SnapshotFile) -> Result<LazyFrame> {
+        self.inner.scan(file)
+    }
+
+    fn size(&self, file: &SnapshotFile) -> Option<u64> {
+        self.inner.size(file)
+    }
+
+    fn remove(&self, file: &SnapshotFile) -> Result<()> {
+        self.inner.remove(file)
+    }
+
+    fn recover(&self) -> Result<Vec<PathBuf>> {
+        self.inner.recover()
+    }
+
+    fn manifest(&self) -> Result<Option<Manifest>> {
+        self.inner.manifest()
+    }
+
+    fn atomically(&self, operation: &str, change: &mut dyn FnMut() -> Result<()>) -> Result<()> {
+        self.inner.atomically(operation, change)
+    }
+}
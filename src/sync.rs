@@ -0,0 +1,184 @@
+use crate::{
+    dataset::{FileKind, SnapshotFile},
+    manifest::Manifest,
+    storage::{check_network, Storage, WriteOptions},
+};
+use anyhow::{bail, Context, Result};
+use bitcoin::Network;
+use chrono::{TimeZone, Utc};
+use polars::prelude::*;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, io::Cursor};
+use tracing::{debug, info};
+
+/// Hex SHA-256 of the parquet bytes `/v1/files/{height}/{timestamp}/{kind}` answers with
+pub const CHECKSUM_HEADER: &str = "x-wtf-sha256";
+
+/// A recorded file as `/v1/files` lists it
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct RemoteFile {
+    pub height: u64,
+    pub timestamp: i64,
+    pub kind: String,
+    /// Bytes it takes up on the peer, if it is stored on its own
+    pub size: Option<u64>,
+    /// Rows and checksum from the peer's manifest, if it keeps one
+    #[serde(default)]
+    pub rows: Option<usize>,
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+/// Which files to list or pull, everything by default
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FileFilter {
+    /// Unix timestamps, inclusive
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub min_height: Option<u64>,
+    pub max_height: Option<u64>,
+}
+
+impl FileFilter {
+    pub fn matches(&self, file: &SnapshotFile) -> bool {
+        self.from.is_none_or(|from| file.timestamp >= from)
+            && self.to.is_none_or(|to| file.timestamp <= to)
+            && self.min_height.is_none_or(|min| file.height >= min)
+            && self.max_height.is_none_or(|max| file.height <= max)
+    }
+}
+
+/// What a pull from a peer did
+#[derive(Debug, Default, Serialize)]
+pub struct Synced {
+    /// Files the peer listed within the filter
+    pub listed: usize,
+    pub pulled: usize,
+    pub bytes: u64,
+}
+
+/// Replicates the dataset of another instance serving its files with `serve --serve-files`
+pub struct PeerSync {
+    client: Client,
+    endpoint: String,
+}
+
+impl PeerSync {
+    /// `endpoint` is the peer's address like `http://other-recorder:3000`
+    pub fn new(endpoint: impl Into<String>, client: Client) -> Self {
+        PeerSync {
+            client,
+            endpoint: endpoint.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// `file` as the peer serves it: parquet, with the checksum of the bytes
+    pub fn encode(frame: &mut DataFrame) -> Result<(Vec<u8>, String)> {
+        let mut bytes = Vec::new();
+        ParquetWriter::new(&mut bytes)
+            .with_compression(WriteOptions::default().compression)
+            .finish(frame)?;
+        let checksum = Manifest::checksum(&bytes);
+        Ok((bytes, checksum))
+    }
+
+    /// Download the files within `filter` that are missing from `storage`, checking each
+    /// against its checksum and network before writing it
+    pub async fn pull(
+        &self,
+        storage: &dyn Storage,
+        filter: &FileFilter,
+        network: Network,
+    ) -> Result<Synced> {
+        let url = format!("{}/v1/files", self.endpoint);
+        let listed: Vec<RemoteFile> = self
+            .client
+            .get(&url)
+            .query(filter)
+            .send()
+            .await
+            .with_context(|| format!("GET {url}"))?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("decoding {url}"))?;
+        let present: HashSet<(u64, i64, &str)> = storage
+            .list()?
+            .into_iter()
+            .map(|file| (file.height, file.timestamp, file.kind.as_str()))
+            .collect();
+
+        let mut synced = Synced {
+            listed: listed.len(),
+            ..Default::default()
+        };
+        for remote in &listed {
+            // a newer peer may write kinds this version doesn't know
+            let Some(kind) = FileKind::parse(&remote.kind) else {
+                debug!("skipping {} file of unknown kind", remote.kind);
+                continue;
+            };
+            if present.contains(&(remote.height, remote.timestamp, kind.as_str())) {
+                continue;
+            }
+            let url = format!(
+                "{}/v1/files/{}/{}/{}",
+                self.endpoint, remote.height, remote.timestamp, remote.kind
+            );
+            let response = self
+                .client
+                .get(&url)
+                .send()
+                .await
+                .with_context(|| format!("GET {url}"))?
+                .error_for_status()?;
+            let expected = response
+                .headers()
+                .get(CHECKSUM_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from);
+            let bytes = response.bytes().await?;
+            let checksum = Manifest::checksum(&bytes);
+            match expected.or_else(|| remote.sha256.clone()) {
+                Some(expected) if expected == checksum => {}
+                Some(expected) => bail!("{url} has checksum {checksum}, expected {expected}"),
+                None => bail!("{url} came without a checksum"),
+            }
+            // the listing is from the manifest, the file from disk
+            if remote
+                .sha256
+                .as_ref()
+                .is_some_and(|listed| *listed != checksum)
+            {
+                bail!("{url} has checksum {checksum}, the peer's manifest another");
+            }
+
+            let at = Utc.timestamp_opt(remote.timestamp, 0).unwrap();
+            let mut frame = ParquetReader::new(Cursor::new(&bytes)).finish()?;
+            let file = SnapshotFile {
+                height: remote.height,
+                timestamp: remote.timestamp,
+                kind,
+                path: url.clone().into(),
+            };
+            check_network(&frame, network, &file)?;
+            if let Some(rows) = remote.rows.filter(|rows| *rows != frame.height()) {
+                bail!(
+                    "{url} has {} rows, the peer's manifest {rows}",
+                    frame.height()
+                );
+            }
+            storage.write(at, remote.height, kind, &mut frame)?;
+            debug!("pulled {url}");
+            synced.pulled += 1;
+            synced.bytes += bytes.len() as u64;
+        }
+        info!(
+            "pulled {} of {} files from {}, {} bytes",
+            synced.pulled, synced.listed, self.endpoint, synced.bytes
+        );
+        Ok(synced)
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+/// Tells systemd how the service is doing, for units with `Type=notify` and `WatchdogSec=`.
+/// Without `$NOTIFY_SOCKET`, when not started by systemd, nothing is sent.
+pub struct Notifier {
+    socket: Option<(UnixDatagram, SocketAddr)>,
+    /// Half of `WatchdogSec=`, how often the watchdog is fed
+    watchdog: Option<Duration>,
+    fed: Option<Instant>,
+    ready: bool,
+    /// Sending failed before, warned about once
+    failed: AtomicBool,
+}
+
+impl Notifier {
+    pub fn from_env() -> Self {
+        let socket = std::env::var("NOTIFY_SOCKET").ok().and_then(|path| {
+            let address = match path.strip_prefix('@') {
+                Some(name) => SocketAddr::from_abstract_name(name),
+                None => SocketAddr::from_pathname(&path),
+            };
+            match address.and_then(|address| Ok((UnixDatagram::unbound()?, address))) {
+                Ok(socket) => Some(socket),
+                Err(e) => {
+                    warn!("can't notify systemd at {path}: {e}");
+                    None
+                }
+            }
+        });
+        // the watchdog is meant for another process if WATCHDOG_PID names one
+        let ours =
+            std::env::var("WATCHDOG_PID").map_or(true, |pid| pid.parse() == Ok(std::process::id()));
+        let watchdog = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse().ok())
+            .filter(|usec| ours && *usec > 0)
+            .map(|usec: u64| Duration::from_micros(usec / 2));
+        if let (Some(_), Some(every)) = (&socket, watchdog) {
+            debug!("feeding the systemd watchdog every {every:?}");
+        }
+        Notifier {
+            socket,
+            watchdog,
+            fed: None,
+            ready: false,
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    /// How often [`Notifier::watchdog`] needs to be called, if systemd watches
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.socket.as_ref().and(self.watchdog)
+    }
+
+    /// Up and running, the first call only. `status` shows in `systemctl status`.
+    pub fn ready(&mut self, status: &str) {
+        if !self.ready {
+            self.ready = true;
+            self.send(&format!("READY=1\nSTATUS={status}"));
+        } else {
+            self.status(status);
+        }
+    }
+
+    pub fn status(&self, status: &str) {
+        self.send(&format!("STATUS={status}"));
+    }
+
+    /// Still alive, at most every half `WatchdogSec=` however often it's called. A process
+    /// hanging without calling it gets restarted.
+    pub fn watchdog(&mut self) {
+        let Some(every) = self.watchdog_interval() else {
+            return;
+        };
+        if self.fed.is_none_or(|fed| fed.elapsed() >= every) {
+            self.fed = Some(Instant::now());
+            self.send("WATCHDOG=1");
+        }
+    }
+
+    pub fn stopping(&self) {
+        self.send("STOPPING=1");
+    }
+
+    fn send(&self, state: &str) {
+        if let Some((socket, address)) = &self.socket {
+            match socket.send_to_addr(state.as_bytes(), address) {
+                Ok(_) => self.failed.store(false, Ordering::Relaxed),
+                Err(e) if !self.failed.swap(true, Ordering::Relaxed) => {
+                    warn!("notifying systemd failed: {e}")
+                }
+                Err(e) => debug!("notifying systemd failed: {e}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,453 @@
+use crate::{
+    dataset::{FileKind, SnapshotFile},
+    manifest::Manifest,
+    storage::{LocalStorage, Storage},
+};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread::{self, ThreadId},
+};
+use tracing::{debug, info, warn};
+
+/// Directory of the log, below the directory of a network's files
+pub const LOG_DIR: &str = "_log";
+/// A checkpoint of the whole table is written every this many commits, readers start from the
+/// latest one instead of the first commit
+const CHECKPOINT_INTERVAL: u64 = 100;
+/// Removed files stay on disk this long, for readers still on an older version
+const RETAIN_REMOVED_SECS: i64 = 3600;
+/// Times a commit is retried after another writer took its version
+const MAX_COMMIT_ATTEMPTS: usize = 10;
+/// Suffix of commits and checkpoints still being written. Not `tmp`, recovering the dataset
+/// removes those while another process may be committing.
+const PARTIAL_SUFFIX: &str = "partial";
+
+/// A change to the files making up the table, by path relative to the directory of the
+/// network's files
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Add { path: String },
+    Remove { path: String },
+}
+
+impl Action {
+    fn path(&self) -> &str {
+        match self {
+            Action::Add { path } | Action::Remove { path } => path,
+        }
+    }
+}
+
+/// An entry of the log, `{version}.json` with the version zero padded to 20 digits
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Commit {
+    pub version: u64,
+    pub timestamp: i64,
+    /// What made the change: `create`, `write`, `remove`, `compact`, `prune` and so on
+    pub operation: String,
+    pub actions: Vec<Action>,
+}
+
+/// The table as of a version
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TableSnapshot {
+    pub version: u64,
+    pub files: BTreeSet<String>,
+    /// Files removed, with the time they were, until they are deleted from disk
+    pub removed: BTreeMap<String, i64>,
+}
+
+impl TableSnapshot {
+    fn apply(&mut self, commit: &Commit) {
+        for action in &commit.actions {
+            match action {
+                Action::Add { path } => {
+                    self.removed.remove(path);
+                    self.files.insert(path.clone());
+                }
+                Action::Remove { path } => {
+                    if self.files.remove(path) {
+                        self.removed.insert(path.clone(), commit.timestamp);
+                    }
+                }
+            }
+        }
+        self.version = commit.version;
+    }
+}
+
+/// A log of the changes to a set of files, in the manner of Delta Lake: every change is a
+/// commit of files added and removed, numbered in sequence and never rewritten. Readers take
+/// the files of one version and see a change entirely or not at all, writers racing for a
+/// version retry with the next one.
+pub struct Table {
+    root: PathBuf,
+    log: PathBuf,
+    /// Held while committing, writers in other processes race for versions instead
+    lock: Mutex<()>,
+}
+
+impl Table {
+    /// The log of the files below `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        Table {
+            log: root.join(LOG_DIR),
+            root,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn commit_path(&self, version: u64) -> PathBuf {
+        self.log.join(format!("{version:020}.json"))
+    }
+
+    fn checkpoint_path(&self, version: u64) -> PathBuf {
+        self.log.join(format!("{version:020}.checkpoint.json"))
+    }
+
+    /// Versions of the commits and of the checkpoints in the log, in order
+    fn versions(&self) -> Result<(Vec<u64>, Vec<u64>)> {
+        let (mut commits, mut checkpoints) = (Vec::new(), Vec::new());
+        let entries = match std::fs::read_dir(&self.log) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok((commits, checkpoints)),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", self.log.display())),
+        };
+        for entry in entries {
+            let name = entry?.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            if let Some(version) = name.strip_suffix(".checkpoint.json") {
+                checkpoints.extend(version.parse::<u64>().ok());
+            } else if let Some(version) = name.strip_suffix(".json") {
+                commits.extend(version.parse::<u64>().ok());
+            }
+        }
+        commits.sort_unstable();
+        checkpoints.sort_unstable();
+        Ok((commits, checkpoints))
+    }
+
+    /// Version of the last commit, `None` before the first
+    pub fn latest_version(&self) -> Result<Option<u64>> {
+        Ok(self.versions()?.0.last().copied())
+    }
+
+    /// The table as of `version`, the latest without
+    pub fn snapshot(&self, version: Option<u64>) -> Result<TableSnapshot> {
+        let (commits, checkpoints) = self.versions()?;
+        let Some(latest) = commits.last().copied() else {
+            bail!("{} has no commits", self.log.display());
+        };
+        let version = version.unwrap_or(latest);
+        if version > latest {
+            bail!(
+                "{} has no version {version}, the latest is {latest}",
+                self.log.display()
+            );
+        }
+        let (mut snapshot, from) = match checkpoints.iter().rev().find(|cp| **cp <= version) {
+            Some(checkpoint) => (
+                self.read(&self.checkpoint_path(*checkpoint))?,
+                checkpoint + 1,
+            ),
+            None => (TableSnapshot::default(), 0),
+        };
+        let commits = commits.iter().filter(|v| (from..=version).contains(*v));
+        for (expected, version) in (from..).zip(commits) {
+            // the commits before a checkpoint are cleaned up eventually, not those after it
+            if *version != expected {
+                bail!("{} has no commit {expected}", self.log.display());
+            }
+            let commit: Commit = self.read(&self.commit_path(*version))?;
+            snapshot.apply(&commit);
+        }
+        if snapshot.version != version {
+            bail!("{} has no commit {version}", self.log.display());
+        }
+        Ok(snapshot)
+    }
+
+    fn read<T: serde::de::DeserializeOwned>(&self, path: &Path) -> Result<T> {
+        let json = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        serde_json::from_slice(&json).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    /// Append `actions` as the next commit, returning the table with them. Losing the version
+    /// to another writer moves the commit to the one after, unless that writer removed a file
+    /// `actions` removes as well.
+    pub fn commit(&self, operation: &str, actions: Vec<Action>) -> Result<TableSnapshot> {
+        let _lock = self.lock.lock().unwrap();
+        std::fs::create_dir_all(&self.log)
+            .with_context(|| format!("creating {}", self.log.display()))?;
+        for _ in 0..MAX_COMMIT_ATTEMPTS {
+            let (mut snapshot, version) = match self.latest_version()? {
+                Some(latest) => (self.snapshot(Some(latest))?, latest + 1),
+                None => (TableSnapshot::default(), 0),
+            };
+            let mut files = snapshot.files.clone();
+            for action in &actions {
+                match action {
+                    Action::Add { path } => {
+                        files.insert(path.clone());
+                    }
+                    Action::Remove { path } if !files.remove(path) => {
+                        bail!("{path} was removed by another commit before version {version}")
+                    }
+                    Action::Remove { .. } => {}
+                }
+            }
+            let commit = Commit {
+                version,
+                timestamp: Utc::now().timestamp(),
+                operation: operation.to_string(),
+                actions: actions.clone(),
+            };
+            if !self.write_new(&self.commit_path(version), &serde_json::to_vec(&commit)?)? {
+                debug!("version {version} was taken, retrying");
+                continue;
+            }
+            snapshot.apply(&commit);
+            if version > 0 && version % CHECKPOINT_INTERVAL == 0 {
+                if let Err(e) = self.checkpoint(&snapshot) {
+                    warn!("checkpoint of version {version} failed: {e:#}");
+                }
+            }
+            return Ok(snapshot);
+        }
+        bail!("{MAX_COMMIT_ATTEMPTS} versions were taken while committing, giving up")
+    }
+
+    /// Write `bytes` to `path` unless it exists, all at once. Returns whether it was written.
+    fn write_new(&self, path: &Path, bytes: &[u8]) -> Result<bool> {
+        let partial = path.with_extension(format!("{}.{PARTIAL_SUFFIX}", std::process::id()));
+        std::fs::write(&partial, bytes)
+            .with_context(|| format!("writing {}", partial.display()))?;
+        // a link fails where a rename would replace the commit of another writer
+        let linked = std::fs::hard_link(&partial, path);
+        let _ = std::fs::remove_file(&partial);
+        match linked {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e).with_context(|| format!("writing {}", path.display())),
+        }
+    }
+
+    /// Write `snapshot` as a checkpoint and clean up the log before the previous one, which
+    /// stays readable. Removed files that are gone from disk are left out.
+    fn checkpoint(&self, snapshot: &TableSnapshot) -> Result<()> {
+        let mut checkpoint = snapshot.clone();
+        checkpoint
+            .removed
+            .retain(|path, _| self.root.join(path).exists());
+        self.write_new(
+            &self.checkpoint_path(snapshot.version),
+            &serde_json::to_vec(&checkpoint)?,
+        )?;
+        let (commits, checkpoints) = self.versions()?;
+        let Some(previous) = checkpoints.iter().rev().find(|cp| **cp < snapshot.version) else {
+            return Ok(());
+        };
+        for version in commits.iter().filter(|v| *v <= previous) {
+            let _ = std::fs::remove_file(self.commit_path(*version));
+        }
+        for version in checkpoints.iter().filter(|v| *v < previous) {
+            let _ = std::fs::remove_file(self.checkpoint_path(*version));
+        }
+        debug!("checkpoint at version {}", snapshot.version);
+        Ok(())
+    }
+}
+
+/// Parquet files of a [`LocalStorage`] under a [`Table`], so that compaction, pruning and
+/// downsampling change the dataset in one commit and readers like `wtf serve` never see
+/// their half-written state. Files are listed from the latest version of the log, removed
+/// files stay on disk for an hour after, for readers that listed them before.
+pub struct TableStorage {
+    inner: LocalStorage,
+    table: Table,
+    /// Actions of the changes under way, by the thread making them
+    pending: Mutex<HashMap<ThreadId, Vec<Action>>>,
+}
+
+impl TableStorage {
+    /// The files of `inner` under the log next to them. A dataset without one gets it, the
+    /// first commit listing the files there are already.
+    pub fn open(inner: LocalStorage) -> Result<Self> {
+        let table = Table::new(inner.root());
+        let storage = TableStorage {
+            inner,
+            table,
+            pending: Mutex::new(HashMap::new()),
+        };
+        if storage.table.latest_version()?.is_none() {
+            let files = storage.inner.list()?;
+            let actions = files
+                .iter()
+                .map(|file| {
+                    Ok(Action::Add {
+                        path: storage.relative(&file.path)?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            // another process creating it at the same time adds the same files once more
+            storage.table.commit("create", actions)?;
+            info!("listing {} files in a new transaction log", files.len());
+        }
+        Ok(storage)
+    }
+
+    fn relative(&self, path: &Path) -> Result<String> {
+        let relative = path
+            .strip_prefix(self.table.root())
+            .with_context(|| format!("{} is outside the table", path.display()))?;
+        Ok(relative.to_string_lossy().into_owned())
+    }
+
+    fn file(&self, path: &str) -> Option<SnapshotFile> {
+        SnapshotFile::parse(&self.table.root().join(path))
+    }
+
+    /// Commit `action`, or add it to the change this thread is making
+    fn record(&self, operation: &str, action: Action) -> Result<()> {
+        if let Some(pending) = self
+            .pending
+            .lock()
+            .unwrap()
+            .get_mut(&thread::current().id())
+        {
+            pending.push(action);
+            return Ok(());
+        }
+        let snapshot = self.table.commit(operation, vec![action])?;
+        self.expire(&snapshot);
+        Ok(())
+    }
+
+    /// Delete the files removed long enough ago that no reader should be on a version that
+    /// had them
+    fn expire(&self, snapshot: &TableSnapshot) {
+        let before = Utc::now().timestamp() - RETAIN_REMOVED_SECS;
+        for (path, _) in snapshot.removed.iter().filter(|(_, at)| **at < before) {
+            let Some(file) = self.file(path) else {
+                continue;
+            };
+            if !file.path.exists() {
+                continue;
+            }
+            match self.inner.remove(&file) {
+                Ok(()) => debug!("deleted {path}, removed from the table"),
+                Err(e) => warn!("deleting {path} failed: {e:#}"),
+            }
+        }
+    }
+}
+
+impl Storage for TableStorage {
+    fn write(
+        &self,
+        now: DateTime<Utc>,
+        height: u64,
+        kind: FileKind,
+        frame: &mut DataFrame,
+    ) -> Result<SnapshotFile> {
+        let file = self.inner.write(now, height, kind, frame)?;
+        let path = self.relative(&file.path)?;
+        self.record("write", Action::Add { path })?;
+        Ok(file)
+    }
+
+    fn list(&self) -> Result<Vec<SnapshotFile>> {
+        let snapshot = self.table.snapshot(None)?;
+        let mut files: Vec<SnapshotFile> = snapshot
+            .files
+            .iter()
+            .filter_map(|path| self.file(path))
+            .collect();
+        SnapshotFile::sort(&mut files);
+        Ok(files)
+    }
+
+    fn read(&self, file: &SnapshotFile) -> Result<DataFrame> {
+        self.inner.read(file)
+    }
+
+    fn scan(&self, file: &SnapshotFile) -> Result<LazyFrame> {
+        self.inner.scan(file)
+    }
+
+    fn size(&self, file: &SnapshotFile) -> Option<u64> {
+        self.inner.size(file)
+    }
+
+    /// Removes `file` from the table, it is deleted from disk an hour later
+    fn remove(&self, file: &SnapshotFile) -> Result<()> {
+        let path = self.relative(&file.path)?;
+        self.record("remove", Action::Remove { path })
+    }
+
+    /// Recover the files like [`LocalStorage`] does and remove those quarantined from the
+    /// table
+    fn recover(&self) -> Result<Vec<PathBuf>> {
+        let recovered = self.inner.recover()?;
+        let snapshot = self.table.snapshot(None)?;
+        let mut actions = Vec::new();
+        for path in &recovered {
+            let Some(quarantined) = path.to_str().and_then(|p| p.strip_suffix(".corrupt")) else {
+                continue;
+            };
+            let quarantined = self.relative(Path::new(quarantined))?;
+            if snapshot.files.contains(&quarantined) {
+                actions.push(Action::Remove { path: quarantined });
+            }
+        }
+        if !actions.is_empty() {
+            self.table.commit("recover", actions)?;
+        }
+        Ok(recovered)
+    }
+
+    fn manifest(&self) -> Result<Option<Manifest>> {
+        self.inner.manifest()
+    }
+
+    /// One commit for everything `change` writes and removes. Files written by a change that
+    /// fails are never part of the table.
+    fn atomically(&self, operation: &str, change: &mut dyn FnMut() -> Result<()>) -> Result<()> {
+        let id = thread::current().id();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            // part of a change already under way
+            if pending.contains_key(&id) {
+                drop(pending);
+                return change();
+            }
+            pending.insert(id, Vec::new());
+        }
+        let result = change();
+        let actions = self.pending.lock().unwrap().remove(&id).unwrap_or_default();
+        result?;
+        if actions.is_empty() {
+            return Ok(());
+        }
+        let paths: Vec<&str> = actions.iter().map(Action::path).collect();
+        debug!("{operation}: {}", paths.join(", "));
+        let snapshot = self.table.commit(operation, actions)?;
+        self.expire(&snapshot);
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use crate::{record::Mempool, replay::Snapshot, score::Assembler};
+use bitcoin::{Denomination, Txid};
+
+/// Consensus limit of a block
+pub const BLOCK_WEIGHT: u64 = 4_000_000;
+/// Left for the header and coinbase, like Bitcoin Core's block assembler does
+const RESERVED_WEIGHT: u64 = 4_000;
+
+/// A block the mempool would fill if it were mined now
+#[derive(Debug, Clone, Default)]
+pub struct ProjectedBlock {
+    /// In mining order. Histogram snapshots replay without txids, their blocks list none.
+    pub txids: Vec<Txid>,
+    pub weight: u64,
+    pub fees_sat: f64,
+    /// Lowest package fee rate in the block, sat/vB
+    pub min_fee_rate: f64,
+    pub max_fee_rate: f64,
+    /// A package didn't fit and was left for a later block, so the block is as full as it gets
+    pub full: bool,
+}
+
+/// Transactions that go into the same block, in the order they are picked
+struct Package {
+    fee_rate: f64,
+    /// Txid if the snapshot recorded it, weight and fee in sats, ancestors first
+    txs: Vec<(Option<Txid>, u64, f64)>,
+}
+
+/// Projected blocks of a mempool, the next block first
+#[derive(Debug, Clone, Default)]
+pub struct Template {
+    pub blocks: Vec<ProjectedBlock>,
+}
+
+impl Template {
+    /// Fill at most `max_blocks` blocks from the mempool, one after the other as the block
+    /// assembler would: each block takes the packages with the best ancestor score that fit,
+    /// updating the scores of their descendants as they go in, and what doesn't fit is left for
+    /// a later block. Whatever doesn't fit into `max_blocks` is left out.
+    pub fn build(mempool: &Mempool, max_blocks: usize) -> Self {
+        let capacity = BLOCK_WEIGHT - RESERVED_WEIGHT;
+        let mut assembler = Assembler::new(mempool);
+        let mut blocks = Vec::new();
+        while blocks.len() < max_blocks {
+            let (packages, left_out) = assembler.next_block(capacity);
+            if packages.is_empty() {
+                break;
+            }
+            let mut block = ProjectedBlock {
+                min_fee_rate: f64::INFINITY,
+                max_fee_rate: f64::NEG_INFINITY,
+                full: left_out,
+                ..Default::default()
+            };
+            for package in packages {
+                for (txid, entry) in package
+                    .txids
+                    .iter()
+                    .filter_map(|txid| mempool.get_key_value(txid))
+                {
+                    block.txids.push(*txid);
+                    block.weight += entry.weight;
+                    block.fees_sat += entry.modified_fee.to_float_in(Denomination::Satoshi);
+                }
+                block.min_fee_rate = block.min_fee_rate.min(package.fee_rate);
+                block.max_fee_rate = block.max_fee_rate.max(package.fee_rate);
+            }
+            blocks.push(block);
+        }
+        Template { blocks }
+    }
+
+    /// Pack a recorded mempool. Dependencies aren't recorded, every transaction is taken on
+    /// the effective fee rate of its package as recorded, on its own where that is unknown.
+    pub fn from_snapshot(snapshot: &Snapshot, max_blocks: usize) -> Self {
+        let mut packages: Vec<Package> = snapshot
+            .transactions
+            .iter()
+            .filter(|(_, tx)| tx.weight > 0.)
+            .map(|(txid, tx)| Package {
+                fee_rate: tx.mining_fee_rate(),
+                txs: vec![(txid.parse().ok(), tx.weight as u64, tx.fee_sat)],
+            })
+            .collect();
+        packages.sort_by(|a, b| b.fee_rate.total_cmp(&a.fee_rate));
+        Self::pack(packages, max_blocks)
+    }
+
+    fn pack(packages: impl IntoIterator<Item = Package>, max_blocks: usize) -> Self {
+        let capacity = BLOCK_WEIGHT - RESERVED_WEIGHT;
+        let mut blocks: Vec<ProjectedBlock> = Vec::new();
+
+        for package in packages {
+            let weight: u64 = package.txs.iter().map(|(_, weight, _)| weight).sum();
+            // smaller packages still fill up a block a larger one didn't fit in
+            let mut index = 0;
+            while index < blocks.len() && blocks[index].weight + weight > capacity {
+                blocks[index].full = true;
+                index += 1;
+            }
+            if index >= max_blocks {
+                continue;
+            }
+            if index == blocks.len() {
+                blocks.push(ProjectedBlock {
+                    min_fee_rate: package.fee_rate,
+                    max_fee_rate: package.fee_rate,
+                    ..Default::default()
+                });
+            }
+
+            let block = &mut blocks[index];
+            for (txid, _, fee) in package.txs {
+                block.txids.extend(txid);
+                block.fees_sat += fee;
+            }
+            block.weight += weight;
+            block.min_fee_rate = block.min_fee_rate.min(package.fee_rate);
+            block.max_fee_rate = block.max_fee_rate.max(package.fee_rate);
+        }
+        Template { blocks }
+    }
+
+    /// Fee rate (sat/vB) that gets a transaction into block `n`, 1 being the next one. `None`
+    /// when the block has room left, any fee rate the node relays will do.
+    pub fn fee_rate_for_block(&self, n: usize) -> Option<f64> {
+        let block = self.blocks.get(n.checked_sub(1)?)?;
+        block.full.then_some(block.min_fee_rate)
+    }
+
+    /// [`Template::fee_rate_for_block`] of every projected block
+    pub fn boundaries(&self) -> Vec<Option<f64>> {
+        (1..=self.blocks.len())
+            .map(|n| self.fee_rate_for_block(n))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{record::MempoolEntry, replay::Transaction};
+    use bitcoin::{hashes::Hash, Amount, Wtxid};
+
+    fn txid(name: &str) -> Txid {
+        Txid::hash(name.as_bytes())
+    }
+
+    /// `name` paying `fee_rate` sat/vB for `vsize`, spending outputs of `parents`
+    fn add(mempool: &mut Mempool, name: &str, vsize: u64, fee_rate: u64, parents: &[&str]) {
+        let fee = Amount::from_sat(vsize * fee_rate);
+        let depends: Vec<Txid> = parents.iter().map(|parent| txid(parent)).collect();
+        for parent in &depends {
+            let entry = mempool.get_mut(parent).unwrap();
+            entry.spent_by = entry.spent_by.iter().copied().chain([txid(name)]).collect();
+        }
+        let entry = MempoolEntry {
+            wtxid: Wtxid::all_zeros(),
+            vsize,
+            weight: vsize * 4,
+            time: 1_700_000_000,
+            height: 800_000,
+            fee,
+            modified_fee: fee,
+            ancestor_fees: fee,
+            descendant_fees: fee,
+            ancestor_count: 1 + depends.len() as u32,
+            descendant_count: 1,
+            descendant_size: vsize,
+            bip125_replaceable: false,
+            unbroadcast: None,
+            depends: depends.into(),
+            spent_by: Box::new([]),
+        };
+        mempool.insert(txid(name), entry);
+    }
+
+    #[test]
+    fn child_pays_for_its_parent() {
+        let mut mempool = Mempool::new();
+        add(&mut mempool, "parent", 1_000, 1, &[]);
+        add(&mut mempool, "child", 1_000, 100, &["parent"]);
+        add(&mut mempool, "other", 1_000, 20, &[]);
+        let template = Template::build(&mempool, 3);
+        assert_eq!(template.blocks.len(), 1);
+        let block = &template.blocks[0];
+        assert_eq!(block.txids, [txid("parent"), txid("child"), txid("other")]);
+        assert_eq!(block.weight, 12_000);
+        assert_eq!(block.fees_sat, 121_000.);
+        assert_eq!(block.min_fee_rate, 20.);
+        assert_eq!(block.max_fee_rate, 50.5);
+        // room left, any fee rate gets in
+        assert_eq!(template.fee_rate_for_block(1), None);
+    }
+
+    #[test]
+    fn what_doesnt_fit_is_left_for_the_next_block() {
+        let mut mempool = Mempool::new();
+        for (name, fee_rate) in [("a", 30), ("b", 20), ("c", 10)] {
+            add(&mut mempool, name, 450_000, fee_rate, &[]);
+        }
+        let template = Template::build(&mempool, 3);
+        assert_eq!(template.blocks.len(), 2);
+        assert_eq!(template.blocks[0].txids, [txid("a"), txid("b")]);
+        assert!(template.blocks[0].full);
+        assert_eq!(template.blocks[1].txids, [txid("c")]);
+        assert_eq!(template.boundaries(), [Some(20.), None]);
+        assert_eq!(Template::build(&mempool, 1).blocks.len(), 1);
+        assert!(Template::build(&Mempool::new(), 3).blocks.is_empty());
+    }
+
+    #[test]
+    fn recorded_snapshots_pack_on_the_effective_fee_rate() {
+        let mut snapshot = Snapshot::new(800_000, 1_700_000_000);
+        // a parent paid for by a child, at the rate of the pair
+        for (key, fee_rate, effective_fee_rate) in [
+            ("parent", 1., Some(50.)),
+            ("other", 20., None),
+            ("low", 10., None),
+        ] {
+            let tx = Transaction {
+                weight: 1_900_000.,
+                fee_sat: fee_rate * 475_000.,
+                first_seen_at: None,
+                effective_fee_rate,
+            };
+            snapshot.transactions.insert(key.to_string(), tx);
+        }
+        let template = Template::from_snapshot(&snapshot, 2);
+        assert!(template.blocks[0].full);
+        assert_eq!(template.fee_rate_for_block(1), Some(20.));
+        assert_eq!(template.blocks[1].min_fee_rate, 10.);
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::{bail, Context, Result};
+use axum::extract::connect_info::Connected;
+use hyper::server::accept::{self, Accept};
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use std::{
+    io::{self, BufReader},
+    net::SocketAddr,
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context as TaskContext, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::debug;
+
+/// Clients that haven't finished the handshake by then are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Handshaken connections waiting for the server to take them
+const BACKLOG: usize = 64;
+
+/// HTTPS for `serve` with a certificate and key in PEM files, as Let's Encrypt and `openssl`
+/// write them. The files are read once, a renewed certificate needs a restart.
+pub struct Tls {
+    acceptor: TlsAcceptor,
+}
+
+impl Tls {
+    /// `cert` holds the certificate chain, leaf first, `key` its PKCS#8, RSA or EC private key
+    pub fn load(cert: &Path, key: &Path) -> Result<Self> {
+        let certs = rustls_pemfile::certs(&mut Self::open(cert)?)
+            .with_context(|| format!("reading certificates from {}", cert.display()))?;
+        if certs.is_empty() {
+            bail!("{} has no certificates", cert.display());
+        }
+        let key = rustls_pemfile::read_all(&mut Self::open(key)?)
+            .with_context(|| format!("reading the private key from {}", key.display()))?
+            .into_iter()
+            .find_map(|item| match item {
+                rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+                _ => None,
+            })
+            .with_context(|| format!("{} has no private key", key.display()))?;
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs.into_iter().map(Certificate).collect(), key)
+            .context("the private key isn't usable")?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Tls {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+        })
+    }
+
+    fn open(path: &Path) -> Result<BufReader<std::fs::File>> {
+        let file =
+            std::fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        Ok(BufReader::new(file))
+    }
+
+    /// Connections to `listen` once their handshake is done, for `hyper::Server::builder`.
+    /// Handshakes run apart from accepting so slow or failing clients don't hold up others.
+    pub async fn bind(
+        self,
+        listen: SocketAddr,
+    ) -> Result<impl Accept<Conn = TlsConnection, Error = io::Error>> {
+        let listener = TcpListener::bind(listen)
+            .await
+            .with_context(|| format!("binding {listen}"))?;
+        let (sender, receiver) = mpsc::channel(BACKLOG);
+        tokio::spawn(async move {
+            loop {
+                let (stream, remote) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // like running out of file descriptors, which takes a moment to pass
+                        debug!("accepting failed: {e}");
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                let (acceptor, handshaken) = (self.acceptor.clone(), sender.clone());
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = handshaken.send(Ok(TlsConnection { stream, remote })).await;
+                        }
+                        Ok(Err(e)) => debug!("{remote}: TLS handshake failed: {e}"),
+                        Err(_) => debug!("{remote}: TLS handshake timed out"),
+                    }
+                });
+                if sender.is_closed() {
+                    break;
+                }
+            }
+        });
+        Ok(accept::from_stream(ReceiverStream::new(receiver)))
+    }
+}
+
+/// A client's connection after the handshake, knowing its address for `ConnectInfo`
+pub struct TlsConnection {
+    stream: TlsStream<TcpStream>,
+    remote: SocketAddr,
+}
+
+impl Connected<&TlsConnection> for SocketAddr {
+    fn connect_info(connection: &TlsConnection) -> Self {
+        connection.remote
+    }
+}
+
+impl AsyncRead for TlsConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
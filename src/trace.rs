@@ -0,0 +1,276 @@
+use crate::{dataset::FileKind, query::Query, replay::Replay, storage::Storage};
+use anyhow::{bail, Result};
+use polars::prelude::*;
+use serde::Serialize;
+
+/// Something that happened to a transaction, as recorded
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TraceEvent {
+    /// First recorded in the snapshot taken at `timestamp`, or again after leaving
+    Entered {
+        timestamp: i64,
+        height: u64,
+        /// sat/vB of the package it would be mined with
+        effective_fee_rate: Option<f64>,
+        /// % of the mempool's vsize ahead of it
+        queue_percentile: Option<f64>,
+    },
+    /// Spent an input of `txid`, which left the mempool in the same snapshot
+    Replaces { timestamp: i64, txid: String },
+    /// Gone from the mempool in the snapshot taken at `timestamp`
+    Left {
+        timestamp: i64,
+        height: u64,
+        /// `mined`, `rbf-replaced`, `expired`, `purged` (`evicted-below-minfee` in files
+        /// `wtf migrate` hasn't upgraded) or `unknown`
+        reason: Option<String>,
+    },
+    /// `txid` spent one of its inputs and took its place
+    ReplacedBy {
+        timestamp: i64,
+        txid: String,
+        fee_rate_sat_vb: Option<f64>,
+    },
+    /// In the block at `height`, seen at `timestamp`
+    Confirmed {
+        timestamp: i64,
+        height: u64,
+        block_hash: Option<String>,
+        wait_blocks: Option<u64>,
+        wait_secs: Option<i64>,
+    },
+    /// Notified with ZMQ `rawtx` but never in the mempool
+    Rejected { timestamp: i64 },
+}
+
+impl TraceEvent {
+    pub fn timestamp(&self) -> i64 {
+        match self {
+            TraceEvent::Entered { timestamp, .. }
+            | TraceEvent::Replaces { timestamp, .. }
+            | TraceEvent::Left { timestamp, .. }
+            | TraceEvent::ReplacedBy { timestamp, .. }
+            | TraceEvent::Confirmed { timestamp, .. }
+            | TraceEvent::Rejected { timestamp } => *timestamp,
+        }
+    }
+}
+
+/// The life of a transaction through the dataset, for stuck transactions and for checking
+/// on the recorder
+#[derive(Debug, Clone, Serialize)]
+pub struct TxTrace {
+    pub txid: String,
+    /// When the node first saw it
+    pub first_seen_at: Option<u64>,
+    pub fee_sat: Option<f64>,
+    pub vsize: Option<u64>,
+    pub fee_rate_sat_vb: Option<f64>,
+    /// Height and time of every snapshot it was in the mempool in
+    pub snapshots: Vec<(u64, i64)>,
+    /// Oldest first
+    pub events: Vec<TraceEvent>,
+}
+
+impl TxTrace {
+    /// Trace `txid` through the full, delta, block and rejected files of the dataset
+    #[tracing::instrument(skip(storage))]
+    pub fn find(storage: &dyn Storage, txid: &str) -> Result<Self> {
+        let mut trace = TxTrace {
+            txid: txid.to_string(),
+            first_seen_at: None,
+            fee_sat: None,
+            vsize: None,
+            fee_rate_sat_vb: None,
+            snapshots: Vec::new(),
+            events: Vec::new(),
+        };
+        let mut deltas = None;
+        for (table, frame) in Query::lookup(storage, txid)? {
+            match table {
+                "deltas" => deltas = Some(frame),
+                "blocks" => trace.confirmations(&frame)?,
+                "rejected" => {
+                    let timestamps = frame.column("snapshot_timestamp")?.i64()?;
+                    for timestamp in timestamps.into_iter().flatten() {
+                        trace.events.push(TraceEvent::Rejected { timestamp });
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(deltas) = deltas {
+            // full before delta when both were written in the same second
+            let deltas = deltas.sort(["snapshot_timestamp", "kind"], vec![false, true])?;
+            trace.presence(storage, &deltas)?;
+        }
+        trace.replacements(storage)?;
+        if trace.events.is_empty() {
+            bail!("{txid} is not in the recorded dataset");
+        }
+        trace.events.sort_by_key(TraceEvent::timestamp);
+        Ok(trace)
+    }
+
+    /// Entries and exits from the rows of `deltas`, oldest first, and the snapshots in
+    /// between
+    fn presence(&mut self, storage: &dyn Storage, deltas: &DataFrame) -> Result<()> {
+        let heights = deltas.column("height")?.u64()?;
+        let timestamps = deltas.column("snapshot_timestamp")?.i64()?;
+        let kinds = deltas.column("kind")?.utf8()?;
+        let weights = deltas.column("weight")?.f64()?;
+        let fees = deltas.column("fee_sat")?.f64()?;
+        let first_seen = deltas.column("first_seen_at")?.u64()?;
+        // files written before these were recorded lack them
+        let reasons = Self::optional(deltas, "removal_reason", DataType::Utf8)?;
+        let reasons = reasons.utf8()?;
+        let replaces = Self::optional(deltas, "replaces_txid", DataType::Utf8)?;
+        let replaces = replaces.utf8()?;
+        let effective = Self::optional(deltas, "effective_fee_rate", DataType::Float64)?;
+        let effective = effective.f64()?;
+        let queue = Self::optional(deltas, "queue_percentile", DataType::Float64)?;
+        let queue = queue.f64()?;
+        let vsizes = Self::optional(deltas, "vsize", DataType::UInt64)?;
+        let vsizes = vsizes.u64()?;
+
+        // (timestamp, kind, present) of every row
+        let mut changes = Vec::new();
+        let mut present = false;
+        for row in 0..deltas.height() {
+            let (Some(height), Some(timestamp), Some(weight)) =
+                (heights.get(row), timestamps.get(row), weights.get(row))
+            else {
+                continue;
+            };
+            let kind = kinds.get(row).and_then(FileKind::parse);
+            if weight >= 0. {
+                if self.fee_sat.is_none() {
+                    self.first_seen_at = first_seen.get(row);
+                    self.fee_sat = fees.get(row);
+                    self.vsize = vsizes.get(row).or(Some((weight / 4.).ceil() as u64));
+                    self.fee_rate_sat_vb = fees.get(row).map(|fee| fee / (weight / 4.));
+                }
+                // full snapshots list it again at every height
+                if !present {
+                    self.events.push(TraceEvent::Entered {
+                        timestamp,
+                        height,
+                        effective_fee_rate: effective.get(row),
+                        queue_percentile: queue.get(row),
+                    });
+                }
+                if let Some(txid) = replaces.get(row) {
+                    self.events.push(TraceEvent::Replaces {
+                        timestamp,
+                        txid: txid.to_string(),
+                    });
+                }
+                present = true;
+            } else {
+                self.events.push(TraceEvent::Left {
+                    timestamp,
+                    height,
+                    reason: reasons.get(row).map(str::to_string),
+                });
+                present = false;
+            }
+            changes.push((timestamp, kind, weight >= 0.));
+        }
+
+        // in every snapshot from entering to leaving, and out of a full snapshot without it
+        let Some(first) = changes.first().map(|(timestamp, _, _)| *timestamp) else {
+            return Ok(());
+        };
+        let mut changes = changes.into_iter().peekable();
+        let mut present = false;
+        for entry in Replay::new(storage)?.entries() {
+            if entry.timestamp < first || entry.kind == FileKind::Histogram {
+                continue;
+            }
+            match changes.peek() {
+                Some((timestamp, kind, now_present))
+                    if *timestamp == entry.timestamp
+                        && (kind.is_none() || *kind == Some(entry.kind)) =>
+                {
+                    present = *now_present;
+                    changes.next();
+                }
+                _ if entry.kind == FileKind::Full => present = false,
+                _ => {}
+            }
+            if present {
+                self.snapshots.push((entry.height, entry.timestamp));
+            } else if changes.peek().is_none() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// The blocks that confirmed it, more than one after a reorg
+    fn confirmations(&mut self, blocks: &DataFrame) -> Result<()> {
+        let heights = blocks.column("confirmed_height")?.u64()?;
+        let confirmed_at = blocks.column("confirmed_at")?.i64()?;
+        let hashes = Self::optional(blocks, "block_hash", DataType::Utf8)?;
+        let hashes = hashes.utf8()?;
+        let wait_blocks = Self::optional(blocks, "wait_blocks", DataType::UInt64)?;
+        let wait_blocks = wait_blocks.u64()?;
+        let wait_secs = Self::optional(blocks, "wait_secs", DataType::Int64)?;
+        let wait_secs = wait_secs.i64()?;
+        for row in 0..blocks.height() {
+            let (Some(height), Some(timestamp)) = (heights.get(row), confirmed_at.get(row)) else {
+                continue;
+            };
+            self.events.push(TraceEvent::Confirmed {
+                timestamp,
+                height,
+                block_hash: hashes.get(row).map(str::to_string),
+                wait_blocks: wait_blocks.get(row),
+                wait_secs: wait_secs.get(row),
+            });
+        }
+        Ok(())
+    }
+
+    /// Transactions recorded replacing it
+    fn replacements(&mut self, storage: &dyn Storage) -> Result<()> {
+        let mut context = Query::context(storage)?;
+        if !context.get_tables().iter().any(|table| table == "deltas") {
+            return Ok(());
+        }
+        let deltas = context.execute("SELECT * FROM deltas")?;
+        if deltas.schema()?.get("replaces_txid").is_none() {
+            return Ok(());
+        }
+        let replacing = deltas
+            .filter(col("replaces_txid").eq(lit(self.txid.as_str())))
+            .select([
+                col("snapshot_timestamp"),
+                col("txid"),
+                col("fee_rate_sat_vb"),
+            ])
+            .collect()?;
+        let timestamps = replacing.column("snapshot_timestamp")?.i64()?;
+        let txids = replacing.column("txid")?.utf8()?;
+        let fee_rates = replacing.column("fee_rate_sat_vb")?.f64()?;
+        for row in 0..replacing.height() {
+            if let (Some(timestamp), Some(txid)) = (timestamps.get(row), txids.get(row)) {
+                self.events.push(TraceEvent::ReplacedBy {
+                    timestamp,
+                    txid: txid.to_string(),
+                    fee_rate_sat_vb: fee_rates.get(row),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Column `name` of `frame`, all nulls if it has none
+    fn optional(frame: &DataFrame, name: &str, dtype: DataType) -> Result<Series> {
+        Ok(match frame.column(name) {
+            Ok(column) => column.clone(),
+            Err(_) => Series::full_null(name, frame.height(), &dtype),
+        })
+    }
+}
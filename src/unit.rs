@@ -0,0 +1,107 @@
+use anyhow::Result;
+use polars::prelude::*;
+use serde::Deserialize;
+
+/// Denomination of the fee rates put out. Wallets and Bitcoin Core's RPCs differ: Core
+/// settings take sat/kvB, `estimatesmartfee` answers BTC/kvB and weight-based code counts
+/// sat/WU. Converted fee rates are rounded up to what the unit can express, so paying one
+/// never falls below the estimate.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum, utoipa::ToSchema,
+)]
+pub enum FeeUnit {
+    #[default]
+    #[value(name = "sat/vB")]
+    #[serde(rename = "sat/vB", alias = "sat/vb")]
+    SatVb,
+    /// Whole sat/kvB
+    #[value(name = "sat/kvB")]
+    #[serde(rename = "sat/kvB", alias = "sat/kvb")]
+    SatKvb,
+    /// Eight decimals, one sat/kvB
+    #[value(name = "btc/kvB")]
+    #[serde(rename = "BTC/kvB", alias = "btc/kvB", alias = "btc/kvb")]
+    BtcKvb,
+    /// Three decimals
+    #[value(name = "sat/WU")]
+    #[serde(rename = "sat/WU", alias = "sat/wu")]
+    SatWu,
+}
+
+impl FeeUnit {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FeeUnit::SatVb => "sat/vB",
+            FeeUnit::SatKvb => "sat/kvB",
+            FeeUnit::BtcKvb => "BTC/kvB",
+            FeeUnit::SatWu => "sat/WU",
+        }
+    }
+
+    /// Ending of column names in this unit, like `sat_vb`
+    pub fn suffix(self) -> &'static str {
+        match self {
+            FeeUnit::SatVb => "sat_vb",
+            FeeUnit::SatKvb => "sat_kvb",
+            FeeUnit::BtcKvb => "btc_kvb",
+            FeeUnit::SatWu => "sat_wu",
+        }
+    }
+
+    /// `sat_vb` in this unit. sat/vB is passed through as estimated.
+    pub fn convert(self, sat_vb: f64) -> f64 {
+        // the multiplication can land a hair above a whole sat/kvB, which mustn't round up
+        let sat_kvb = || (sat_vb * 1000. - 1e-6).ceil().max(0.);
+        match self {
+            FeeUnit::SatVb => sat_vb,
+            FeeUnit::SatKvb => sat_kvb(),
+            FeeUnit::BtcKvb => sat_kvb() / 1e8,
+            FeeUnit::SatWu => (sat_kvb() / 4.).ceil() / 1000.,
+        }
+    }
+
+    /// `sat_vb` in this unit with its decimals, without the unit
+    pub fn number(self, sat_vb: f64) -> String {
+        let rate = self.convert(sat_vb);
+        match self {
+            FeeUnit::SatVb => format!("{rate:.2}"),
+            FeeUnit::SatKvb => format!("{rate:.0}"),
+            FeeUnit::BtcKvb => format!("{rate:.8}"),
+            FeeUnit::SatWu => format!("{rate:.3}"),
+        }
+    }
+
+    /// `sat_vb` in this unit, like `0.00012345 BTC/kvB`
+    pub fn format(self, sat_vb: f64) -> String {
+        format!("{} {}", self.number(sat_vb), self.as_str())
+    }
+
+    /// Convert the `*_sat_vb` columns of `frame` and rename them after this unit. A column
+    /// already in the unit, like the `fee_rate_sat_wu` of deltas, is replaced by the converted
+    /// one so both round alike.
+    pub fn convert_frame(self, mut frame: DataFrame) -> Result<DataFrame> {
+        if self == FeeUnit::SatVb {
+            return Ok(frame);
+        }
+        let names: Vec<String> = frame
+            .get_column_names()
+            .into_iter()
+            .filter(|name| name.ends_with("_sat_vb"))
+            .map(String::from)
+            .collect();
+        for name in names {
+            let rates = frame.column(&name)?.cast(&DataType::Float64)?;
+            let renamed = format!("{}{}", name.trim_end_matches("sat_vb"), self.suffix());
+            let mut converted: Series = rates.f64()?.apply(|rate| self.convert(rate)).into_series();
+            converted.rename(&renamed);
+            if frame.column(&renamed).is_ok() {
+                frame.replace(&renamed, converted)?;
+                let _ = frame.drop_in_place(&name)?;
+            } else {
+                frame.replace(&name, converted)?;
+                frame.rename(&name, &renamed)?;
+            }
+        }
+        Ok(frame)
+    }
+}
@@ -0,0 +1,466 @@
+use crate::{
+    dataset::{FileKind, SnapshotFile},
+    replay::{Entry, Replay, Snapshot},
+    storage::Storage,
+};
+use anyhow::Result;
+use chrono::{NaiveDate, TimeZone, Utc};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+use tracing::{debug, info};
+
+/// Transactions may be first seen this long after the snapshot they're in, the node's clock
+/// and the recorder's don't agree to the second
+const CLOCK_SLACK_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Issue {
+    /// The file can't be read, or is missing a column
+    Unreadable,
+    /// A delta without the full snapshot of its height before it
+    NoFullSnapshot,
+    /// A lower height than the snapshot before, without a reorg
+    OutOfOrder,
+    /// A delta of another block than the full snapshot of its height, a reorg to a block at
+    /// the same height without a full snapshot of it
+    ForkedDelta,
+    /// Rows removing a transaction that isn't in the mempool
+    UnknownRemoval,
+    /// Rows adding a transaction that is in the mempool already
+    DuplicateAddition,
+    /// Rows without a txid, weight or fee, with a negative fee or a count of zero
+    InvalidRow,
+    /// Rows of transactions first seen after the snapshot was taken
+    SeenLater,
+    /// A file the manifest doesn't list
+    Unlisted,
+    /// A file the manifest lists that isn't there
+    Missing,
+    /// A file of another size or checksum than the manifest has
+    Mismatch,
+}
+
+impl Issue {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Issue::Unreadable => "unreadable",
+            Issue::NoFullSnapshot => "no-full-snapshot",
+            Issue::OutOfOrder => "out-of-order",
+            Issue::ForkedDelta => "forked-delta",
+            Issue::UnknownRemoval => "unknown-removal",
+            Issue::DuplicateAddition => "duplicate-addition",
+            Issue::InvalidRow => "invalid-row",
+            Issue::SeenLater => "seen-later",
+            Issue::Unlisted => "unlisted",
+            Issue::Missing => "missing",
+            Issue::Mismatch => "mismatch",
+        }
+    }
+}
+
+/// Something wrong with a snapshot
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    pub height: u64,
+    pub timestamp: i64,
+    pub kind: FileKind,
+    pub path: String,
+    pub issue: Issue,
+    pub detail: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Verified {
+    pub days: usize,
+    pub snapshots: usize,
+    /// Deltas after an unreadable snapshot of their height, they can't be checked
+    pub unchecked: usize,
+    pub problems: Vec<Problem>,
+}
+
+pub struct Verify;
+
+impl Verify {
+    /// Replay the deltas of every height from its full snapshot, or only those of `day`, and
+    /// check that each applies cleanly to the mempool before it
+    #[tracing::instrument(skip(storage))]
+    pub fn run(storage: &dyn Storage, day: Option<NaiveDate>) -> Result<Verified> {
+        let replay = Replay::new(storage)?;
+        let day_of = |timestamp: i64| Utc.timestamp_opt(timestamp, 0).unwrap().date_naive();
+        let in_day = |entry: &Entry| day.is_none_or(|day| day_of(entry.timestamp) == day);
+        // heights spanning midnight start from a full snapshot of the day before
+        let heights: HashSet<u64> = replay
+            .entries()
+            .iter()
+            .filter(|entry| in_day(entry))
+            .map(|entry| entry.height)
+            .collect();
+        let reorgs: HashSet<i64> = storage
+            .list()?
+            .into_iter()
+            .filter(|file| file.kind == FileKind::Reorg)
+            .map(|file| file.timestamp)
+            .collect();
+
+        let mut verified = Verified::default();
+        let mut days: BTreeSet<NaiveDate> = BTreeSet::new();
+        // the height and block being replayed and its mempool, `None` after an unreadable
+        // snapshot
+        let mut state: Option<(u64, Option<String>, Option<HashSet<String>>)> = None;
+        let mut full_at: Option<i64> = None;
+        let mut previous_height: Option<u64> = None;
+        for entry in replay
+            .entries()
+            .iter()
+            .filter(|entry| heights.contains(&entry.height))
+        {
+            let report = in_day(entry);
+            let mut problems = Vec::new();
+            if report {
+                verified.snapshots += 1;
+                days.insert(day_of(entry.timestamp));
+            }
+            if previous_height.is_some_and(|previous| previous > entry.height)
+                && !reorgs.contains(&entry.timestamp)
+            {
+                problems.push((
+                    Issue::OutOfOrder,
+                    format!("height {} after {}", entry.height, previous_height.unwrap()),
+                ));
+            }
+            previous_height = Some(entry.height);
+
+            if matches!(entry.kind, FileKind::Full | FileKind::Histogram) {
+                state = Some((entry.height, None, Some(HashSet::new())));
+                full_at = Some(entry.timestamp);
+            }
+            // the full snapshot written on shutdown has the changes of the last delta already
+            let subsumed = entry.kind == FileKind::Delta && full_at == Some(entry.timestamp);
+            match state.as_mut() {
+                Some((height, block_hash, Some(pool))) if *height == entry.height => {
+                    let checked =
+                        Self::check(&replay, entry, block_hash, pool, subsumed, &mut problems);
+                    if let Err(e) = checked {
+                        problems.push((Issue::Unreadable, format!("{e:#}")));
+                        state = Some((entry.height, None, None));
+                    }
+                }
+                Some((height, _, None)) if *height == entry.height => {
+                    if report {
+                        verified.unchecked += 1;
+                    }
+                }
+                _ => problems.push((
+                    Issue::NoFullSnapshot,
+                    format!("no full snapshot of height {} before it", entry.height),
+                )),
+            }
+
+            if !report {
+                continue;
+            }
+            let path = replay.file(entry).path.display().to_string();
+            verified
+                .problems
+                .extend(problems.into_iter().map(|(issue, detail)| Problem {
+                    height: entry.height,
+                    timestamp: entry.timestamp,
+                    kind: entry.kind,
+                    path: path.clone(),
+                    issue,
+                    detail,
+                }));
+        }
+        verified.days = days.len();
+        info!(
+            "verified {} snapshots of {} days, {} problems",
+            verified.snapshots,
+            verified.days,
+            verified.problems.len()
+        );
+        Ok(verified)
+    }
+
+    /// Compare the files of the dataset, or of `day`, with its manifest: sizes only, or with
+    /// `checksums` the SHA-256 of every file too. Returns how many files were compared, `None`
+    /// if the storage keeps no manifest.
+    #[tracing::instrument(skip(storage))]
+    pub fn manifest(
+        storage: &dyn Storage,
+        day: Option<NaiveDate>,
+        checksums: bool,
+    ) -> Result<Option<(usize, Vec<Problem>)>> {
+        let Some(manifest) = storage.manifest()? else {
+            return Ok(None);
+        };
+        let in_day = |timestamp: i64| {
+            day.is_none_or(|day| Utc.timestamp_opt(timestamp, 0).unwrap().date_naive() == day)
+        };
+        let problem = |file: &SnapshotFile, issue: Issue, detail: String| Problem {
+            height: file.height,
+            timestamp: file.timestamp,
+            kind: file.kind,
+            path: file.path.display().to_string(),
+            issue,
+            detail,
+        };
+        let mut problems = Vec::new();
+        let files: Vec<SnapshotFile> = storage
+            .list()?
+            .into_iter()
+            .filter(|file| in_day(file.timestamp))
+            .collect();
+        for file in &files {
+            let Some(entry) = manifest.get(file) else {
+                problems.push(problem(file, Issue::Unlisted, "not in the manifest".into()));
+                continue;
+            };
+            let size = storage.size(file);
+            if size.is_some_and(|size| size != entry.bytes) {
+                problems.push(problem(
+                    file,
+                    Issue::Mismatch,
+                    format!("{} bytes, the manifest has {}", size.unwrap(), entry.bytes),
+                ));
+                continue;
+            }
+            if checksums {
+                if let Err(e) = manifest.check(file) {
+                    problems.push(problem(file, Issue::Mismatch, format!("{e:#}")));
+                }
+            }
+        }
+        let present: HashSet<(u64, i64, &str)> = files
+            .iter()
+            .map(|file| (file.height, file.timestamp, file.kind.as_str()))
+            .collect();
+        for (name, entry) in &manifest.files {
+            let Some(kind) = FileKind::parse(&entry.kind) else {
+                continue;
+            };
+            if !in_day(entry.timestamp)
+                || present.contains(&(entry.height, entry.timestamp, kind.as_str()))
+            {
+                continue;
+            }
+            let file = SnapshotFile {
+                height: entry.height,
+                timestamp: entry.timestamp,
+                kind,
+                path: name.into(),
+            };
+            problems.push(problem(
+                &file,
+                Issue::Missing,
+                "listed in the manifest".into(),
+            ));
+        }
+        debug!("checked {} files against the manifest", files.len());
+        Ok(Some((files.len(), problems)))
+    }
+
+    /// Apply the rows of `entry` to `pool`, the mempool at `block_hash`, noting what doesn't
+    /// fit. The rows of a `subsumed` delta are only checked on their own, those of a delta of
+    /// another block not at all.
+    fn check(
+        replay: &Replay,
+        entry: &Entry,
+        block_hash: &mut Option<String>,
+        pool: &mut HashSet<String>,
+        subsumed: bool,
+        problems: &mut Vec<(Issue, String)>,
+    ) -> Result<()> {
+        let frame = replay.read(entry)?;
+        let recorded_at = Snapshot::block_hash_of(&frame)?;
+        match (block_hash.as_ref(), recorded_at) {
+            (Some(anchor), Some(recorded_at)) if *anchor != recorded_at => {
+                problems.push((
+                    Issue::ForkedDelta,
+                    format!("of block {recorded_at} after the full snapshot of {anchor}"),
+                ));
+                return Ok(());
+            }
+            (None, recorded_at) => *block_hash = recorded_at,
+            _ => {}
+        }
+        if entry.kind == FileKind::Histogram {
+            // buckets have no txids to follow
+            frame.column("fee_rate_sat_vb")?.f64()?;
+            frame.column("vsize")?.u64()?;
+            return Ok(());
+        }
+        let txids = frame.column("txid")?.utf8()?;
+        let weights = frame.column("weight")?.f64()?;
+        let fees = frame.column("fee_sat")?.f64()?;
+        let first_seen = frame.column("first_seen_at")?.u64()?;
+        // not in files of the first schema
+        let zero_counts: Vec<bool> = match (
+            frame.column("ancestor_count"),
+            frame.column("descendant_count"),
+        ) {
+            (Ok(ancestors), Ok(descendants)) => ancestors
+                .u64()?
+                .into_iter()
+                .zip(descendants.u64()?)
+                .map(|(ancestors, descendants)| ancestors == Some(0) || descendants == Some(0))
+                .collect(),
+            _ => vec![false; frame.height()],
+        };
+
+        let mut counts = [0usize; 4];
+        let mut examples: [Option<String>; 4] = Default::default();
+        let mut note = |index: usize, txid: Option<&str>| {
+            counts[index] += 1;
+            if examples[index].is_none() {
+                examples[index] = txid.map(String::from);
+            }
+        };
+        let rows = txids
+            .into_iter()
+            .zip(weights)
+            .zip(fees)
+            .zip(first_seen)
+            .zip(zero_counts);
+        for ((((txid, weight), fee_sat), first_seen_at), zero_count) in rows {
+            let (Some(txid), Some(weight), Some(fee_sat)) = (txid, weight, fee_sat) else {
+                note(0, txid);
+                continue;
+            };
+            let removal = weight < 0.;
+            if !weight.is_finite()
+                || weight == 0.
+                || (removal && entry.kind == FileKind::Full)
+                || !fee_sat.is_finite()
+                || fee_sat < 0.
+                || zero_count
+            {
+                note(0, Some(txid));
+                continue;
+            }
+            if first_seen_at.is_some_and(|at| at > entry.timestamp as u64 + CLOCK_SLACK_SECS) {
+                note(3, Some(txid));
+            }
+            if subsumed {
+                continue;
+            }
+            if removal {
+                if !pool.remove(txid) {
+                    note(1, Some(txid));
+                }
+            } else if !pool.insert(txid.to_string()) {
+                note(2, Some(txid));
+            }
+        }
+
+        let issues = [
+            (Issue::InvalidRow, "invalid"),
+            (
+                Issue::UnknownRemoval,
+                "removing a transaction not in the mempool",
+            ),
+            (
+                Issue::DuplicateAddition,
+                "adding a transaction already in the mempool",
+            ),
+            (Issue::SeenLater, "first seen after the snapshot"),
+        ];
+        for (index, (issue, what)) in issues.into_iter().enumerate() {
+            if counts[index] == 0 {
+                continue;
+            }
+            let example = examples[index]
+                .as_ref()
+                .map(|txid| format!(", e.g. {txid}"))
+                .unwrap_or_default();
+            problems.push((issue, format!("{} rows {what}{example}", counts[index])));
+        }
+        debug!("{} transactions at {}", pool.len(), entry.timestamp);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        record::{DeltaFrame, Mempool, MempoolEntry},
+        storage::LocalStorage,
+    };
+    use bitcoin::{hashes::Hash, Amount, BlockHash, Network, Txid, Wtxid};
+
+    fn mempool(names: &[&str]) -> Mempool {
+        names
+            .iter()
+            .map(|name| {
+                let entry = MempoolEntry {
+                    wtxid: Wtxid::hash(name.as_bytes()),
+                    vsize: 200,
+                    weight: 800,
+                    time: 1_700_000_000,
+                    height: 800_000,
+                    fee: Amount::from_sat(2_000),
+                    modified_fee: Amount::from_sat(2_000),
+                    ancestor_fees: Amount::from_sat(2_000),
+                    descendant_fees: Amount::from_sat(2_000),
+                    ancestor_count: 1,
+                    descendant_count: 1,
+                    descendant_size: 200,
+                    bip125_replaceable: false,
+                    unbroadcast: None,
+                    depends: Box::new([]),
+                    spent_by: Box::new([]),
+                };
+                (Txid::hash(name.as_bytes()), entry)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fork_at_the_same_height_is_anchored_on_the_block() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(dir.path(), Network::Regtest);
+        let (a, b) = (BlockHash::hash(b"a"), BlockHash::hash(b"b"));
+        let (before, after) = (mempool(&["x", "y"]), mempool(&["x", "y", "z"]));
+        let forked = mempool(&["y", "w"]);
+        let at = |minute: i64| Utc.timestamp_opt(1_700_000_000 + minute * 60, 0).unwrap();
+        let write = |minute: i64, delta: DeltaFrame| {
+            let mut frame = delta.frame;
+            storage
+                .write(at(minute), 800_000, delta.kind, &mut frame)
+                .unwrap();
+        };
+        write(0, DeltaFrame::full(&before, &a));
+        write(1, DeltaFrame::between(&before, &after, &a, &HashSet::new()));
+        // the tip moved to b at the same height without a full snapshot of it
+        write(2, DeltaFrame::between(&after, &forked, &b, &HashSet::new()));
+
+        let verified = Verify::run(&storage, None).unwrap();
+        let issues: Vec<(i64, Issue)> = verified
+            .problems
+            .iter()
+            .map(|problem| (problem.timestamp, problem.issue))
+            .collect();
+        assert_eq!(issues, [(at(2).timestamp(), Issue::ForkedDelta)]);
+        // the delta of b isn't applied to the mempool of a
+        let replay = Replay::new(&storage).unwrap();
+        assert!(replay.at(at(2).timestamp()).is_err());
+        let snapshot = replay.at(at(1).timestamp()).unwrap();
+        assert_eq!(snapshot.transactions.len(), 3);
+        assert_eq!(snapshot.block_hash, Some(a.to_string()));
+
+        // what the recorder writes: a full snapshot of b
+        write(3, DeltaFrame::full(&forked, &b));
+        assert_eq!(Verify::run(&storage, None).unwrap().problems.len(), 1);
+        let snapshot = Replay::new(&storage)
+            .unwrap()
+            .at(at(3).timestamp())
+            .unwrap();
+        assert_eq!(snapshot.block_hash, Some(b.to_string()));
+        let mut txids: Vec<String> = snapshot.transactions.into_keys().collect();
+        txids.sort();
+        let mut expected: Vec<String> = forked.keys().map(Txid::to_string).collect();
+        expected.sort();
+        assert_eq!(txids, expected);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::{
+    node::Node,
+    record::MempoolEntry,
+    replay::{Replay, Snapshot},
+    score::Score,
+    storage::Storage,
+    template::Template,
+};
+use anyhow::{bail, Result};
+use bitcoin::{Denomination, Txid};
+use serde::Serialize;
+use std::{collections::HashMap, time::Duration};
+use tracing::debug;
+
+/// Expected time between blocks
+const BLOCK_INTERVAL_SECS: i64 = 600;
+
+/// Where a transaction stands in the projected blocks of a mempool
+#[derive(Debug, Clone, Serialize)]
+pub struct TxPosition {
+    pub txid: Txid,
+    pub height: u64,
+    /// Unix timestamp of the mempool
+    pub timestamp: i64,
+    /// `node` or `recorded`
+    pub source: &'static str,
+    pub vsize: u64,
+    /// Its own fee rate, sat/vB
+    pub fee_rate: f64,
+    /// The rate of the package it is mined with, sat/vB. A recorded mempool has no
+    /// dependencies, there it's the transaction's own.
+    pub effective_fee_rate: f64,
+    /// Virtual size mined before it
+    pub vsize_ahead: u64,
+    /// Projected block it lands in, 1 for the next one
+    pub block: usize,
+    /// Expected wait, ten minutes a block
+    pub eta_secs: i64,
+}
+
+/// How a watched transaction left the mempool
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case", tag = "outcome")]
+pub enum Departure {
+    /// Mined in the block at `height`
+    Confirmed { height: u64 },
+    /// Gone without being found in a block, replaced or evicted. Recorded mempools have no
+    /// blocks to search, there this is all that's known.
+    Left { height: u64 },
+}
+
+/// Where to look for the transaction
+#[derive(Clone, Copy)]
+pub enum Source<'a> {
+    Node(&'a dyn Node),
+    Recorded(&'a dyn Storage),
+}
+
+impl TxPosition {
+    /// `txid` in the node's mempool right now, `None` if it isn't there
+    pub async fn from_node(node: &dyn Node, txid: &Txid) -> Result<Option<Self>> {
+        let height = node.get_chain_info().await?.blocks;
+        let mempool = MempoolEntry::mempool(node.get_mempool().await?);
+        let Some(entry) = mempool.get(txid) else {
+            return Ok(None);
+        };
+        let fee = entry.fee.to_float_in(Denomination::Satoshi);
+        let effective_fee_rate = Score::effective_fee_rates(&mempool)
+            .get(txid)
+            .copied()
+            .unwrap_or(fee / entry.vsize.max(1) as f64);
+        let weights: HashMap<Txid, u64> = mempool
+            .iter()
+            .map(|(txid, entry)| (*txid, entry.weight))
+            .collect();
+        let template = Template::build(&mempool, usize::MAX);
+        Ok(
+            Self::locate(&template, &weights, txid).map(|(block, weight_ahead)| TxPosition {
+                txid: *txid,
+                height,
+                timestamp: chrono::Utc::now().timestamp(),
+                source: "node",
+                vsize: entry.vsize,
+                fee_rate: fee / entry.vsize.max(1) as f64,
+                effective_fee_rate,
+                vsize_ahead: weight_ahead.div_ceil(4),
+                block,
+                eta_secs: block as i64 * BLOCK_INTERVAL_SECS,
+            }),
+        )
+    }
+
+    /// `txid` in a recorded mempool, `None` if it isn't there
+    pub fn from_snapshot(snapshot: &Snapshot, txid: &Txid) -> Option<Self> {
+        let tx = snapshot.transactions.get(&txid.to_string())?;
+        let weights: HashMap<Txid, u64> = snapshot
+            .transactions
+            .iter()
+            .filter_map(|(txid, tx)| Some((txid.parse().ok()?, tx.weight as u64)))
+            .collect();
+        let template = Template::from_snapshot(snapshot, usize::MAX);
+        let (block, weight_ahead) = Self::locate(&template, &weights, txid)?;
+        Some(TxPosition {
+            txid: *txid,
+            height: snapshot.height,
+            timestamp: snapshot.timestamp,
+            source: "recorded",
+            vsize: (tx.weight / 4.).ceil() as u64,
+            fee_rate: tx.fee_rate_sat_vb(),
+            effective_fee_rate: tx.fee_rate_sat_vb(),
+            vsize_ahead: weight_ahead.div_ceil(4),
+            block,
+            eta_secs: block as i64 * BLOCK_INTERVAL_SECS,
+        })
+    }
+
+    /// The block `txid` is projected into and the weight mined before it
+    fn locate(
+        template: &Template,
+        weights: &HashMap<Txid, u64>,
+        txid: &Txid,
+    ) -> Option<(usize, u64)> {
+        let mut weight_ahead = 0;
+        for (index, block) in template.blocks.iter().enumerate() {
+            match block.txids.iter().position(|tx| tx == txid) {
+                Some(position) => {
+                    weight_ahead += block.txids[..position]
+                        .iter()
+                        .filter_map(|tx| weights.get(tx))
+                        .sum::<u64>();
+                    return Some((index + 1, weight_ahead));
+                }
+                None => weight_ahead += block.weight,
+            }
+        }
+        None
+    }
+
+    /// `txid` in the node's mempool or the latest recorded snapshot
+    pub async fn find(source: Source<'_>, txid: &Txid) -> Result<Option<Self>> {
+        match source {
+            Source::Node(node) => Self::from_node(node, txid).await,
+            Source::Recorded(storage) => Ok(Self::from_snapshot(
+                &Replay::new(storage)?.at(i64::MAX)?,
+                txid,
+            )),
+        }
+    }
+
+    /// Look for `txid` again every `poll` and hand every new position to `update`, until it
+    /// leaves the mempool. It has to be in there to begin with.
+    pub async fn follow(
+        source: Source<'_>,
+        txid: &Txid,
+        poll: Duration,
+        mut update: impl FnMut(&TxPosition),
+    ) -> Result<Departure> {
+        let Some(mut position) = Self::find(source, txid).await? else {
+            bail!("{txid} is not in the mempool");
+        };
+        update(&position);
+        loop {
+            tokio::time::sleep(poll).await;
+            let Some(next) = Self::find(source, txid).await? else {
+                return Self::departure(source, txid, position.height).await;
+            };
+            if (next.block, next.vsize_ahead, next.height)
+                != (position.block, position.vsize_ahead, position.height)
+            {
+                update(&next);
+            }
+            position = next;
+        }
+    }
+
+    /// Search the blocks on top of `height`, the last one `txid` was seen in the mempool at
+    async fn departure(source: Source<'_>, txid: &Txid, height: u64) -> Result<Departure> {
+        match source {
+            Source::Node(node) => {
+                let tip = node.get_chain_info().await?.blocks;
+                for height in height + 1..=tip {
+                    let block = node.get_block(&node.get_block_hash(height).await?).await?;
+                    if block.txdata.iter().any(|tx| tx.txid() == *txid) {
+                        return Ok(Departure::Confirmed { height });
+                    }
+                }
+                debug!("{txid} is in none of the blocks up to {tip}");
+                Ok(Departure::Left { height: tip })
+            }
+            Source::Recorded(storage) => {
+                let snapshot = Replay::new(storage)?.at(i64::MAX)?;
+                Ok(Departure::Left {
+                    height: snapshot.height,
+                })
+            }
+        }
+    }
+}
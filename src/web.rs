@@ -0,0 +1,153 @@
+/// Read-only fee page served at `/`: the estimates of `/v1/targets`, the latest mempool by fee
+/// rate band and the fee rate percentiles of the last day from `/v1/history`. Everything is
+/// inline, the browser showing it only needs to reach `serve`. An `api_key` in the page's query
+/// is passed on to the endpoints.
+pub const DASHBOARD: &str = r##"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>WhatTheFee</title>
+<style>
+body { font-family: system-ui, sans-serif; margin: 2em auto; max-width: 60em; padding: 0 1em; }
+h1 { font-size: 1.4em; }
+h2 { font-size: 1.1em; margin-top: 2em; }
+table { border-collapse: collapse; }
+th, td { padding: 0.2em 0.8em; text-align: right; }
+th { border-bottom: 1px solid #999; }
+.band { display: flex; align-items: center; margin: 2px 0; }
+.band span { width: 9em; text-align: right; padding-right: 0.5em; font-size: 0.9em; }
+.band div { background: #f7931a; height: 1em; }
+.muted, .band em { color: #777; font-size: 0.9em; font-style: normal; padding-left: 0.5em; }
+svg { width: 100%; height: 16em; }
+svg text { font-size: 10px; fill: #777; }
+#error { color: #b00; }
+</style>
+</head>
+<body>
+<h1>WhatTheFee</h1>
+<p class="muted" id="status">loading</p>
+<p id="error"></p>
+<h2>Estimates</h2>
+<table id="estimates"></table>
+<h2>Mempool</h2>
+<div id="histogram"></div>
+<h2>Last day</h2>
+<svg id="history" viewBox="0 0 600 200" preserveAspectRatio="none"></svg>
+<p class="muted" id="legend"></p>
+<script>
+const key = new URLSearchParams(location.search).get("api_key");
+const COLORS = ["#bbb", "#f7c27a", "#f7931a", "#c0640a", "#6b3500"];
+
+async function get(path, query) {
+  const params = new URLSearchParams(query);
+  if (key) params.set("api_key", key);
+  const response = await fetch(path + "?" + params);
+  if (!response.ok) throw new Error(path + ": " + response.status + " " + await response.text());
+  return response.json();
+}
+
+function el(tag, text) {
+  const node = document.createElement(tag);
+  if (text !== undefined) node.textContent = text;
+  return node;
+}
+
+function row(table, tag, cells) {
+  const tr = el("tr");
+  cells.forEach(cell => tr.appendChild(el(tag, cell)));
+  table.appendChild(tr);
+}
+
+const rate = value => value === null || value === undefined ? "-" : value.toFixed(1);
+
+function estimates(targets) {
+  const table = document.getElementById("estimates");
+  table.replaceChildren();
+  row(table, "th", ["blocks", "sat/vB", "projected", "historical", "confirmations"]);
+  targets.estimates.forEach(e => row(table, "td", [
+    e.target, rate(e.fee_rate_sat_vb), rate(e.projected_sat_vb),
+    rate(e.historical_sat_vb), e.confirmations,
+  ]));
+}
+
+function histogram(history, latest) {
+  const div = document.getElementById("histogram");
+  div.replaceChildren();
+  if (!latest) return;
+  const max = Math.max(1, ...latest.depth_vsize);
+  // highest fee rates first, the next block is taken from the top
+  for (let band = history.bands.length - 1; band >= 0; band--) {
+    const vsize = latest.depth_vsize[band];
+    const line = el("div");
+    line.className = "band";
+    const upper = history.bands[band + 1];
+    line.appendChild(el("span", history.bands[band] + (upper ? "-" + upper : "+") + " sat/vB"));
+    const bar = el("div");
+    bar.style.width = (70 * vsize / max) + "%";
+    line.appendChild(bar);
+    line.appendChild(el("em", (vsize / 1e6).toFixed(2) + " MvB"));
+    div.appendChild(line);
+  }
+}
+
+function chart(history) {
+  const svg = document.getElementById("history");
+  svg.replaceChildren();
+  const buckets = history.buckets.filter(bucket => bucket.fee_rates);
+  if (buckets.length < 2) return;
+  const max = Math.max(1, ...buckets.map(bucket => Math.max(...bucket.fee_rates)));
+  // fee rates span orders of magnitude, scaled by their logarithm
+  const y = value => 195 - 185 * Math.log1p(value) / Math.log1p(max);
+  const x = timestamp => 600 * (timestamp - history.from) / Math.max(1, history.to - history.from);
+  const ns = "http://www.w3.org/2000/svg";
+  history.percentiles.forEach((percentile, index) => {
+    const line = document.createElementNS(ns, "polyline");
+    const points = buckets.map(bucket => x(bucket.start) + "," + y(bucket.fee_rates[index]));
+    line.setAttribute("points", points.join(" "));
+    line.setAttribute("fill", "none");
+    line.setAttribute("stroke", COLORS[index % COLORS.length]);
+    line.setAttribute("vector-effect", "non-scaling-stroke");
+    svg.appendChild(line);
+  });
+  const label = document.createElementNS(ns, "text");
+  label.setAttribute("x", 2);
+  label.setAttribute("y", 12);
+  label.textContent = rate(max) + " sat/vB";
+  svg.appendChild(label);
+  const legend = document.getElementById("legend");
+  legend.replaceChildren("Fee rate ");
+  history.percentiles.forEach((percentile, index) => {
+    const entry = el("span", (index ? ", " : "") + percentile * 100 + "%");
+    entry.style.color = COLORS[index % COLORS.length];
+    legend.appendChild(entry);
+  });
+  legend.append(" of the mempool pays at most");
+}
+
+async function refresh() {
+  try {
+    const [targets, history] = await Promise.all([
+      get("v1/targets", {}),
+      get("v1/history", { resolution: "5m" }),
+    ]);
+    const latest = history.buckets[history.buckets.length - 1];
+    estimates(targets);
+    histogram(history, latest);
+    chart(history);
+    document.getElementById("status").textContent = latest
+      ? "block " + latest.height + ", " + latest.mempool_txs + " transactions, as of "
+        + new Date(latest.timestamp * 1000).toLocaleString()
+      : "nothing recorded yet";
+    document.getElementById("error").textContent = "";
+  } catch (e) {
+    document.getElementById("error").textContent = e.message;
+  }
+}
+
+refresh();
+setInterval(refresh, 60 * 1000);
+</script>
+</body>
+</html>
+"##;
@@ -0,0 +1,98 @@
+use crate::{dataset::FileKind, metrics::Metrics, storage::Storage};
+use chrono::{DateTime, Utc};
+use polars::prelude::DataFrame;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::error;
+
+/// Files queued for writing at most, beyond that the recorder waits for the writer
+const WRITE_QUEUE_FILES: usize = 32;
+
+/// A file to write
+struct Job {
+    now: DateTime<Utc>,
+    height: u64,
+    kind: FileKind,
+    frame: DataFrame,
+}
+
+/// Writes files on a thread of its own, so compressing a big delta doesn't hold up the next
+/// snapshot. Files are written in the order they were queued. Once the queue is full queuing
+/// waits, which the metrics count.
+pub struct Writer {
+    sender: mpsc::Sender<Job>,
+    handle: JoinHandle<()>,
+    failed: Arc<AtomicBool>,
+    metrics: Arc<Metrics>,
+}
+
+impl Writer {
+    pub fn start(storage: Arc<dyn Storage>, metrics: Arc<Metrics>) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<Job>(WRITE_QUEUE_FILES);
+        let failed = Arc::new(AtomicBool::new(false));
+        let handle = tokio::task::spawn_blocking({
+            let (failed, metrics) = (failed.clone(), metrics.clone());
+            move || {
+                while let Some(mut job) = receiver.blocking_recv() {
+                    if let Err(e) = storage.write(job.now, job.height, job.kind, &mut job.frame) {
+                        error!("writing {} file failed: {e:#}", job.kind.as_str());
+                        metrics.write_failed();
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    metrics.write_queued(receiver.len());
+                }
+            }
+        });
+        Writer {
+            sender,
+            handle,
+            failed,
+            metrics,
+        }
+    }
+
+    /// Queue a file, waiting while the queue is full
+    pub async fn write(&self, now: DateTime<Utc>, height: u64, kind: FileKind, frame: DataFrame) {
+        let job = Job {
+            now,
+            height,
+            kind,
+            frame,
+        };
+        let job = match self.sender.try_send(job) {
+            Err(mpsc::error::TrySendError::Full(job)) => job,
+            _ => {
+                self.metrics.write_queued(self.queued());
+                return;
+            }
+        };
+        let start = Instant::now();
+        // only closed once the writer is finished
+        let _ = self.sender.send(job).await;
+        self.metrics.write_waited(start.elapsed());
+        self.metrics.write_queued(self.queued());
+    }
+
+    /// Whether a file failed to write since the last call
+    pub fn take_failed(&self) -> bool {
+        self.failed.swap(false, Ordering::Relaxed)
+    }
+
+    /// Wait until everything queued is written
+    pub async fn finish(self) {
+        drop(self.sender);
+        if let Err(e) = self.handle.await {
+            error!("writer failed: {e}");
+        }
+    }
+
+    fn queued(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+}
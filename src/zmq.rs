@@ -0,0 +1,259 @@
+use crate::{
+    dataset::{FileKind, SnapshotFile},
+    sink::Sink,
+};
+use anyhow::{anyhow, Result};
+use bitcoin::{consensus::deserialize, Transaction};
+use bytes::Bytes;
+use polars::prelude::*;
+use std::collections::HashSet;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, info, warn};
+use zeromq::{PubSocket, Socket, SocketRecv, SocketSend, SubSocket, ZmqMessage};
+
+const TOPICS: [&str; 3] = ["sequence", "rawtx", "hashblock"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Added,
+    Removed,
+    BlockConnected,
+    BlockDisconnected,
+    RawTx,
+}
+
+impl EventKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Added => "added",
+            EventKind::Removed => "removed",
+            EventKind::BlockConnected => "block_connected",
+            EventKind::BlockDisconnected => "block_disconnected",
+            EventKind::RawTx => "rawtx",
+        }
+    }
+
+    pub fn is_block(&self) -> bool {
+        matches!(
+            self,
+            EventKind::BlockConnected | EventKind::BlockDisconnected
+        )
+    }
+}
+
+/// A single notification as received from Bitcoin Core
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub kind: EventKind,
+    /// txid or block hash
+    pub hash: String,
+    /// Mempool sequence number, only set for mempool additions and removals
+    pub mempool_sequence: Option<u64>,
+    /// Only set for raw transactions
+    pub weight: Option<u64>,
+    /// A raw coinbase transaction, published with its block
+    pub coinbase: bool,
+    pub received_at_ms: i64,
+}
+
+pub struct ZmqListener;
+
+impl ZmqListener {
+    /// Subscribe to `sequence`, `rawtx` and `hashblock` on every endpoint, merging all
+    /// notifications into one channel.
+    pub async fn subscribe(endpoints: &[String]) -> Result<UnboundedReceiver<Event>> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        for endpoint in endpoints {
+            let mut socket = SubSocket::new();
+            socket.connect(endpoint).await?;
+            for topic in TOPICS {
+                socket.subscribe(topic).await?;
+            }
+            tokio::spawn(Self::listen(endpoint.clone(), socket, sender.clone()));
+        }
+        Ok(receiver)
+    }
+
+    async fn listen(endpoint: String, mut socket: SubSocket, sender: UnboundedSender<Event>) {
+        loop {
+            let message = match socket.recv().await {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("zmq endpoint {endpoint} failed, relying on snapshots only: {e}");
+                    return;
+                }
+            };
+            match Self::parse(&message) {
+                Some(event) => {
+                    if sender.send(event).is_err() {
+                        return;
+                    }
+                }
+                None => debug!("ignoring unexpected zmq message from {endpoint}"),
+            }
+        }
+    }
+
+    /// Messages are `[topic, body, sequence]`, hashes come in display byte order
+    fn parse(message: &ZmqMessage) -> Option<Event> {
+        let topic = message.get(0)?;
+        let body = message.get(1)?;
+        let received_at_ms = chrono::Utc::now().timestamp_millis();
+
+        let event = match topic.as_ref() {
+            b"sequence" if body.len() >= 33 => {
+                let kind = match body[32] {
+                    b'A' => EventKind::Added,
+                    b'R' => EventKind::Removed,
+                    b'C' => EventKind::BlockConnected,
+                    b'D' => EventKind::BlockDisconnected,
+                    _ => return None,
+                };
+                let mempool_sequence = body
+                    .get(33..41)
+                    .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()));
+                Event {
+                    kind,
+                    hash: Self::to_hex(&body[..32]),
+                    mempool_sequence,
+                    weight: None,
+                    coinbase: false,
+                    received_at_ms,
+                }
+            }
+            b"hashblock" if body.len() == 32 => Event {
+                kind: EventKind::BlockConnected,
+                hash: Self::to_hex(body),
+                mempool_sequence: None,
+                weight: None,
+                coinbase: false,
+                received_at_ms,
+            },
+            b"rawtx" => {
+                let tx: Transaction = deserialize(body).ok()?;
+                Event {
+                    kind: EventKind::RawTx,
+                    hash: tx.txid().to_string(),
+                    mempool_sequence: None,
+                    weight: Some(tx.weight().to_wu()),
+                    coinbase: tx.is_coin_base(),
+                    received_at_ms,
+                }
+            }
+            _ => return None,
+        };
+        Some(event)
+    }
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// Raw transactions among `events` that were never in the mempool: not added to it since
+    /// the previous snapshot nor `seen` there. Bitcoin Core publishes `rawtx` for what it
+    /// accepts and for the transactions of connected blocks, so these are confirmed
+    /// transactions the node's relay policy kept out, or that never were relayed to it.
+    pub fn rejected(events: &[Event], seen: impl Fn(&str) -> bool) -> Vec<&Event> {
+        let added: HashSet<&str> = events
+            .iter()
+            .filter(|event| event.kind == EventKind::Added)
+            .map(|event| event.hash.as_str())
+            .collect();
+        let mut rejected: Vec<&Event> = events
+            .iter()
+            .filter(|event| event.kind == EventKind::RawTx && !event.coinbase)
+            .filter(|event| !added.contains(event.hash.as_str()) && !seen(&event.hash))
+            .collect();
+        // published again should the block be disconnected and connected
+        let mut unique = HashSet::new();
+        rejected.retain(|event| unique.insert(event.hash.as_str()));
+        rejected
+    }
+
+    pub fn create_rejected_frame(events: &[&Event]) -> DataFrame {
+        let txids: Vec<&str> = events.iter().map(|e| e.hash.as_str()).collect();
+        let weights: Vec<Option<u64>> = events.iter().map(|e| e.weight).collect();
+        let received: Vec<i64> = events.iter().map(|e| e.received_at_ms).collect();
+
+        DataFrame::new(vec![
+            Series::new("txid", txids),
+            Series::new("weight", weights),
+            Series::new("received_at_ms", received),
+        ])
+        .unwrap()
+    }
+
+    pub fn create_events_frame(events: &[Event]) -> DataFrame {
+        let kinds: Vec<&str> = events.iter().map(|e| e.kind.as_str()).collect();
+        let hashes: Vec<&str> = events.iter().map(|e| e.hash.as_str()).collect();
+        let sequences: Vec<Option<u64>> = events.iter().map(|e| e.mempool_sequence).collect();
+        let weights: Vec<Option<u64>> = events.iter().map(|e| e.weight).collect();
+        let received: Vec<i64> = events.iter().map(|e| e.received_at_ms).collect();
+
+        DataFrame::new(vec![
+            Series::new("event", kinds),
+            Series::new("hash", hashes),
+            Series::new("mempool_sequence", sequences),
+            Series::new("weight", weights),
+            Series::new("received_at_ms", received),
+        ])
+        .unwrap()
+    }
+}
+
+/// Publishes every full and delta frame the way Bitcoin Core publishes its notifications:
+/// `[topic, body, sequence]` with the topic `full` or `delta`, the tagged frame as Arrow IPC
+/// file in the body and a little endian u32 sequence number per topic.
+pub struct ZmqPublisher {
+    sender: UnboundedSender<(FileKind, Vec<u8>)>,
+}
+
+impl ZmqPublisher {
+    pub async fn bind(endpoint: &str) -> Result<Self> {
+        let mut socket = PubSocket::new();
+        let bound = socket.bind(endpoint).await?;
+        info!("publishing: {bound}");
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(Self::publish(socket, receiver));
+        Ok(ZmqPublisher { sender })
+    }
+
+    async fn publish(mut socket: PubSocket, mut receiver: UnboundedReceiver<(FileKind, Vec<u8>)>) {
+        let (mut full_sequence, mut delta_sequence) = (0u32, 0u32);
+        while let Some((kind, body)) = receiver.recv().await {
+            let sequence = match kind {
+                FileKind::Full => &mut full_sequence,
+                _ => &mut delta_sequence,
+            };
+            let frames = vec![
+                Bytes::from_static(kind.as_str().as_bytes()),
+                Bytes::from(body),
+                Bytes::copy_from_slice(&sequence.to_le_bytes()),
+            ];
+            *sequence = sequence.wrapping_add(1);
+            let message = ZmqMessage::try_from(frames).expect("message has frames");
+            // without subscribers the message is dropped, like Core does
+            if let Err(e) = socket.send(message).await {
+                warn!("zmq publish failed: {e}");
+            }
+        }
+    }
+}
+
+impl Sink for ZmqPublisher {
+    fn name(&self) -> &str {
+        "zmq"
+    }
+
+    fn accept(&self, file: &SnapshotFile, frame: &DataFrame) -> Result<()> {
+        if !matches!(file.kind, FileKind::Full | FileKind::Delta) {
+            return Ok(());
+        }
+        let mut frame = file.tag(frame)?;
+        let mut body = Vec::new();
+        IpcWriter::new(&mut body).finish(&mut frame)?;
+        self.sender
+            .send((file.kind, body))
+            .map_err(|_| anyhow!("zmq publisher stopped"))
+    }
+}